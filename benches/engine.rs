@@ -0,0 +1,401 @@
+//! Benchmarks for the resolver and installer, against a synthetic index that we
+//! generate on the fly and serve from localhost, so the numbers don't depend on PyPI or
+//! the network, and mean the same thing from one run to the next. To run them:
+//!
+//!   cargo bench
+//!
+//! or `cargo bench -- --test` to just check that they work. Criterion keeps its results
+//! under target/criterion, and compares against the last run, so run them once before
+//! a change and once after.
+//!
+//! This only gets posy_core's public API, like anyone else embedding it would.
+
+use criterion::{BatchSize, Criterion};
+use eyre::Result;
+use posy_core::{
+    Blueprint, Brief, EnvForest, KVDirStore, PackageDB, PackageName, PybiPlatform,
+};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+use zip::write::FileOptions;
+
+#[path = "../src/test_util/static_http_server.rs"]
+mod static_http_server;
+use static_http_server::StaticHTTPServer;
+
+const PYTHON_VERSION: &str = "3.10.8";
+const PYBI_PLATFORM: &str = "manylinux_2_17_x86_64";
+
+/// The dependency graph to generate.
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    /// p0 depends on p1, which depends on p2, ... Every package has a few versions.
+    Chain { depth: usize },
+    /// One package that depends on `width` others, which have no dependencies.
+    FanOut { width: usize },
+    /// Every version of `top` needs a different version of `mid` (and so on down),
+    /// but `pin` needs the oldest `bottom`, and the solver only finds that out after
+    /// trying the newest of everything. So it has to back out of `depth` dead ends.
+    Backtracking { depth: usize },
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::Chain { depth } => write!(f, "chain-{depth}"),
+            Shape::FanOut { width } => write!(f, "fan-out-{width}"),
+            Shape::Backtracking { depth } => write!(f, "backtracking-{depth}"),
+        }
+    }
+}
+
+/// A simple index (PEP 503) full of made-up packages, with real wheels behind it, and
+/// one pybi to resolve against.
+pub struct SyntheticIndex {
+    dir: tempfile::TempDir,
+    // normalized name -> links to its artifacts
+    links: BTreeMap<String, Vec<String>>,
+}
+
+impl SyntheticIndex {
+    pub fn new() -> Result<SyntheticIndex> {
+        let mut index = SyntheticIndex {
+            dir: tempfile::tempdir()?,
+            links: BTreeMap::new(),
+        };
+        index.add_python()?;
+        Ok(index)
+    }
+
+    /// A new index with `shape` in it, and the requirements a Brief needs to pull the
+    /// whole thing in.
+    pub fn with_shape(shape: Shape) -> Result<(SyntheticIndex, Vec<String>)> {
+        let mut index = SyntheticIndex::new()?;
+        let roots = match shape {
+            Shape::Chain { depth } => {
+                for i in 0..depth {
+                    for v in 1..=3 {
+                        let requires = if i + 1 < depth {
+                            vec![format!("p{} >= 1", i + 1)]
+                        } else {
+                            vec![]
+                        };
+                        index.add_wheel(
+                            &format!("p{i}"),
+                            &format!("{v}.0"),
+                            &requires,
+                        )?;
+                    }
+                }
+                vec!["p0".to_string()]
+            }
+            Shape::FanOut { width } => {
+                let leaves: Vec<String> =
+                    (0..width).map(|i| format!("leaf{i}")).collect();
+                for leaf in &leaves {
+                    for v in 1..=3 {
+                        index.add_wheel(leaf, &format!("{v}.0"), &[])?;
+                    }
+                }
+                index.add_wheel("hub", "1.0", &leaves)?;
+                vec!["hub".to_string()]
+            }
+            Shape::Backtracking { depth } => {
+                for v in 1..=depth {
+                    let version = format!("{v}.0");
+                    let exactly = |name: &str| vec![format!("{name} == {version}")];
+                    index.add_wheel("top", &version, &exactly("mid"))?;
+                    index.add_wheel("mid", &version, &exactly("bottom"))?;
+                    index.add_wheel("bottom", &version, &[])?;
+                }
+                index.add_wheel("pin", "1.0", &["bottom == 1.0".to_string()])?;
+                vec!["top".to_string(), "pin".to_string()]
+            }
+        };
+        Ok((index, roots))
+    }
+
+    fn add_artifact(
+        &mut self,
+        name: &str,
+        filename: &str,
+        files: &[(String, Vec<u8>)],
+    ) -> Result<()> {
+        let path = self.dir.path().join(filename);
+        write_zip(&path, files)?;
+        let digest =
+            ring::digest::digest(&ring::digest::SHA256, &std::fs::read(&path)?);
+        let hash = data_encoding::HEXLOWER.encode(digest.as_ref());
+        let normalized: PackageName = name.try_into()?;
+        self.links
+            .entry(normalized.normalized().to_string())
+            .or_default()
+            .push(format!(
+                "<a href=\"/{filename}#sha256={hash}\">{filename}</a>"
+            ));
+        Ok(())
+    }
+
+    pub fn add_wheel(
+        &mut self,
+        name: &str,
+        version: &str,
+        requires: &[String],
+    ) -> Result<()> {
+        let dist_info = format!("{name}-{version}.dist-info");
+        let mut metadata =
+            format!("Metadata-Version: 2.1\nName: {name}\nVersion: {version}\n");
+        for req in requires {
+            metadata.push_str(&format!("Requires-Dist: {req}\n"));
+        }
+        let files = [
+            (
+                format!("{name}/__init__.py"),
+                format!("__version__ = {version:?}\n").into_bytes(),
+            ),
+            (
+                format!("{dist_info}/WHEEL"),
+                b"Wheel-Version: 1.0\nGenerator: posy-bench\nRoot-Is-Purelib: true\n\
+                  Tag: py3-none-any\n"
+                    .to_vec(),
+            ),
+            (format!("{dist_info}/METADATA"), metadata.into_bytes()),
+        ];
+        self.add_artifact(name, &format!("{name}-{version}-py3-none-any.whl"), &files)
+    }
+
+    fn add_python(&mut self) -> Result<()> {
+        let stdlib = "lib/python3.10";
+        let markers = serde_json::json!({
+            "implementation_name": "cpython",
+            "implementation_version": PYTHON_VERSION,
+            "os_name": "posix",
+            "platform_machine": "x86_64",
+            "platform_python_implementation": "CPython",
+            "platform_system": "Linux",
+            "python_full_version": PYTHON_VERSION,
+            "python_version": "3.10",
+            "sys_platform": "linux",
+        });
+        let paths = serde_json::json!({
+            "stdlib": stdlib,
+            "platstdlib": stdlib,
+            "purelib": format!("{stdlib}/site-packages"),
+            "platlib": format!("{stdlib}/site-packages"),
+            "include": "include/python3.10",
+            "platinclude": "include/python3.10",
+            "scripts": "bin",
+            "data": ".",
+        });
+        let metadata = format!(
+            "Metadata-Version: 2.1\nName: cpython_unofficial\n\
+             Version: {PYTHON_VERSION}\n\
+             Pybi-Environment-Marker-Variables: {markers}\nPybi-Paths: {paths}\n\
+             Pybi-Wheel-Tag: py3-none-any\n"
+        );
+        let files = [
+            (
+                "pybi-info/PYBI".to_string(),
+                b"Pybi-Version: 1.0\nGenerator: posy-bench\n".to_vec(),
+            ),
+            ("pybi-info/METADATA".to_string(), metadata.into_bytes()),
+            (
+                format!("{stdlib}/site.py"),
+                b"ENABLE_USER_SITE = None\n".to_vec(),
+            ),
+            (format!("{stdlib}/site-packages/README.txt"), Vec::new()),
+        ];
+        let filename =
+            format!("cpython_unofficial-{PYTHON_VERSION}-{PYBI_PLATFORM}.pybi");
+        self.add_artifact("cpython_unofficial", &filename, &files)
+    }
+
+    /// Writes out the index pages, and starts serving everything.
+    pub fn serve(self) -> Result<ServedIndex> {
+        for (name, links) in &self.links {
+            let dir = self.dir.path().join("simple").join(name);
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join("index.html"), links.join("\n"))?;
+        }
+        let server = StaticHTTPServer::new(self.dir.path());
+        Ok(ServedIndex {
+            url: server.url("/simple/"),
+            _server: server,
+            _dir: self.dir,
+        })
+    }
+}
+
+fn write_zip(path: &Path, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let mut z = zip::ZipWriter::new(std::fs::File::create(path)?);
+    for (name, data) in files {
+        z.start_file(name, FileOptions::default())?;
+        z.write_all(data)?;
+    }
+    z.finish()?;
+    Ok(())
+}
+
+pub struct ServedIndex {
+    pub url: Url,
+    // declared in this order so the server stops before its files go away
+    _server: StaticHTTPServer,
+    _dir: tempfile::TempDir,
+}
+
+impl ServedIndex {
+    pub fn brief(&self, requirements: &[String]) -> Result<Brief> {
+        Ok(Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: requirements
+                .iter()
+                .map(|r| r.as_str().try_into())
+                .collect::<Result<_>>()?,
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        })
+    }
+
+    pub fn platform(&self) -> PybiPlatform {
+        PybiPlatform::new(PYBI_PLATFORM)
+    }
+}
+
+/// Everything posy keeps on disk, starting out empty.
+struct Scratch {
+    dir: tempfile::TempDir,
+    forest: EnvForest,
+    store: KVDirStore,
+}
+
+impl Scratch {
+    fn new() -> Result<Scratch> {
+        let dir = tempfile::tempdir()?;
+        Ok(Scratch {
+            forest: EnvForest::new(&dir.path().join("envs"))?,
+            store: KVDirStore::new(&dir.path().join("store"))?,
+            dir,
+        })
+    }
+
+    fn db(&self, index: &ServedIndex) -> Result<PackageDB> {
+        Ok(PackageDB::new(
+            &[index.url.clone()],
+            &self.dir.path().join("cache"),
+            &self.forest,
+            &self.store,
+        )?)
+    }
+
+    fn resolve(&self, index: &ServedIndex, brief: &Brief) -> Result<Blueprint> {
+        Ok(brief.resolve(&self.db(index)?, &[&index.platform()], None, &[])?)
+    }
+}
+
+const SHAPES: &[Shape] = &[
+    Shape::Chain { depth: 50 },
+    Shape::FanOut { width: 200 },
+    Shape::Backtracking { depth: 30 },
+];
+
+fn bench_resolve(c: &mut Criterion) -> Result<()> {
+    let mut group = c.benchmark_group("resolve");
+    for shape in SHAPES {
+        let (index, roots) = SyntheticIndex::with_shape(*shape)?;
+        let index = index.serve()?;
+        let brief = index.brief(&roots)?;
+
+        // nothing cached: every index page and every wheel's metadata comes over http
+        group.bench_function(format!("{shape}/cold"), |b| {
+            b.iter_batched(
+                || Scratch::new().unwrap(),
+                |scratch| scratch.resolve(&index, &brief).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+
+        // same on-disk caches every time, like running `posy` again
+        let scratch = Scratch::new()?;
+        scratch.resolve(&index, &brief)?;
+        group.bench_function(format!("{shape}/warm"), |b| {
+            b.iter(|| scratch.resolve(&index, &brief).unwrap())
+        });
+    }
+    group.finish();
+    Ok(())
+}
+
+fn bench_install(c: &mut Criterion) -> Result<()> {
+    let mut group = c.benchmark_group("install");
+    for shape in SHAPES {
+        let (index, roots) = SyntheticIndex::with_shape(*shape)?;
+        let index = index.serve()?;
+        let blueprint = Scratch::new()?.resolve(&index, &index.brief(&roots)?)?;
+        let platform = index.platform();
+
+        group.bench_function(shape.to_string(), |b| {
+            b.iter_batched(
+                || Scratch::new().unwrap(),
+                |scratch| {
+                    let db = scratch.db(&index).unwrap();
+                    scratch
+                        .forest
+                        .get_env(&db, &blueprint, &[&platform], &[])
+                        .unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+    Ok(())
+}
+
+/// The benchmarks are only useful if the synthetic index actually resolves, so this
+/// checks that the smallest version of each shape does.
+fn check_synthetic_index() -> Result<()> {
+    for (shape, expected) in [
+        (
+            Shape::Chain { depth: 3 },
+            vec!["p0 3.0", "p1 3.0", "p2 3.0"],
+        ),
+        (
+            Shape::FanOut { width: 2 },
+            vec!["hub 1.0", "leaf0 3.0", "leaf1 3.0"],
+        ),
+        (
+            Shape::Backtracking { depth: 3 },
+            vec!["bottom 1.0", "mid 1.0", "pin 1.0", "top 1.0"],
+        ),
+    ] {
+        let (index, roots) = SyntheticIndex::with_shape(shape)?;
+        let index = index.serve()?;
+        let blueprint = Scratch::new()?.resolve(&index, &index.brief(&roots)?)?;
+        let mut pins: Vec<String> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+            .collect();
+        pins.sort_unstable();
+        assert_eq!(pins, expected, "{shape}");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    check_synthetic_index()?;
+    let mut c = Criterion::default()
+        .sample_size(10)
+        .warm_up_time(Duration::from_secs(1))
+        .measurement_time(Duration::from_secs(10))
+        .configure_from_args();
+    bench_resolve(&mut c)?;
+    bench_install(&mut c)?;
+    c.final_summary();
+    Ok(())
+}
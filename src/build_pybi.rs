@@ -0,0 +1,429 @@
+//! Making pybis, out of a Python that's already been built and installed somewhere
+//! relocatable -- e.g. so an organization can publish its own patched interpreters to
+//! an internal index, and resolve against them like any other.
+//!
+//! The interpreter knows most of what goes in the metadata: its marker variables, its
+//! sysconfig paths, and which wheels it can load. `PybiSpec::from_interpreter` runs it
+//! and asks. What it can't know is which platforms the build actually runs on -- a
+//! manylinux build looks the same from the inside as one that only works on the distro
+//! it was built on -- so the platform tags have to come from whoever's building it.
+//!
+//! Members go in sorted, and the zip is reproducible (see `reproducible_zip`), so the
+//! same tree always gives the same bytes, and the same hash.
+
+use crate::check_pybi::REQUIRED_PATHS;
+use crate::prelude::*;
+use crate::reproducible_zip::ReproducibleZip;
+use crate::tree::NiceSymlinkPaths;
+use std::collections::BTreeMap;
+// `as _`, so it doesn't shadow the prelude's io::Write
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const PYBI_INFO_SCRIPT: &str = include_str!("data-files/pybi-info.py");
+
+/// What goes in a pybi's filename and metadata.
+#[derive(Debug, Clone)]
+pub struct PybiSpec {
+    pub name: PybiName,
+    pub environment_marker_variables: BTreeMap<String, String>,
+    /// sysconfig's paths, relative to the root, with `/` separators
+    pub paths: BTreeMap<String, String>,
+    /// The wheel tags this Python can load, best first. A `-PLATFORM` suffix stands
+    /// for each of the pybi's platform tags.
+    pub wheel_tags: Vec<String>,
+}
+
+/// What pybi-info.py prints.
+#[derive(Deserialize)]
+struct InterpreterInfo {
+    prefix: PathBuf,
+    markers: BTreeMap<String, String>,
+    paths: BTreeMap<String, String>,
+    abi: String,
+    version: (u32, u32),
+}
+
+impl PybiSpec {
+    /// Runs `python` (which should be somewhere inside `root`) to find out about it.
+    /// The pybi is named after the implementation (e.g. `cpython`), at its Python
+    /// version, with no build tag; change `name` afterwards if you want something
+    /// else.
+    pub fn from_interpreter(
+        root: &Path,
+        python: &Path,
+        platform_tags: &[String],
+    ) -> Result<PybiSpec> {
+        context!("asking {} about itself", python.display());
+        if platform_tags.is_empty() {
+            bail!("a pybi needs at least one platform tag");
+        }
+        let output = Command::new(python)
+            .args(["-I", "-c", PYBI_INFO_SCRIPT])
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "{} failed ({}):\n{}",
+                python.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        let info: InterpreterInfo = serde_json::from_slice(&output.stdout)?;
+        if fs::canonicalize(&info.prefix)? != fs::canonicalize(root)? {
+            bail!(
+                "{} says its prefix is {}, not {}; pybis have to be built from the \
+                 Python's whole installation directory",
+                python.display(),
+                info.prefix.display(),
+                root.display()
+            );
+        }
+        let marker = |var: &str| {
+            info.markers
+                .get(var)
+                .ok_or_else(|| eyre!("interpreter didn't report {var}"))
+        };
+        let implementation = marker("implementation_name")?;
+        let name = PybiName {
+            distribution: implementation.as_str().try_into()?,
+            version: marker("python_full_version")?.as_str().try_into()?,
+            build_number: None,
+            build_name: String::new(),
+            arch_tags: platform_tags.to_vec(),
+        };
+        let wheel_tags = wheel_tag_templates(implementation, info.version, &info.abi);
+        Ok(PybiSpec {
+            name,
+            environment_marker_variables: info.markers,
+            paths: info.paths,
+            wheel_tags,
+        })
+    }
+
+    /// The contents of pybi-info/METADATA.
+    pub fn metadata(&self) -> Result<String> {
+        let mut text = String::new();
+        writeln!(text, "Metadata-Version: 2.2")?;
+        writeln!(text, "Name: {}", self.name.distribution.as_given())?;
+        writeln!(text, "Version: {}", self.name.version)?;
+        writeln!(
+            text,
+            "Pybi-Environment-Marker-Variables: {}",
+            serde_json::to_string(&self.environment_marker_variables)?
+        )?;
+        writeln!(text, "Pybi-Paths: {}", serde_json::to_string(&self.paths)?)?;
+        for tag in &self.wheel_tags {
+            writeln!(text, "Pybi-Wheel-Tag: {tag}")?;
+        }
+        Ok(text)
+    }
+}
+
+/// The wheel tags an interpreter can load, best first, in the same order as
+/// `packaging.tags.sys_tags()`, but with `PLATFORM` in place of the platform tags.
+fn wheel_tag_templates(
+    implementation: &str,
+    (major, minor): (u32, u32),
+    abi: &str,
+) -> Vec<String> {
+    let interpreter = match implementation {
+        "cpython" => "cp",
+        "pypy" => "pp",
+        other => other,
+    };
+    let interpreter = format!("{interpreter}{major}{minor}");
+    let mut tags = vec![format!("{interpreter}-{abi}-PLATFORM")];
+    // Debug and free-threaded builds can't load the stable ABI
+    let abi3 = implementation == "cpython" && abi == format!("cp{major}{minor}");
+    if abi3 {
+        tags.push(format!("{interpreter}-abi3-PLATFORM"));
+    }
+    tags.push(format!("{interpreter}-none-PLATFORM"));
+    if abi3 && major == 3 {
+        // The stable ABI only goes back to 3.2
+        for older in (2..minor).rev() {
+            tags.push(format!("cp3{older}-abi3-PLATFORM"));
+        }
+    }
+    let generic = || (0..=minor).rev().map(|m| format!("py{major}{m}"));
+    for py in generic() {
+        tags.push(format!("{py}-none-PLATFORM"));
+    }
+    tags.push(format!("py{major}-none-PLATFORM"));
+    tags.push(format!("{interpreter}-none-any"));
+    for py in generic() {
+        tags.push(format!("{py}-none-any"));
+    }
+    tags.push(format!("py{major}-none-any"));
+    tags
+}
+
+/// A pybi we just wrote.
+#[derive(Debug)]
+pub struct BuiltPybi {
+    pub path: PathBuf,
+    pub name: PybiName,
+    pub hash: ArtifactHash,
+}
+
+enum Member {
+    Dir,
+    File { executable: bool },
+    Symlink(String),
+}
+
+/// Everything under `root`, depth first in sorted order, as `/`-separated relative
+/// paths. Symlinks are kept as symlinks, not followed.
+fn walk(root: &Path) -> Result<Vec<(String, Member)>> {
+    fn walk_into(
+        root: &Path,
+        prefix: &str,
+        members: &mut Vec<(String, Member)>,
+    ) -> Result<()> {
+        let mut entries = fs::read_dir(root.join(prefix))?
+            .map(|entry| {
+                let entry = entry?;
+                let name = entry.file_name().into_string().map_err(|name| {
+                    eyre!("{prefix}/{name:?}: pybi paths have to be UTF-8")
+                })?;
+                Ok((name, entry))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        for (name, entry) in entries {
+            let path = if prefix.is_empty() {
+                name
+            } else {
+                format!("{prefix}/{name}")
+            };
+            // We write our own pybi-info
+            if path == "pybi-info" {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                let target = target.to_str().ok_or_else(|| {
+                    eyre!("{path}: symlink target {target:?} isn't UTF-8")
+                })?;
+                members.push((path, Member::Symlink(target.replace('\\', "/"))));
+            } else if file_type.is_dir() {
+                members.push((path.clone(), Member::Dir));
+                walk_into(root, &path, members)?;
+            } else {
+                members.push((
+                    path,
+                    Member::File {
+                        executable: is_executable(&entry.metadata()?),
+                    },
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    let mut members = Vec::new();
+    walk_into(root, "", &mut members)?;
+    Ok(members)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Zips up `root` as the pybi that `spec` describes, in `out_dir`.
+pub fn build_pybi(root: &Path, spec: &PybiSpec, out_dir: &Path) -> Result<BuiltPybi> {
+    context!("building {} from {}", spec.name, root.display());
+    for key in REQUIRED_PATHS {
+        if !spec.paths.contains_key(*key) {
+            bail!("pybis need a '{key}' path");
+        }
+    }
+    // Env::python looks here, so it had better exist
+    let windows = spec.name.arch_tags.iter().any(|tag| tag.starts_with("win"));
+    let python = format!(
+        "{}/{}",
+        spec.paths["scripts"],
+        if windows { "python.exe" } else { "python" }
+    );
+    if fs::symlink_metadata(root.join(&python)).is_err() {
+        bail!("posy runs pybis as {python}, but there's nothing there");
+    }
+
+    let mut z = ReproducibleZip::new(tempfile::NamedTempFile::new_in(out_dir)?);
+    for (path, member) in walk(root)? {
+        match member {
+            Member::Dir => z.add_directory(&path)?,
+            Member::File { executable } => {
+                let data = fs::read(root.join(&path))
+                    .wrap_err_with(|| format!("reading {path}"))?;
+                z.add_file(&path, &data, executable)?;
+            }
+            Member::Symlink(target) => {
+                // Relative and inside the tree, or it won't survive being moved.
+                let symlink = NiceSymlinkPaths::new(
+                    &path.as_str().try_into()?,
+                    target.as_bytes(),
+                )?;
+                z.add_symlink(&path, &symlink.target)?;
+            }
+        }
+    }
+    let generated = [
+        (
+            "pybi-info/PYBI",
+            format!(
+                "Pybi-Version: 1.0\nGenerator: posy {}\n",
+                env!("CARGO_PKG_VERSION")
+            ),
+        ),
+        ("pybi-info/METADATA", spec.metadata()?),
+    ];
+    z.add_directory("pybi-info")?;
+    for (path, data) in generated {
+        z.add_file(path, data.as_bytes(), false)?;
+    }
+    let tmp = z.finish("pybi-info/RECORD")?;
+
+    let path = out_dir.join(spec.name.to_string());
+    tmp.persist(&path)?;
+    // Make sure we can read back what we wrote
+    let pybi = Pybi::new(spec.name.clone(), Box::new(fs::File::open(&path)?))?;
+    pybi.metadata()
+        .wrap_err("reading back the new pybi's metadata")?;
+    Ok(BuiltPybi {
+        hash: ArtifactHash::sha256_of(fs::File::open(&path)?)?,
+        path,
+        name: spec.name.clone(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec() -> PybiSpec {
+        let markers = [
+            ("implementation_name", "cpython"),
+            ("os_name", "posix"),
+            ("python_full_version", "3.11.2"),
+        ];
+        let paths = [
+            ("stdlib", "lib/python3.11"),
+            ("platstdlib", "lib/python3.11"),
+            ("purelib", "lib/python3.11/site-packages"),
+            ("platlib", "lib/python3.11/site-packages"),
+            ("scripts", "bin"),
+            ("data", "."),
+        ];
+        let to_map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        PybiSpec {
+            name: "cpython-3.11.2-manylinux_2_17_x86_64.pybi"
+                .try_into()
+                .unwrap(),
+            environment_marker_variables: to_map(&markers[..]),
+            paths: to_map(&paths[..]),
+            wheel_tags: wheel_tag_templates("cpython", (3, 11), "cp311"),
+        }
+    }
+
+    #[test]
+    fn test_wheel_tag_templates() {
+        let tags = wheel_tag_templates("cpython", (3, 11), "cp311");
+        assert_eq!(
+            &tags[..5],
+            &[
+                "cp311-cp311-PLATFORM",
+                "cp311-abi3-PLATFORM",
+                "cp311-none-PLATFORM",
+                "cp310-abi3-PLATFORM",
+                "cp39-abi3-PLATFORM",
+            ]
+        );
+        assert!(tags.contains(&"cp32-abi3-PLATFORM".to_string()));
+        assert!(!tags.contains(&"cp31-abi3-PLATFORM".to_string()));
+        assert_eq!(tags.last().unwrap(), "py3-none-any");
+
+        let debug = wheel_tag_templates("cpython", (3, 13), "cp313td");
+        assert_eq!(debug[0], "cp313-cp313td-PLATFORM");
+        assert!(!debug.iter().any(|tag| tag.contains("abi3")));
+
+        let pypy = wheel_tag_templates("pypy", (3, 9), "pypy39_pp73");
+        assert_eq!(
+            pypy[..2],
+            ["pp39-pypy39_pp73-PLATFORM", "pp39-none-PLATFORM"]
+        );
+    }
+
+    #[test]
+    fn test_build_pybi() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let write = |path: &str, data: &[u8]| -> Result<()> {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, data)?;
+            Ok(())
+        };
+        write("bin/python", b"#!/bin/sh\n")?;
+        write("lib/python3.11/os.py", b"import sys\n")?;
+        write("lib/python3.11/site-packages/README.txt", b"")?;
+        // a stale pybi-info gets replaced
+        write("pybi-info/METADATA", b"junk")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("python", root.path().join("bin/python3"))?;
+
+        let out = tempfile::tempdir()?;
+        let built = build_pybi(root.path(), &spec(), out.path())?;
+        assert_eq!(
+            built.path,
+            out.path().join("cpython-3.11.2-manylinux_2_17_x86_64.pybi")
+        );
+
+        let pybi =
+            Pybi::new(built.name.clone(), Box::new(fs::File::open(&built.path)?))?;
+        let (_, metadata) = pybi.metadata()?;
+        assert_eq!(metadata.environment_marker_variables["os_name"], "posix");
+        assert_eq!(metadata.path("scripts")?.to_string(), "bin");
+        assert_eq!(metadata.tags[0], "cp311-cp311-PLATFORM");
+
+        let mut z = zip::ZipArchive::new(fs::File::open(&built.path)?)?;
+        let record = Record::parse(std::str::from_utf8(&slurp(
+            &mut z.by_name("pybi-info/RECORD")?,
+        )?)?)?;
+        record
+            .get("lib/python3.11/os.py")
+            .unwrap()
+            .check(b"import sys\n")?;
+        assert!(record.get("pybi-info/METADATA").is_some());
+        assert!(record.get("pybi-info/RECORD").is_some());
+        assert!(z.by_name("bin/").is_ok());
+
+        // Same tree, same bytes
+        let again = tempfile::tempdir()?;
+        assert_eq!(
+            build_pybi(root.path(), &spec(), again.path())?.hash,
+            built.hash
+        );
+
+        let mut no_python = spec();
+        no_python.paths.insert("scripts".into(), "Scripts".into());
+        assert!(build_pybi(root.path(), &no_python, again.path()).is_err());
+        Ok(())
+    }
+}
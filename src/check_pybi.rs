@@ -0,0 +1,325 @@
+//! Is this pybi any good? Checks that its metadata makes sense -- the paths, the wheel
+//! tag templates, the marker variables -- and that its files match its RECORD.
+//!
+//! `posy python check` runs this on pybis you've built, before you publish them;
+//! `EnvForest` runs it on every pybi it downloads, before unpacking it into the store,
+//! and refuses the ones with errors. Warnings are for things that are dubious, but
+//! that posy can cope with, like a wheel tag that hard-codes a platform.
+
+use crate::platform_tags::{missing_marker_variables, MARKER_VARIABLES};
+use crate::prelude::*;
+use crate::tree::UnpackLimits;
+use std::collections::BTreeSet;
+
+/// The sysconfig paths that installing into a pybi, or running it, needs.
+pub const REQUIRED_PATHS: &[&str] =
+    &["stdlib", "purelib", "platlib", "scripts", "data"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// These describe whatever kernel the pybi was built on, so pybis are better off
+/// leaving them out.
+const MACHINE_SPECIFIC_MARKERS: &[&str] = &["platform_release", "platform_version"];
+
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn warning(&mut self, message: String) {
+        self.0.push(Problem {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    fn error(&mut self, message: String) {
+        self.0.push(Problem {
+            severity: Severity::Error,
+            message,
+        });
+    }
+}
+
+/// Everything that's wrong with `pybi`, errors first. Only fails if we can't read it
+/// at all, or if it's past `limits`.
+pub fn check_pybi(pybi: &Pybi, limits: &UnpackLimits) -> Result<Vec<Problem>> {
+    context!("Checking {}", pybi.name());
+    let mut problems = Problems::default();
+    match pybi.metadata() {
+        Ok((_, metadata)) => {
+            check_paths(pybi, &metadata, &mut problems);
+            check_wheel_tags(&metadata, &mut problems);
+            check_markers(pybi.name(), &metadata, &mut problems);
+        }
+        Err(err) => problems.error(format!("{err:#}")),
+    }
+    match pybi.check_record(limits) {
+        Ok(None) => problems.warning("there's no pybi-info/RECORD".into()),
+        Ok(Some(mismatches)) => {
+            for mismatch in mismatches {
+                problems.error(mismatch);
+            }
+        }
+        // not a problem with the pybi as such; we just shouldn't go any further
+        Err(err) if err.downcast_ref::<InstallError>().is_some() => return Err(err),
+        Err(err) => problems.error(format!("{err:#}")),
+    }
+    let mut problems = problems.0;
+    problems.sort_by_key(|problem| std::cmp::Reverse(problem.severity));
+    Ok(problems)
+}
+
+fn check_paths(pybi: &Pybi, metadata: &PybiCoreMetadata, problems: &mut Problems) {
+    for key in REQUIRED_PATHS {
+        if !metadata.paths.contains_key(*key) {
+            problems.error(format!("Pybi-Paths has no '{key}'"));
+        }
+    }
+    let files = pybi.file_names();
+    let has_dir = |dir: &NicePathBuf| {
+        let prefix = format!("{dir}/");
+        dir.pieces().is_empty() || files.iter().any(|name| name.starts_with(&prefix))
+    };
+    let mut keys: Vec<&String> = metadata.paths.keys().collect();
+    keys.sort_unstable();
+    for key in keys {
+        let dir = &metadata.paths[key];
+        if dir.pieces().first().map(String::as_str) == Some("pybi-info") {
+            problems.error(format!("Pybi-Paths puts '{key}' inside pybi-info/"));
+        } else if REQUIRED_PATHS.contains(&key.as_str()) && !has_dir(dir) {
+            problems.warning(format!(
+                "Pybi-Paths says '{key}' is {dir}, but there's nothing in it"
+            ));
+        }
+    }
+    if let Some(scripts) = metadata.paths.get("scripts") {
+        let windows = metadata
+            .environment_marker_variables
+            .get("os_name")
+            .map_or(false, |os_name| os_name == "nt");
+        let python = format!(
+            "{scripts}/{}",
+            if windows { "python.exe" } else { "python" }
+        );
+        if !files.contains(&python) {
+            problems.warning(format!("there's no {python}, which is what posy runs"));
+        }
+    }
+}
+
+fn check_wheel_tags(metadata: &PybiCoreMetadata, problems: &mut Problems) {
+    if metadata.tags.is_empty() {
+        problems.warning("no Pybi-Wheel-Tags, so no wheels can be installed".into());
+    }
+    let mut seen = HashSet::new();
+    for tag in &metadata.tags {
+        if !seen.insert(tag) {
+            problems.warning(format!("Pybi-Wheel-Tag {tag} is listed twice"));
+        }
+        let pieces: Vec<&str> = tag.split('-').collect();
+        let [python, abi, platform] = pieces.as_slice() else {
+            problems.error(format!(
+                "Pybi-Wheel-Tag {tag} should be python-abi-platform"
+            ));
+            continue;
+        };
+        if [python, abi, platform].iter().any(|piece| piece.is_empty()) {
+            problems.error(format!("Pybi-Wheel-Tag {tag} has an empty piece"));
+        } else if tag.matches("PLATFORM").count() > usize::from(*platform == "PLATFORM")
+        {
+            problems.error(format!(
+                "Pybi-Wheel-Tag {tag}: PLATFORM can only be the whole platform tag"
+            ));
+        } else if *platform != "PLATFORM" && *platform != "any" {
+            problems.warning(format!(
+                "Pybi-Wheel-Tag {tag} names a platform; usually it's PLATFORM, which \
+                 stands for the pybi's own platform tags"
+            ));
+        }
+    }
+}
+
+fn check_markers(
+    name: &PybiName,
+    metadata: &PybiCoreMetadata,
+    problems: &mut Problems,
+) {
+    let vars = &metadata.environment_marker_variables;
+    let mut unknown: Vec<&String> = vars
+        .keys()
+        .filter(|var| !MARKER_VARIABLES.contains(&var.as_str()))
+        .collect();
+    unknown.sort_unstable();
+    for var in unknown {
+        problems.warning(format!("unknown marker variable {var}"));
+    }
+    for var in MACHINE_SPECIFIC_MARKERS {
+        if vars.contains_key(*var) {
+            problems.warning(format!(
+                "{var} is set, but it depends on the machine, not the Python"
+            ));
+        }
+    }
+    // Whatever we can work out for ourselves had better agree with what it says
+    let mut contradictions = BTreeSet::new();
+    for tag in &name.arch_tags {
+        let mut derived = HashMap::new();
+        PybiPlatform::new(tag).synthesize_marker_variables(metadata, &mut derived);
+        for (var, value) in derived {
+            if let Some(declared) = vars.get(&var) {
+                if *declared != value {
+                    contradictions.insert(format!(
+                        "marker variable {var} is {declared:?}, but for {tag} it \
+                         should be {value:?}"
+                    ));
+                }
+            }
+        }
+    }
+    for contradiction in contradictions {
+        problems.error(contradiction);
+    }
+    let mut filled_in = vars.clone();
+    if let Some(tag) = name.arch_tags.first() {
+        PybiPlatform::new(tag).synthesize_marker_variables(metadata, &mut filled_in);
+    }
+    let missing: Vec<&str> = missing_marker_variables(&filled_in)
+        .into_iter()
+        .filter(|var| !MACHINE_SPECIFIC_MARKERS.contains(var))
+        .collect();
+    if !missing.is_empty() {
+        problems.warning(format!("missing marker variables: {}", missing.join(", ")));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use zip::write::FileOptions;
+
+    const GOOD_METADATA: &str = indoc::indoc! {r#"
+        Metadata-Version: 2.2
+        Name: cpython
+        Version: 3.11.2
+        Pybi-Environment-Marker-Variables: {"sys_platform": "linux"}
+        Pybi-Paths: {"stdlib": "lib/python3.11", "purelib": "lib/python3.11/site-packages", "platlib": "lib/python3.11/site-packages", "scripts": "bin", "data": "."}
+        Pybi-Wheel-Tag: cp311-cp311-PLATFORM
+        Pybi-Wheel-Tag: py3-none-any
+    "#};
+
+    /// A small pybi with `metadata`, and a RECORD that matches it unless
+    /// `tweak_record` messes it up.
+    fn pybi(metadata: &str, tweak_record: impl FnOnce(&mut Vec<RecordEntry>)) -> Pybi {
+        let files: &[(&str, &[u8])] = &[
+            ("bin/python", b"#!/bin/sh\n"),
+            ("lib/python3.11/os.py", b"import sys\n"),
+            ("lib/python3.11/site-packages/README.txt", b""),
+            ("pybi-info/PYBI", b"Pybi-Version: 1.0\n"),
+            ("pybi-info/METADATA", metadata.as_bytes()),
+        ];
+        let mut record: Vec<RecordEntry> = files
+            .iter()
+            .map(|(path, data)| RecordEntry::for_data(path, data))
+            .collect();
+        tweak_record(&mut record);
+        let record = Record::new("pybi-info/RECORD", record).render();
+        let mut zipped = Cursor::new(Vec::new());
+        {
+            let mut w = zip::ZipWriter::new(&mut zipped);
+            for (path, data) in files {
+                w.start_file(*path, FileOptions::default()).unwrap();
+                w.write_all(data).unwrap();
+            }
+            w.start_file("pybi-info/RECORD", FileOptions::default())
+                .unwrap();
+            w.write_all(record.as_bytes()).unwrap();
+            w.finish().unwrap();
+        }
+        let name = "cpython-3.11.2-manylinux_2_17_x86_64.pybi"
+            .try_into()
+            .unwrap();
+        Pybi::new(name, Box::new(zipped)).unwrap()
+    }
+
+    fn messages(problems: &[Problem], severity: Severity) -> Vec<&str> {
+        problems
+            .iter()
+            .filter(|problem| problem.severity == severity)
+            .map(|problem| problem.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_check_good_pybi() -> Result<()> {
+        let good = pybi(GOOD_METADATA, |_| ());
+        assert_eq!(check_pybi(&good, &Default::default())?, vec![]);
+        // reading every member is held to the same limits as unpacking them
+        let limits = UnpackLimits {
+            max_members: 2,
+            ..Default::default()
+        };
+        assert!(check_pybi(&good, &limits).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_bad_pybi() -> Result<()> {
+        let markers = r#""sys_platform": "darwin", "platform_release": "6.1", "os_nmae": "posix""#;
+        let tags = "cp311-PLATFORM\nPybi-Wheel-Tag: cp311-cp311-linux_x86_64";
+        let metadata = GOOD_METADATA
+            .replace(r#""sys_platform": "linux""#, markers)
+            .replace(r#", "data": ".""#, "")
+            .replace("py3-none-any", tags);
+        let bad = pybi(&metadata, |record| {
+            record[1] = RecordEntry::for_data("lib/python3.11/os.py", b"import os\n");
+            record.remove(2);
+            record.push(RecordEntry::for_data("bin/python3", b""));
+        });
+        let problems = check_pybi(&bad, &Default::default())?;
+        assert_eq!(problems[0].severity, Severity::Error);
+        let errors = messages(&problems, Severity::Error);
+        for expected in [
+            "Pybi-Paths has no 'data'",
+            "Pybi-Wheel-Tag cp311-PLATFORM should be python-abi-platform",
+            "marker variable sys_platform is \"darwin\", but for manylinux_2_17_x86_64 \
+             it should be \"linux\"",
+            "lib/python3.11/site-packages/README.txt isn't listed in RECORD",
+            "bin/python3 is listed in RECORD, but isn't there",
+        ] {
+            assert!(errors.contains(&expected), "{expected:?} not in {errors:#?}");
+        }
+        assert!(errors
+            .iter()
+            .any(|error| error.starts_with("lib/python3.11/os.py: ")));
+        let warnings = messages(&problems, Severity::Warning);
+        assert!(warnings.contains(&"unknown marker variable os_nmae"));
+        assert!(warnings
+            .iter()
+            .any(|w| w.starts_with("platform_release is set")));
+        assert!(warnings
+            .iter()
+            .any(|w| w.starts_with("Pybi-Wheel-Tag cp311-cp311-linux_x86_64 names")));
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+//! The `posy` command line. It lives in the library, not in main.rs, so that it can
+//! use the engine's internals without them having to be public.
+
+use crate::prelude::*;
+use crate::{commands, config, output, telemetry, timings};
+
+use clap::{CommandFactory, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(author, version, about)]
+struct Cli {
+    #[command(flatten)]
+    output_args: output::OutputArgs,
+    #[command(flatten)]
+    config_overrides: config::ConfigOverrides,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new project.
+    Init(commands::InitArgs),
+    /// Run a command inside the project's environment.
+    Run(commands::RunArgs),
+    /// Manage Jupyter kernels for the project's environments.
+    Kernel(commands::KernelArgs),
+    /// Manage the Python interpreters that posy has downloaded.
+    Python(commands::PythonArgs),
+    /// Run a tool from PyPI in its own environment, without needing a project.
+    Exec(commands::ExecArgs),
+    /// Pin the project's environments in posy.lock.
+    Lock(commands::LockArgs),
+    /// Fetch the index pages and metadata that locking the project's environments is
+    /// likely to need, without resolving anything, so a later step can lock offline.
+    Preheat(commands::PreheatArgs),
+    /// Pin one of the project's environments to match what another tool installed.
+    Import(commands::ImportArgs),
+    /// Write the project's locked environments out for other tools: as a uv.lock or
+    /// pdm.lock, a Dockerfile, a conda environment.yml, or a snapshot for GitHub's
+    /// dependency graph.
+    Export(commands::ExportArgs),
+    /// Copy every file the project's locked environments need into a directory, to
+    /// install from later without network access (using --wheelhouse).
+    Bundle(commands::BundleArgs),
+    /// List packages in the project's environment that have newer versions available.
+    Outdated(commands::OutdatedArgs),
+    /// Explain why the project's environment doesn't have some version of a package.
+    WhyNot(commands::WhyNotArgs),
+    /// Check the project's pinned packages for known vulnerabilities, and for releases
+    /// that have been yanked since they were locked.
+    Audit(commands::AuditArgs),
+    /// Report the licenses of the project's pinned packages.
+    Licenses(commands::LicensesArgs),
+    /// Set up one of the project's environments for another tool, like tox or nox, to
+    /// run things in.
+    Provision(commands::ProvisionArgs),
+    /// Work on wheel files directly, e.g. to change their tags.
+    Wheel(commands::WheelArgs),
+    /// Show the settings from posy.toml, and which file each one came from.
+    Config(commands::ConfigArgs),
+    /// Serve JSON-RPC requests on stdin/stdout (or a socket), for editor integrations.
+    Daemon(commands::DaemonArgs),
+    /// Print a shell completion script.
+    Completions(commands::CompletionsArgs),
+    #[command(name = "__complete", hide = true)]
+    Complete(commands::CompleteArgs),
+}
+
+pub fn main() -> Result<()> {
+    let cli = Cli::parse();
+    output::init(&cli.output_args)?;
+    cli.config_overrides.install();
+
+    let result = match cli.command {
+        Command::Init(args) => args.run(),
+        Command::Run(args) => args.run(),
+        Command::Kernel(args) => args.run(),
+        Command::Python(args) => args.run(),
+        Command::Exec(args) => args.run(),
+        Command::Lock(args) => args.run(),
+        Command::Preheat(args) => args.run(),
+        Command::Import(args) => args.run(),
+        Command::Export(args) => args.run(),
+        Command::Bundle(args) => args.run(),
+        Command::Outdated(args) => args.run(),
+        Command::WhyNot(args) => args.run(),
+        Command::Audit(args) => args.run(),
+        Command::Licenses(args) => args.run(),
+        Command::Provision(args) => args.run(),
+        Command::Wheel(args) => args.run(),
+        Command::Config(args) => args.run(),
+        Command::Daemon(args) => args.run(),
+        Command::Completions(args) => args.run(Cli::command()),
+        Command::Complete(args) => args.run(),
+    };
+    if let Some(report) = timings::report() {
+        eprint!("{report}");
+    }
+    telemetry::shutdown();
+    match result {
+        Err(err) if output::json() => {
+            output::print_json_error(&err);
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
@@ -0,0 +1,155 @@
+use crate::osv::{AdvisoryDb, Vulnerability};
+use crate::output;
+use crate::prelude::*;
+use crate::project::DEFAULT_ENV;
+use crate::resolve::YankedPin;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to check.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+    /// Check against a downloaded copy of the OSV database (e.g. PyPI/all.zip from
+    /// https://osv-vulnerabilities.storage.googleapis.com), instead of asking
+    /// api.osv.dev. This doesn't touch the network at all, so yanked releases are
+    /// checked against the index pages we have cached.
+    #[arg(long, value_name = "PATH")]
+    offline_db: Option<PathBuf>,
+}
+
+/// `--format json` prints a list of these, one for each package that has known
+/// vulnerabilities or has been yanked.
+#[derive(Serialize)]
+struct Finding<'a> {
+    name: &'a str,
+    version: &'a Version,
+    vulnerabilities: Vec<Vulnerability>,
+    /// Set if the pinned release has been yanked from the index since it was locked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    yanked: Option<Yanked>,
+}
+
+#[derive(Serialize)]
+struct Yanked {
+    reason: Option<String>,
+    /// The pinned files that were yanked
+    files: Vec<String>,
+}
+
+impl AuditArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let locked = super::locked_env(&project, &self.env_name)?;
+        let mut options = super::db_options(&global, &project)?;
+        options.offline |= self.offline_db.is_some();
+        let mut yanked =
+            super::with_package_db(options, |db, _| locked.blueprint.yanked(db))?;
+        let db = match &self.offline_db {
+            Some(path) => AdvisoryDb::offline(path)?,
+            None if global.offline => {
+                bail!("can't ask api.osv.dev in offline mode; use --offline-db instead")
+            }
+            None => AdvisoryDb::online(
+                global.allowed_hosts.as_ref(),
+                global.proxy.as_deref(),
+            )?,
+        };
+
+        // The pybi isn't on PyPI, so OSV doesn't know about it.
+        let packages: Vec<(&PackageName, &Version)> = locked
+            .blueprint
+            .wheels
+            .iter()
+            .map(|(p, _)| (&p.name, &p.version))
+            .collect();
+        let mut take_yanked = |name: &PackageName| {
+            let i = yanked.iter().position(|y| &y.name == name)?;
+            let YankedPin { reason, files, .. } = yanked.swap_remove(i);
+            Some(Yanked { reason, files })
+        };
+        let mut findings: Vec<Finding> = packages
+            .iter()
+            .zip(db.check(&packages)?)
+            .map(|((name, version), vulnerabilities)| Finding {
+                name: name.as_given(),
+                version,
+                vulnerabilities,
+                yanked: take_yanked(name),
+            })
+            .filter(|f| !f.vulnerabilities.is_empty() || f.yanked.is_some())
+            .collect();
+        // whatever's left is the pybi, which OSV doesn't know about but can still be
+        // yanked
+        let pybi = &locked.blueprint.pybi;
+        if let Some(yanked) = take_yanked(&pybi.name) {
+            findings.push(Finding {
+                name: pybi.name.as_given(),
+                version: &pybi.version,
+                vulnerabilities: Vec::new(),
+                yanked: Some(yanked),
+            });
+        }
+        let count: usize = findings.iter().map(|f| f.vulnerabilities.len()).sum();
+        let vulnerable = findings
+            .iter()
+            .filter(|f| !f.vulnerabilities.is_empty())
+            .count();
+        let yanked_count = findings.iter().filter(|f| f.yanked.is_some()).count();
+
+        if output::json() {
+            output::print_json(&findings)?;
+        } else {
+            for finding in &findings {
+                println!("{} {}", finding.name, finding.version);
+                for vuln in &finding.vulnerabilities {
+                    let mut ids = vec![vuln.id.as_str()];
+                    ids.extend(vuln.aliases.iter().map(|a| a.as_str()));
+                    println!(
+                        "  {} [{}]",
+                        ids.join(" / "),
+                        vuln.severity.as_deref().unwrap_or("unknown severity")
+                    );
+                    if let Some(summary) = &vuln.summary {
+                        println!("    {summary}");
+                    }
+                    match vuln.fixed_in.iter().find(|v| v > &finding.version) {
+                        Some(fixed) => println!("    fixed in {fixed}"),
+                        None => println!("    no fixed version yet"),
+                    }
+                }
+                if let Some(yanked) = &finding.yanked {
+                    match &yanked.reason {
+                        Some(reason) => println!("  yanked: {reason}"),
+                        None => println!("  yanked (no reason given)"),
+                    }
+                }
+            }
+        }
+        // Non-zero exit, so this can gate CI
+        match (count, yanked_count) {
+            (0, 0) => (),
+            (_, 0) => {
+                bail!("found {count} known vulnerabilities in {vulnerable} packages")
+            }
+            (0, _) => bail!("{yanked_count} pinned packages have been yanked"),
+            (_, _) => bail!(
+                "found {count} known vulnerabilities in {vulnerable} packages, and \
+                 {yanked_count} pinned packages have been yanked"
+            ),
+        }
+        if !output::json() {
+            println!(
+                "No known vulnerabilities or yanked releases in {} packages",
+                locked.blueprint.wheels.len()
+            );
+        }
+        Ok(())
+    }
+}
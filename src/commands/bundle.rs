@@ -0,0 +1,131 @@
+use crate::env::pinned_artifacts;
+use crate::output;
+use crate::package_db::{ArtifactInfo, Wheelhouse};
+use crate::prelude::*;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct BundleArgs {
+    /// The directory to put everything in. Anything that's already there is kept.
+    #[arg(short, long, value_name = "DIR")]
+    output: PathBuf,
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to bundle. (Can be repeated.) Defaults to
+    /// all of them.
+    #[arg(short, long = "env", value_name = "NAME")]
+    env_names: Vec<String>,
+    /// A platform to bundle for, e.g. manylinux_2_17_x86_64. (Can be repeated.)
+    /// Defaults to the ones this machine can run.
+    #[arg(long = "platform", value_name = "TAG")]
+    platforms: Vec<String>,
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct BundleReport {
+    path: PathBuf,
+    environments: Vec<String>,
+    /// Everything the environments need
+    files: usize,
+    /// How many of those weren't there already
+    added: usize,
+}
+
+impl BundleArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        if global.wheelhouse.is_some() {
+            bail!("can't bundle from a wheelhouse into another one");
+        }
+        let env_names: Vec<String> = if self.env_names.is_empty() {
+            let names = project.config.environment_names();
+            names.into_iter().map(String::from).collect()
+        } else {
+            self.env_names
+        };
+        let locked = env_names
+            .iter()
+            .map(|env_name| super::locked_env(&project, env_name))
+            .collect::<Result<Vec<_>>>()?;
+        let options = super::db_options(&global, &project)?;
+        let platforms: Vec<PybiPlatform> = self
+            .platforms
+            .iter()
+            .map(|tag| PybiPlatform::new(tag))
+            .collect();
+        let platforms: Vec<&PybiPlatform> = if platforms.is_empty() {
+            PybiPlatform::native_platforms()?.to_vec()
+        } else {
+            platforms.iter().collect()
+        };
+        let mut wheelhouse = Wheelhouse::create(&self.output)?;
+
+        let (files, added) = super::with_package_db(options, |db, _| {
+            let mut artifacts: Vec<ArtifactInfo> = Vec::new();
+            for (env_name, locked) in env_names.iter().zip(&locked) {
+                let pinned = pinned_artifacts(db, &locked.blueprint, &platforms)
+                    .wrap_err_with(|| format!("bundling environment '{env_name}'"))?;
+                for ai in pinned {
+                    if !artifacts.iter().any(|seen| seen.url == ai.url) {
+                        artifacts.push(ai);
+                    }
+                }
+            }
+            // Building these from the wheelhouse would need their build requirements
+            // in there too, and we'd only find out what those are by building them
+            let sdists: Vec<String> = artifacts
+                .iter()
+                .filter(|ai| ai.is::<Sdist>())
+                .map(|ai| ai.name.to_string())
+                .collect();
+            if !sdists.is_empty() {
+                let tags: Vec<&str> = platforms.iter().map(|p| p.core_tag()).collect();
+                bail!(
+                    "can't bundle sdists, only wheels, but on {} these don't have a \
+                     wheel to use: {}",
+                    tags.join(", "),
+                    sdists.join(", ")
+                );
+            }
+            let to_add: Vec<&ArtifactInfo> = artifacts
+                .iter()
+                .filter(|ai| !wheelhouse.contains(ai))
+                .collect();
+            crate::util::block_on(db.prefetch_artifacts(&to_add));
+            for ai in &to_add {
+                context!("Adding {} to the wheelhouse", ai.name);
+                wheelhouse.add(ai, &mut db.get_artifact_file(ai)?)?;
+            }
+            wheelhouse.save()?;
+            Ok((artifacts.len(), to_add.len()))
+        })?;
+
+        if output::json() {
+            return output::print_json(&BundleReport {
+                path: self.output,
+                environments: env_names,
+                files,
+                added,
+            });
+        }
+        println!(
+            "Bundled {files} files for {} into {} ({added} new)",
+            env_names
+                .iter()
+                .map(|name| format!("'{name}'"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.output.display()
+        );
+        println!(
+            "To install from it, pass --wheelhouse {} to posy (or set POSY_WHEELHOUSE)",
+            self.output.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use crate::package_db::known_package_names;
+use crate::prelude::*;
+use clap::{Args, Subcommand};
+use clap_complete::Shell;
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// Which shell to generate completions for. To use them, for example:
+    ///
+    ///   bash: posy completions bash > ~/.local/share/bash-completion/completions/posy
+    ///   zsh:  source <(posy completions zsh)
+    ///   fish: posy completions fish > ~/.config/fish/completions/posy.fish
+    ///   PowerShell: posy completions powershell | Out-String | Invoke-Expression
+    shell: Shell,
+}
+
+impl CompletionsArgs {
+    /// `cli` is the whole command line interface, which we can't get at from in here.
+    pub fn run(self, mut cli: clap::Command) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        clap_complete::generate(self.shell, &mut cli, "posy", &mut stdout);
+        // The static completions know about subcommands and flags. For the things that
+        // depend on where you are -- like which scripts the project has -- the shell
+        // calls back into `posy __complete`.
+        let dynamic = dynamic_completions(self.shell, &cli);
+        stdout.write_all(dynamic.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// The arguments whose values `posy __complete` can suggest, by their clap id, and
+/// what to ask it for. Which subcommands they're on, and what their flags are called,
+/// comes from the command tree, so the scripts can't offer anything that isn't there.
+/// (Arguments with a fixed set of values, like `posy export`'s `tool`, are left to the
+/// static completions.)
+const DYNAMIC_ARGS: &[(&str, &str)] = &[
+    ("command", "scripts"),
+    ("env_name", "envs"),
+    ("tool", "packages"),
+    ("with", "packages"),
+];
+
+/// One of `DYNAMIC_ARGS`, as it turns up under a top-level subcommand.
+#[derive(PartialEq, Eq, Debug)]
+struct DynamicArg {
+    subcommand: String,
+    kind: &'static str,
+    /// `-e`, `--env`, ...; empty if it's positional.
+    flags: Vec<String>,
+    /// Counting from 0, for positionals.
+    position: usize,
+}
+
+fn dynamic_args(cli: &clap::Command) -> Vec<DynamicArg> {
+    fn walk(
+        top: &str,
+        command: &clap::Command,
+        nested: bool,
+        found: &mut Vec<DynamicArg>,
+    ) {
+        let positionals: Vec<_> = command.get_positionals().collect();
+        for arg in command.get_arguments() {
+            let kind = match DYNAMIC_ARGS
+                .iter()
+                .find(|(id, _)| arg.get_id().as_str() == *id)
+            {
+                Some(&(_, kind)) => kind,
+                None => continue,
+            };
+            if !arg.get_possible_values().is_empty() {
+                continue;
+            }
+            let mut flags = Vec::new();
+            if let Some(short) = arg.get_short() {
+                flags.push(format!("-{short}"));
+            }
+            if let Some(long) = arg.get_long() {
+                flags.push(format!("--{long}"));
+            }
+            let position = positionals
+                .iter()
+                .position(|p| p.get_id() == arg.get_id())
+                .unwrap_or(0);
+            // The scripts only count positionals after the top-level subcommand, so
+            // a nested subcommand's flags are fine, but not its positionals.
+            if flags.is_empty() && nested {
+                continue;
+            }
+            let arg = DynamicArg {
+                subcommand: top.into(),
+                kind,
+                flags,
+                position,
+            };
+            if !found.contains(&arg) {
+                found.push(arg);
+            }
+        }
+        for sub in command.get_subcommands() {
+            walk(top, sub, true, found);
+        }
+    }
+
+    let mut found = Vec::new();
+    for sub in cli.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        walk(sub.get_name(), sub, false, &mut found);
+    }
+    found
+}
+
+fn dynamic_completions(shell: Shell, cli: &clap::Command) -> String {
+    let args = dynamic_args(cli);
+    let template = match shell {
+        Shell::Bash => include_str!("../data-files/completions/posy.bash"),
+        Shell::Zsh => include_str!("../data-files/completions/posy.zsh"),
+        Shell::Fish => include_str!("../data-files/completions/posy.fish"),
+        _ => return String::new(),
+    };
+    if shell == Shell::Fish {
+        let lines: Vec<String> = args.iter().filter_map(fish_completion).collect();
+        return template.replace("@COMPLETIONS@", &lines.join("\n"));
+    }
+    // bash and zsh both match "<subcommand> <previous word>" for flags, and
+    // "<subcommand> <positionals so far>" for positionals
+    let case = |indent: &str, patterns: Vec<String>, kind: &str| {
+        format!("{indent}{}) kind={kind} ;;", patterns.join("|"))
+    };
+    let mut options = Vec::new();
+    let mut positionals = Vec::new();
+    for arg in &args {
+        if arg.flags.is_empty() {
+            let pattern = format!("\"{} {}\"", arg.subcommand, arg.position);
+            positionals.push(case("            ", vec![pattern], arg.kind));
+        } else {
+            let patterns = arg
+                .flags
+                .iter()
+                .map(|flag| format!("\"{} {flag}\"", arg.subcommand))
+                .collect();
+            options.push(case("        ", patterns, arg.kind));
+        }
+    }
+    template
+        .replace("@OPTIONS@", &options.join("\n"))
+        .replace("@POSITIONALS@", &positionals.join("\n"))
+}
+
+/// fish can't count positionals, so it only completes a subcommand's first one.
+fn fish_completion(arg: &DynamicArg) -> Option<String> {
+    let source = match arg.kind {
+        "packages" => "(posy __complete packages (commandline -ct) 2>/dev/null)".into(),
+        kind => format!("(posy __complete {kind} 2>/dev/null)"),
+    };
+    let mut line = format!(
+        "complete -c posy -n \"__fish_seen_subcommand_from {}\"",
+        arg.subcommand
+    );
+    if arg.flags.is_empty() {
+        if arg.position > 0 {
+            return None;
+        }
+        line += " -f";
+    } else {
+        for flag in &arg.flags {
+            match flag.strip_prefix("--") {
+                Some(long) => line += &format!(" -l {long}"),
+                None => line += &format!(" -s {}", &flag[1..]),
+            }
+        }
+        line += " -f -r";
+    }
+    Some(format!("{line} -a \"{source}\""))
+}
+
+/// `posy __complete`, which the shell completion scripts call. Prints one candidate per
+/// line, and never fails: if we can't come up with anything, there's just nothing to
+/// offer.
+#[derive(Args)]
+pub struct CompleteArgs {
+    #[command(subcommand)]
+    what: CompleteWhat,
+}
+
+#[derive(Subcommand)]
+enum CompleteWhat {
+    /// Package names we've seen on an index before.
+    Packages {
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    /// The current project's scripts.
+    Scripts,
+    /// The current project's environments.
+    Envs,
+}
+
+impl CompleteArgs {
+    pub fn run(self) -> Result<()> {
+        let candidates = match self.what {
+            CompleteWhat::Packages { prefix } => {
+                // a broken posy.toml shouldn't break completion
+                let cache_dir = super::project(None)
+                    .and_then(|project| super::global_config(&project))
+                    .map(|global| global.cache_dir())
+                    .unwrap_or_else(|_| PROJECT_DIRS.cache_dir().into());
+                known_package_names(&cache_dir, &prefix)
+            }
+            CompleteWhat::Scripts => match super::project(None) {
+                Ok(project) => project.config.scripts.into_keys().collect(),
+                Err(_) => Vec::new(),
+            },
+            CompleteWhat::Envs => match super::project(None) {
+                Ok(project) => project
+                    .config
+                    .environment_names()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+        };
+        for candidate in candidates {
+            println!("{candidate}");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::{Arg, ArgAction, Command};
+
+    fn cli() -> Command {
+        let env = || Arg::new("env_name").short('e').long("env");
+        Command::new("posy")
+            .subcommand(
+                Command::new("run")
+                    .arg(env())
+                    .arg(Arg::new("with").long("with").action(ArgAction::Append))
+                    .arg(Arg::new("command").num_args(1..)),
+            )
+            .subcommand(Command::new("exec").arg(Arg::new("tool")))
+            .subcommand(
+                Command::new("export")
+                    .arg(Arg::new("tool").value_parser(["github", "pylock"]))
+                    .arg(env()),
+            )
+            .subcommand(
+                Command::new("kernel").subcommand(Command::new("install").arg(env())),
+            )
+            .subcommand(Command::new("__complete").hide(true).arg(env()))
+    }
+
+    #[test]
+    fn test_dynamic_completions() {
+        let bash = dynamic_completions(Shell::Bash, &cli());
+        for case in [
+            r#""run -e"|"run --env") kind=envs ;;"#,
+            r#""run --with") kind=packages ;;"#,
+            r#""run 0") kind=scripts ;;"#,
+            r#""exec 0") kind=packages ;;"#,
+            r#""export -e"|"export --env") kind=envs ;;"#,
+            r#""kernel -e"|"kernel --env") kind=envs ;;"#,
+        ] {
+            assert!(bash.contains(case), "{case}");
+        }
+        // only what's in the tree
+        assert!(!bash.contains("export 0"));
+        assert!(!bash.contains("__complete -e"));
+        assert!(!bash.contains("add"));
+        assert!(!bash.contains('@'));
+
+        let zsh = dynamic_completions(Shell::Zsh, &cli());
+        assert!(zsh.contains(r#""exec 0") kind=packages ;;"#));
+        assert!(!zsh.contains('@'));
+
+        let fish = dynamic_completions(Shell::Fish, &cli());
+        assert!(fish.contains(
+            r#"complete -c posy -n "__fish_seen_subcommand_from run" -s e -l env -f -r -a "(posy __complete envs 2>/dev/null)""#
+        ));
+        assert!(fish.contains(
+            r#"complete -c posy -n "__fish_seen_subcommand_from exec" -f -a "(posy __complete packages (commandline -ct) 2>/dev/null)""#
+        ));
+        assert!(!fish.contains("from export\" -f"));
+
+        assert_eq!(dynamic_completions(Shell::PowerShell, &cli()), "");
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config::{ConfigLayer, GlobalConfig, Setting, SETTINGS, TABLE_SETTINGS};
+use crate::output;
+use crate::prelude::*;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Only show these settings, e.g. `index-urls` or `env.PYTHONWARNINGS`.
+    keys: Vec<String>,
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct ConfigReport<'a> {
+    /// Every file we looked for, in precedence order, lowest first
+    layers: Vec<LayerFile>,
+    settings: BTreeMap<&'a str, &'a [Setting]>,
+}
+
+#[derive(Serialize)]
+struct LayerFile {
+    layer: ConfigLayer,
+    path: PathBuf,
+    exists: bool,
+}
+
+impl ConfigArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let mut layers = vec![
+            (ConfigLayer::System, GlobalConfig::system_path()),
+            (ConfigLayer::User, GlobalConfig::path()),
+        ];
+        if project.lockfile_path().is_some() {
+            layers.push((ConfigLayer::Project, project.root.join("posy.toml")));
+        }
+        let layers: Vec<LayerFile> = layers
+            .into_iter()
+            .map(|(layer, path)| LayerFile {
+                layer,
+                exists: path.is_file(),
+                path,
+            })
+            .collect();
+
+        // All the known settings first, in the usual order, then the entries of tables
+        // like `[env]`
+        let mut keys: Vec<&str> = SETTINGS
+            .iter()
+            .copied()
+            .filter(|k| !TABLE_SETTINGS.contains(k))
+            .collect();
+        keys.extend(global.settings.keys().map(String::as_str).filter(|k| {
+            let table = k.split_once('.').map(|(table, _)| table);
+            table.map_or(false, |table| TABLE_SETTINGS.contains(&table))
+        }));
+        if !self.keys.is_empty() {
+            for key in &self.keys {
+                if !keys.contains(&key.as_str()) {
+                    bail!("unknown setting '{key}' (known: {})", SETTINGS.join(", "));
+                }
+            }
+            keys.retain(|key| self.keys.iter().any(|k| k == key));
+        }
+
+        if output::json() {
+            let settings = keys
+                .iter()
+                .filter_map(|key| Some((*key, global.settings.get(*key)?.as_slice())))
+                .collect();
+            return output::print_json(&ConfigReport { layers, settings });
+        }
+        for file in &layers {
+            let missing = if file.exists { "" } else { " (not found)" };
+            println!("# {} layer: {}{missing}", file.layer, file.path.display());
+        }
+        for key in keys {
+            match global.settings.get(key) {
+                Some(settings) => {
+                    for setting in settings {
+                        let overridden = if setting.effective {
+                            ""
+                        } else {
+                            ", overridden"
+                        };
+                        let line = format!("{key} = {}", setting.value);
+                        let from = format!(
+                            "{}: {}{overridden}",
+                            setting.layer, setting.source
+                        );
+                        if setting.effective {
+                            println!("{line}  # {from}");
+                        } else {
+                            println!("# {line}  # {from}");
+                        }
+                    }
+                }
+                None => println!("# {key}: {}", default_value(&global, key)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What we use for `key` when no layer sets it.
+fn default_value(global: &GlobalConfig, key: &str) -> String {
+    match key {
+        "cache-dir" => format!("defaults to {}", global.cache_dir().display()),
+        "data-dir" => format!("defaults to {}", global.data_dir().display()),
+        "offline" => "defaults to false".into(),
+        "stale-if-error" => "off".into(),
+        "concurrency" => "no limit".into(),
+        "platforms" => match PybiPlatform::native_platforms() {
+            Ok(platforms) => {
+                let tags: Vec<&str> = platforms.iter().map(|p| p.core_tag()).collect();
+                format!("detected as {}", tags.join(", "))
+            }
+            Err(err) => format!("couldn't detect: {err}"),
+        },
+        _ => "not set".into(),
+    }
+}
@@ -0,0 +1,673 @@
+//! `posy daemon`: a long-running posy for editors and other tools to talk to, so they
+//! don't pay for process startup and cold caches on every call. Index pages and
+//! metadata stay in memory between requests, so e.g. re-resolving after someone edits
+//! pyproject.toml only fetches what's new.
+//!
+//! It speaks JSON-RPC 2.0. Messages can be framed the way LSP does it (a
+//! `Content-Length` header, a blank line, then the body), or just be one JSON object
+//! per line; responses are framed the same way as the request they answer. Batches
+//! aren't supported.
+//!
+//! The methods all take an optional `{"env": NAME}` (default: "default"):
+//!
+//! - `resolve`: what the environment would be locked to now, without writing
+//!   posy.lock.
+//! - `lock-status`: whether posy.lock is up to date, like `posy lock --check`.
+//! - `tree`: the locked packages and which of them depend on which.
+//! - `install`: makes sure the environment is installed, updating posy.lock first if
+//!   needed (unless `"frozen": true`), and says how to run things in it. With
+//!   `"python": VERSION`, it's re-resolved for that Python instead, like
+//!   `posy provision --python`.
+//! - `shutdown`: stops the daemon, after replying.
+//!
+//! pyproject.toml and posy.lock are re-read for every request, but posy.toml and the
+//! project's policy files are only read at startup. The index is only checked for new
+//! releases the first time we need each package.
+//!
+//! Nothing a client sends should take the daemon down: messages over
+//! MAX_MESSAGE_SIZE, bad framing, and requests that panic all get an error response,
+//! and a client whose connection breaks is dropped without bothering the next one.
+
+use crate::config::GlobalConfig;
+use crate::env::EnvForest;
+use crate::lockfile::{Lockfile, PackageChange};
+use crate::output;
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::project::{Project, DEFAULT_ENV};
+use crate::resolve::PinnedPackage;
+use clap::Args;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use super::provision::{python_override, run_env_vars, Installed};
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Listen on a Unix socket at PATH, instead of using stdin and stdout. Clients are
+    /// served one at a time.
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+}
+
+// https://www.jsonrpc.org/specification#error_object
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The method ran, and failed. `data` has the error like `--format json` reports it.
+const POSY_ERROR: i64 = -32000;
+/// The method hit a bug in posy, and gave up partway through.
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Bigger messages (or lines, for line-at-a-time framing) get an error instead of being
+/// read in. Real requests are a few hundred bytes.
+const MAX_MESSAGE_SIZE: u64 = 1 << 20;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    jsonrpc: String,
+    /// Without an id, it's a notification, and doesn't get a response.
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Display) -> RpcError {
+        RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+struct Params {
+    env: Option<String>,
+    frozen: bool,
+    python: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Lines,
+    Headers,
+}
+
+fn content_length(header: &str) -> Option<&str> {
+    let (name, value) = header.split_once(':')?;
+    name.trim()
+        .eq_ignore_ascii_case("content-length")
+        .then(|| value.trim())
+}
+
+fn too_big() -> RpcError {
+    let message = format!("message is bigger than {MAX_MESSAGE_SIZE} bytes");
+    RpcError::new(INVALID_REQUEST, message)
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Reads the next line, keeping at most MAX_MESSAGE_SIZE bytes of it; says whether
+/// there was more, which gets skipped. None at EOF.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<(Vec<u8>, bool)>> {
+    let mut line = Vec::new();
+    let mut limited = (&mut *input).take(MAX_MESSAGE_SIZE);
+    if limited.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    let cut = line.len() as u64 == MAX_MESSAGE_SIZE && !line.ends_with(b"\n");
+    if cut {
+        let mut rest = Vec::new();
+        loop {
+            rest.clear();
+            let n = (&mut *input).take(64 << 10).read_until(b'\n', &mut rest)?;
+            if n == 0 || rest.ends_with(b"\n") {
+                break;
+            }
+        }
+    }
+    Ok(Some((line, cut)))
+}
+
+/// Reads the next message, however the client framed it: its body, or what was wrong
+/// with it, to send back as an error. None at EOF. Only fails if the connection does.
+#[allow(clippy::type_complexity)]
+fn read_message<R: BufRead>(
+    input: &mut R,
+) -> Result<Option<(Framing, Result<Vec<u8>, RpcError>)>> {
+    let (line, cut) = loop {
+        match read_line(input)? {
+            None => return Ok(None),
+            Some((line, cut)) if cut || !is_blank(&line) => break (line, cut),
+            Some(_) => (),
+        }
+    };
+    if cut {
+        return Ok(Some((Framing::Lines, Err(too_big()))));
+    }
+    let header = String::from_utf8_lossy(&line);
+    let Some(length) = content_length(&header) else {
+        return Ok(Some((Framing::Lines, Ok(line))));
+    };
+    let length = length.parse::<u64>().map_err(|err| {
+        RpcError::new(PARSE_ERROR, format!("bad Content-Length: {err}"))
+    });
+    // skip any other headers, up to the blank line
+    loop {
+        match read_line(input)? {
+            None => bail!("connection closed in the middle of a message"),
+            Some((line, false)) if is_blank(&line) => break,
+            Some(_) => (),
+        }
+    }
+    // (if we couldn't read the length, there's no telling where the body ends, and
+    // the next message will probably come out garbled too, but at least we're still
+    // here)
+    let length = match length {
+        Ok(length) => length,
+        Err(error) => return Ok(Some((Framing::Headers, Err(error)))),
+    };
+    if length > MAX_MESSAGE_SIZE {
+        // skip over it, to be ready for the next one
+        io::copy(&mut (&mut *input).take(length), &mut io::sink())?;
+        return Ok(Some((Framing::Headers, Err(too_big()))));
+    }
+    let mut body = vec![0; length as usize];
+    input.read_exact(&mut body)?;
+    Ok(Some((Framing::Headers, Ok(body))))
+}
+
+fn write_message<W: Write>(
+    output: &mut W,
+    framing: Framing,
+    body: &[u8],
+) -> Result<()> {
+    match framing {
+        Framing::Lines => {
+            output.write_all(body)?;
+            output.write_all(b"\n")?;
+        }
+        Framing::Headers => {
+            write!(output, "Content-Length: {}\r\n\r\n", body.len())?;
+            output.write_all(body)?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn respond(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+    }
+}
+
+/// Handles one message, using `call` to run the method. Returns the response to send,
+/// if any, and whether it was a shutdown request.
+fn handle_message<F>(body: &[u8], call: F) -> (Option<Value>, bool)
+where
+    F: FnOnce(&str, Params) -> Result<Value, RpcError>,
+{
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(err) => {
+            let error = RpcError::new(PARSE_ERROR, err);
+            return (Some(respond(Value::Null, Err(error))), false);
+        }
+    };
+    let request: Request = match serde_json::from_value(value) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => {
+            let error =
+                RpcError::new(INVALID_REQUEST, "only JSON-RPC 2.0 is supported");
+            return (Some(respond(Value::Null, Err(error))), false);
+        }
+        Err(err) => {
+            let error = RpcError::new(INVALID_REQUEST, err);
+            return (Some(respond(Value::Null, Err(error))), false);
+        }
+    };
+    let shutdown = request.method == "shutdown";
+    let params = if request.params.is_null() {
+        Ok(Params::default())
+    } else {
+        serde_json::from_value(request.params)
+            .map_err(|err| RpcError::new(INVALID_PARAMS, err))
+    };
+    let result = params.and_then(|params| call_isolated(&request.method, params, call));
+    (request.id.map(|id| respond(id, result)), shutdown)
+}
+
+/// Runs `call`, turning a panic into an error response, so that a bug in one method
+/// doesn't take the whole daemon down with it. (Whatever was cached before the panic
+/// stays cached; the caches only ever get added to, so that's still consistent.)
+fn call_isolated<F>(method: &str, params: Params, call: F) -> Result<Value, RpcError>
+where
+    F: FnOnce(&str, Params) -> Result<Value, RpcError>,
+{
+    match catch_unwind(AssertUnwindSafe(|| call(method, params))) {
+        Ok(result) => result,
+        Err(payload) => {
+            let why = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("(no message)");
+            let message = format!("internal error in {method:?}: {why}");
+            Err(RpcError::new(INTERNAL_ERROR, message))
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Pin {
+    name: String,
+    version: Version,
+}
+
+impl Pin {
+    fn new(pin: &PinnedPackage) -> Pin {
+        Pin {
+            name: pin.name.as_given().to_owned(),
+            version: pin.version.clone(),
+        }
+    }
+}
+
+/// What `resolve` returns.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResolveResult {
+    /// Whether posy.lock already had this
+    fresh: bool,
+    /// How it differs from posy.lock
+    changes: Vec<PackageChange>,
+    python: Pin,
+    /// Sorted by name
+    packages: Vec<Pin>,
+}
+
+/// What `lock-status` returns.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct LockStatus {
+    lockfile: PathBuf,
+    fresh: bool,
+    /// Environments that are out of date, or are in posy.lock but not pyproject.toml.
+    stale: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct TreeNode {
+    name: String,
+    version: Version,
+    /// Which of the other locked packages this one requires. See
+    /// Blueprint::dependency_graph.
+    dependencies: Vec<String>,
+}
+
+/// What `tree` returns.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Tree {
+    python: Pin,
+    /// The packages that were asked for directly
+    roots: Vec<String>,
+    packages: Vec<TreeNode>,
+    /// Groups of packages that depend on each other in a circle, so there's no
+    /// following `dependencies` down to a leaf from them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cycles: Vec<Vec<String>>,
+}
+
+struct Daemon<'a> {
+    global: &'a GlobalConfig,
+    db: &'a PackageDB<'a>,
+    env_forest: &'a EnvForest,
+    project_dir: Option<&'a Path>,
+}
+
+impl<'a> Daemon<'a> {
+    fn call(&self, method: &str, params: Params) -> Result<Value, RpcError> {
+        let env_name = params.env.as_deref().unwrap_or(DEFAULT_ENV);
+        let result = match method {
+            "resolve" => self.resolve(env_name),
+            "lock-status" => self.lock_status(),
+            "tree" => self.tree(env_name),
+            "install" => {
+                self.install(env_name, params.python.as_deref(), params.frozen)
+            }
+            "shutdown" => Ok(Value::Null),
+            _ => {
+                let message = format!("no method called {method:?}");
+                return Err(RpcError::new(METHOD_NOT_FOUND, message));
+            }
+        };
+        result.map_err(|err| RpcError {
+            code: POSY_ERROR,
+            message: format!("{err:#}"),
+            data: Some(output::json_error(&err)),
+        })
+    }
+
+    fn project(&self) -> Result<Project> {
+        super::project(self.project_dir)
+    }
+
+    fn lockfile(project: &Project) -> Result<Lockfile> {
+        match project.lockfile_path() {
+            Some(path) => Lockfile::load(&path),
+            None => Ok(Lockfile::default()),
+        }
+    }
+
+    fn resolve(&self, env_name: &str) -> Result<Value> {
+        let project = self.project()?;
+        let trees = super::local_trees(&project)?;
+        let mut lockfile = Daemon::lockfile(&project)?;
+        let changes =
+            super::lock_env(self.db, &project, &trees, env_name, &mut lockfile)?;
+        let blueprint = &lockfile.environments[env_name].blueprint;
+        let mut packages: Vec<Pin> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| Pin::new(pin))
+            .collect();
+        packages.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(serde_json::to_value(ResolveResult {
+            fresh: changes.is_none(),
+            changes: changes.unwrap_or_default(),
+            python: Pin::new(&blueprint.pybi),
+            packages,
+        })?)
+    }
+
+    fn lock_status(&self) -> Result<Value> {
+        let project = self.project()?;
+        let Some(path) = project.lockfile_path() else {
+            bail!("no pyproject.toml found");
+        };
+        let trees = super::local_trees(&project)?;
+        let lockfile = Lockfile::load(&path)?;
+        let stale =
+            super::stale_environments(self.db, &project, &trees, &lockfile, true)?;
+        Ok(serde_json::to_value(LockStatus {
+            fresh: stale.is_empty() && path.exists(),
+            lockfile: path,
+            stale,
+        })?)
+    }
+
+    fn tree(&self, env_name: &str) -> Result<Value> {
+        let locked = super::locked_env(&self.project()?, env_name)?;
+        let graph = locked.blueprint.dependency_graph()?;
+        let mut packages: Vec<TreeNode> = locked
+            .blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| TreeNode {
+                name: pin.name.as_given().to_owned(),
+                version: pin.version.clone(),
+                dependencies: graph[&pin.name]
+                    .iter()
+                    .map(|name| name.as_given().to_owned())
+                    .collect(),
+            })
+            .collect();
+        packages.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let roots = locked
+            .brief
+            .requirements
+            .iter()
+            .filter(|req| graph.contains_key(&req.name))
+            .map(|req| req.name.as_given().to_owned())
+            .collect();
+        let cycles = locked
+            .blueprint
+            .cycles()?
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|name| name.as_given().to_owned())
+                    .collect()
+            })
+            .collect();
+        Ok(serde_json::to_value(Tree {
+            python: Pin::new(&locked.blueprint.pybi),
+            roots,
+            packages,
+            cycles,
+        })?)
+    }
+
+    fn install(
+        &self,
+        env_name: &str,
+        python: Option<&str>,
+        frozen: bool,
+    ) -> Result<Value> {
+        let project = self.project()?;
+        let python = python.map(python_override).transpose()?;
+        let env = super::project_env_in(
+            self.db,
+            self.env_forest,
+            &project,
+            env_name,
+            python.as_ref(),
+            &[],
+            frozen,
+        )?;
+        // the same as `posy provision --format json`: everything `posy run` would set
+        let env_vars = run_env_vars(self.global, &project, env_name, &env)?;
+        Ok(serde_json::to_value(Installed::new(env, env_vars))?)
+    }
+
+    /// Answers requests from one client until it goes away, or asks us to shut down.
+    /// Returns true in the second case. Only fails if the connection does.
+    fn serve<R: BufRead, W: Write>(&self, mut input: R, mut output: W) -> Result<bool> {
+        while let Some((framing, body)) = read_message(&mut input)? {
+            let (response, shutdown) = match body {
+                Ok(body) => {
+                    handle_message(&body, |method, params| self.call(method, params))
+                }
+                Err(error) => (Some(respond(Value::Null, Err(error))), false),
+            };
+            if let Some(response) = response {
+                write_message(&mut output, framing, &serde_json::to_vec(&response)?)?;
+            }
+            if shutdown {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl DaemonArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let options = super::db_options(&global, &project)?;
+        super::with_package_db(options, |db, env_forest| {
+            let daemon = Daemon {
+                global: &global,
+                db,
+                env_forest,
+                project_dir: self.project.as_deref(),
+            };
+            match &self.socket {
+                None => {
+                    let stdin = std::io::stdin();
+                    daemon.serve(stdin.lock(), std::io::stdout().lock())?;
+                    Ok(())
+                }
+                #[cfg(unix)]
+                Some(path) => {
+                    use std::os::unix::net::UnixListener;
+                    let listener = UnixListener::bind(path)
+                        .wrap_err_with(|| format!("listening on {}", path.display()))?;
+                    info!("Listening on {}", path.display());
+                    let result = (|| -> Result<()> {
+                        for stream in listener.incoming() {
+                            // one client's broken connection is no reason to stop
+                            // serving the rest
+                            let served =
+                                stream.map_err(eyre::Report::from).and_then(|stream| {
+                                    let input = BufReader::new(stream.try_clone()?);
+                                    daemon.serve(input, stream)
+                                });
+                            match served {
+                                Ok(true) => break,
+                                Ok(false) => (),
+                                Err(err) => warn!("dropping client: {err:#}"),
+                            }
+                        }
+                        Ok(())
+                    })();
+                    let _ = std::fs::remove_file(path);
+                    result
+                }
+                #[cfg(not(unix))]
+                Some(_) => bail!("--socket is only supported on Unix"),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_framing() -> Result<()> {
+        let mut input: &[u8] = b"{\"a\": 1}\n\n\
+            Content-Length: 8\r\nContent-Type: application/json\r\n\r\n{\"b\": 2}\
+            {\"c\": 3}\n";
+        let (framing, body) = read_message(&mut input)?.unwrap();
+        assert_eq!(
+            (framing, body.unwrap().as_slice()),
+            (Framing::Lines, &b"{\"a\": 1}\n"[..])
+        );
+        let (framing, body) = read_message(&mut input)?.unwrap();
+        assert_eq!(
+            (framing, body.unwrap().as_slice()),
+            (Framing::Headers, &b"{\"b\": 2}"[..])
+        );
+        let (framing, _) = read_message(&mut input)?.unwrap();
+        assert_eq!(framing, Framing::Lines);
+        assert!(read_message(&mut input)?.is_none());
+
+        // too big, either way: an error, and then on to the next one
+        let big = MAX_MESSAGE_SIZE + 10;
+        let mut framed = format!("Content-Length: {big}\r\n\r\n").into_bytes();
+        framed.extend(vec![b' '; big as usize]);
+        framed.extend(vec![b'x'; big as usize]);
+        framed.extend(b"\n{}\n");
+        let mut input = framed.as_slice();
+        let (framing, body) = read_message(&mut input)?.unwrap();
+        assert_eq!((framing, body.unwrap_err()), (Framing::Headers, too_big()));
+        let (framing, body) = read_message(&mut input)?.unwrap();
+        assert_eq!((framing, body.unwrap_err()), (Framing::Lines, too_big()));
+        let (_, body) = read_message(&mut input)?.unwrap();
+        assert_eq!(body.unwrap(), b"{}\n");
+
+        let mut input: &[u8] = b"Content-Length: lots\r\n\r\n{}";
+        let (_, body) = read_message(&mut input)?.unwrap();
+        assert_eq!(body.unwrap_err().code, PARSE_ERROR);
+        // but a connection that goes away mid-message is an error of its own
+        let mut input: &[u8] = b"Content-Length: 10\r\n";
+        assert!(read_message(&mut input).is_err());
+
+        let mut output = Vec::new();
+        write_message(&mut output, Framing::Headers, b"{}")?;
+        write_message(&mut output, Framing::Lines, b"{}")?;
+        assert_eq!(output, b"Content-Length: 2\r\n\r\n{}{}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_message() {
+        let call = |method: &str, params: Params| match method {
+            "echo" => Ok(json!(params.env)),
+            "shutdown" => Ok(Value::Null),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "nope")),
+        };
+        let handle = |body: &str| handle_message(body.as_bytes(), call);
+
+        let (response, shutdown) = handle(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "echo",
+                "params": {"env": "dev"}}"#,
+        );
+        assert_eq!(
+            response.unwrap(),
+            json!({"jsonrpc": "2.0", "id": 1, "result": "dev"})
+        );
+        assert!(!shutdown);
+
+        // notifications don't get answers
+        let (response, _) = handle(r#"{"jsonrpc": "2.0", "method": "echo"}"#);
+        assert!(response.is_none());
+
+        let error_code = |body: &str| handle(body).0.unwrap()["error"]["code"].clone();
+        assert_eq!(error_code("{oops"), json!(PARSE_ERROR));
+        assert_eq!(
+            error_code(r#"{"id": 1, "method": "echo"}"#),
+            json!(INVALID_REQUEST)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc": "1.0", "id": 1, "method": "echo"}"#),
+            json!(INVALID_REQUEST)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc": "2.0", "id": 1, "method": "nope"}"#),
+            json!(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error_code(
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "echo",
+                    "params": {"envv": 1}}"#
+            ),
+            json!(INVALID_PARAMS)
+        );
+
+        let (response, _) = handle_message(
+            br#"{"jsonrpc": "2.0", "id": 2, "method": "echo"}"#,
+            |_, _| panic!("oh no"),
+        );
+        let error = &response.unwrap()["error"];
+        assert_eq!(error["code"], json!(INTERNAL_ERROR));
+        assert!(error["message"].as_str().unwrap().contains("oh no"));
+
+        let (response, shutdown) =
+            handle(r#"{"jsonrpc": "2.0", "id": "x", "method": "shutdown"}"#);
+        assert_eq!(response.unwrap()["id"], json!("x"));
+        assert!(shutdown);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::config::GlobalConfig;
+use crate::kvstore::KVFileStore;
+use crate::prelude::*;
+use crate::project::DEFAULT_PYTHON;
+use crate::resolve::{AllowPre, Blueprint, Brief};
+use clap::Args;
+use std::ffi::OsString;
+
+#[derive(Args)]
+pub struct ExecArgs {
+    /// Re-resolve the tool's dependencies, instead of reusing whatever we picked the
+    /// last time this tool was run.
+    #[arg(long)]
+    refresh: bool,
+    /// Which Python to run the tool with.
+    #[arg(long, value_name = "REQUIREMENT", default_value = DEFAULT_PYTHON)]
+    python: PythonRequirement,
+    /// The script to run. Defaults to the name of the tool's package.
+    #[arg(long, value_name = "NAME")]
+    bin: Option<String>,
+    /// The tool to install, e.g. "black" or "black == 24.4".
+    tool: UserRequirement,
+    /// Arguments to pass through to the tool.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<OsString>,
+}
+
+impl ExecArgs {
+    pub fn run(self) -> Result<()> {
+        let global = GlobalConfig::load()?;
+        let brief = Brief {
+            python: self.python,
+            requirements: vec![self.tool.clone()],
+            allow_pre: AllowPre::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+
+        // Tool environments are just regular environments in the EnvForest, so all we
+        // need to remember is which Blueprint we picked for each Brief.
+        let blueprints = KVFileStore::new(&global.cache_dir().join("tool-blueprints"))?;
+        let key = serde_json::to_vec(&brief)?;
+        let handle = blueprints.lock(&key.as_slice())?;
+        let saved: Option<Blueprint> = handle
+            .reader()
+            .and_then(|r| serde_json::from_reader(r).ok());
+
+        let platforms = PybiPlatform::native_platforms()?;
+        let options = super::global_db_options(&global)?;
+        let env = super::with_package_db(options, |db, env_forest| {
+            let blueprint = match saved {
+                Some(blueprint) if !self.refresh => blueprint,
+                saved => {
+                    let blueprint =
+                        brief.resolve(db, platforms, saved.as_ref(), &[])?;
+                    let mut writer = handle.begin()?;
+                    serde_json::to_writer(&mut writer, &blueprint)?;
+                    writer.commit()?;
+                    blueprint
+                }
+            };
+            Ok(env_forest.get_env(db, &blueprint, platforms, &[])?)
+        })?;
+        drop(handle);
+
+        let bin = self
+            .bin
+            .unwrap_or_else(|| self.tool.name.as_given().to_string());
+        let mut argv = vec![OsString::from(bin)];
+        argv.extend(self.args);
+        super::exec(super::env_command(&env, &argv, &global.run_env_layers())?)
+    }
+}
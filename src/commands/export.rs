@@ -0,0 +1,335 @@
+use crate::exporters::{self, DockerfileOptions, GithubJob};
+use crate::exporters::{DOCKERFILE_HEADER, EXPORT_HEADER};
+use crate::lockfile::{blueprint_diff, LockedEnv, Lockfile, LOCKFILE_NAME};
+use crate::output;
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::project::{Project, DEFAULT_ENV};
+use crate::resolve::Blueprint;
+use clap::{Args, ValueEnum};
+use std::path::{Path, PathBuf};
+
+/// The glibc in the official Python images (Debian bookworm), which is as new as the
+/// manylinux wheels we pick for them can be.
+const IMAGE_GLIBC: &str = "2_36";
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Which tool's lockfile to write; `dockerfile` for a Dockerfile that installs
+    /// the same pins; `conda` for an environment.yml that pip installs them into; or
+    /// `github` for a snapshot of every environment, to submit to GitHub's dependency
+    /// graph.
+    #[arg(value_enum)]
+    tool: ExportTool,
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to export. (`github` always does all of
+    /// them.)
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+    /// Where to write it, or - for stdout. Defaults to uv.lock, pdm.lock, Dockerfile,
+    /// environment.yml or dependency-snapshot.json, next to posy.lock.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// For `dockerfile`: the image's platform, linux/amd64 or linux/arm64. The
+    /// environment gets re-resolved for it, and it's an error if that comes out any
+    /// different from posy.lock.
+    #[arg(long, value_name = "PLATFORM", default_value = "linux/amd64")]
+    platform: String,
+    /// For `github`: the commit the snapshot is for. Defaults to $GITHUB_SHA.
+    #[arg(long, value_name = "SHA")]
+    sha: Option<String>,
+    /// For `github`: the branch or tag the snapshot is for, like refs/heads/main.
+    /// Defaults to $GITHUB_REF.
+    #[arg(long = "ref", value_name = "REF")]
+    git_ref: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ExportTool {
+    Uv,
+    Pdm,
+    Dockerfile,
+    Conda,
+    Github,
+}
+
+impl ExportTool {
+    fn filename(self) -> &'static str {
+        match self {
+            ExportTool::Uv => "uv.lock",
+            ExportTool::Pdm => "pdm.lock",
+            ExportTool::Dockerfile => "Dockerfile",
+            ExportTool::Conda => "environment.yml",
+            ExportTool::Github => "dependency-snapshot.json",
+        }
+    }
+
+    /// What the files we write start with (give or take a line), so we know it's ours
+    /// to overwrite. GitHub's snapshots are JSON, which doesn't have comments.
+    fn header(self) -> Option<&'static str> {
+        match self {
+            ExportTool::Uv | ExportTool::Pdm | ExportTool::Conda => Some(EXPORT_HEADER),
+            ExportTool::Dockerfile => Some(DOCKERFILE_HEADER),
+            ExportTool::Github => None,
+        }
+    }
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct ExportReport {
+    path: PathBuf,
+    /// Unset for `github`, which covers every environment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<String>,
+    packages: usize,
+}
+
+/// The platform tag of the wheels an image for `platform` (as Docker spells it) can
+/// use.
+fn image_platform(platform: &str) -> Result<PybiPlatform> {
+    let arch = match platform {
+        "linux/amd64" | "linux/x86_64" => "x86_64",
+        "linux/arm64" | "linux/arm64/v8" | "linux/aarch64" => "aarch64",
+        _ => bail!(
+            "can't write a Dockerfile for {platform}; posy knows linux/amd64 and \
+             linux/arm64"
+        ),
+    };
+    Ok(PybiPlatform::new(&format!(
+        "manylinux_{IMAGE_GLIBC}_{arch}"
+    )))
+}
+
+/// `locked`'s pins, re-resolved for the image's platform. Usually nothing changes,
+/// but a different OS or CPU can pull in different dependencies, or need versions that
+/// have wheels for it. The image is supposed to run what posy.lock says, so if it
+/// can't, that's an error.
+fn image_blueprint(
+    db: &PackageDB,
+    locked: &LockedEnv,
+    platform: &str,
+) -> Result<Blueprint> {
+    let target = image_platform(platform)?;
+    let like = Some(&locked.blueprint);
+    let blueprint = locked.brief.resolve(db, &[&target], like, &[])?;
+    let changes = blueprint_diff(Some(&locked.blueprint), &blueprint);
+    if !changes.is_empty() {
+        let changes: Vec<String> = changes
+            .iter()
+            .map(|change| format!("\n  {}", change.render()))
+            .collect();
+        bail!(
+            "on {platform}, the pins would differ from {LOCKFILE_NAME}:{}",
+            changes.concat()
+        );
+    }
+    Ok(blueprint)
+}
+
+/// The local packages that `env_name` installs, relative to the project root.
+fn local_dirs(project: &Project, env_name: &str) -> Result<Vec<String>> {
+    let brief = project.config.brief(env_name)?;
+    let mut dirs = Vec::new();
+    for tree in super::local_trees(project)? {
+        if !brief.requirements.iter().any(|req| req.name == tree.name) {
+            continue;
+        }
+        let relative = tree.root.strip_prefix(&project.root).unwrap_or(&tree.root);
+        let dir = relative.to_string_lossy().replace('\\', "/");
+        dirs.push(if dir.is_empty() { ".".into() } else { dir });
+    }
+    Ok(dirs)
+}
+
+/// What to call the conda environment: after the project, and the environment too
+/// if it's not the default one.
+fn conda_env_name(project: &Project, env_name: &str) -> String {
+    let base = match &project.package {
+        Some(name) => name.normalized().to_owned(),
+        None => project
+            .root
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "project".into()),
+    };
+    match env_name {
+        DEFAULT_ENV => base,
+        _ => format!("{base}-{env_name}"),
+    }
+}
+
+/// Won't overwrite a file unless we wrote it, going by whether it has `header`.
+fn check_replaceable(path: &Path, header: &str) -> Result<()> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Ok(());
+    };
+    if !text.lines().take(2).any(|l| l.starts_with(header)) {
+        bail!(
+            "{} wasn't written by posy; pass -o to write somewhere else",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Which workflow run we're in, from the variables GitHub Actions sets. Outside of
+/// Actions, you have to say which commit this is for.
+fn github_job(sha: Option<String>, git_ref: Option<String>) -> Result<GithubJob> {
+    let var = |name: &str| std::env::var(name).ok();
+    let (Some(sha), Some(git_ref)) = (
+        sha.or_else(|| var("GITHUB_SHA")),
+        git_ref.or_else(|| var("GITHUB_REF")),
+    ) else {
+        bail!("pass --sha and --ref, or run this in GitHub Actions");
+    };
+    let correlator = match (var("GITHUB_WORKFLOW"), var("GITHUB_JOB")) {
+        (Some(workflow), Some(job)) => format!("{workflow}_{job}_posy"),
+        _ => "posy".into(),
+    };
+    let run_id = var("GITHUB_RUN_ID");
+    let html_url = match (var("GITHUB_SERVER_URL"), var("GITHUB_REPOSITORY"), &run_id) {
+        (Some(server), Some(repo), Some(id)) => {
+            Some(format!("{server}/{repo}/actions/runs/{id}"))
+        }
+        _ => None,
+    };
+    Ok(GithubJob {
+        sha,
+        git_ref,
+        correlator,
+        id: run_id.unwrap_or_else(|| "posy".into()),
+        html_url,
+    })
+}
+
+/// posy.lock's path from the root of the repository, as far as we can tell: the
+/// checkout in GitHub Actions, or else the current directory.
+fn source_location(lockfile: &Path) -> Result<String> {
+    let root = match std::env::var_os("GITHUB_WORKSPACE") {
+        Some(root) => PathBuf::from(root),
+        None => std::env::current_dir()?,
+    };
+    let relative = lockfile
+        .strip_prefix(&root)
+        .unwrap_or_else(|_| Path::new(LOCKFILE_NAME));
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
+
+impl ExportArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let (text, packages) = match self.tool {
+            ExportTool::Github => self.github_snapshot(&project)?,
+            _ => self.render(&project)?,
+        };
+
+        let path = match self.output {
+            Some(path) if path.as_os_str() == "-" => None,
+            Some(path) => Some(path),
+            None => Some(project.root.join(self.tool.filename())),
+        };
+        let Some(path) = path else {
+            print!("{text}");
+            return Ok(());
+        };
+        if let Some(header) = self.tool.header() {
+            check_replaceable(&path, header)?;
+        }
+        context!("Writing {}", path.display());
+        std::fs::write(&path, &text)?;
+        let env = match self.tool {
+            ExportTool::Github => None,
+            _ => Some(self.env_name),
+        };
+        if output::json() {
+            return output::print_json(&ExportReport {
+                path,
+                env,
+                packages,
+            });
+        }
+        match env {
+            Some(env) => println!(
+                "Wrote {packages} pinned packages from environment '{env}' to {}",
+                path.display()
+            ),
+            None => println!(
+                "Wrote {packages} pinned packages from all environments to {}",
+                path.display()
+            ),
+        }
+        Ok(())
+    }
+
+    /// The text to write, and how many pins are in it.
+    fn github_snapshot(&self, project: &Project) -> Result<(String, usize)> {
+        let Some(path) = project.lockfile_path() else {
+            bail!("no pyproject.toml found");
+        };
+        let lockfile = Lockfile::load(&path)?;
+        if lockfile.environments.is_empty() {
+            bail!("there's nothing in {LOCKFILE_NAME} yet; run `posy lock` first");
+        }
+        let job = github_job(self.sha.clone(), self.git_ref.clone())?;
+        let text = exporters::to_github_snapshot(
+            &lockfile.environments,
+            &source_location(&path)?,
+            &job,
+            std::time::SystemTime::now(),
+        )?;
+        let packages = lockfile
+            .environments
+            .values()
+            .map(|locked| locked.blueprint.wheels.len())
+            .sum();
+        Ok((text, packages))
+    }
+
+    /// The text to write for one of the lockfile formats or a Dockerfile, and how many
+    /// pins are in it.
+    fn render(&self, project: &Project) -> Result<(String, usize)> {
+        let global = super::global_config(project)?;
+        let locked = super::locked_env(project, &self.env_name)?;
+        let options = super::db_options(&global, project)?;
+        // uv wants an index to credit the packages to; that's whichever one comes
+        // after the pybi index
+        let registry = options
+            .index_urls
+            .iter()
+            .find(|url| **url != *super::PYBI_INDEX_URL)
+            .unwrap_or(&super::DEFAULT_INDEX_URLS[1])
+            .clone();
+        let (python, packages) = super::with_package_db(options, |db, _| {
+            let blueprint = match self.tool {
+                ExportTool::Dockerfile => image_blueprint(db, &locked, &self.platform)?,
+                _ => locked.blueprint.clone(),
+            };
+            let packages = exporters::exported_packages(db, &blueprint)?;
+            Ok((blueprint.pybi.version, packages))
+        })?;
+        let text = match self.tool {
+            ExportTool::Uv => exporters::to_uv_lock(&packages, &python, &registry),
+            ExportTool::Pdm => exporters::to_pdm_lock(&packages, &self.env_name),
+            ExportTool::Dockerfile => exporters::to_dockerfile(
+                &packages,
+                &python,
+                &DockerfileOptions {
+                    env_name: &self.env_name,
+                    platform: &self.platform,
+                    local_dirs: &local_dirs(project, &self.env_name)?,
+                },
+            )?,
+            ExportTool::Conda => exporters::to_conda_env(
+                &packages,
+                &python,
+                &conda_env_name(project, &self.env_name),
+            )?,
+            ExportTool::Github => unreachable!(),
+        };
+        Ok((text, packages.len()))
+    }
+}
@@ -0,0 +1,291 @@
+use crate::importers::{self, Imported, PoetryProject};
+use crate::lockfile::{Lockfile, PackageChange, LOCKFILE_NAME};
+use crate::output;
+use crate::prelude::*;
+use crate::project::{Project, DEFAULT_ENV};
+use clap::{Args, Subcommand};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ImportArgs {
+    #[command(subcommand)]
+    from: ImportFrom,
+}
+
+#[derive(Subcommand)]
+enum ImportFrom {
+    /// Pin an environment to what `pip freeze` says is installed somewhere.
+    Freeze {
+        /// The `pip freeze` output, or - for stdin.
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+        #[command(flatten)]
+        target: ImportTarget,
+    },
+    /// Lock an environment to the versions and files in a uv.lock. The project's
+    /// requirements still decide what goes in it.
+    Uv {
+        /// The uv.lock, or - for stdin. Defaults to the one next to pyproject.toml.
+        #[arg(value_name = "FILE")]
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        target: ImportTarget,
+    },
+    /// Lock an environment to the versions and files in a pdm.lock. The project's
+    /// requirements still decide what goes in it.
+    Pdm {
+        /// The pdm.lock, or - for stdin. Defaults to the one next to pyproject.toml.
+        #[arg(value_name = "FILE")]
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        target: ImportTarget,
+    },
+    /// Move a Poetry project over: copy its dependencies and groups from
+    /// `[tool.poetry]` into `[tool.posy]`, and lock every environment to the versions
+    /// and files in poetry.lock.
+    Poetry {
+        /// The Poetry project, if it's not the one in the current directory or its
+        /// parents.
+        #[arg(long, value_name = "DIR")]
+        project: Option<PathBuf>,
+        /// Work out the changes, but don't write pyproject.toml or posy.lock.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Where imported pins go.
+#[derive(Args)]
+struct ImportTarget {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to pin.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+    /// Work out the pins, but don't write posy.lock.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct ImportReport {
+    lockfile: PathBuf,
+    env: String,
+    /// How the environment's pins changed
+    changes: Vec<PackageChange>,
+    /// What we couldn't import, and why
+    skipped: Vec<String>,
+    /// Whether we wrote posy.lock. Always false with `--dry-run`.
+    updated: bool,
+}
+
+/// What `posy import poetry --format json` prints.
+#[derive(Serialize)]
+struct PoetryImportReport {
+    pyproject: PathBuf,
+    lockfile: PathBuf,
+    /// How each environment's pins changed
+    environments: BTreeMap<String, Vec<PackageChange>>,
+    /// What we couldn't import, and why
+    skipped: Vec<String>,
+    /// Whether we wrote pyproject.toml and posy.lock
+    updated: bool,
+}
+
+/// Reads a file, or stdin if it's `-`.
+fn read_input(path: &PathBuf) -> Result<String> {
+    if path.as_os_str() == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        context!("Reading {}", path.display());
+        Ok(std::fs::read_to_string(path)?)
+    }
+}
+
+impl ImportArgs {
+    pub fn run(self) -> Result<()> {
+        match self.from {
+            ImportFrom::Freeze { path, target } => {
+                target.import(importers::parse_freeze(&read_input(&path)?))
+            }
+            ImportFrom::Uv { path, target } => {
+                let text = read_input(&target.input_path(path, "uv.lock")?)?;
+                target.import(importers::parse_uv_lock(&text)?)
+            }
+            ImportFrom::Pdm { path, target } => {
+                let text = read_input(&target.input_path(path, "pdm.lock")?)?;
+                target.import(importers::parse_pdm_lock(&text)?)
+            }
+            ImportFrom::Poetry { project, dry_run } => import_poetry(project, dry_run),
+        }
+    }
+}
+
+fn import_poetry(project: Option<PathBuf>, dry_run: bool) -> Result<()> {
+    let project = super::project(project.as_deref())?;
+    let global = super::global_config(&project)?;
+    let root = project.root;
+    let pyproject_path = root.join("pyproject.toml");
+    if !pyproject_path.is_file() {
+        bail!("no pyproject.toml found");
+    }
+    let pyproject = std::fs::read_to_string(&pyproject_path)?;
+    let poetry = PoetryProject::parse(&pyproject)?;
+    let poetry_lock = root.join("poetry.lock");
+    let imported = if poetry_lock.is_file() {
+        context!("Reading {}", poetry_lock.display());
+        importers::parse_poetry_lock(&std::fs::read_to_string(&poetry_lock)?)?
+    } else {
+        warn!(
+            "no poetry.lock, so there are no versions to keep; resolving from scratch"
+        );
+        Imported::default()
+    };
+    let pyproject = poetry.write_posy_config(&pyproject)?;
+    let project = Project::from_pyproject(&root, &pyproject)?;
+    let mut skipped = poetry.skipped.clone();
+    skipped.extend(imported.skipped.iter().cloned());
+    for skipped in &skipped {
+        warn!("skipping {skipped}");
+    }
+
+    let path = root.join(LOCKFILE_NAME);
+    let mut lockfile = Lockfile::load(&path)?;
+    let options = super::db_options(&global, &project)?;
+    let environments = super::with_package_db(options, |db, _| {
+        let mut environments = BTreeMap::new();
+        for env_name in project.config.environment_names() {
+            context!("Locking environment '{env_name}'");
+            let changes = importers::lock_imported(
+                db,
+                &project,
+                env_name,
+                &imported,
+                PybiPlatform::native_platforms()?,
+                &mut lockfile,
+            )?;
+            environments.insert(env_name.to_owned(), changes);
+        }
+        Ok(environments)
+    })?;
+    if !dry_run {
+        std::fs::write(&pyproject_path, &pyproject)?;
+        lockfile.save(&path)?;
+    }
+
+    if output::json() {
+        return output::print_json(&PoetryImportReport {
+            pyproject: pyproject_path,
+            lockfile: path,
+            environments,
+            skipped,
+            updated: !dry_run,
+        });
+    }
+    for (env_name, changes) in &environments {
+        println!("Environment '{env_name}':");
+        for change in changes {
+            println!("  {}", change.render());
+        }
+    }
+    if dry_run {
+        println!("Dry run: not writing pyproject.toml or {LOCKFILE_NAME}");
+    } else {
+        println!(
+            "Added [tool.posy] to {} and locked it in {}. [tool.poetry] is still \
+             there; delete it once you're done with Poetry.",
+            pyproject_path.display(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+impl ImportTarget {
+    /// `path`, or if that's not given, the file called `default` at the project root.
+    fn input_path(&self, path: Option<PathBuf>, default: &str) -> Result<PathBuf> {
+        match path {
+            Some(path) => Ok(path),
+            None => Ok(super::project(self.project.as_deref())?.root.join(default)),
+        }
+    }
+
+    fn import(self, imported: Imported) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let Some(path) = project.lockfile_path() else {
+            bail!("no pyproject.toml found; create a project first with `posy init`");
+        };
+        project.config.environment(&self.env_name)?;
+        for skipped in &imported.skipped {
+            warn!("skipping {skipped}");
+        }
+        if imported.requirements.is_empty() && imported.pins.is_empty() {
+            bail!("nothing to import");
+        }
+        if imported.requirements.is_empty()
+            && project
+                .config
+                .brief(&self.env_name)?
+                .requirements
+                .is_empty()
+        {
+            bail!(
+                "environment '{}' doesn't have any requirements for the imported pins \
+                 to apply to; add them to [tool.posy] first",
+                self.env_name
+            );
+        }
+        let mut lockfile = Lockfile::load(&path)?;
+        let options = super::db_options(&global, &project)?;
+        let changes = super::with_package_db(options, |db, _| {
+            importers::lock_imported(
+                db,
+                &project,
+                &self.env_name,
+                &imported,
+                PybiPlatform::native_platforms()?,
+                &mut lockfile,
+            )
+        })?;
+        if !self.dry_run {
+            lockfile.save(&path)?;
+        }
+
+        if output::json() {
+            return output::print_json(&ImportReport {
+                lockfile: path,
+                env: self.env_name,
+                changes,
+                skipped: imported.skipped,
+                updated: !self.dry_run,
+            });
+        }
+        for change in &changes {
+            println!("  {}", change.render());
+        }
+        if self.dry_run {
+            println!("Dry run: not writing {LOCKFILE_NAME}");
+        } else if imported.requirements.is_empty() {
+            println!(
+                "Locked environment '{}' in {}, keeping the imported versions.",
+                self.env_name,
+                path.display()
+            );
+        } else {
+            println!(
+                "Pinned environment '{}' in {}. Add your direct requirements to \
+                 pyproject.toml; `posy lock` will keep these versions where it can.",
+                self.env_name,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use crate::lockfile::{Lockfile, LOCKFILE_NAME};
+use crate::output;
+use crate::prelude::*;
+use crate::project::{Project, DEFAULT_ENV, DEFAULT_PYTHON};
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct InitArgs {
+    /// Where to create the project. Defaults to the current directory; if it doesn't
+    /// exist, it gets created.
+    #[arg(value_name = "DIR")]
+    dir: Option<PathBuf>,
+    /// The package name. Defaults to the directory's name.
+    #[arg(long)]
+    name: Option<String>,
+    /// Which Python the project wants.
+    #[arg(long, value_name = "REQUIREMENT", default_value = DEFAULT_PYTHON)]
+    python: PythonRequirement,
+    /// Also create an importable package under src/, to put your code in.
+    #[arg(long)]
+    package: bool,
+    /// Don't resolve the new project's environment and write posy.lock. (That needs
+    /// to talk to the package index.)
+    #[arg(long)]
+    no_lock: bool,
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct InitReport {
+    root: PathBuf,
+    name: String,
+    /// Everything we created, relative to `root`
+    created: Vec<PathBuf>,
+}
+
+/// The new project's pyproject.toml. setuptools can build a project with nothing in
+/// it, and finds a src/ package on its own, so the same one works either way.
+fn pyproject_toml(name: &PackageName, python: &PythonRequirement) -> String {
+    indoc::formatdoc! {
+        r#"
+            [build-system]
+            requires = ["setuptools >= 61"]
+            build-backend = "setuptools.build_meta"
+
+            [project]
+            name = "{name}"
+            version = "0.1.0"
+            dependencies = []
+
+            [tool.posy]
+            python = "{python}"
+        "#,
+        name = name.as_given(),
+        python = python,
+    }
+}
+
+/// The name you'd `import`.
+fn module_name(name: &PackageName) -> String {
+    name.normalized().replace('-', "_")
+}
+
+impl InitArgs {
+    pub fn run(self) -> Result<()> {
+        let root = match self.dir {
+            Some(dir) => dir,
+            None => std::env::current_dir()?,
+        };
+        let pyproject = root.join("pyproject.toml");
+        if pyproject.exists() {
+            bail!("{} already exists", pyproject.display());
+        }
+        let name: PackageName = match &self.name {
+            Some(name) => name.as_str().try_into()?,
+            None => {
+                let root = fs::canonicalize(&root).unwrap_or_else(|_| root.clone());
+                let Some(dir_name) = root.file_name() else {
+                    bail!("can't guess a package name for {}", root.display())
+                };
+                dir_name.to_string_lossy().as_ref().try_into().wrap_err(
+                    "can't use the directory name as a package name; use --name",
+                )?
+            }
+        };
+
+        let mut created = Vec::new();
+        let mut create = |path: &Path, contents: &str| -> Result<()> {
+            let full = root.join(path);
+            if let Some(parent) = full.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&full, contents)?;
+            created.push(path.to_owned());
+            Ok(())
+        };
+        let contents = pyproject_toml(&name, &self.python);
+        create(Path::new("pyproject.toml"), &contents)?;
+        if self.package {
+            let src = Path::new("src").join(module_name(&name));
+            create(&src.join("__init__.py"), "")?;
+        }
+
+        if !self.no_lock {
+            let project = Project::load(&root)?;
+            let global = super::global_config(&project)?;
+            let trees = super::local_trees(&project)?;
+            let mut lockfile = Lockfile::default();
+            let options = super::db_options(&global, &project)?;
+            super::with_package_db(options, |db, _| {
+                super::lock_env(db, &project, &trees, DEFAULT_ENV, &mut lockfile)
+            })?;
+            lockfile.save(&root.join(LOCKFILE_NAME))?;
+            created.push(LOCKFILE_NAME.into());
+        }
+
+        if output::json() {
+            return output::print_json(&InitReport {
+                root,
+                name: name.as_given().to_string(),
+                created,
+            });
+        }
+        println!("Created project {} in {}", name.as_given(), root.display());
+        for path in &created {
+            println!("  {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::ProjectConfig;
+
+    #[test]
+    fn test_init_pyproject() -> Result<()> {
+        let name: PackageName = "My.Project".try_into()?;
+        let python: PythonRequirement = DEFAULT_PYTHON.try_into()?;
+        let contents = pyproject_toml(&name, &python);
+        let config = ProjectConfig::parse_from(&contents)?;
+        assert_eq!(config.python.unwrap().to_string(), DEFAULT_PYTHON);
+
+        let tmp = tempfile::tempdir()?;
+        fs::write(tmp.path().join("pyproject.toml"), &contents)?;
+        let project = Project::load(tmp.path())?;
+        assert_eq!(project.package, Some(name.clone()));
+        assert_eq!(module_name(&name), "my_project");
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::output;
+use crate::prelude::*;
+use crate::project::{Project, DEFAULT_ENV};
+use clap::{Args, Subcommand};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Args)]
+pub struct KernelArgs {
+    #[command(subcommand)]
+    command: KernelCommand,
+}
+
+#[derive(Subcommand)]
+enum KernelCommand {
+    /// Register one of the project's environments as a Jupyter kernel.
+    Install(InstallArgs),
+    /// Remove a kernel that was registered by `posy kernel install`.
+    Remove {
+        /// The kernel's name, as printed by `posy kernel install`.
+        name: String,
+    },
+}
+
+#[derive(Args)]
+struct InstallArgs {
+    /// Which of the project's environments the kernel should use.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+    /// The kernel's name. Defaults to one based on the project directory and
+    /// environment.
+    #[arg(long)]
+    name: Option<String>,
+    /// The name Jupyter shows in its kernel picker.
+    #[arg(long)]
+    display_name: Option<String>,
+}
+
+/// Where Jupyter looks for per-user kernelspecs. This mirrors `jupyter --data-dir`.
+fn kernels_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("JUPYTER_DATA_DIR") {
+        return Ok(PathBuf::from(dir).join("kernels"));
+    }
+    let base =
+        directories::BaseDirs::new().ok_or(eyre!("couldn't find home directory"))?;
+    let data_dir = if cfg!(target_os = "macos") {
+        base.home_dir().join("Library").join("Jupyter")
+    } else {
+        // $XDG_DATA_HOME/jupyter on Linux, %APPDATA%\jupyter on Windows
+        base.data_dir().join("jupyter")
+    };
+    Ok(data_dir.join("kernels"))
+}
+
+/// Jupyter only allows ASCII letters, digits, '.', '-', and '_' in kernel names, and
+/// they're case-insensitive.
+fn kernel_name(project_root: &Path, env_name: &str) -> String {
+    let dir_name = project_root
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "project".into());
+    format!("posy-{dir_name}-{env_name}")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl KernelArgs {
+    pub fn run(self) -> Result<()> {
+        match self.command {
+            KernelCommand::Install(args) => args.run(),
+            KernelCommand::Remove { name } => remove(&name),
+        }
+    }
+}
+
+impl InstallArgs {
+    fn run(self) -> Result<()> {
+        let Some(project) = Project::find(&std::env::current_dir()?)? else {
+            bail!("no pyproject.toml found; kernels are registered per-project");
+        };
+        let root = fs::canonicalize(&project.root)?;
+
+        // Build the environment now, so that starting the kernel the first time isn't
+        // mysteriously slow, and so we can check that it can actually run a kernel.
+        let global = super::global_config(&project)?;
+        let env = super::project_env(
+            &global,
+            &project,
+            &self.env_name,
+            &[],
+            false,
+            false,
+            false,
+        )?;
+        let mut check = Command::new(&env.python);
+        check.args(["-c", "import ipykernel"]).envs(env.env_vars()?);
+        if !check.status()?.success() {
+            warn!(
+                "ipykernel isn't installed in environment '{}'; add it to the \
+                 requirements or the kernel won't start",
+                self.env_name
+            );
+        }
+
+        let name = self
+            .name
+            .unwrap_or_else(|| kernel_name(&root, &self.env_name));
+        let display_name = self.display_name.unwrap_or_else(|| {
+            let dir_name = root.file_name().unwrap_or_default().to_string_lossy();
+            format!("{dir_name} ({}, posy)", self.env_name)
+        });
+        // We don't point Jupyter straight at the environment's interpreter, because
+        // the environment changes whenever the project's requirements do. Going through
+        // `posy run` means the kernel always gets the current one.
+        let posy = std::env::current_exe()?;
+        let kernelspec = json!({
+            "argv": [
+                posy,
+                "run",
+                "--project",
+                root,
+                "--env",
+                self.env_name,
+                "python",
+                "-m",
+                "ipykernel_launcher",
+                "-f",
+                "{connection_file}",
+            ],
+            "display_name": display_name,
+            "language": "python",
+            "metadata": {
+                "posy": {
+                    "project": root,
+                    "env": self.env_name,
+                },
+            },
+        });
+
+        let kernel_dir = kernels_dir()?.join(&name);
+        fs::create_dir_all(&kernel_dir)?;
+        fs::write(
+            kernel_dir.join("kernel.json"),
+            serde_json::to_string_pretty(&kernelspec)?,
+        )?;
+        if output::json() {
+            output::print_json(&json!({ "name": name, "path": kernel_dir }))?;
+        } else {
+            println!("Installed kernel '{name}' in {}", kernel_dir.display());
+        }
+        Ok(())
+    }
+}
+
+fn remove(name: &str) -> Result<()> {
+    let kernel_dir = kernels_dir()?.join(name);
+    let kernelspec: serde_json::Value =
+        match fs::read_to_string(kernel_dir.join("kernel.json")) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                bail!("no kernel named '{name}' in {}", kernel_dir.display())
+            }
+            Err(e) => Err(e)?,
+        };
+    // Don't go deleting kernels that belong to someone else.
+    if kernelspec.pointer("/metadata/posy").is_none() {
+        bail!("kernel '{name}' wasn't installed by posy; not removing it");
+    }
+    fs::remove_dir_all(&kernel_dir)?;
+    if output::json() {
+        output::print_json(&json!({ "name": name, "path": kernel_dir }))?;
+    } else {
+        println!("Removed kernel '{name}'");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kernel_name() {
+        assert_eq!(
+            kernel_name(Path::new("/home/me/My Project"), "default"),
+            "posy-my_project-default"
+        );
+        assert_eq!(
+            kernel_name(Path::new("/src/web.app"), "gpu"),
+            "posy-web.app-gpu"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::output;
+use crate::package_db::WheelBuilder;
+use crate::prelude::*;
+use crate::project::DEFAULT_ENV;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct LicensesArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to report on.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+    /// Print one CSV row per package, instead of grouping them by license.
+    #[arg(long)]
+    csv: bool,
+}
+
+/// Where we got a package's license from, best first.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum LicenseSource {
+    LicenseExpression,
+    Classifier,
+    LicenseField,
+    Unknown,
+}
+
+/// `--format json` prints a list of these, one for each pinned package.
+#[derive(Debug, Serialize)]
+struct PackageLicense {
+    name: String,
+    version: Version,
+    license: String,
+    source: LicenseSource,
+    /// The raw metadata, for anyone who wants to double-check our summary
+    license_expression: Option<String>,
+    license_classifiers: Vec<String>,
+    license_field: Option<String>,
+    license_files: Vec<String>,
+}
+
+/// The License field is often the whole license text, which is no good as a summary.
+const MAX_LICENSE_FIELD_LEN: usize = 60;
+
+fn summarize(metadata: &WheelCoreMetadata) -> (String, LicenseSource) {
+    if let Some(expr) = &metadata.license_expression {
+        return (expr.trim().to_string(), LicenseSource::LicenseExpression);
+    }
+    let classifiers: Vec<&str> = metadata
+        .display
+        .classifiers
+        .iter()
+        .filter_map(|c| c.strip_prefix("License :: "))
+        .map(|c| c.rsplit(" :: ").next().unwrap().trim())
+        .collect();
+    if !classifiers.is_empty() {
+        return (classifiers.join(", "), LicenseSource::Classifier);
+    }
+    if let Some(field) = &metadata.license {
+        let first_line = field.lines().map(str::trim).find(|l| !l.is_empty());
+        if let Some(line) = first_line {
+            let useful = !line.eq_ignore_ascii_case("unknown");
+            if useful && line.len() <= MAX_LICENSE_FIELD_LEN {
+                return (line.to_string(), LicenseSource::LicenseField);
+            }
+        }
+    }
+    ("UNKNOWN".into(), LicenseSource::Unknown)
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into()
+    }
+}
+
+impl LicensesArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let locked = super::locked_env(&project, &self.env_name)?;
+        let blueprint = &locked.blueprint;
+        let options = super::db_options(&global, &project)?;
+        let report = super::with_package_db(options, |db, _| {
+            // In case something only has an sdist, and we don't have its metadata
+            // cached from when we resolved.
+            let builder = WheelBuilder::new(
+                db,
+                &blueprint.pybi.name,
+                &blueprint.pybi.version,
+                PybiPlatform::native_platforms()?,
+                &[],
+            )?;
+            let mut report = Vec::new();
+            for (pinned, _) in &blueprint.wheels {
+                let ais = db.artifacts_for_version(&pinned.name, &pinned.version)?;
+                let (_, metadata) = db.get_metadata::<Wheel, _>(ais, Some(&builder))?;
+                let (license, source) = summarize(&metadata);
+                report.push(PackageLicense {
+                    name: pinned.name.as_given().to_string(),
+                    version: pinned.version.clone(),
+                    license,
+                    source,
+                    license_expression: metadata.license_expression,
+                    license_classifiers: metadata
+                        .display
+                        .classifiers
+                        .into_iter()
+                        .filter(|c| c.starts_with("License :: "))
+                        .collect(),
+                    license_field: metadata.license,
+                    license_files: metadata.license_files,
+                });
+            }
+            Ok(report)
+        })?;
+
+        if output::json() {
+            return output::print_json(&report);
+        }
+        if self.csv {
+            println!("name,version,license,source");
+            for p in &report {
+                println!(
+                    "{},{},{},{}",
+                    csv_field(&p.name),
+                    p.version,
+                    csv_field(&p.license),
+                    serde_json::to_value(p.source)?.as_str().unwrap()
+                );
+            }
+            return Ok(());
+        }
+        let mut grouped: BTreeMap<&str, Vec<&PackageLicense>> = BTreeMap::new();
+        for p in &report {
+            grouped.entry(&p.license).or_default().push(p);
+        }
+        for (license, packages) in grouped {
+            println!("{license} ({})", packages.len());
+            for p in packages {
+                println!("  {} {}", p.name, p.version);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summarize_license() {
+        let metadata = |text: &str| -> WheelCoreMetadata {
+            let text = format!("Metadata-Version: 2.1\nName: x\nVersion: 1\n{text}");
+            text.as_bytes().try_into().unwrap()
+        };
+        assert_eq!(
+            summarize(&metadata(
+                "License-Expression: MIT OR Apache-2.0\n\
+                 Classifier: License :: OSI Approved :: MIT License\n"
+            )),
+            ("MIT OR Apache-2.0".into(), LicenseSource::LicenseExpression)
+        );
+        assert_eq!(
+            summarize(&metadata(
+                "License: BSD\nClassifier: License :: OSI Approved :: MIT License\n"
+            )),
+            ("MIT License".into(), LicenseSource::Classifier)
+        );
+        assert_eq!(
+            summarize(&metadata("License: BSD-3-Clause\n")),
+            ("BSD-3-Clause".into(), LicenseSource::LicenseField)
+        );
+        assert_eq!(
+            summarize(&metadata("License: UNKNOWN\n")),
+            ("UNKNOWN".into(), LicenseSource::Unknown)
+        );
+        assert_eq!(csv_field("MIT, BSD"), "\"MIT, BSD\"");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::GlobalConfig;
+use crate::lockfile::{Lockfile, PackageChange, LOCKFILE_NAME};
+use crate::output;
+use crate::package_db::{LocalTree, PackageDB};
+use crate::prelude::*;
+use crate::project::Project;
+use crate::resolve::MetadataDivergence;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::{lock_env, stale_environments};
+
+#[derive(Args)]
+pub struct LockArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Don't change anything; just fail if posy.lock is missing or out of date. Useful
+    /// in CI.
+    #[arg(long)]
+    check: bool,
+    /// Work out what would change, but don't write posy.lock.
+    #[arg(long, conflicts_with = "check")]
+    dry_run: bool,
+    /// Also fetch the metadata of every wheel of every pinned version, not just the
+    /// one the resolver used, and fail if any of them disagree about their
+    /// dependencies or Requires-Python. Those would break installs on other
+    /// platforms, so posy.lock doesn't get written until they're fixed. This can take
+    /// a while.
+    #[arg(long)]
+    check_wheels: bool,
+    /// Resolve every environment again, against the index as it was when it was
+    /// locked (ignoring anything uploaded since), instead of just the out of date
+    /// ones. With `--check`, fails unless that reproduces posy.lock exactly.
+    #[arg(long)]
+    snapshot: bool,
+}
+
+/// What `--format json` prints.
+#[derive(Serialize)]
+struct LockReport<'a> {
+    lockfile: &'a Path,
+    /// Whether posy.lock matched pyproject.toml before we ran. (With `--check`, this
+    /// is the answer.)
+    fresh: bool,
+    /// Environments that were out of date, or are in posy.lock but not
+    /// pyproject.toml.
+    stale: Vec<String>,
+    /// How the pins changed in each environment that had to be re-resolved. Empty
+    /// with `--check`.
+    changes: BTreeMap<String, Vec<PackageChange>>,
+    /// Whether we wrote a new posy.lock. Always false with `--check` or `--dry-run`,
+    /// or if `--check-wheels` found anything.
+    updated: bool,
+    /// With `--check-wheels`, the pinned wheels whose metadata doesn't match what we
+    /// resolved with.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    divergent_wheels: Vec<MetadataDivergence>,
+}
+
+/// Every divergence in any of `lockfile`'s environments, once each.
+fn divergent_wheels(
+    db: &PackageDB,
+    lockfile: &Lockfile,
+) -> Result<Vec<MetadataDivergence>> {
+    let mut divergences = Vec::new();
+    for locked in lockfile.environments.values() {
+        divergences.extend(locked.blueprint.metadata_divergences(db)?);
+    }
+    divergences.sort_by(|a, b| a.wheel.cmp(&b.wheel));
+    divergences.dedup_by(|a, b| a.wheel == b.wheel);
+    Ok(divergences)
+}
+
+/// Logs `divergences`, and fails if there are any.
+fn report_divergences(divergences: &[MetadataDivergence]) -> Result<()> {
+    if divergences.is_empty() {
+        return Ok(());
+    }
+    for divergence in divergences {
+        warn!(
+            "{} doesn't match the metadata we resolved {} {} with (from {}):",
+            divergence.wheel,
+            divergence.name.as_given(),
+            divergence.version,
+            divergence.resolved_from
+        );
+        for difference in &divergence.differences {
+            warn!("  {difference}");
+        }
+    }
+    bail!(
+        "found {} wheels with inconsistent metadata",
+        divergences.len()
+    );
+}
+
+impl LockArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let Some(path) = project.lockfile_path() else {
+            bail!("no pyproject.toml found; there's nothing to lock");
+        };
+        let trees = super::local_trees(&project)?;
+        let mut lockfile = Lockfile::load(&path)?;
+        if self.snapshot {
+            return self.run_snapshot(&project, &global, &trees, &path, lockfile);
+        }
+        let env_names = project.config.environment_names();
+        let options = super::db_options(&global, &project)?;
+
+        super::with_package_db(options, |db, _| {
+            let stale =
+                stale_environments(db, &project, &trees, &lockfile, !self.check)?;
+            let mut report = LockReport {
+                lockfile: &path,
+                fresh: stale.is_empty() && path.exists(),
+                stale,
+                changes: BTreeMap::new(),
+                updated: false,
+                divergent_wheels: Vec::new(),
+            };
+
+            if self.check {
+                if self.check_wheels && report.stale.is_empty() {
+                    report.divergent_wheels = divergent_wheels(db, &lockfile)?;
+                }
+                if output::json() {
+                    output::print_json(&report)?;
+                }
+                if !report.stale.is_empty() {
+                    bail!(
+                        "{LOCKFILE_NAME} is out of date for: {}; run `posy lock` to \
+                         update it",
+                        report.stale.join(", ")
+                    );
+                }
+                report_divergences(&report.divergent_wheels)?;
+                if !output::json() {
+                    println!("{LOCKFILE_NAME} is up to date");
+                }
+                return Ok(());
+            }
+
+            let before = lockfile.environments.len();
+            lockfile
+                .environments
+                .retain(|name, _| env_names.contains(&name.as_str()));
+            let mut changed = lockfile.environments.len() != before;
+            for env_name in &env_names {
+                let env_changes =
+                    lock_env(db, &project, &trees, env_name, &mut lockfile)?;
+                if let Some(env_changes) = env_changes {
+                    changed = true;
+                    report.changes.insert(env_name.to_string(), env_changes);
+                }
+            }
+            let needs_save = changed || !path.exists();
+            if self.check_wheels {
+                report.divergent_wheels = divergent_wheels(db, &lockfile)?;
+            }
+            let consistent = report.divergent_wheels.is_empty();
+            if needs_save && !self.dry_run && consistent {
+                lockfile.save(&path)?;
+                report.updated = true;
+            }
+            if output::json() {
+                output::print_json(&report)?;
+            } else if report.updated {
+                println!("Updated {}", path.display());
+            } else if !needs_save {
+                println!("{LOCKFILE_NAME} is already up to date");
+            } else if self.dry_run {
+                println!("Dry run: not writing {LOCKFILE_NAME}");
+            } else {
+                println!("Not writing {LOCKFILE_NAME}");
+            }
+            report_divergences(&report.divergent_wheels)
+        })
+    }
+
+    /// `--snapshot`. Environments might have been locked at different times, so each
+    /// one gets its own PackageDB, pinned to its own snapshot.
+    fn run_snapshot(
+        &self,
+        project: &Project,
+        global: &GlobalConfig,
+        trees: &[LocalTree],
+        path: &Path,
+        mut lockfile: Lockfile,
+    ) -> Result<()> {
+        let mut report = LockReport {
+            lockfile: path,
+            fresh: true,
+            stale: Vec::new(),
+            changes: BTreeMap::new(),
+            updated: false,
+            divergent_wheels: Vec::new(),
+        };
+        for env_name in project.config.environment_names() {
+            let Some(locked) = lockfile.environments.get(env_name) else {
+                bail!(
+                    "environment '{env_name}' isn't in {LOCKFILE_NAME} yet, so there's \
+                     no snapshot to resolve it against"
+                );
+            };
+            let Some(snapshot) = &locked.snapshot else {
+                bail!(
+                    "{LOCKFILE_NAME} doesn't say what the index looked like when \
+                     environment '{env_name}' was locked"
+                );
+            };
+            let mut options = super::db_options(global, project)?;
+            options.snapshot = Some(snapshot.time()?);
+            let before = serde_json::to_value(locked)?;
+            let changes = super::with_package_db(options, |db, _| {
+                lock_env(db, project, trees, env_name, &mut lockfile)
+            })?;
+            if serde_json::to_value(&lockfile.environments[env_name])? != before {
+                report.stale.push(env_name.to_string());
+                report
+                    .changes
+                    .insert(env_name.to_string(), changes.unwrap_or_default());
+            }
+        }
+        report.fresh = report.stale.is_empty();
+
+        if self.check {
+            if output::json() {
+                output::print_json(&report)?;
+            }
+            if !report.fresh {
+                bail!(
+                    "resolving against the recorded snapshots doesn't reproduce \
+                     {LOCKFILE_NAME} for: {}",
+                    report.stale.join(", ")
+                );
+            }
+            if !output::json() {
+                println!("{LOCKFILE_NAME} is reproducible from its snapshots");
+            }
+            return Ok(());
+        }
+        if !report.fresh && !self.dry_run {
+            lockfile.save(path)?;
+            report.updated = true;
+        }
+        if output::json() {
+            output::print_json(&report)?;
+        } else if report.updated {
+            println!("Updated {}", path.display());
+        } else if !report.fresh {
+            println!("Dry run: not writing {LOCKFILE_NAME}");
+        } else {
+            println!("{LOCKFILE_NAME} is reproducible from its snapshots");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,664 @@
+mod audit;
+mod bundle;
+mod completions;
+mod config;
+mod daemon;
+mod exec;
+mod export;
+mod import;
+mod init;
+mod kernel;
+mod licenses;
+mod lock;
+mod outdated;
+mod preheat;
+mod provision;
+mod python;
+mod run;
+mod wheel;
+mod why_not;
+
+pub use audit::AuditArgs;
+pub use bundle::BundleArgs;
+pub use completions::{CompleteArgs, CompletionsArgs};
+pub use config::ConfigArgs;
+pub use daemon::DaemonArgs;
+pub use exec::ExecArgs;
+pub use export::ExportArgs;
+pub use import::ImportArgs;
+pub use init::InitArgs;
+pub use kernel::KernelArgs;
+pub use licenses::LicensesArgs;
+pub use lock::LockArgs;
+pub use outdated::OutdatedArgs;
+pub use preheat::PreheatArgs;
+pub use provision::ProvisionArgs;
+pub use python::PythonArgs;
+pub use run::RunArgs;
+pub use wheel::WheelArgs;
+pub use why_not::WhyNotArgs;
+
+use crate::config::{EnvConfig, GlobalConfig};
+use crate::env::{Env, EnvForest};
+use crate::kvstore::KVDirStore;
+use crate::lockfile::{
+    blueprint_diff, IndexSnapshot, LockedEnv, Lockfile, PackageChange, LOCKFILE_NAME,
+};
+use crate::package_db::{
+    AttestationConfig, CredentialHelpers, HelperCommand, HostAllowlist, LocalTree,
+    PackageDB, TrustedRoot, WheelBuilder, Wheelhouse,
+};
+use crate::pip_config::PipIndexConfig;
+use crate::policy::Policy;
+use crate::prelude::*;
+use crate::project::{Project, ProjectConfig};
+use crate::resolve::{external_requirements, Blueprint, Brief};
+use crate::tree::UnpackLimits;
+use once_cell::unsync::OnceCell;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+// "cpython_unofficial" pybis live here
+static PYBI_INDEX_URL: Lazy<Url> =
+    Lazy::new(|| Url::parse("https://pybi.vorpus.org").unwrap());
+
+static DEFAULT_INDEX_URLS: Lazy<Vec<Url>> = Lazy::new(|| {
+    vec![
+        PYBI_INDEX_URL.clone(),
+        Url::parse("https://pypi.org/simple/").unwrap(),
+    ]
+});
+
+/// Which indexes to use: posy.toml's `index-urls` if it has any, or else pip's, if
+/// posy.toml says to look at those. Empty means the defaults.
+fn index_urls(global: &GlobalConfig) -> Result<Vec<Url>> {
+    let mut urls = global.index_urls.clone();
+    if urls.is_empty() && global.use_pip_config {
+        let pip = PipIndexConfig::load()?;
+        if !pip.is_empty() {
+            urls = pip.index_urls();
+            debug!("using pip's indexes: {urls:?}");
+        }
+    }
+    if !urls.is_empty() {
+        urls.insert(0, PYBI_INDEX_URL.clone());
+    }
+    Ok(urls)
+}
+
+/// The knobs on a PackageDB that come from config or the command line.
+#[derive(Debug, Clone, Default)]
+pub struct DbOptions {
+    pub build_constraints: Vec<UserRequirement>,
+    pub require_hashes: bool,
+    pub attestations: AttestationConfig,
+    pub policy: Policy,
+    pub unpack_limits: UnpackLimits,
+    pub allowed_hosts: Option<HostAllowlist>,
+    pub pip_cache_dir: Option<PathBuf>,
+    /// Empty means the defaults: posy's pybi index, and PyPI
+    pub index_urls: Vec<Url>,
+    /// Name -> url, for requirements that say which index they come from
+    pub named_indexes: BTreeMap<String, Url>,
+    pub proxy: Option<String>,
+    /// None means the platform's usual places
+    pub cache_dir: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub offline: bool,
+    /// See `PackageDB::set_wheelhouse`
+    pub wheelhouse: Option<PathBuf>,
+    pub stale_if_error: Option<Duration>,
+    /// None means no particular limit
+    pub concurrency: Option<usize>,
+    /// Host pattern -> command; see `CredentialHelpers`
+    pub credential_helpers: BTreeMap<String, HelperCommand>,
+    /// See `EnvForest::set_rebuild`
+    pub rebuild_envs: bool,
+    /// See `PackageDB::set_snapshot`
+    pub snapshot: Option<SystemTime>,
+    pub wheel_preference: WheelPreference,
+    /// See `PackageDB::set_no_binary`
+    pub no_binary: Vec<PackageName>,
+}
+
+/// Sets up a PackageDB and the EnvForest that goes with it, and passes them to `f`.
+///
+/// PackageDB borrows a bunch of stuff that has to live somewhere, so it's easiest to
+/// just keep it all on our stack while the command runs.
+pub fn with_package_db<F, T>(options: DbOptions, f: F) -> Result<T>
+where
+    F: FnOnce(&PackageDB, &EnvForest) -> Result<T>,
+{
+    let cache_dir = match &options.cache_dir {
+        Some(dir) => dir.as_path(),
+        None => PROJECT_DIRS.cache_dir(),
+    };
+    let data_dir = match &options.data_dir {
+        Some(dir) => dir.as_path(),
+        None => PROJECT_DIRS.data_local_dir(),
+    };
+    let mut env_forest = EnvForest::new(&data_dir.join("envs"))?;
+    env_forest.set_rebuild(options.rebuild_envs);
+    // This is the temporary directory we use for sdist builds. It's also a
+    // content-addressed store, so if we want to build the same package twice (e.g.
+    // first to get metadata, and then to get a wheel), we can re-use the same build
+    // directory.
+    let build_tmp = tempfile::TempDir::new()?;
+    let build_store = KVDirStore::new(build_tmp.path())?;
+
+    let index_urls = match options.index_urls.is_empty() {
+        true => &*DEFAULT_INDEX_URLS,
+        false => &options.index_urls,
+    };
+    let mut db = PackageDB::new(
+        index_urls,
+        cache_dir,
+        // PackageDB needs a place to install packages, in case it has to build some
+        // sdists. Using a shared env_forest is efficient, because it means different
+        // builds can share the same package installs.
+        &env_forest,
+        &build_store,
+    )?;
+    db.set_build_constraints(options.build_constraints);
+    db.set_require_hashes(options.require_hashes);
+    db.set_named_indexes(options.named_indexes);
+    db.set_attestations(options.attestations);
+    db.set_policy(options.policy);
+    db.set_snapshot(options.snapshot);
+    db.set_unpack_limits(options.unpack_limits);
+    db.set_wheel_preference(options.wheel_preference);
+    db.set_no_binary(options.no_binary);
+    db.set_allowed_hosts(options.allowed_hosts);
+    db.set_pip_cache(options.pip_cache_dir);
+    db.set_proxy(options.proxy.as_deref())?;
+    db.set_offline(options.offline);
+    if let Some(dir) = &options.wheelhouse {
+        db.set_wheelhouse(Some(Wheelhouse::open(dir)?));
+    }
+    db.set_stale_if_error(options.stale_if_error);
+    db.set_concurrency(options.concurrency);
+    db.set_credential_helpers(CredentialHelpers::new(options.credential_helpers)?);
+    f(&db, &env_forest)
+}
+
+/// Finds the project we're working on: either the one in `dir`, if given, or else
+/// whatever contains the current directory. Outside of a project, you get an empty one
+/// with the default configuration, i.e. just a bare Python.
+pub fn project(dir: Option<&Path>) -> Result<Project> {
+    let project = match dir {
+        Some(dir) => Some(Project::load(dir)?),
+        None => Project::find(&std::env::current_dir()?)?,
+    };
+    match project {
+        Some(project) => Ok(project),
+        None => {
+            debug!("no pyproject.toml found, using default configuration");
+            Ok(Project {
+                root: std::env::current_dir()?,
+                config: ProjectConfig::default(),
+                package: None,
+            })
+        }
+    }
+}
+
+/// The configuration for working on `project`: the system and user posy.toml, and
+/// the project's own, if it's a real project (with a pyproject.toml).
+pub fn global_config(project: &Project) -> Result<GlobalConfig> {
+    let root = project.lockfile_path().map(|_| project.root.as_path());
+    GlobalConfig::load_for(root)
+}
+
+/// `config`, plus the trusted root from posy.toml to check attestations against.
+fn attestations(
+    global: &GlobalConfig,
+    mut config: AttestationConfig,
+) -> Result<AttestationConfig> {
+    if let Some(path) = &global.sigstore_trusted_root {
+        config.trusted_root = Some(TrustedRoot::load(path)?);
+    }
+    Ok(config)
+}
+
+/// The PackageDB options that apply everywhere, from posy.toml.
+pub fn global_db_options(global: &GlobalConfig) -> Result<DbOptions> {
+    let policy_paths: Vec<&Path> = global.policy.iter().map(|p| p.as_path()).collect();
+    Ok(DbOptions {
+        build_constraints: global.build_constraints.clone(),
+        attestations: attestations(global, AttestationConfig::default())?,
+        policy: Policy::load(&policy_paths)?,
+        unpack_limits: global.unpack_limits,
+        allowed_hosts: global.allowed_hosts.clone(),
+        pip_cache_dir: global.pip_cache_dir(),
+        index_urls: index_urls(global)?,
+        named_indexes: global.indexes.clone(),
+        proxy: global.proxy.clone(),
+        cache_dir: Some(global.cache_dir()),
+        data_dir: Some(global.data_dir()),
+        offline: global.offline,
+        wheelhouse: global.wheelhouse.clone(),
+        stale_if_error: global.stale_if_error,
+        concurrency: global.concurrency,
+        credential_helpers: global.credential_helpers.clone(),
+        wheel_preference: global.wheel_preference.clone(),
+        no_binary: global.no_binary.clone(),
+        ..Default::default()
+    })
+}
+
+/// The PackageDB options for working on a project. Build constraints and policy files
+/// are the user's global ones plus the project's own; attestation policy is the
+/// project's.
+pub fn db_options(global: &GlobalConfig, project: &Project) -> Result<DbOptions> {
+    let mut build_constraints = global.build_constraints.clone();
+    build_constraints.extend(project.config.build_constraints.iter().cloned());
+    let policy_paths: Vec<&Path> = global
+        .policy
+        .iter()
+        .chain(&project.config.policy)
+        .map(|p| p.as_path())
+        .collect();
+    Ok(DbOptions {
+        build_constraints,
+        require_hashes: false,
+        attestations: attestations(global, project.config.attestations.clone())?,
+        policy: Policy::load(&policy_paths)?,
+        unpack_limits: global.unpack_limits,
+        allowed_hosts: global.allowed_hosts.clone(),
+        pip_cache_dir: global.pip_cache_dir(),
+        index_urls: index_urls(global)?,
+        named_indexes: global.indexes.clone(),
+        proxy: global.proxy.clone(),
+        cache_dir: Some(global.cache_dir()),
+        data_dir: Some(global.data_dir()),
+        offline: global.offline,
+        wheelhouse: global.wheelhouse.clone(),
+        stale_if_error: global.stale_if_error,
+        concurrency: global.concurrency,
+        credential_helpers: global.credential_helpers.clone(),
+        rebuild_envs: false,
+        snapshot: None,
+        wheel_preference: global.wheel_preference.clone(),
+        no_binary: global.no_binary.clone(),
+    })
+}
+
+/// The project's local packages -- itself, if it's a package, and any workspace
+/// members -- ready to build.
+pub fn local_trees(project: &Project) -> Result<Vec<LocalTree>> {
+    project
+        .local_packages()?
+        .into_iter()
+        .map(|(name, root)| LocalTree::new(name, &root))
+        .collect()
+}
+
+/// What we actually resolve for an environment: its requirements, minus the local
+/// packages, plus whatever the local packages need. Unless that's all written down in
+/// their `[project]` tables, working it out means running their build backends, so we
+/// need a Blueprint to take a Python from.
+///
+/// Unless `build` is set, we only use what the build backends told us last time, and
+/// return None if one of the local packages has changed since.
+fn external_brief(
+    db: &PackageDB,
+    brief: &Brief,
+    trees: &[LocalTree],
+    python_from: &Blueprint,
+    build: bool,
+) -> Result<Option<Brief>> {
+    let mut external = brief.clone();
+    if trees.is_empty() {
+        return Ok(Some(external));
+    }
+    let builder = OnceCell::new();
+    let mut local = HashMap::new();
+    for tree in trees {
+        let pyproject = std::fs::read_to_string(tree.root.join("pyproject.toml"))?;
+        let metadata = match crate::project::static_metadata(&pyproject)? {
+            Some(metadata) => metadata,
+            None if !build => match db.cached_local_tree_metadata(tree) {
+                Some(metadata) => metadata,
+                None => {
+                    info!(
+                        "{} has changed, and we'd have to run its build backend to \
+                         see what it needs now",
+                        tree.name.as_given()
+                    );
+                    return Ok(None);
+                }
+            },
+            None => builder
+                .get_or_try_init(|| {
+                    WheelBuilder::new(
+                        db,
+                        &python_from.pybi.name,
+                        &python_from.pybi.version,
+                        PybiPlatform::native_platforms()?,
+                        &[],
+                    )
+                })?
+                .local_tree_metadata(tree)?,
+        };
+        local.insert(tree.name.clone(), metadata);
+    }
+    external.requirements = external_requirements(&brief.requirements, &local)?;
+    Ok(Some(external))
+}
+
+/// Whether `lockfile` has an up to date Blueprint for one of the project's
+/// environments.
+///
+/// Local packages without static metadata might need their build backends run to tell.
+/// With `build` unset -- for `--frozen` and `--check`, which shouldn't be running
+/// anything -- we only go on what they said last time, and if they've changed since,
+/// the environment counts as stale.
+pub fn lock_is_fresh(
+    db: &PackageDB,
+    project: &Project,
+    trees: &[LocalTree],
+    env_name: &str,
+    lockfile: &Lockfile,
+    build: bool,
+) -> Result<bool> {
+    let Some(locked) = lockfile.environments.get(env_name) else {
+        return Ok(false);
+    };
+    // if the policy has changed since we locked, then we might have to move off some
+    // pins that aren't allowed anymore
+    for (pin, _) in &locked.blueprint.wheels {
+        // likewise if no-binary has been added for something we pinned a release of
+        // that only has wheels, since there'd be nothing to build
+        if db.no_binary(&pin.name) && pin.source.is_none() && !pin.is_built_locally() {
+            let ais = db.artifacts_for_version(&pin.name, &pin.version)?;
+            if !ais.iter().any(|ai| ai.is::<Sdist>()) {
+                info!(
+                    "Environment '{env_name}' has to be re-locked: {} is set to \
+                     no-binary, but {} doesn't have an sdist",
+                    pin.name.as_given(),
+                    pin.version
+                );
+                return Ok(false);
+            }
+        }
+        if let Some(why) = db.policy().excludes_release(&pin.name, &pin.version)? {
+            info!("Environment '{env_name}' has to be re-locked: {why}");
+            return Ok(false);
+        }
+        if db.policy().checks_licenses() {
+            let ais = db.artifacts_for_version(&pin.name, &pin.version)?;
+            let why = match db.get_license(ais, None) {
+                Ok(license) => db.policy().excludes_license(license.as_deref()),
+                // probably an sdist we haven't built; re-locking will build it
+                Err(err) => Some(format!("couldn't check its license: {err}")),
+            };
+            if let Some(why) = why {
+                info!(
+                    "Environment '{env_name}' has to be re-locked: {} {}: {why}",
+                    pin.name.as_given(),
+                    pin.version
+                );
+                return Ok(false);
+            }
+        }
+    }
+    let brief = project.config.brief(env_name)?;
+    match external_brief(db, &brief, trees, &locked.blueprint, build)? {
+        Some(external) => locked.is_fresh(&external),
+        None => Ok(false),
+    }
+}
+
+/// The project's environments that `lockfile` is out of date for, plus any it has that
+/// the project doesn't anymore (marked "(removed)"). See `lock_is_fresh` for `build`.
+pub fn stale_environments(
+    db: &PackageDB,
+    project: &Project,
+    trees: &[LocalTree],
+    lockfile: &Lockfile,
+    build: bool,
+) -> Result<Vec<String>> {
+    let env_names = project.config.environment_names();
+    let mut stale = Vec::new();
+    for env_name in &env_names {
+        if !lock_is_fresh(db, project, trees, env_name, lockfile, build)? {
+            stale.push(env_name.to_string());
+        }
+    }
+    for env_name in lockfile.environments.keys() {
+        if !env_names.contains(&env_name.as_str()) {
+            stale.push(format!("{env_name} (removed)"));
+        }
+    }
+    Ok(stale)
+}
+
+/// Makes sure `lockfile` has an up to date Blueprint for one of the project's
+/// environments, re-resolving if necessary. When we do, we keep as many of the old pins
+/// as we can.
+///
+/// Returns None if it was already up to date, or else the pins that changed (which
+/// might be none of them, if e.g. someone just loosened a requirement). Those get
+/// logged too.
+pub fn lock_env(
+    db: &PackageDB,
+    project: &Project,
+    trees: &[LocalTree],
+    env_name: &str,
+    lockfile: &mut Lockfile,
+) -> Result<Option<Vec<PackageChange>>> {
+    // against a snapshot, the point is to find out whether we get the same answer
+    if db.snapshot().is_none()
+        && lock_is_fresh(db, project, trees, env_name, lockfile, true)?
+    {
+        return Ok(None);
+    }
+    let platforms = PybiPlatform::native_platforms()?;
+    let brief = project.config.brief(env_name)?;
+    let like = match lockfile.environments.get(env_name) {
+        Some(locked) => locked.blueprint.clone(),
+        None => {
+            // We can't ask the local packages what they depend on until we have a
+            // Python to run their build backends, so this takes two passes.
+            let mut first = brief.clone();
+            first
+                .requirements
+                .retain(|req| !trees.iter().any(|tree| tree.name == req.name));
+            first.resolve(db, platforms, None, &[])?
+        }
+    };
+    let external = external_brief(db, &brief, trees, &like, true)?
+        .expect("building is allowed, so we always get a Brief");
+    let blueprint = external.resolve(db, platforms, Some(&like), &[])?;
+    let old = lockfile.environments.get(env_name).map(|l| &l.blueprint);
+    let changes = blueprint_diff(old, &blueprint);
+    if changes.is_empty() {
+        info!("Environment '{env_name}': no changes to pinned versions");
+    } else {
+        info!("Environment '{env_name}':");
+        for change in &changes {
+            info!("  {}", change.render());
+        }
+    }
+    let snapshot = match lockfile.environments.get(env_name) {
+        Some(LockedEnv {
+            snapshot: Some(snapshot),
+            ..
+        }) if db.snapshot().is_some() => {
+            for (name, then, now) in snapshot.changed_since(db) {
+                // the serial's all we have to go on for files without an upload time
+                let undated = db.undated(name);
+                if undated > 0 {
+                    bail!(
+                        "{} has changed since {} (serial {then} -> {now}), and \
+                         {undated} of its files don't say when they were uploaded, so \
+                         there's no telling which of them were there then",
+                        name.as_given(),
+                        snapshot.time
+                    );
+                }
+                info!(
+                    "{} has changed since {} (serial {then} -> {now}); ignoring \
+                     anything uploaded after that",
+                    name.as_given(),
+                    snapshot.time
+                );
+            }
+            snapshot.clone()
+        }
+        _ => IndexSnapshot::take(db, &blueprint),
+    };
+    lockfile.environments.insert(
+        env_name.into(),
+        LockedEnv {
+            brief: external,
+            blueprint,
+            snapshot: Some(snapshot),
+        },
+    );
+    Ok(Some(changes))
+}
+
+/// The locked Blueprint for one of the project's environments, for commands that just
+/// report on it. These don't resolve anything, so you have to have run `posy lock` (or
+/// `posy run`) first.
+pub fn locked_env(project: &Project, env_name: &str) -> Result<LockedEnv> {
+    project.config.environment(env_name)?;
+    let Some(path) = project.lockfile_path() else {
+        bail!("no pyproject.toml found");
+    };
+    match Lockfile::load(&path)?.environments.remove(env_name) {
+        Some(locked) => Ok(locked),
+        None => bail!(
+            "environment '{env_name}' isn't in {LOCKFILE_NAME} yet; run `posy lock` \
+             first"
+        ),
+    }
+}
+
+/// Gets one of the project's environments, for the current machine.
+///
+/// Its Blueprint comes from posy.lock, which gets updated first if it's out of date --
+/// unless `frozen` is set, in which case that's an error.
+///
+/// Local packages get built and installed on top. They're only rebuilt when their
+/// source trees change, so this is cheap, and whatever you run always sees the current
+/// code.
+///
+/// If `with` is non-empty, those get layered on top, without disturbing anything the
+/// environment would have gotten on its own. They don't go in posy.lock.
+///
+/// With `require_hashes`, every package has to be pinned by hash, and every file we
+/// install has to match one of those hashes. With `rebuild`, the environment gets
+/// thrown away and put together again from scratch, instead of reused.
+pub fn project_env(
+    global: &GlobalConfig,
+    project: &Project,
+    env_name: &str,
+    with: &[UserRequirement],
+    frozen: bool,
+    require_hashes: bool,
+    rebuild: bool,
+) -> Result<Env> {
+    let options = DbOptions {
+        require_hashes,
+        rebuild_envs: rebuild,
+        ..db_options(global, project)?
+    };
+    with_package_db(options, |db, env_forest| {
+        project_env_in(db, env_forest, project, env_name, None, with, frozen)
+    })
+}
+
+/// `project_env`, using a PackageDB you already have.
+///
+/// If `python` is set, the environment is re-resolved for that Python instead of the
+/// locked one (see `Brief::provision`, which also decides how `frozen` applies to
+/// that). Like `with`, that doesn't go in posy.lock.
+pub fn project_env_in(
+    db: &PackageDB,
+    env_forest: &EnvForest,
+    project: &Project,
+    env_name: &str,
+    python: Option<&PythonRequirement>,
+    with: &[UserRequirement],
+    frozen: bool,
+) -> Result<Env> {
+    let trees = local_trees(project)?;
+    let lockfile_path = project.lockfile_path();
+    let mut lockfile = match &lockfile_path {
+        Some(path) => Lockfile::load(path)?,
+        None => Lockfile::default(),
+    };
+    let platforms = PybiPlatform::native_platforms()?;
+    if frozen {
+        if !lock_is_fresh(db, project, &trees, env_name, &lockfile, false)? {
+            bail!(
+                "environment '{env_name}' isn't up to date in {LOCKFILE_NAME}, and \
+                 --frozen means we can't update it"
+            );
+        }
+    } else if lock_env(db, project, &trees, env_name, &mut lockfile)?.is_some() {
+        if let Some(path) = &lockfile_path {
+            lockfile.save(path)?;
+        }
+    }
+    let locked = &lockfile.environments[env_name];
+    let blueprint = locked
+        .brief
+        .provision(db, &locked.blueprint, python, with, platforms, frozen)
+        .wrap_err_with(|| format!("setting up environment '{env_name}'"))?;
+    let mut env = env_forest.get_env(db, &blueprint, platforms, &[])?;
+    for tree in &trees {
+        env_forest.add_local_tree(db, &mut env, &blueprint, tree)?;
+    }
+    Ok(env)
+}
+
+/// Makes a Command that runs inside `env`, with the given config layers applied on top.
+pub fn env_command<S: AsRef<OsStr>>(
+    env: &Env,
+    argv: &[S],
+    layers: &[&EnvConfig],
+) -> Result<Command> {
+    let (program, args) = argv.split_first().ok_or(eyre!("empty command"))?;
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    // env.env_vars() gives us the magic environment variables needed to run a command
+    // in our new environment.
+    cmd.envs(env.env_vars()?);
+    for layer in layers {
+        layer.apply(&mut cmd)?;
+    }
+    Ok(cmd)
+}
+
+/// Replaces the current process with `cmd`, or as close as we can get on this
+/// platform. Only returns on error.
+pub fn exec(mut cmd: Command) -> Result<()> {
+    // We're not coming back, so this is our last chance
+    if let Some(report) = crate::timings::report() {
+        eprint!("{report}");
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(cmd.exec())?;
+        unreachable!();
+    }
+    #[cfg(windows)]
+    {
+        // XX FIXME: factor out the windows trampoline code and reuse it here.
+        //
+        // unwrap() is safe b/c this branch only runs on windows, and Windows doesn't
+        // have special exit statuses; that's a special thing for Unix signals.
+        std::process::exit(cmd.status()?.code().unwrap());
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        not_supported
+    }
+}
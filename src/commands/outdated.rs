@@ -0,0 +1,232 @@
+use crate::output;
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::project::DEFAULT_ENV;
+use crate::resolve::{Blueprint, Brief};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct OutdatedArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to check.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+}
+
+/// A package in the Blueprint that has a newer version on the index.
+///
+/// `--format json` prints a Report, with one of these for each outdated package.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Outdated {
+    name: String,
+    current: Version,
+    latest: Version,
+    /// The newest version that everything in the environment would accept, if that's
+    /// newer than what we have.
+    upgradable_to: Option<Version>,
+    /// Whatever's stopping us from going all the way to `latest`.
+    blocked_by: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    /// Can go all the way to the latest version, just by re-locking.
+    free: Vec<Outdated>,
+    /// Some requirement says no to the latest version.
+    blocked: Vec<Outdated>,
+}
+
+impl OutdatedArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let locked = super::locked_env(&project, &self.env_name)?;
+        let options = super::db_options(&global, &project)?;
+        let report = super::with_package_db(options, |db, _| {
+            build_report(db, &locked.brief, &locked.blueprint)
+        })?;
+
+        if output::json() {
+            return output::print_json(&report);
+        }
+        if report.free.is_empty() && report.blocked.is_empty() {
+            println!("Everything in '{}' is up to date", self.env_name);
+            return Ok(());
+        }
+        if !report.free.is_empty() {
+            println!("Free to upgrade:");
+            for o in &report.free {
+                println!("  {} {} -> {}", o.name, o.current, o.latest);
+            }
+        }
+        if !report.blocked.is_empty() {
+            println!("Blocked by specifiers:");
+            for o in &report.blocked {
+                print!("  {} {} (latest: {}", o.name, o.current, o.latest);
+                if let Some(v) = &o.upgradable_to {
+                    print!("; can go to {v}");
+                }
+                println!(")");
+                for reason in &o.blocked_by {
+                    println!("      held back by {reason}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn build_report(
+    db: &PackageDB,
+    brief: &Brief,
+    blueprint: &Blueprint,
+) -> Result<Report> {
+    let python_version = &blueprint.pybi.version;
+    let mut pinned = vec![&blueprint.pybi];
+    pinned.extend(blueprint.wheels.iter().map(|(p, _)| p));
+
+    let mut report = Report::default();
+    for package in pinned {
+        // Everything that has an opinion about which versions of `package` we can use
+        let mut specifiers: Vec<(String, &Specifiers)> = Vec::new();
+        if package.name == brief.python.name {
+            specifiers.push((
+                format!("your requirements ({})", brief.python),
+                &brief.python.specifiers,
+            ));
+        }
+        for req in brief.requirements.iter().chain(&brief.constraints) {
+            if req.name == package.name {
+                let who = format!("your requirements ({req})");
+                specifiers.push((who, &req.specifiers));
+            }
+        }
+        for (other, metadata) in &blueprint.wheels {
+            for req in &metadata.inner.requires_dist {
+                if req.name == package.name {
+                    specifiers.push((
+                        format!("{} {} ({req})", other.name.as_given(), other.version),
+                        &req.specifiers,
+                    ));
+                }
+            }
+        }
+
+        let allow_pre = package.version.is_prerelease()
+            || brief.allow_pre.allow_pre_for(&package.name);
+        let is_pybi = package.name == blueprint.pybi.name;
+        let mut candidates = Vec::new();
+        for (version, ais) in db.available_artifacts(&package.name)? {
+            if !allow_pre && version.is_prerelease() {
+                continue;
+            }
+            let usable = ais.iter().any(|ai| {
+                !ai.yanked.yanked
+                    && (is_pybi
+                        || match &ai.requires_python {
+                            Some(rp) => rp
+                                .parse::<Specifiers>()
+                                .and_then(|rp| rp.satisfied_by(python_version))
+                                .unwrap_or(true),
+                            None => true,
+                        })
+            });
+            if usable {
+                candidates.push(version);
+            }
+        }
+
+        let Some(outdated) =
+            check_package(&package.name, &package.version, &candidates, &specifiers)?
+        else {
+            continue;
+        };
+        if outdated.blocked_by.is_empty() {
+            report.free.push(outdated);
+        } else {
+            report.blocked.push(outdated);
+        }
+    }
+    Ok(report)
+}
+
+/// `candidates` are the versions we could install, newest first.
+fn check_package(
+    name: &PackageName,
+    current: &Version,
+    candidates: &[&Version],
+    specifiers: &[(String, &Specifiers)],
+) -> Result<Option<Outdated>> {
+    let Some(&latest) = candidates.first() else {
+        return Ok(None);
+    };
+    if latest <= current {
+        return Ok(None);
+    }
+    let mut blocked_by = Vec::new();
+    for (who, spec) in specifiers {
+        if !spec.satisfied_by(latest)? {
+            blocked_by.push(who.clone());
+        }
+    }
+    let mut upgradable_to = None;
+    for &candidate in candidates {
+        if candidate <= current {
+            break;
+        }
+        let mut ok = true;
+        for (_, spec) in specifiers {
+            ok &= spec.satisfied_by(candidate)?;
+        }
+        if ok {
+            upgradable_to = Some(candidate.clone());
+            break;
+        }
+    }
+    Ok(Some(Outdated {
+        name: name.as_given().to_string(),
+        current: current.clone(),
+        latest: latest.clone(),
+        upgradable_to,
+        blocked_by,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_package() -> Result<()> {
+        let name: PackageName = "trio".try_into()?;
+        let versions: Vec<Version> = ["0.22.0", "0.21.2", "0.21.0"]
+            .iter()
+            .map(|v| v.parse())
+            .collect::<Result<_>>()?;
+        let candidates: Vec<&Version> = versions.iter().collect();
+        let current = &versions[2];
+
+        let outdated = check_package(&name, current, &candidates, &[])?.unwrap();
+        assert_eq!(outdated.latest, versions[0]);
+        assert_eq!(outdated.upgradable_to.as_ref(), Some(&versions[0]));
+        assert!(outdated.blocked_by.is_empty());
+
+        let pin: Specifiers = "< 0.22".parse()?;
+        let outdated = check_package(
+            &name,
+            current,
+            &candidates,
+            &[("your requirements (trio < 0.22)".into(), &pin)],
+        )?
+        .unwrap();
+        assert_eq!(outdated.upgradable_to.as_ref(), Some(&versions[1]));
+        assert_eq!(outdated.blocked_by, vec!["your requirements (trio < 0.22)"]);
+
+        assert!(check_package(&name, &versions[0], &candidates, &[])?.is_none());
+        Ok(())
+    }
+}
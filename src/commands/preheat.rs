@@ -0,0 +1,81 @@
+use crate::lockfile::Lockfile;
+use crate::output;
+use crate::prelude::*;
+use crate::resolve::Preheated;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct PreheatArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to preheat. (Can be repeated.) Defaults to
+    /// all of them.
+    #[arg(short, long = "env", value_name = "NAME")]
+    env_names: Vec<String>,
+    /// How many versions of each package to fetch metadata for, in the order the
+    /// resolver would try them.
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    versions: usize,
+}
+
+impl PreheatArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let env_names: Vec<String> = if self.env_names.is_empty() {
+            let names = project.config.environment_names();
+            names.into_iter().map(String::from).collect()
+        } else {
+            for env_name in &self.env_names {
+                project.config.environment(env_name)?;
+            }
+            self.env_names
+        };
+        let lockfile = match project.lockfile_path() {
+            Some(path) => Lockfile::load(&path)?,
+            None => Lockfile::default(),
+        };
+        let trees = super::local_trees(&project)?;
+        let options = super::db_options(&global, &project)?;
+        let platforms = PybiPlatform::native_platforms()?;
+
+        super::with_package_db(options, |db, _| {
+            let mut report: BTreeMap<&str, Preheated> = BTreeMap::new();
+            for env_name in &env_names {
+                let locked = lockfile.environments.get(env_name);
+                // The locked Brief already has the local packages' own requirements
+                // in place of the packages themselves. Without one, we can't ask them
+                // without a Python to build with, so we leave them out.
+                let brief = match locked {
+                    Some(locked) => locked.brief.clone(),
+                    None => {
+                        let mut brief = project.config.brief(env_name)?;
+                        brief
+                            .requirements
+                            .retain(|req| !trees.iter().any(|t| t.name == req.name));
+                        brief
+                    }
+                };
+                let like = locked.map(|locked| &locked.blueprint);
+                let preheated = brief
+                    .preheat(db, platforms, like, self.versions)
+                    .wrap_err_with(|| format!("preheating environment '{env_name}'"))?;
+                if !output::json() {
+                    println!(
+                        "{env_name}: {} index pages, metadata for {} releases",
+                        preheated.index_pages, preheated.metadata
+                    );
+                }
+                report.insert(env_name, preheated);
+            }
+            if output::json() {
+                output::print_json(&report)?;
+            }
+            Ok(())
+        })
+    }
+}
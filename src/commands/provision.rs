@@ -0,0 +1,154 @@
+//! `posy provision`: sets up one of the project's environments for some other tool to
+//! run things in -- mainly test runners like tox and nox, which would otherwise make
+//! their own virtualenvs and pip install into them.
+//!
+//! The contract is `posy --format json provision --env NAME [--python VERSION]`, which
+//! prints an `Installed` object: the interpreter, the directories to put on $PATH, and
+//! the exact environment variables `posy run` would set. Tools that provision a lot of
+//! environments can keep a `posy daemon` running instead, and call its `install`
+//! method, which takes the same `env` and `python`.
+
+use crate::config::GlobalConfig;
+use crate::env::Env;
+use crate::output;
+use crate::prelude::*;
+use crate::project::{Project, DEFAULT_ENV};
+use clap::Args;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ProvisionArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to set up.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+    /// Use this Python instead of the locked one: a version like 3.11, a name like
+    /// python3.11 or py311, or a requirement like "cpython_unofficial >= 3.11". The
+    /// rest of the environment keeps posy.lock's pins where it can.
+    #[arg(long, value_name = "PYTHON", value_parser = python_override)]
+    python: Option<PythonRequirement>,
+    /// Add an extra package to the environment, without changing the versions of
+    /// anything else. (Can be repeated.)
+    #[arg(long, value_name = "REQUIREMENT")]
+    with: Vec<UserRequirement>,
+    /// Use exactly what's in posy.lock. If it's missing or out of date, fail instead of
+    /// resolving.
+    #[arg(long)]
+    frozen: bool,
+}
+
+/// What `--format json` prints, and what the daemon's `install` returns.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Installed {
+    pub python: PathBuf,
+    pub bin_dirs: Vec<PathBuf>,
+    pub lib_dirs: Vec<PathBuf>,
+    /// What to set to run something in the environment
+    pub env_vars: BTreeMap<String, String>,
+}
+
+impl Installed {
+    pub fn new(env: Env, env_vars: BTreeMap<String, String>) -> Installed {
+        Installed {
+            python: env.python,
+            bin_dirs: env.bin_dirs,
+            lib_dirs: env.lib_dirs,
+            env_vars,
+        }
+    }
+}
+
+static PYTHON_NAME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:python|py)?([0-9]+)\.?([0-9]+)?$").unwrap());
+
+/// Reads `--python`. Version numbers mean any release in that series, the way tox and
+/// nox mean them: "3.11" and "py311" are both `cpython_unofficial == 3.11.*`.
+pub fn python_override(spec: &str) -> Result<PythonRequirement> {
+    let Some(captures) = PYTHON_NAME.captures(spec) else {
+        return spec.try_into();
+    };
+    let major = &captures[1];
+    let series = match captures.get(2) {
+        Some(minor) => format!("{major}.{}", minor.as_str()),
+        // py311: one digit of major version, the rest is minor
+        None if major.len() > 1 => format!("{}.{}", &major[..1], &major[1..]),
+        None => major.to_owned(),
+    };
+    format!("cpython_unofficial == {series}.*")
+        .as_str()
+        .try_into()
+}
+
+impl ProvisionArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let options = super::db_options(&global, &project)?;
+        let env = super::with_package_db(options, |db, env_forest| {
+            super::project_env_in(
+                db,
+                env_forest,
+                &project,
+                &self.env_name,
+                self.python.as_ref(),
+                &self.with,
+                self.frozen,
+            )
+        })?;
+        if output::json() {
+            let env_vars = run_env_vars(&global, &project, &self.env_name, &env)?;
+            return output::print_json(&Installed::new(env, env_vars));
+        }
+        println!("{}", env.python.display());
+        Ok(())
+    }
+}
+
+/// Everything `posy run` would set in the environment: the environment's own
+/// variables, then posy.toml's and the project's `[run-env]`s.
+pub fn run_env_vars(
+    global: &GlobalConfig,
+    project: &Project,
+    env_name: &str,
+    env: &Env,
+) -> Result<BTreeMap<String, String>> {
+    let mut layers = global.run_env_layers();
+    layers.extend(project.config.run_env_layers(env_name)?);
+    let cmd = super::env_command(env, &[&env.python], &layers)?;
+    Ok(cmd
+        .get_envs()
+        .filter_map(|(name, value)| {
+            let value = value?.to_string_lossy().into_owned();
+            Some((name.to_string_lossy().into_owned(), value))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_python_override() -> Result<()> {
+        let requirement = |s: &str| python_override(s).unwrap().to_string();
+        let py311 = PythonRequirement::try_from("cpython_unofficial == 3.11.*")?;
+        assert_eq!(requirement("3.11"), py311.to_string());
+        assert_eq!(requirement("python3.11"), py311.to_string());
+        assert_eq!(requirement("py311"), py311.to_string());
+        assert_eq!(
+            requirement("3"),
+            PythonRequirement::try_from("cpython_unofficial == 3.*")?.to_string()
+        );
+        assert_eq!(
+            requirement("cpython_unofficial >= 3.10"),
+            PythonRequirement::try_from("cpython_unofficial >= 3.10")?.to_string()
+        );
+        assert!(python_override("cpython_unofficial[tk]").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use crate::build_pybi::{build_pybi, PybiSpec};
+use crate::check_pybi::{check_pybi, Problem, Severity};
+use crate::config::GlobalConfig;
+use crate::env::EnvForest;
+use crate::output;
+use crate::package_db::{ArtifactInfo, PackageDB};
+use crate::prelude::*;
+use crate::project::DEFAULT_PYTHON;
+use crate::resolve::{AllowPre, Brief};
+use crate::tree::UnpackLimits;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct PythonArgs {
+    #[command(subcommand)]
+    command: PythonCommand,
+}
+
+#[derive(Subcommand)]
+enum PythonCommand {
+    /// List the Pythons that are available for this machine, and which ones are
+    /// already installed.
+    List {
+        /// Only show the ones that are installed.
+        #[arg(long)]
+        installed: bool,
+        /// Include pre-releases.
+        #[arg(long)]
+        pre: bool,
+    },
+    /// Download and unpack a Python, so it's ready whenever an environment wants it.
+    Install {
+        /// A version prefix like "3.12", or a full requirement like
+        /// "cpython_unofficial >= 3.11". Defaults to the newest release.
+        #[arg(value_parser = parse_python_requirement)]
+        python: Option<PythonRequirement>,
+    },
+    /// Delete installed Pythons. Anything that still needs one will download it again.
+    Remove {
+        /// Which ones to remove: a version prefix like "3.12", or a full requirement.
+        #[arg(value_parser = parse_python_requirement)]
+        python: PythonRequirement,
+    },
+    /// Package a Python that's been built and installed into a directory of its own
+    /// (e.g. with `./configure --prefix=DIR && make install`) as a pybi, ready to
+    /// publish to an index. It has to be relocatable, and runnable here.
+    Build(BuildArgs),
+    /// Check pybis for problems, like metadata that doesn't make sense or files that
+    /// don't match the RECORD, before publishing them.
+    Check {
+        /// The .pybi files to check.
+        #[arg(required = true)]
+        pybis: Vec<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// The Python's installation directory.
+    root: PathBuf,
+    /// A platform the build runs on, e.g. manylinux_2_17_x86_64. (Can be repeated.)
+    #[arg(long = "platform", value_name = "TAG", required = true)]
+    platforms: Vec<String>,
+    /// What to call it. Defaults to the implementation, e.g. "cpython".
+    #[arg(long)]
+    name: Option<PackageName>,
+    /// A build number, to tell apart rebuilds of the same version.
+    #[arg(long)]
+    build: Option<u32>,
+    /// The interpreter, relative to the root. Defaults to bin/python3 (python.exe on
+    /// Windows).
+    #[arg(long)]
+    python: Option<PathBuf>,
+    /// Where to write the pybi.
+    #[arg(long, default_value = ".")]
+    out_dir: PathBuf,
+}
+
+/// What `--format json` prints for `list`, one per version.
+#[derive(Serialize)]
+struct PythonVersion {
+    name: String,
+    version: Version,
+    /// Whether there's a build for this machine on the index
+    available: bool,
+    /// Where it's unpacked, if it is
+    installed: Vec<PathBuf>,
+}
+
+/// What `--format json` prints for `check`, one per pybi.
+#[derive(Serialize)]
+struct PybiCheck {
+    path: PathBuf,
+    /// Errors first, then warnings
+    problems: Vec<Problem>,
+}
+
+/// The name the default Python requirement uses.
+fn default_python_name() -> PackageName {
+    let python: PythonRequirement = DEFAULT_PYTHON.try_into().unwrap();
+    python.name.clone()
+}
+
+/// "3.12" is short for "cpython_unofficial == 3.12.*", and "3.12.1" for
+/// "cpython_unofficial == 3.12.1". Anything that doesn't start with a digit is taken
+/// as a full requirement.
+fn parse_python_requirement(s: &str) -> Result<PythonRequirement> {
+    let s = s.trim();
+    if !s.starts_with(|c: char| c.is_ascii_digit()) {
+        return s.try_into();
+    }
+    let version: Version = s.try_into()?;
+    let specifier = if s.split('.').count() >= 3 {
+        format!("== {version}")
+    } else {
+        format!("== {version}.*")
+    };
+    format!("{} {specifier}", default_python_name().as_given())
+        .as_str()
+        .try_into()
+}
+
+fn matches(python: &PythonRequirement, name: &PackageName, version: &Version) -> bool {
+    &python.name == name && python.specifiers.satisfied_by(version).unwrap_or(false)
+}
+
+/// Whether any of `ais` is a pybi we could run here.
+fn runs_here(ais: &[ArtifactInfo], platforms: &[&PybiPlatform]) -> bool {
+    ais.iter().filter(|ai| !ai.yanked.yanked).any(|ai| {
+        let Some(name) = ai.name.inner_as::<PybiName>() else {
+            return false;
+        };
+        let tags = name.all_tags();
+        platforms
+            .iter()
+            .any(|p| p.max_compatibility(tags.iter()).is_some())
+    })
+}
+
+impl PythonArgs {
+    pub fn run(self) -> Result<()> {
+        // These don't need an index, or anything else from the config
+        let command = match self.command {
+            PythonCommand::Build(args) => return build(args),
+            PythonCommand::Check { pybis } => {
+                // (except for how far we'll go reading them)
+                return check(&pybis, &GlobalConfig::load()?.unpack_limits);
+            }
+            command => command,
+        };
+        let global = GlobalConfig::load()?;
+        let options = super::global_db_options(&global)?;
+        super::with_package_db(options, |db, env_forest| match command {
+            PythonCommand::List { installed, pre } => {
+                list(db, env_forest, installed, pre)
+            }
+            PythonCommand::Install { python } => install(db, env_forest, python),
+            PythonCommand::Remove { python } => remove(env_forest, &python),
+            PythonCommand::Build(_) | PythonCommand::Check { .. } => unreachable!(),
+        })
+    }
+}
+
+fn list(
+    db: &PackageDB,
+    env_forest: &EnvForest,
+    installed_only: bool,
+    pre: bool,
+) -> Result<()> {
+    let name = default_python_name();
+    let mut versions: Vec<PythonVersion> = Vec::new();
+    if !installed_only {
+        let platforms = PybiPlatform::native_platforms()?;
+        for (version, ais) in db.available_artifacts(&name)? {
+            if (pre || !version.is_prerelease()) && runs_here(ais, platforms) {
+                versions.push(PythonVersion {
+                    name: name.as_given().to_string(),
+                    version: version.clone(),
+                    available: true,
+                    installed: Vec::new(),
+                });
+            }
+        }
+    }
+    for pybi in env_forest.pybis()? {
+        let metadata = pybi.metadata;
+        let found = versions.iter_mut().find(|v| {
+            v.version == metadata.version && v.name == metadata.name.as_given()
+        });
+        match found {
+            Some(v) => v.installed.push(pybi.root),
+            None => versions.push(PythonVersion {
+                name: metadata.name.as_given().to_string(),
+                version: metadata.version,
+                available: false,
+                installed: vec![pybi.root],
+            }),
+        }
+    }
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+
+    if output::json() {
+        return output::print_json(&versions);
+    }
+    for v in &versions {
+        if v.installed.is_empty() {
+            println!("{} {}", v.name, v.version);
+        } else {
+            println!("{} {}  (installed)", v.name, v.version);
+        }
+    }
+    Ok(())
+}
+
+fn install(
+    db: &PackageDB,
+    env_forest: &EnvForest,
+    python: Option<PythonRequirement>,
+) -> Result<()> {
+    let brief = Brief {
+        python: match python {
+            Some(python) => python,
+            None => DEFAULT_PYTHON.try_into()?,
+        },
+        requirements: Vec::new(),
+        allow_pre: AllowPre::default(),
+        constraints: Vec::new(),
+        sources: Default::default(),
+    };
+    // An environment with nothing in it is just the Python
+    let platforms = PybiPlatform::native_platforms()?;
+    let blueprint = brief.resolve(db, platforms, None, &[])?;
+    let env = env_forest.get_env(db, &blueprint, platforms, &[])?;
+    let pybi = &blueprint.pybi;
+    if output::json() {
+        return output::print_json(&serde_json::json!({
+            "name": pybi.name.as_given(),
+            "version": pybi.version,
+            "python": env.python,
+        }));
+    }
+    println!(
+        "Installed {} {} at {}",
+        pybi.name.as_given(),
+        pybi.version,
+        env.python.display()
+    );
+    Ok(())
+}
+
+fn remove(env_forest: &EnvForest, python: &PythonRequirement) -> Result<()> {
+    let mut removed = Vec::new();
+    for pybi in env_forest.pybis()? {
+        let metadata = &pybi.metadata;
+        if matches(python, &metadata.name, &metadata.version) {
+            env_forest.remove_pybi(&pybi)?;
+            removed.push(PythonVersion {
+                name: metadata.name.as_given().to_string(),
+                version: metadata.version.clone(),
+                available: false,
+                installed: vec![pybi.root],
+            });
+        }
+    }
+    if removed.is_empty() {
+        bail!("no installed Python matches {python}");
+    }
+    if output::json() {
+        return output::print_json(&removed);
+    }
+    for r in &removed {
+        println!("Removed {} {}", r.name, r.version);
+    }
+    Ok(())
+}
+
+fn build(args: BuildArgs) -> Result<()> {
+    let python = args.python.unwrap_or_else(|| {
+        if cfg!(windows) {
+            "python.exe".into()
+        } else {
+            "bin/python3".into()
+        }
+    });
+    let mut spec = PybiSpec::from_interpreter(
+        &args.root,
+        &args.root.join(python),
+        &args.platforms,
+    )?;
+    if let Some(name) = args.name {
+        spec.name.distribution = name;
+    }
+    spec.name.build_number = args.build;
+    let built = build_pybi(&args.root, &spec, &args.out_dir)?;
+    if output::json() {
+        return output::print_json(&serde_json::json!({
+            "filename": built.name.to_string(),
+            "path": built.path,
+            "hash": built.hash,
+        }));
+    }
+    println!("Built {}", built.path.display());
+    println!("  {}", built.hash);
+    Ok(())
+}
+
+fn check(paths: &[PathBuf], limits: &UnpackLimits) -> Result<()> {
+    let mut checks = Vec::new();
+    for path in paths {
+        let filename = path.file_name().and_then(|name| name.to_str());
+        let name: PybiName = filename
+            .ok_or_else(|| eyre!("{} isn't a pybi", path.display()))?
+            .try_into()?;
+        let pybi = Pybi::new(name, Box::new(std::fs::File::open(path)?))?;
+        checks.push(PybiCheck {
+            path: path.clone(),
+            problems: check_pybi(&pybi, limits)?,
+        });
+    }
+    let errors = checks
+        .iter()
+        .flat_map(|check| &check.problems)
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    if output::json() {
+        output::print_json(&checks)?;
+    } else {
+        for check in &checks {
+            if check.problems.is_empty() {
+                println!("{}: ok", check.path.display());
+            } else {
+                println!("{}:", check.path.display());
+                for problem in &check.problems {
+                    println!("  {problem}");
+                }
+            }
+        }
+    }
+    if errors > 0 {
+        bail!("found {errors} errors");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_python_requirement() -> Result<()> {
+        let name = default_python_name();
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+
+        let short = parse_python_requirement("3.12")?;
+        assert!(matches(&short, &name, &v("3.12.0")));
+        assert!(matches(&short, &name, &v("3.12.4")));
+        assert!(!matches(&short, &name, &v("3.11.9")));
+
+        let exact = parse_python_requirement("3.11.2")?;
+        assert!(matches(&exact, &name, &v("3.11.2")));
+        assert!(!matches(&exact, &name, &v("3.11.3")));
+
+        let full = parse_python_requirement("cpython_unofficial >= 3.10")?;
+        assert!(matches(&full, &name, &v("3.11.2")));
+        assert!(!matches(&full, &name, &v("3.9.0")));
+        Ok(())
+    }
+}
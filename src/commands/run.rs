@@ -0,0 +1,115 @@
+use crate::config::EnvConfig;
+use crate::manifest::InstallManifest;
+use crate::prelude::*;
+use crate::project::DEFAULT_ENV;
+use clap::Args;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use super::env_command;
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => {
+            Ok((name.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected NAME=VALUE, not {s:?}")),
+    }
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    pub project: Option<PathBuf>,
+    /// Which of the project's environments to run in.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    pub env_name: String,
+    /// Set an environment variable for the command. Overrides anything set in
+    /// pyproject.toml or posy.toml. (Can be repeated.)
+    #[arg(long = "env-var", value_name = "NAME=VALUE", value_parser = parse_env_var)]
+    env_vars: Vec<(String, String)>,
+    /// Add an extra package to the environment for just this run, without changing
+    /// the versions of anything else. (Can be repeated.)
+    #[arg(long, value_name = "REQUIREMENT")]
+    with: Vec<UserRequirement>,
+    /// Use exactly what's in posy.lock. If it's missing or out of date, fail instead of
+    /// resolving.
+    #[arg(long)]
+    frozen: bool,
+    /// Refuse to install anything that posy.lock doesn't pin by hash, including
+    /// packages it has no hashes for at all.
+    #[arg(long)]
+    require_hashes: bool,
+    /// Throw away the environment and every package in it, and install them all again
+    /// from scratch. For when something in it seems to be broken.
+    #[arg(long)]
+    rebuild: bool,
+    /// Before running anything, write a manifest of every file in the environment --
+    /// with its hash, its package, and the artifact that package came from -- to
+    /// PATH.
+    #[arg(long, value_name = "PATH")]
+    install_manifest: Option<PathBuf>,
+    /// The command to run, followed by its arguments. Can also be the name of a script
+    /// from `[tool.posy.scripts]`.
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<OsString>,
+}
+
+impl RunArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let project_config = &project.config;
+        let cli_env = EnvConfig {
+            env: self.env_vars.into_iter().collect(),
+            path: Vec::new(),
+        };
+
+        let env = super::project_env(
+            &global,
+            &project,
+            &self.env_name,
+            &self.with,
+            self.frozen,
+            self.require_hashes,
+            self.rebuild,
+        )?;
+        if let Some(path) = &self.install_manifest {
+            context!("Writing install manifest to {}", path.display());
+            fs::write(path, InstallManifest::new(&env.packages)?.to_json()?)?;
+        }
+
+        let mut layers = global.run_env_layers();
+        layers.extend(project_config.run_env_layers(&self.env_name)?);
+        layers.push(&cli_env);
+
+        let script = match self.command[0].to_str() {
+            Some(name) => project_config.script_commands(name)?,
+            None => None,
+        };
+        let Some(mut script) = script else {
+            return super::exec(env_command(&env, &self.command, &layers)?);
+        };
+        // Extra arguments go on the end of the script's main command, so `posy run test
+        // -k foo` works the way you'd expect.
+        let mut main: Vec<OsString> = script
+            .pop()
+            .unwrap()
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        main.extend(self.command[1..].iter().cloned());
+        for pre in script {
+            let pre_str = pre.join(" ");
+            info!("running {pre_str}");
+            let status = env_command(&env, &pre, &layers)?.status()?;
+            if !status.success() {
+                bail!("pre-command '{pre_str}' failed ({status})");
+            }
+        }
+        super::exec(env_command(&env, &main, &layers)?)
+    }
+}
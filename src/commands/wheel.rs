@@ -0,0 +1,193 @@
+use crate::output;
+use crate::prelude::*;
+use crate::wheel_writer::WheelWriter;
+use clap::{Args, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct WheelArgs {
+    #[command(subcommand)]
+    command: WheelCommand,
+}
+
+#[derive(Subcommand)]
+enum WheelCommand {
+    /// Change a wheel's tags -- its filename, and the WHEEL and RECORD inside it --
+    /// like `wheel tags` does. E.g. for a wheel built on a new glibc, that you know
+    /// only needs an older one: `--platform-tag manylinux_2_17_x86_64`.
+    Retag(RetagArgs),
+}
+
+#[derive(Args)]
+struct RetagArgs {
+    /// The .whl file to retag.
+    wheel: PathBuf,
+    /// New Python tags, separated by dots. Start with "+" to add to the existing ones.
+    #[arg(long, value_name = "TAGS")]
+    python_tag: Option<String>,
+    /// New ABI tags, separated by dots. Start with "+" to add to the existing ones.
+    #[arg(long, value_name = "TAGS")]
+    abi_tag: Option<String>,
+    /// New platform tags, separated by dots. Start with "+" to add to the existing
+    /// ones.
+    #[arg(long, value_name = "TAGS")]
+    platform_tag: Option<String>,
+    /// A new build tag. An empty one removes it.
+    #[arg(long)]
+    build: Option<String>,
+    /// Delete the original wheel afterwards.
+    #[arg(long)]
+    remove: bool,
+    /// Where to write the new wheel. Defaults to next to the original.
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+}
+
+/// `wheel tags`-style tag arguments: "a.b" replaces the tags with a and b, and "+a.b"
+/// adds them to the end of `old`, skipping ones that are already there.
+fn apply_tags(old: &[String], new: Option<&str>) -> Result<Vec<String>> {
+    let Some(new) = new else {
+        return Ok(old.to_vec());
+    };
+    let (mut tags, new) = match new.strip_prefix('+') {
+        Some(rest) => (old.to_vec(), rest),
+        None => (Vec::new(), new),
+    };
+    for tag in new.split('.') {
+        if tag.is_empty() || tag.contains('-') {
+            bail!("invalid tag {tag:?}");
+        }
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.into());
+        }
+    }
+    Ok(tags)
+}
+
+fn retagged(old: &WheelName, args: &RetagArgs) -> Result<WheelName> {
+    let build = match &args.build {
+        Some(build) if build.is_empty() => String::new(),
+        Some(build) => format!("-{build}"),
+        None => format_build_tag(old),
+    };
+    let filename = format!(
+        "{}-{}{build}-{}-{}-{}.whl",
+        old.distribution.as_given(),
+        old.version,
+        apply_tags(&old.py_tags, args.python_tag.as_deref())?.join("."),
+        apply_tags(&old.abi_tags, args.abi_tag.as_deref())?.join("."),
+        apply_tags(&old.arch_tags, args.platform_tag.as_deref())?.join("."),
+    );
+    filename.as_str().try_into()
+}
+
+fn format_build_tag(name: &WheelName) -> String {
+    match (name.build_number, name.build_name.as_str()) {
+        (None, "") => String::new(),
+        (None, build_name) => format!("-{build_name}"),
+        (Some(number), build_name) => format!("-{number}{build_name}"),
+    }
+}
+
+impl WheelArgs {
+    pub fn run(self) -> Result<()> {
+        match self.command {
+            WheelCommand::Retag(args) => retag(&args),
+        }
+    }
+}
+
+fn retag(args: &RetagArgs) -> Result<()> {
+    let filename = args
+        .wheel
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(eyre!("can't tell what {} is called", args.wheel.display()))?;
+    let old: WheelName = filename.try_into()?;
+    let new = retagged(&old, args)?;
+    let out_dir = match &args.out_dir {
+        Some(dir) => dir.clone(),
+        None => match args.wheel.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+    let mut writer = WheelWriter::from_zip(old, fs::File::open(&args.wheel)?)
+        .wrap_err_with(|| format!("reading {}", args.wheel.display()))?;
+    writer.set_name(new)?;
+    let (path, hash) = writer.write_to_dir(&out_dir)?;
+    if args.remove && !same_file(&path, &args.wheel) {
+        fs::remove_file(&args.wheel)?;
+    }
+    if output::json() {
+        return output::print_json(&serde_json::json!({
+            "filename": writer.name().to_string(),
+            "path": path,
+            "hash": hash,
+        }));
+    }
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_tags() -> Result<()> {
+        let old = tags(&["manylinux_2_28_x86_64"]);
+        assert_eq!(apply_tags(&old, None)?, old);
+        assert_eq!(
+            apply_tags(&old, Some("manylinux_2_17_x86_64.manylinux2014_x86_64"))?,
+            tags(&["manylinux_2_17_x86_64", "manylinux2014_x86_64"])
+        );
+        assert_eq!(
+            apply_tags(&old, Some("+manylinux_2_17_x86_64.manylinux_2_28_x86_64"))?,
+            tags(&["manylinux_2_28_x86_64", "manylinux_2_17_x86_64"])
+        );
+        assert!(apply_tags(&old, Some("a..b")).is_err());
+        assert!(apply_tags(&old, Some("a-b")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_retagged() -> Result<()> {
+        let old: WheelName =
+            "foo-1.0-1-cp311-cp311-manylinux_2_28_x86_64.whl".try_into()?;
+        let args = RetagArgs {
+            wheel: PathBuf::new(),
+            python_tag: None,
+            abi_tag: Some("+abi3".into()),
+            platform_tag: Some("manylinux_2_17_x86_64".into()),
+            build: None,
+            remove: false,
+            out_dir: None,
+        };
+        assert_eq!(
+            retagged(&old, &args)?.to_string(),
+            "foo-1.0-1-cp311-cp311.abi3-manylinux_2_17_x86_64.whl"
+        );
+        let args = RetagArgs {
+            build: Some("".into()),
+            ..args
+        };
+        assert_eq!(
+            retagged(&old, &args)?.to_string(),
+            "foo-1.0-cp311-cp311.abi3-manylinux_2_17_x86_64.whl"
+        );
+        Ok(())
+    }
+}
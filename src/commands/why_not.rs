@@ -0,0 +1,370 @@
+use crate::output;
+use crate::package_db::PackageDB;
+use crate::platform_tags::PybiPlatform;
+use crate::prelude::*;
+use crate::project::DEFAULT_ENV;
+use crate::resolve::{pick_best_pybi, Blueprint, Brief, PythonFilter};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct WhyNotArgs {
+    /// The package to ask about.
+    package: PackageName,
+    /// The version of it you expected to get.
+    version: Version,
+    /// Use the project in this directory, instead of looking for one in the current
+    /// directory or its parents.
+    #[arg(long, value_name = "DIR")]
+    project: Option<PathBuf>,
+    /// Which of the project's environments to ask about.
+    #[arg(short, long = "env", value_name = "NAME", default_value = DEFAULT_ENV)]
+    env_name: String,
+}
+
+/// One reason the resolver passed over a version.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum Reason {
+    /// It's what we picked, actually.
+    Chosen,
+    /// Nothing in the environment needs the package at all.
+    NotNeeded,
+    NotOnIndex,
+    Policy {
+        why: String,
+    },
+    PreRelease,
+    /// It's set to no-binary, and only has wheels.
+    NoBinary,
+    /// None of its files work on any of `platforms`.
+    WrongPlatform {
+        platforms: Vec<String>,
+    },
+    Yanked {
+        why: Option<String>,
+    },
+    RequiresPython {
+        requires_python: String,
+        python: Version,
+    },
+    /// `who` (a pinned package, or the Brief) asked for something else.
+    OutOfRange {
+        who: String,
+        requirement: String,
+    },
+    /// It would have done, but the resolver prefers `version`.
+    LostTo {
+        version: Version,
+    },
+    /// Nothing rules it out on its own terms, so it must be its dependencies that
+    /// don't fit with the rest of the environment.
+    Conflicts,
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Chosen => write!(f, "it's the version we picked"),
+            Reason::NotNeeded => write!(f, "nothing in the environment needs it"),
+            Reason::NotOnIndex => write!(f, "the index doesn't have it"),
+            Reason::Policy { why } => write!(f, "policy: {why}"),
+            Reason::PreRelease => write!(
+                f,
+                "it's a pre-release, and nothing asked for pre-releases of it"
+            ),
+            Reason::NoBinary => write!(
+                f,
+                "it's set to no-binary, and doesn't have an sdist to build"
+            ),
+            Reason::WrongPlatform { platforms } => write!(
+                f,
+                "it doesn't have anything for this platform ({})",
+                platforms.join(", ")
+            ),
+            Reason::Yanked { why: None } => write!(f, "it's been yanked"),
+            Reason::Yanked { why: Some(why) } => {
+                write!(f, "it's been yanked ({why})")
+            }
+            Reason::RequiresPython {
+                requires_python,
+                python,
+            } => write!(
+                f,
+                "it requires Python {requires_python}, and the environment has {python}"
+            ),
+            Reason::OutOfRange { who, requirement } => {
+                write!(f, "{who} says {requirement}")
+            }
+            Reason::LostTo { version } => write!(
+                f,
+                "{version} works too, and the resolver likes it better (it's newer, \
+                 or it was already locked)"
+            ),
+            Reason::Conflicts => write!(
+                f,
+                "nothing rules it out by itself, so its dependencies must clash with \
+                 something else in the environment (try requiring that version \
+                 directly to see what)"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    package: String,
+    version: Version,
+    reasons: Vec<Reason>,
+}
+
+impl WhyNotArgs {
+    pub fn run(self) -> Result<()> {
+        let project = super::project(self.project.as_deref())?;
+        let global = super::global_config(&project)?;
+        let locked = super::locked_env(&project, &self.env_name)?;
+        let options = super::db_options(&global, &project)?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let reasons = super::with_package_db(options, |db, _| {
+            explain(
+                db,
+                &locked.brief,
+                &locked.blueprint,
+                platforms,
+                &self.package,
+                &self.version,
+            )
+        })?;
+
+        if output::json() {
+            return output::print_json(&Report {
+                package: self.package.as_given().to_string(),
+                version: self.version,
+                reasons,
+            });
+        }
+        println!(
+            "Why not {} {} in '{}':",
+            self.package.as_given(),
+            self.version,
+            self.env_name
+        );
+        for reason in &reasons {
+            println!("  - {reason}");
+        }
+        Ok(())
+    }
+}
+
+/// Everything that kept `version` of `name` out of `blueprint` (resolved for
+/// `platforms`), roughly in the order the resolver would have run into them.
+fn explain(
+    db: &PackageDB,
+    brief: &Brief,
+    blueprint: &Blueprint,
+    platforms: &[&PybiPlatform],
+    name: &PackageName,
+    version: &Version,
+) -> Result<Vec<Reason>> {
+    let is_pybi = name == &blueprint.pybi.name;
+    let pinned = if is_pybi {
+        Some(&blueprint.pybi)
+    } else {
+        blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| pin)
+            .find(|pin| &pin.name == name)
+    };
+    if pinned.map_or(false, |pin| &pin.version == version) {
+        return Ok(vec![Reason::Chosen]);
+    }
+    let artifacts = db.available_artifacts(name)?;
+    let Some(ais) = artifacts.get(version) else {
+        return Ok(vec![Reason::NotOnIndex]);
+    };
+
+    let mut reasons = Vec::new();
+    let policy = db.policy();
+    if let Some(why) = policy.excludes_release(name, version)? {
+        reasons.push(Reason::Policy { why });
+    }
+    let all_pre = artifacts.keys().all(|v| v.is_prerelease());
+    if version.is_prerelease() && !all_pre && !brief.allow_pre.allow_pre_for(name) {
+        reasons.push(Reason::PreRelease);
+    }
+    // Each artifact only has to get past the first thing that rules it out, so these
+    // only count if they rule out all of them. (The same checks as the resolver, in
+    // the same order; see fetch_and_sort_versions.)
+    let python = PythonFilter::new(&blueprint.pybi.version);
+    let no_binary = db.no_binary(name);
+    let mut binary = false;
+    let mut yanked = None;
+    let mut requires_python = None;
+    let mut excluded = None;
+    let now = std::time::SystemTime::now();
+    let mut usable = Vec::new();
+    for ai in ais {
+        if no_binary && ai.name.inner_as::<SdistName>().is_none() {
+            binary = true;
+            continue;
+        }
+        if ai.yanked.yanked {
+            yanked.get_or_insert(ai.yanked.reason.clone());
+            continue;
+        }
+        if let Some(rp) = ai.requires_python.as_ref().filter(|_| !is_pybi) {
+            let allowed = python.allows(rp).wrap_err_with(|| {
+                format!("{} has a Requires-Python we can't parse", ai.name)
+            })?;
+            if !allowed {
+                requires_python.get_or_insert(rp.clone());
+                continue;
+            }
+        }
+        if let Some(why) = policy.excludes_artifact(ai, now) {
+            excluded.get_or_insert(why);
+            continue;
+        }
+        usable.push(ai.clone());
+    }
+    if usable.is_empty() {
+        if binary {
+            reasons.push(Reason::NoBinary);
+        }
+        if let Some(why) = yanked {
+            reasons.push(Reason::Yanked { why });
+        }
+        if let Some(requires_python) = requires_python {
+            reasons.push(Reason::RequiresPython {
+                requires_python,
+                python: blueprint.pybi.version.clone(),
+            });
+        }
+        if let Some(why) = excluded {
+            reasons.push(Reason::Policy { why });
+        }
+    } else if is_pybi && pick_best_pybi(&usable, platforms).is_none() {
+        reasons.push(Reason::WrongPlatform {
+            platforms: platforms.iter().map(|p| p.core_tag().to_owned()).collect(),
+        });
+    }
+
+    let your_requirements = "the environment's requirements".to_string();
+    if is_pybi && !brief.python.specifiers.satisfied_by(version)? {
+        reasons.push(Reason::OutOfRange {
+            who: your_requirements.clone(),
+            requirement: brief.python.to_string(),
+        });
+    }
+    for req in brief.requirements.iter().chain(&brief.constraints) {
+        if &req.name == name && !req.specifiers.satisfied_by(version)? {
+            reasons.push(Reason::OutOfRange {
+                who: your_requirements.clone(),
+                requirement: req.to_string(),
+            });
+        }
+    }
+    for (pin, req) in blueprint.requirements_on(name)? {
+        if !req.specifiers.satisfied_by(version)? {
+            reasons.push(Reason::OutOfRange {
+                who: format!("{} {}", pin.name.as_given(), pin.version),
+                requirement: req.to_string(),
+            });
+        }
+    }
+
+    if reasons.is_empty() {
+        reasons.push(match pinned {
+            None => Reason::NotNeeded,
+            Some(pin) if pin.version > *version => Reason::LostTo {
+                version: pin.version.clone(),
+            },
+            Some(_) => Reason::Conflicts,
+        });
+    }
+    Ok(reasons)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package_db::MemoryIndex;
+    use crate::test_util::{test_platform, TestDB};
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_pybi("cpython_unofficial", "3.10.9", "macosx_11_0_arm64")?;
+        index.add_wheel("app", "1.0", &["lib < 3"])?;
+        index.add_wheel("lib", "1.0", &[])?;
+        index.add_wheel("lib", "1.5", &["missing"])?;
+        index.add_wheel("lib", "2.0", &[])?;
+        let yanked = index.add_wheel("lib", "2.1", &[])?;
+        yanked.yanked.yanked = true;
+        yanked.yanked.reason = Some("oops".into());
+        index.add_wheel("lib", "2.2", &[])?.requires_python = Some(">= 3.11".into());
+        index.add_wheel("lib", "2.3rc1", &[])?;
+        index.add_wheel("lib", "3.0", &[])?;
+        index.add_wheel("unrelated", "1.0", &[])?;
+        index.add_wheel("broken", "1.0", &[])?.requires_python = Some("3!!".into());
+        index.add_wheel("wheel-only", "1.0", &[])?;
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        db.set_no_binary(vec!["wheel-only".try_into()?]);
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["app".try_into()?, "lib != 1.0".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+        let platform = test_platform();
+        let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+
+        let why_not = |name: &str, version: &str| -> Result<Vec<String>> {
+            let name: PackageName = name.try_into()?;
+            let version: Version = version.try_into()?;
+            Ok(
+                explain(&db, &brief, &blueprint, &[&platform], &name, &version)?
+                    .iter()
+                    .map(|reason| reason.to_string())
+                    .collect(),
+            )
+        };
+        assert_eq!(why_not("lib", "2.0")?, vec!["it's the version we picked"]);
+        assert_eq!(why_not("lib", "4.0")?, vec!["the index doesn't have it"]);
+        assert_eq!(
+            why_not("unrelated", "1.0")?,
+            vec!["nothing in the environment needs it"]
+        );
+        assert_eq!(
+            why_not("lib", "1.0")?,
+            vec!["the environment's requirements say lib != 1.0"]
+        );
+        // we don't look any deeper than that
+        assert!(why_not("lib", "1.5")?[0].starts_with("2.0 works too"));
+        assert_eq!(why_not("lib", "2.1")?, vec!["it's been yanked (oops)"]);
+        assert_eq!(
+            why_not("lib", "2.2")?,
+            vec!["it requires Python >= 3.11, and the environment has 3.10.8"]
+        );
+        assert_eq!(
+            why_not("lib", "2.3rc1")?,
+            vec!["it's a pre-release, and nothing asked for pre-releases of it"]
+        );
+        assert_eq!(why_not("lib", "3.0")?, vec!["app 1.0 says lib < 3"]);
+        assert_eq!(
+            why_not("cpython_unofficial", "3.10.9")?,
+            vec!["it doesn't have anything for this platform (manylinux_2_17_x86_64)"]
+        );
+        // the resolver would have choked on it too, so we do
+        assert!(why_not("broken", "1.0").is_err());
+        assert_eq!(
+            why_not("wheel-only", "1.0")?,
+            vec!["it's set to no-binary, and doesn't have an sdist to build"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1030 @@
+use crate::package_db::{HelperCommand, HostAllowlist, PipCache};
+use crate::prelude::*;
+use crate::tree::UnpackLimits;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+// Configuration that isn't about any one environment, stored in posy.toml files: one
+// for the whole machine, one inside the user's config directory (e.g.
+// ~/.config/posy/posy.toml on Linux), and one next to the project's pyproject.toml.
+// On top of those, the most important settings can also be set with POSY_*
+// environment variables (see `ENV_VARS`), and then with command-line flags (see
+// `ConfigOverrides`), so CI can configure posy without writing any files.
+
+/// Environment variables and PATH additions to inject into commands that we run inside
+/// a posy environment.
+///
+/// These can be declared in several places -- the user's global posy.toml, the
+/// project's pyproject.toml, and on the command line. Each place is a separate layer,
+/// and they're applied in that order, so later layers win. Values can refer to
+/// `${NAME}`, which expands to whatever NAME was set to before this layer was applied
+/// (or the empty string if it wasn't set at all), so e.g.
+///
+///   LD_LIBRARY_PATH = "vendor/lib:${LD_LIBRARY_PATH}"
+///
+/// extends the inherited value instead of replacing it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EnvConfig {
+    pub env: BTreeMap<String, String>,
+    /// Extra directories to search for executables, ahead of everything else on $PATH
+    /// (including the environment's own scripts).
+    pub path: Vec<PathBuf>,
+}
+
+static VAR_REF: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+fn expand_vars<F>(value: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    VAR_REF
+        .replace_all(value, |captures: &regex::Captures| {
+            lookup(&captures[1]).unwrap_or_default()
+        })
+        .into_owned()
+}
+
+/// What the child process would see for `name`, given what's been configured on `cmd`
+/// so far.
+fn inherited(cmd: &Command, name: &str) -> Option<OsString> {
+    for (key, value) in cmd.get_envs() {
+        if key == OsStr::new(name) {
+            return value.map(|v| v.to_owned());
+        }
+    }
+    std::env::var_os(name)
+}
+
+impl EnvConfig {
+    /// Relative `path` entries are interpreted relative to the file that declared them.
+    pub fn rebase(mut self, base: &Path) -> EnvConfig {
+        self.path = self.path.into_iter().map(|p| base.join(p)).collect();
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.path.is_empty()
+    }
+
+    pub fn apply(&self, cmd: &mut Command) -> Result<()> {
+        if !self.path.is_empty() {
+            let mut paths = self.path.clone();
+            if let Some(old_path) = inherited(cmd, "PATH") {
+                paths.extend(std::env::split_paths(&old_path));
+            }
+            cmd.env("PATH", std::env::join_paths(&paths)?);
+        }
+        // expand everything before setting anything, so that references always see the
+        // previous layer's value
+        let expanded = self
+            .env
+            .iter()
+            .map(|(name, value)| {
+                let value = expand_vars(value, |var| {
+                    inherited(cmd, var).map(|v| v.to_string_lossy().into_owned())
+                });
+                (name, value)
+            })
+            .collect::<Vec<_>>();
+        for (name, value) in expanded {
+            cmd.env(name, value);
+        }
+        Ok(())
+    }
+}
+
+/// Where a setting came from. They're read in this order, and later ones take
+/// precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigLayer {
+    /// Machine-wide, for administrators: /etc/posy/posy.toml, or
+    /// %PROGRAMDATA%\posy\posy.toml on Windows.
+    System,
+    /// The user's own, in the platform's config directory.
+    User,
+    /// Next to the project's pyproject.toml.
+    Project,
+    /// POSY_* environment variables.
+    Environment,
+    /// Flags like `--offline`, which apply to every command.
+    CommandLine,
+}
+
+impl Display for ConfigLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConfigLayer::System => "system",
+            ConfigLayer::User => "user",
+            ConfigLayer::Project => "project",
+            ConfigLayer::Environment => "environment",
+            ConfigLayer::CommandLine => "command-line",
+        })
+    }
+}
+
+/// One setting, as written in one of the layers. For `posy config`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Setting {
+    pub layer: ConfigLayer,
+    /// The file, environment variable, or flag it came from
+    pub source: String,
+    /// The value, as TOML
+    pub value: String,
+    /// False if a later layer replaced it.
+    pub effective: bool,
+}
+
+/// Every setting posy.toml can have. Most of them are replaced outright by later
+/// layers; the ones in `ACCUMULATED_SETTINGS` add up instead.
+pub const SETTINGS: &[&str] = &[
+    "index-urls",
+    "indexes",
+    "use-pip-config",
+    "ignore-pip-cache",
+    "cache-dir",
+    "data-dir",
+    "platforms",
+    "proxy",
+    "offline",
+    "wheelhouse",
+    "sigstore-trusted-root",
+    "stale-if-error",
+    "concurrency",
+    "allowed-hosts",
+    "credential-helpers",
+    "policy",
+    "build-constraints",
+    "unpack-limits",
+    "wheel-preference",
+    "no-binary",
+    "env",
+    "path",
+];
+
+/// Every layer's build constraints and policy apply, so e.g. a user can't opt out of
+/// their administrator's policy. `[env]` and `path` are applied one layer at a time,
+/// like `EnvConfig` says, so within `[env]` it's each variable that gets replaced.
+const ACCUMULATED_SETTINGS: &[&str] = &["policy", "build-constraints", "path"];
+
+/// Settings that a project's posy.toml can only make stricter, since it comes along
+/// with the project and we might not trust it: each unpack limit ends up the lower of
+/// the two, and a host has to be on every `allowed-hosts` list.
+const TIGHTEN_ONLY_SETTINGS: &[&str] = &["allowed-hosts", "unpack-limits"];
+
+/// Tables whose entries are replaced one at a time, and show up in `posy config` as
+/// e.g. `env.PYTHONWARNINGS`.
+pub const TABLE_SETTINGS: &[&str] = &["env", "indexes", "credential-helpers"];
+
+/// The environment variables that stand in for settings, between the posy.toml files
+/// and the command line. Lists are separated by commas or whitespace, and true/false
+/// can also be 1/0, yes/no, or on/off. Empty ones are ignored, and relative paths are
+/// relative to the current directory.
+///
+/// There's also POSY_CONFIG, which is read instead of the user's posy.toml.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("POSY_INDEX_URL", "index-urls"),
+    ("POSY_USE_PIP_CONFIG", "use-pip-config"),
+    ("POSY_IGNORE_PIP_CACHE", "ignore-pip-cache"),
+    ("POSY_CACHE_DIR", "cache-dir"),
+    ("POSY_DATA_DIR", "data-dir"),
+    ("POSY_PLATFORMS", "platforms"),
+    ("POSY_PROXY", "proxy"),
+    ("POSY_OFFLINE", "offline"),
+    ("POSY_WHEELHOUSE", "wheelhouse"),
+    ("POSY_SIGSTORE_TRUSTED_ROOT", "sigstore-trusted-root"),
+    ("POSY_STALE_IF_ERROR", "stale-if-error"),
+    ("POSY_CONCURRENCY", "concurrency"),
+    ("POSY_ALLOWED_HOSTS", "allowed-hosts"),
+    ("POSY_POLICY", "policy"),
+    ("POSY_NO_BINARY", "no-binary"),
+];
+
+/// Turns one of `ENV_VARS` into the TOML value posy.toml would have for it.
+fn env_value(var: &str, key: &str, value: &str) -> Result<toml_edit::Item> {
+    let parsed = match key {
+        "index-urls" | "platforms" | "allowed-hosts" | "no-binary" => {
+            let items = value.split(|c: char| c == ',' || c.is_whitespace());
+            let array: toml_edit::Array = items.filter(|i| !i.is_empty()).collect();
+            toml_edit::Value::from(array)
+        }
+        "use-pip-config" | "ignore-pip-cache" | "offline" => {
+            match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => true.into(),
+                "0" | "false" | "no" | "off" => false.into(),
+                _ => bail!("{var} should be true or false, not {value:?}"),
+            }
+        }
+        "concurrency" => match value.parse::<i64>() {
+            Ok(n) if n > 0 => n.into(),
+            _ => bail!("{var} should be a positive number, not {value:?}"),
+        },
+        _ => value.into(),
+    };
+    Ok(toml_edit::value(parsed))
+}
+
+/// What the POSY_* environment variables say, as a posy.toml would say it. `lookup`
+/// is `std::env::var`, except in tests.
+fn env_document<F>(lookup: F) -> Result<toml_edit::Document>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut document = toml_edit::Document::new();
+    for (var, key) in ENV_VARS {
+        match lookup(var) {
+            Some(value) if !value.trim().is_empty() => {
+                document[*key] = env_value(var, key, value.trim())?;
+            }
+            _ => (),
+        }
+    }
+    Ok(document)
+}
+
+/// Which of `ENV_VARS` sets `key`, for `posy config`.
+fn env_var_for(key: &str) -> String {
+    match ENV_VARS.iter().find(|(_, k)| *k == key) {
+        Some((var, _)) => var.to_string(),
+        None => key.into(),
+    }
+}
+
+/// Settings that can be overridden for a single run, whatever posy.toml and the
+/// POSY_* environment variables say. These work with every command.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ConfigOverrides {
+    /// Don't touch the network: use whatever's cached, however old, and fail if
+    /// something isn't. [env: POSY_OFFLINE]
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Install only from this directory, made by `posy bundle`, instead of any index.
+    /// [env: POSY_WHEELHOUSE]
+    #[arg(long, value_name = "DIR", global = true)]
+    wheelhouse: Option<PathBuf>,
+    /// Where to look for packages, instead of PyPI. (Can be repeated.) [env:
+    /// POSY_INDEX_URL]
+    #[arg(long = "index-url", value_name = "URL", global = true)]
+    index_urls: Vec<Url>,
+    /// Where to keep downloads. [env: POSY_CACHE_DIR]
+    #[arg(long, value_name = "DIR", global = true)]
+    cache_dir: Option<PathBuf>,
+    /// Where to keep installed Pythons and environments. [env: POSY_DATA_DIR]
+    #[arg(long, value_name = "DIR", global = true)]
+    data_dir: Option<PathBuf>,
+    /// Platform tags to target instead of this machine's, separated by commas. [env:
+    /// POSY_PLATFORMS]
+    #[arg(long, value_name = "TAGS", value_delimiter = ',', global = true)]
+    platforms: Vec<String>,
+    /// How many downloads to run at once. [env: POSY_CONCURRENCY]
+    #[arg(long, value_name = "N", global = true)]
+    concurrency: Option<usize>,
+    /// An HTTP(S) proxy for everything we fetch. [env: POSY_PROXY]
+    #[arg(long, value_name = "URL", global = true)]
+    proxy: Option<String>,
+    /// Packages to build from source instead of installing their wheels, separated by
+    /// commas. [env: POSY_NO_BINARY]
+    #[arg(long, value_name = "PACKAGES", value_delimiter = ',', global = true)]
+    no_binary: Vec<PackageName>,
+}
+
+static OVERRIDES: once_cell::sync::OnceCell<ConfigOverrides> =
+    once_cell::sync::OnceCell::new();
+
+impl ConfigOverrides {
+    /// Makes these apply to every `GlobalConfig` we load from now on.
+    pub fn install(self) {
+        // (only main calls this, once)
+        let _ = OVERRIDES.set(self);
+    }
+
+    /// These flags, as a posy.toml would say them.
+    fn document(&self) -> Result<toml_edit::Document> {
+        let mut document = toml_edit::Document::new();
+        if self.offline {
+            document["offline"] = toml_edit::value(true);
+        }
+        if !self.index_urls.is_empty() {
+            let urls = self.index_urls.iter().map(|url| url.as_str());
+            document["index-urls"] =
+                toml_edit::value(urls.collect::<toml_edit::Array>());
+        }
+        if let Some(dir) = &self.wheelhouse {
+            document["wheelhouse"] = toml_edit::value(path_str(dir)?);
+        }
+        if let Some(dir) = &self.cache_dir {
+            document["cache-dir"] = toml_edit::value(path_str(dir)?);
+        }
+        if let Some(dir) = &self.data_dir {
+            document["data-dir"] = toml_edit::value(path_str(dir)?);
+        }
+        if !self.platforms.is_empty() {
+            let platforms = self.platforms.iter().map(String::as_str);
+            document["platforms"] =
+                toml_edit::value(platforms.collect::<toml_edit::Array>());
+        }
+        if let Some(concurrency) = self.concurrency {
+            if concurrency == 0 {
+                bail!("--concurrency has to be at least 1");
+            }
+            document["concurrency"] = toml_edit::value(concurrency as i64);
+        }
+        if let Some(proxy) = &self.proxy {
+            document["proxy"] = toml_edit::value(proxy.as_str());
+        }
+        if !self.no_binary.is_empty() {
+            let names = self.no_binary.iter().map(|name| name.as_given());
+            document["no-binary"] =
+                toml_edit::value(names.collect::<toml_edit::Array>());
+        }
+        Ok(document)
+    }
+
+    /// Which flag sets `key`, for `posy config`.
+    fn flag_for(key: &str) -> String {
+        match key {
+            "index-urls" => "--index-url".into(),
+            _ => format!("--{key}"),
+        }
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| eyre!("{} isn't valid UTF-8", path.display()))
+}
+
+/// What a single posy.toml says.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct LayerConfig {
+    build_constraints: Vec<UserRequirement>,
+    policy: Option<PathBuf>,
+    unpack_limits: Option<UnpackLimits>,
+    allowed_hosts: Option<HostAllowlist>,
+    ignore_pip_cache: Option<bool>,
+    index_urls: Option<Vec<Url>>,
+    indexes: BTreeMap<String, Url>,
+    use_pip_config: Option<bool>,
+    cache_dir: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    platforms: Option<Vec<String>>,
+    proxy: Option<String>,
+    offline: Option<bool>,
+    wheelhouse: Option<PathBuf>,
+    sigstore_trusted_root: Option<PathBuf>,
+    stale_if_error: Option<String>,
+    concurrency: Option<usize>,
+    credential_helpers: BTreeMap<String, HelperCommand>,
+    wheel_preference: Option<WheelPreference>,
+    no_binary: Option<Vec<PackageName>>,
+    #[serde(flatten)]
+    run_env: EnvConfig,
+}
+
+/// Configuration that isn't specific to one project's environments, from the system,
+/// user, and project posy.toml files, then the environment and the command line (see
+/// `ConfigLayer`). Relative paths in each file are relative to that file.
+#[derive(Debug, Clone, Default)]
+pub struct GlobalConfig {
+    /// Constraints for resolving sdist build environments, on top of whatever each
+    /// project has.
+    pub build_constraints: Vec<UserRequirement>,
+    /// Organizational policy files, that apply whenever we resolve anything. See the
+    /// `policy` module for the format.
+    pub policy: Vec<PathBuf>,
+    /// Limits on the sizes of the artifacts we unpack; see `tree::UnpackLimits`. A
+    /// project's posy.toml can only lower them.
+    pub unpack_limits: UnpackLimits,
+    /// If set, the only hosts we'll fetch anything from -- indexes, mirrors, the CDNs
+    /// they hand out artifacts from, and api.osv.dev for `posy audit`. See
+    /// `HostAllowlist` for the syntax. A project's posy.toml can only narrow it down.
+    pub allowed_hosts: Option<HostAllowlist>,
+    /// Normally we look in pip's cache before downloading anything, since it probably
+    /// has a lot of what we want already. Set this to stop that.
+    pub ignore_pip_cache: bool,
+    /// Where to look for packages, in order, instead of PyPI. (Pythons still come from
+    /// posy's own pybi index.)
+    pub index_urls: Vec<Url>,
+    /// More indexes, by name, that are only used for the requirements that say to get
+    /// them from there (see `RequirementSource`):
+    ///
+    ///   [indexes]
+    ///   internal = "https://pypi.internal.example.com/simple/"
+    pub indexes: BTreeMap<String, Url>,
+    /// If `index-urls` isn't set, use whatever indexes pip is configured to use; see
+    /// the `pip_config` module.
+    pub use_pip_config: bool,
+    /// Where to keep downloads and other things we can always fetch again, instead of
+    /// the platform's cache directory.
+    pub cache_dir: Option<PathBuf>,
+    /// Where to keep installed Pythons and environments, instead of the platform's
+    /// data directory.
+    pub data_dir: Option<PathBuf>,
+    /// Platform tags to use instead of detecting this machine's, e.g.
+    /// `["manylinux_2_17_x86_64"]` to stick to an older glibc baseline than we're
+    /// running on.
+    pub platforms: Vec<String>,
+    /// An HTTP(S) proxy for everything we fetch, like `http://proxy.example.com:3128`.
+    pub proxy: Option<String>,
+    /// Never go to the network; see `Http::set_offline`.
+    pub offline: bool,
+    /// Install from this directory, made by `posy bundle`, instead of the indexes,
+    /// and don't go to the network at all; see `PackageDB::set_wheelhouse`.
+    pub wheelhouse: Option<PathBuf>,
+    /// A sigstore `trusted_root.json` to check attestations against; see the
+    /// `attestations` module. Without one, attestations can't be checked at all.
+    pub sigstore_trusted_root: Option<PathBuf>,
+    /// If the index is down, use cached pages that went out of date up to this long
+    /// ago, like "3d", instead of failing. See `Http::set_stale_if_error`. Zero turns
+    /// it back off.
+    pub stale_if_error: Option<Duration>,
+    /// How many index pages and artifacts to fetch at once. None means no particular
+    /// limit.
+    pub concurrency: Option<usize>,
+    /// Programs to get private indexes' credentials from, by host; see
+    /// `CredentialHelpers`.
+    pub credential_helpers: BTreeMap<String, HelperCommand>,
+    /// How to choose between wheels that would all work; see `WheelPreference`.
+    pub wheel_preference: WheelPreference,
+    /// Packages to build from their sdists instead of installing any of their wheels,
+    /// e.g. because policy says native code has to be compiled in-house. Locking skips
+    /// their releases that only have wheels, so adding a package here can mean
+    /// re-locking: posy.lock counts as out of date if it pins one of those.
+    pub no_binary: Vec<PackageName>,
+    /// Each layer's `[env]` and `path`, in order.
+    pub run_envs: Vec<EnvConfig>,
+    /// Where each setting came from, in the order the layers were read.
+    pub settings: BTreeMap<String, Vec<Setting>>,
+}
+
+/// Reads `path`, if it's there.
+fn read_if_exists(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)?,
+    }
+}
+
+impl GlobalConfig {
+    /// The user's posy.toml, or whatever POSY_CONFIG says to use instead.
+    pub fn path() -> PathBuf {
+        match std::env::var_os("POSY_CONFIG") {
+            Some(path) if !path.is_empty() => path.into(),
+            _ => PROJECT_DIRS.config_dir().join("posy.toml"),
+        }
+    }
+
+    /// The machine-wide posy.toml.
+    pub fn system_path() -> PathBuf {
+        if cfg!(windows) {
+            let program_data = std::env::var_os("PROGRAMDATA")
+                .unwrap_or_else(|| OsString::from(r"C:\ProgramData"));
+            Path::new(&program_data).join("posy").join("posy.toml")
+        } else {
+            PathBuf::from("/etc/posy/posy.toml")
+        }
+    }
+
+    /// The system and user layers, for commands that don't work on a project.
+    pub fn load() -> Result<GlobalConfig> {
+        GlobalConfig::load_for(None)
+    }
+
+    /// The system and user layers, plus the project's posy.toml, if there's a project
+    /// at `project_root`, and then the environment and the command line.
+    pub fn load_for(project_root: Option<&Path>) -> Result<GlobalConfig> {
+        let mut layers = vec![
+            (ConfigLayer::System, GlobalConfig::system_path()),
+            (ConfigLayer::User, GlobalConfig::path()),
+        ];
+        if let Some(root) = project_root {
+            layers.push((ConfigLayer::Project, root.join("posy.toml")));
+        }
+        let mut config = GlobalConfig::default();
+        for (layer, path) in layers {
+            if let Some(contents) = read_if_exists(&path)? {
+                context!("Loading configuration from {}", path.display());
+                config.add_layer(layer, &path, &contents)?;
+            }
+        }
+        let cwd = std::env::current_dir()?;
+        let environment = env_document(|var| std::env::var(var).ok())?;
+        config.add_document(
+            ConfigLayer::Environment,
+            &cwd,
+            &environment,
+            env_var_for,
+        )?;
+        if let Some(overrides) = OVERRIDES.get() {
+            let flags = overrides.document()?;
+            config.add_document(
+                ConfigLayer::CommandLine,
+                &cwd,
+                &flags,
+                ConfigOverrides::flag_for,
+            )?;
+        }
+        // This is process-wide, so it has to happen before anyone needs the platforms
+        if !config.platforms.is_empty() {
+            PybiPlatform::set_native_platforms(&config.platforms)?;
+        }
+        Ok(config)
+    }
+
+    fn add_layer(
+        &mut self,
+        layer: ConfigLayer,
+        path: &Path,
+        contents: &str,
+    ) -> Result<()> {
+        let document: toml_edit::Document = contents.parse()?;
+        let source = path.display().to_string();
+        self.add_document(layer, path.parent().unwrap(), &document, |_| source.clone())
+    }
+
+    /// Adds a layer on top of the ones so far. Relative paths are relative to `base`,
+    /// and `source` says where each key came from.
+    fn add_document<F>(
+        &mut self,
+        layer: ConfigLayer,
+        base: &Path,
+        document: &toml_edit::Document,
+        source: F,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> String,
+    {
+        let parsed: LayerConfig = toml_edit::de::from_str(&document.to_string())?;
+        for (key, item) in document.iter() {
+            if TABLE_SETTINGS.contains(&key) {
+                if let Some(table) = item.as_table_like() {
+                    for (name, value) in table.iter() {
+                        let entry_key = format!("{key}.{name}");
+                        self.record(entry_key, layer, source(key), value, true);
+                    }
+                }
+            } else if SETTINGS.contains(&key) {
+                let replaces = !ACCUMULATED_SETTINGS.contains(&key)
+                    && !(layer == ConfigLayer::Project
+                        && TIGHTEN_ONLY_SETTINGS.contains(&key));
+                self.record(key.into(), layer, source(key), item, replaces);
+            } else {
+                warn!("{}: unknown setting '{key}'", source(key));
+            }
+        }
+
+        let LayerConfig {
+            build_constraints,
+            policy,
+            unpack_limits,
+            allowed_hosts,
+            ignore_pip_cache,
+            index_urls,
+            indexes,
+            use_pip_config,
+            cache_dir,
+            data_dir,
+            platforms,
+            proxy,
+            offline,
+            wheelhouse,
+            sigstore_trusted_root,
+            stale_if_error,
+            concurrency,
+            credential_helpers,
+            wheel_preference,
+            no_binary,
+            run_env,
+        } = parsed;
+        self.build_constraints.extend(build_constraints);
+        self.policy.extend(policy.map(|p| base.join(p)));
+        let tighten_only = layer == ConfigLayer::Project;
+        if let Some(unpack_limits) = unpack_limits {
+            self.unpack_limits = match tighten_only {
+                true => self.unpack_limits.tightened(&unpack_limits),
+                false => unpack_limits,
+            };
+        }
+        if let Some(allowed_hosts) = allowed_hosts {
+            self.allowed_hosts = match (tighten_only, self.allowed_hosts.take()) {
+                (true, Some(earlier)) => Some(earlier.narrowed(allowed_hosts)),
+                _ => Some(allowed_hosts),
+            };
+        }
+        if let Some(ignore_pip_cache) = ignore_pip_cache {
+            self.ignore_pip_cache = ignore_pip_cache;
+        }
+        if let Some(index_urls) = index_urls {
+            self.index_urls = index_urls;
+        }
+        self.indexes.extend(indexes);
+        if let Some(use_pip_config) = use_pip_config {
+            self.use_pip_config = use_pip_config;
+        }
+        if let Some(cache_dir) = cache_dir {
+            self.cache_dir = Some(base.join(cache_dir));
+        }
+        if let Some(data_dir) = data_dir {
+            self.data_dir = Some(base.join(data_dir));
+        }
+        if let Some(platforms) = platforms {
+            self.platforms = platforms;
+        }
+        if proxy.is_some() {
+            self.proxy = proxy;
+        }
+        if let Some(offline) = offline {
+            self.offline = offline;
+        }
+        if let Some(wheelhouse) = wheelhouse {
+            self.wheelhouse = Some(base.join(wheelhouse));
+        }
+        if let Some(trusted_root) = sigstore_trusted_root {
+            self.sigstore_trusted_root = Some(base.join(trusted_root));
+        }
+        if let Some(stale_if_error) = stale_if_error {
+            let max_stale =
+                humantime::parse_duration(&stale_if_error).wrap_err_with(|| {
+                    format!(
+                        "stale-if-error should be like \"3d\", not {stale_if_error:?}"
+                    )
+                })?;
+            self.stale_if_error = Some(max_stale).filter(|d| !d.is_zero());
+        }
+        if concurrency.is_some() {
+            self.concurrency = concurrency;
+        }
+        for (host, command) in credential_helpers {
+            self.credential_helpers.insert(host, command.rebase(base));
+        }
+        if let Some(wheel_preference) = wheel_preference {
+            wheel_preference.validate()?;
+            self.wheel_preference = wheel_preference;
+        }
+        if let Some(no_binary) = no_binary {
+            self.no_binary = no_binary;
+        }
+        if !run_env.is_empty() {
+            self.run_envs.push(run_env.rebase(base));
+        }
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        key: String,
+        layer: ConfigLayer,
+        source: String,
+        item: &toml_edit::Item,
+        replaces: bool,
+    ) {
+        let settings = self.settings.entry(key).or_default();
+        if replaces {
+            for setting in settings.iter_mut() {
+                setting.effective = false;
+            }
+        }
+        settings.push(Setting {
+            layer,
+            source,
+            value: item.to_string().trim().into(),
+            effective: true,
+        });
+    }
+
+    /// Where to look for pip's cache, if we're looking at all.
+    pub fn pip_cache_dir(&self) -> Option<PathBuf> {
+        match self.ignore_pip_cache {
+            true => None,
+            false => PipCache::default_location(),
+        }
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None => PROJECT_DIRS.cache_dir().into(),
+        }
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        match &self.data_dir {
+            Some(dir) => dir.clone(),
+            None => PROJECT_DIRS.data_local_dir().into(),
+        }
+    }
+
+    /// The `[env]` layers to apply to commands, before the project's own.
+    pub fn run_env_layers(&self) -> Vec<&EnvConfig> {
+        self.run_envs.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_vars() {
+        let lookup = |var: &str| match var {
+            "HOME" => Some("/home/me".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand_vars("${HOME}/bin", lookup), "/home/me/bin");
+        assert_eq!(expand_vars("a:${MISSING}:b", lookup), "a::b");
+        assert_eq!(expand_vars("$HOME ${ HOME}", lookup), "$HOME ${ HOME}");
+    }
+
+    #[test]
+    fn test_config_layers() -> Result<()> {
+        let mut config = GlobalConfig::default();
+        config.add_layer(
+            ConfigLayer::System,
+            Path::new("/etc/posy/posy.toml"),
+            indoc::indoc! {r#"
+                index-urls = ["https://mirror.example.com/simple/"]
+                policy = "policy.toml"
+                proxy = "http://proxy.example.com:3128"
+
+                [env]
+                PIP_NO_INPUT = "1"
+
+                [indexes]
+                internal = "https://pypi.internal.example.com/simple/"
+
+                [credential-helpers]
+                "*.pkg.dev" = "gar-helper"
+                "pypi.internal.example.com" = ["helpers/vault.sh", "--quiet"]
+            "#},
+        )?;
+        config.add_layer(
+            ConfigLayer::User,
+            Path::new("/home/me/.config/posy/posy.toml"),
+            indoc::indoc! {r#"
+                index-urls = ["https://other.example.com/simple/"]
+                policy = "/home/me/mine.toml"
+                cache-dir = "cache"
+
+                [indexes]
+                nightly = "https://nightly.example.com/simple/"
+
+                [credential-helpers]
+                "*.pkg.dev" = "my-gar-helper"
+
+                [wheel-preference]
+                prefer = "pure"
+            "#},
+        )?;
+
+        // replaced
+        assert_eq!(
+            config.index_urls,
+            vec![Url::parse("https://other.example.com/simple/")?]
+        );
+        // accumulated
+        assert_eq!(
+            config.policy,
+            vec![
+                PathBuf::from("/etc/posy/policy.toml"),
+                PathBuf::from("/home/me/mine.toml")
+            ]
+        );
+        // left alone
+        assert_eq!(
+            config.proxy.as_deref(),
+            Some("http://proxy.example.com:3128")
+        );
+        assert_eq!(
+            config.cache_dir(),
+            PathBuf::from("/home/me/.config/posy/cache")
+        );
+        assert_eq!(config.run_env_layers().len(), 1);
+        assert_eq!(config.wheel_preference.prefer, Some(WheelKind::Pure));
+        // added to, one name at a time
+        assert_eq!(
+            config.indexes.keys().collect::<Vec<_>>(),
+            vec!["internal", "nightly"]
+        );
+        // replaced one host at a time
+        assert_eq!(
+            config.credential_helpers,
+            BTreeMap::from([
+                (
+                    "*.pkg.dev".to_string(),
+                    HelperCommand::WithArgs(vec!["my-gar-helper".into()])
+                ),
+                (
+                    "pypi.internal.example.com".to_string(),
+                    HelperCommand::WithArgs(vec![
+                        "/etc/posy/helpers/vault.sh".into(),
+                        "--quiet".into()
+                    ])
+                ),
+            ])
+        );
+
+        let index_urls = &config.settings["index-urls"];
+        assert_eq!(index_urls.len(), 2);
+        assert!(!index_urls[0].effective);
+        assert!(index_urls[1].effective);
+        assert_eq!(index_urls[1].layer, ConfigLayer::User);
+        assert_eq!(
+            index_urls[1].value,
+            r#"["https://other.example.com/simple/"]"#
+        );
+        assert!(config.settings["policy"].iter().all(|s| s.effective));
+        assert_eq!(config.settings["env.PIP_NO_INPUT"][0].value, r#""1""#);
+        assert!(!config.settings["credential-helpers.*.pkg.dev"][0].effective);
+
+        let bad_level = config.add_layer(
+            ConfigLayer::Project,
+            Path::new("/work/posy.toml"),
+            "wheel-preference = { manylinux = \"2.17\" }",
+        );
+        assert!(bad_level.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_only_tightens() -> Result<()> {
+        let mut config = GlobalConfig::default();
+        config.add_layer(
+            ConfigLayer::User,
+            Path::new("/home/me/.config/posy/posy.toml"),
+            indoc::indoc! {r#"
+                allowed-hosts = ["pypi.org", "*.pythonhosted.org"]
+
+                [unpack-limits]
+                max-members = 1000
+            "#},
+        )?;
+        config.add_layer(
+            ConfigLayer::Project,
+            Path::new("/work/posy.toml"),
+            indoc::indoc! {r#"
+                allowed-hosts = ["pypi.org", "evil.example.com"]
+
+                [unpack-limits]
+                max-members = 1000000
+                max-compression-ratio = 10
+            "#},
+        )?;
+        let allowed = config.allowed_hosts.as_ref().unwrap();
+        assert!(allowed
+            .check(&Url::parse("https://pypi.org/simple/")?)
+            .is_ok());
+        for url in [
+            "https://evil.example.com/",
+            "https://files.pythonhosted.org/",
+        ] {
+            assert!(allowed.check(&Url::parse(url)?).is_err());
+        }
+        assert_eq!(config.unpack_limits.max_members, 1000);
+        assert_eq!(config.unpack_limits.max_compression_ratio, 10);
+        assert!(config.settings["allowed-hosts"].iter().all(|s| s.effective));
+
+        // but the user can still loosen things for themselves
+        config.add_layer(
+            ConfigLayer::Environment,
+            Path::new("/work"),
+            "allowed-hosts = [\"evil.example.com\"]",
+        )?;
+        let allowed = config.allowed_hosts.as_ref().unwrap();
+        assert!(allowed
+            .check(&Url::parse("https://evil.example.com/")?)
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_and_command_line() -> Result<()> {
+        let mut config = GlobalConfig::default();
+        config.add_layer(
+            ConfigLayer::User,
+            Path::new("/home/me/.config/posy/posy.toml"),
+            indoc::indoc! {r#"
+                index-urls = ["https://mirror.example.com/simple/"]
+                offline = false
+                concurrency = 2
+            "#},
+        )?;
+        let environment = env_document(|var| match var {
+            "POSY_INDEX_URL" => Some(
+                "https://a.example.com/simple/, https://b.example.com/simple/".into(),
+            ),
+            "POSY_OFFLINE" => Some("yes".into()),
+            "POSY_CONCURRENCY" => Some("4".into()),
+            "POSY_CACHE_DIR" => Some("cache".into()),
+            "POSY_STALE_IF_ERROR" => Some("2d".into()),
+            "POSY_PROXY" => Some("".into()),
+            "POSY_WHEELHOUSE" => Some("wheels".into()),
+            "POSY_SIGSTORE_TRUSTED_ROOT" => Some("trusted_root.json".into()),
+            "POSY_NO_BINARY" => Some("numpy,scipy".into()),
+            _ => None,
+        })?;
+        let cwd = Path::new("/work");
+        config.add_document(
+            ConfigLayer::Environment,
+            cwd,
+            &environment,
+            env_var_for,
+        )?;
+        let overrides = ConfigOverrides {
+            concurrency: Some(8),
+            no_binary: vec!["Pillow".try_into()?],
+            ..Default::default()
+        };
+        config.add_document(
+            ConfigLayer::CommandLine,
+            cwd,
+            &overrides.document()?,
+            ConfigOverrides::flag_for,
+        )?;
+
+        assert_eq!(
+            config.index_urls,
+            vec![
+                Url::parse("https://a.example.com/simple/")?,
+                Url::parse("https://b.example.com/simple/")?
+            ]
+        );
+        assert!(config.offline);
+        assert_eq!(config.concurrency, Some(8));
+        assert_eq!(config.cache_dir(), PathBuf::from("/work/cache"));
+        assert_eq!(config.wheelhouse, Some(PathBuf::from("/work/wheels")));
+        assert_eq!(
+            config.sigstore_trusted_root,
+            Some(PathBuf::from("/work/trusted_root.json"))
+        );
+        let pillow: PackageName = "pillow".try_into()?;
+        assert_eq!(config.no_binary, vec![pillow]);
+        assert_eq!(config.settings["no-binary"].len(), 2);
+        assert_eq!(config.proxy, None);
+        assert_eq!(
+            config.stale_if_error,
+            Some(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+
+        let concurrency = &config.settings["concurrency"];
+        assert_eq!(
+            concurrency
+                .iter()
+                .map(|s| (s.layer, s.source.as_str(), s.effective))
+                .collect::<Vec<_>>(),
+            vec![
+                (ConfigLayer::User, "/home/me/.config/posy/posy.toml", false),
+                (ConfigLayer::Environment, "POSY_CONCURRENCY", false),
+                (ConfigLayer::CommandLine, "--concurrency", true),
+            ]
+        );
+
+        assert!(
+            env_document(|var| (var == "POSY_OFFLINE").then(|| "maybe".into()))
+                .is_err()
+        );
+        assert!(
+            env_document(|var| (var == "POSY_CONCURRENCY").then(|| "0".into()))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_layering() {
+        let global: EnvConfig = toml_edit::de::from_str(indoc::indoc! {r#"
+            path = ["/opt/tools/bin"]
+
+            [env]
+            GREETING = "hello"
+            UNTOUCHED = "same"
+        "#})
+        .unwrap();
+        let project: EnvConfig = toml_edit::de::from_str(indoc::indoc! {r#"
+            [env]
+            GREETING = "${GREETING} world"
+        "#})
+        .unwrap();
+
+        let mut cmd = Command::new("true");
+        cmd.env("PATH", "/usr/bin");
+        global.apply(&mut cmd).unwrap();
+        project.apply(&mut cmd).unwrap();
+
+        assert_eq!(inherited(&cmd, "GREETING").unwrap(), "hello world");
+        assert_eq!(inherited(&cmd, "UNTOUCHED").unwrap(), "same");
+        let path = inherited(&cmd, "PATH").unwrap();
+        assert_eq!(
+            std::env::split_paths(&path).collect::<Vec<_>>(),
+            vec![PathBuf::from("/opt/tools/bin"), PathBuf::from("/usr/bin")]
+        );
+    }
+}
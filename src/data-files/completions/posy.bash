@@ -0,0 +1,48 @@
+
+# Dynamic completions: script names for `posy run`, package names for `posy exec` and
+# `--with`, and environment names for `--env`. posy fills in which subcommands and
+# flags those are from its own command tree. Everything else goes to the static
+# completions above.
+_posy_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local subcommand="" positionals=0 i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -*) ;;
+            *)
+                if [[ -z "$subcommand" ]]; then
+                    subcommand="${COMP_WORDS[i]}"
+                else
+                    positionals=$((positionals + 1))
+                fi
+                ;;
+        esac
+    done
+    local kind=""
+    case "$subcommand $prev" in
+@OPTIONS@
+    esac
+    if [[ -z "$kind" && "$cur" != -* ]]; then
+        case "$subcommand $positionals" in
+@POSITIONALS@
+        esac
+    fi
+    case "$kind" in
+        packages)
+            COMPREPLY=($(posy __complete packages "$cur" 2>/dev/null))
+            ;;
+        envs)
+            COMPREPLY=($(compgen -W "$(posy __complete envs 2>/dev/null)" -- "$cur"))
+            ;;
+        scripts)
+            COMPREPLY=($(compgen -W "$(posy __complete scripts 2>/dev/null)" -- "$cur"))
+            COMPREPLY+=($(compgen -c -- "$cur"))
+            ;;
+        *)
+            _posy "$@"
+            ;;
+    esac
+}
+
+complete -F _posy_dynamic -o bashdefault -o default posy
@@ -0,0 +1,5 @@
+
+# Dynamic completions: script names for `posy run`, package names for `posy exec` and
+# `--with`, and environment names for `--env`. posy fills in which subcommands and
+# flags those are from its own command tree.
+@COMPLETIONS@
@@ -0,0 +1,64 @@
+# Run with the interpreter that `posy python build` is packaging, to find out what goes
+# in its pybi-info/METADATA. Prints JSON on stdout; everything else gets worked out on
+# the Rust side.
+import json
+import os
+import platform
+import sys
+import sysconfig
+
+
+def format_full_version(info):
+    version = "{0.major}.{0.minor}.{0.micro}".format(info)
+    if info.releaselevel != "final":
+        version += info.releaselevel[0] + str(info.serial)
+    return version
+
+
+def abi_tag():
+    if sys.implementation.name == "cpython":
+        abi = "cp{0}{1}".format(*sys.version_info[:2])
+        if sysconfig.get_config_var("Py_GIL_DISABLED"):
+            abi += "t"
+        if hasattr(sys, "gettotalrefcount"):
+            abi += "d"
+        return abi
+    # e.g. pypy39-pp73-x86_64-linux-gnu -> pypy39_pp73
+    soabi = sysconfig.get_config_var("SOABI")
+    if soabi:
+        return "_".join(soabi.split("-")[:2]).replace(".", "_")
+    return "none"
+
+
+# platform_release and platform_version are left out on purpose: they describe the
+# kernel we happen to be running on, not the pybi.
+markers = {
+    "implementation_name": sys.implementation.name,
+    "implementation_version": format_full_version(sys.implementation.version),
+    "os_name": os.name,
+    "platform_machine": platform.machine(),
+    "platform_python_implementation": platform.python_implementation(),
+    "platform_system": platform.system(),
+    "python_full_version": platform.python_version(),
+    "python_version": ".".join(platform.python_version_tuple()[:2]),
+    "sys_platform": sys.platform,
+}
+
+prefix = sys.prefix
+paths = {}
+for key, path in sysconfig.get_paths().items():
+    relative = os.path.relpath(path, prefix)
+    if relative == os.pardir or relative.startswith(os.pardir + os.sep):
+        sys.exit("sysconfig path {} ({}) is outside of {}".format(key, path, prefix))
+    paths[key] = relative.replace(os.sep, "/")
+
+json.dump(
+    {
+        "prefix": prefix,
+        "markers": markers,
+        "paths": paths,
+        "abi": abi_tag(),
+        "version": list(sys.version_info[:2]),
+    },
+    sys.stdout,
+)
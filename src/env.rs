@@ -1,10 +1,15 @@
 use std::borrow::Cow;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use crate::kvstore::KVDirStore;
-use crate::package_db::{ArtifactInfo, PackageDB, WheelBuilder};
-use crate::resolve::{PinnedPackage, WheelResolveMetadata};
+use crate::check_pybi::{check_pybi, Severity};
+use crate::error::Error;
+use crate::kvstore::{KVDirStore, PathKey};
+use crate::package_db::{
+    ArtifactInfo, AttestationRule, LocalTree, PackageDB, WheelBuilder,
+};
+use crate::resolve::{PinnedPackage, RequirementSource, WheelResolveMetadata};
 use crate::trampolines::{FindPython, ScriptPlatform, TrampolineMaker};
 use crate::tree::WriteTreeFS;
 use crate::{platform_tags::PybiPlatform, prelude::*, resolve::Blueprint};
@@ -31,18 +36,192 @@ use crate::{platform_tags::PybiPlatform, prelude::*, resolve::Blueprint};
 //     or I guess can use distlib's launchers, with either #!/usr/bin/env python.exe for
 //     find-on-path, or #!./python.exe for relative path
 
+/// Where installed Pythons and packages live, each unpacked once and shared between
+/// all the environments that use it.
+///
+/// Environments are remembered too, by the hash of their Blueprint, so asking for one
+/// we've put together before -- from any project with the same lock -- doesn't have to
+/// look at a single package.
 pub struct EnvForest {
     store: KVDirStore,
+    rebuild: bool,
+    /// The environments we've already rebuilt, in `rebuild` mode
+    rebuilt: Mutex<HashSet<Vec<u8>>>,
 }
 
-fn pick_pinned_binary<'a, 'b, T: BinaryArtifact>(
-    db: &'a PackageDB,
+/// A Python interpreter that's unpacked in the EnvForest.
+pub struct InstalledPybi {
+    pub root: PathBuf,
+    pub metadata: PybiCoreMetadata,
+}
+
+/// The metadata of the Python unpacked at `root`.
+fn read_pybi_metadata(root: &Path) -> Result<PybiCoreMetadata> {
+    fs::read(root.join("pybi-info").join("METADATA"))?
+        .as_slice()
+        .try_into()
+}
+
+fn env_trampoline_maker() -> TrampolineMaker {
+    TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Both)
+}
+
+/// The Pybi-Paths that say where the parts of a wheel go
+const WHEEL_PATHS: &[&str] = &["purelib", "platlib", "scripts", "data", "include"];
+
+/// Where each part of a wheel goes, inside its directory in the forest: the same
+/// places its Python would put them under its own prefix, going by the pybi's
+/// Pybi-Paths. purelib and platlib can be two different directories.
+struct WheelLayout {
+    /// Short and unique to this layout, so a wheel unpacked for one Python doesn't get
+    /// used for another that lays things out differently
+    id: String,
+    pybi_paths: HashMap<String, NicePathBuf>,
+}
+
+impl WheelLayout {
+    fn new(pybi_metadata: &PybiCoreMetadata) -> Result<WheelLayout> {
+        let mut pybi_paths = HashMap::new();
+        for key in WHEEL_PATHS {
+            // only wheels with headers need an include directory
+            if *key == "include" && !pybi_metadata.paths.contains_key(*key) {
+                continue;
+            }
+            pybi_paths.insert(key.to_string(), pybi_metadata.path(key)?.clone());
+        }
+        let mut described: Vec<String> = pybi_paths
+            .iter()
+            .map(|(key, path)| format!("{key}={path}"))
+            .collect();
+        described.sort_unstable();
+        let described = described.join("\n");
+        let digest = ring::digest::digest(&ring::digest::SHA256, described.as_bytes());
+        let id = data_encoding::BASE64URL_NOPAD.encode(&digest.as_ref()[..6]);
+        Ok(WheelLayout { id, pybi_paths })
+    }
+
+    /// The paths to unpack `name`'s wheel with. Headers go in a directory of their own
+    /// under the Python's include directory, like with a regular install.
+    fn paths(&self, name: &PackageName) -> Result<HashMap<String, NicePathBuf>> {
+        let mut paths = self.pybi_paths.clone();
+        if let Some(include) = paths.remove("include") {
+            let name: NicePathBuf = name.as_given().try_into()?;
+            paths.insert("headers".into(), include.join(&name));
+        }
+        Ok(paths)
+    }
+
+    fn bin_dir(&self, root: &Path) -> PathBuf {
+        root.join(self.pybi_paths["scripts"].to_native())
+    }
+
+    /// purelib, and then platlib if it's somewhere else
+    fn lib_dirs(&self, root: &Path) -> Vec<PathBuf> {
+        let mut dirs = vec![root.join(self.pybi_paths["purelib"].to_native())];
+        if self.pybi_paths["platlib"] != self.pybi_paths["purelib"] {
+            dirs.push(root.join(self.pybi_paths["platlib"].to_native()));
+        }
+        dirs
+    }
+}
+
+/// Where an installed wheel lives in the forest. Environments share installs, but a
+/// REQUESTED file says something about the environment, not the package, so a package
+/// that was asked for directly gets an install of its own.
+struct WheelKey<'a> {
+    hash: &'a ArtifactHash,
+    requested: bool,
+    layout: &'a WheelLayout,
+}
+
+impl PathKey for WheelKey<'_> {
+    fn key(&self) -> PathBuf {
+        let layout = Path::new("layout").join(&self.layout.id);
+        if self.requested {
+            layout.join("requested").join(self.hash.key())
+        } else {
+            layout.join(self.hash.key())
+        }
+    }
+}
+
+/// Makes sure no two packages wrote different files to the same place outside of
+/// their libraries and scripts -- data files and headers, say. Here, each package has a
+/// directory of its own, but anything that looks for those files in one place, the way
+/// they'd be in a regular install, would only see one of them.
+fn check_data_conflicts(
+    layout: &WheelLayout,
+    installs: &[(&PackageName, &Path)],
+) -> Result<()> {
+    fn walk(dir: &Path, skip: &[PathBuf], out: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if skip.contains(&path) {
+                continue;
+            }
+            if path.is_dir() {
+                walk(&path, skip, out)?;
+            } else {
+                out.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut seen: HashMap<PathBuf, (&PackageName, PathBuf)> = HashMap::new();
+    let mut conflicts = Vec::new();
+    for (name, root) in installs {
+        let mut skip = layout.lib_dirs(root);
+        skip.push(layout.bin_dir(root));
+        let mut files = Vec::new();
+        walk(root, &skip, &mut files)?;
+        for file in files {
+            let relative = file.strip_prefix(root)?.to_path_buf();
+            match seen.get(&relative) {
+                Some((other, other_file)) => {
+                    if fs::read(&file)? != fs::read(other_file)? {
+                        conflicts.push(format!(
+                            "{} (from {} and {})",
+                            relative.display(),
+                            other.as_given(),
+                            name.as_given()
+                        ));
+                    }
+                }
+                None => {
+                    seen.insert(relative, (name, file));
+                }
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        bail!(
+            "packages install different files to the same place:\n  {}",
+            conflicts.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// The best binary for `pin` on any of `platforms`, narrowed down to the hashes we
+/// pinned (see `PinnedPackage::pinned_artifact`).
+fn pick_pinned_binary<'b, T: BinaryArtifact>(
+    db: &PackageDB,
     platforms: &[&'b T::Platform],
     pin: &PinnedPackage,
-) -> Result<(&'a ArtifactInfo, &'b T::Platform)>
+    preference: &WheelPreference,
+) -> Result<(ArtifactInfo, &'b T::Platform)>
 where
     T::Name: BinaryName,
 {
+    let no_binaries = || InstallError::NoCompatibleBinaries {
+        name: pin.name.as_given().to_owned(),
+        version: pin.version.to_owned(),
+    };
+    if db.no_binary(&pin.name) {
+        // so the caller falls back to building the sdist
+        return Err(no_binaries().into());
+    }
     for platform in platforms {
         let mut scored_candidates = db
             .artifacts_for_version(&pin.name, &pin.version)?
@@ -52,33 +231,105 @@ where
                     if let Some(score) =
                         platform.max_compatibility(name.all_tags().iter())
                     {
-                        return Some((ai, score));
+                        return Some((ai, name, score));
                     }
                 }
                 None
             })
             .collect::<Vec<_>>();
-        scored_candidates.sort_unstable_by_key(|(_, score)| *score);
-        for (ai, _) in scored_candidates {
-            if ai.hash.is_none() {
+        // best first
+        scored_candidates.sort_unstable_by_key(|(ai, name, score)| {
+            std::cmp::Reverse(preference.key(*score, *name, ai.size))
+        });
+        for (ai, _, _) in scored_candidates {
+            if ai.hash().is_none() {
                 warn!("best scoring artifact {} has no hash", ai.name);
-            } else if !pin.hashes.contains(ai.hash.as_ref().unwrap()) {
+            } else if !pin.covers(ai, db.require_hashes()) {
                 warn!("best scoring artifact {} does not appear in lock file (maybe need to update pins?)", ai.name);
             } else {
-                return Ok((ai, platform));
+                return Ok((pin.pinned_artifact(ai), platform));
             }
         }
     }
-    Err(PosyError::NoCompatibleBinaries {
-        name: pin.name.as_given().to_owned(),
-        version: pin.version.to_owned(),
-    })?
+    Err(no_binaries())?
+}
+
+/// Every artifact that installing `blueprint` on any of `pybi_platforms` would use:
+/// for each platform, the pybi and wheels `EnvForest::get_env` would pick there, or
+/// the sdist for a package without a wheel that fits. Packages built from source
+/// trees don't have an artifact, so they're left out. For `posy bundle`.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn pinned_artifacts(
+    db: &PackageDB,
+    blueprint: &Blueprint,
+    pybi_platforms: &[&PybiPlatform],
+) -> Result<Vec<ArtifactInfo>> {
+    db.with_direct_references(|| _pinned_artifacts(db, blueprint, pybi_platforms))
+}
+
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+fn _pinned_artifacts(
+    db: &PackageDB,
+    blueprint: &Blueprint,
+    pybi_platforms: &[&PybiPlatform],
+) -> Result<Vec<ArtifactInfo>> {
+    for (pin, _) in &blueprint.wheels {
+        if let Some(url) = &pin.url {
+            db.add_direct_reference(&pin.name, url)?;
+        }
+    }
+    blueprint.use_index_sources(db)?;
+    let mut picked = Vec::new();
+    for platform in pybi_platforms {
+        let (pybi_ai, _) = pick_pinned_binary::<Pybi>(
+            db,
+            &[*platform],
+            &blueprint.pybi,
+            &WheelPreference::default(),
+        )?;
+        let (_, pybi_metadata) = db.get_metadata::<Pybi, _>(&[&pybi_ai], None)?;
+        picked.push(pybi_ai);
+        let wheel_platform = platform.wheel_platform(&pybi_metadata)?;
+        for (pin, _) in &blueprint.wheels {
+            if pin.is_built_locally() {
+                continue;
+            }
+            let pick = pick_pinned_binary::<Wheel>(
+                db,
+                &[&wheel_platform],
+                pin,
+                db.wheel_preference(),
+            );
+            match pick {
+                Ok((ai, _)) => picked.push(ai),
+                Err(err) => {
+                    match err.downcast_ref::<InstallError>() {
+                        Some(InstallError::NoCompatibleBinaries { .. }) => (),
+                        _ => return Err(err),
+                    };
+                    let sdist = db
+                        .artifacts_for_version(&pin.name, &pin.version)?
+                        .iter()
+                        .find(|ai| ai.is::<Sdist>() && pin.covers(ai, true));
+                    match sdist {
+                        Some(sdist) => picked.push(pin.pinned_artifact(sdist)),
+                        None => return Err(err),
+                    }
+                }
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    picked.retain(|ai| seen.insert(ai.url.clone()));
+    Ok(picked)
 }
 
 impl EnvForest {
     pub fn new(base: &Path) -> Result<EnvForest> {
         Ok(EnvForest {
             store: KVDirStore::new(base)?,
+            rebuild: false,
+            rebuilt: Default::default(),
         })
     }
 
@@ -106,7 +357,12 @@ impl EnvForest {
         Ok(())
     }
 
-    // do we already have the
+    /// Throw away every environment we're asked for (once each), along with all the
+    /// packages in it, and build it again from scratch. For when something in the
+    /// forest seems to have gotten corrupted.
+    pub fn set_rebuild(&mut self, rebuild: bool) {
+        self.rebuild = rebuild;
+    }
 
     pub fn get_env(
         &self,
@@ -114,22 +370,167 @@ impl EnvForest {
         blueprint: &Blueprint,
         pybi_platforms: &[&PybiPlatform],
         build_stack: &[&PackageName],
+    ) -> Result<Env, Error> {
+        Ok(self._get_env(db, blueprint, pybi_platforms, build_stack)?)
+    }
+
+    /// Throws away the environment for `blueprint`, and every package in it, so the
+    /// next `get_env` builds it all from scratch. Other environments that shared those
+    /// packages will have to unpack them again too.
+    pub fn invalidate_env(
+        &self,
+        db: &PackageDB,
+        blueprint: &Blueprint,
+        pybi_platforms: &[&PybiPlatform],
+    ) -> Result<()> {
+        match env_key(db, blueprint, pybi_platforms)? {
+            Some(key) => self.invalidate(&key),
+            None => Ok(()),
+        }
+    }
+
+    fn invalidate(&self, key: &[u8]) -> Result<()> {
+        if let Ok(manifest) = self.load_manifest(key) {
+            for package in &manifest.packages {
+                debug!("removing {}", package.root.display());
+                self.store.remove_entry(&package.root)?;
+            }
+        }
+        self.store.remove(&key)
+    }
+
+    fn load_manifest(&self, key: &[u8]) -> Result<EnvManifest> {
+        let lock = self.store.lock(&key)?;
+        Ok(serde_json::from_slice(&fs::read(lock.join(ENV_MANIFEST))?)?)
+    }
+
+    /// The environment we made for `key` before, if it's all still there.
+    fn reuse_env(&self, key: &[u8]) -> Result<Env> {
+        let manifest = self.load_manifest(key)?;
+        for path in manifest.packages.iter().map(|p| &p.root) {
+            if !path.exists() {
+                bail!("{} is gone", path.display());
+            }
+        }
+        let pybi_metadata = read_pybi_metadata(&manifest.packages[0].root)?;
+        let wheel_platform = PybiPlatform::new(&manifest.platform_core_tag)
+            .wheel_platform(&pybi_metadata)?;
+        Ok(Env {
+            platform_core_tag: manifest.platform_core_tag,
+            wheel_platform,
+            python: manifest.python,
+            pythonw: manifest.pythonw,
+            bin_dirs: manifest.bin_dirs,
+            lib_dirs: manifest.lib_dirs,
+            packages: manifest.packages,
+        })
+    }
+
+    fn _get_env(
+        &self,
+        db: &PackageDB,
+        blueprint: &Blueprint,
+        pybi_platforms: &[&PybiPlatform],
+        build_stack: &[&PackageName],
+    ) -> Result<Env> {
+        // before reusing anything: what we built last time might have been built
+        // under laxer rules
+        check_blueprint(db, blueprint)?;
+        let Some(key) = env_key(db, blueprint, pybi_platforms)? else {
+            return self.build_env(db, blueprint, pybi_platforms, build_stack);
+        };
+        if self.rebuild && self.rebuilt.lock().unwrap().insert(key.clone()) {
+            self.invalidate(&key)?;
+        } else if self.store.contains(&key.as_slice()) {
+            match self.reuse_env(&key) {
+                Ok(env) => return Ok(env),
+                Err(err) => {
+                    debug!("can't reuse environment: {err:#}");
+                    self.store.remove(&key.as_slice())?;
+                }
+            }
+        }
+        let env = self.build_env(db, blueprint, pybi_platforms, build_stack)?;
+        let manifest = serde_json::to_vec_pretty(&EnvManifest {
+            platform_core_tag: env.platform_core_tag.clone(),
+            python: env.python.clone(),
+            pythonw: env.pythonw.clone(),
+            bin_dirs: env.bin_dirs.clone(),
+            lib_dirs: env.lib_dirs.clone(),
+            packages: env.packages.clone(),
+        })?;
+        self.store.get_or_set(&key.as_slice(), |path| {
+            Ok(fs::write(path.join(ENV_MANIFEST), manifest)?)
+        })?;
+        Ok(env)
+    }
+
+    /// Any direct references in `blueprint` are just for this environment, even if
+    /// we're partway through resolving another one.
+    fn build_env(
+        &self,
+        db: &PackageDB,
+        blueprint: &Blueprint,
+        pybi_platforms: &[&PybiPlatform],
+        build_stack: &[&PackageName],
+    ) -> Result<Env> {
+        db.with_direct_references(|| {
+            self._build_env(db, blueprint, pybi_platforms, build_stack)
+        })
+    }
+
+    fn _build_env(
+        &self,
+        db: &PackageDB,
+        blueprint: &Blueprint,
+        pybi_platforms: &[&PybiPlatform],
+        build_stack: &[&PackageName],
     ) -> Result<Env> {
-        let (pybi_ai, pybi_platform) =
-            pick_pinned_binary::<Pybi>(db, pybi_platforms, &blueprint.pybi)?;
+        for (pin, _) in &blueprint.wheels {
+            if let Some(url) = &pin.url {
+                db.add_direct_reference(&pin.name, url)?;
+            }
+        }
+        blueprint.use_index_sources(db)?;
+        // (the preferences are only for wheels)
+        let (pybi_ai, pybi_platform) = pick_pinned_binary::<Pybi>(
+            db,
+            pybi_platforms,
+            &blueprint.pybi,
+            &WheelPreference::default(),
+        )?;
+        let pybi_ai = &pybi_ai;
         let pybi_hash = pybi_ai.require_hash()?;
+        progress!(
+            "install-pybi",
+            package = blueprint.pybi.name.as_given(),
+            version = %blueprint.pybi.version
+        );
         let pybi_root = self.store.get_or_set(&pybi_hash, |path| {
             let pybi = db.get_artifact::<Pybi>(pybi_ai)?;
+            let mut errors = Vec::new();
+            for problem in check_pybi(&pybi, db.unpack_limits())? {
+                match problem.severity {
+                    Severity::Error => errors.push(problem.message),
+                    Severity::Warning => {
+                        debug!("{}: {}", pybi_ai.name, problem.message)
+                    }
+                }
+            }
+            if !errors.is_empty() {
+                Err(InstallError::InvalidPybi {
+                    name: pybi_ai.name.to_string(),
+                    problems: errors,
+                })?;
+            }
             context!("Unpacking {}", pybi_ai.name);
-            pybi.unpack(&mut WriteTreeFS::new(path))?;
+            timing!("unpack");
+            pybi.unpack(&mut WriteTreeFS::new(path), db.unpack_limits())?;
             let (_, pybi_metadata) = pybi.metadata()?;
             EnvForest::munge_unpacked_pybi(path, &pybi_metadata)?;
             Ok(())
         })?;
-        let pybi_metadata: PybiCoreMetadata =
-            fs::read(pybi_root.join("pybi-info").join("METADATA"))?
-                .as_slice()
-                .try_into()?;
+        let pybi_metadata = read_pybi_metadata(&pybi_root)?;
         let wheel_platform = pybi_platform.wheel_platform(&pybi_metadata)?;
         let pybi_platform_slice = [pybi_platform];
         let wheel_builder = WheelBuilder::new(
@@ -139,130 +540,206 @@ impl EnvForest {
             &pybi_platform_slice,
             build_stack,
         )?;
-        let trampoline_maker =
-            TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Both);
+        for (pin, _) in &blueprint.wheels {
+            if let (Some(source), true) = (&pin.source, pin.is_built_locally()) {
+                source.apply(db, &pin.name, Some((&wheel_builder, &wheel_platform)))?;
+            }
+        }
+        let trampoline_maker = env_trampoline_maker();
+        let layout = WheelLayout::new(&pybi_metadata)?;
 
-        let paths: HashMap<String, NicePathBuf> = HashMap::from([
-            ("scripts".into(), "bin".try_into().unwrap()),
-            ("purelib".into(), "lib".try_into().unwrap()),
-            ("platlib".into(), "lib".try_into().unwrap()),
-            ("data".into(), ".".try_into().unwrap()),
-        ]);
+        // Start all the downloads we're going to need at once, instead of waiting for
+        // each in turn as we get to it.
+        let picks: Vec<_> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| {
+                pick_pinned_binary::<Wheel>(
+                    db,
+                    &[&wheel_platform],
+                    pin,
+                    db.wheel_preference(),
+                )
+            })
+            .collect();
+        let to_fetch: Vec<&ArtifactInfo> = blueprint
+            .wheels
+            .iter()
+            .zip(&picks)
+            .filter_map(|((pin, _), pick)| Some((pin, &pick.as_ref().ok()?.0)))
+            .filter(|(pin, ai)| {
+                ai.hash().map_or(false, |hash| {
+                    let requested = blueprint.requested.contains(&pin.name);
+                    let layout = &layout;
+                    !self.store.contains(&WheelKey {
+                        hash,
+                        requested,
+                        layout,
+                    })
+                })
+            })
+            .map(|(_, ai)| ai)
+            .collect();
+        crate::util::block_on(db.prefetch_artifacts(&to_fetch));
 
         let mut wheel_roots = Vec::new();
+        let mut packages = vec![InstalledPackage {
+            name: blueprint.pybi.name.clone(),
+            version: blueprint.pybi.version.clone(),
+            source: pybi_ai.name.to_string(),
+            source_hash: pybi_hash.clone(),
+            root: pybi_root.clone(),
+        }];
 
-        for (pin, expected_metadata) in &blueprint.wheels {
+        for ((pin, expected_metadata), pick) in blueprint.wheels.iter().zip(picks) {
             context!("installing {} {}", pin.name.as_given(), pin.version);
-            let (ai, wheel_root) =
-                match pick_pinned_binary::<Wheel>(db, &[&wheel_platform], pin) {
-                    Ok((wheel_ai, _)) => {
-                        // we're using a binary wheel
-                        context!("using binary wheel from {}", wheel_ai.url);
-                        let wheel_hash = wheel_ai.require_hash()?;
-                        let wheel_root =
-                            self.store.get_or_set(&wheel_hash, |path| {
-                                let wheel = {
-                                    context!("Fetching {}", wheel_ai.url);
-                                    db.get_artifact::<Wheel>(wheel_ai)?
-                                };
-                                wheel.unpack(
-                                    &paths,
-                                    &trampoline_maker,
-                                    WriteTreeFS::new(path),
-                                )?;
-                                Ok(())
-                            })?;
-                        (wheel_ai, wheel_root)
-                    }
-                    Err(err) => {
-                        match err.downcast_ref::<PosyError>() {
-                            Some(PosyError::NoCompatibleBinaries { .. }) => (),
-                            _ => return Err(err),
+            let requested = blueprint.requested.contains(&pin.name);
+            progress!(
+                "install",
+                package = pin.name.as_given(),
+                version = %pin.version
+            );
+            let (ai, wheel_root) = match pick {
+                Ok((wheel_ai, _)) => {
+                    // we're using a binary wheel
+                    context!("using binary wheel from {}", wheel_ai.url);
+                    let key = WheelKey {
+                        hash: wheel_ai.require_hash()?,
+                        requested,
+                        layout: &layout,
+                    };
+                    let wheel_root = self.store.get_or_set(&key, |path| {
+                        let wheel = {
+                            context!("Fetching {}", wheel_ai.url);
+                            db.get_artifact::<Wheel>(&wheel_ai)?
                         };
-                        // couldn't find a compatible wheel; see if we have an sdist
-                        if let Some(sdist_ai) = db
-                            .artifacts_for_version(&pin.name, &pin.version)?
-                            .iter()
-                            .find(|ai| ai.is::<Sdist>())
-                        {
-                            context!("using sdist from {}", sdist_ai.url);
-                            let sdist_hash = sdist_ai.require_hash()?;
-                            let handle = self.store.lock(&sdist_hash)?;
-                            fs::create_dir_all(&handle)?;
-                            // first check if we already have any unpacked wheels
-                            // that we can use
-                            let mut candidates = Vec::new();
-                            for entry in fs::read_dir(&handle)? {
-                                let entry = entry?;
-                                let name = match entry.file_name().into_string() {
-                                    Ok(name) => name,
-                                    Err(_) => continue,
-                                };
-                                if !name.ends_with(".whl") {
-                                    continue;
-                                }
-                                let wheel_name: WheelName = name.as_str().try_into()?;
-                                if let Some(score) = wheel_platform
-                                    .max_compatibility(wheel_name.all_tags())
-                                {
-                                    candidates.push((score, name));
-                                }
+                        timing!("unpack");
+                        wheel.unpack(
+                            &layout.paths(&pin.name)?,
+                            &trampoline_maker,
+                            db.unpack_limits(),
+                            requested,
+                            WriteTreeFS::new(path),
+                        )?;
+                        Ok(())
+                    })?;
+                    (wheel_ai, wheel_root)
+                }
+                Err(err) => {
+                    match err.downcast_ref::<InstallError>() {
+                        Some(InstallError::NoCompatibleBinaries { .. }) => (),
+                        _ => return Err(err),
+                    };
+                    // couldn't find a compatible wheel; see if we have an sdist
+                    if let Some(sdist_ai) = db
+                        .artifacts_for_version(&pin.name, &pin.version)?
+                        .iter()
+                        .find(|ai| {
+                            ai.is::<Sdist>()
+                                && (pin.hashes.is_empty()
+                                    || pin.covers(ai, db.require_hashes()))
+                        })
+                    {
+                        let sdist_ai = pin.pinned_artifact(sdist_ai);
+                        context!("using sdist from {}", sdist_ai.url);
+                        let key = WheelKey {
+                            hash: sdist_ai.require_hash()?,
+                            requested,
+                            layout: &layout,
+                        };
+                        let handle = self.store.lock(&key)?;
+                        fs::create_dir_all(&handle)?;
+                        // first check if we already have any unpacked wheels
+                        // that we can use
+                        let mut candidates = Vec::new();
+                        for entry in fs::read_dir(&handle)? {
+                            let entry = entry?;
+                            let name = match entry.file_name().into_string() {
+                                Ok(name) => name,
+                                Err(_) => continue,
+                            };
+                            if !name.ends_with(".whl") {
+                                continue;
                             }
-                            if let Some((_, name)) =
-                                candidates.iter().max_by_key(|(score, _)| score)
+                            let wheel_name: WheelName = name.as_str().try_into()?;
+                            if let Some(score) =
+                                wheel_platform.max_compatibility(wheel_name.all_tags())
                             {
-                                (sdist_ai, handle.join(name))
-                            } else {
-                                // couldn't find one already installed... try to
-                                // build one and install it
-                                // unwrap is ok b/c we know we're passing an sdist
-                                // ai here
-                                let local_wheel = db
-                                    .get_locally_built_binary::<Wheel>(
-                                        sdist_ai,
-                                        &wheel_builder,
-                                        &wheel_platform,
-                                    )
-                                    .unwrap()?;
-                                let tmp = handle.tempdir()?;
-                                local_wheel.unpack(
-                                    &paths,
-                                    &trampoline_maker,
-                                    WriteTreeFS::new(&tmp),
-                                )?;
-                                let wheel_root =
-                                    handle.join(local_wheel.name().to_string());
-                                fs::rename(tmp.into_path(), &wheel_root)?;
-                                (sdist_ai, wheel_root)
+                                candidates.push((score, name));
                             }
+                        }
+                        if let Some((_, name)) =
+                            candidates.iter().max_by_key(|(score, _)| score)
+                        {
+                            (sdist_ai, handle.join(name))
                         } else {
-                            bail!("no compatible wheel or sdist found");
+                            // couldn't find one already installed... try to
+                            // build one and install it
+                            // unwrap is ok b/c we know we're passing an sdist
+                            // ai here
+                            let local_wheel = db
+                                .get_locally_built_binary::<Wheel>(
+                                    &sdist_ai,
+                                    &wheel_builder,
+                                    &wheel_platform,
+                                )
+                                .unwrap()?;
+                            let tmp = handle.tempdir()?;
+                            timing!("unpack");
+                            local_wheel.unpack(
+                                &layout.paths(&pin.name)?,
+                                &trampoline_maker,
+                                db.unpack_limits(),
+                                requested,
+                                WriteTreeFS::new(&tmp),
+                            )?;
+                            let wheel_root =
+                                handle.join(local_wheel.name().to_string());
+                            fs::rename(tmp.into_path(), &wheel_root)?;
+                            (sdist_ai, wheel_root)
                         }
+                    } else if db.no_binary(&pin.name) {
+                        bail!(
+                            "{} is set to no-binary, but {} doesn't have an sdist to \
+                             build",
+                            pin.name.as_given(),
+                            pin.version
+                        );
+                    } else {
+                        bail!("no compatible wheel or sdist found");
                     }
-                };
+                }
+            };
 
             // OK, we have an installed wheel. Find its metadata so we can confirm it's
             // consistent with what the blueprint was expecting.
-            let mut top_levels = Vec::new();
-            let lib = wheel_root.join("lib");
-            for entry in fs::read_dir(&lib)? {
-                let entry = entry?;
-                if let Ok(name) = entry.file_name().into_string() {
-                    top_levels.push(name);
+            let mut dist_info = None;
+            for lib in layout.lib_dirs(&wheel_root) {
+                let mut top_levels = Vec::new();
+                if lib.exists() {
+                    for entry in fs::read_dir(&lib)? {
+                        let entry = entry?;
+                        if let Ok(name) = entry.file_name().into_string() {
+                            top_levels.push(name);
+                        }
+                    }
+                }
+                if let Some(found) = Wheel::find_special_wheel_dir(
+                    top_levels,
+                    &pin.name,
+                    &pin.version,
+                    ".dist-info",
+                )? {
+                    dist_info = Some(lib.join(found));
+                    break;
                 }
             }
-            let dist_info = Wheel::find_special_wheel_dir(
-                top_levels,
-                &pin.name,
-                &pin.version,
-                ".dist-info",
-            )?
-            .ok_or(eyre!(".dist-info/ missing"))?;
+            let dist_info = dist_info.ok_or(eyre!(".dist-info/ missing"))?;
             let found_metadata: WheelCoreMetadata =
-                fs::read(lib.join(&dist_info).join("METADATA"))?
+                fs::read(dist_info.join("METADATA"))?
                     .as_slice()
                     .try_into()?;
-            let found_metadata = WheelResolveMetadata::from(ai, &found_metadata);
+            let found_metadata = WheelResolveMetadata::from(&ai, &found_metadata);
 
             if found_metadata.inner != expected_metadata.inner {
                 bail!(
@@ -287,8 +764,22 @@ impl EnvForest {
                 );
             }
 
+            packages.push(InstalledPackage {
+                name: pin.name.clone(),
+                version: pin.version.clone(),
+                source: ai.name.to_string(),
+                source_hash: ai.require_hash()?.clone(),
+                root: wheel_root.clone(),
+            });
             wheel_roots.push(wheel_root);
         }
+        let installs: Vec<(&PackageName, &Path)> = blueprint
+            .wheels
+            .iter()
+            .zip(&wheel_roots)
+            .map(|((pin, _), root)| (&pin.name, root.as_path()))
+            .collect();
+        check_data_conflicts(&layout, &installs)?;
 
         let pybi_bin = pybi_root.join(pybi_metadata.path("scripts")?.to_native());
         let (python_basename, pythonw_basename) = if cfg!(unix) {
@@ -301,9 +792,12 @@ impl EnvForest {
 
         let mut bin_dirs = Vec::<PathBuf>::new();
         bin_dirs.push(pybi_bin);
-        bin_dirs.extend(wheel_roots.iter().map(|root| root.join("bin")));
+        bin_dirs.extend(wheel_roots.iter().map(|root| layout.bin_dir(root)));
 
-        let lib_dirs = wheel_roots.iter().map(|root| root.join("lib")).collect();
+        let lib_dirs = wheel_roots
+            .iter()
+            .flat_map(|root| layout.lib_dirs(root))
+            .collect();
 
         Ok(Env {
             platform_core_tag: pybi_platform.core_tag().into(),
@@ -312,10 +806,189 @@ impl EnvForest {
             pythonw,
             bin_dirs,
             lib_dirs,
+            packages,
         })
     }
 }
 
+impl EnvForest {
+    /// All the Pythons we've unpacked so far, oldest version first.
+    pub fn pybis(&self) -> Result<Vec<InstalledPybi>> {
+        let mut pybis = Vec::new();
+        for root in self.store.find_entries(Path::new("pybi-info"))? {
+            let metadata = read_pybi_metadata(&root)?;
+            pybis.push(InstalledPybi { root, metadata });
+        }
+        pybis.sort_by(|a, b| {
+            (&a.metadata.name, &a.metadata.version)
+                .cmp(&(&b.metadata.name, &b.metadata.version))
+        });
+        Ok(pybis)
+    }
+
+    /// Deletes an unpacked Python. Environments don't keep their own copy, so if
+    /// something needs it later, it just gets unpacked again.
+    pub fn remove_pybi(&self, pybi: &InstalledPybi) -> Result<()> {
+        self.store.remove_entry(&pybi.root)
+    }
+}
+
+impl EnvForest {
+    /// Builds `tree` into a wheel and adds it to `env`, which should be the
+    /// environment for `blueprint`. The wheel only gets rebuilt when the tree changes.
+    pub fn add_local_tree(
+        &self,
+        db: &PackageDB,
+        env: &mut Env,
+        blueprint: &Blueprint,
+        tree: &LocalTree,
+    ) -> Result<()> {
+        context!("installing {}", tree.root.display());
+        let wheel_builder = WheelBuilder::new(
+            db,
+            &blueprint.pybi.name,
+            &blueprint.pybi.version,
+            PybiPlatform::native_platforms()?,
+            &[],
+        )?;
+        let wheel = wheel_builder.local_tree_wheel(tree, &env.wheel_platform)?;
+        let layout = WheelLayout::new(&read_pybi_metadata(&env.packages[0].root)?)?;
+        let key = format!("local-tree {} {} {}", tree.hash, wheel.name(), layout.id);
+        let wheel_root = self.store.get_or_set(&key.as_bytes(), |path| {
+            // the project is always something we asked for
+            wheel.unpack(
+                &layout.paths(&tree.name)?,
+                &env_trampoline_maker(),
+                db.unpack_limits(),
+                true,
+                WriteTreeFS::new(path),
+            )?;
+            Ok(())
+        })?;
+        // The project goes ahead of its dependencies (but after python itself), so it
+        // can't get shadowed by an old copy of itself pulled in by some dependency.
+        env.bin_dirs.insert(1, layout.bin_dir(&wheel_root));
+        for (i, lib) in layout.lib_dirs(&wheel_root).into_iter().enumerate() {
+            env.lib_dirs.insert(i, lib);
+        }
+        env.packages.insert(
+            1,
+            InstalledPackage {
+                name: tree.name.clone(),
+                version: wheel.name().version.clone(),
+                source: tree.root.display().to_string(),
+                source_hash: tree.hash.clone(),
+                root: wheel_root,
+            },
+        );
+        Ok(())
+    }
+}
+
+const ENV_MANIFEST: &str = "env.json";
+
+/// The rules about `blueprint` that don't depend on which artifacts we pick, so that
+/// `_get_env` can check them even for an environment it already has.
+fn check_blueprint(db: &PackageDB, blueprint: &Blueprint) -> Result<()> {
+    if db.require_hashes() {
+        let unhashed = blueprint.unhashed();
+        if !unhashed.is_empty() {
+            bail!(
+                "hashes are required, but there are none pinned for {}",
+                unhashed
+                    .iter()
+                    .map(|pin| format!("{} {}", pin.name.as_given(), pin.version))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    let pins = std::iter::once(&blueprint.pybi)
+        .chain(blueprint.wheels.iter().map(|(pin, _)| pin));
+    for pin in pins {
+        if let Some(why) = db.policy().excludes_release(&pin.name, &pin.version)? {
+            bail!(
+                "can't install {} {}: {why}",
+                pin.name.as_given(),
+                pin.version
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The key an environment is remembered under in the forest, or None if it can't be
+/// reused: a package built from a local directory can change without its pin
+/// changing. Besides the blueprint, it covers whatever picks between the artifacts
+/// of a pin, so changing the wheel preference or no-binary gets a fresh environment,
+/// and whatever checks those artifacts as we fetch them: an environment built without
+/// `--require-hashes` or with laxer attestation rules doesn't count for one with.
+fn env_key(
+    db: &PackageDB,
+    blueprint: &Blueprint,
+    pybi_platforms: &[&PybiPlatform],
+) -> Result<Option<Vec<u8>>> {
+    let from_directory = blueprint.wheels.iter().any(|(pin, _)| {
+        pin.is_built_locally()
+            && matches!(pin.source, Some(RequirementSource::Path { .. }))
+    });
+    if from_directory {
+        return Ok(None);
+    }
+    let platforms: Vec<&str> = pybi_platforms.iter().map(|p| p.core_tag()).collect();
+    let mut key = format!("env {} {}", blueprint.content_hash()?, platforms.join(" "));
+    // an environment built from wheels doesn't count once they're not allowed
+    for (pin, _) in &blueprint.wheels {
+        if db.no_binary(&pin.name) {
+            key.push_str(&format!(" no-binary:{}", pin.name.normalized()));
+        }
+    }
+    // (left out when they're the defaults, so those keys stay the same as they always
+    // were)
+    if db.require_hashes() {
+        key.push_str(" require-hashes");
+    }
+    let attestations = db.attestations();
+    if attestations.trusted_root.is_some() {
+        key.push_str(" trusted-root");
+    }
+    for pin in std::iter::once(&blueprint.pybi)
+        .chain(blueprint.wheels.iter().map(|(pin, _)| pin))
+    {
+        let rule = attestations.rule_for(&pin.name);
+        if rule != AttestationRule::default() {
+            key.push_str(&format!(
+                " attestations:{}={}",
+                pin.name.normalized(),
+                serde_json::to_string(&rule)?
+            ));
+        }
+    }
+    let preference = db.wheel_preference();
+    if *preference != WheelPreference::default() {
+        key.push_str(&format!(
+            " wheel-preference:{}",
+            serde_json::to_string(preference)?
+        ));
+    }
+    Ok(Some(key.into_bytes()))
+}
+
+/// What we remember about an environment, to hand it back out without rebuilding it.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct EnvManifest {
+    platform_core_tag: String,
+    python: PathBuf,
+    pythonw: PathBuf,
+    bin_dirs: Vec<PathBuf>,
+    lib_dirs: Vec<PathBuf>,
+    /// Python first, like Env::packages
+    packages: Vec<InstalledPackage>,
+}
+
+/// An environment put together from an EnvForest: where its Python is, and the paths
+/// it needs to see its packages. See `env_vars` for running things in it.
 pub struct Env {
     // XX TODO for GC support: hold a lock to prevent anything from being GC'ed out from
     // under us
@@ -325,6 +998,20 @@ pub struct Env {
     pub pythonw: PathBuf,
     pub bin_dirs: Vec<PathBuf>,
     pub lib_dirs: Vec<PathBuf>,
+    /// Everything that went into the environment, python first
+    pub packages: Vec<InstalledPackage>,
+}
+
+/// One package's directory in the EnvForest, and where it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstalledPackage {
+    pub name: PackageName,
+    pub version: Version,
+    /// The artifact's filename, or for local trees, the tree's path
+    pub source: String,
+    pub source_hash: ArtifactHash,
+    pub root: PathBuf,
 }
 
 impl Env {
@@ -379,3 +1066,232 @@ impl Env {
 //         todo!()
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(paths: &str) -> Result<WheelLayout> {
+        let metadata = indoc::formatdoc! {r#"
+            Metadata-Version: 2.1
+            Name: cpython
+            Version: 3.11.2
+            Pybi-Environment-Marker-Variables: {{}}
+            Pybi-Paths: {paths}
+        "#};
+        WheelLayout::new(&metadata.as_bytes().try_into()?)
+    }
+
+    #[test]
+    fn test_wheel_layout() -> Result<()> {
+        let split = layout(concat!(
+            r#"{"purelib": "lib/python3.11/site-packages", "#,
+            r#""platlib": "lib64/python3.11/site-packages", "scripts": "bin", "#,
+            r#""data": ".", "include": "include/python3.11"}"#,
+        ))?;
+        let paths = split.paths(&"NumPy".try_into()?)?;
+        assert_eq!(paths["headers"].to_string(), "include/python3.11/NumPy");
+        assert_eq!(paths["data"].to_string(), ".");
+        let root = Path::new("root");
+        assert_eq!(
+            split.lib_dirs(root),
+            vec![
+                root.join("lib/python3.11/site-packages"),
+                root.join("lib64/python3.11/site-packages"),
+            ]
+        );
+
+        let shared = layout(
+            r#"{"purelib": "lib", "platlib": "lib", "scripts": "bin", "data": "."}"#,
+        )?;
+        assert_eq!(shared.lib_dirs(root), vec![root.join("lib")]);
+        // no include directory, so wheels with headers can't go in
+        assert!(!shared.paths(&"numpy".try_into()?)?.contains_key("headers"));
+        assert_ne!(split.id, shared.id);
+
+        assert!(layout(r#"{"purelib": "lib", "platlib": "lib"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_conflicts() -> Result<()> {
+        let layout = layout(
+            r#"{"purelib": "lib", "platlib": "lib", "scripts": "bin", "data": "."}"#,
+        )?;
+        let tmp = tempfile::tempdir()?;
+        let install = |name: &str, files: &[(&str, &str)]| -> Result<PathBuf> {
+            let root = tmp.path().join(name);
+            for (path, contents) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, contents)?;
+            }
+            Ok(root)
+        };
+        // same module or script names are someone else's problem; same contents are
+        // fine
+        let a = install(
+            "a",
+            &[("lib/x.py", "a"), ("bin/x", "a"), ("share/x.txt", "same")],
+        )?;
+        let b = install(
+            "b",
+            &[("lib/x.py", "b"), ("bin/x", "b"), ("share/x.txt", "same")],
+        )?;
+        let c = install("c", &[("share/x.txt", "different")])?;
+        let (a_name, b_name, c_name): (PackageName, PackageName, PackageName) =
+            ("a".try_into()?, "b".try_into()?, "c".try_into()?);
+
+        check_data_conflicts(&layout, &[(&a_name, &a), (&b_name, &b)])?;
+        let err = check_data_conflicts(&layout, &[(&a_name, &a), (&c_name, &c)])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("x.txt (from a and c)"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_key() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::resolve::Brief;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &[])?;
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        let platform = test_platform();
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Default::default(),
+            sources: Default::default(),
+        };
+        let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+        let key = |db: &PackageDB| env_key(db, &blueprint, &[&platform]);
+
+        let plain = key(&db)?.unwrap();
+        // the same pins can still end up with different wheels
+        db.set_wheel_preference(WheelPreference {
+            prefer: Some(WheelKind::Pure),
+            ..Default::default()
+        });
+        let pure = key(&db)?.unwrap();
+        assert_ne!(pure, plain);
+        db.set_no_binary(vec!["foo".try_into()?]);
+        assert_ne!(key(&db)?.unwrap(), pure);
+        db.set_wheel_preference(Default::default());
+        db.set_no_binary(Vec::new());
+        assert_eq!(key(&db)?.unwrap(), plain);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_binary_picks_sdist() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::resolve::Brief;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &[])?;
+        index.add(
+            "foo-1.0.tar.gz",
+            b"Metadata-Version: 2.1\nName: foo\nVersion: 1.0\n",
+        )?;
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        let platform = test_platform();
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Default::default(),
+            sources: Default::default(),
+        };
+        let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+        let foo = |db: &PackageDB| -> Result<String> {
+            let picked = pinned_artifacts(db, &blueprint, &[&platform])?;
+            Ok(picked[1].name.to_string())
+        };
+
+        assert_eq!(foo(&db)?, "foo-1.0-py3-none-any.whl");
+        // there's a perfectly good wheel, but we build the sdist anyway
+        db.set_no_binary(vec!["foo".try_into()?]);
+        assert_eq!(foo(&db)?, "foo-1.0.tar.gz");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reuse_checks_blueprint() -> Result<()> {
+        use crate::package_db::{MemoryIndex, PackageSource};
+        use crate::resolve::Brief;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &[])?;
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index.clone())?;
+        let platform = test_platform();
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Default::default(),
+            sources: Default::default(),
+        };
+        // a lock from before we pinned hashes
+        let mut blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+        blueprint.pybi.hashes.clear();
+        for (pin, _) in &mut blueprint.wheels {
+            pin.hashes.clear();
+        }
+
+        // the memory index can't be installed from, so put together what building
+        // it would have left behind
+        let forest = EnvForest::new(&test_db.path().join("forest"))?;
+        let pybi_root = test_db.path().join("pybi");
+        fs::create_dir_all(pybi_root.join("pybi-info"))?;
+        let pybi_ai =
+            &db.available_artifacts(&blueprint.pybi.name)?[&blueprint.pybi.version][0];
+        fs::write(
+            pybi_root.join("pybi-info/METADATA"),
+            index.metadata(pybi_ai).unwrap(),
+        )?;
+        let key = env_key(&db, &blueprint, &[&platform])?.unwrap();
+        forest.store.get_or_set(&key.as_slice(), |path| {
+            let manifest = EnvManifest {
+                platform_core_tag: platform.core_tag().into(),
+                python: pybi_root.join("bin/python"),
+                pythonw: pybi_root.join("bin/python"),
+                bin_dirs: vec![pybi_root.join("bin")],
+                lib_dirs: Vec::new(),
+                packages: vec![InstalledPackage {
+                    name: blueprint.pybi.name.clone(),
+                    version: blueprint.pybi.version.clone(),
+                    source: pybi_ai.name.to_string(),
+                    source_hash: pybi_ai.require_hash()?,
+                    root: pybi_root.clone(),
+                }],
+            };
+            Ok(fs::write(
+                path.join(ENV_MANIFEST),
+                serde_json::to_vec(&manifest)?,
+            )?)
+        })?;
+
+        let env = forest.get_env(&db, &blueprint, &[&platform], &[])?;
+        assert_eq!(env.packages[0].root, pybi_root);
+
+        db.set_require_hashes(true);
+        let err = forest
+            .get_env(&db, &blueprint, &[&platform], &[])
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("hashes are required"));
+        Ok(())
+    }
+}
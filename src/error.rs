@@ -1,12 +1,247 @@
+//! What can go wrong, for code that wants to handle some failures specially.
+//!
+//! Inside posy_core everything returns `eyre::Report`, so errors pick up context as
+//! they bubble up. The entry points -- `Brief::resolve`, `EnvForest::get_env`, and
+//! `PackageDB::new` -- wrap that in an `Error`, whose `kind()` says which of the
+//! typed errors below it was about, if any. The message (and `source()` chain) is the
+//! same as the report's, context and all.
+
 use crate::prelude::*;
+use std::error::Error as StdError;
+use std::path::PathBuf;
 use thiserror::Error;
 
+/// An error from one of posy_core's entry points.
+pub struct Error(eyre::Report);
+
+/// The typed errors an `Error` can be about. If several are involved, e.g. a build
+/// failure while resolving, this is the outermost one; use `Error::find` to look for
+/// a particular one anywhere in the chain.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind<'a> {
+    Resolve(&'a ResolveError),
+    Network(&'a NetworkError),
+    Metadata(&'a MetadataError),
+    Install(&'a InstallError),
+    /// Anything else: I/O errors, bad input, bugs...
+    Other,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind<'_> {
+        for cause in self.0.chain() {
+            if let Some(err) = cause.downcast_ref() {
+                return ErrorKind::Resolve(err);
+            }
+            if let Some(err) = cause.downcast_ref() {
+                return ErrorKind::Network(err);
+            }
+            if let Some(err) = cause.downcast_ref() {
+                return ErrorKind::Metadata(err);
+            }
+            if let Some(err) = cause.downcast_ref() {
+                return ErrorKind::Install(err);
+            }
+        }
+        ErrorKind::Other
+    }
+
+    /// The outermost `E` in this error's chain of causes.
+    pub fn find<E: StdError + 'static>(&self) -> Option<&E> {
+        self.0.chain().find_map(|cause| cause.downcast_ref())
+    }
+
+    pub fn into_report(self) -> eyre::Report {
+        self.0
+    }
+}
+
+impl From<eyre::Report> for Error {
+    fn from(report: eyre::Report) -> Self {
+        Error(report)
+    }
+}
+
+impl Display for Error {
+    // Passes the formatter straight through, so `{:#}` still shows the whole chain
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.chain().nth(1)
+    }
+}
+
 #[derive(Error, Debug)]
-pub enum PosyError {
+#[non_exhaustive]
+pub enum ResolveError {
+    #[error(
+        "{explanation}{}",
+        .hints.iter().map(|hint| format!("\nhint: {hint}")).collect::<String>()
+    )]
+    NoSolution {
+        /// pubgrub's account of which requirements conflict
+        explanation: String,
+        /// Guesses at what to do about it, e.g. "maybe you meant foo-bar"
+        hints: Vec<String>,
+    },
+    #[error(
+        "no compatible pybis found for {requirement}{}",
+        .details.iter().map(|line| format!("\n  {line}")).collect::<String>()
+    )]
+    NoPybiFound {
+        requirement: String,
+        /// Every version we saw and why it didn't do, then what each platform accepts
+        details: Vec<String>,
+    },
+    #[error("{package} v{version} depends on itself")]
+    SelfDependency { package: String, version: Version },
+    #[error(
+        "{package} v{version}'s dependency on {dependency} has self-contradictory \
+         version ranges"
+    )]
+    EmptyDependencyRange {
+        package: String,
+        version: Version,
+        dependency: String,
+    },
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum NetworkError {
+    #[error("couldn't fetch {url}")]
+    Transport {
+        url: Url,
+        #[source]
+        source: ureq::Transport,
+    },
+    #[error("error fetching {url}: {status}")]
+    HttpStatus { url: Url, status: u16 },
+    #[error("hit redirection limit at {url}")]
+    TooManyRedirects { url: Url },
+    #[error("remote file does not support range requests")]
+    RangeRequestsNotSupported,
+    #[error("can't fetch {url}: it isn't cached, and we're in offline mode")]
+    Offline { url: Url },
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum MetadataError {
+    #[error("couldn't parse {what}")]
+    Invalid {
+        /// Where the metadata came from, e.g. `foo-1.0.dist-info/METADATA`
+        what: String,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    #[error(
+        "{field} mismatch between {path} and filename ({in_metadata} != \
+         {in_filename})"
+    )]
+    Mismatch {
+        path: String,
+        /// "name" or "version"
+        field: &'static str,
+        in_metadata: String,
+        in_filename: String,
+    },
+    #[error("couldn't find any metadata for {}", .artifacts.join(", "))]
+    NotFound {
+        /// The filenames we tried
+        artifacts: Vec<String>,
+    },
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum InstallError {
     #[error("no compatible binaries found for {name} {version}")]
     NoCompatibleBinaries { name: String, version: Version },
-    #[error("no compatible pybis found for requirement and platform")]
-    NoPybiFound,
-    #[error("remote file does not support range requests")]
-    LazyRemoteFileNotSupported,
+    #[error(
+        "{name} looks broken:{}",
+        .problems.iter().map(|problem| format!("\n    {problem}")).collect::<String>()
+    )]
+    InvalidPybi {
+        name: String,
+        /// Just the errors, not the warnings
+        problems: Vec<String>,
+    },
+    #[error(
+        "refusing to unpack: {what} is {value}, over the limit of {limit} (this could \
+         be an attack, e.g. a zip bomb; see unpack-limits in posy.toml)"
+    )]
+    UnpackLimitExceeded {
+        what: String,
+        value: u64,
+        limit: u64,
+    },
+    #[error(
+        "building {package}{} with {backend} failed ({status})\n\
+         last lines of output:\n{}\
+         full build log: {}{}",
+        .version.as_ref().map(|v| format!(" {v}")).unwrap_or_default(),
+        .tail.iter().map(|line| format!("    {line}\n")).collect::<String>(),
+        .log.display(),
+        .hints.iter().map(|hint| format!("\nhint: {hint}")).collect::<String>(),
+    )]
+    BuildFailed {
+        package: String,
+        /// None for local source trees
+        version: Option<Version>,
+        backend: String,
+        status: String,
+        log: PathBuf,
+        tail: Vec<String>,
+        /// Guesses at what went wrong, e.g. "you need a Rust compiler"
+        hints: Vec<String>,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_kind() {
+        let inner: eyre::Report = InstallError::NoCompatibleBinaries {
+            name: "foo".into(),
+            version: "1.0".try_into().unwrap(),
+        }
+        .into();
+        let err = Error::from(inner.wrap_err("installing foo"));
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::Install(InstallError::NoCompatibleBinaries { .. })
+        ));
+        assert_eq!(err.to_string(), "installing foo");
+        assert_eq!(
+            format!("{err:#}"),
+            "installing foo: no compatible binaries found for foo 1.0"
+        );
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            "no compatible binaries found for foo 1.0"
+        );
+
+        // and it still works after a trip through another eyre::Report
+        let err = Error::from(eyre::Report::new(err).wrap_err("setting up"));
+        assert!(err.find::<InstallError>().is_some());
+        assert!(matches!(err.kind(), ErrorKind::Install(_)));
+        assert!(err.find::<NetworkError>().is_none());
+
+        let err = Error::from(eyre!("something else"));
+        assert!(matches!(err.kind(), ErrorKind::Other));
+    }
 }
@@ -0,0 +1,68 @@
+use crate::prelude::*;
+use std::fmt::Write;
+
+use super::{pip_hashes, pip_requirement, ExportedPackage, EXPORT_HEADER};
+
+/// YAML's double-quoted strings are a superset of JSON's, so this is always safe, even
+/// for urls with `#` or `: ` in them.
+fn quoted(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+/// A conda environment.yml named `name`, that gets `python` from conda-forge and
+/// everything else with pip, pinned and hash-checked.
+///
+/// conda passes the `pip:` entries to pip as a requirements file, so the `--hash`
+/// options work the same as they would in one. Conda's Python build isn't the same as
+/// the pybi we resolved for, but it's the same version, so the same wheels apply.
+pub fn to_conda_env(
+    packages: &[ExportedPackage],
+    python: &Version,
+    name: &str,
+) -> Result<String> {
+    let mut text = String::new();
+    writeln!(text, "{EXPORT_HEADER} conda`.")?;
+    writeln!(text, "name: {}", quoted(name))?;
+    writeln!(text, "channels:")?;
+    writeln!(text, "  - conda-forge")?;
+    writeln!(text, "dependencies:")?;
+    writeln!(text, "  - {}", quoted(&format!("python={python}")))?;
+    writeln!(text, "  - pip")?;
+    if !packages.is_empty() {
+        writeln!(text, "  - pip:")?;
+        for package in packages {
+            let mut line = pip_requirement(package);
+            for hash in pip_hashes(package)? {
+                write!(line, " --hash={hash}")?;
+            }
+            writeln!(text, "      - {}", quoted(&line))?;
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_conda_env() -> Result<()> {
+        let mut packages = super::super::test_packages()?;
+        packages[1].url = Some(Url::parse("https://example.com/trio.whl#egg=trio")?);
+        let text = to_conda_env(&packages, &"3.10.8".try_into()?, "myapp")?;
+        assert!(text.starts_with(EXPORT_HEADER));
+        assert!(text.contains("name: \"myapp\"\n"));
+        assert!(text.contains("  - \"python=3.10.8\"\n"));
+        assert!(text.contains(&format!(
+            "      - \"attrs==22.2.0 --hash=sha256:{} --hash=sha256:{}\"\n",
+            "aa".repeat(32),
+            "bb".repeat(32)
+        )));
+        assert!(text.contains(&format!(
+            "      - \"trio @ {} --hash=sha256:{}\"\n",
+            "https://example.com/trio.whl#egg=trio",
+            "cc".repeat(32)
+        )));
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::prelude::*;
+use std::fmt::Write;
+
+use super::{pip_hashes, pip_requirement, ExportedPackage};
+
+/// The first line of every Dockerfile we write, so we can tell ours apart from ones
+/// people wrote by hand.
+pub const DOCKERFILE_HEADER: &str = "# Generated by `posy export dockerfile`";
+
+/// What goes in the image, besides the pins.
+pub struct DockerfileOptions<'a> {
+    /// Which of the project's environments the pins came from
+    pub env_name: &'a str,
+    /// The image's platform, as Docker spells it, e.g. `linux/arm64`
+    pub platform: &'a str,
+    /// The local packages to build and install on top, as paths relative to the
+    /// project root (which is the build context)
+    pub local_dirs: &'a [String],
+}
+
+/// A pip requirements file that installs exactly `packages`, and fails if anything it
+/// downloads doesn't match the hashes we have for them.
+fn requirements_txt(packages: &[ExportedPackage]) -> Result<String> {
+    let mut text = String::new();
+    for package in packages {
+        text.push_str(&pip_requirement(package));
+        for hash in pip_hashes(package)? {
+            write!(text, " \\\n    --hash={hash}")?;
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// A multi-stage Dockerfile that installs `packages` into a venv, using the official
+/// image for `python`.
+///
+/// The first stage uses the full image, which has compilers, in case some pins only
+/// have sdists; the final stage copies the venv onto the slim image. Both image tags
+/// name the exact Python version, so its `/usr/local/bin/python` is the same in both
+/// and the venv still works after the copy.
+///
+/// The pins have `--no-deps`, since they're already the whole environment. Local
+/// packages also get `--no-deps`, but pip still fetches their build requirements
+/// unpinned, the same way it would anywhere else.
+pub fn to_dockerfile(
+    packages: &[ExportedPackage],
+    python: &Version,
+    options: &DockerfileOptions,
+) -> Result<String> {
+    let DockerfileOptions {
+        env_name,
+        platform,
+        local_dirs,
+    } = options;
+    let pip = "/opt/venv/bin/pip install --no-cache-dir --no-deps";
+    let mut text = String::new();
+    writeln!(text, "# syntax=docker/dockerfile:1")?;
+    writeln!(text, "{DOCKERFILE_HEADER}, from environment '{env_name}'")?;
+    writeln!(
+        text,
+        "# in posy.lock, for {platform}. Regenerate it after re-locking."
+    )?;
+    writeln!(text)?;
+    writeln!(text, "FROM --platform={platform} python:{python} AS build")?;
+    writeln!(text, "RUN python -m venv /opt/venv")?;
+    if !packages.is_empty() {
+        writeln!(text, "COPY <<\"REQUIREMENTS\" /tmp/requirements.txt")?;
+        text.push_str(&requirements_txt(packages)?);
+        writeln!(text, "REQUIREMENTS")?;
+        writeln!(text, "RUN {pip} --require-hashes -r /tmp/requirements.txt")?;
+    }
+    for dir in local_dirs.iter() {
+        let dest = match dir.as_str() {
+            "." => "/src".to_owned(),
+            dir => format!("/src/{dir}"),
+        };
+        writeln!(text, "COPY {dir} {dest}")?;
+        writeln!(text, "RUN {pip} {dest}")?;
+    }
+    writeln!(text)?;
+    writeln!(text, "FROM --platform={platform} python:{python}-slim")?;
+    writeln!(text, "COPY --from=build /opt/venv /opt/venv")?;
+    writeln!(text, "ENV VIRTUAL_ENV=/opt/venv PATH=/opt/venv/bin:$PATH")?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_dockerfile() -> Result<()> {
+        let packages = super::super::test_packages()?;
+        let local_dirs = vec![".".to_owned()];
+        let options = DockerfileOptions {
+            env_name: "default",
+            platform: "linux/arm64",
+            local_dirs: &local_dirs,
+        };
+        let text = to_dockerfile(&packages, &"3.10.8".try_into()?, &options)?;
+        assert!(text.lines().nth(1).unwrap().starts_with(DOCKERFILE_HEADER));
+        assert!(text.contains("FROM --platform=linux/arm64 python:3.10.8 AS build\n"));
+        assert!(text.contains("FROM --platform=linux/arm64 python:3.10.8-slim\n"));
+        let attrs = format!(
+            "attrs==22.2.0 \\\n    --hash=sha256:{} \\\n    --hash=sha256:{}\n",
+            "aa".repeat(32),
+            "bb".repeat(32)
+        );
+        assert!(text.contains(&attrs));
+        assert!(text.contains("COPY . /src\nRUN "));
+
+        let mut unhashable = packages;
+        unhashable[0].wheels[0].hash = ArtifactHash::from_hex("md5", &"aa".repeat(16))?;
+        unhashable[0].sdists.clear();
+        assert!(to_dockerfile(&unhashable, &"3.10.8".try_into()?, &options).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use crate::lockfile::LockedEnv;
+use crate::prelude::*;
+use crate::project::DEFAULT_ENV;
+use crate::resolve::Blueprint;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+/// Which workflow run a snapshot comes from. GitHub keeps the latest snapshot for each
+/// `correlator`, so re-running the same job replaces its old one.
+#[derive(Debug, Clone, Serialize)]
+pub struct GithubJob {
+    #[serde(skip)]
+    pub sha: String,
+    #[serde(skip)]
+    pub git_ref: String,
+    pub correlator: String,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html_url: Option<String>,
+}
+
+// The Dependency Submission API's snapshot format; see
+// https://docs.github.com/en/rest/dependency-graph/dependency-submission
+
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    version: u32,
+    sha: &'a str,
+    #[serde(rename = "ref")]
+    git_ref: &'a str,
+    job: &'a GithubJob,
+    detector: Detector,
+    scanned: String,
+    manifests: BTreeMap<String, Manifest>,
+}
+
+#[derive(Serialize)]
+struct Detector {
+    name: &'static str,
+    version: &'static str,
+    url: &'static str,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    name: String,
+    file: ManifestFile,
+    resolved: BTreeMap<String, ResolvedPackage>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    source_location: String,
+}
+
+#[derive(Serialize)]
+struct ResolvedPackage {
+    package_url: String,
+    relationship: &'static str,
+    scope: &'static str,
+    dependencies: Vec<String>,
+}
+
+/// A pin's package URL (https://github.com/package-url/purl-spec). `+` would mean a
+/// space in the version, so local version labels get it escaped.
+fn purl(name: &PackageName, version: &Version) -> String {
+    let version = version.to_string().replace('+', "%2B");
+    format!("pkg:pypi/{}@{version}", name.normalized())
+}
+
+fn manifest(
+    blueprint: &Blueprint,
+    source_location: &str,
+    name: String,
+    scope: &'static str,
+) -> Manifest {
+    let purls: HashMap<&PackageName, String> = blueprint
+        .wheels
+        .iter()
+        .map(|(pin, _)| (&pin.name, purl(&pin.name, &pin.version)))
+        .collect();
+    let mut resolved = BTreeMap::new();
+    for (pin, metadata) in &blueprint.wheels {
+        let mut dependencies: Vec<String> = metadata
+            .inner
+            .requires_dist
+            .iter()
+            .filter_map(|req| purls.get(&req.name).cloned())
+            .collect();
+        dependencies.sort_unstable();
+        dependencies.dedup();
+        let relationship = match blueprint.requested.contains(&pin.name) {
+            true => "direct",
+            false => "indirect",
+        };
+        resolved.insert(
+            pin.name.normalized().to_owned(),
+            ResolvedPackage {
+                package_url: purls[&pin.name].clone(),
+                relationship,
+                scope,
+                dependencies,
+            },
+        );
+    }
+    Manifest {
+        name,
+        file: ManifestFile {
+            source_location: source_location.into(),
+        },
+        resolved,
+    }
+}
+
+/// A dependency snapshot of every environment in posy.lock, for submitting to
+/// GitHub's dependency graph (and so Dependabot). `source_location` is posy.lock's
+/// path from the root of the repository.
+///
+/// Each environment becomes its own manifest. The default one is what the project
+/// needs to run; the others are counted as development dependencies. The pybis aren't
+/// included, since GitHub doesn't track Python itself.
+pub fn to_github_snapshot(
+    environments: &BTreeMap<String, LockedEnv>,
+    source_location: &str,
+    job: &GithubJob,
+    scanned: SystemTime,
+) -> Result<String> {
+    let manifests = environments
+        .iter()
+        .map(|(env_name, locked)| {
+            let name = format!("{source_location} ({env_name})");
+            let scope = match env_name.as_str() {
+                DEFAULT_ENV => "runtime",
+                _ => "development",
+            };
+            let manifest = manifest(&locked.blueprint, source_location, name, scope);
+            (manifest.name.clone(), manifest)
+        })
+        .collect();
+    let snapshot = Snapshot {
+        version: 0,
+        sha: &job.sha,
+        git_ref: &job.git_ref,
+        job,
+        detector: Detector {
+            name: "posy",
+            version: env!("CARGO_PKG_VERSION"),
+            url: env!("CARGO_PKG_REPOSITORY"),
+        },
+        scanned: humantime::format_rfc3339_seconds(scanned).to_string(),
+        manifests,
+    };
+    Ok(serde_json::to_string_pretty(&snapshot)? + "\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{
+        Brief, PinnedPackage, WheelResolveMetadata, WheelResolveMetadataInner,
+    };
+
+    #[test]
+    fn test_to_github_snapshot() -> Result<()> {
+        let pin = |name: &str, version: &str| -> Result<PinnedPackage> {
+            Ok(PinnedPackage {
+                name: name.try_into()?,
+                version: version.try_into()?,
+                hashes: Vec::new(),
+                url: None,
+                source: None,
+            })
+        };
+        let metadata = |requires_dist: Vec<PackageRequirement>| WheelResolveMetadata {
+            provenance: String::new(),
+            inner: WheelResolveMetadataInner {
+                requires_dist,
+                requires_python: Default::default(),
+                extras: HashSet::new(),
+            },
+        };
+        let blueprint = Blueprint {
+            pybi: pin("cpython_unofficial", "3.11.1")?,
+            wheels: vec![
+                (
+                    pin("trio", "0.22.0")?,
+                    metadata(vec![
+                        "attrs >= 19.2.0".try_into()?,
+                        "outcome".try_into()?,
+                    ]),
+                ),
+                (pin("attrs", "22.2.0+local")?, metadata(Vec::new())),
+            ],
+            requested: vec!["trio".try_into()?],
+            marker_expressions: HashMap::new(),
+        };
+        let locked = LockedEnv {
+            brief: Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: vec!["trio".try_into()?],
+                allow_pre: Default::default(),
+                constraints: Vec::new(),
+                sources: Default::default(),
+            },
+            blueprint,
+            snapshot: None,
+        };
+        let mut environments = BTreeMap::new();
+        environments.insert("default".to_owned(), locked.clone());
+        environments.insert("test".to_owned(), locked);
+        let job = GithubJob {
+            sha: "0".repeat(40),
+            git_ref: "refs/heads/main".into(),
+            correlator: "ci_lock".into(),
+            id: "1234".into(),
+            html_url: None,
+        };
+        let text = to_github_snapshot(
+            &environments,
+            "app/posy.lock",
+            &job,
+            SystemTime::UNIX_EPOCH,
+        )?;
+        let snapshot: serde_json::Value = serde_json::from_str(&text)?;
+        assert_eq!(snapshot["ref"], "refs/heads/main");
+        assert_eq!(snapshot["job"]["correlator"], "ci_lock");
+        assert_eq!(snapshot["scanned"], "1970-01-01T00:00:00Z");
+
+        let default = &snapshot["manifests"]["app/posy.lock (default)"];
+        assert_eq!(default["file"]["source_location"], "app/posy.lock");
+        let trio = &default["resolved"]["trio"];
+        assert_eq!(trio["relationship"], "direct");
+        assert_eq!(trio["scope"], "runtime");
+        // outcome isn't pinned, so it's not a dependency of anything here
+        assert_eq!(
+            trio["dependencies"],
+            serde_json::json!(["pkg:pypi/attrs@22.2.0%2Blocal"])
+        );
+        assert_eq!(default["resolved"]["attrs"]["relationship"], "indirect");
+        let test = &snapshot["manifests"]["app/posy.lock (test)"];
+        assert_eq!(test["resolved"]["trio"]["scope"], "development");
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Writing a locked environment out in other tools' lockfile formats, so that a team
+//! that's partway through moving to posy can keep everyone on the same pins -- or as a
+//! Dockerfile, to build a container with them, a conda environment.yml, or a snapshot
+//! for GitHub's dependency graph.
+//!
+//! This is best-effort. We write down the same files posy would install, but the other
+//! tools' lockfiles also describe the project itself (uv's root package, PDM's content
+//! hash), which posy doesn't track. Expect the other tool to decide its lock is out of
+//! date and re-lock; when it does, it starts from these pins.
+
+mod conda;
+mod dockerfile;
+mod github;
+mod pdm;
+mod uv;
+
+pub use conda::to_conda_env;
+pub use dockerfile::{to_dockerfile, DockerfileOptions, DOCKERFILE_HEADER};
+pub use github::{to_github_snapshot, GithubJob};
+pub use pdm::to_pdm_lock;
+pub use uv::to_uv_lock;
+
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::resolve::Blueprint;
+use crate::util::url_filename;
+
+/// How the lockfiles and environment.yml we write start, followed by the tool's name.
+/// `posy export` only overwrites files that have it.
+pub const EXPORT_HEADER: &str = "# Exported from posy.lock by `posy export";
+
+/// One of a pinned package's files.
+#[derive(Debug, Clone)]
+pub struct ExportedFile {
+    pub filename: String,
+    pub url: Url,
+    pub hash: ArtifactHash,
+}
+
+/// A pinned package, and the files posy would install it from.
+#[derive(Debug, Clone)]
+pub struct ExportedPackage {
+    pub name: PackageName,
+    pub version: Version,
+    /// Set if it came from a `name @ url` requirement instead of the index
+    pub url: Option<Url>,
+    pub requires_python: Specifiers,
+    /// The entries in its metadata that name other pinned packages
+    pub dependencies: Vec<PackageRequirement>,
+    pub sdists: Vec<ExportedFile>,
+    pub wheels: Vec<ExportedFile>,
+}
+
+/// The other tools all write hashes as `sha256:<hex>`.
+fn colon_hash(hash: &ArtifactHash) -> String {
+    format!(
+        "{}:{}",
+        hash.mode,
+        data_encoding::HEXLOWER.encode(&hash.raw_data)
+    )
+}
+
+/// The hash algorithms pip's `--require-hashes` knows about.
+const PIP_HASHES: &[&str] = &["sha256", "sha384", "sha512"];
+
+/// How pip spells a pin: `name==version`, or `name @ url`.
+fn pip_requirement(package: &ExportedPackage) -> String {
+    match &package.url {
+        Some(url) => format!("{} @ {url}", package.name.normalized()),
+        None => format!("{}=={}", package.name.normalized(), package.version),
+    }
+}
+
+/// The `--hash` values pip can check a package's files against. In hash-checking mode
+/// pip refuses anything without one, so it's an error if there aren't any.
+fn pip_hashes(package: &ExportedPackage) -> Result<Vec<String>> {
+    let hashes: Vec<String> = package
+        .sdists
+        .iter()
+        .chain(package.wheels.iter())
+        .filter(|file| PIP_HASHES.contains(&file.hash.mode.as_str()))
+        .map(|file| colon_hash(&file.hash))
+        .collect();
+    if hashes.is_empty() {
+        bail!(
+            "{} {} has no hashes that pip can check",
+            package.name.as_given(),
+            package.version
+        );
+    }
+    Ok(hashes)
+}
+
+/// Looks up the files behind each of `blueprint`'s pins, sorted by name. The pybi
+/// isn't included; the other tools bring their own Python.
+pub fn exported_packages(
+    db: &PackageDB,
+    blueprint: &Blueprint,
+) -> Result<Vec<ExportedPackage>> {
+    let pinned: HashSet<&PackageName> =
+        blueprint.wheels.iter().map(|(pin, _)| &pin.name).collect();
+    let mut packages = Vec::new();
+    for (pin, metadata) in &blueprint.wheels {
+        let mut sdists = Vec::new();
+        let mut wheels = Vec::new();
+        if let Some(url) = &pin.url {
+            let filename = url_filename(url).unwrap_or_default();
+            if let Some(hash) = ArtifactHash::strongest(&pin.hashes) {
+                let file = ExportedFile {
+                    url: url.clone(),
+                    hash: hash.clone(),
+                    filename,
+                };
+                match file.filename.ends_with(".whl") {
+                    true => wheels.push(file),
+                    false => sdists.push(file),
+                }
+            }
+        } else {
+            for ai in db.artifacts_for_version(&pin.name, &pin.version)? {
+                // the hash we pinned, not whatever the index says now
+                let ours = ai.hashes.iter().filter(|hash| pin.hashes.contains(hash));
+                let Some(hash) = ArtifactHash::strongest(ours) else {
+                    continue;
+                };
+                let file = ExportedFile {
+                    filename: ai.name.to_string(),
+                    url: ai.url.clone(),
+                    hash: hash.clone(),
+                };
+                if ai.is::<Wheel>() {
+                    wheels.push(file);
+                } else if ai.is::<Sdist>() {
+                    sdists.push(file);
+                }
+            }
+        }
+        let mut dependencies: Vec<PackageRequirement> = metadata
+            .inner
+            .requires_dist
+            .iter()
+            .filter(|req| pinned.contains(&req.name))
+            .cloned()
+            .collect();
+        dependencies.sort_by(|a, b| a.name.normalized().cmp(b.name.normalized()));
+        packages.push(ExportedPackage {
+            name: pin.name.clone(),
+            version: pin.version.clone(),
+            url: pin.url.clone(),
+            requires_python: metadata.inner.requires_python.clone(),
+            dependencies,
+            sdists,
+            wheels,
+        });
+    }
+    packages.sort_by(|a, b| a.name.normalized().cmp(b.name.normalized()));
+    Ok(packages)
+}
+
+#[cfg(test)]
+fn test_packages() -> Result<Vec<ExportedPackage>> {
+    let hash = |c: &str| ArtifactHash::from_hex("sha256", &c.repeat(32));
+    let file = |filename: &str, h: &str| -> Result<ExportedFile> {
+        Ok(ExportedFile {
+            filename: filename.into(),
+            url: Url::parse(&format!("https://example.com/{filename}"))?,
+            hash: hash(h)?,
+        })
+    };
+    Ok(vec![
+        ExportedPackage {
+            name: "attrs".try_into()?,
+            version: "22.2.0".try_into()?,
+            url: None,
+            requires_python: ">= 3.6".try_into()?,
+            dependencies: Vec::new(),
+            sdists: vec![file("attrs-22.2.0.tar.gz", "aa")?],
+            wheels: vec![file("attrs-22.2.0-py3-none-any.whl", "bb")?],
+        },
+        ExportedPackage {
+            name: "trio".try_into()?,
+            version: "0.22.0".try_into()?,
+            url: None,
+            requires_python: ">= 3.7".try_into()?,
+            dependencies: vec!["attrs >= 19.2.0".try_into()?],
+            sdists: Vec::new(),
+            wheels: vec![file("trio-0.22.0-py3-none-any.whl", "cc")?],
+        },
+    ])
+}
@@ -0,0 +1,80 @@
+use crate::prelude::*;
+use crate::util::multiline_array;
+use toml_edit::{value, Array, ArrayOfTables, Document, InlineTable, Item, Table};
+
+use super::{colon_hash, ExportedPackage, EXPORT_HEADER};
+
+/// The lock format we write. 4.4 is the oldest one that has per-package `groups`.
+const LOCK_VERSION: &str = "4.4.1";
+
+/// A pdm.lock (format 4) with `packages`, all in the dependency group `group`.
+///
+/// PDM also records a hash of the project's dependencies, to notice when the lock is
+/// out of date. We don't know how PDM will read the project's pyproject.toml, so we
+/// leave that out, and PDM will want to re-lock; `pdm lock --update-reuse` keeps
+/// these pins.
+pub fn to_pdm_lock(packages: &[ExportedPackage], group: &str) -> String {
+    let mut d = Document::new();
+    let mut metadata = Table::new();
+    metadata["groups"] = value(Array::from_iter([group]));
+    metadata["lock_version"] = value(LOCK_VERSION);
+    d["metadata"] = Item::Table(metadata);
+
+    let mut tables = ArrayOfTables::new();
+    for package in packages {
+        let mut table = Table::new();
+        table.decor_mut().set_prefix("\n");
+        table["name"] = value(package.name.normalized());
+        table["version"] = value(package.version.to_string());
+        if !package.requires_python.0.is_empty() {
+            table["requires_python"] = value(package.requires_python.to_string());
+        }
+        if let Some(url) = &package.url {
+            table["url"] = value(url.as_str());
+        }
+        table["groups"] = value(Array::from_iter([group]));
+        if !package.dependencies.is_empty() {
+            table["dependencies"] = value(multiline_array(
+                package.dependencies.iter().map(|req| req.to_string()),
+            ));
+        }
+        let files = package.sdists.iter().chain(package.wheels.iter());
+        table["files"] = value(multiline_array(files.map(|file| {
+            let mut entry = InlineTable::new();
+            entry.insert("file", file.filename.as_str().into());
+            entry.insert("hash", colon_hash(&file.hash).into());
+            entry
+        })));
+        tables.push(table);
+    }
+    d["package"] = Item::ArrayOfTables(tables);
+    format!("{EXPORT_HEADER} pdm`.\n\n{d}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::importers::parse_pdm_lock;
+
+    #[test]
+    fn test_to_pdm_lock() -> Result<()> {
+        let packages = super::super::test_packages()?;
+        let lock = to_pdm_lock(&packages, "default");
+        assert!(lock.starts_with("# Exported from posy.lock"));
+        assert!(lock.contains("lock_version = \"4.4.1\""));
+        let dependency = packages[1].dependencies[0].to_string();
+        assert!(lock.contains(&format!("\"{dependency}\"")));
+
+        let imported = parse_pdm_lock(&lock)?;
+        let pins: Vec<String> = imported.pins.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pins,
+            vec![
+                UserRequirement::try_from("attrs == 22.2.0")?.to_string(),
+                UserRequirement::try_from("trio == 0.22.0")?.to_string(),
+            ]
+        );
+        assert_eq!(imported.hashes[&packages[0].name].len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use crate::prelude::*;
+use crate::util::multiline_array;
+use toml_edit::{value, ArrayOfTables, Document, InlineTable, Item, Table};
+
+use super::{colon_hash, ExportedFile, ExportedPackage, EXPORT_HEADER};
+
+fn file_table(file: &ExportedFile) -> InlineTable {
+    let mut table = InlineTable::new();
+    table.insert("url", file.url.as_str().into());
+    table.insert("hash", colon_hash(&file.hash).into());
+    table
+}
+
+/// A uv.lock (version 1) with `packages`, which were resolved for `python`.
+///
+/// uv wants to know which index each package came from. posy doesn't keep track of
+/// that when there are several, so everything gets attributed to `registry`.
+pub fn to_uv_lock(
+    packages: &[ExportedPackage],
+    python: &Version,
+    registry: &Url,
+) -> String {
+    let mut d = Document::new();
+    d["version"] = value(1i64);
+    // we only resolved for this one Python, so that's all we can vouch for
+    let python = python.to_string();
+    let release: Vec<&str> = python.split('.').take(2).collect();
+    d["requires-python"] = value(format!("=={}.*", release.join(".")));
+    let registry = registry.as_str().trim_end_matches('/');
+
+    let mut tables = ArrayOfTables::new();
+    for package in packages {
+        let mut table = Table::new();
+        table.decor_mut().set_prefix("\n");
+        table["name"] = value(package.name.normalized());
+        table["version"] = value(package.version.to_string());
+        let mut source = InlineTable::new();
+        match &package.url {
+            Some(url) => source.insert("url", url.as_str().into()),
+            None => source.insert("registry", registry.into()),
+        };
+        table["source"] = value(source);
+        let mut names: Vec<&str> = package
+            .dependencies
+            .iter()
+            .map(|req| req.name.normalized())
+            .collect();
+        names.dedup();
+        if !names.is_empty() {
+            table["dependencies"] =
+                value(multiline_array(names.into_iter().map(|name| {
+                    let mut dep = InlineTable::new();
+                    dep.insert("name", name.into());
+                    dep
+                })));
+        }
+        if let Some(sdist) = package.sdists.first() {
+            table["sdist"] = value(file_table(sdist));
+        }
+        if !package.wheels.is_empty() {
+            table["wheels"] =
+                value(multiline_array(package.wheels.iter().map(file_table)));
+        }
+        tables.push(table);
+    }
+    d["package"] = Item::ArrayOfTables(tables);
+    format!("{EXPORT_HEADER} uv`.\n\n{d}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::importers::parse_uv_lock;
+
+    #[test]
+    fn test_to_uv_lock() -> Result<()> {
+        let packages = super::super::test_packages()?;
+        let registry = Url::parse("https://pypi.org/simple/")?;
+        let lock = to_uv_lock(&packages, &"3.10.8".try_into()?, &registry);
+        assert!(lock.starts_with(EXPORT_HEADER));
+        assert!(lock.contains("requires-python = \"==3.10.*\""));
+        assert!(lock.contains("source = { registry = \"https://pypi.org/simple\" }"));
+        assert!(lock.contains("{ name = \"attrs\" }"));
+
+        // uv.lock -> posy -> uv.lock keeps the same pins and files
+        let imported = parse_uv_lock(&lock)?;
+        let pins: Vec<String> = imported.pins.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pins,
+            vec![
+                UserRequirement::try_from("attrs == 22.2.0")?.to_string(),
+                UserRequirement::try_from("trio == 0.22.0")?.to_string(),
+            ]
+        );
+        assert_eq!(imported.hashes[&packages[0].name].len(), 2);
+        assert_eq!(
+            imported.hashes[&packages[1].name],
+            vec![packages[1].wheels[0].hash.clone()]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use crate::prelude::*;
+
+use super::{exact_version, Imported};
+
+/// Ubuntu's pip used to list this in every freeze, but it was never a real package.
+const BOGUS: &[&str] = &["pkg-resources==0.0.0"];
+
+/// Reads `pip freeze` output. Each line is one installed package: `name==version`, or
+/// `name @ url` for things that were installed from a URL or a local file.
+///
+/// Editable installs (`-e ...`) are somebody's working copy, which can't be pinned, so
+/// they get skipped -- if it's one of the project's own packages, posy installs it
+/// anyway. So does anything else that isn't an exact pin, and any pip options.
+pub fn parse_freeze(text: &str) -> Imported {
+    let mut imported = Imported::default();
+    for line in text.lines() {
+        // pip allows comments after whitespace, but '#' can also be part of a URL
+        let line = match line.find(" #").or_else(|| line.find("\t#")) {
+            Some(i) => &line[..i],
+            None => line,
+        }
+        .trim();
+        if line.is_empty() || line.starts_with('#') || BOGUS.contains(&line) {
+            continue;
+        }
+        if line.starts_with("-e ") || line.starts_with("--editable") {
+            imported.skipped.push(format!(
+                "{line}: editable installs can't be pinned (if it's part of this \
+                 project, add it as a local package instead)"
+            ));
+            continue;
+        }
+        if line.starts_with('-') {
+            imported
+                .skipped
+                .push(format!("{line}: pip options don't apply to posy"));
+            continue;
+        }
+        match UserRequirement::try_from(line) {
+            Ok(req) if req.url.is_some() || exact_version(&req).is_some() => {
+                imported.requirements.push(req)
+            }
+            Ok(_) => imported
+                .skipped
+                .push(format!("{line}: not pinned to an exact version")),
+            Err(err) => imported.skipped.push(format!("{line}: {err}")),
+        }
+    }
+    imported
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_freeze() {
+        let imported = parse_freeze(indoc::indoc! {"
+            attrs==22.2.0
+            # Editable install with no version control (myproj==0.1)
+            -e /home/me/myproj
+            -e git+https://github.com/me/thing@abc123#egg=thing
+            pkg-resources==0.0.0
+            Trio==0.22.0  # the good one
+            wheelhouse @ file:///tmp/wheelhouse-1.0-py3-none-any.whl#sha256=abcd
+            --index-url https://example.com/simple
+            sniffio>=1.3
+            not a requirement
+        "});
+        let requirements: Vec<String> = imported
+            .requirements
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(
+            requirements,
+            vec![
+                "attrs == 22.2.0",
+                "Trio == 0.22.0",
+                "wheelhouse @ \
+                 file:///tmp/wheelhouse-1.0-py3-none-any.whl#sha256=abcd",
+            ]
+        );
+        let skipped: Vec<&str> = imported
+            .skipped
+            .iter()
+            .map(|s| s.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                "-e /home/me/myproj",
+                "-e git+https",
+                "--index-url https",
+                "sniffio>=1.3",
+                "not a requirement",
+            ]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Bringing environments over from other tools. Each importer turns the other tool's
+//! description of an environment into exact requirements, and then we resolve those to
+//! get a Blueprint, with hashes from the index, for one of the project's environments
+//! in posy.lock.
+//!
+//! Some tools (like `pip freeze`) only tell us what's installed, so the imported pins
+//! become the environment's requirements, and the next `posy lock` re-resolves from the
+//! project's real ones -- starting from the imported versions, so anything that
+//! doesn't have to change, won't. Others (like Poetry) know what the project asked
+//! for too, and then their pins just constrain the versions we pick for it.
+
+mod freeze;
+mod pdm;
+mod poetry;
+mod uv;
+
+pub use freeze::parse_freeze;
+pub use pdm::parse_pdm_lock;
+pub use poetry::{parse_poetry_lock, PoetryProject};
+pub use uv::parse_uv_lock;
+
+use crate::lockfile::{
+    blueprint_diff, IndexSnapshot, LockedEnv, Lockfile, PackageChange,
+};
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::project::Project;
+use crate::resolve::{AllowPre, Blueprint, Brief};
+
+/// What we got out of another tool's idea of an environment.
+#[derive(Debug, Clone, Default)]
+pub struct Imported {
+    /// Exact pins (`name == version`) and direct references (`name @ url`), to use
+    /// as the environment's requirements. If this is empty, we use the project's own.
+    pub requirements: Vec<UserRequirement>,
+    /// Exact pins that only apply if something needs the package
+    pub pins: Vec<UserRequirement>,
+    /// The files the other tool was willing to install, for packages where it
+    /// recorded them
+    pub hashes: HashMap<PackageName, Vec<ArtifactHash>>,
+    /// Whatever we couldn't use, and why, for telling the user
+    pub skipped: Vec<String>,
+}
+
+/// The version `req` pins exactly, if it does.
+pub fn exact_version(req: &UserRequirement) -> Option<Version> {
+    match req.specifiers.0.as_slice() {
+        [spec] if spec.op == CompareOp::Equal && !spec.value.ends_with(".*") => {
+            spec.value.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Poetry, uv, and PDM all write hashes as `sha256:<hex>`.
+fn parse_colon_hash(hash: &str) -> Result<ArtifactHash> {
+    let Some((mode, hex)) = hash.split_once(':') else {
+        bail!("bad hash {hash:?}");
+    };
+    ArtifactHash::from_hex(mode, hex)
+}
+
+/// The hashes in a list of `{file = "...", hash = "sha256:..."}` tables (or with `url`
+/// instead of `file`, for uv).
+fn file_hashes(files: &toml_edit::Value) -> Result<Vec<ArtifactHash>> {
+    let Some(files) = files.as_array() else {
+        bail!("expected a list of files");
+    };
+    files
+        .iter()
+        .filter_map(|file| file.as_inline_table()?.get("hash")?.as_str())
+        .map(parse_colon_hash)
+        .collect()
+}
+
+/// Turns the `(name, version, hashes)` of every package in another tool's lockfile
+/// into pins.
+///
+/// Lockfiles that cover several platforms at once can pin a package to different
+/// versions on each, e.g. an older numpy for older Pythons. Pins on both versions
+/// would rule out everything, so packages like that don't get pinned at all.
+fn pins_from_locked(
+    locked: Vec<(String, String, Vec<ArtifactHash>)>,
+    mut skipped: Vec<String>,
+) -> Result<Imported> {
+    let mut versions: HashMap<PackageName, Vec<String>> = HashMap::new();
+    let mut imported = Imported::default();
+    for (name, version, hashes) in locked {
+        let pin: UserRequirement =
+            format!("{name} == {version}").as_str().try_into()?;
+        let seen = versions.entry(pin.name.clone()).or_default();
+        if !seen.contains(&version) {
+            seen.push(version);
+        }
+        if !hashes.is_empty() {
+            let known = imported.hashes.entry(pin.name.clone()).or_default();
+            for hash in hashes {
+                if !known.contains(&hash) {
+                    known.push(hash);
+                }
+            }
+        }
+        if !imported.pins.iter().any(|p| p.name == pin.name) {
+            imported.pins.push(pin);
+        }
+    }
+    imported.pins.retain(|pin| {
+        let seen = &versions[&pin.name];
+        if seen.len() > 1 {
+            skipped.push(format!(
+                "{}: locked at several versions ({}), so it's not pinned",
+                pin.name.as_given(),
+                seen.join(", ")
+            ));
+            imported.hashes.remove(&pin.name);
+        }
+        seen.len() == 1
+    });
+    imported.skipped = skipped;
+    Ok(imported)
+}
+
+/// Resolves `imported` for one of the project's environments, using the Python the
+/// project asks for, and puts the result in `lockfile`. Returns how the pins changed
+/// from what was there before.
+///
+/// If the other tool recorded hashes for a package, we only keep the files it knew
+/// about, so the import can't quietly start trusting anything new.
+///
+/// The brief we lock with is the environment's own, exactly as it is in
+/// pyproject.toml. Any imported requirements only steer this one resolve, so once
+/// they've been written down, `posy lock` sees the change and re-locks.
+pub fn lock_imported(
+    db: &PackageDB,
+    project: &Project,
+    env_name: &str,
+    imported: &Imported,
+    platforms: &[&PybiPlatform],
+    lockfile: &mut Lockfile,
+) -> Result<Vec<PackageChange>> {
+    let brief = project.config.brief(env_name)?;
+    let mut constrained = Brief {
+        constraints: imported.pins.clone(),
+        ..brief.clone()
+    };
+    if !imported.requirements.is_empty() {
+        constrained.requirements = imported.requirements.clone();
+    }
+    // an exact pin on a pre-release means we want it
+    if let AllowPre::Some(pre) = &mut constrained.allow_pre {
+        pre.extend(
+            imported
+                .requirements
+                .iter()
+                .chain(imported.pins.iter())
+                .filter(|req| exact_version(req).map_or(false, |v| v.is_prerelease()))
+                .map(|req| req.name.clone()),
+        );
+    }
+    let mut blueprint: Blueprint = constrained
+        .resolve(db, platforms, None, &[])
+        .wrap_err("the imported pins don't work together")?;
+    for (pin, _) in &mut blueprint.wheels {
+        let Some(known) = imported.hashes.get(&pin.name) else {
+            continue;
+        };
+        pin.hashes.retain(|hash| known.contains(hash));
+        if pin.hashes.is_empty() {
+            bail!(
+                "none of the imported hashes for {} {} match the files on the index",
+                pin.name.as_given(),
+                pin.version
+            );
+        }
+    }
+    let old = lockfile.environments.get(env_name).map(|l| &l.blueprint);
+    let changes = blueprint_diff(old, &blueprint);
+    let snapshot = IndexSnapshot::take(db, &blueprint);
+    lockfile.environments.insert(
+        env_name.into(),
+        LockedEnv {
+            brief,
+            blueprint,
+            snapshot: Some(snapshot),
+        },
+    );
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package_db::MemoryIndex;
+    use crate::project::{ProjectConfig, DEFAULT_ENV};
+    use crate::test_util::{test_platform, TestDB};
+
+    #[test]
+    fn test_lock_imported() -> Result<()> {
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("attrs", "21.4.0", &[])?;
+        index.add_wheel("attrs", "22.2.0", &[])?;
+        index.add_wheel("trio", "0.22.0", &["attrs >= 19"])?;
+        index.add_wheel("trio", "0.23.0rc1", &["attrs >= 19"])?;
+
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let project = Project {
+            root: test_db.path().to_owned(),
+            config: ProjectConfig::default(),
+            package: None,
+        };
+        let platform = test_platform();
+        let mut lockfile = Lockfile::default();
+
+        let imported = parse_freeze("attrs==21.4.0\ntrio==0.23.0rc1\n");
+        let changes = lock_imported(
+            &db,
+            &project,
+            DEFAULT_ENV,
+            &imported,
+            &[&platform],
+            &mut lockfile,
+        )?;
+        let changes: Vec<&str> = changes.iter().map(|c| c.name()).collect();
+        assert_eq!(changes, vec!["attrs", "cpython_unofficial", "trio"]);
+        let locked = &lockfile.environments[DEFAULT_ENV];
+        let mut pins: Vec<String> = locked
+            .blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+            .collect();
+        pins.sort_unstable();
+        assert_eq!(pins, vec!["attrs 21.4.0", "trio 0.23.0rc1"]);
+        // the brief is still the project's, pre-releases and all
+        assert!(locked.brief.requirements.is_empty());
+        assert!(!locked.brief.allow_pre.allow_pre_for(&"trio".try_into()?));
+        assert!(locked
+            .blueprint
+            .wheels
+            .iter()
+            .all(|(pin, _)| !pin.hashes.is_empty()));
+
+        let imported = parse_freeze("attrs==21.4.0\ntrio==0.22.0\nmissing==1.0\n");
+        assert!(lock_imported(
+            &db,
+            &project,
+            DEFAULT_ENV,
+            &imported,
+            &[&platform],
+            &mut lockfile,
+        )
+        .is_err());
+
+        // pins only constrain the project's own requirements...
+        let project = Project {
+            config: ProjectConfig::parse_from(
+                "[tool.posy]\nrequirements = [\"trio\"]\n",
+            )?,
+            ..project
+        };
+        let mut imported = Imported {
+            pins: parse_freeze("attrs==21.4.0\nunused==1.0\n").requirements,
+            ..Imported::default()
+        };
+        lock_imported(
+            &db,
+            &project,
+            DEFAULT_ENV,
+            &imported,
+            &[&platform],
+            &mut lockfile,
+        )?;
+        let locked = &lockfile.environments[DEFAULT_ENV];
+        assert_eq!(locked.blueprint.wheels.len(), 2);
+        assert!(locked.brief.constraints.is_empty());
+        // ...and hashes from the other tool have to match the index
+        imported.hashes.insert(
+            "attrs".try_into()?,
+            vec![ArtifactHash::from_hex("sha256", &"ab".repeat(32))?],
+        );
+        assert!(lock_imported(
+            &db,
+            &project,
+            DEFAULT_ENV,
+            &imported,
+            &[&platform],
+            &mut lockfile,
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::prelude::*;
+use toml_edit::Document;
+
+use super::{file_hashes, pins_from_locked, Imported};
+
+/// Reads a pdm.lock. Every package PDM got from an index becomes a pin, with the
+/// hashes of the files it recorded for it: under each package in lock format 4, or in
+/// `[metadata.files]`, keyed by `"name version"`, in older ones.
+///
+/// PDM gives a package one entry for each set of extras it's used with, all pinned to
+/// the same version, so those are just one pin. Editable installs, local paths, git
+/// repositories, and urls get skipped.
+pub fn parse_pdm_lock(text: &str) -> Result<Imported> {
+    let d = text.parse::<Document>()?;
+    let old_style_files = d
+        .get("metadata")
+        .and_then(|m| m.get("files"))
+        .and_then(|f| f.as_table_like());
+    let mut locked = Vec::new();
+    let mut skipped = Vec::new();
+    let Some(packages) = d.get("package").and_then(|p| p.as_array_of_tables()) else {
+        return Ok(Imported::default());
+    };
+    for package in packages.iter() {
+        let (Some(name), Some(version)) = (
+            package.get("name").and_then(|n| n.as_str()),
+            package.get("version").and_then(|v| v.as_str()),
+        ) else {
+            bail!("every [[package]] in pdm.lock needs a name and version");
+        };
+        context!("Reading {name} {version} from pdm.lock");
+        if package.get("editable").and_then(|e| e.as_bool()) == Some(true) {
+            skipped.push(format!(
+                "{name} {version}: editable installs can't be pinned"
+            ));
+            continue;
+        }
+        if let Some(kind) = ["git", "path", "url"]
+            .into_iter()
+            .find(|kind| package.contains_key(kind))
+        {
+            skipped.push(format!(
+                "{name} {version}: comes from a {kind}, not an index"
+            ));
+            continue;
+        }
+        let files = package.get("files").and_then(|f| f.as_value()).or_else(|| {
+            old_style_files?
+                .get(&format!("{name} {version}"))?
+                .as_value()
+        });
+        let hashes = match files {
+            Some(files) => file_hashes(files)?,
+            None => Vec::new(),
+        };
+        locked.push((name.to_owned(), version.to_owned(), hashes));
+    }
+    pins_from_locked(locked, skipped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_pdm_lock() -> Result<()> {
+        let hash = |c: &str| format!("sha256:{}", c.repeat(64));
+        let lock = indoc::formatdoc! {r#"
+            [metadata]
+            groups = ["default"]
+            lock_version = "4.4.1"
+
+            [[package]]
+            name = "attrs"
+            version = "22.2.0"
+            groups = ["default"]
+            files = [
+                {{file = "attrs-22.2.0-py3-none-any.whl", hash = "{a}"}},
+            ]
+
+            [[package]]
+            name = "trio"
+            version = "0.22.0"
+            groups = ["default"]
+            files = [
+                {{file = "trio-0.22.0-py3-none-any.whl", hash = "{b}"}},
+            ]
+
+            [[package]]
+            name = "trio"
+            version = "0.22.0"
+            extras = ["foo"]
+            groups = ["default"]
+            files = [
+                {{file = "trio-0.22.0-py3-none-any.whl", hash = "{b}"}},
+            ]
+
+            [[package]]
+            name = "mylib"
+            version = "0.1.0"
+            path = "../mylib"
+        "#, a = hash("a"), b = hash("b")};
+        let imported = parse_pdm_lock(&lock)?;
+        let pins: Vec<String> = imported.pins.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pins,
+            vec![
+                UserRequirement::try_from("attrs == 22.2.0")?.to_string(),
+                UserRequirement::try_from("trio == 0.22.0")?.to_string(),
+            ]
+        );
+        assert_eq!(imported.skipped.len(), 1);
+
+        let old = indoc::formatdoc! {r#"
+            [[package]]
+            name = "attrs"
+            version = "22.2.0"
+
+            [metadata]
+            lock_version = "3.1"
+
+            [metadata.files]
+            "attrs 22.2.0" = [
+                {{url = "https://example.com/attrs.whl", hash = "{a}"}},
+            ]
+        "#, a = hash("a")};
+        let imported = parse_pdm_lock(&old)?;
+        let attrs: PackageName = "attrs".try_into()?;
+        assert_eq!(
+            imported.hashes[&attrs],
+            vec![ArtifactHash::from_hex("sha256", &"a".repeat(64))?]
+        );
+        Ok(())
+    }
+}
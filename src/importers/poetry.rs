@@ -0,0 +1,495 @@
+use crate::prelude::*;
+use crate::util::multiline_array;
+use std::collections::BTreeMap;
+use toml_edit::{Document, Item};
+
+use super::{file_hashes, pins_from_locked, Imported};
+
+/// What a Poetry project asks for, from `[tool.poetry]` in its pyproject.toml.
+#[derive(Debug, Clone, Default)]
+pub struct PoetryProject {
+    /// From the `python` entry in its dependencies, if it's not just `*`
+    pub python: Option<PythonRequirement>,
+    pub requirements: Vec<UserRequirement>,
+    /// Dependency groups, which become environments. The old-style
+    /// `[tool.poetry.dev-dependencies]` is the `dev` group.
+    pub groups: BTreeMap<String, Vec<UserRequirement>>,
+    /// Whatever we couldn't use, and why, for telling the user
+    pub skipped: Vec<String>,
+}
+
+/// One release number, split up: `1.2.3b1` is `[1, 2, 3]`.
+fn release(version: &str) -> Result<Vec<u64>> {
+    let mut parts = Vec::new();
+    for part in version.split('.') {
+        let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if digits.is_empty() {
+            break;
+        }
+        parts.push(digits.parse()?);
+        if digits.len() != part.len() {
+            break;
+        }
+    }
+    if parts.is_empty() {
+        bail!("bad version {version:?}");
+    }
+    Ok(parts)
+}
+
+/// `version`'s release number, truncated to `len` parts, with the last one bumped.
+/// `bump("1.2.3", 2)` is `1.3`.
+fn bump(version: &[u64], len: usize) -> String {
+    let mut upper = version[..len].to_vec();
+    *upper.last_mut().unwrap() += 1;
+    upper
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Translates one of Poetry's version constraints into PEP 440 specifiers. On top of
+/// the usual operators, Poetry has:
+///
+/// - `^1.2.3`: compatible with 1.2.3, i.e. `>= 1.2.3, < 2`. The leftmost non-zero
+///   part is the one that can't change, so `^0.2.3` is `>= 0.2.3, < 0.3`.
+/// - `~1.2.3`: `>= 1.2.3, < 1.3` (but `~1` is `>= 1, < 2`)
+/// - `1.2.3` on its own, which means `== 1.2.3`
+/// - `*`, which means anything
+///
+/// and lets you separate the parts with spaces instead of commas. `||` (either of two
+/// ranges) has no PEP 440 equivalent, so that's an error.
+pub fn poetry_specifiers(constraint: &str) -> Result<String> {
+    if constraint.contains("||") || constraint.contains(" | ") {
+        bail!("can't translate {constraint:?}: PEP 440 has no 'or'");
+    }
+    // glue operators back onto their versions, so ">= 1.2, < 2" and ">=1.2 <2" come
+    // out the same
+    let mut tokens: Vec<String> = Vec::new();
+    for token in constraint.split([',', ' ']).filter(|t| !t.is_empty()) {
+        match tokens.last_mut() {
+            Some(last) if last.chars().all(|c| "<>=!~^".contains(c)) => {
+                last.push_str(token)
+            }
+            _ => tokens.push(token.into()),
+        }
+    }
+    let mut specifiers = Vec::new();
+    for token in &tokens {
+        let token = token.as_str();
+        if token == "*" {
+            continue;
+        }
+        if let Some(version) = token.strip_prefix('^') {
+            let parts = release(version)?;
+            let fixed = parts
+                .iter()
+                .position(|n| *n != 0)
+                .unwrap_or(parts.len() - 1);
+            specifiers.push(format!(">= {version}"));
+            specifiers.push(format!("< {}", bump(&parts, fixed + 1)));
+        } else if let Some(version) =
+            token.strip_prefix('~').filter(|v| !v.starts_with('='))
+        {
+            let parts = release(version)?;
+            specifiers.push(format!(">= {version}"));
+            specifiers.push(format!("< {}", bump(&parts, parts.len().min(2))));
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+            specifiers.push(format!("== {token}"));
+        } else {
+            let split = token
+                .find(|c: char| !"<>=!~".contains(c))
+                .unwrap_or(token.len());
+            let (op, version) = token.split_at(split);
+            specifiers.push(format!("{op} {version}"));
+        }
+    }
+    Ok(specifiers.join(", "))
+}
+
+/// The marker for a dependency's `python = "..."` restriction.
+fn python_marker(constraint: &str) -> Result<String> {
+    let specifiers = poetry_specifiers(constraint)?;
+    Ok(specifiers
+        .split(", ")
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let (op, version) = s.split_once(' ').unwrap();
+            format!("python_version {op} '{version}'")
+        })
+        .collect::<Vec<_>>()
+        .join(" and "))
+}
+
+/// One entry in a Poetry dependency table, as a requirement. Returns None for
+/// optional dependencies, which only get installed for one of the project's extras.
+fn poetry_requirement(name: &str, spec: &Item) -> Result<Option<UserRequirement>> {
+    let mut extras = Vec::new();
+    let mut markers = Vec::new();
+    let constraint = if let Some(constraint) = spec.as_str() {
+        constraint.to_owned()
+    } else if let Some(table) = spec.as_table_like() {
+        for kind in ["git", "path", "url"] {
+            if table.contains_key(kind) {
+                bail!("{kind} dependencies can't be imported; add it by hand");
+            }
+        }
+        if table.get("optional").and_then(|o| o.as_bool()) == Some(true) {
+            return Ok(None);
+        }
+        if let Some(list) = table.get("extras").and_then(|e| e.as_array()) {
+            extras.extend(list.iter().filter_map(|e| e.as_str()));
+        }
+        if let Some(python) = table.get("python").and_then(|p| p.as_str()) {
+            markers.push(python_marker(python)?);
+        }
+        if let Some(marker) = table.get("markers").and_then(|m| m.as_str()) {
+            markers.push(marker.to_owned());
+        }
+        let stray: Vec<&str> = ["platform", "source", "allow-prereleases"]
+            .into_iter()
+            .filter(|key| table.contains_key(key))
+            .collect();
+        if !stray.is_empty() {
+            warn!(
+                "{name}: ignoring Poetry-specific settings {}",
+                stray.join(", ")
+            );
+        }
+        let version = table.get("version").and_then(|v| v.as_str());
+        version.unwrap_or("*").to_owned()
+    } else if spec.is_array() {
+        bail!("multiple-constraint dependencies can't be imported; add it by hand");
+    } else {
+        bail!("expected a version or a table");
+    };
+
+    let mut req = name.to_owned();
+    if !extras.is_empty() {
+        req.push_str(&format!("[{}]", extras.join(", ")));
+    }
+    req.push(' ');
+    req.push_str(&poetry_specifiers(&constraint)?);
+    match markers.as_slice() {
+        [] => (),
+        [marker] => req.push_str(&format!("; {marker}")),
+        _ => {
+            let markers: Vec<String> =
+                markers.iter().map(|m| format!("({m})")).collect();
+            req.push_str(&format!("; {}", markers.join(" and ")));
+        }
+    }
+    Ok(Some(req.as_str().try_into()?))
+}
+
+impl PoetryProject {
+    /// Reads `[tool.poetry]` out of a pyproject.toml.
+    pub fn parse(pyproject: &str) -> Result<PoetryProject> {
+        let d = pyproject.parse::<Document>()?;
+        let Some(poetry) = d.get("tool").and_then(|tool| tool.get("poetry")) else {
+            bail!("pyproject.toml doesn't have a [tool.poetry] table");
+        };
+        let mut project = PoetryProject::default();
+
+        let mut tables: Vec<(Option<String>, &Item)> = Vec::new();
+        if let Some(deps) = poetry.get("dependencies") {
+            tables.push((None, deps));
+        }
+        if let Some(deps) = poetry.get("dev-dependencies") {
+            tables.push((Some("dev".into()), deps));
+        }
+        if let Some(groups) = poetry.get("group").and_then(|g| g.as_table_like()) {
+            for (group, table) in groups.iter() {
+                if let Some(deps) = table.get("dependencies") {
+                    tables.push((Some(group.into()), deps));
+                }
+            }
+        }
+
+        for (group, deps) in tables {
+            let Some(deps) = deps.as_table_like() else {
+                bail!("expected a table of dependencies");
+            };
+            let mut requirements = Vec::new();
+            for (name, spec) in deps.iter() {
+                if name == "python" {
+                    if group.is_none() {
+                        let constraint = spec.as_str().ok_or_else(|| {
+                            eyre!("expected python = \"<constraint>\"")
+                        })?;
+                        let specifiers = poetry_specifiers(constraint).wrap_err(
+                            "can't translate the project's python requirement",
+                        )?;
+                        if !specifiers.is_empty() {
+                            project.python = Some(
+                                format!("cpython_unofficial {specifiers}")
+                                    .as_str()
+                                    .try_into()?,
+                            );
+                        }
+                    }
+                    continue;
+                }
+                match poetry_requirement(name, spec) {
+                    Ok(Some(req)) => requirements.push(req),
+                    Ok(None) => project.skipped.push(format!(
+                        "{name}: optional dependencies (for extras) aren't imported"
+                    )),
+                    Err(err) => project.skipped.push(format!("{name}: {err}")),
+                }
+            }
+            match group {
+                None => project.requirements.extend(requirements),
+                Some(group) => project
+                    .groups
+                    .entry(group)
+                    .or_default()
+                    .extend(requirements),
+            }
+        }
+        Ok(project)
+    }
+
+    /// `pyproject`, with a `[tool.posy]` that asks for the same things: the main
+    /// dependencies for the default environment, plus one environment for each group.
+    /// We leave `[tool.poetry]` alone, in case anyone's still using it.
+    pub fn write_posy_config(&self, pyproject: &str) -> Result<String> {
+        let mut d = pyproject.parse::<Document>()?;
+        if !d.contains_key("tool") {
+            let mut tool = toml_edit::Table::new();
+            tool.set_implicit(true);
+            d.insert("tool", Item::Table(tool));
+        }
+        let tool = d["tool"]
+            .as_table_mut()
+            .ok_or_else(|| eyre!("expected [tool] to be a table"))?;
+        if !tool.contains_key("posy") {
+            let mut posy = toml_edit::Table::new();
+            posy.decor_mut().set_prefix("\n");
+            tool.insert("posy", Item::Table(posy));
+        }
+        let posy = tool["posy"]
+            .as_table_mut()
+            .ok_or_else(|| eyre!("expected [tool.posy] to be a table"))?;
+        for key in ["python", "requirements", "environments"] {
+            if posy.contains_key(key) {
+                bail!("[tool.posy] already has {key}; not overwriting it");
+            }
+        }
+        if let Some(python) = &self.python {
+            posy["python"] = toml_edit::value(python.to_string());
+        }
+        posy["requirements"] = requirements_array(&self.requirements);
+        if !self.groups.is_empty() {
+            let mut environments = toml_edit::Table::new();
+            environments.set_implicit(true);
+            for (group, requirements) in &self.groups {
+                let mut env = toml_edit::Table::new();
+                env.decor_mut().set_prefix("\n");
+                env["requirements"] = requirements_array(requirements);
+                environments.insert(group, Item::Table(env));
+            }
+            posy.insert("environments", Item::Table(environments));
+        }
+        Ok(d.to_string())
+    }
+}
+
+/// A TOML array with one requirement per line.
+fn requirements_array(requirements: &[UserRequirement]) -> Item {
+    toml_edit::value(multiline_array(requirements.iter().map(|r| r.to_string())))
+}
+
+/// Reads a poetry.lock. Every locked package becomes a pin, along with the hashes of
+/// the files Poetry recorded for it (under each package in lock format 2, or in
+/// `[metadata.files]` in format 1).
+///
+/// Packages Poetry got from a git repository, a local directory or file, or some
+/// other url get skipped, since there's no version on an index to pin. Packages from
+/// other indexes are fine, as long as posy is configured to use the same ones.
+pub fn parse_poetry_lock(text: &str) -> Result<Imported> {
+    let d = text.parse::<Document>()?;
+    let mut locked = Vec::new();
+    let mut skipped = Vec::new();
+    let old_style_files = d
+        .get("metadata")
+        .and_then(|m| m.get("files"))
+        .and_then(|f| f.as_table_like());
+    let Some(packages) = d.get("package").and_then(|p| p.as_array_of_tables()) else {
+        return Ok(Imported::default());
+    };
+    for package in packages.iter() {
+        let (Some(name), Some(version)) = (
+            package.get("name").and_then(|n| n.as_str()),
+            package.get("version").and_then(|v| v.as_str()),
+        ) else {
+            bail!("every [[package]] in poetry.lock needs a name and version");
+        };
+        context!("Reading {name} {version} from poetry.lock");
+        let source = package
+            .get("source")
+            .and_then(|s| s.get("type"))
+            .and_then(|t| t.as_str());
+        if let Some(kind @ ("git" | "directory" | "file" | "url")) = source {
+            skipped.push(format!(
+                "{name} {version}: comes from a {kind}, not an index"
+            ));
+            continue;
+        }
+        let files = package
+            .get("files")
+            .and_then(|f| f.as_value())
+            .or_else(|| old_style_files?.get(name)?.as_value());
+        let hashes = match files {
+            Some(files) => file_hashes(files)?,
+            None => Vec::new(),
+        };
+        locked.push((name.to_owned(), version.to_owned(), hashes));
+    }
+    pins_from_locked(locked, skipped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_poetry_specifiers() -> Result<()> {
+        for (poetry, pep440) in [
+            ("^1.2.3", ">= 1.2.3, < 2"),
+            ("^0.2.3", ">= 0.2.3, < 0.3"),
+            ("^0.0.3", ">= 0.0.3, < 0.0.4"),
+            ("^0.0", ">= 0.0, < 0.1"),
+            ("^2.0b1", ">= 2.0b1, < 3"),
+            ("~1.2.3", ">= 1.2.3, < 1.3"),
+            ("~1", ">= 1, < 2"),
+            ("~=1.2", "~= 1.2"),
+            ("1.2.3", "== 1.2.3"),
+            ("1.2.*", "== 1.2.*"),
+            ("*", ""),
+            (">= 1.2, < 2", ">= 1.2, < 2"),
+            (">=1.2 <2,!=1.5", ">= 1.2, < 2, != 1.5"),
+        ] {
+            assert_eq!(poetry_specifiers(poetry)?, pep440, "{poetry}");
+        }
+        assert!(poetry_specifiers("^1.0 || ^2.0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_poetry_project() -> Result<()> {
+        let pyproject = indoc::indoc! {r#"
+            [tool.poetry]
+            name = "example"
+            version = "0.1.0"
+
+            [tool.poetry.dependencies]
+            python = "^3.8"
+            attrs = "^22.1"
+            trio = { version = ">=0.22", python = ">=3.9", extras = ["foo"] }
+            pytest-timeout = { version = "*", optional = true }
+            mylib = { path = "../mylib" }
+
+            [tool.poetry.dev-dependencies]
+            black = "~23.1"
+
+            [tool.poetry.group.docs.dependencies]
+            sphinx = { version = "^6", markers = "sys_platform != 'win32'" }
+        "#};
+        let project = PoetryProject::parse(pyproject)?;
+        assert_eq!(
+            project.python.as_ref().unwrap().to_string(),
+            PythonRequirement::try_from("cpython_unofficial >= 3.8, < 4")?.to_string()
+        );
+        let names = |reqs: &[UserRequirement]| -> Vec<String> {
+            reqs.iter().map(|r| r.name.as_given().to_owned()).collect()
+        };
+        assert_eq!(names(&project.requirements), vec!["attrs", "trio"]);
+        assert_eq!(
+            project.requirements[1].to_string(),
+            UserRequirement::try_from("trio[foo] >= 0.22; python_version >= '3.9'")?
+                .to_string()
+        );
+        assert_eq!(names(&project.groups["dev"]), vec!["black"]);
+        assert_eq!(names(&project.groups["docs"]), vec!["sphinx"]);
+        assert_eq!(project.skipped.len(), 2);
+
+        let written = project.write_posy_config(pyproject)?;
+        assert!(written.starts_with(pyproject));
+        let config = crate::project::ProjectConfig::parse_from(&written)?;
+        assert_eq!(config.requirements.len(), 2);
+        assert_eq!(config.brief("dev")?.requirements.len(), 3);
+        assert_eq!(config.brief("docs")?.requirements.len(), 3);
+        // once is enough
+        assert!(project.write_posy_config(&written).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_poetry_lock() -> Result<()> {
+        let hash = |c: &str| format!("sha256:{}", c.repeat(64));
+        let lock = indoc::formatdoc! {r#"
+            [[package]]
+            name = "attrs"
+            version = "22.2.0"
+            optional = false
+            python-versions = ">=3.6"
+            files = [
+                {{file = "attrs-22.2.0-py3-none-any.whl", hash = "{a}"}},
+                {{file = "attrs-22.2.0.tar.gz", hash = "{b}"}},
+            ]
+
+            [[package]]
+            name = "mylib"
+            version = "0.1.0"
+            files = []
+
+            [package.source]
+            type = "directory"
+            url = "../mylib"
+
+            [[package]]
+            name = "trio"
+            version = "0.23.0rc1"
+
+            [metadata]
+            lock-version = "2.0"
+        "#, a = hash("a"), b = hash("b")};
+        let imported = parse_poetry_lock(&lock)?;
+        let pins: Vec<String> = imported.pins.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pins,
+            vec![
+                UserRequirement::try_from("attrs == 22.2.0")?.to_string(),
+                UserRequirement::try_from("trio == 0.23.0rc1")?.to_string(),
+            ]
+        );
+        assert!(imported.requirements.is_empty());
+        assert_eq!(imported.skipped.len(), 1);
+        let attrs: PackageName = "attrs".try_into()?;
+        assert_eq!(imported.hashes[&attrs].len(), 2);
+        assert_eq!(imported.hashes.len(), 1);
+
+        // lock format 1 keeps the hashes off to the side
+        let old = indoc::formatdoc! {r#"
+            [[package]]
+            name = "attrs"
+            version = "22.2.0"
+
+            [metadata]
+            lock-version = "1.1"
+
+            [metadata.files]
+            attrs = [
+                {{file = "attrs-22.2.0-py3-none-any.whl", hash = "{a}"}},
+            ]
+        "#, a = hash("a")};
+        let imported = parse_poetry_lock(&old)?;
+        assert_eq!(
+            imported.hashes[&attrs],
+            vec![ArtifactHash::from_hex("sha256", &"a".repeat(64))?]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::prelude::*;
+use toml_edit::Document;
+
+use super::{file_hashes, parse_colon_hash, pins_from_locked, Imported};
+
+/// Reads a uv.lock. Every package uv got from an index becomes a pin, with the hashes
+/// of its sdist and wheels.
+///
+/// uv locks the project itself and any workspace members too (as `editable` or
+/// `virtual` sources); posy installs those from the source tree anyway, so they get
+/// skipped without comment. Packages from git, a url, or a path outside the project
+/// get skipped with a note.
+pub fn parse_uv_lock(text: &str) -> Result<Imported> {
+    let d = text.parse::<Document>()?;
+    if let Some(version) = d.get("version").and_then(|v| v.as_integer()) {
+        if version != 1 {
+            warn!("uv.lock says it's version {version}; we only know version 1");
+        }
+    }
+    let mut locked = Vec::new();
+    let mut skipped = Vec::new();
+    let Some(packages) = d.get("package").and_then(|p| p.as_array_of_tables()) else {
+        return Ok(Imported::default());
+    };
+    for package in packages.iter() {
+        let Some(name) = package.get("name").and_then(|n| n.as_str()) else {
+            bail!("every [[package]] in uv.lock needs a name");
+        };
+        let source = package.get("source").and_then(|s| s.as_table_like());
+        let kind = source.and_then(|s| s.iter().next()).map(|(kind, _)| kind);
+        match kind {
+            Some("registry") => (),
+            Some("editable" | "virtual") => continue,
+            Some(kind) => {
+                skipped.push(format!("{name}: comes from a {kind}, not an index"));
+                continue;
+            }
+            None => bail!("{name} in uv.lock has no source"),
+        }
+        let Some(version) = package.get("version").and_then(|v| v.as_str()) else {
+            bail!("{name} in uv.lock has no version");
+        };
+        context!("Reading {name} {version} from uv.lock");
+        let mut hashes = Vec::new();
+        let sdist = package.get("sdist").and_then(|s| s.get("hash"));
+        if let Some(hash) = sdist.and_then(|h| h.as_str()) {
+            hashes.push(parse_colon_hash(hash)?);
+        }
+        if let Some(wheels) = package.get("wheels").and_then(|w| w.as_value()) {
+            hashes.extend(file_hashes(wheels)?);
+        }
+        locked.push((name.to_owned(), version.to_owned(), hashes));
+    }
+    pins_from_locked(locked, skipped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_uv_lock() -> Result<()> {
+        let hash = |c: &str| format!("sha256:{}", c.repeat(64));
+        let lock = indoc::formatdoc! {r#"
+            version = 1
+            requires-python = ">=3.8"
+
+            [[package]]
+            name = "attrs"
+            version = "22.2.0"
+            source = {{ registry = "https://pypi.org/simple" }}
+            sdist = {{ url = "https://example.com/attrs.tar.gz", hash = "{a}" }}
+            wheels = [
+                {{ url = "https://example.com/attrs.whl", hash = "{b}" }},
+            ]
+
+            [[package]]
+            name = "example"
+            version = "0.1.0"
+            source = {{ editable = "." }}
+            dependencies = [
+                {{ name = "attrs" }},
+                {{ name = "numpy" }},
+            ]
+
+            [[package]]
+            name = "numpy"
+            version = "1.24.4"
+            source = {{ registry = "https://pypi.org/simple" }}
+
+            [[package]]
+            name = "numpy"
+            version = "2.1.0"
+            source = {{ registry = "https://pypi.org/simple" }}
+
+            [[package]]
+            name = "vendored"
+            version = "1.0"
+            source = {{ git = "https://github.com/example/vendored" }}
+        "#, a = hash("a"), b = hash("b")};
+        let imported = parse_uv_lock(&lock)?;
+        let pins: Vec<String> = imported.pins.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pins,
+            vec![UserRequirement::try_from("attrs == 22.2.0")?.to_string()]
+        );
+        let attrs: PackageName = "attrs".try_into()?;
+        assert_eq!(imported.hashes[&attrs].len(), 2);
+        // numpy is locked twice, vendored comes from git
+        assert_eq!(imported.skipped.len(), 2);
+        let numpy: PackageName = "numpy".try_into()?;
+        assert!(!imported.hashes.contains_key(&numpy));
+        Ok(())
+    }
+}
@@ -353,6 +353,11 @@ impl KVDirStore {
         })
     }
 
+    /// Whether `key` has an entry. Doesn't take the lock, so it's only a hint.
+    pub fn contains<K: PathKey>(&self, key: &K) -> bool {
+        self.base.join(key.key()).exists()
+    }
+
     pub fn get_or_set<K, F>(&self, key: &K, f: F) -> Result<PathBuf>
     where
         K: PathKey,
@@ -366,6 +371,60 @@ impl KVDirStore {
         }
         Ok(lock.path)
     }
+
+    /// The paths of all the entries whose directories contain `marker`. This doesn't
+    /// take any locks, so it's only good for things like listing out what's in the
+    /// store.
+    pub fn find_entries(&self, marker: &Path) -> Result<Vec<PathBuf>> {
+        fn walk(dir: &Path, marker: &Path, depth: usize, out: &mut Vec<PathBuf>) {
+            let Ok(entries) = fs::read_dir(dir) else {
+                return;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                if path.join(marker).exists() {
+                    out.push(path);
+                } else if depth > 0 {
+                    walk(&path, marker, depth - 1, out);
+                }
+            }
+        }
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&self.base)? {
+            let path = entry?.path();
+            if path != self.tmp && path.is_dir() {
+                // ArtifactHash keys are the deepest: mode, then the nested suffix
+                walk(&path, marker, DIR_NEST_DEPTH, &mut found);
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Deletes the entry for `key`, if there is one.
+    pub fn remove<K: PathKey>(&self, key: &K) -> Result<()> {
+        self.remove_entry(&self.base.join(key.key()))
+    }
+
+    /// Deletes an entry that was found by `find_entries`. Anyone who wants it back will
+    /// have to recreate it.
+    pub fn remove_entry(&self, path: &Path) -> Result<()> {
+        if !path.starts_with(&self.base) || path.starts_with(&self.tmp) {
+            bail!("{} isn't in this store", path.display());
+        }
+        let _lock = lock(path, LockMode::Lock)?;
+        if path.exists() {
+            // Move it out of the way first, so nobody sees a half-deleted directory
+            let tmp = tempfile::tempdir_in(&self.tmp)?;
+            let doomed = tmp.path().join("doomed");
+            fs::rename(path, &doomed)?;
+            fs::remove_dir_all(&doomed)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct KVDirLock {
@@ -0,0 +1,117 @@
+//! posy's engine, for other Rust tools that want to resolve and install Python
+//! environments without shelling out to the `posy` binary. The binary is just a CLI
+//! on top of this.
+//!
+//! The moving parts are:
+//!
+//! - `Brief`: what the user asked for (a Python, some requirements, some
+//!   constraints). `Brief::resolve` turns it into a...
+//! - `Blueprint`: exact pins for the Python and every package, with hashes, for one
+//!   or more platforms. This is what goes in lock files.
+//! - `PackageDB`: where packages come from. It wraps the index, the on-disk caches,
+//!   and sdist building, and carries settings like the policy and `require_hashes`.
+//!   `PackageDB::in_memory` gives you one that gets everything from a
+//!   `PackageSource` instead of the network -- a `MemoryIndex`, or your own.
+//! - `PybiPlatform` / `WheelPlatform`: which platforms a Python or wheel can run on.
+//!   `PybiPlatform::native_platforms` is the machine we're on.
+//! - `EnvForest`: the installer. `EnvForest::get_env` unpacks a Blueprint and
+//!   returns an `Env`, which says how to run things in it.
+//!
+//! So, roughly:
+//!
+//!   let db = PackageDB::new(&[index_url], &cache_dir, &build_forest, &build_store)?;
+//!   let platforms = PybiPlatform::native_platforms()?;
+//!   let blueprint = brief.resolve(&db, platforms, None, &[])?;
+//!   let env = forest.get_env(&db, &blueprint, platforms, &[])?;
+//!
+//! For looking inside a wheel or pybi without downloading all of it, `RemoteZip` reads
+//! single members of a zip from anything that can seek, within `UnpackLimits`.
+//!
+//! Nothing in here prints to the terminal or reads the command line. The entry points
+//! above return `Error`, whose `kind()` tells resolution failures, network trouble,
+//! bad metadata, and install problems apart; the plumbing underneath uses
+//! `eyre::Report`. Everything else goes through `tracing`: log messages under the
+//! `posy_core` target, plus the spans and events from `context!`, `timing!`, and
+//! `progress!` (see the `timings` and `progress` modules for layers that consume
+//! them). Install whatever subscriber you like.
+//!
+//! The command line itself is in here too, behind the `cli` feature, so that it can
+//! get at everything else; with default features off you get just the engine.
+
+#![allow(
+    clippy::declare_interior_mutable_const,
+    clippy::borrow_interior_mutable_const,
+    clippy::module_inception,
+    clippy::result_large_err,
+    clippy::type_complexity,
+    clippy::upper_case_acronyms,
+    clippy::wrong_self_convention
+)]
+pub(crate) mod check_pybi;
+pub(crate) mod env;
+pub(crate) mod error;
+pub(crate) mod kvstore;
+pub(crate) mod package_db;
+pub(crate) mod platform_tags;
+pub(crate) mod policy;
+pub(crate) mod prelude;
+pub mod progress;
+pub(crate) mod remote_zip;
+pub(crate) mod resolve;
+pub(crate) mod seek_slice;
+pub mod timings;
+pub(crate) mod trampolines;
+pub(crate) mod tree;
+pub(crate) mod util;
+pub(crate) mod vocab;
+pub(crate) mod zip_index;
+
+#[cfg(feature = "cli")]
+mod build_pybi;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
+#[cfg(feature = "cli")]
+mod commands;
+#[cfg(feature = "cli")]
+mod config;
+#[cfg(feature = "cli")]
+mod exporters;
+#[cfg(feature = "cli")]
+mod importers;
+#[cfg(feature = "cli")]
+mod lockfile;
+#[cfg(feature = "cli")]
+mod manifest;
+#[cfg(feature = "cli")]
+mod osv;
+#[cfg(feature = "cli")]
+mod output;
+#[cfg(feature = "cli")]
+mod pip_config;
+#[cfg(feature = "cli")]
+mod project;
+#[cfg(feature = "cli")]
+mod reproducible_zip;
+#[cfg(feature = "cli")]
+mod telemetry;
+#[cfg(feature = "cli")]
+mod wheel_writer;
+
+#[cfg(test)]
+mod test_util;
+
+pub use env::{Env, EnvForest, InstalledPackage};
+pub use error::{
+    Error, ErrorKind, InstallError, MetadataError, NetworkError, ResolveError,
+};
+pub use kvstore::KVDirStore;
+pub use package_db::{ArtifactInfo, MemoryIndex, PackageDB, PackageSource};
+pub use platform_tags::{PybiPlatform, WheelPlatform};
+pub use remote_zip::RemoteZip;
+pub use resolve::{AllowPre, Blueprint, Brief, PinnedPackage, RequirementSource};
+pub use tree::UnpackLimits;
+pub use vocab::{
+    ArtifactHash, ArtifactName, PackageName, PythonRequirement, UserRequirement,
+    Version,
+};
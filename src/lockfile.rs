@@ -0,0 +1,414 @@
+use crate::package_db::PackageDB;
+use crate::policy::parse_timestamp;
+use crate::prelude::*;
+use crate::resolve::{Blueprint, Brief, RequirementSource};
+use console::Style;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const LOCKFILE_NAME: &str = "posy.lock";
+
+/// posy.lock: the Blueprint we picked for each of a project's environments, so that
+/// everyone working on the project gets the same versions of everything, and so we
+/// don't have to talk to the index every time we run something.
+///
+/// Each Blueprint is stored along with the Brief it was resolved from. If the Brief
+/// we'd resolve today is different -- e.g. because someone edited the requirements --
+/// then the Blueprint is out of date.
+///
+/// It's JSON, because it's for computers; people should edit pyproject.toml instead.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub environments: BTreeMap<String, LockedEnv>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedEnv {
+    pub brief: Brief,
+    pub blueprint: Blueprint,
+    /// What the index looked like when we resolved. Missing for environments that
+    /// were locked before we kept track.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<IndexSnapshot>,
+}
+
+/// Which state of the index a Blueprint was resolved against, so that `posy lock
+/// --snapshot` can resolve it again against the same one and get the same answer,
+/// however much has been uploaded since.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IndexSnapshot {
+    /// When we resolved, as an RFC 3339 timestamp
+    pub time: String,
+    /// Each pinned package's last serial on the index, for indexes that have them
+    /// (see `PackageDB::last_serial`). Re-resolving against the snapshot checks these
+    /// for packages with files the index doesn't date, since that's the only way to
+    /// tell whether any of them are new.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub serials: BTreeMap<PackageName, u64>,
+}
+
+impl IndexSnapshot {
+    /// The index as it is right now, as far as `blueprint` is concerned. (Or as it
+    /// was, if `db` is pinned to an older snapshot.)
+    pub fn take(db: &PackageDB, blueprint: &Blueprint) -> IndexSnapshot {
+        let time = db.snapshot().unwrap_or_else(SystemTime::now);
+        let serials = std::iter::once(&blueprint.pybi)
+            .chain(blueprint.wheels.iter().map(|(pin, _)| pin))
+            .filter_map(|pin| Some((pin.name.clone(), db.last_serial(&pin.name)?)))
+            .collect();
+        IndexSnapshot {
+            time: humantime::format_rfc3339_seconds(time).to_string(),
+            serials,
+        }
+    }
+
+    pub fn time(&self) -> Result<SystemTime> {
+        parse_timestamp(&self.time)
+            .ok_or_else(|| eyre!("can't parse snapshot time {:?}", self.time))
+    }
+
+    /// The packages that have changed on the index since, with their serials then
+    /// and now.
+    pub fn changed_since<'a>(
+        &'a self,
+        db: &PackageDB,
+    ) -> Vec<(&'a PackageName, u64, u64)> {
+        self.serials
+            .iter()
+            .filter_map(|(name, &then)| match db.last_serial(name) {
+                Some(now) if now != then => Some((name, then, now)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl LockedEnv {
+    /// Whether this Blueprint was resolved from `brief`.
+    pub fn is_fresh(&self, brief: &Brief) -> Result<bool> {
+        Ok(serde_json::to_value(&self.brief)? == serde_json::to_value(brief)?)
+    }
+
+    /// Every local path this environment came from, both in the Brief and in the pins
+    /// it led to.
+    fn source_paths(&mut self) -> Vec<&mut PathBuf> {
+        let pins = std::iter::once(&mut self.blueprint.pybi)
+            .chain(self.blueprint.wheels.iter_mut().map(|(pin, _)| pin))
+            .filter_map(|pin| pin.source.as_mut());
+        self.brief
+            .sources
+            .values_mut()
+            .chain(pins)
+            .filter_map(|source| match source {
+                RequirementSource::Path { path } => Some(path),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// How one package's pin changed between two Blueprints.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum PackageChange {
+    Added {
+        name: String,
+        version: Version,
+    },
+    Removed {
+        name: String,
+        version: Version,
+    },
+    Upgraded {
+        name: String,
+        old: Version,
+        new: Version,
+    },
+    Downgraded {
+        name: String,
+        old: Version,
+        new: Version,
+    },
+}
+
+impl PackageChange {
+    pub fn name(&self) -> &str {
+        match self {
+            PackageChange::Added { name, .. }
+            | PackageChange::Removed { name, .. }
+            | PackageChange::Upgraded { name, .. }
+            | PackageChange::Downgraded { name, .. } => name,
+        }
+    }
+
+    /// One line, colored for the terminal: green for new packages, red for removed
+    /// ones, yellow for version changes.
+    pub fn render(&self) -> String {
+        let green = Style::new().green().for_stderr();
+        let red = Style::new().red().for_stderr();
+        let yellow = Style::new().yellow().for_stderr();
+        match self {
+            PackageChange::Added { name, version } => {
+                format!("{} {name} {version}", green.apply_to("+"))
+            }
+            PackageChange::Removed { name, version } => {
+                format!("{} {name} {version}", red.apply_to("-"))
+            }
+            PackageChange::Upgraded { name, old, new } => format!(
+                "{} {name} {old} -> {}",
+                yellow.apply_to("↑"),
+                green.apply_to(new)
+            ),
+            PackageChange::Downgraded { name, old, new } => format!(
+                "{} {name} {old} -> {}",
+                yellow.apply_to("↓"),
+                red.apply_to(new)
+            ),
+        }
+    }
+}
+
+/// What changed going from `old` to `new`, sorted by package name. We only care about
+/// versions: if a pin gained or lost some hashes, that's not worth mentioning.
+pub fn blueprint_diff(old: Option<&Blueprint>, new: &Blueprint) -> Vec<PackageChange> {
+    let pins = |bp: &Blueprint| -> BTreeMap<PackageName, Version> {
+        std::iter::once(&bp.pybi)
+            .chain(bp.wheels.iter().map(|(pin, _)| pin))
+            .map(|pin| (pin.name.clone(), pin.version.clone()))
+            .collect()
+    };
+    let old = old.map(pins).unwrap_or_default();
+    let new = pins(new);
+    let mut changes = Vec::new();
+    for (name, new_version) in &new {
+        let name_str = name.as_given().to_string();
+        match old.get(name) {
+            None => changes.push(PackageChange::Added {
+                name: name_str,
+                version: new_version.clone(),
+            }),
+            Some(old_version) if old_version < new_version => {
+                changes.push(PackageChange::Upgraded {
+                    name: name_str,
+                    old: old_version.clone(),
+                    new: new_version.clone(),
+                })
+            }
+            Some(old_version) if old_version > new_version => {
+                changes.push(PackageChange::Downgraded {
+                    name: name_str,
+                    old: old_version.clone(),
+                    new: new_version.clone(),
+                })
+            }
+            Some(_) => (),
+        }
+    }
+    for (name, old_version) in &old {
+        if !new.contains_key(name) {
+            changes.push(PackageChange::Removed {
+                name: name.as_given().to_string(),
+                version: old_version.clone(),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.name().cmp(b.name()));
+    changes
+}
+
+impl Lockfile {
+    /// A missing lockfile is the same as an empty one.
+    ///
+    /// Path sources are written relative to the directory the lockfile is in, so that
+    /// the lockfile means the same thing in every checkout of the project; we put the
+    /// directory back on here, so they match the project's own config again.
+    pub fn load(path: &Path) -> Result<Lockfile> {
+        context!("Reading {}", path.display());
+        let mut lockfile: Lockfile = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Default::default())
+            }
+            Err(e) => Err(e)?,
+        };
+        let root = path.parent().unwrap_or(Path::new(""));
+        for locked in lockfile.environments.values_mut() {
+            for source_path in locked.source_paths() {
+                if source_path.is_relative() {
+                    *source_path = root.join(&source_path);
+                }
+            }
+        }
+        Ok(lockfile)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        context!("Writing {}", path.display());
+        let root = path.parent().unwrap_or(Path::new(""));
+        let mut relative = Lockfile {
+            environments: self.environments.clone(),
+        };
+        for locked in relative.environments.values_mut() {
+            for source_path in locked.source_paths() {
+                if let Ok(rest) = source_path.strip_prefix(root) {
+                    *source_path = rest.into();
+                }
+            }
+        }
+        let mut contents = serde_json::to_string_pretty(&relative)?;
+        contents.push('\n');
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{
+        PinnedPackage, WheelResolveMetadata, WheelResolveMetadataInner,
+    };
+
+    #[test]
+    fn test_lockfile_roundtrip() -> Result<()> {
+        let mut brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["trio".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+        let tmp = tempfile::tempdir()?;
+        let local = RequirementSource::Path {
+            path: tmp.path().join("../vendor/trio"),
+        };
+        brief.sources.insert("trio".try_into()?, local.clone());
+        let blueprint = Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython_unofficial".try_into()?,
+                version: "3.11.1".try_into()?,
+                hashes: Vec::new(),
+                url: None,
+                source: None,
+            },
+            wheels: Vec::new(),
+            requested: Vec::new(),
+            marker_expressions: HashMap::new(),
+        };
+        let mut lockfile = Lockfile::default();
+        lockfile.environments.insert(
+            "default".into(),
+            LockedEnv {
+                brief: brief.clone(),
+                blueprint,
+                snapshot: Some(IndexSnapshot {
+                    time: "2023-01-23T04:56:07Z".into(),
+                    serials: [("trio".try_into()?, 16489937)].into(),
+                }),
+            },
+        );
+
+        let path = tmp.path().join(LOCKFILE_NAME);
+        assert!(Lockfile::load(&path)?.environments.is_empty());
+        lockfile.save(&path)?;
+        // Nothing about where this checkout happens to live goes in the file
+        let written = fs::read_to_string(&path)?;
+        assert!(!written.contains(tmp.path().to_str().unwrap()));
+        assert!(written.contains("../vendor/trio"));
+        let loaded = Lockfile::load(&path)?;
+        let locked = &loaded.environments["default"];
+        assert_eq!(locked.brief.sources.values().next(), Some(&local));
+        assert!(locked.is_fresh(&brief)?);
+        let snapshot = locked.snapshot.as_ref().unwrap();
+        assert_eq!(snapshot.serials.len(), 1);
+        assert_eq!(
+            snapshot
+                .time()?
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            1674449767
+        );
+
+        let mut changed = brief;
+        changed.requirements.push("attrs".try_into()?);
+        assert!(!locked.is_fresh(&changed)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blueprint_diff() -> Result<()> {
+        let pin = |name: &str, version: &str| -> Result<PinnedPackage> {
+            Ok(PinnedPackage {
+                name: name.try_into()?,
+                version: version.try_into()?,
+                hashes: Vec::new(),
+                url: None,
+                source: None,
+            })
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: String::new(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: Vec::new(),
+                requires_python: Default::default(),
+                extras: HashSet::new(),
+            },
+        };
+        let blueprint = |pybi, wheels: Vec<PinnedPackage>| Blueprint {
+            pybi,
+            wheels: wheels
+                .into_iter()
+                .map(|pin| (pin, metadata.clone()))
+                .collect(),
+            requested: Vec::new(),
+            marker_expressions: HashMap::new(),
+        };
+        let old = blueprint(
+            pin("cpython_unofficial", "3.11.1")?,
+            vec![
+                pin("trio", "0.22.0")?,
+                pin("attrs", "22.2.0")?,
+                pin("idna", "3.4")?,
+            ],
+        );
+        let new = blueprint(
+            pin("cpython_unofficial", "3.11.1")?,
+            vec![
+                pin("trio", "0.21.0")?,
+                pin("attrs", "23.1.0")?,
+                pin("sniffio", "1.3")?,
+            ],
+        );
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+        assert_eq!(
+            blueprint_diff(Some(&old), &new),
+            vec![
+                PackageChange::Upgraded {
+                    name: "attrs".into(),
+                    old: v("22.2.0"),
+                    new: v("23.1.0"),
+                },
+                PackageChange::Removed {
+                    name: "idna".into(),
+                    version: v("3.4"),
+                },
+                PackageChange::Added {
+                    name: "sniffio".into(),
+                    version: v("1.3"),
+                },
+                PackageChange::Downgraded {
+                    name: "trio".into(),
+                    old: v("0.22.0"),
+                    new: v("0.21.0"),
+                },
+            ]
+        );
+        assert!(blueprint_diff(Some(&old), &old).is_empty());
+        assert_eq!(blueprint_diff(None, &old).len(), 4);
+        Ok(())
+    }
+}
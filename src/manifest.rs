@@ -0,0 +1,204 @@
+//! Install manifests: a record of every file an environment is made of, which package
+//! it belongs to, and which artifact that package was installed from. It's what an
+//! auditor needs to check a deployed machine against its lock, without having to trust
+//! posy's caches.
+//!
+//! The JSON is byte-for-byte deterministic for a given set of files, so it can be
+//! signed and verified with any detached-signature tool (`gpg --detach-sign`, `cosign
+//! sign-blob`, ...).
+
+use crate::env::InstalledPackage;
+use crate::prelude::*;
+use ring::digest;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstallManifest {
+    /// Python first, then everything else in install order
+    pub packages: Vec<ManifestPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestPackage {
+    pub name: String,
+    pub version: Version,
+    /// The artifact's filename, or for local packages, the source tree
+    pub source: String,
+    pub source_hash: ArtifactHash,
+    /// Where the files are, on this machine
+    pub root: String,
+    /// Sorted by path
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestFile {
+    /// Relative to the package's root, with / separators
+    pub path: String,
+    /// Regular files have a hash...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<ArtifactHash>,
+    /// ...and symlinks have a target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub executable: bool,
+}
+
+/// Python writes bytecode caches into these as it goes, so including them would make
+/// the manifest change every time something gets imported for the first time.
+const SKIPPED_DIRS: &[&str] = &["__pycache__"];
+
+fn sha256(path: &Path) -> Result<ArtifactHash> {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    let mut f = fs::File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match f.read(&mut buf)? {
+            0 => break,
+            n => ctx.update(&buf[..n]),
+        }
+    }
+    Ok(ArtifactHash {
+        mode: "sha256".into(),
+        raw_data: ctx.finish().as_ref().to_vec(),
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &fs::Metadata) -> bool {
+    false
+}
+
+fn scan(root: &Path) -> Result<Vec<ManifestFile>> {
+    context!("Scanning {}", root.display());
+    let mut files = Vec::new();
+    let mut todo = vec![(String::new(), root.to_owned())];
+    while let Some((prefix, dir)) = todo.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                eyre!("non-unicode filename {:?} in {}", name, dir.display())
+            })?;
+            let path = format!("{prefix}{name}");
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_str()) {
+                    todo.push((format!("{path}/"), entry.path()));
+                }
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                files.push(ManifestFile {
+                    path,
+                    hash: None,
+                    symlink: Some(target.to_string_lossy().replace('\\', "/")),
+                    executable: false,
+                });
+            } else {
+                files.push(ManifestFile {
+                    hash: Some(sha256(&entry.path())?),
+                    symlink: None,
+                    executable: is_executable(&entry.metadata()?),
+                    path,
+                });
+            }
+        }
+    }
+    files.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+impl InstallManifest {
+    /// Hashes everything that's installed for `packages` (see `Env::packages`).
+    pub fn new(packages: &[InstalledPackage]) -> Result<InstallManifest> {
+        Ok(InstallManifest {
+            packages: packages
+                .iter()
+                .map(|package| {
+                    Ok(ManifestPackage {
+                        name: package.name.as_given().to_owned(),
+                        version: package.version.clone(),
+                        source: package.source.clone(),
+                        source_hash: package.source_hash.clone(),
+                        root: package.root.display().to_string(),
+                        files: scan(&package.root)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_install_manifest() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("foo");
+        fs::create_dir_all(root.join("lib/foo/__pycache__"))?;
+        fs::create_dir_all(root.join("bin"))?;
+        fs::write(root.join("lib/foo/__init__.py"), "")?;
+        fs::write(root.join("lib/foo/__pycache__/__init__.pyc"), "junk")?;
+        fs::write(root.join("bin/foo"), "#!python\n")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(
+                root.join("bin/foo"),
+                fs::Permissions::from_mode(0o755),
+            )?;
+            std::os::unix::fs::symlink("foo", root.join("bin/foo-alias"))?;
+        }
+        let package = InstalledPackage {
+            name: "Foo".try_into()?,
+            version: "1.0".try_into()?,
+            source: "foo-1.0-py3-none-any.whl".into(),
+            source_hash: ArtifactHash::from_hex("sha256", &"ab".repeat(32))?,
+            root: root.clone(),
+        };
+        let manifest = InstallManifest::new(&[package.clone()])?;
+        let files = &manifest.packages[0].files;
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(
+            paths,
+            vec!["bin/foo", "bin/foo-alias", "lib/foo/__init__.py"]
+        );
+        #[cfg(unix)]
+        {
+            assert!(files[0].executable);
+            assert_eq!(files[1].symlink.as_deref(), Some("foo"));
+            assert!(!files[2].executable);
+        }
+        #[cfg(not(unix))]
+        assert_eq!(paths, vec!["bin/foo", "lib/foo/__init__.py"]);
+        assert_eq!(
+            files.last().unwrap().hash.as_ref().unwrap().to_string(),
+            // sha256 of the empty string
+            "sha256=e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        // same files, same bytes
+        let json = manifest.to_json()?;
+        assert_eq!(InstallManifest::new(&[package])?.to_json()?, json);
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+//! Looking up known vulnerabilities in the OSV database (https://osv.dev), which
+//! includes the PyPA advisory database.
+
+use crate::package_db::HostAllowlist;
+use crate::prelude::*;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+const OSV_API: &str = "https://api.osv.dev/v1";
+const ECOSYSTEM: &str = "PyPI";
+
+/// A known vulnerability that affects some package version we've got.
+#[derive(Debug, Clone, Serialize)]
+pub struct Vulnerability {
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+    /// Whatever the advisory gives us: e.g. "HIGH", or a CVSS vector
+    pub severity: Option<String>,
+    /// Versions that don't have the problem anymore, oldest first
+    pub fixed_in: Vec<Version>,
+}
+
+// The parts of the OSV schema that we use:
+//   https://ossf.github.io/osv-schema/
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OsvRecord {
+    id: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    summary: Option<String>,
+    #[serde(default)]
+    severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    affected: Vec<OsvAffected>,
+    database_specific: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OsvSeverity {
+    score: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OsvAffected {
+    package: Option<OsvPackage>,
+    #[serde(default)]
+    ranges: Vec<OsvRange>,
+    #[serde(default)]
+    versions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OsvPackage {
+    ecosystem: String,
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OsvRange {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    events: Vec<OsvEvent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OsvEvent {
+    Introduced(String),
+    Fixed(String),
+    LastAffected(String),
+    Limit(String),
+}
+
+impl OsvAffected {
+    fn is_for(&self, name: &PackageName) -> bool {
+        match &self.package {
+            Some(package) => {
+                package.ecosystem == ECOSYSTEM
+                    && PackageName::try_from(package.name.as_str())
+                        .map_or(false, |n| &n == name)
+            }
+            None => false,
+        }
+    }
+
+    fn affects(&self, version: &Version) -> bool {
+        let listed = self
+            .versions
+            .iter()
+            .any(|v| Version::try_from(v.as_str()).map_or(false, |v| &v == version));
+        listed
+            || self
+                .ranges
+                .iter()
+                .filter(|r| r.kind == "ECOSYSTEM")
+                .any(|r| r.affects(version))
+    }
+}
+
+impl OsvRange {
+    /// Walks the events in version order, tracking whether `version` is in an
+    /// affected stretch.
+    fn affects(&self, version: &Version) -> bool {
+        let mut events: Vec<(Version, &OsvEvent)> = self
+            .events
+            .iter()
+            .filter_map(|event| {
+                let v = match event {
+                    OsvEvent::Introduced(v)
+                    | OsvEvent::Fixed(v)
+                    | OsvEvent::LastAffected(v)
+                    | OsvEvent::Limit(v) => v,
+                };
+                Version::try_from(v.as_str()).ok().map(|v| (v, event))
+            })
+            .collect();
+        events.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut affected = false;
+        for (v, event) in events {
+            match event {
+                OsvEvent::Introduced(_) if version >= &v => affected = true,
+                OsvEvent::Fixed(_) if version >= &v => affected = false,
+                OsvEvent::LastAffected(_) if version > &v => affected = false,
+                OsvEvent::Limit(_) if version >= &v => affected = false,
+                _ => (),
+            }
+        }
+        affected
+    }
+}
+
+impl OsvRecord {
+    fn affects(&self, name: &PackageName, version: &Version) -> bool {
+        self.affected
+            .iter()
+            .any(|a| a.is_for(name) && a.affects(version))
+    }
+
+    fn for_package(&self, name: &PackageName) -> Vulnerability {
+        let mut fixed_in: Vec<Version> = self
+            .affected
+            .iter()
+            .filter(|a| a.is_for(name))
+            .flat_map(|a| &a.ranges)
+            .flat_map(|r| &r.events)
+            .filter_map(|event| match event {
+                OsvEvent::Fixed(v) => Version::try_from(v.as_str()).ok(),
+                _ => None,
+            })
+            .collect();
+        fixed_in.sort();
+        fixed_in.dedup();
+        // GitHub's advisories have a nice human-readable severity; otherwise fall back
+        // on the CVSS vector.
+        let severity = self
+            .database_specific
+            .as_ref()
+            .and_then(|d| d.get("severity"))
+            .and_then(|s| s.as_str())
+            .map(|s| s.to_string())
+            .or_else(|| self.severity.first().map(|s| s.score.clone()));
+        Vulnerability {
+            id: self.id.clone(),
+            aliases: self.aliases.clone(),
+            summary: self.summary.clone(),
+            severity,
+            fixed_in,
+        }
+    }
+}
+
+/// Where we get advisories from.
+pub enum AdvisoryDb {
+    /// Ask api.osv.dev
+    Online(ureq::Agent),
+    /// A downloaded copy of the database, e.g. from
+    /// https://osv-vulnerabilities.storage.googleapis.com/PyPI/all.zip -- either the
+    /// zip file itself, or a directory of JSON files.
+    Offline(Vec<OsvRecord>),
+}
+
+impl AdvisoryDb {
+    pub fn online(
+        allowlist: Option<&HostAllowlist>,
+        proxy: Option<&str>,
+    ) -> Result<AdvisoryDb> {
+        // everything we fetch is from OSV_API, so checking once is enough
+        if let Some(allowlist) = allowlist {
+            allowlist.check(&Url::parse(OSV_API)?)?;
+        }
+        Ok(AdvisoryDb::Online(crate::package_db::new_ureq_agent(
+            proxy,
+        )?))
+    }
+
+    pub fn offline(path: &Path) -> Result<AdvisoryDb> {
+        context!("Loading advisory database from {}", path.display());
+        let mut records = Vec::new();
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                let path = entry?.path();
+                if path.extension() == Some(OsStr::new("json")) {
+                    records.push(serde_json::from_slice(&fs::read(&path)?)?);
+                }
+            }
+        } else {
+            let mut zip = zip::ZipArchive::new(fs::File::open(path)?)?;
+            for i in 0..zip.len() {
+                let entry = zip.by_index(i)?;
+                if entry.name().ends_with(".json") {
+                    records.push(serde_json::from_reader(entry)?);
+                }
+            }
+        }
+        Ok(AdvisoryDb::Offline(records))
+    }
+
+    /// For each package, the vulnerabilities that affect that version of it.
+    pub fn check(
+        &self,
+        packages: &[(&PackageName, &Version)],
+    ) -> Result<Vec<Vec<Vulnerability>>> {
+        match self {
+            AdvisoryDb::Offline(records) => Ok(packages
+                .iter()
+                .map(|(name, version)| {
+                    records
+                        .iter()
+                        .filter(|r| r.affects(name, version))
+                        .map(|r| r.for_package(name))
+                        .collect()
+                })
+                .collect()),
+            AdvisoryDb::Online(agent) => check_online(agent, packages),
+        }
+    }
+}
+
+fn check_online(
+    agent: &ureq::Agent,
+    packages: &[(&PackageName, &Version)],
+) -> Result<Vec<Vec<Vulnerability>>> {
+    #[derive(Deserialize)]
+    struct BatchResponse {
+        results: Vec<BatchResult>,
+    }
+    #[derive(Deserialize)]
+    struct BatchResult {
+        #[serde(default)]
+        vulns: Vec<BatchVuln>,
+    }
+    #[derive(Deserialize)]
+    struct BatchVuln {
+        id: String,
+    }
+
+    let queries: Vec<serde_json::Value> = packages
+        .iter()
+        .map(|(name, version)| {
+            serde_json::json!({
+                "package": { "name": name.normalized(), "ecosystem": ECOSYSTEM },
+                "version": version.to_string(),
+            })
+        })
+        .collect();
+    // The batch API only tells us which advisories match, so then we have to go back
+    // for the details.
+    let response: BatchResponse = agent
+        .post(&format!("{OSV_API}/querybatch"))
+        .send_json(serde_json::json!({ "queries": queries }))?
+        .into_json()?;
+    let mut records: HashMap<String, OsvRecord> = HashMap::new();
+    let mut results = Vec::new();
+    for ((name, _), result) in packages.iter().zip(response.results) {
+        let mut vulns = Vec::new();
+        for BatchVuln { id } in result.vulns {
+            if !records.contains_key(&id) {
+                let record: OsvRecord = agent
+                    .get(&format!("{OSV_API}/vulns/{id}"))
+                    .call()?
+                    .into_json()?;
+                records.insert(id.clone(), record);
+            }
+            vulns.push(records[&id].for_package(name));
+        }
+        results.push(vulns);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_osv_matching() -> Result<()> {
+        let record: OsvRecord = serde_json::from_str(indoc::indoc! {r#"
+            {
+                "id": "GHSA-h5c8-rqwp-cp95",
+                "aliases": ["CVE-2024-22195"],
+                "summary": "Jinja vulnerable to HTML attribute injection",
+                "database_specific": {"severity": "MODERATE"},
+                "affected": [
+                    {
+                        "package": {"ecosystem": "PyPI", "name": "Jinja2"},
+                        "ranges": [
+                            {
+                                "type": "ECOSYSTEM",
+                                "events": [{"introduced": "0"}, {"fixed": "3.1.3"}]
+                            }
+                        ]
+                    },
+                    {
+                        "package": {"ecosystem": "PyPI", "name": "other"},
+                        "ranges": [
+                            {
+                                "type": "ECOSYSTEM",
+                                "events": [
+                                    {"introduced": "1.0"},
+                                    {"last_affected": "1.2"}
+                                ]
+                            }
+                        ],
+                        "versions": ["0.5"]
+                    }
+                ]
+            }
+        "#})?;
+        let jinja: PackageName = "jinja2".try_into()?;
+        let other: PackageName = "other".try_into()?;
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+
+        assert!(record.affects(&jinja, &v("3.1.2")));
+        assert!(!record.affects(&jinja, &v("3.1.3")));
+        assert!(record.affects(&other, &v("1.2")));
+        assert!(!record.affects(&other, &v("1.2.1")));
+        assert!(!record.affects(&other, &v("0.9")));
+        assert!(record.affects(&other, &v("0.5")));
+
+        let vuln = record.for_package(&jinja);
+        assert_eq!(vuln.severity.as_deref(), Some("MODERATE"));
+        assert_eq!(vuln.fixed_in, vec![v("3.1.3")]);
+        assert_eq!(vuln.aliases, vec!["CVE-2024-22195"]);
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@ use crate::prelude::*;
 use std::fmt::Debug;
 
 use console::{Emoji, Style, StyledObject};
+use once_cell::sync::OnceCell;
 use tracing::{
     field::{Field, Visit},
     metadata::LevelFilter,
@@ -24,6 +25,70 @@ enum ColorChoice {
     Never,
 }
 
+/// How commands report their results on stdout. Log messages and errors always go to
+/// stderr, so they never get mixed up with the results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// For humans
+    Text,
+    /// One JSON document per command, for scripts and editors. Each command documents
+    /// its schema on the type it serializes; fields are only ever added, never
+    /// renamed or removed.
+    Json,
+}
+
+static FORMAT: OnceCell<OutputFormat> = OnceCell::new();
+
+/// Whether commands should print JSON instead of text.
+pub fn json() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Prints a command's result, in `--format json` mode.
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// In `--format json` mode, a failed command prints this on stderr, so that scripts
+/// don't have to scrape our error messages.
+#[derive(Serialize)]
+struct JsonError {
+    error: String,
+    /// What we were in the middle of, outermost first
+    context: Vec<String>,
+    /// The chain of underlying causes, outermost first
+    causes: Vec<String>,
+}
+
+impl JsonError {
+    fn new(err: &eyre::Report) -> JsonError {
+        let context = err
+            .handler()
+            .downcast_ref::<PosyEyreHandler>()
+            .map(|h| h.context.clone())
+            .unwrap_or_default();
+        JsonError {
+            error: err.to_string(),
+            context,
+            causes: err.chain().skip(1).map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+/// The same thing `print_json_error` prints, for sending some other way.
+pub fn json_error(err: &eyre::Report) -> serde_json::Value {
+    serde_json::to_value(JsonError::new(err)).unwrap_or_default()
+}
+
+pub fn print_json_error(err: &eyre::Report) {
+    let report = JsonError::new(err);
+    eprintln!(
+        "{}",
+        serde_json::to_string(&report).unwrap_or_else(|_| report.error.clone())
+    );
+}
+
 #[derive(Args)]
 pub struct OutputArgs {
     /// Increase verbosity. (Can be repeated.)
@@ -34,6 +99,17 @@ pub struct OutputArgs {
     quiet: u8,
     #[arg(long, default_value_t = ColorChoice::Auto, value_enum, value_name = "WHEN", global = true)]
     color: ColorChoice,
+    /// How to print results on stdout.
+    #[arg(long, default_value_t = OutputFormat::Text, value_enum, global = true)]
+    format: OutputFormat,
+    /// When we're done, print how long each phase took (fetching index pages and
+    /// metadata, resolving, downloading, ...) to stderr.
+    #[arg(long, global = true)]
+    timings: bool,
+    /// Stream progress events, as newline-delimited JSON, to this file descriptor (on
+    /// Unix) or file.
+    #[arg(long, value_name = "FD|PATH", global = true)]
+    progress_json: Option<String>,
 }
 
 struct PosyUILayer;
@@ -110,7 +186,7 @@ impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PosyUILayer {
     /// inside the tracing_subscriber registry entry for this Span.
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("span should already exist!");
-        if span.metadata().target() == POSY_CONTEXT_TARGET {
+        if span.metadata().target() == crate::util::POSY_CONTEXT_TARGET {
             attrs.record(&mut WithMessage(&|msg| {
                 let as_string = MessageAsString(format!("{:?}", msg));
                 span.extensions_mut().insert(as_string);
@@ -131,14 +207,6 @@ impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PosyUILayer {
     }
 }
 
-pub const POSY_CONTEXT_TARGET: &str = "posy::context";
-#[macro_export]
-macro_rules! context {
-    ($($arg:tt)*) => {
-        let _guard = tracing::span!(target: "posy::context", tracing::Level::ERROR, "context", $($arg)*).entered();
-    }
-}
-
 struct PosyEyreHandler {
     context: Vec<String>,
     backtrace: backtrace::Backtrace,
@@ -171,7 +239,10 @@ impl eyre::EyreHandler for PosyEyreHandler {
     }
 }
 
-pub fn init(args: &OutputArgs) {
+pub fn init(args: &OutputArgs) -> Result<()> {
+    FORMAT
+        .set(args.format)
+        .expect("output already initialized?");
     eyre::set_hook(Box::new(|_| Box::new(PosyEyreHandler::new())))
         .expect("eyre handler already installed?");
 
@@ -196,8 +267,23 @@ pub fn init(args: &OutputArgs) {
         ColorChoice::Never => console::set_colors_enabled_stderr(false),
     }
 
+    let progress = match &args.progress_json {
+        Some(target) => Some(progress::open(target)?),
+        None => None,
+    };
+
     let s = tracing_subscriber::registry()
-        .with(PosyUILayer.with_filter(Targets::new().with_target("posy", global_level)))
+        .with(args.timings.then(crate::timings::layer))
+        .with(crate::telemetry::layer()?)
+        .with(progress)
+        .with(
+            PosyUILayer.with_filter(
+                Targets::new()
+                    .with_target("posy", global_level)
+                    .with_target("posy_core", global_level)
+                    .with_target(progress::POSY_PROGRESS_TARGET, LevelFilter::OFF),
+            ),
+        )
         .with(
             tracing_subscriber::fmt::layer().with_filter(
                 EnvFilter::builder()
@@ -207,4 +293,13 @@ pub fn init(args: &OutputArgs) {
             ),
         );
     s.init();
+    if cfg!(not(feature = "otel")) {
+        if let Some(endpoint) = crate::telemetry::traces_endpoint() {
+            warn!(
+                "not exporting traces to {endpoint}: this posy was built without the \
+                 'otel' feature"
+            );
+        }
+    }
+    Ok(())
 }
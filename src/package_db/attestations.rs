@@ -0,0 +1,482 @@
+//! PEP 740 attestations: when an index publishes provenance for an artifact, we fetch
+//! it and check it before installing anything.
+//!
+//! What we check, for each attestation:
+//!
+//! - its in-toto statement names exactly this file, with the sha256 we pinned
+//! - the DSSE signature over that statement verifies against the key in its signing
+//!   certificate
+//! - a Rekor log from our trusted root recorded that signature, and a Fulcio CA from
+//!   our trusted root issued the certificate, valid at that time (see `sigstore`)
+//! - the source repository in the certificate matches what the project config
+//!   expects, if the config says anything
+//!
+//! Without a trusted root we can't check any of that, so `required` fails and
+//! `preferred` lets things through unchecked.
+
+use crate::prelude::*;
+use http::Request;
+use std::collections::BTreeMap;
+
+use super::http::{CacheMode, Http};
+use super::sigstore::{Certificate, Identity, TransparencyLogEntry, TrustedRoot};
+use super::simple_api::ArtifactInfo;
+
+/// How much we care about attestations for a package.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttestationPolicy {
+    /// Refuse anything that doesn't come with a valid attestation.
+    Required,
+    /// If there are attestations they have to be valid, but if there aren't, fine.
+    #[default]
+    Preferred,
+    /// Don't even look.
+    Ignored,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum RawAttestationRule {
+    Policy(AttestationPolicy),
+    Table {
+        policy: Option<AttestationPolicy>,
+        repository: Option<String>,
+    },
+}
+
+/// What we expect of one package's attestations. Either just a policy, or a table that
+/// also pins the publisher:
+///
+///   requests = { repository = "psf/requests" }
+///
+/// Pinning a publisher implies `policy = "required"`, unless you say otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "RawAttestationRule")]
+pub struct AttestationRule {
+    pub policy: AttestationPolicy,
+    /// The source repository the signing certificate has to say it was issued for:
+    /// a URL, or `owner/name` on GitHub. Compared case-insensitively.
+    pub repository: Option<String>,
+}
+
+impl From<RawAttestationRule> for AttestationRule {
+    fn from(raw: RawAttestationRule) -> Self {
+        match raw {
+            RawAttestationRule::Policy(policy) => Self {
+                policy,
+                repository: None,
+            },
+            RawAttestationRule::Table { policy, repository } => Self {
+                policy: policy.unwrap_or(match repository {
+                    Some(_) => AttestationPolicy::Required,
+                    None => AttestationPolicy::default(),
+                }),
+                repository,
+            },
+        }
+    }
+}
+
+/// `[tool.posy.attestations]`:
+///
+///   [tool.posy.attestations]
+///   default = "preferred"
+///
+///   [tool.posy.attestations.packages]
+///   requests = { repository = "psf/requests" }
+///   some-old-thing = "ignored"
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct AttestationConfig {
+    pub default: AttestationPolicy,
+    pub packages: BTreeMap<PackageName, AttestationRule>,
+    /// What we check signatures against. Comes from `sigstore-trusted-root` in
+    /// posy.toml, not from the project.
+    #[serde(skip)]
+    pub trusted_root: Option<TrustedRoot>,
+}
+
+impl AttestationConfig {
+    pub fn rule_for(&self, p: &PackageName) -> AttestationRule {
+        self.packages.get(p).cloned().unwrap_or(AttestationRule {
+            policy: self.default,
+            repository: None,
+        })
+    }
+}
+
+// The provenance file an index links to, as described in PEP 740. Anything we don't
+// use gets ignored.
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Provenance {
+    pub version: u32,
+    pub attestation_bundles: Vec<AttestationBundle>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttestationBundle {
+    pub publisher: Publisher,
+    pub attestations: Vec<Attestation>,
+}
+
+/// Who the provenance file says published the artifact. Nothing signs this, so it's
+/// only good for error messages; the real answer is in the signing certificate.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Publisher {
+    pub kind: String,
+    pub repository: Option<String>,
+}
+
+impl Display for Publisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.repository {
+            Some(repository) => write!(f, "{} publisher for {repository}", self.kind),
+            None => write!(f, "{} publisher", self.kind),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Attestation {
+    pub verification_material: VerificationMaterial,
+    pub envelope: Envelope,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VerificationMaterial {
+    /// base64 DER
+    pub certificate: String,
+    #[serde(default)]
+    pub transparency_entries: Vec<TransparencyLogEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Envelope {
+    /// base64 JSON, an in-toto Statement
+    pub statement: String,
+    /// base64
+    pub signature: String,
+}
+
+const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+#[derive(Debug, Clone, Deserialize)]
+struct Statement {
+    #[serde(rename = "_type")]
+    type_: String,
+    subject: Vec<Subject>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Subject {
+    name: String,
+    digest: HashMap<String, String>,
+}
+
+/// DSSE's "pre-authentication encoding", which is what actually gets signed.
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "DSSEv1 {} {payload_type} {} ",
+        payload_type.len(),
+        payload.len()
+    )
+    .into_bytes();
+    out.extend_from_slice(payload);
+    out
+}
+
+fn verify_attestation(
+    attestation: &Attestation,
+    filename: &str,
+    sha256: &str,
+    root: &TrustedRoot,
+) -> Result<Identity> {
+    let b64 = &data_encoding::BASE64;
+    let statement_bytes = b64.decode(attestation.envelope.statement.as_bytes())?;
+    let statement: Statement = serde_json::from_slice(&statement_bytes)?;
+    if statement.type_ != STATEMENT_TYPE {
+        bail!("unknown statement type {}", statement.type_);
+    }
+    let [subject] = statement.subject.as_slice() else {
+        bail!("statement should have exactly one subject");
+    };
+    if subject.name != filename {
+        bail!("statement is about {}, not {filename}", subject.name);
+    }
+    match subject.digest.get("sha256") {
+        Some(digest) if digest.eq_ignore_ascii_case(sha256) => (),
+        Some(digest) => bail!("statement has sha256 {digest}, but we pinned {sha256}"),
+        None => bail!("statement has no sha256 digest"),
+    }
+
+    let material = &attestation.verification_material;
+    let certificate_der = b64.decode(material.certificate.as_bytes())?;
+    let certificate = Certificate::parse(&certificate_der)?;
+    let signature = b64.decode(attestation.envelope.signature.as_bytes())?;
+    let payload = pae(PAYLOAD_TYPE, &statement_bytes);
+    certificate.key.verify(&payload, &signature)?;
+
+    // Fulcio certificates only last a few minutes, so what makes one good is that a
+    // log we trust saw the signature while it was still valid.
+    let mut problems = Vec::new();
+    let mut logged = None;
+    for entry in &material.transparency_entries {
+        match root.verify_dsse_entry(entry, &payload, &signature, &certificate_der) {
+            Ok(time) => {
+                logged = Some(time);
+                break;
+            }
+            Err(err) => problems.push(format!("{err:#}")),
+        }
+    }
+    let Some(logged) = logged else {
+        if problems.is_empty() {
+            bail!("signature isn't in a transparency log");
+        }
+        bail!("signature isn't in a transparency log: {}", problems.join("; "));
+    };
+    root.verify_certificate(&certificate, logged)?;
+    certificate.identity()
+}
+
+/// Finds an attestation in `provenance` that vouches for `filename` with hash
+/// `sha256` (as hex), from a publisher that `rule` is happy with. Who the publisher is
+/// comes from the signing certificate, not from what the provenance file says about
+/// itself.
+pub fn verify(
+    provenance: &Provenance,
+    filename: &str,
+    sha256: &str,
+    rule: &AttestationRule,
+    root: &TrustedRoot,
+) -> Result<Identity> {
+    if provenance.version != 1 {
+        bail!("unknown provenance version {}", provenance.version);
+    }
+    let mut problems = Vec::new();
+    for bundle in &provenance.attestation_bundles {
+        for attestation in &bundle.attestations {
+            let verified = verify_attestation(attestation, filename, sha256, root);
+            let identity = match verified {
+                Ok(identity) => identity,
+                Err(err) => {
+                    problems.push(format!("{}: {err:#}", bundle.publisher));
+                    continue;
+                }
+            };
+            match &rule.repository {
+                Some(expected) if !identity.is_repository(expected) => {
+                    problems.push(format!(
+                        "{}: signed by {identity}, but we expect {expected}",
+                        bundle.publisher
+                    ));
+                }
+                _ => return Ok(identity),
+            }
+        }
+    }
+    if problems.is_empty() {
+        bail!("no attestations for {filename}");
+    }
+    bail!(
+        "no valid attestations for {filename}:\n  {}",
+        problems.join("\n  ")
+    );
+}
+
+/// Checks `ai` against whatever `config` says about attestations for its package.
+/// Only fetches the provenance if the policy cares.
+pub fn check(http: &Http, config: &AttestationConfig, ai: &ArtifactInfo) -> Result<()> {
+    let rule = config.rule_for(ai.name.distribution());
+    if rule.policy == AttestationPolicy::Ignored {
+        return Ok(());
+    }
+    let Some(url) = &ai.provenance else {
+        if rule.policy == AttestationPolicy::Required {
+            bail!("{} has no attestations, but they're required", ai.name);
+        }
+        return Ok(());
+    };
+    let Some(root) = &config.trusted_root else {
+        if rule.policy == AttestationPolicy::Required {
+            bail!(
+                "{} needs its attestations checked, but there's no sigstore trusted \
+                 root to check them against (set sigstore-trusted-root in posy.toml)",
+                ai.name
+            );
+        }
+        debug!("not checking attestations for {}: no trusted root", ai.name);
+        return Ok(());
+    };
+    context!("Checking attestations for {}", ai.name);
+    let Some(sha256) = ai.hashes.iter().find(|h| h.mode == "sha256") else {
+        bail!("{} has no sha256 hash to check attestations against", ai.name);
+    };
+    let request = Request::builder().uri(url.as_str()).body(())?;
+    let response = http.request(request, CacheMode::Default)?;
+    if response.status().as_u16() >= 400 {
+        Err(NetworkError::HttpStatus {
+            url: url.clone(),
+            status: response.status().as_u16(),
+        })?;
+    }
+    let provenance: Provenance = serde_json::from_reader(response.into_body())?;
+    let identity = verify(
+        &provenance,
+        &ai.name.to_string(),
+        &data_encoding::HEXLOWER.encode(&sha256.raw_data),
+        &rule,
+        root,
+    )?;
+    debug!("{} is attested by {identity}", ai.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package_db::sigstore::fake::*;
+    use ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let sigstore = FakeSigstore::new();
+        let root = sigstore.trusted_root()?;
+        let key = generate(&ECDSA_P256_SHA256_ASN1_SIGNING);
+        let issue = Issue {
+            key: &key,
+            not_before: "20240101000000Z",
+            not_after: "20240101001000Z",
+            repository: Some("https://github.com/Example/Foo"),
+            code_signing: true,
+        };
+        // 2024-01-01T00:05:00Z
+        let logged_at = 1704067500;
+
+        let filename = "foo-1.0-py3-none-any.whl";
+        let sha256 = "ab".repeat(32);
+        let statement = serde_json::json!({
+            "_type": STATEMENT_TYPE,
+            "subject": [{"name": filename, "digest": {"sha256": sha256}}],
+            "predicateType": "https://docs.pypi.org/attestations/publish/v1",
+            "predicate": null,
+        })
+        .to_string();
+        let payload = pae(PAYLOAD_TYPE, statement.as_bytes());
+        let b64 = &data_encoding::BASE64;
+        let provenance = |issue: &Issue, publisher: &str, logged: bool| {
+            let certificate = sigstore.issue(issue);
+            let signature = sign(issue.key, &payload);
+            let entries = match logged {
+                true => {
+                    vec![sigstore.log(&payload, &signature, &certificate, logged_at)]
+                }
+                false => vec![],
+            };
+            serde_json::from_value::<Provenance>(serde_json::json!({
+                "version": 1,
+                "attestation_bundles": [{
+                    "publisher": {
+                        "kind": "GitHub",
+                        "repository": publisher,
+                        "workflow": "release.yml",
+                    },
+                    "attestations": [{
+                        "version": 1,
+                        "verification_material": {
+                            "certificate": b64.encode(&certificate),
+                            "transparency_entries": entries,
+                        },
+                        "envelope": {
+                            "statement": b64.encode(statement.as_bytes()),
+                            "signature": b64.encode(&signature),
+                        },
+                    }],
+                }],
+            }))
+        };
+
+        let good = provenance(&issue, "Example/Foo", true)?;
+        let anyone = AttestationRule::default();
+        let identity = verify(&good, filename, &sha256, &anyone, &root)?;
+        assert_eq!(
+            identity.repository.as_deref(),
+            Some("https://github.com/Example/Foo")
+        );
+        let pinned = AttestationRule {
+            policy: AttestationPolicy::Required,
+            repository: Some("example/foo".into()),
+        };
+        verify(&good, filename, &sha256, &pinned, &root)?;
+
+        let elsewhere = AttestationRule {
+            policy: AttestationPolicy::Required,
+            repository: Some("evil/foo".into()),
+        };
+        assert!(verify(&good, filename, &sha256, &elsewhere, &root).is_err());
+        assert!(verify(&good, filename, &"cd".repeat(32), &anyone, &root).is_err());
+        let other_file = "foo-2.0-py3-none-any.whl";
+        assert!(verify(&good, other_file, &sha256, &anyone, &root).is_err());
+
+        let mut forged = good.clone();
+        let other = statement.replace(&sha256, &"cd".repeat(32));
+        forged.attestation_bundles[0].attestations[0]
+            .envelope
+            .statement = b64.encode(other.as_bytes());
+        assert!(verify(&forged, filename, &"cd".repeat(32), &anyone, &root).is_err());
+
+        // The publisher the provenance file claims doesn't count for anything; only
+        // the certificate does.
+        let evil = Issue {
+            repository: Some("https://github.com/evil/foo"),
+            ..issue
+        };
+        let lying = provenance(&evil, "example/foo", true)?;
+        verify(&lying, filename, &sha256, &anyone, &root)?;
+        assert!(verify(&lying, filename, &sha256, &pinned, &root).is_err());
+        let gitlab = Issue {
+            repository: Some("https://gitlab.com/example/foo"),
+            ..issue
+        };
+        let gitlab = provenance(&gitlab, "example/foo", true)?;
+        assert!(verify(&gitlab, filename, &sha256, &pinned, &root).is_err());
+
+        // Signed, but never logged
+        let unlogged = provenance(&issue, "example/foo", false)?;
+        assert!(verify(&unlogged, filename, &sha256, &anyone, &root).is_err());
+        // Certificate from somebody else's CA, logged in somebody else's log
+        let other_sigstore = FakeSigstore::new();
+        let other_root = other_sigstore.trusted_root()?;
+        assert!(verify(&good, filename, &sha256, &anyone, &other_root).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_attestation_config() -> Result<()> {
+        let config: AttestationConfig = toml_edit::de::from_str(indoc::indoc! {r#"
+            default = "ignored"
+
+            [packages]
+            Requests = { repository = "psf/requests" }
+            careful = "required"
+            lenient = { policy = "preferred", repository = "me/lenient" }
+        "#})?;
+        let rule = |name: &str| -> Result<AttestationRule> {
+            Ok(config.rule_for(&name.try_into()?))
+        };
+        assert_eq!(
+            rule("requests")?,
+            AttestationRule {
+                policy: AttestationPolicy::Required,
+                repository: Some("psf/requests".into()),
+            }
+        );
+        assert_eq!(rule("careful")?.policy, AttestationPolicy::Required);
+        assert_eq!(rule("lenient")?.policy, AttestationPolicy::Preferred);
+        assert_eq!(rule("other")?.policy, AttestationPolicy::Ignored);
+        Ok(())
+    }
+}
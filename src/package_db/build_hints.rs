@@ -0,0 +1,155 @@
+use crate::prelude::*;
+
+// Build failures mostly come down to the same handful of missing system dependencies,
+// but the actual complaint is usually buried in hundreds of lines of compiler output.
+// So we look for the usual suspects, and say what they mean in plain words.
+
+// gcc says "No such file or directory", clang says "file not found"
+static MISSING_HEADER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"fatal error: ([\w./+-]+\.h): No such file or directory",
+        r"|fatal error: '([\w./+-]+\.h)' file not found",
+    ))
+    .unwrap()
+});
+
+static MISSING_PKG_CONFIG_PACKAGE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"Package '?([\w.+-]+)'?,? was not found in the pkg-config search path")
+        .unwrap()
+});
+
+static MISSING_MODULE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"ModuleNotFoundError: No module named '([\w.]+)'").unwrap()
+});
+
+/// Substrings that mean some tool isn't installed, and what to tell the user.
+const MISSING_TOOLS: &[(&str, &str)] = &[
+    ("error: command 'gcc' failed", NEED_C_COMPILER),
+    ("error: command 'cc' failed", NEED_C_COMPILER),
+    ("unable to execute 'gcc'", NEED_C_COMPILER),
+    ("gcc: not found", NEED_C_COMPILER),
+    (
+        "Microsoft Visual C++ 14.0 or greater is required",
+        "this package needs the Microsoft C++ Build Tools",
+    ),
+    ("can't find Rust compiler", NEED_RUST_COMPILER),
+    ("cargo: not found", NEED_RUST_COMPILER),
+    (
+        "Could not find a Fortran compiler",
+        "this package needs a Fortran compiler (e.g. gfortran)",
+    ),
+    ("pkg-config: not found", "this package needs pkg-config"),
+    ("CMake must be installed", "this package needs CMake"),
+];
+
+const NEED_C_COMPILER: &str = "this package needs a C compiler (e.g. gcc or clang)";
+const NEED_RUST_COMPILER: &str =
+    "this package needs a Rust compiler; see https://rustup.rs";
+
+/// Headers that have a well known package name, on the assumption that most people
+/// building things are on Debian/Ubuntu or Fedora/RHEL.
+const KNOWN_HEADERS: &[(&str, &str)] = &[
+    ("openssl/", "OpenSSL (libssl-dev or openssl-devel)"),
+    ("ffi.h", "libffi (libffi-dev or libffi-devel)"),
+    ("zlib.h", "zlib (zlib1g-dev or zlib-devel)"),
+    ("libxml/", "libxml2 (libxml2-dev or libxml2-devel)"),
+    ("libxslt/", "libxslt (libxslt1-dev or libxslt-devel)"),
+    ("jpeglib.h", "libjpeg (libjpeg-dev or libjpeg-turbo-devel)"),
+    ("pg_config.h", "PostgreSQL (libpq-dev or libpq-devel)"),
+    ("libpq-fe.h", "PostgreSQL (libpq-dev or libpq-devel)"),
+    ("mysql.h", "MySQL (libmysqlclient-dev or mysql-devel)"),
+    ("sqlite3.h", "SQLite (libsqlite3-dev or sqlite-devel)"),
+];
+
+/// Looks through a failed build's output for signs of common problems.
+pub(super) fn build_failure_hints(log: &str) -> Vec<String> {
+    let mut hints = Vec::new();
+    let mut add = |hint: String| {
+        if !hints.contains(&hint) {
+            hints.push(hint);
+        }
+    };
+    for captures in MISSING_HEADER.captures_iter(log) {
+        let header = captures
+            .get(1)
+            .or_else(|| captures.get(2))
+            .unwrap()
+            .as_str();
+        let known = KNOWN_HEADERS
+            .iter()
+            .find(|(prefix, _)| header.starts_with(prefix));
+        add(match known {
+            Some((_, package)) => format!(
+                "the build couldn't find {header}; you probably need to install the \
+                 development headers for {package}"
+            ),
+            None => format!(
+                "the build couldn't find {header}; you probably need to install the \
+                 development package that provides it"
+            ),
+        });
+    }
+    for captures in MISSING_PKG_CONFIG_PACKAGE.captures_iter(log) {
+        add(format!(
+            "the build needs the system library '{}', which pkg-config couldn't find",
+            &captures[1]
+        ));
+    }
+    for (needle, hint) in MISSING_TOOLS {
+        if log.contains(needle) {
+            add(hint.to_string());
+        }
+    }
+    for captures in MISSING_MODULE.captures_iter(log) {
+        add(format!(
+            "the build tried to import '{}', but the package doesn't list it in its \
+             build requirements",
+            &captures[1]
+        ));
+    }
+    hints
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_failure_hints() {
+        let log = indoc::indoc! {r#"
+            building '_cffi_backend' extension
+            gcc -pthread -fPIC -c src/c/_cffi_backend.c
+            src/c/_cffi_backend.c:15:10: fatal error: ffi.h: No such file or directory
+            src/c/other.c:3:10: fatal error: 'foo/bar.h' file not found
+               15 | #include <ffi.h>
+            compilation terminated.
+            error: command 'gcc' failed with exit code 1
+            error: command 'gcc' failed with exit code 1
+        "#};
+        assert_eq!(
+            build_failure_hints(log),
+            vec![
+                "the build couldn't find ffi.h; you probably need to install the \
+                 development headers for libffi (libffi-dev or libffi-devel)",
+                "the build couldn't find foo/bar.h; you probably need to install the \
+                 development package that provides it",
+                "this package needs a C compiler (e.g. gcc or clang)",
+            ]
+        );
+
+        assert_eq!(
+            build_failure_hints("error: can't find Rust compiler\n"),
+            vec!["this package needs a Rust compiler; see https://rustup.rs"]
+        );
+        assert_eq!(
+            build_failure_hints(
+                "Package libsystemd was not found in the pkg-config search path.\n"
+            ),
+            vec![
+                "the build needs the system library 'libsystemd', which pkg-config \
+                 couldn't find"
+            ]
+        );
+        assert!(build_failure_hints("everything is fine\n").is_empty());
+    }
+}
@@ -1,7 +1,12 @@
-use std::{ffi::OsString, fs, io, path::PathBuf};
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     env::Env,
+    error::ErrorKind,
     kvstore::KVDirLock,
     package_db::PackageDB,
     prelude::*,
@@ -9,7 +14,8 @@ use crate::{
     tree::WriteTreeFS,
 };
 
-use super::ArtifactInfo;
+use super::build_hints::build_failure_hints;
+use super::{ArtifactInfo, LocalTree};
 
 // Wheel build context lifecycle:
 //
@@ -57,6 +63,36 @@ enum Pep517Goal {
     Wheel,
 }
 
+/// Something we can run a PEP 517 build on.
+#[derive(Clone, Copy)]
+enum BuildSource<'s> {
+    Sdist(&'s ArtifactInfo),
+    Tree(&'s LocalTree),
+}
+
+impl<'s> BuildSource<'s> {
+    fn name(&self) -> &'s PackageName {
+        match self {
+            BuildSource::Sdist(ai) => ai.name.distribution(),
+            BuildSource::Tree(tree) => &tree.name,
+        }
+    }
+
+    fn version(&self) -> Option<&'s Version> {
+        match self {
+            BuildSource::Sdist(ai) => Some(ai.name.version()),
+            BuildSource::Tree(_) => None,
+        }
+    }
+
+    fn hash(&self) -> Result<&'s ArtifactHash> {
+        match self {
+            BuildSource::Sdist(ai) => ai.require_hash(),
+            BuildSource::Tree(tree) => Ok(&tree.hash),
+        }
+    }
+}
+
 enum Pep517Succeeded {
     WheelMetadata {
         handle: KVDirLock,
@@ -93,6 +129,18 @@ impl<'a> WheelBuilder<'a> {
         })
     }
 
+    /// Which builds of an sdist are interchangeable. Wheels built with the same key
+    /// go in the same wheel cache directory, and then we pick between them based on
+    /// their tags.
+    fn build_key(&self, source: BuildSource) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&BuildKey {
+            source: source.hash()?,
+            python: self.target_python,
+            python_version: self.target_python_version,
+            build_constraints: sorted_strings(&self.db.build_constraints),
+        })?)
+    }
+
     fn new_build_stack(
         &'a self,
         package: &'a PackageName,
@@ -115,10 +163,68 @@ impl<'a> WheelBuilder<'a> {
         wheel_platform: &WheelPlatform,
     ) -> Result<Wheel> {
         trace!("Building wheel from source for {} {}", sdist_ai.name.distribution().as_given(), sdist_ai.name.version());
-        let new_build_stack = self.new_build_stack(sdist_ai.name.distribution())?;
+        self.built_wheel(BuildSource::Sdist(sdist_ai), wheel_platform)
+    }
+
+    /// Builds a wheel from a local source tree. Wheels are cached based on the tree's
+    /// contents, so this only actually runs the build backend when something changed.
+    pub fn local_tree_wheel(
+        &self,
+        tree: &LocalTree,
+        wheel_platform: &WheelPlatform,
+    ) -> Result<Wheel> {
+        trace!("Building wheel from local tree {}", tree.root.display());
+        self.built_wheel(BuildSource::Tree(tree), wheel_platform)
+    }
+
+    /// Like `local_tree_wheel`, but gives back where the wheel is in the cache, so it
+    /// can be used like any other local file.
+    pub fn local_tree_wheel_path(
+        &self,
+        tree: &LocalTree,
+        wheel_platform: &WheelPlatform,
+    ) -> Result<PathBuf> {
+        let wheel = self.local_tree_wheel(tree, wheel_platform)?;
+        let key = self.build_key(BuildSource::Tree(tree))?;
+        let handle = self.db.wheel_cache.lock(&key.as_slice())?;
+        Ok(handle.join(wheel.name().to_string()))
+    }
+
+    /// Like the metadata for sdists, this is cached by hash, so asking for it again is
+    /// cheap as long as the tree hasn't changed.
+    #[cfg_attr(not(feature = "cli"), allow(dead_code))]
+    pub fn local_tree_metadata(&self, tree: &LocalTree) -> Result<WheelCoreMetadata> {
+        trace!("Getting metadata from local tree {}", tree.root.display());
+        if let Some(metadata) = self.db.cached_local_tree_metadata(tree) {
+            return Ok(metadata);
+        }
+        let source = BuildSource::Tree(tree);
+        let new_build_stack = self.new_build_stack(source.name())?;
+        let goal = Pep517Goal::WheelMetadata;
+        let metadata_buf = match self.pep517(source, goal, None, &new_build_stack)? {
+            Pep517Succeeded::WheelMetadata { dist_info, .. } => {
+                fs::read(dist_info.join("METADATA"))?
+            }
+            Pep517Succeeded::Wheel { wheel } => wheel.metadata()?.0,
+        };
+        self.db
+            .metadata_cache
+            .get_or_set(&tree.hash, |w| Ok(w.write_all(&metadata_buf)?))?;
+        Ok(metadata_buf.as_slice().try_into()?)
+    }
+
+    fn built_wheel(
+        &self,
+        source: BuildSource,
+        wheel_platform: &WheelPlatform,
+    ) -> Result<Wheel> {
+        let new_build_stack = self.new_build_stack(source.name())?;
 
         // check if we already have a usable wheel cached; and if so, find the best one
-        let handle = self.db.wheel_cache.lock(sdist_ai.require_hash()?)?;
+        let handle = self
+            .db
+            .wheel_cache
+            .lock(&self.build_key(source)?.as_slice())?;
         fs::create_dir_all(&handle)?;
 
         let mut best: Option<(i32, OsString, WheelName)> = None;
@@ -138,7 +244,14 @@ impl<'a> WheelBuilder<'a> {
             let name: WheelName = str_name.parse()?;
             let maybe_score = wheel_platform.max_compatibility(name.all_tags());
             if let Some(score) = maybe_score {
-                if best.is_none() || best.as_ref().unwrap().0 < score {
+                let better = match &best {
+                    None => true,
+                    Some((best_score, _, best_name)) => {
+                        binary_preference(*best_score, best_name)
+                            < binary_preference(score, &name)
+                    }
+                };
+                if better {
                     best = Some((score, os_name, name))
                 }
             }
@@ -151,12 +264,7 @@ impl<'a> WheelBuilder<'a> {
 
         // nothing in cache -- we'll have to build it ourselves (which will implicitly
         // add to the cache)
-        match self.pep517(
-            sdist_ai,
-            Pep517Goal::Wheel,
-            Some(handle),
-            &new_build_stack,
-        )? {
+        match self.pep517(source, Pep517Goal::Wheel, Some(handle), &new_build_stack)? {
             Pep517Succeeded::Wheel { wheel } => {
                 if wheel_platform
                     .max_compatibility(wheel.name().all_tags())
@@ -178,8 +286,22 @@ impl<'a> WheelBuilder<'a> {
         trace!("Getting metadata from source for {} {}", sdist_ai.name.distribution().as_given(), sdist_ai.name.version());
         let new_build_stack = self.new_build_stack(sdist_ai.name.distribution())?;
 
+        // If the sdist has trustworthy static metadata, we don't need to build anything
+        let sdist = self.db.get_artifact::<Sdist>(sdist_ai)?;
+        if let Some(pkg_info) = sdist.pkg_info()? {
+            let static_metadata = WheelCoreMetadata::from_static_pkg_info(&pkg_info)
+                .map_err(|err| MetadataError::Invalid {
+                    what: format!("PKG-INFO in {}", sdist_ai.name),
+                    source: err.into(),
+                })?;
+            if let Some(metadata) = static_metadata {
+                debug!("using static PKG-INFO metadata from {}", sdist_ai.name);
+                return Ok((pkg_info, metadata));
+            }
+        }
+
         match self.pep517(
-            sdist_ai,
+            BuildSource::Sdist(sdist_ai),
             Pep517Goal::WheelMetadata,
             None,
             &new_build_stack,
@@ -201,6 +323,48 @@ impl<'a> WheelBuilder<'a> {
         reqs: &[UserRequirement],
         like: Option<&Blueprint>,
         new_build_stack: &[&PackageName],
+    ) -> Result<(Blueprint, Env)> {
+        // Build environments are pinned: once we've picked versions for a given set of
+        // build requirements, we keep using them, so builds are reproducible and don't
+        // need to touch the network once everything's cached.
+        let key = serde_json::to_vec(&BuildEnvKey {
+            python: self.target_python,
+            python_version: self.target_python_version,
+            requires: sorted_strings(reqs),
+            constraints: sorted_strings(&self.db.build_constraints),
+            platforms: self.build_platforms.iter().map(|p| p.core_tag()).collect(),
+        })?;
+        let saved: Option<Blueprint> = self
+            .db
+            .build_blueprints
+            .get(&key.as_slice())
+            .and_then(|r| serde_json::from_reader(r).ok());
+        if let Some(blueprint) = saved {
+            let env = self.db.build_forest.get_env(
+                self.db,
+                &blueprint,
+                &self.build_platforms,
+                new_build_stack,
+            )?;
+            return Ok((blueprint, env));
+        }
+
+        let (blueprint, env) =
+            self.resolve_env_for_build(reqs, like, new_build_stack)?;
+        // Don't hold the lock while resolving, because resolving can recursively
+        // trigger other builds that want the same build environment.
+        let handle = self.db.build_blueprints.lock(&key.as_slice())?;
+        let mut writer = handle.begin()?;
+        serde_json::to_writer(&mut writer, &blueprint)?;
+        writer.commit()?;
+        Ok((blueprint, env))
+    }
+
+    fn resolve_env_for_build(
+        &self,
+        reqs: &[UserRequirement],
+        like: Option<&Blueprint>,
+        new_build_stack: &[&PackageName],
     ) -> Result<(Blueprint, Env)> {
         // if we've already resolved a version of this environment, then we can skip
         // over the tricky stuff and just re-use the pybi + any matching wheels
@@ -210,11 +374,14 @@ impl<'a> WheelBuilder<'a> {
                     name: self.target_python.clone(),
                     extras: Default::default(),
                     specifiers: Default::default(),
+                    url: None,
                     env_marker_expr: Default::default(),
                 })
                 .unwrap(),
                 requirements: reqs.into(),
                 allow_pre: Default::default(),
+                constraints: self.db.build_constraints.clone(),
+                sources: Default::default(),
             }
             .resolve(
                 self.db,
@@ -251,6 +418,7 @@ impl<'a> WheelBuilder<'a> {
                     value: self.target_python_version.to_string(),
                 }]),
                 extras: Default::default(),
+                url: None,
                 env_marker_expr: Default::default(),
             })
             .unwrap(),
@@ -263,6 +431,7 @@ impl<'a> WheelBuilder<'a> {
                     value: format!("{}.*", same_minor),
                 }]),
                 extras: Default::default(),
+                url: None,
                 env_marker_expr: Default::default(),
             })
             .unwrap(),
@@ -272,6 +441,7 @@ impl<'a> WheelBuilder<'a> {
                 name: self.target_python.clone(),
                 extras: Default::default(),
                 specifiers: Default::default(),
+                url: None,
                 env_marker_expr: Default::default(),
             })
             .unwrap(),
@@ -289,6 +459,8 @@ impl<'a> WheelBuilder<'a> {
                 python: candidate,
                 requirements: Vec::new(),
                 allow_pre,
+                constraints: Vec::new(),
+                sources: Default::default(),
             };
             let result =
                 brief.resolve(self.db, &self.build_platforms, None, new_build_stack);
@@ -297,9 +469,9 @@ impl<'a> WheelBuilder<'a> {
                     found_python = Some((brief.python, blueprint));
                     break;
                 }
-                Err(err) => match err.downcast_ref::<PosyError>() {
-                    Some(PosyError::NoPybiFound) => continue,
-                    _ => return Err(err),
+                Err(err) => match err.kind() {
+                    ErrorKind::Resolve(ResolveError::NoPybiFound { .. }) => continue,
+                    _ => return Err(err.into_report()),
                 },
             }
         }
@@ -314,6 +486,8 @@ impl<'a> WheelBuilder<'a> {
             python: pyreq,
             requirements: reqs.into(),
             allow_pre: Default::default(),
+            constraints: self.db.build_constraints.clone(),
+            sources: Default::default(),
         };
         let blueprint = brief.resolve(
             self.db,
@@ -332,19 +506,40 @@ impl<'a> WheelBuilder<'a> {
 
     fn pep517(
         &self,
-        sdist_ai: &ArtifactInfo,
+        source: BuildSource,
         goal: Pep517Goal,
         wheel_cache_handle: Option<KVDirLock>,
         new_build_stack: &[&PackageName],
     ) -> Result<Pep517Succeeded> {
-        let sdist_hash = sdist_ai.require_hash()?;
-        let handle = self.db.build_store.lock(&sdist_hash)?;
+        timing!("build");
+        let build_key = self.build_key(source)?;
+        let handle = self.db.build_store.lock(&build_key.as_slice())?;
+        let log_path = self.db.build_logs.join(format!(
+            "{}-{}.log",
+            source.name().normalized(),
+            match source.version() {
+                Some(version) => version.to_string(),
+                None => "local".into(),
+            }
+        ));
 
         if !handle.exists() {
+            // A fresh build gets a fresh log
+            fs::create_dir_all(&self.db.build_logs)?;
+            fs::File::create(&log_path)?;
             let tempdir = handle.tempdir()?;
-            let sdist = self.db.get_artifact::<Sdist>(sdist_ai)?;
             let unpack_path = tempdir.path().join("sdist");
-            sdist.unpack(&mut WriteTreeFS::new(&unpack_path))?;
+            match source {
+                BuildSource::Sdist(sdist_ai) => {
+                    let sdist = self.db.get_artifact::<Sdist>(sdist_ai)?;
+                    let limits = self.db.unpack_limits();
+                    sdist.unpack(&mut WriteTreeFS::new(&unpack_path), limits)?;
+                }
+                // Same layout as an unpacked sdist: one top-level directory
+                BuildSource::Tree(tree) => {
+                    tree.copy_to(&unpack_path.join(tree.name.as_given()))?
+                }
+            }
             const BUILD_FRONTEND_PY: &[u8] =
                 include_bytes!("data-files/build-frontend.py");
             fs::write(tempdir.path().join("build-frontend.py"), BUILD_FRONTEND_PY)?;
@@ -376,7 +571,7 @@ impl<'a> WheelBuilder<'a> {
                 // Store the wheel in the wheel cache
                 let wheel_cache_handle = match wheel_cache_handle {
                     Some(h) => h,
-                    None => self.db.wheel_cache.lock(&sdist_hash)?,
+                    None => self.db.wheel_cache.lock(&build_key.as_slice())?,
                 };
                 fs::create_dir_all(&wheel_cache_handle)?;
                 let target_path = wheel_cache_handle.join(wheel_name.to_string());
@@ -401,13 +596,15 @@ impl<'a> WheelBuilder<'a> {
                 });
             }
             // Otherwise, we're not done. Turn the crank again.
-            self.pep517_step(&handle, goal, new_build_stack)?;
+            self.pep517_step(source, &handle, &log_path, goal, new_build_stack)?;
         }
     }
 
     fn pep517_step(
         &self,
+        source: BuildSource,
         handle: &KVDirLock,
+        log_path: &Path,
         goal: Pep517Goal,
         new_build_stack: &[&PackageName],
     ) -> Result<()> {
@@ -464,7 +661,28 @@ impl<'a> WheelBuilder<'a> {
 
         serde_json::to_writer(fs::File::create(&saved_blueprint_path)?, &blueprint)?;
 
-        let mut child = std::process::Command::new("python")
+        let backend = build_system
+            .build_backend
+            .unwrap_or_else(|| "setuptools (setup.py)".into());
+        // Build backends are chatty, so their output goes to a log, and we only show it
+        // if something goes wrong.
+        let mut log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?;
+        writeln!(log, "==> posy: running {backend} ({goal:?})")?;
+        debug!(
+            "build log for {}: {}",
+            source.name().as_given(),
+            log_path.display()
+        );
+
+        let mut cmd = std::process::Command::new(&env.python);
+        // Make sure nothing from the user's own Python setup leaks into the build.
+        for var in ISOLATED_ENV_VARS {
+            cmd.env_remove(var);
+        }
+        let mut child = cmd
             .args([
                 handle.join("build-frontend.py").as_os_str(),
                 handle.as_os_str(),
@@ -472,25 +690,85 @@ impl<'a> WheelBuilder<'a> {
                 OsString::from(binary_wheel_tag).as_ref(),
             ])
             .stdin(std::process::Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
             .current_dir(&sdist_root)
             .envs(env.env_vars()?)
             .spawn()?;
 
         let status = child.wait()?;
         if !status.success() {
-            bail!("Build failed (exit status: {status})");
+            let output = String::from_utf8_lossy(&fs::read(log_path)?).into_owned();
+            let lines: Vec<&str> = output.lines().collect();
+            let tail = lines[lines.len().saturating_sub(BUILD_LOG_TAIL_LINES)..]
+                .iter()
+                .map(|line| line.to_string())
+                .collect();
+            Err(InstallError::BuildFailed {
+                package: source.name().as_given().into(),
+                version: source.version().cloned(),
+                backend,
+                status: status.to_string(),
+                log: log_path.to_owned(),
+                tail,
+                hints: build_failure_hints(&output),
+            })?;
         }
 
         Ok(())
     }
 }
 
+/// How much of the build log to show when a build fails.
+const BUILD_LOG_TAIL_LINES: usize = 20;
+
+/// Environment variables that could make the build environment's Python pick up
+/// packages or configuration from outside of it.
+const ISOLATED_ENV_VARS: &[&str] = &[
+    "PYTHONPATH",
+    "PYTHONHOME",
+    "PYTHONSTARTUP",
+    "PYTHONUSERBASE",
+    "VIRTUAL_ENV",
+    "CONDA_PREFIX",
+    "__PYVENV_LAUNCHER__",
+];
+
+fn sorted_strings<T: Display>(items: &[T]) -> Vec<String> {
+    let mut strings = items.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+    strings.sort();
+    strings
+}
+
+#[derive(Serialize)]
+struct BuildKey<'a> {
+    source: &'a ArtifactHash,
+    python: &'a PackageName,
+    python_version: &'a Version,
+    build_constraints: Vec<String>,
+}
+
+/// Identifies a build environment, for looking up the Blueprint we used last time.
+#[derive(Serialize)]
+struct BuildEnvKey<'a> {
+    python: &'a PackageName,
+    python_version: &'a Version,
+    requires: Vec<String>,
+    constraints: Vec<String>,
+    platforms: Vec<&'a str>,
+}
+
 /// Used to parse the `[build-system]` table in pyproject.toml.
+///
+/// If there's no `build-backend`, then this is a legacy setup.py project (PEP 517 says
+/// so even if `[build-system]` is there). build-frontend.py then uses setuptools's
+/// `__legacy__` backend, or, if the build environment's setuptools is too old to have
+/// one, runs `setup.py bdist_wheel` directly.
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(rename_all = "kebab-case", default)]
 struct PyprojectBuildSystemStanza {
     requires: Vec<String>,
-    build_backend: String,
+    build_backend: Option<String>,
     backend_path: Vec<String>,
 }
 
@@ -498,7 +776,7 @@ impl Default for PyprojectBuildSystemStanza {
     fn default() -> Self {
         Self {
             requires: vec!["setuptools".into(), "wheel".into()],
-            build_backend: "setuptools.build_meta:__legacy__".into(),
+            build_backend: None,
             backend_path: Vec::new(),
         }
     }
@@ -514,3 +792,32 @@ impl PyprojectBuildSystemStanza {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_system_stanza() -> Result<()> {
+        let modern = PyprojectBuildSystemStanza::parse_from(indoc::indoc! {r#"
+            [build-system]
+            requires = ["hatchling"]
+            build-backend = "hatchling.build"
+        "#})?;
+        assert_eq!(modern.requires, vec!["hatchling"]);
+        assert_eq!(modern.build_backend.as_deref(), Some("hatchling.build"));
+
+        // no build-backend means setup.py, even if there are requirements
+        let legacy = PyprojectBuildSystemStanza::parse_from(indoc::indoc! {r#"
+            [build-system]
+            requires = ["setuptools", "cython"]
+        "#})?;
+        assert_eq!(legacy.requires, vec!["setuptools", "cython"]);
+        assert!(legacy.build_backend.is_none());
+
+        let missing = PyprojectBuildSystemStanza::parse_from("[tool.black]\n")?;
+        assert_eq!(missing.requires, vec!["setuptools", "wheel"]);
+        assert!(missing.build_backend.is_none());
+        Ok(())
+    }
+}
@@ -43,12 +43,36 @@ for backend_path in build_system["backend-path"]:
     backend_paths.append(str(resolved))
 sys.path[:0] = backend_paths
 
-# https://packaging.python.org/en/latest/specifications/entry-points/
-modname, qualname_separator, qualname = build_system["build-backend"].partition(":")
-backend = import_module(modname)
-if qualname_separator:
-    for attr in qualname.split("."):
-        backend = getattr(backend, attr)
+
+class SetupPyBackend:
+    """For setup.py projects whose setuptools predates setuptools.build_meta: build
+    the wheel the way pip used to, and see what comes out."""
+
+    @staticmethod
+    def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):
+        from subprocess import run
+
+        run(
+            [sys.executable, "setup.py", "bdist_wheel", "--dist-dir", wheel_directory],
+            check=True,
+        )
+        [wheel] = [p.name for p in Path(wheel_directory).glob("*.whl")]
+        return wheel
+
+
+if build_system["build-backend"] is None:
+    # "legacy" project, see PEP 517
+    try:
+        from setuptools.build_meta import __legacy__ as backend
+    except ImportError:
+        backend = SetupPyBackend
+else:
+    # https://packaging.python.org/en/latest/specifications/entry-points/
+    modname, qualname_separator, qualname = build_system["build-backend"].partition(":")
+    backend = import_module(modname)
+    if qualname_separator:
+        for attr in qualname.split("."):
+            backend = getattr(backend, attr)
 
 if not (work_dir / "get_requires_for_build_wheel").exists():
     try:
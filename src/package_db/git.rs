@@ -0,0 +1,223 @@
+use crate::kvstore::KVDirStore;
+use crate::prelude::*;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::http::HostAllowlist;
+
+/// Runs git, and gives back what it printed.
+fn git<I, S>(args: I) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new("git");
+    cmd.args(args);
+    // never stop to ask for a password; there's nobody there to answer
+    cmd.env("GIT_TERMINAL_PROMPT", "0");
+    let output = cmd
+        .output()
+        .wrap_err("couldn't run git (is it installed?)")?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// A checkout of `rev` (a branch, tag, or commit; None means whatever the repository's
+/// default branch is) from the repository at `url`, plus the commit that turned out to
+/// be.
+///
+/// Checkouts are cached in `store` by url and commit. Branches and tags move, so we ask
+/// the repository where they point every time (with `git ls-remote`), and only reuse
+/// a checkout if it's still the same commit. That means that offline, only commits
+/// work.
+///
+/// With an `allowlist`, the repository has to be on an allowed host, and git isn't
+/// allowed to follow redirects, since we couldn't check where they go.
+pub fn checkout(
+    store: &KVDirStore,
+    url: &Url,
+    rev: Option<&str>,
+    offline: bool,
+    allowlist: Option<&HostAllowlist>,
+) -> Result<(PathBuf, String)> {
+    context!(
+        "Checking out {url} at {}",
+        rev.unwrap_or("the default branch")
+    );
+    if let Some(rev) = rev {
+        // otherwise git would take it for an option
+        if rev.starts_with('-') {
+            bail!("{rev:?} isn't a git revision");
+        }
+    }
+    let mut config = Vec::new();
+    if let Some(allowlist) = allowlist {
+        allowlist.check(url)?;
+        config.extend(["-c", "http.followRedirects=false"].map(OsStr::new));
+    }
+    let commit = match rev {
+        Some(rev) if is_commit(rev) => rev.to_string(),
+        _ if offline => bail!(
+            "can't find out where {} points while offline; use a commit instead",
+            rev.unwrap_or("the default branch")
+        ),
+        _ => match (ls_remote(&config, url, rev.unwrap_or("HEAD"))?, rev) {
+            (Some(commit), _) => commit,
+            // not a branch or tag, so it's an abbreviated commit or some such, which
+            // stays put as well as a full one does
+            (None, Some(rev)) => rev.to_string(),
+            (None, None) => bail!("{url} doesn't have a default branch"),
+        },
+    };
+    let key = format!("{url}\0{commit}");
+    if offline && !store.contains(&key.as_bytes()) {
+        bail!("can't check out {url} while offline, and we don't have it cached");
+    }
+    let root = store.get_or_set(&key.as_bytes(), |path| {
+        let path = path.join("tree");
+        let mut args = config.clone();
+        args.extend([
+            OsStr::new("clone"),
+            OsStr::new("--quiet"),
+            OsStr::new("--"),
+            OsStr::new(url.as_str()),
+            path.as_os_str(),
+        ]);
+        git(args)?;
+        git([
+            OsStr::new("-C"),
+            path.as_os_str(),
+            OsStr::new("checkout"),
+            OsStr::new("--quiet"),
+            OsStr::new(&commit),
+            // and nothing after it is a path
+            OsStr::new("--"),
+        ])?;
+        Ok(())
+    })?;
+    let tree = root.join("tree");
+    let commit = head_commit(&tree)?;
+    Ok((tree, commit))
+}
+
+/// Whether `rev` is spelled like a full commit hash (sha1 or sha256).
+fn is_commit(rev: &str) -> bool {
+    matches!(rev.len(), 40 | 64) && rev.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The commit that the branch or tag `rev` points to on the remote, if it's a branch
+/// or tag at all. For annotated tags, that's the commit the tag is on, not the tag
+/// itself.
+fn ls_remote(config: &[&OsStr], url: &Url, rev: &str) -> Result<Option<String>> {
+    let mut args = config.to_vec();
+    args.extend([
+        OsStr::new("ls-remote"),
+        OsStr::new("--"),
+        OsStr::new(url.as_str()),
+        OsStr::new(rev),
+    ]);
+    let output = git(args)?;
+    let refs: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect();
+    let peeled = refs.iter().find(|(_, name)| name.ends_with("^{}"));
+    Ok(peeled
+        .or(refs.first())
+        .map(|(commit, _)| commit.to_string()))
+}
+
+fn head_commit(tree: &Path) -> Result<String> {
+    git([
+        OsStr::new("-C"),
+        tree.as_os_str(),
+        OsStr::new("rev-parse"),
+        OsStr::new("HEAD"),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkout_allowlist() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let store = KVDirStore::new(tmp.path())?;
+        let url = Url::parse("https://git.example.com/me/thing")?;
+        let allowlist = HostAllowlist::new(vec!["pypi.org".into()]);
+        // refused before git ever runs
+        let err = checkout(&store, &url, None, false, Some(&allowlist)).unwrap_err();
+        assert!(format!("{err:#}").contains("isn't in allowed-hosts"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_follows_branches() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let repo = tmp.path().join("repo");
+        let commit = |message: &str| -> Result<String> {
+            std::fs::write(repo.join("file"), message)?;
+            git([
+                OsStr::new("-C"),
+                repo.as_os_str(),
+                OsStr::new("add"),
+                OsStr::new("file"),
+            ])?;
+            git([
+                OsStr::new("-C"),
+                repo.as_os_str(),
+                OsStr::new("-c"),
+                OsStr::new("user.name=posy"),
+                OsStr::new("-c"),
+                OsStr::new("user.email=posy@example.com"),
+                OsStr::new("commit"),
+                OsStr::new("--quiet"),
+                OsStr::new("-m"),
+                OsStr::new(message),
+            ])?;
+            head_commit(&repo)
+        };
+        std::fs::create_dir(&repo)?;
+        git([
+            OsStr::new("init"),
+            OsStr::new("--quiet"),
+            OsStr::new("-b"),
+            OsStr::new("main"),
+            repo.as_os_str(),
+        ])?;
+        let first = commit("first")?;
+        let url = Url::from_directory_path(&repo).unwrap();
+        let store = KVDirStore::new(&tmp.path().join("store"))?;
+
+        let (tree, got) = checkout(&store, &url, Some("main"), false, None)?;
+        assert_eq!(got, first);
+        assert_eq!(std::fs::read_to_string(tree.join("file"))?, "first");
+
+        // the branch moves on, and so do we
+        let second = commit("second")?;
+        let (tree, got) = checkout(&store, &url, Some("main"), false, None)?;
+        assert_eq!(got, second);
+        assert_eq!(std::fs::read_to_string(tree.join("file"))?, "second");
+        let (_, got) = checkout(&store, &url, None, false, None)?;
+        assert_eq!(got, second);
+
+        // but a commit stays put, and works offline once we have it
+        let (tree, got) = checkout(&store, &url, Some(&first), true, None)?;
+        assert_eq!(got, first);
+        assert_eq!(std::fs::read_to_string(tree.join("file"))?, "first");
+        assert!(checkout(&store, &url, Some("main"), true, None).is_err());
+
+        let err = checkout(&store, &url, Some("--upload-pack=touch"), false, None)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("isn't a git revision"));
+        Ok(())
+    }
+}
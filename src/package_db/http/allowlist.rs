@@ -0,0 +1,125 @@
+use crate::prelude::*;
+
+/// The only hosts we're allowed to talk to, for running somewhere with strict egress
+/// rules. Set with `allowed-hosts` in posy.toml; when it's unset, anything goes.
+///
+/// Each entry is a hostname (`pypi.org`), a hostname with a port (`mirror:8080`), or
+/// `*.` plus a domain, which allows every subdomain of it (`*.pythonhosted.org`) but
+/// not the domain itself. Without a port, any port is fine.
+///
+/// This covers everything posy fetches itself, including every hop of a redirect. It
+/// can't do anything about build backends that go to the network on their own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct HostAllowlist {
+    hosts: Vec<String>,
+    /// More lists that a host has to be on as well, from `narrowed`
+    #[serde(skip)]
+    also: Vec<Vec<String>>,
+}
+
+impl HostAllowlist {
+    pub fn new(hosts: Vec<String>) -> HostAllowlist {
+        HostAllowlist {
+            hosts,
+            also: Vec::new(),
+        }
+    }
+
+    /// Only the hosts that both this and `other` allow.
+    pub fn narrowed(mut self, other: HostAllowlist) -> HostAllowlist {
+        self.also.push(other.hosts);
+        self.also.extend(other.also);
+        self
+    }
+
+    pub(super) fn allows(&self, url: &Url) -> bool {
+        std::iter::once(&self.hosts)
+            .chain(&self.also)
+            .all(|hosts| list_allows(hosts, url))
+    }
+
+    /// Fails unless we're allowed to fetch `url`.
+    pub fn check(&self, url: &Url) -> Result<()> {
+        if !self.allows(url) {
+            bail!(
+                "refusing to connect to {}: {} isn't in allowed-hosts",
+                url,
+                url.host_str().unwrap_or("(no host)")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn list_allows(hosts: &[String], url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_ascii_lowercase();
+    let port = url.port_or_known_default();
+    hosts.iter().any(|entry| {
+        let entry = entry.to_ascii_lowercase();
+        // rsplit_once, so that IPv6 literals like [::1]:8080 work
+        let (pattern, entry_port) = match entry.rsplit_once(':') {
+            Some((pattern, p)) => match p.parse() {
+                Ok(p) => (pattern.to_owned(), Some(p)),
+                Err(_) => (entry.clone(), None),
+            },
+            None => (entry.clone(), None),
+        };
+        if entry_port.is_some() && entry_port != port {
+            return false;
+        }
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .map_or(false, |rest| rest.ends_with('.')),
+            None => host == pattern,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_allowlist() {
+        let allowlist = HostAllowlist::new(
+            [
+                "pypi.org",
+                "*.pythonhosted.org",
+                "Mirror.example.com:8080",
+                "[::1]:3141",
+            ]
+            .iter()
+            .map(|h| h.to_string())
+            .collect(),
+        );
+        for url in [
+            "https://pypi.org/simple/",
+            "http://pypi.org:1234/simple/",
+            "https://files.pythonhosted.org/packages/foo.whl",
+            "https://a.b.pythonhosted.org/",
+            "http://mirror.example.com:8080/simple/",
+            "http://[::1]:3141/",
+        ] {
+            assert!(allowlist.check(&Url::parse(url).unwrap()).is_ok(), "{url}");
+        }
+        for url in [
+            "https://test.pypi.org/simple/",
+            "https://pypi.org.evil.com/",
+            "https://pythonhosted.org/",
+            "https://evilpythonhosted.org/",
+            "http://mirror.example.com/simple/",
+            "http://[::1]:8080/",
+            "file:///etc/passwd",
+        ] {
+            assert!(allowlist.check(&Url::parse(url).unwrap()).is_err(), "{url}");
+        }
+        assert!(HostAllowlist::default()
+            .check(&Url::parse("https://pypi.org/").unwrap())
+            .is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use crate::prelude::*;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex};
+
+use super::HostAllowlist;
+
+/// External programs that hand us credentials for private indexes, so that tokens
+/// never have to be written into posy.toml -- e.g. a wrapper around `gcloud auth
+/// print-access-token`, or a CI job's OIDC exchange. Set with `[credential-helpers]`
+/// in posy.toml, which maps hosts (matched like `allowed-hosts` entries) to commands:
+///
+///   [credential-helpers]
+///   "pypi.internal.example.com" = "posy-credential-vault"
+///   "*.pkg.dev" = ["python3", "tools/gar-token.py"]
+///
+/// The protocol is docker-credential-helpers' `get`: we run the command with `get` on
+/// the end, write the server's URL (like `https://pypi.internal.example.com`) to its
+/// stdin, and it prints
+///
+///   {"ServerURL": "...", "Username": "...", "Secret": "..."}
+///
+/// If Username is empty or `<token>`, Secret is sent as a bearer token; otherwise the
+/// two go as basic auth. Helpers only run for https URLs, and the credentials only go
+/// to the host they were issued for, so a redirect to some CDN doesn't take them along.
+///
+/// What a helper gives us is remembered per host until the host turns it down (see
+/// `forget`), since the protocol has no way to say when it expires.
+#[derive(Debug, Default)]
+pub struct CredentialHelpers {
+    helpers: Vec<(HostAllowlist, Vec<String>)>,
+    cache: Mutex<HashMap<String, Slot>>,
+    // notified whenever a helper finishes
+    finished: Condvar,
+}
+
+#[derive(Debug)]
+enum Slot {
+    /// Some thread is running the helper for this server right now
+    Running,
+    /// The header value, or None if the helper had nothing for us
+    Ready(Option<String>),
+}
+
+/// A helper command as written in posy.toml: either just a program, or a program and
+/// its arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum HelperCommand {
+    Program(String),
+    WithArgs(Vec<String>),
+}
+
+impl HelperCommand {
+    /// Relative program paths (with a `/` in them) are relative to `base`, like other
+    /// paths in posy.toml. Bare names are looked up on $PATH as usual.
+    pub fn rebase(self, base: &Path) -> HelperCommand {
+        let mut argv = self.into_argv();
+        if let Some(program) = argv.first_mut() {
+            let path = Path::new(program.as_str());
+            if path.is_relative() && program.contains('/') {
+                *program = base.join(path).to_string_lossy().into_owned();
+            }
+        }
+        HelperCommand::WithArgs(argv)
+    }
+
+    fn into_argv(self) -> Vec<String> {
+        match self {
+            HelperCommand::Program(program) => vec![program],
+            HelperCommand::WithArgs(argv) => argv,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HelperResponse {
+    #[serde(rename = "Username", default)]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: String,
+}
+
+impl CredentialHelpers {
+    /// `helpers` maps host patterns to commands. When several match, exact hosts win
+    /// over `*.` patterns.
+    pub fn new<I>(helpers: I) -> Result<CredentialHelpers>
+    where
+        I: IntoIterator<Item = (String, HelperCommand)>,
+    {
+        let mut parsed = Vec::new();
+        for (host, command) in helpers {
+            let argv = command.into_argv();
+            if argv.is_empty() {
+                bail!("credential helper for {host} has an empty command");
+            }
+            parsed.push((host.starts_with("*."), HostAllowlist::new(vec![host]), argv));
+        }
+        parsed.sort_by_key(|(wildcard, _, _)| *wildcard);
+        let parsed = parsed
+            .into_iter()
+            .map(|(_, hosts, argv)| (hosts, argv))
+            .collect();
+        Ok(CredentialHelpers {
+            helpers: parsed,
+            cache: Default::default(),
+            finished: Default::default(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.helpers.is_empty()
+    }
+
+    /// The Authorization header to send with a request for `url`, if any.
+    pub fn authorization(&self, url: &Url) -> Result<Option<String>> {
+        if url.scheme() != "https" {
+            return Ok(None);
+        }
+        let argv = match self.helpers.iter().find(|(hosts, _)| hosts.allows(url)) {
+            Some((_, argv)) => argv,
+            None => return Ok(None),
+        };
+        let server = url.origin().ascii_serialization();
+        let mut cache = self.cache.lock().unwrap();
+        // If someone's already running the helper for this server -- say, in a burst
+        // of parallel fetches -- wait for them instead of running it again.
+        loop {
+            match cache.get(&server) {
+                Some(Slot::Ready(header)) => return Ok(header.clone()),
+                Some(Slot::Running) => cache = self.finished.wait(cache).unwrap(),
+                None => break,
+            }
+        }
+        cache.insert(server.clone(), Slot::Running);
+        // (and not holding the lock while it runs, so other hosts don't have to wait)
+        drop(cache);
+        let result = run_helper(argv, &server)
+            .wrap_err_with(|| format!("getting credentials for {server}"));
+        let mut cache = self.cache.lock().unwrap();
+        match &result {
+            Ok(header) => cache.insert(server, Slot::Ready(header.clone())),
+            // so the next request tries again
+            Err(_) => cache.remove(&server),
+        };
+        self.finished.notify_all();
+        result
+    }
+
+    /// Forgets the credentials for `url`'s server, so the next `authorization` runs
+    /// the helper again. For when the server turns down `rejected` -- they've probably
+    /// expired. If we've already replaced them with something else, those are kept.
+    pub fn forget(&self, url: &Url, rejected: &str) {
+        let server = url.origin().ascii_serialization();
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(Slot::Ready(Some(header))) = cache.get(&server) {
+            if header == rejected {
+                cache.remove(&server);
+            }
+        }
+    }
+}
+
+fn run_helper(argv: &[String], server: &str) -> Result<Option<String>> {
+    debug!("running credential helper {argv:?} for {server}");
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("couldn't run credential helper {:?}", argv[0]))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(format!("{server}\n").as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // docker's helpers say this when they just don't have anything
+        if stderr.contains("credentials not found") {
+            return Ok(None);
+        }
+        bail!(
+            "credential helper {:?} failed ({}): {}",
+            argv[0],
+            output.status,
+            stderr.trim()
+        );
+    }
+    let response: HelperResponse = serde_json::from_slice(&output.stdout)
+        .wrap_err("credential helper printed something that isn't a credential")?;
+    let header = match response.username.as_str() {
+        "" | "<token>" => format!("Bearer {}", response.secret),
+        username => {
+            let pair = format!("{username}:{}", response.secret);
+            format!("Basic {}", data_encoding::BASE64.encode(pair.as_bytes()))
+        }
+    };
+    Ok(Some(header))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn helper_script(dir: &Path, name: &str, body: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_credential_helpers() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let calls = tmp.path().join("calls");
+        // a new token every time
+        let token = helper_script(
+            tmp.path(),
+            "token",
+            &format!(
+                r#"test "$1" = get || exit 1
+                read server
+                echo "$server" >> {calls}
+                n=$(wc -l < {calls} | tr -d ' ')
+                echo '{{"ServerURL": "'$server'", "Secret": "s3cret'$n'"}}'"#,
+                calls = calls.display()
+            ),
+        );
+        let basic = helper_script(
+            tmp.path(),
+            "basic",
+            r#"echo '{"Username": "me", "Secret": "pw"}'"#,
+        );
+        let broken = helper_script(tmp.path(), "broken", "echo nope >&2; exit 1");
+        let helpers = CredentialHelpers::new([
+            ("*.pkg.dev".to_string(), HelperCommand::Program(token)),
+            (
+                "basic.example.com".to_string(),
+                HelperCommand::WithArgs(vec![basic]),
+            ),
+            (
+                "broken.example.com".to_string(),
+                HelperCommand::Program(broken),
+            ),
+        ])?;
+
+        let url = Url::parse("https://us-python.pkg.dev/proj/repo/simple/foo/")?;
+        assert_eq!(
+            helpers.authorization(&url)?.as_deref(),
+            Some("Bearer s3cret1")
+        );
+        // cached, so the helper only ran once
+        helpers.authorization(&url.join("../bar/")?)?;
+        assert_eq!(
+            std::fs::read_to_string(&calls)?,
+            "https://us-python.pkg.dev\n"
+        );
+        // until the server turns it down
+        helpers.forget(&url, "Bearer s3cret1");
+        assert_eq!(
+            helpers.authorization(&url)?.as_deref(),
+            Some("Bearer s3cret2")
+        );
+        // and a stale rejection doesn't throw away the new one
+        helpers.forget(&url, "Bearer s3cret1");
+        assert_eq!(
+            helpers.authorization(&url)?.as_deref(),
+            Some("Bearer s3cret2")
+        );
+
+        let url = Url::parse("https://basic.example.com/simple/")?;
+        assert_eq!(
+            helpers.authorization(&url)?.as_deref(),
+            Some("Basic bWU6cHc=")
+        );
+
+        // not over plain http, and not to anyone else
+        let url = Url::parse("http://basic.example.com/simple/")?;
+        assert_eq!(helpers.authorization(&url)?, None);
+        let url = Url::parse("https://pypi.org/simple/")?;
+        assert_eq!(helpers.authorization(&url)?, None);
+
+        let url = Url::parse("https://broken.example.com/simple/")?;
+        let err = helpers.authorization(&url).unwrap_err();
+        assert!(format!("{err:#}").contains("nope"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_helper_command() {
+        let base = Path::new("/home/me/project");
+        let command: HelperCommand = serde_json::from_str(r#""helper""#).unwrap();
+        assert_eq!(
+            command.rebase(base),
+            HelperCommand::WithArgs(vec!["helper".into()])
+        );
+        let command: HelperCommand =
+            serde_json::from_str(r#"["tools/helper.sh", "--quiet"]"#).unwrap();
+        assert_eq!(
+            command.rebase(base),
+            HelperCommand::WithArgs(vec![
+                "/home/me/project/tools/helper.sh".into(),
+                "--quiet".into()
+            ])
+        );
+    }
+}
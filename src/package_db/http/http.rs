@@ -3,11 +3,14 @@ use crate::seek_slice::SeekSlice;
 
 use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy};
 use std::io::SeekFrom;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 
 use super::super::ArtifactInfo;
 use super::ureq_glue::{do_request_ureq, new_ureq_agent};
-use super::LazyRemoteFile;
+use super::{CredentialHelpers, HostAllowlist, LazyRemoteFile, PipCache};
 use crate::kvstore::{KVFileLock, KVFileStore};
 
 const MAX_REDIRECTS: u16 = 5;
@@ -21,6 +24,11 @@ pub enum CacheStatus {
     StaleAndChanged,
     Miss,
     Uncacheable,
+    // Out of date, but we're offline, so it's the best we've got
+    StaleOffline,
+    // Out of date, but the server couldn't tell us what's new (see
+    // `Http::set_stale_if_error`)
+    StaleIfError,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -81,11 +89,100 @@ fn make_response(
     response
 }
 
-pub struct Http(Rc<HttpInner>);
+// Cheap to clone, and Send, so fetches can be farmed out to tokio's blocking pool.
+#[derive(Clone)]
+pub struct Http(Arc<HttpInner>);
 
 impl Http {
     pub fn new(http_cache: KVFileStore, hash_cache: KVFileStore) -> Http {
-        Http(Rc::new(HttpInner::new(http_cache, hash_cache)))
+        Http(Arc::new(HttpInner::new(http_cache, hash_cache)))
+    }
+
+    /// Only talk to these hosts. Has to be called before this gets shared.
+    pub fn set_allowlist(&mut self, allowlist: Option<HostAllowlist>) {
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .allowlist = allowlist;
+    }
+
+    /// Check pip's cache (see `PipCache`) before downloading anything we know the hash
+    /// of. Has to be called before this gets shared.
+    pub fn set_pip_cache(&mut self, pip_cache: Option<PipCache>) {
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .pip_cache = pip_cache;
+    }
+
+    /// Sends everything through `proxy`. Has to be called before this gets shared.
+    pub fn set_proxy(&mut self, proxy: Option<&str>) -> Result<()> {
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .agent = new_ureq_agent(proxy)?;
+        Ok(())
+    }
+
+    /// Ask these for credentials before talking to the hosts they cover. Has to be
+    /// called before this gets shared.
+    pub fn set_credential_helpers(&mut self, helpers: CredentialHelpers) {
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .credentials = helpers;
+    }
+
+    /// Never touch the network: answer everything from the cache, however stale, and
+    /// fail with `NetworkError::Offline` for anything that isn't there. Has to be
+    /// called before this gets shared.
+    pub fn set_offline(&mut self, offline: bool) {
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .offline = offline;
+    }
+
+    pub fn offline(&self) -> bool {
+        self.0.offline
+    }
+
+    /// The hosts we're allowed to talk to, if we're limited; see `set_allowlist`.
+    pub fn allowlist(&self) -> Option<&HostAllowlist> {
+        self.0.allowlist.as_ref()
+    }
+
+    /// When a cache entry needs revalidating, but the server is down (we can't connect,
+    /// or it answers with a 5xx), use the old copy anyway -- with a warning -- as long
+    /// as it went stale no more than `max_stale` ago. Index pages only go stale after
+    /// a few minutes, so even a small value gets through most outages. None means
+    /// fail as usual. Has to be called before this gets shared.
+    pub fn set_stale_if_error(&mut self, max_stale: Option<Duration>) {
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .stale_if_error = max_stale;
+    }
+
+    /// How many background fetches (see `spawn_limited`) can run at once. None means
+    /// as many as tokio's blocking pool allows. Has to be called before this gets
+    /// shared.
+    pub fn set_concurrency(&mut self, concurrency: Option<usize>) {
+        let permits = concurrency.unwrap_or(Semaphore::MAX_PERMITS).max(1);
+        Arc::get_mut(&mut self.0)
+            .expect("Http is already shared")
+            .fetch_limit = Arc::new(Semaphore::new(permits));
+    }
+
+    /// Runs `f` on tokio's blocking pool, once there's room under the concurrency
+    /// limit. Waiting for room happens here, so the returned handle only has to wait
+    /// for `f` itself.
+    pub async fn spawn_limited<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // (the semaphore is never closed)
+        let permit = self.0.fetch_limit.clone().acquire_owned().await.unwrap();
+        tokio::task::spawn_blocking(move || {
+            let result = f();
+            drop(permit);
+            result
+        })
     }
 
     pub fn request(
@@ -105,16 +202,39 @@ impl Http {
         self.0.get_hashed(url, maybe_hash, cache_mode)
     }
 
+    /// Starts downloading `url` into the by-hash cache in the background, so a later
+    /// `get_hashed` finds it there. Runs on tokio's blocking pool, since ureq is
+    /// synchronous, so it has to be called from inside the runtime.
+    pub async fn prefetch_hashed(
+        &self,
+        url: Url,
+        hash: ArtifactHash,
+    ) -> JoinHandle<Result<()>> {
+        let http = self.clone();
+        self.spawn_limited(move || {
+            timing!("download");
+            http.get_hashed(&url, Some(&hash), CacheMode::Default)
+                .map(drop)
+                .wrap_err_with(|| format!("prefetching {url}"))
+        })
+        .await
+    }
+
     pub fn get_lazy(&self, ai: &ArtifactInfo) -> Result<Box<dyn ReadPlusSeek>> {
+        // Range requests can't be answered from the cache, but the whole file might be.
+        // And local files are right there anyway.
+        if self.0.offline || ai.url.scheme() == "file" {
+            return self.get_hashed(&ai.url, ai.hash(), CacheMode::Default);
+        }
         match LazyRemoteFile::new(self.0.clone(), &ai.url) {
             Ok(lazy) => Ok(Box::new(lazy)),
             Err(err) => {
-                match err.downcast_ref::<PosyError>() {
+                match err.downcast_ref::<NetworkError>() {
                     // Doesn't support Range: requests, or similar issue. Fall back on
                     // fetching the whole file via the normal path.
-                    Some(PosyError::LazyRemoteFileNotSupported) => Ok(
-                        self.get_hashed(&ai.url, ai.hash.as_ref(), CacheMode::Default)?
-                    ),
+                    Some(NetworkError::RangeRequestsNotSupported) => {
+                        Ok(self.get_hashed(&ai.url, ai.hash(), CacheMode::Default)?)
+                    }
                     _ => Err(err)?,
                 }
             }
@@ -126,6 +246,12 @@ pub struct HttpInner {
     agent: ureq::Agent,
     http_cache: KVFileStore,
     hash_cache: KVFileStore,
+    allowlist: Option<HostAllowlist>,
+    pip_cache: Option<PipCache>,
+    credentials: CredentialHelpers,
+    offline: bool,
+    stale_if_error: Option<Duration>,
+    fetch_limit: Arc<Semaphore>,
 }
 
 // pass in Option<ArtifactHash> to request/request_if_cached, thread through to fill_cache
@@ -180,6 +306,31 @@ where
     Ok((policy, body))
 }
 
+/// The cached response for `request`, as long as it's gone stale for no more than
+/// `max_stale` (None means however long) -- and the server didn't say it must never be
+/// used stale (`must-revalidate`).
+fn accept_stale(
+    policy: &CachePolicy,
+    request: &http::Request<()>,
+    max_stale: Option<Duration>,
+) -> Option<http::response::Parts> {
+    let mut relaxed = http::Request::new(());
+    *relaxed.method_mut() = request.method().clone();
+    *relaxed.uri_mut() = request.uri().clone();
+    *relaxed.headers_mut() = request.headers().clone();
+    let directive = match max_stale {
+        Some(max_stale) => format!("max-stale={}", max_stale.as_secs()),
+        None => "max-stale".into(),
+    };
+    relaxed
+        .headers_mut()
+        .insert(http::header::CACHE_CONTROL, directive.try_into().unwrap());
+    match policy.before_request(&relaxed, SystemTime::now()) {
+        BeforeRequest::Fresh(parts) => Some(parts),
+        BeforeRequest::Stale { .. } => None,
+    }
+}
+
 fn key_for_request<T>(req: &http::Request<T>) -> Vec<u8> {
     let mut key: Vec<u8> = Default::default();
     let method = req.method().to_string().into_bytes();
@@ -193,12 +344,54 @@ fn key_for_request<T>(req: &http::Request<T>) -> Vec<u8> {
     key
 }
 
+/// How often to report on a download that's still going, for big artifacts.
+const PROGRESS_INTERVAL: u64 = 16 << 20;
+
+/// std::io::copy, plus "download-progress" events along the way.
+fn copy_with_progress<R: Read, W: Write>(
+    url: &Url,
+    body: &mut R,
+    out: &mut W,
+) -> Result<u64> {
+    let mut buf = vec![0; 256 * 1024];
+    let mut bytes = 0;
+    let mut next_report = PROGRESS_INTERVAL;
+    loop {
+        let n = match body.read(&mut buf) {
+            Ok(0) => return Ok(bytes),
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        out.write_all(&buf[..n])?;
+        bytes += n as u64;
+        if bytes >= next_report {
+            progress!("download-progress", url = %url, bytes);
+            next_report += PROGRESS_INTERVAL;
+        }
+    }
+}
+
 impl HttpInner {
     pub fn new(http_cache: KVFileStore, hash_cache: KVFileStore) -> HttpInner {
         HttpInner {
-            agent: new_ureq_agent(),
+            // (only parsing a proxy can fail)
+            agent: new_ureq_agent(None).unwrap(),
             http_cache,
             hash_cache,
+            allowlist: None,
+            pip_cache: None,
+            credentials: CredentialHelpers::default(),
+            offline: false,
+            stale_if_error: None,
+            fetch_limit: Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+        }
+    }
+
+    fn offline_error(request: &http::Request<()>) -> eyre::Report {
+        match Url::parse(&request.uri().to_string()) {
+            Ok(url) => NetworkError::Offline { url }.into(),
+            Err(err) => err.into(),
         }
     }
 
@@ -208,6 +401,9 @@ impl HttpInner {
         cache_mode: CacheMode,
     ) -> Result<http::Response<ReadPlusMaybeSeek>> {
         if cache_mode == CacheMode::NoStore {
+            if self.offline {
+                return Err(HttpInner::offline_error(request));
+            }
             let (parts, body) = do_request_ureq(&self.agent, request)?.into_parts();
             Ok(make_response(
                 parts,
@@ -261,8 +457,42 @@ impl HttpInner {
                         if cache_mode == CacheMode::OnlyIfCached {
                             return Err(NotCached {}.into());
                         }
+                        if self.offline {
+                            return match accept_stale(&old_policy, request, None) {
+                                Some(parts) => Ok(make_response(
+                                    parts,
+                                    ReadPlusMaybeSeek::CanSeek(Box::new(old_body)),
+                                    CacheStatus::StaleOffline,
+                                )),
+                                None => Err(HttpInner::offline_error(request)),
+                            };
+                        }
+                        // worked out up front, while we still have the original request
+                        let fallback = self.stale_if_error.and_then(|max_stale| {
+                            accept_stale(&old_policy, request, Some(max_stale))
+                        });
                         let request = http::Request::from_parts(new_parts, ());
-                        let response = do_request_ureq(&self.agent, &request)?;
+                        let result = do_request_ureq(&self.agent, &request);
+                        let failure = match &result {
+                            Ok(response) if response.status().is_server_error() => {
+                                Some(format!("status {}", response.status()))
+                            }
+                            Ok(_) => None,
+                            Err(err) => Some(format!("{err:#}")),
+                        };
+                        if let (Some(failure), Some(parts)) = (failure, fallback) {
+                            warn!(
+                                "couldn't reach {} ({failure}), so using an \
+                                 out-of-date cached copy",
+                                request.uri()
+                            );
+                            return Ok(make_response(
+                                parts,
+                                ReadPlusMaybeSeek::CanSeek(Box::new(old_body)),
+                                CacheStatus::StaleIfError,
+                            ));
+                        }
+                        let response = result?;
                         match old_policy.after_response(
                             &request,
                             &response,
@@ -294,6 +524,9 @@ impl HttpInner {
                 if cache_mode == CacheMode::OnlyIfCached {
                     return Err(NotCached {}.into());
                 }
+                if self.offline {
+                    return Err(HttpInner::offline_error(request));
+                }
                 let response = do_request_ureq(&self.agent, request)?;
                 let new_policy = CachePolicy::new(request, &response);
                 let (parts, body) = response.into_parts();
@@ -314,7 +547,23 @@ impl HttpInner {
         };
         for attempt in 0..=max_redirects {
             let url = Url::parse(&request.uri().to_string())?;
+            // every time around, so that redirects can't take us anywhere else either
+            if let Some(allowlist) = &self.allowlist {
+                allowlist.check(&url)?;
+            }
+            // also every time around, so a redirect elsewhere doesn't take the last
+            // host's credentials with it
+            let sent = self.authorize(&mut request, &url)?;
             let mut response = self.one_request(&request, cache_mode)?;
+            if let (Some(rejected), 401 | 403) = (&sent, response.status().as_u16()) {
+                // They've probably expired, so get fresh ones and try once more
+                debug!("{url} turned down our credentials; asking the helper again");
+                self.credentials.forget(&url, rejected);
+                let fresh = self.authorize(&mut request, &url)?;
+                if fresh.is_some() && fresh != sent {
+                    response = self.one_request(&request, cache_mode)?;
+                }
+            }
             if REDIRECT_STATUSES.contains(&response.status().as_u16()) {
                 if attempt < max_redirects {
                     if let Some(target) = response.headers().get("Location") {
@@ -324,7 +573,7 @@ impl HttpInner {
                         continue;
                     }
                 } else {
-                    bail!("hit redirection limit at {}", url);
+                    return Err(NetworkError::TooManyRedirects { url }.into());
                 }
             }
             // attach the actual URL to the response, so our caller knows where it came
@@ -335,21 +584,53 @@ impl HttpInner {
         unreachable!()
     }
 
+    /// Puts the credentials for `url` on `request`, if a helper has any, replacing
+    /// whatever was there before. Returns what it put there.
+    fn authorize(
+        &self,
+        request: &mut http::Request<()>,
+        url: &Url,
+    ) -> Result<Option<String>> {
+        let headers = request.headers_mut();
+        headers.remove(http::header::AUTHORIZATION);
+        if self.offline {
+            return Ok(None);
+        }
+        let authorization = self.credentials.authorization(url)?;
+        if let Some(value) = &authorization {
+            let mut value: http::HeaderValue = value.as_str().try_into()?;
+            value.set_sensitive(true);
+            headers.insert(http::header::AUTHORIZATION, value);
+        }
+        Ok(authorization)
+    }
+
     pub fn get_hashed(
         &self,
         url: &Url,
         maybe_hash: Option<&ArtifactHash>,
         cache_mode: CacheMode,
     ) -> Result<Box<dyn ReadPlusSeek>> {
+        if url.scheme() == "file" {
+            return self.get_local(url, maybe_hash);
+        }
         let request = http::Request::builder().uri(url.as_str()).body(())?;
         match (maybe_hash, cache_mode) {
             (Some(hash), CacheMode::Default) => {
                 Ok(self.hash_cache.get_or_set(&hash, |mut w| {
+                    let from_pip =
+                        self.pip_cache.as_ref().and_then(|c| c.find(url, hash));
+                    if let Some(mut f) = from_pip {
+                        std::io::copy(&mut f, &mut w)?;
+                        return Ok(());
+                    }
+                    progress!("download-start", url = %url);
                     let mut body =
                         self.request(request, CacheMode::NoStore)?.into_body();
-                    let mut checker = hash.checker(&mut w)?;
-                    std::io::copy(&mut body, &mut checker)?;
+                    let mut checker = hash.background_checker(&mut w)?;
+                    let bytes = copy_with_progress(url, &mut body, &mut checker)?;
                     checker.finish()?;
+                    progress!("download-finish", url = %url, bytes);
                     Ok(())
                 })?)
             }
@@ -362,4 +643,65 @@ impl HttpInner {
                 .force_seek()?),
         }
     }
+
+    /// `get_hashed` for a file:// url, e.g. a wheel a requirement points at on the
+    /// local filesystem. It gets copied into the cache too, so we check the hash at the
+    /// same point as for anything else, and later changes to the file can't sneak in.
+    fn get_local(
+        &self,
+        url: &Url,
+        maybe_hash: Option<&ArtifactHash>,
+    ) -> Result<Box<dyn ReadPlusSeek>> {
+        let path = url
+            .to_file_path()
+            .map_err(|()| eyre!("{url} isn't a local path"))?;
+        context!("Reading {}", path.display());
+        match maybe_hash {
+            Some(hash) => Ok(self.hash_cache.get_or_set(&hash, |mut w| {
+                let mut checker = hash.checker(&mut w)?;
+                std::io::copy(&mut std::fs::File::open(&path)?, &mut checker)?;
+                checker.finish()?;
+                Ok(())
+            })?),
+            None => Ok(Box::new(std::fs::File::open(&path)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy(cache_control: &str) -> (http::Request<()>, CachePolicy) {
+        let request = http::Request::builder()
+            .uri("https://example.com/simple/foo/")
+            .body(())
+            .unwrap();
+        // fresh for a minute, but we got it an hour ago
+        let response = http::Response::builder()
+            .status(200)
+            .header("cache-control", cache_control)
+            .header("age", "3600")
+            .body(())
+            .unwrap();
+        let policy = CachePolicy::new(&request, &response);
+        (request, policy)
+    }
+
+    #[test]
+    fn test_accept_stale() {
+        let (request, stale) = policy("max-age=60");
+        assert!(matches!(
+            stale.before_request(&request, SystemTime::now()),
+            BeforeRequest::Stale { .. }
+        ));
+        assert!(accept_stale(&stale, &request, None).is_some());
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert!(accept_stale(&stale, &request, Some(day)).is_some());
+        let minute = Duration::from_secs(60);
+        assert!(accept_stale(&stale, &request, Some(minute)).is_none());
+
+        let (request, strict) = policy("max-age=60, must-revalidate");
+        assert!(accept_stale(&strict, &request, None).is_none());
+    }
 }
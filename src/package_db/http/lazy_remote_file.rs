@@ -4,13 +4,14 @@ use super::http::{CacheMode, HttpInner};
 use std::cmp;
 use std::collections::BTreeMap;
 use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
 
 // semi-arbitrary, but ideally should be large enough to catch all the zip index +
 // dist-info data at the end of common wheel files
 const LAZY_FETCH_SIZE: u64 = 10_000;
 
 pub struct LazyRemoteFile {
-    http: Rc<HttpInner>,
+    http: Arc<HttpInner>,
     url: Url,
     loaded: BTreeMap<u64, Vec<u8>>,
     length: u64,
@@ -239,7 +240,7 @@ impl Read for LazyRemoteFile {
 }
 
 impl LazyRemoteFile {
-    pub fn new(http: Rc<HttpInner>, url: &Url) -> Result<LazyRemoteFile> {
+    pub fn new(http: Arc<HttpInner>, url: &Url) -> Result<LazyRemoteFile> {
         context!("Fetching metadata for {url}");
         // Instead of doing a HEAD request to get the length, it would be more efficient
         // to fetch the end of the file and the length in a single Range: request
@@ -254,7 +255,7 @@ impl LazyRemoteFile {
         let length = match fetch_range(&http, "HEAD", url, "bytes=0-1")? {
             RangeResponse::NotSatisfiable { total_len } => total_len,
             RangeResponse::Partial { total_len, .. } => total_len,
-            RangeResponse::Complete(_) => Err(PosyError::LazyRemoteFileNotSupported)?,
+            RangeResponse::Complete(_) => Err(NetworkError::RangeRequestsNotSupported)?,
         };
         Ok(LazyRemoteFile {
             http,
@@ -275,13 +276,13 @@ mod test {
 
     use super::*;
 
-    fn tmp_http() -> (tempfile::TempDir, Rc<HttpInner>) {
+    fn tmp_http() -> (tempfile::TempDir, Arc<HttpInner>) {
         let caches = tempfile::tempdir().unwrap();
         let http = HttpInner::new(
             KVFileStore::new(&caches.path().join("http")).unwrap(),
             KVFileStore::new(&caches.path().join("hashed")).unwrap(),
         );
-        (caches, Rc::new(http))
+        (caches, Arc::new(http))
     }
 
     #[test]
@@ -1,7 +1,13 @@
+mod allowlist;
+mod credentials;
 mod http;
 pub mod lazy_remote_file;
+mod pip_cache;
 pub mod ureq_glue;
 pub mod user_agent;
 
+pub use self::allowlist::HostAllowlist;
+pub use self::credentials::{CredentialHelpers, HelperCommand};
 pub use self::http::{CacheMode, Http, HttpInner, NotCached};
 pub use self::lazy_remote_file::LazyRemoteFile;
+pub use self::pip_cache::PipCache;
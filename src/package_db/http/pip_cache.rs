@@ -0,0 +1,171 @@
+use crate::prelude::*;
+use crate::util::url_filename;
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha224};
+use std::fs::{self, File};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+/// pip's cache directory, which we peek into before downloading an artifact, so that
+/// switching to posy doesn't mean re-downloading everything pip already has. We never
+/// write to it, and anything we find there has to match the hash we're looking for,
+/// same as if we'd downloaded it -- so whatever pip left lying around, the worst case
+/// is that we ignore it.
+///
+/// There are two places we look:
+///
+/// - `http-v2/`: pip's HTTP cache (pip 23.3 and later). Each response body lives in
+///   its own file, at a path derived from the url, so this is a cheap lookup. The
+///   older `http/` layout keeps bodies inside msgpack blobs, and we don't bother with
+///   it.
+/// - `wheels/`: wheels that pip built from sdists. These are in directories named
+///   after what pip built them from, so we just go by filename. They only match a
+///   hash from the index if the build was reproducible, so hits here are rare.
+pub struct PipCache {
+    root: PathBuf,
+    // filename -> paths under wheels/, filled in the first time we need it
+    wheels: OnceCell<HashMap<String, Vec<PathBuf>>>,
+}
+
+impl PipCache {
+    pub fn new(root: &Path) -> PipCache {
+        PipCache {
+            root: root.into(),
+            wheels: OnceCell::new(),
+        }
+    }
+
+    /// Where pip keeps its cache on this machine: `$PIP_CACHE_DIR`, or else the
+    /// platform's usual spot.
+    #[cfg_attr(not(feature = "cli"), allow(dead_code))]
+    pub fn default_location() -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os("PIP_CACHE_DIR") {
+            return Some(dir.into());
+        }
+        let cache_dir = directories::BaseDirs::new()?.cache_dir().to_owned();
+        if cfg!(windows) {
+            Some(cache_dir.join("pip").join("Cache"))
+        } else {
+            Some(cache_dir.join("pip"))
+        }
+    }
+
+    /// Where pip's HTTP cache would keep the body of `url`. pip's cachecontrol keys
+    /// its entries by the url without the fragment, and spreads them out by the first
+    /// five hex digits of the key's sha224.
+    fn http_body_path(&self, url: &Url) -> PathBuf {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        let key = data_encoding::HEXLOWER.encode(&Sha224::digest(url.as_str()));
+        let mut path = self.root.join("http-v2");
+        for c in key[..5].chars() {
+            path.push(c.to_string());
+        }
+        path.push(format!("{key}.body"));
+        path
+    }
+
+    fn wheels(&self) -> &HashMap<String, Vec<PathBuf>> {
+        self.wheels.get_or_init(|| {
+            let mut wheels: HashMap<String, Vec<PathBuf>> = HashMap::new();
+            let mut todo = vec![self.root.join("wheels")];
+            while let Some(dir) = todo.pop() {
+                let Ok(entries) = fs::read_dir(&dir) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    match entry.file_type() {
+                        Ok(t) if t.is_dir() => todo.push(path),
+                        Ok(t) if t.is_file() => {
+                            if let Some(name) =
+                                path.file_name().and_then(|n| n.to_str())
+                            {
+                                if name.ends_with(".whl") {
+                                    wheels.entry(name.into()).or_default().push(path);
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            wheels
+        })
+    }
+
+    /// A copy of `url` from pip's cache, if there is one that matches `hash`.
+    pub fn find(&self, url: &Url, hash: &ArtifactHash) -> Option<File> {
+        let filename = url_filename(url)?;
+        let wheels = match filename.ends_with(".whl") {
+            true => self.wheels().get(&filename).map(|p| p.as_slice()),
+            false => None,
+        };
+        std::iter::once(self.http_body_path(url))
+            .chain(wheels.unwrap_or_default().iter().cloned())
+            .find_map(|path| {
+                let mut f = File::open(&path).ok()?;
+                let mut checker = hash.checker(std::io::sink()).ok()?;
+                std::io::copy(&mut f, &mut checker).ok()?;
+                if let Err(err) = checker.finish() {
+                    debug!("ignoring {} from pip's cache: {err}", path.display());
+                    return None;
+                }
+                f.seek(SeekFrom::Start(0)).ok()?;
+                debug!("using {} from pip's cache", path.display());
+                Some(f)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pip_cache() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cache = PipCache::new(tmp.path());
+        let body = b"pretend this is a wheel";
+        let hash = {
+            let digest = ring::digest::digest(&ring::digest::SHA256, body);
+            ArtifactHash {
+                mode: "sha256".into(),
+                raw_data: digest.as_ref().to_vec(),
+            }
+        };
+        let wrong_hash = ArtifactHash::from_hex("sha256", &"ab".repeat(32))?;
+
+        // cachecontrol's key for this url (computed with hashlib)
+        let url = Url::parse(
+            "https://files.pythonhosted.org/packages/\
+             ab/cd/attrs-22.2.0-py3-none-any.whl#sha256=abcd",
+        )?;
+        let key = "65476856b0ef4bc4a7f64943172c7899b7aea0f3412660a055a246b6";
+        let path = cache.http_body_path(&url);
+        assert_eq!(
+            path,
+            tmp.path()
+                .join("http-v2/6/5/4/7/6")
+                .join(format!("{key}.body"))
+        );
+        assert!(cache.find(&url, &hash).is_none());
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, body)?;
+        let mut found = cache.find(&url, &hash).unwrap();
+        assert_eq!(slurp(&mut found)?, body);
+        assert!(cache.find(&url, &wrong_hash).is_none());
+
+        // wheels pip built itself get found by filename (and since wheels/ only gets
+        // scanned once, we need a fresh PipCache to see the new one)
+        let url = Url::parse("https://example.com/foo-1.0-py3-none-any.whl")?;
+        assert!(cache.find(&url, &hash).is_none());
+        let cache = PipCache::new(tmp.path());
+        let wheel_dir = tmp.path().join("wheels/aa/bb/cc/ddeeff");
+        fs::create_dir_all(&wheel_dir)?;
+        fs::write(wheel_dir.join("foo-1.0-py3-none-any.whl"), body)?;
+        assert!(cache.find(&url, &hash).is_some());
+        assert!(cache.find(&url, &wrong_hash).is_none());
+        Ok(())
+    }
+}
@@ -6,14 +6,20 @@ use ureq::{Agent, AgentBuilder, Error::*, OrAnyStatus};
 
 use super::user_agent::user_agent;
 
-pub fn new_ureq_agent() -> Agent {
-    AgentBuilder::new()
+/// `proxy` is a URL like `http://proxy.example.com:3128`; see `ureq::Proxy::new`.
+pub fn new_ureq_agent(proxy: Option<&str>) -> Result<Agent> {
+    let mut builder = AgentBuilder::new()
         .user_agent(&user_agent())
         // we handle redirects in the caching layer
         .redirects(0)
         .timeout_read(Duration::from_secs(15))
-        .timeout_write(Duration::from_secs(15))
-        .build()
+        .timeout_write(Duration::from_secs(15));
+    if let Some(proxy) = proxy {
+        let proxy = ureq::Proxy::new(proxy)
+            .wrap_err_with(|| format!("invalid proxy {proxy:?}"))?;
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build())
 }
 
 const SLEEP_TIMES: &[u64] = &[250, 500, 1000, 2000, 4000]; // milliseconds
@@ -66,12 +72,14 @@ pub fn do_request_ureq(
     agent: &Agent,
     req: &http::Request<()>,
 ) -> Result<http::Response<impl Read>> {
-    let mut ureq_req =
-        agent.request_url(req.method().as_str(), &Url::parse(&req.uri().to_string())?);
+    let url = Url::parse(&req.uri().to_string())?;
+    let mut ureq_req = agent.request_url(req.method().as_str(), &url);
     for (name, value) in req.headers().into_iter() {
         ureq_req = ureq_req.set(name.as_str(), std::str::from_utf8(value.as_bytes())?);
     }
-    let ureq_response = call_with_retry(ureq_req).or_any_status()?;
+    let ureq_response = call_with_retry(ureq_req)
+        .or_any_status()
+        .map_err(|source| NetworkError::Transport { url, source })?;
     let mut response = http::Response::builder().status(ureq_response.status());
     for name in ureq_response.headers_names() {
         for value in ureq_response.all(&name) {
@@ -0,0 +1,132 @@
+use crate::prelude::*;
+use ring::digest;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories that show up in source checkouts, but aren't part of the source, and are
+/// often huge. We skip them when deciding whether the tree has changed, and when
+/// copying it to build.
+const IGNORED_DIRS: &[&str] = &[
+    ".git",
+    ".hg",
+    ".svn",
+    ".venv",
+    ".tox",
+    ".nox",
+    ".mypy_cache",
+    ".pytest_cache",
+    "__pycache__",
+];
+
+/// Build output directories. These names are reasonable for real source directories
+/// too, so we only skip them at the top level.
+const IGNORED_TOP_LEVEL_DIRS: &[&str] = &["build", "dist", "target"];
+
+fn ignored(prefix: &str, name: &str) -> bool {
+    IGNORED_DIRS.contains(&name)
+        || (prefix.is_empty() && IGNORED_TOP_LEVEL_DIRS.contains(&name))
+        || name.ends_with(".egg-info")
+}
+
+/// A source tree on the local filesystem -- e.g. the project we're working on -- that
+/// we can build like an sdist.
+///
+/// It's identified by a hash of its contents, so that we can tell when it needs to be
+/// rebuilt.
+#[derive(Debug, Clone)]
+pub struct LocalTree {
+    pub name: PackageName,
+    pub root: PathBuf,
+    pub hash: ArtifactHash,
+}
+
+impl LocalTree {
+    pub fn new(name: PackageName, root: &Path) -> Result<LocalTree> {
+        context!("Scanning {}", root.display());
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        for (relpath, path) in files(root)? {
+            let contents = fs::read(&path)?;
+            ctx.update(relpath.as_bytes());
+            ctx.update(b"\0");
+            ctx.update(&(contents.len() as u64).to_le_bytes());
+            ctx.update(&contents);
+        }
+        Ok(LocalTree {
+            name,
+            root: root.to_owned(),
+            hash: ArtifactHash {
+                mode: "sha256".into(),
+                raw_data: ctx.finish().as_ref().into(),
+            },
+        })
+    }
+
+    /// Copies the tree into `dest`, so the build backend can make a mess without
+    /// touching the user's checkout.
+    pub fn copy_to(&self, dest: &Path) -> Result<()> {
+        for (relpath, path) in files(&self.root)? {
+            let target = dest.join(relpath);
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::copy(&path, &target)?;
+        }
+        Ok(())
+    }
+}
+
+/// All the files in the tree that we care about, as (relative path with / separators,
+/// full path), sorted.
+fn files(root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut found = Vec::new();
+    let mut todo = vec![(String::new(), root.to_owned())];
+    while let Some((prefix, dir)) = todo.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                eyre!("non-unicode filename {:?} in {}", name, dir.display())
+            })?;
+            let relpath = format!("{prefix}{name}");
+            if entry.file_type()?.is_dir() {
+                if !ignored(&prefix, &name) {
+                    todo.push((format!("{relpath}/"), entry.path()));
+                }
+            } else {
+                found.push((relpath, entry.path()));
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local_tree_hash() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        fs::create_dir_all(tmp.path().join("src/pkg"))?;
+        fs::write(
+            tmp.path().join("pyproject.toml"),
+            "[project]\nname = 'pkg'\n",
+        )?;
+        fs::write(tmp.path().join("src/pkg/__init__.py"), "x = 1\n")?;
+        let name: PackageName = "pkg".parse()?;
+        let before = LocalTree::new(name.clone(), tmp.path())?;
+
+        // junk doesn't count
+        fs::create_dir_all(tmp.path().join("src/pkg/__pycache__"))?;
+        fs::write(tmp.path().join("src/pkg/__pycache__/x.pyc"), "junk")?;
+        assert_eq!(LocalTree::new(name.clone(), tmp.path())?.hash, before.hash);
+
+        // but real changes do
+        fs::write(tmp.path().join("src/pkg/__init__.py"), "x = 2\n")?;
+        assert_ne!(LocalTree::new(name, tmp.path())?.hash, before.hash);
+
+        let dest = tempfile::tempdir()?;
+        before.copy_to(dest.path())?;
+        assert!(dest.path().join("src/pkg/__init__.py").exists());
+        assert!(!dest.path().join("src/pkg/__pycache__").exists());
+        Ok(())
+    }
+}
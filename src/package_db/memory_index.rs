@@ -0,0 +1,185 @@
+use crate::prelude::*;
+
+use super::simple_api::ArtifactInfo;
+
+/// Somewhere besides a real index that a PackageDB can get packages from, without any
+/// network: which files each package has, and what's in their METADATA. That's all
+/// resolving needs; installing also has to be able to download the files' urls.
+///
+/// `MemoryIndex` is the simple version. To serve packages out of your own storage
+/// instead, implement this and hand it to `PackageDB::in_memory`.
+pub trait PackageSource {
+    /// All of `p`'s files, or None if we don't have it at all.
+    fn artifacts(&self, p: &PackageName) -> Option<Vec<ArtifactInfo>>;
+
+    /// The METADATA of one of the files from `artifacts`, if we have it. Otherwise the
+    /// PackageDB looks it up the same way as for anything from an index.
+    fn metadata(&self, ai: &ArtifactInfo) -> Option<&[u8]>;
+}
+
+/// Packages that only exist in memory, for resolving against without a real index or
+/// any network -- mostly for tests. Each artifact is just a name plus its METADATA;
+/// there's nothing behind the url, so resolving works but installing doesn't.
+///
+/// Use it with `PackageDB::in_memory`. It's a `PackageSource`, like any other.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIndex {
+    artifacts: HashMap<PackageName, Vec<ArtifactInfo>>,
+    metadata: HashMap<Url, Vec<u8>>,
+}
+
+impl MemoryIndex {
+    pub fn new() -> MemoryIndex {
+        Default::default()
+    }
+
+    /// Adds an artifact called `filename` (a wheel, pybi, or sdist), with `metadata`
+    /// as its METADATA. Returns it, so the caller can fill in things like
+    /// `requires_python` or `yanked`.
+    pub fn add(
+        &mut self,
+        filename: &str,
+        metadata: &[u8],
+    ) -> Result<&mut ArtifactInfo> {
+        let name: ArtifactName = filename.try_into()?;
+        let url = Url::parse(&format!("memory:///{filename}"))?;
+        // Something stable and unique, since caches are keyed by hash
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        context.update(filename.as_bytes());
+        context.update(metadata);
+        let hash = ArtifactHash {
+            mode: "sha256".into(),
+            raw_data: context.finish().as_ref().to_vec(),
+        };
+        if self.metadata.insert(url.clone(), metadata.into()).is_some() {
+            bail!("{filename} is already in the index");
+        }
+        let artifacts = self
+            .artifacts
+            .entry(name.distribution().clone())
+            .or_default();
+        artifacts.push(ArtifactInfo {
+            name,
+            url,
+            hashes: vec![hash],
+            requires_python: None,
+            dist_info_metadata: Default::default(),
+            yanked: Default::default(),
+            provenance: None,
+            upload_time: None,
+            size: None,
+        });
+        Ok(artifacts.last_mut().unwrap())
+    }
+
+    /// Adds a `py3-none-any` wheel that depends on `requires_dist`.
+    pub fn add_wheel(
+        &mut self,
+        name: &str,
+        version: &str,
+        requires_dist: &[&str],
+    ) -> Result<&mut ArtifactInfo> {
+        let mut metadata =
+            format!("Metadata-Version: 2.1\nName: {name}\nVersion: {version}\n");
+        for req in requires_dist {
+            metadata.push_str(&format!("Requires-Dist: {req}\n"));
+        }
+        self.add(
+            &format!("{name}-{version}-py3-none-any.whl"),
+            metadata.as_bytes(),
+        )
+    }
+
+    /// Adds a CPython pybi for `platform` (e.g. `manylinux_2_17_x86_64`), with enough
+    /// metadata to resolve against.
+    pub fn add_pybi(
+        &mut self,
+        name: &str,
+        version: &str,
+        platform: &str,
+    ) -> Result<&mut ArtifactInfo> {
+        let python_version = version.split('.').take(2).collect::<Vec<_>>().join(".");
+        let markers = serde_json::json!({
+            "implementation_name": "cpython",
+            "implementation_version": version,
+            "platform_python_implementation": "CPython",
+            "python_full_version": version,
+            "python_version": python_version,
+        });
+        let paths = serde_json::json!({
+            "stdlib": format!("lib/python{python_version}"),
+            "purelib": format!("lib/python{python_version}/site-packages"),
+            "platlib": format!("lib/python{python_version}/site-packages"),
+            "scripts": "bin",
+            "data": ".",
+        });
+        let metadata = indoc::formatdoc! {"
+            Metadata-Version: 2.1
+            Name: {name}
+            Version: {version}
+            Pybi-Environment-Marker-Variables: {markers}
+            Pybi-Paths: {paths}
+            Pybi-Wheel-Tag: py3-none-any
+        "};
+        self.add(
+            &format!("{name}-{version}-{platform}.pybi"),
+            metadata.as_bytes(),
+        )
+    }
+}
+
+impl PackageSource for MemoryIndex {
+    fn artifacts(&self, p: &PackageName) -> Option<Vec<ArtifactInfo>> {
+        self.artifacts.get(p).cloned()
+    }
+
+    fn metadata(&self, ai: &ArtifactInfo) -> Option<&[u8]> {
+        self.metadata.get(&ai.url).map(|m| m.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::Brief;
+    use crate::test_util::{test_platform, TestDB};
+
+    #[test]
+    fn test_resolve_in_memory() -> Result<()> {
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &["bar"])?;
+        index.add_wheel("foo", "2.0", &["bar >= 2", "baz; python_version < '3'"])?;
+        index.add_wheel("foo", "3.0", &[])?.requires_python = Some(">= 3.11".into());
+        index.add_wheel("bar", "1.0", &[])?;
+        index.add_wheel("bar", "2.0", &[])?;
+        index.add_wheel("bar", "3.0", &[])?.yanked.yanked = true;
+        assert!(index.add_wheel("bar", "1.0", &[]).is_err());
+
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+        let platform = test_platform();
+        let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+
+        assert_eq!(blueprint.pybi.version.to_string(), "3.10.8");
+        let mut pins: Vec<String> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+            .collect();
+        pins.sort_unstable();
+        assert_eq!(pins, vec!["bar 2.0", "foo 2.0"]);
+
+        // and something that isn't in the index just isn't there
+        let missing: PackageName = "missing".try_into()?;
+        assert!(db.available_artifacts(&missing)?.is_empty());
+        Ok(())
+    }
+}
@@ -1,8 +1,22 @@
+mod attestations;
+mod build_hints;
 mod build_wheel;
+mod git;
 mod http;
+mod local_tree;
+mod memory_index;
 mod package_db;
+mod sigstore;
 mod simple_api;
+mod wheelhouse;
 
+pub use attestations::{AttestationConfig, AttestationRule};
 pub use build_wheel::WheelBuilder;
-pub use package_db::PackageDB;
+pub use http::ureq_glue::new_ureq_agent;
+pub use http::{CredentialHelpers, HelperCommand, HostAllowlist, PipCache};
+pub use local_tree::LocalTree;
+pub use memory_index::{MemoryIndex, PackageSource};
+pub use package_db::{known_package_names, PackageDB};
+pub use sigstore::TrustedRoot;
 pub use simple_api::ArtifactInfo;
+pub use wheelhouse::{Wheelhouse, WHEELHOUSE_MANIFEST};
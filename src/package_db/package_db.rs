@@ -1,27 +1,167 @@
 use crate::env::EnvForest;
+use crate::error::Error;
 use crate::prelude::*;
+use crate::util::did_you_mean;
 use elsa::FrozenMap;
 use indexmap::IndexMap;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
+use super::attestations::{self, AttestationConfig};
 use super::http::{CacheMode, Http, NotCached};
-use super::simple_api::{fetch_simple_api, pack_by_version, ArtifactInfo};
+use super::memory_index::PackageSource;
+use super::simple_api::{
+    cached_simple_api, fetch_simple_api, pack_by_version, ArtifactInfo, ProjectInfo,
+};
+use super::wheelhouse::Wheelhouse;
+use super::{CredentialHelpers, HostAllowlist, LocalTree, PipCache, WheelBuilder};
 use crate::kvstore::{KVDirStore, KVFileStore};
+use crate::platform_tags::PybiPlatform;
+use crate::resolve::{Blueprint, Brief, RequirementSource};
+use crate::resolve::{WheelResolveMetadata, WheelResolveMetadataInner};
+use crate::tree::UnpackLimits;
+use crate::zip_index::ZipIndex;
 
 static NO_ARTIFACTS: [ArtifactInfo; 0] = [];
 
+/// `p`'s page on each of the indexes, if it has one there.
+fn fetch_project_infos(
+    http: &Http,
+    index_urls: &[Url],
+    p: &PackageName,
+) -> Result<Vec<(Url, Option<ProjectInfo>)>> {
+    let mut pages = Vec::new();
+    for index_url in index_urls {
+        let url = index_url.join(&format!("{}/", p.normalized()))?;
+        let pi = fetch_simple_api(http, &url)?;
+        pages.push((url, pi));
+    }
+    Ok(pages)
+}
+
+/// Whether `ai` showed up on the index after `time`, or None if the index didn't say
+/// when it was uploaded.
+fn uploaded_after(ai: &ArtifactInfo, time: SystemTime) -> Option<bool> {
+    let uploaded = parse_timestamp(ai.upload_time.as_deref()?)?;
+    Some(uploaded > time)
+}
+
+/// What an index page said, boiled down to something we can compare later.
+fn page_digest(pi: Option<&ProjectInfo>) -> Result<ArtifactHash> {
+    ArtifactHash::sha256_of(serde_json::to_vec(&pi)?.as_slice())
+}
+
+/// Inside the cache directory, one empty file for every package name we've found on an
+/// index, so shell completion can offer them without touching the network.
+const PACKAGE_NAMES_DIR: &str = "package-names";
+
+/// Inside the cache directory, the parts of each wheel's metadata that the resolver
+/// cares about, as JSON, keyed by artifact hash. Bump the version if the format
+/// changes, so we don't trip over old entries.
+const RESOLVE_METADATA_DIR: &str = "resolve-metadata-v1";
+
+/// Inside the cache directory, a ZipIndex (as CBOR) for each wheel or pybi we've
+/// opened out of the by-hash cache, keyed by the same hash.
+const ZIP_INDEX_DIR: &str = "zip-index-v1";
+
+/// Inside the cache directory, whole resolutions, keyed by everything that went into
+/// them except the index pages; see `PackageDB::cached_resolution`.
+const RESOLUTIONS_DIR: &str = "resolutions-v1";
+
+/// Everything a resolution depends on, besides what the indexes say.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResolutionKey<'a> {
+    brief: &'a Brief,
+    like: Option<ArtifactHash>,
+    platforms: Vec<&'a str>,
+    index_urls: &'a [Url],
+    named_indexes: &'a BTreeMap<String, Url>,
+    build_constraints: Vec<String>,
+    no_binary: Vec<&'a str>,
+    snapshot: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SavedResolution {
+    blueprint: Blueprint,
+    /// Every index page we'd looked at by the time we were done
+    index_pages: BTreeMap<Url, ArtifactHash>,
+}
+
+/// The names of all the packages we've ever looked up successfully that start with
+/// `prefix`, sorted. Best-effort: it's just a hint.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn known_package_names(cache_path: &Path, prefix: &str) -> Vec<String> {
+    names_in(&cache_path.join(PACKAGE_NAMES_DIR), prefix)
+}
+
+fn names_in(dir: &Path, prefix: &str) -> Vec<String> {
+    let prefix = prefix.to_ascii_lowercase().replace(['_', '.'], "-");
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(&prefix))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Everything we know about where to get packages: the indexes, the caches, how to
+/// build sdists, and the rules (policy, hashes, allowed hosts) for what we're willing
+/// to use. Resolving and installing both go through one of these.
 pub struct PackageDB<'a> {
     http: Http,
-    metadata_cache: KVFileStore,
     index_urls: Vec<Url>,
+    // only for the packages that ask for them (see `add_index_source`)
+    named_indexes: BTreeMap<String, Url>,
 
+    pub(super) metadata_cache: KVFileStore,
+    resolve_metadata_cache: KVFileStore,
+    zip_index_cache: KVFileStore,
+    resolutions: KVFileStore,
     pub(super) wheel_cache: KVDirStore,
+    git_checkouts: KVDirStore,
+    pub(super) build_blueprints: KVFileStore,
+    pub(super) build_logs: PathBuf,
+    package_names: PathBuf,
+    pub(super) build_constraints: Vec<UserRequirement>,
+    require_hashes: bool,
+    attestations: Arc<AttestationConfig>,
+    policy: Policy,
+    snapshot: Option<SystemTime>,
+    unpack_limits: UnpackLimits,
+    wheel_preference: WheelPreference,
+    no_binary: HashSet<PackageName>,
     pub(super) build_forest: &'a EnvForest,
     pub(super) build_store: &'a KVDirStore,
+    // packages that don't come from any real index (see `in_memory`)
+    source: Option<Box<dyn PackageSource + 'a>>,
+    // where everything comes from instead of the indexes, if set
+    wheelhouse: Option<Wheelhouse>,
 
     // memo table to make sure we're internally consistent within a single invocation,
     // and to let us return references instead of copying everything everywhere
     artifacts: FrozenMap<PackageName, Box<IndexMap<Version, Vec<ArtifactInfo>>>>,
+    // packages that come from a `name @ url` requirement instead of the index, in
+    // whatever we're resolving or installing right now (see `with_direct_references`)
+    direct_references: Mutex<HashMap<PackageName, Url>>,
+    // what each url we've seen in a direct reference points to
+    direct_artifacts: FrozenMap<Url, Box<(Url, IndexMap<Version, Vec<ArtifactInfo>>)>>,
+    // packages that come from one of `named_indexes`, instead of `index_urls`
+    index_sources: FrozenMap<PackageName, Box<Url>>,
+    // every index page we've fetched, and what it said (see `page_digest`)
+    index_pages: Mutex<BTreeMap<Url, ArtifactHash>>,
+    // each project's last serial, for indexes that tell us (see `last_serial`)
+    serials: FrozenMap<PackageName, Box<u64>>,
+    // artifacts the snapshot couldn't date, per project (see `undated`)
+    undated: FrozenMap<PackageName, Box<usize>>,
 }
 
 impl<'db> PackageDB<'db> {
@@ -30,20 +170,336 @@ impl<'db> PackageDB<'db> {
         cache_path: &Path,
         build_forest: &'db EnvForest,
         build_store: &'db KVDirStore,
-    ) -> Result<PackageDB<'db>> {
+    ) -> Result<PackageDB<'db>, Error> {
         let http_cache = KVFileStore::new(&cache_path.join("http"))?;
         let hash_cache = KVFileStore::new(&cache_path.join("by-hash"))?;
         Ok(PackageDB {
             http: Http::new(http_cache, hash_cache),
             metadata_cache: KVFileStore::new(&cache_path.join("metadata"))?,
+            resolve_metadata_cache: KVFileStore::new(
+                &cache_path.join(RESOLVE_METADATA_DIR),
+            )?,
+            zip_index_cache: KVFileStore::new(&cache_path.join(ZIP_INDEX_DIR))?,
+            resolutions: KVFileStore::new(&cache_path.join(RESOLUTIONS_DIR))?,
             wheel_cache: KVDirStore::new(&cache_path.join("local-wheels"))?,
+            git_checkouts: KVDirStore::new(&cache_path.join("git-checkouts"))?,
+            build_blueprints: KVFileStore::new(&cache_path.join("build-blueprints"))?,
+            build_logs: cache_path.join("build-logs"),
+            package_names: cache_path.join(PACKAGE_NAMES_DIR),
+            build_constraints: Vec::new(),
+            require_hashes: false,
+            attestations: Default::default(),
+            policy: Default::default(),
+            snapshot: None,
+            unpack_limits: Default::default(),
+            wheel_preference: Default::default(),
+            no_binary: Default::default(),
             index_urls: index_urls.into(),
+            named_indexes: Default::default(),
             build_forest,
             build_store,
             artifacts: Default::default(),
+            direct_references: Default::default(),
+            direct_artifacts: Default::default(),
+            index_sources: Default::default(),
+            index_pages: Default::default(),
+            serials: Default::default(),
+            undated: Default::default(),
+            source: None,
+            wheelhouse: None,
         })
     }
 
+    /// A PackageDB that gets everything from `source` (e.g. a `MemoryIndex`) instead
+    /// of going to the network. The on-disk caches under `cache_path` still get used as
+    /// normal.
+    pub fn in_memory(
+        source: impl PackageSource + 'db,
+        cache_path: &Path,
+        build_forest: &'db EnvForest,
+        build_store: &'db KVDirStore,
+    ) -> Result<PackageDB<'db>, Error> {
+        let mut db = PackageDB::new(&[], cache_path, build_forest, build_store)?;
+        db.source = Some(Box::new(source));
+        Ok(db)
+    }
+
+    /// Constraints that apply when resolving build environments for sdists, but not
+    /// anything else. Useful for steering around broken releases of build backends.
+    pub fn set_build_constraints(&mut self, constraints: Vec<UserRequirement>) {
+        self.build_constraints = constraints;
+    }
+
+    /// Refuse to install anything that isn't pinned by the exact hash we check it
+    /// against (like pip's `--require-hashes`).
+    pub fn set_require_hashes(&mut self, require_hashes: bool) {
+        self.require_hashes = require_hashes;
+    }
+
+    pub fn require_hashes(&self) -> bool {
+        self.require_hashes
+    }
+
+    /// Indexes that we only look at for the packages that ask for them, by name; see
+    /// `add_index_source`.
+    pub fn set_named_indexes(&mut self, indexes: BTreeMap<String, Url>) {
+        self.named_indexes = indexes;
+    }
+
+    /// Which packages need PEP 740 attestations, and from whom. Checked before we
+    /// download anything.
+    pub fn set_attestations(&mut self, config: AttestationConfig) {
+        self.attestations = Arc::new(config);
+    }
+
+    pub fn attestations(&self) -> &AttestationConfig {
+        &self.attestations
+    }
+
+    /// What the resolver is and isn't allowed to pick.
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Pretend the indexes are as they were at `time`: anything uploaded after that
+    /// just isn't there. This only works as well as the index's upload times do.
+    /// Artifacts without one (e.g. from indexes that don't have the JSON API) can't be
+    /// dated, so they're let through, with a warning; `undated` says how many there
+    /// were, if you need to be stricter than that.
+    pub fn set_snapshot(&mut self, time: Option<SystemTime>) {
+        self.snapshot = time;
+    }
+
+    pub fn snapshot(&self) -> Option<SystemTime> {
+        self.snapshot
+    }
+
+    /// The index's serial number for the last change to `p`, if we've looked it up on
+    /// an index that says (like PyPI).
+    pub fn last_serial(&self, p: &PackageName) -> Option<u64> {
+        self.serials.get(p).copied()
+    }
+
+    /// How many of `p`'s artifacts the snapshot let through only because we can't
+    /// tell when they were uploaded (see `set_snapshot`).
+    pub fn undated(&self, p: &PackageName) -> usize {
+        self.undated.get(p).copied().unwrap_or(0)
+    }
+
+    /// Refuse to fetch anything from hosts that aren't on `allowlist`. None means
+    /// there's no restriction.
+    pub fn set_allowed_hosts(&mut self, allowlist: Option<HostAllowlist>) {
+        self.http.set_allowlist(allowlist);
+    }
+
+    /// Before downloading an artifact, look for it in pip's cache at `pip_cache_dir`
+    /// (see `PipCache`). None means don't.
+    pub fn set_pip_cache(&mut self, pip_cache_dir: Option<PathBuf>) {
+        self.http
+            .set_pip_cache(pip_cache_dir.map(|dir| PipCache::new(&dir)));
+    }
+
+    /// Sends all our requests through an HTTP(S) proxy, like
+    /// `http://proxy.example.com:3128`.
+    pub fn set_proxy(&mut self, proxy: Option<&str>) -> Result<()> {
+        self.http.set_proxy(proxy)
+    }
+
+    /// Get credentials for private indexes from these; see `CredentialHelpers`.
+    pub fn set_credential_helpers(&mut self, helpers: CredentialHelpers) {
+        self.http.set_credential_helpers(helpers);
+    }
+
+    /// Answer everything from the cache, and fail on anything that isn't there,
+    /// instead of going to the network.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.http.set_offline(offline);
+    }
+
+    pub fn offline(&self) -> bool {
+        self.http.offline()
+    }
+
+    /// Get everything from `wheelhouse` instead of the indexes, and don't go to the
+    /// network for anything else either. Packages it doesn't have just aren't there.
+    pub fn set_wheelhouse(&mut self, wheelhouse: Option<Wheelhouse>) {
+        if wheelhouse.is_some() {
+            self.http.set_offline(true);
+        }
+        self.wheelhouse = wheelhouse;
+    }
+
+    /// If the index is down, serve pages from the cache that went stale up to
+    /// `max_stale` ago, with a warning, instead of failing.
+    pub fn set_stale_if_error(&mut self, max_stale: Option<std::time::Duration>) {
+        self.http.set_stale_if_error(max_stale);
+    }
+
+    /// How many index pages and artifacts to fetch at once, when we're fetching a lot
+    /// of them. None means no particular limit.
+    pub fn set_concurrency(&mut self, concurrency: Option<usize>) {
+        self.http.set_concurrency(concurrency);
+    }
+
+    /// How big the artifacts we unpack are allowed to get.
+    pub fn set_unpack_limits(&mut self, limits: UnpackLimits) {
+        self.unpack_limits = limits;
+    }
+
+    pub fn unpack_limits(&self) -> &UnpackLimits {
+        &self.unpack_limits
+    }
+
+    /// How to choose between the wheels of a pinned version, when more than one will
+    /// work.
+    pub fn set_wheel_preference(&mut self, preference: WheelPreference) {
+        self.wheel_preference = preference;
+    }
+
+    pub fn wheel_preference(&self) -> &WheelPreference {
+        &self.wheel_preference
+    }
+
+    /// Packages to always build from their sdists when we install them, whatever
+    /// wheels they have. Resolving skips any release of these that doesn't have an
+    /// sdist, since we'd have nothing to build.
+    pub fn set_no_binary(&mut self, names: Vec<PackageName>) {
+        self.no_binary = names.into_iter().collect();
+    }
+
+    pub fn no_binary(&self, name: &PackageName) -> bool {
+        self.no_binary.contains(name)
+    }
+
+    /// Until the end of the current `with_direct_references`, the artifact at `url` is
+    /// the only one we know about for `p`, whatever the index says. Has to happen
+    /// before anything looks up `p`'s artifacts, so we're consistent within a single
+    /// resolve.
+    pub fn add_direct_reference(&self, p: &PackageName, url: &Url) -> Result<()> {
+        if let Some(existing) = self.direct_reference(p) {
+            if existing != url {
+                bail!(
+                    "conflicting direct references for {}: {existing} and {url}",
+                    p.as_given()
+                );
+            }
+            return Ok(());
+        }
+        if self.direct_artifacts.get(url).is_none() {
+            let ai = match &self.wheelhouse {
+                Some(wheelhouse) => wheelhouse.direct_reference(url)?,
+                None => ArtifactInfo::from_direct_reference(url)?,
+            };
+            let mut packed: IndexMap<Version, Vec<ArtifactInfo>> = Default::default();
+            packed.insert(ai.name.version().clone(), vec![ai]);
+            self.direct_artifacts
+                .insert(url.clone(), Box::new((url.clone(), packed)));
+        }
+        let (_, packed) = self.direct_artifacts.get(url).unwrap();
+        let (_, ais) = packed.get_index(0).unwrap();
+        let name = ais[0].name.distribution();
+        if name != p {
+            bail!("{url} is for {}, not {}", name.as_given(), p.as_given());
+        }
+        self.direct_references
+            .lock()
+            .unwrap()
+            .insert(p.clone(), url.clone());
+        Ok(())
+    }
+
+    /// The url `p` comes from, if it's from a direct reference.
+    pub fn direct_reference(&self, p: &PackageName) -> Option<&Url> {
+        let url = self.direct_references.lock().unwrap().get(p).cloned()?;
+        self.direct_artifacts.get(&url).map(|(url, _)| url)
+    }
+
+    /// Runs `f` with no direct references, and afterwards puts back whichever ones
+    /// there were before. Resolving or installing an environment happens inside one
+    /// of these, so that the direct references it adds are its own: environments that
+    /// share a PackageDB (or a build environment set up halfway through resolving
+    /// another) can each get a package from somewhere different.
+    pub fn with_direct_references<T>(&self, f: impl FnOnce() -> T) -> T {
+        let outer = std::mem::take(&mut *self.direct_references.lock().unwrap());
+        let result = f();
+        *self.direct_references.lock().unwrap() = outer;
+        result
+    }
+
+    /// Like `add_direct_reference`, for a wheel or sdist on the local filesystem.
+    /// Nobody hands us a hash for those, so we work out our own. Returns the url we
+    /// ended up using.
+    pub fn add_local_file(&self, p: &PackageName, path: &Path) -> Result<Url> {
+        context!("Reading {}", path.display());
+        let path = std::fs::canonicalize(path)?;
+        let contents = std::fs::read(&path)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, &contents);
+        let mut url = Url::from_file_path(&path)
+            .map_err(|()| eyre!("can't make a file url for {}", path.display()))?;
+        url.set_fragment(Some(&format!(
+            "sha256={}",
+            data_encoding::HEXLOWER.encode(digest.as_ref())
+        )));
+        self.add_direct_reference(p, &url)?;
+        Ok(url)
+    }
+
+    /// From now on, `p` only comes from the index that posy.toml's `[indexes]` calls
+    /// `index`, and not from any of the usual ones. Like `add_direct_reference`, this
+    /// has to happen before anything looks up `p`.
+    pub fn add_index_source(&self, p: &PackageName, index: &str) -> Result<()> {
+        let Some(url) = self.named_indexes.get(index) else {
+            let names = self.named_indexes.keys().map(|name| name.as_str());
+            match did_you_mean(index, names).first() {
+                Some(close) => {
+                    bail!("no index named {index:?} (did you mean {close:?}?)")
+                }
+                None => bail!("no index named {index:?} in posy.toml's [indexes]"),
+            }
+        };
+        if let Some(existing) = self.index_sources.get(p) {
+            if existing != url {
+                bail!("{} can't come from both {existing} and {url}", p.as_given());
+            }
+            return Ok(());
+        }
+        if self.artifacts.get(p).is_some() {
+            bail!(
+                "can't get {} from the {index:?} index, after already using the others",
+                p.as_given()
+            );
+        }
+        self.index_sources.insert(p.clone(), Box::new(url.clone()));
+        Ok(())
+    }
+
+    /// The indexes to look for `p` on.
+    fn index_urls_for(&self, p: &PackageName) -> &[Url] {
+        match self.index_sources.get(p) {
+            Some(url) => std::slice::from_ref(url),
+            None => &self.index_urls,
+        }
+    }
+
+    /// A checkout of a git repository, and the commit it's at; see `git::checkout`.
+    pub fn git_checkout(
+        &self,
+        url: &Url,
+        rev: Option<&str>,
+    ) -> Result<(PathBuf, String)> {
+        super::git::checkout(
+            &self.git_checkouts,
+            url,
+            rev,
+            self.http.offline(),
+            self.http.allowlist(),
+        )
+    }
+
     pub fn artifacts_for_version(
         &self,
         p: &PackageName,
@@ -62,45 +518,360 @@ impl<'db> PackageDB<'db> {
         p: &PackageName,
     ) -> Result<&IndexMap<Version, Vec<ArtifactInfo>>> {
         context!("Looking up available files for {}", p.as_given());
-        if let Some(cached) = self.artifacts.get(p) {
+        if let Some(url) = self.direct_reference(p) {
+            Ok(&self.direct_artifacts.get(url).unwrap().1)
+        } else if let Some(cached) = self.artifacts.get(p) {
             Ok(cached)
+        } else if let Some(wheelhouse) = &self.wheelhouse {
+            self.remember_artifacts(p, vec![wheelhouse.project_info(p)?])
+        } else if let Some(artifacts) =
+            self.source.as_ref().and_then(|s| s.artifacts(p))
+        {
+            let pi = ProjectInfo {
+                meta: Default::default(),
+                artifacts,
+            };
+            self.remember_artifacts(p, vec![pi])
         } else {
-            let mut packed: IndexMap<Version, Vec<ArtifactInfo>> = Default::default();
+            let pages = fetch_project_infos(&self.http, self.index_urls_for(p), p)?;
+            self.remember_artifacts(p, self.remember_pages(pages)?)
+        }
+    }
+
+    /// `available_artifacts` for several packages at once. Whatever isn't memoized yet
+    /// gets fetched concurrently, instead of one index page after another.
+    pub fn available_artifacts_many(
+        &self,
+        packages: &[&PackageName],
+    ) -> Vec<Result<&IndexMap<Version, Vec<ArtifactInfo>>>> {
+        crate::util::block_on(self.prefetch_index(packages));
+        packages
+            .iter()
+            .map(|p| self.available_artifacts(p))
+            .collect()
+    }
+
+    fn remember_pages(
+        &self,
+        pages: Vec<(Url, Option<ProjectInfo>)>,
+    ) -> Result<Vec<ProjectInfo>> {
+        let mut index_pages = self.index_pages.lock().unwrap();
+        let mut pis = Vec::new();
+        for (url, pi) in pages {
+            index_pages.insert(url, page_digest(pi.as_ref())?);
+            pis.extend(pi);
+        }
+        Ok(pis)
+    }
+
+    /// The key to save `brief`'s resolution under, or None if it's not safe to reuse:
+    /// policies can change their minds as time goes by, and local files, PackageSource
+    /// packages, and wheelhouses can change without any index page changing.
+    pub fn resolution_key(
+        &self,
+        brief: &Brief,
+        platforms: &[&PybiPlatform],
+        like: Option<&Blueprint>,
+    ) -> Result<Option<Vec<u8>>> {
+        let local_source = brief.sources.values().any(|source| {
+            matches!(
+                source,
+                RequirementSource::Path { .. } | RequirementSource::Git { .. }
+            )
+        });
+        let local_url = brief
+            .requirements
+            .iter()
+            .any(|req| req.url.as_ref().map_or(false, |url| url.scheme() == "file"));
+        let local_index = self.source.is_some() || self.wheelhouse.is_some();
+        if local_source || local_url || local_index || !self.policy.is_empty() {
+            return Ok(None);
+        }
+        let mut build_constraints: Vec<String> = self
+            .build_constraints
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        build_constraints.sort_unstable();
+        let mut no_binary: Vec<&str> = self
+            .no_binary
+            .iter()
+            .map(|name| name.normalized())
+            .collect();
+        no_binary.sort_unstable();
+        let key = ResolutionKey {
+            brief,
+            like: like.map(|blueprint| blueprint.content_hash()).transpose()?,
+            platforms: platforms.iter().map(|p| p.core_tag()).collect(),
+            index_urls: &self.index_urls,
+            named_indexes: &self.named_indexes,
+            build_constraints,
+            no_binary,
+            snapshot: self.snapshot.map(|time| {
+                time.duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            }),
+        };
+        Ok(Some(serde_json::to_vec(&key)?))
+    }
+
+    /// What we resolved for `key` last time, if none of the index pages it depended on
+    /// have changed since. That only goes by the HTTP cache, so it only works while
+    /// the index says our copies are still fresh -- or while we're offline, when
+    /// they're all we'd have to go on anyway.
+    pub fn cached_resolution(&self, key: &[u8]) -> Option<Blueprint> {
+        let saved: SavedResolution =
+            serde_json::from_reader(self.resolutions.get(&key)?).ok()?;
+        if !self.http.offline() {
+            for (url, digest) in &saved.index_pages {
+                let pi = cached_simple_api(&self.http, url).ok()?;
+                if page_digest(pi.as_ref()).ok()? != *digest {
+                    return None;
+                }
+            }
+        }
+        Some(saved.blueprint)
+    }
+
+    /// Saves `blueprint` as the resolution for `key`, along with every index page
+    /// we've looked at, for `cached_resolution`.
+    pub fn save_resolution(&self, key: &[u8], blueprint: &Blueprint) -> Result<()> {
+        let saved = SavedResolution {
+            blueprint: blueprint.clone(),
+            index_pages: self.index_pages.lock().unwrap().clone(),
+        };
+        let handle = self.resolutions.lock(&key)?;
+        let mut writer = handle.begin()?;
+        serde_json::to_writer(&mut writer, &saved)?;
+        writer.commit()?;
+        Ok(())
+    }
+
+    fn remember_artifacts(
+        &self,
+        p: &PackageName,
+        pis: Vec<ProjectInfo>,
+    ) -> Result<&IndexMap<Version, Vec<ArtifactInfo>>> {
+        let mut packed: IndexMap<Version, Vec<ArtifactInfo>> = Default::default();
+        let mut last_serial = None;
+        let mut undated = 0;
+        for mut pi in pis {
+            last_serial = last_serial.max(pi.meta.last_serial);
+            if let Some(snapshot) = self.snapshot {
+                pi.artifacts
+                    .retain(|ai| match uploaded_after(ai, snapshot) {
+                        Some(after) => !after,
+                        None => {
+                            undated += 1;
+                            true
+                        }
+                    });
+            }
+            pack_by_version(pi, &mut packed)?;
+        }
+        if let Some(last_serial) = last_serial {
+            self.serials.insert(p.clone(), Box::new(last_serial));
+        }
+        if undated > 0 {
+            warn!(
+                "{undated} of {}'s files don't say when they were uploaded, so they're \
+                 included whether or not they were there at the snapshot",
+                p.as_given()
+            );
+            self.undated.insert(p.clone(), Box::new(undated));
+        }
+        if !packed.is_empty() {
+            self.remember_name(p);
+        }
+
+        // sort artifact-infos (arbitrarily) by name, just to have a consistent
+        // order from run-to-run (and make resolution output more consistent)
+        for artifact_infos in packed.values_mut() {
+            artifact_infos.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        // sort into descending order by version
+        packed.sort_unstable_by(|v1, _, v2, _| v2.cmp(v1));
 
-            for index_url in self.index_urls.iter() {
-                let maybe_pi = fetch_simple_api(
-                    &self.http,
-                    &index_url.join(&format!("{}/", p.normalized()))?,
-                )?;
-                if let Some(pi) = maybe_pi {
-                    pack_by_version(pi, &mut packed)?;
+        Ok(self.artifacts.insert(p.clone(), Box::new(packed)))
+    }
+
+    /// Fetches the index pages for all of `packages` at once, so that looking them up
+    /// later doesn't have to wait on the network one at a time. Failures are left for
+    /// the real lookup to report.
+    pub async fn prefetch_index(&self, packages: &[&PackageName]) {
+        let mut tasks = Vec::new();
+        for p in packages {
+            if self.artifacts.get(*p).is_some()
+                || self.direct_reference(p).is_some()
+                || self.source.is_some()
+                || self.wheelhouse.is_some()
+            {
+                continue;
+            }
+            let http = self.http.clone();
+            let index_urls = self.index_urls_for(p).to_vec();
+            let p = (*p).clone();
+            let task = self.http.spawn_limited(move || {
+                let pages = fetch_project_infos(&http, &index_urls, &p);
+                (p, pages)
+            });
+            tasks.push(task.await);
+        }
+        for task in tasks {
+            match task.await {
+                Ok((p, Ok(pages))) => {
+                    // skip if something beat us to it, to stay consistent
+                    if self.artifacts.get(&p).is_none() {
+                        let remembered = self
+                            .remember_pages(pages)
+                            .and_then(|pis| self.remember_artifacts(&p, pis));
+                        if let Err(err) = remembered {
+                            debug!("prefetching {}: {err:#}", p.as_given());
+                        }
+                    }
                 }
+                Ok((p, Err(err))) => debug!("prefetching {}: {err:#}", p.as_given()),
+                Err(err) => debug!("prefetch task failed: {err}"),
             }
+        }
+    }
 
-            // sort artifact-infos (arbitrarily) by name, just to have a consistent
-            // order from run-to-run (and make resolution output more consistent)
-            for artifact_infos in packed.values_mut() {
-                artifact_infos.sort_by(|a, b| a.name.cmp(&b.name));
+    /// Fetches the metadata for each of `releases` (each given as its artifacts) at
+    /// once, the same way `get_metadata` would pull it out of a remote wheel, so that
+    /// looking it up afterwards only has to read the cache. Releases whose metadata we
+    /// already have, or that only have sdists, are left alone, and so are failures, for
+    /// the real lookup to report.
+    pub async fn prefetch_metadata(&self, releases: &[&[ArtifactInfo]]) {
+        if self.source.is_some() || self.wheelhouse.is_some() || self.http.offline() {
+            return;
+        }
+        let mut tasks = Vec::new();
+        for artifacts in releases {
+            let cached = artifacts.iter().any(|ai| {
+                self.resolve_metadata_from_cache(ai).is_some()
+                    || self.metadata_from_cache(ai).is_some()
+            });
+            // (without a hash, there's nowhere to cache it)
+            let wheel = artifacts
+                .iter()
+                .find(|ai| ai.is::<Wheel>() && ai.hash().is_some());
+            let Some(ai) = wheel.filter(|_| !cached) else {
+                continue;
+            };
+            let http = self.http.clone();
+            let ai = ai.clone();
+            let limits = self.unpack_limits;
+            let task = self.http.spawn_limited(move || {
+                let name = ai.name.inner_as::<WheelName>().unwrap().clone();
+                let wheel =
+                    Wheel::new(name, http.get_lazy(&ai)?)?.with_unpack_limits(limits);
+                let (blob, _) = wheel.metadata()?;
+                Ok::<_, eyre::Report>((ai, blob))
+            });
+            tasks.push(task.await);
+        }
+        for task in tasks {
+            match task.await {
+                Ok(Ok((ai, blob))) => {
+                    if let Err(err) = self.put_metadata_in_cache(&ai, &blob) {
+                        debug!("prefetching metadata for {}: {err:#}", ai.name);
+                    }
+                }
+                Ok(Err(err)) => debug!("prefetching metadata: {err:#}"),
+                Err(err) => debug!("prefetch task failed: {err}"),
             }
-            // sort into descending order by version
-            packed.sort_unstable_by(|v1, _, v2, _| v2.cmp(v1));
+        }
+    }
 
-            Ok(self.artifacts.insert(p.clone(), Box::new(packed)))
+    /// Downloads all of `artifacts` into the cache at once, so that installing them
+    /// later doesn't have to wait on the network one at a time. Failures are left for
+    /// the real download to report.
+    pub async fn prefetch_artifacts(&self, artifacts: &[&ArtifactInfo]) {
+        // Attestations first (also in parallel), so we only download things that pass
+        let mut checks = Vec::new();
+        for ai in artifacts {
+            let http = self.http.clone();
+            let config = self.attestations.clone();
+            let ai = (*ai).clone();
+            let check = self
+                .http
+                .spawn_limited(move || attestations::check(&http, &config, &ai));
+            checks.push(check.await);
+        }
+        let mut tasks = Vec::new();
+        for (ai, check) in artifacts.iter().zip(checks) {
+            match check.await {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => {
+                    debug!("{err:#}");
+                    continue;
+                }
+                Err(err) => {
+                    debug!("prefetch task failed: {err}");
+                    continue;
+                }
+            }
+            if let Some(hash) = ai.hash() {
+                let task = self.http.prefetch_hashed(ai.url.clone(), hash.clone());
+                tasks.push(task.await);
+            }
+        }
+        for task in tasks {
+            match task.await {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => debug!("{err:#}"),
+                Err(err) => debug!("prefetch task failed: {err}"),
+            }
+        }
+    }
+
+    /// Packages we've seen before whose names are close to `p`, for when `p` doesn't
+    /// exist.
+    pub fn similar_names(&self, p: &PackageName) -> Vec<String> {
+        let known = names_in(&self.package_names, "");
+        did_you_mean(p.normalized(), known.iter().map(|n| n.as_str()))
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn remember_name(&self, p: &PackageName) {
+        let path = self.package_names.join(p.normalized());
+        if !path.exists() {
+            let result = std::fs::create_dir_all(&self.package_names)
+                .and_then(|_| std::fs::write(&path, b""));
+            if let Err(err) = result {
+                debug!("couldn't remember package name {}: {err}", p.as_given());
+            }
         }
     }
 
     fn metadata_from_cache(&self, ai: &ArtifactInfo) -> Option<Vec<u8>> {
-        slurp(&mut self.metadata_cache.get(&ai.hash.as_ref()?)?).ok()
+        if let Some(metadata) = self.source.as_ref().and_then(|s| s.metadata(ai)) {
+            return Some(metadata.into());
+        }
+        slurp(&mut self.metadata_cache.get(ai.hash()?)?).ok()
     }
 
     fn put_metadata_in_cache(&self, ai: &ArtifactInfo, blob: &[u8]) -> Result<()> {
-        if let Some(hash) = &ai.hash {
+        if let Some(hash) = ai.hash() {
             self.metadata_cache
                 .get_or_set(&hash, |w| Ok(w.write_all(blob)?))?;
         }
         Ok(())
     }
 
+    fn artifact_name<T: Artifact>(&self, ai: &ArtifactInfo) -> Result<T::Name> {
+        Ok(ai
+            .name
+            .inner_as::<T::Name>()
+            .ok_or_else(|| {
+                eyre!("{} is not a {}", ai.name, std::any::type_name::<T>())
+            })?
+            .clone())
+    }
+
     fn open_artifact<T>(
         &self,
         ai: &ArtifactInfo,
@@ -109,14 +880,58 @@ impl<'db> PackageDB<'db> {
     where
         T: Artifact,
     {
-        let artifact_name = ai
-            .name
-            .inner_as::<T::Name>()
-            .ok_or_else(|| {
-                eyre!("{} is not a {}", ai.name, std::any::type_name::<T>())
-            })?
-            .clone();
-        T::new(artifact_name, body)
+        Ok(T::new(self.artifact_name::<T>(ai)?, body)?
+            .with_unpack_limits(self.unpack_limits))
+    }
+
+    fn zip_index_from_cache(&self, hash: &ArtifactHash) -> Option<ZipIndex> {
+        let mut f = self.zip_index_cache.get(hash)?;
+        match ciborium::de::from_reader(&mut f) {
+            Ok(index) => Some(index),
+            Err(err) => {
+                debug!("ignoring bad cached zip index for {hash}: {err}");
+                None
+            }
+        }
+    }
+
+    fn put_zip_index_in_cache(
+        &self,
+        hash: &ArtifactHash,
+        index: &ZipIndex,
+    ) -> Result<()> {
+        let mut blob = Vec::new();
+        ciborium::ser::into_writer(index, &mut blob)?;
+        self.zip_index_cache
+            .get_or_set(hash, |w| Ok(w.write_all(&blob)?))?;
+        Ok(())
+    }
+
+    /// Opens a binary artifact out of the by-hash cache, without having to re-read
+    /// its zip directory if we've opened it before. (Only from the cache, because
+    /// that's verified against the hash, so we know the index still matches.)
+    fn get_cached_binary<T>(&self, ai: &ArtifactInfo) -> Result<T>
+    where
+        T: BinaryArtifact,
+    {
+        let Some(hash) = ai.hash() else {
+            return self._get_artifact(ai, CacheMode::OnlyIfCached);
+        };
+        let body =
+            self.http
+                .get_hashed(&ai.url, Some(hash), CacheMode::OnlyIfCached)?;
+        if let Some(index) = self.zip_index_from_cache(hash) {
+            let artifact = T::with_zip_index(self.artifact_name::<T>(ai)?, body, index);
+            return Ok(artifact.with_unpack_limits(self.unpack_limits));
+        }
+        let artifact = self.open_artifact::<T>(ai, body)?;
+        let saved = artifact
+            .zip_index()
+            .and_then(|index| self.put_zip_index_in_cache(hash, &index));
+        if let Err(err) = saved {
+            debug!("couldn't save zip index for {}: {err}", ai.name);
+        }
+        Ok(artifact)
     }
 
     pub fn get_metadata<'a, T, B>(
@@ -128,6 +943,7 @@ impl<'db> PackageDB<'db> {
         B: std::borrow::Borrow<ArtifactInfo>,
         T: BinaryArtifact,
     {
+        timing!("metadata");
         let matching = || {
             artifacts
                 .iter()
@@ -140,13 +956,19 @@ impl<'db> PackageDB<'db> {
         // cache metadata for wheels as well.
         for ai in artifacts.iter().map(|b| b.borrow()) {
             if let Some(cm) = self.metadata_from_cache(ai) {
-                return Ok((ai, T::parse_metadata(cm.as_slice())?));
+                let metadata = T::parse_metadata(cm.as_slice()).map_err(|err| {
+                    MetadataError::Invalid {
+                        what: format!("cached metadata for {}", ai.name),
+                        source: err.into(),
+                    }
+                })?;
+                return Ok((ai, metadata));
             }
         }
 
         // have we cached any of the artifacts themselves?
         for ai in matching() {
-            let res = self._get_artifact::<T>(ai, CacheMode::OnlyIfCached);
+            let res = self.get_cached_binary::<T>(ai);
             match res {
                 Ok(artifact) => {
                     let (blob, metadata) = artifact.metadata()?;
@@ -202,20 +1024,92 @@ impl<'db> PackageDB<'db> {
             }
         }
 
-        bail!(
-            "couldn't find any {} metadata for {:#?}",
-            std::any::type_name::<T>(),
-            artifacts.iter().map(|ai| ai.borrow()).collect::<Vec<_>>()
-        );
+        Err(MetadataError::NotFound {
+            artifacts: artifacts
+                .iter()
+                .map(|ai| ai.borrow().name.to_string())
+                .collect(),
+        }
+        .into())
+    }
+
+    fn resolve_metadata_from_cache(
+        &self,
+        ai: &ArtifactInfo,
+    ) -> Option<WheelResolveMetadataInner> {
+        let blob = slurp(&mut self.resolve_metadata_cache.get(ai.hash()?)?).ok()?;
+        match serde_json::from_slice(&blob) {
+            Ok(inner) => Some(inner),
+            Err(err) => {
+                debug!(
+                    "ignoring bad cached resolve metadata for {}: {err}",
+                    ai.name
+                );
+                None
+            }
+        }
+    }
+
+    fn put_resolve_metadata_in_cache(
+        &self,
+        ai: &ArtifactInfo,
+        inner: &WheelResolveMetadataInner,
+    ) -> Result<()> {
+        if let Some(hash) = ai.hash() {
+            let blob = serde_json::to_vec(inner)?;
+            self.resolve_metadata_cache
+                .get_or_set(&hash, |w| Ok(w.write_all(&blob)?))?;
+        }
+        Ok(())
+    }
+
+    /// Like `get_metadata::<Wheel>`, but only the parts the resolver needs, and those
+    /// are cached by hash so we never have to open the artifact or reparse its METADATA
+    /// again.
+    pub fn get_resolve_metadata(
+        &self,
+        artifacts: &[ArtifactInfo],
+        builder: Option<&WheelBuilder>,
+    ) -> Result<WheelResolveMetadata> {
+        for ai in artifacts {
+            if let Some(inner) = self.resolve_metadata_from_cache(ai) {
+                let provenance = ai.url.to_string();
+                return Ok(WheelResolveMetadata { provenance, inner });
+            }
+        }
+        let (ai, wheel_metadata) = self.get_metadata::<Wheel, _>(artifacts, builder)?;
+        let resolve_metadata = WheelResolveMetadata::from(ai, &wheel_metadata);
+        self.put_resolve_metadata_in_cache(ai, &resolve_metadata.inner)?;
+        Ok(resolve_metadata)
+    }
+
+    /// What `artifacts` declare as their license, as an SPDX expression, if we can
+    /// tell. See `WheelCoreMetadata::spdx_license`.
+    pub fn get_license(
+        &self,
+        artifacts: &[ArtifactInfo],
+        builder: Option<&WheelBuilder>,
+    ) -> Result<Option<String>> {
+        let (_, metadata) = self.get_metadata::<Wheel, _>(artifacts, builder)?;
+        Ok(metadata.spdx_license())
+    }
+
+    /// The metadata we got from `tree`'s build backend last time, if it hasn't changed
+    /// since. See `WheelBuilder::local_tree_metadata`.
+    #[cfg_attr(not(feature = "cli"), allow(dead_code))]
+    pub fn cached_local_tree_metadata(
+        &self,
+        tree: &LocalTree,
+    ) -> Option<WheelCoreMetadata> {
+        let metadata_buf = slurp(&mut self.metadata_cache.get(&tree.hash)?).ok()?;
+        metadata_buf.as_slice().try_into().ok()
     }
 
     fn _get_artifact<T>(&self, ai: &ArtifactInfo, cache_mode: CacheMode) -> Result<T>
     where
         T: Artifact,
     {
-        let body = self
-            .http
-            .get_hashed(&ai.url, ai.hash.as_ref(), cache_mode)?;
+        let body = self.http.get_hashed(&ai.url, ai.hash(), cache_mode)?;
         self.open_artifact::<T>(ai, body)
     }
 
@@ -223,9 +1117,22 @@ impl<'db> PackageDB<'db> {
     where
         T: Artifact,
     {
+        timing!("download");
+        attestations::check(&self.http, &self.attestations, ai)?;
         self._get_artifact(ai, CacheMode::Default)
     }
 
+    /// The file behind `ai` as it is, checked against its hash -- e.g. to copy it
+    /// somewhere, instead of unpacking it.
+    pub fn get_artifact_file(
+        &self,
+        ai: &ArtifactInfo,
+    ) -> Result<Box<dyn ReadPlusSeek>> {
+        timing!("download");
+        attestations::check(&self.http, &self.attestations, ai)?;
+        self.http.get_hashed(&ai.url, ai.hash(), CacheMode::Default)
+    }
+
     pub fn get_locally_built_binary<T: BinaryArtifact>(
         &self,
         ai: &ArtifactInfo,
@@ -235,3 +1142,253 @@ impl<'db> PackageDB<'db> {
         T::locally_built_binary(builder, ai, platform)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{test_platform, TestDB};
+
+    #[test]
+    fn test_known_package_names() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        assert!(known_package_names(tmp.path(), "").is_empty());
+        let dir = tmp.path().join(PACKAGE_NAMES_DIR);
+        std::fs::create_dir_all(&dir)?;
+        for name in ["trio", "trio-websocket", "attrs"] {
+            std::fs::write(dir.join(name), b"")?;
+        }
+        assert_eq!(
+            known_package_names(tmp.path(), "Trio_"),
+            vec!["trio-websocket"]
+        );
+        assert_eq!(known_package_names(tmp.path(), "").len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_direct_reference() -> Result<()> {
+        let test_db = TestDB::new()?;
+        let db = test_db.db(&[], test_db.path())?;
+
+        let name: PackageName = "foo".try_into()?;
+        let hash = format!("sha256={}", "ab".repeat(32));
+        let url = Url::parse(&format!(
+            "https://example.com/foo-1.0-py3-none-any.whl#{hash}"
+        ))?;
+        db.add_direct_reference(&name, &url)?;
+        // again is fine, as long as it's the same url
+        db.add_direct_reference(&name, &url)?;
+        assert_eq!(db.direct_reference(&name), Some(&url));
+        let version: Version = "1.0".try_into()?;
+        let ais = db.artifacts_for_version(&name, &version)?;
+        assert_eq!(ais.len(), 1);
+        assert_eq!(ais[0].hash().unwrap().to_string(), hash);
+
+        let other = Url::parse(&format!(
+            "https://example.com/foo-2.0-py3-none-any.whl#{hash}"
+        ))?;
+        assert!(db.add_direct_reference(&name, &other).is_err());
+        // ...except in a scope of its own, which starts out empty, and puts back what
+        // was there before when it's done
+        db.with_direct_references(|| -> Result<()> {
+            assert_eq!(db.direct_reference(&name), None);
+            db.add_direct_reference(&name, &other)?;
+            assert_eq!(db.direct_reference(&name), Some(&other));
+            Ok(())
+        })?;
+        assert_eq!(db.direct_reference(&name), Some(&url));
+        assert_eq!(db.artifacts_for_version(&name, &version)?.len(), 1);
+
+        let bar: PackageName = "bar".try_into()?;
+        let no_hash = Url::parse("https://example.com/bar-1.0.tar.gz")?;
+        assert!(db.add_direct_reference(&bar, &no_hash).is_err());
+        // wrong package
+        assert!(db.add_direct_reference(&bar, &url).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_metadata_cache() -> Result<()> {
+        let test_db = TestDB::new()?;
+        let db = test_db.db(&[], test_db.path())?;
+
+        // there's no such file, so we'd better not try to fetch it
+        let url = Url::parse(&format!(
+            "https://example.com/foo-1.0-py3-none-any.whl#sha256={}",
+            "ab".repeat(32)
+        ))?;
+        let ai = ArtifactInfo::from_direct_reference(&url)?;
+
+        let inner = WheelResolveMetadataInner {
+            requires_dist: vec!["attrs >= 22; extra == 'test'".try_into()?],
+            requires_python: ">= 3.7".try_into()?,
+            extras: HashSet::from(["test".try_into()?]),
+        };
+        db.put_resolve_metadata_in_cache(&ai, &inner)?;
+        let found = db.get_resolve_metadata(&[ai.clone()], None)?;
+        assert_eq!(found.inner, inner);
+        assert_eq!(found.provenance, ai.url.to_string());
+
+        // and it's still there next time
+        let db = test_db.db(&[], test_db.path())?;
+        assert_eq!(db.get_resolve_metadata(&[ai], None)?.inner, inner);
+        Ok(())
+    }
+
+    #[test]
+    fn test_available_artifacts_many() -> Result<()> {
+        let test_db = TestDB::new()?;
+        let served = test_db.path().join("served");
+        let hash = format!("sha256={}", "ab".repeat(32));
+        for (name, version) in [("foo", "1.0"), ("bar", "2.0")] {
+            let file = format!("{name}-{version}-py3-none-any.whl");
+            std::fs::create_dir_all(served.join(name))?;
+            std::fs::write(
+                served.join(name).join("index.html"),
+                format!("<a href=\"{file}#{hash}\">{file}</a>"),
+            )?;
+        }
+        let server = crate::test_util::StaticHTTPServer::new(&served);
+        let db = test_db.db(&[server.url("/")], &test_db.path().join("cache"))?;
+
+        let foo: PackageName = "foo".try_into()?;
+        let bar: PackageName = "bar".try_into()?;
+        let missing: PackageName = "missing".try_into()?;
+        let found = db.available_artifacts_many(&[&foo, &bar, &missing]);
+        assert_eq!(found.len(), 3);
+        let versions = |i: usize| -> Vec<String> {
+            found[i]
+                .as_ref()
+                .unwrap()
+                .keys()
+                .map(|v| v.to_string())
+                .collect()
+        };
+        assert_eq!(versions(0), vec!["1.0"]);
+        assert_eq!(versions(1), vec!["2.0"]);
+        assert!(versions(2).is_empty());
+
+        // and now they're memoized, so we don't need the server anymore
+        drop(server);
+        assert_eq!(db.available_artifacts(&bar)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefetch_artifacts() -> Result<()> {
+        let test_db = TestDB::new()?;
+        let served = test_db.path().join("served");
+        std::fs::create_dir_all(&served)?;
+        let server = crate::test_util::StaticHTTPServer::new(&served);
+        let db = test_db.db(&[], &test_db.path().join("cache"))?;
+
+        let body = b"not really a wheel";
+        std::fs::write(served.join("foo-1.0-py3-none-any.whl"), body)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, body);
+        let mut url = server.url("foo-1.0-py3-none-any.whl");
+        url.set_fragment(Some(&format!(
+            "sha256={}",
+            data_encoding::HEXLOWER.encode(digest.as_ref())
+        )));
+        let good = ArtifactInfo::from_direct_reference(&url)?;
+        url.set_fragment(Some(&format!("sha256={}", "00".repeat(32))));
+        let bad = ArtifactInfo::from_direct_reference(&url)?;
+
+        // the bad one just gets skipped; we'd find out when we tried to use it
+        crate::util::block_on(db.prefetch_artifacts(&[&good, &bad]));
+        std::fs::remove_file(served.join("foo-1.0-py3-none-any.whl"))?;
+
+        let mut cached =
+            db.http
+                .get_hashed(&good.url, good.hash(), CacheMode::OnlyIfCached)?;
+        assert_eq!(slurp(&mut cached)?, body);
+        assert!(db
+            .http
+            .get_hashed(&bad.url, bad.hash(), CacheMode::OnlyIfCached)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cached_resolution() -> Result<()> {
+        let test_db = TestDB::new()?;
+        let platform = test_platform();
+        let mut brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &[])?;
+        let memory_db = test_db.in_memory(index)?;
+        // the in-memory index could say anything next time
+        assert!(memory_db
+            .resolution_key(&brief, &[&platform], None)?
+            .is_none());
+        let blueprint = brief.resolve(&memory_db, &[&platform], None, &[])?;
+
+        let mut db = test_db.db(&[], &test_db.path().join("cache"))?;
+        let key = db.resolution_key(&brief, &[&platform], None)?.unwrap();
+        assert!(db.cached_resolution(&key).is_none());
+        db.save_resolution(&key, &blueprint)?;
+        let cached = db.cached_resolution(&key).unwrap();
+        assert_eq!(cached.content_hash()?, blueprint.content_hash()?);
+
+        // anything else is a different resolution
+        let like_key = db.resolution_key(&brief, &[&platform], Some(&blueprint))?;
+        assert_ne!(like_key.unwrap(), key);
+        // no-binary rules out releases that only have wheels
+        db.set_no_binary(vec!["foo".try_into()?]);
+        let no_binary_key = db.resolution_key(&brief, &[&platform], None)?;
+        assert_ne!(no_binary_key.unwrap(), key);
+        db.set_no_binary(Vec::new());
+
+        // and once it depends on a page that isn't in the cache, it's stale
+        let url = Url::parse("https://example.com/simple/foo/")?;
+        db.index_pages
+            .lock()
+            .unwrap()
+            .insert(url, page_digest(None)?);
+        db.save_resolution(&key, &blueprint)?;
+        assert!(db.cached_resolution(&key).is_none());
+
+        // source trees can change without telling anyone
+        brief.sources.insert(
+            "foo".try_into()?,
+            RequirementSource::Path {
+                path: test_db.path().into(),
+            },
+        );
+        assert!(db.resolution_key(&brief, &[&platform], None)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> Result<()> {
+        let mut index = MemoryIndex::new();
+        index.add_wheel("foo", "1.0", &[])?.upload_time =
+            Some("2023-01-01T00:00:00Z".into());
+        index.add_wheel("foo", "2.0", &[])?.upload_time =
+            Some("2023-06-01T00:00:00Z".into());
+        // can't tell when this one showed up, so it's always there
+        index.add_wheel("foo", "3.0", &[])?;
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        db.set_snapshot(parse_timestamp("2023-03-01T00:00:00Z"));
+
+        let foo: PackageName = "foo".try_into()?;
+        let versions: Vec<String> = db
+            .available_artifacts(&foo)?
+            .keys()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(versions, vec!["3.0", "1.0"]);
+        assert_eq!(db.last_serial(&foo), None);
+        assert_eq!(db.undated(&foo), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,1107 @@
+//! Just enough sigstore to check a PEP 740 attestation properly: that its signing
+//! certificate came from a Fulcio instance we trust, that a Rekor log we trust
+//! recorded the signature while that certificate was valid, and who the certificate
+//! says it was issued to.
+//!
+//! What we trust comes from a sigstore trusted root -- the `trusted_root.json` that
+//! sigstore distributes through TUF, and that e.g. `cosign` and `sigstore-python` keep
+//! a copy of. We don't speak TUF ourselves, so the user has to point us at one; see
+//! `sigstore-trusted-root` in posy.toml.
+//!
+//! Not checked: certificate transparency SCTs.
+
+use crate::policy::parse_timestamp;
+use crate::prelude::*;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::der_parser::der::parse_der_utf8string;
+use x509_parser::extensions::ParsedExtension;
+use x509_parser::prelude::FromDer;
+use x509_parser::time::ASN1Time;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+// The OIDs we look for, DER-encoded, which is how x509-parser hands them to us.
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+// Fulcio's own extensions live under 1.3.6.1.4.1.57264.1. The "v1" ones hold the raw
+// string; the ones that replaced them hold a DER UTF8String.
+const OID_FULCIO_ISSUER_V1: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x01];
+const OID_FULCIO_REPOSITORY_V1: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x05];
+const OID_FULCIO_ISSUER: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x08];
+const OID_FULCIO_SOURCE_REPOSITORY: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x0c];
+
+/// A certificate's idea of a time, as a SystemTime.
+fn system_time(time: ASN1Time) -> SystemTime {
+    let secs = time.timestamp();
+    if secs >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    }
+}
+
+/// A DER UTF8String, as Fulcio puts in its newer extensions.
+fn utf8_string(der: &[u8]) -> Result<String> {
+    let (_, string) =
+        parse_der_utf8string(der).map_err(|e| eyre!("bad UTF8String: {e}"))?;
+    Ok(string
+        .as_str()
+        .map_err(|e| eyre!("bad UTF8String: {e}"))?
+        .into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Curve {
+    P256,
+    P384,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignatureHash {
+    Sha256,
+    Sha384,
+}
+
+/// An elliptic curve public key, which is all sigstore uses.
+#[derive(Debug, Clone)]
+pub(super) struct PublicKey {
+    curve: Curve,
+    /// The uncompressed point, which is what ring wants
+    point: Vec<u8>,
+}
+
+impl PublicKey {
+    /// From a DER SubjectPublicKeyInfo.
+    fn from_spki(der: &[u8]) -> Result<PublicKey> {
+        let (_, spki) = SubjectPublicKeyInfo::from_der(der)
+            .map_err(|e| eyre!("malformed public key: {e}"))?;
+        PublicKey::from_parsed(&spki)
+    }
+
+    fn from_parsed(spki: &SubjectPublicKeyInfo) -> Result<PublicKey> {
+        if spki.algorithm.algorithm.as_bytes() != OID_EC_PUBLIC_KEY {
+            bail!("not an elliptic curve key");
+        }
+        let curve = match &spki.algorithm.parameters {
+            Some(parameters) if parameters.data == OID_P256 => Curve::P256,
+            Some(parameters) if parameters.data == OID_P384 => Curve::P384,
+            _ => bail!("unsupported elliptic curve"),
+        };
+        Ok(PublicKey {
+            curve,
+            point: spki.subject_public_key.data.to_vec(),
+        })
+    }
+
+    fn verify_with(
+        &self,
+        hash: SignatureHash,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        use ring::signature as sig;
+        let algorithm = match (self.curve, hash) {
+            (Curve::P256, SignatureHash::Sha256) => &sig::ECDSA_P256_SHA256_ASN1,
+            (Curve::P256, SignatureHash::Sha384) => &sig::ECDSA_P256_SHA384_ASN1,
+            (Curve::P384, SignatureHash::Sha256) => &sig::ECDSA_P384_SHA256_ASN1,
+            (Curve::P384, SignatureHash::Sha384) => &sig::ECDSA_P384_SHA384_ASN1,
+        };
+        sig::UnparsedPublicKey::new(algorithm, &self.point)
+            .verify(message, signature)
+            .map_err(|_| eyre!("bad signature"))
+    }
+
+    /// Checks an ASN.1 ECDSA `signature` over `message`, using the hash that goes with
+    /// the key's curve, like sigstore does.
+    pub(super) fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let hash = match self.curve {
+            Curve::P256 => SignatureHash::Sha256,
+            Curve::P384 => SignatureHash::Sha384,
+        };
+        self.verify_with(hash, message, signature)
+    }
+}
+
+/// The parts of an X.509 certificate that we look at.
+#[derive(Debug, Clone)]
+pub(super) struct Certificate {
+    tbs: Vec<u8>,
+    signature_hash: SignatureHash,
+    signature: Vec<u8>,
+    not_before: SystemTime,
+    not_after: SystemTime,
+    pub(super) key: PublicKey,
+    /// Whether its extended key usage includes code signing
+    code_signing: bool,
+    /// (extnID, extnValue), in order
+    extensions: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Certificate {
+    pub(super) fn parse(der: &[u8]) -> Result<Certificate> {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|e| eyre!("malformed certificate: {e}"))?;
+        let algorithm = cert.signature_algorithm.algorithm.as_bytes();
+        let signature_hash = if algorithm == OID_ECDSA_SHA256 {
+            SignatureHash::Sha256
+        } else if algorithm == OID_ECDSA_SHA384 {
+            SignatureHash::Sha384
+        } else {
+            bail!("certificate has an unsupported signature algorithm");
+        };
+        let mut code_signing = false;
+        let mut extensions = Vec::new();
+        for extension in cert.extensions() {
+            if let ParsedExtension::ExtendedKeyUsage(usage) =
+                extension.parsed_extension()
+            {
+                code_signing = usage.code_signing;
+            }
+            let id = extension.oid.as_bytes().to_vec();
+            extensions.push((id, extension.value.to_vec()));
+        }
+        Ok(Certificate {
+            tbs: cert.tbs_certificate.as_ref().to_vec(),
+            signature_hash,
+            signature: cert.signature_value.data.to_vec(),
+            not_before: system_time(cert.validity().not_before),
+            not_after: system_time(cert.validity().not_after),
+            key: PublicKey::from_parsed(cert.public_key())?,
+            code_signing,
+            extensions,
+        })
+    }
+
+    fn signed_by(&self, issuer: &Certificate) -> bool {
+        issuer
+            .key
+            .verify_with(self.signature_hash, &self.tbs, &self.signature)
+            .is_ok()
+    }
+
+    fn valid_at(&self, time: SystemTime) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+
+    fn extension(&self, oid: &[u8]) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(id, _)| id == oid)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// One of Fulcio's string extensions, whichever of the `v2` (DER) or `v1` (raw)
+    /// versions is there.
+    fn fulcio_string(&self, v2: &[u8], v1: &[u8]) -> Result<Option<String>> {
+        if let Some(value) = self.extension(v2) {
+            return Ok(Some(utf8_string(value)?));
+        }
+        match self.extension(v1) {
+            Some(text) => Ok(Some(std::str::from_utf8(text)?.into())),
+            None => Ok(None),
+        }
+    }
+
+    /// Who Fulcio issued this to.
+    pub(super) fn identity(&self) -> Result<Identity> {
+        let Some(issuer) = self.fulcio_string(OID_FULCIO_ISSUER, OID_FULCIO_ISSUER_V1)?
+        else {
+            bail!("signing certificate doesn't say who issued its identity");
+        };
+        let repository = match self.extension(OID_FULCIO_SOURCE_REPOSITORY) {
+            Some(value) => Some(utf8_string(value)?),
+            // the old extension only ever had GitHub's "owner/name"
+            None => match self.extension(OID_FULCIO_REPOSITORY_V1) {
+                Some(text) => {
+                    Some(format!("https://github.com/{}", std::str::from_utf8(text)?))
+                }
+                None => None,
+            },
+        };
+        Ok(Identity { issuer, repository })
+    }
+}
+
+/// Who a Fulcio certificate vouches for: the OIDC issuer that vouched for them, and the
+/// source repository they were building from, if it was a CI system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Like `https://token.actions.githubusercontent.com`
+    pub issuer: String,
+    /// Like `https://github.com/psf/requests`
+    pub repository: Option<String>,
+}
+
+impl Identity {
+    /// Whether this is `expected`: either a repository URL, or `owner/name`, which is
+    /// short for a repository on GitHub. Case doesn't matter.
+    pub fn is_repository(&self, expected: &str) -> bool {
+        let expected = match expected.contains("://") {
+            true => expected.trim_end_matches('/').to_string(),
+            false => format!("https://github.com/{}", expected.trim_matches('/')),
+        };
+        self.repository.as_ref().map_or(false, |repository| {
+            repository
+                .trim_end_matches('/')
+                .eq_ignore_ascii_case(&expected)
+        })
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.repository {
+            Some(repository) => write!(f, "{repository} (via {})", self.issuer),
+            None => write!(f, "{}", self.issuer),
+        }
+    }
+}
+
+// trusted_root.json, in protobuf's JSON mapping. Anything we don't use gets ignored.
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawTrustedRoot {
+    #[serde(default)]
+    tlogs: Vec<RawTransparencyLog>,
+    #[serde(default)]
+    certificate_authorities: Vec<RawCertificateAuthority>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawTransparencyLog {
+    public_key: RawPublicKey,
+    log_id: LogId,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPublicKey {
+    /// base64 DER SubjectPublicKeyInfo
+    raw_bytes: String,
+    valid_for: Option<RawValidity>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogId {
+    /// base64 SHA-256 of the log's public key
+    key_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCertificateAuthority {
+    cert_chain: RawCertChain,
+    valid_for: Option<RawValidity>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawCertChain {
+    certificates: Vec<RawCertificate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCertificate {
+    /// base64 DER
+    raw_bytes: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawValidity {
+    start: Option<String>,
+    end: Option<String>,
+}
+
+/// When a key or certificate authority was in service. Open-ended on either side if
+/// the trusted root doesn't say.
+#[derive(Debug, Clone, Default)]
+struct Validity {
+    start: Option<SystemTime>,
+    end: Option<SystemTime>,
+}
+
+impl Validity {
+    fn parse(raw: Option<RawValidity>) -> Result<Validity> {
+        let raw = raw.unwrap_or_default();
+        let parse = |s: Option<String>| -> Result<Option<SystemTime>> {
+            match s {
+                Some(s) => match parse_timestamp(&s) {
+                    Some(time) => Ok(Some(time)),
+                    None => bail!("bad timestamp {s:?}"),
+                },
+                None => Ok(None),
+            }
+        };
+        Ok(Validity {
+            start: parse(raw.start)?,
+            end: parse(raw.end)?,
+        })
+    }
+
+    fn contains(&self, time: SystemTime) -> bool {
+        self.start.map_or(true, |start| start <= time)
+            && self.end.map_or(true, |end| time <= end)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TransparencyLog {
+    key_id: Vec<u8>,
+    key: PublicKey,
+    valid: Validity,
+}
+
+#[derive(Debug, Clone)]
+struct CertificateAuthority {
+    certificates: Vec<Certificate>,
+    valid: Validity,
+}
+
+/// The Fulcio certificate authorities and Rekor logs we trust, from a sigstore
+/// `trusted_root.json`.
+#[derive(Debug, Clone)]
+pub struct TrustedRoot {
+    logs: Vec<TransparencyLog>,
+    authorities: Vec<CertificateAuthority>,
+}
+
+impl TrustedRoot {
+    pub fn load(path: &Path) -> Result<TrustedRoot> {
+        context!("Reading sigstore trusted root from {}", path.display());
+        TrustedRoot::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(json: &[u8]) -> Result<TrustedRoot> {
+        let b64 = &data_encoding::BASE64;
+        let raw: RawTrustedRoot = serde_json::from_slice(json)?;
+        let mut logs = Vec::new();
+        for log in raw.tlogs {
+            logs.push(TransparencyLog {
+                key_id: b64.decode(log.log_id.key_id.as_bytes())?,
+                key: PublicKey::from_spki(
+                    &b64.decode(log.public_key.raw_bytes.as_bytes())?,
+                )?,
+                valid: Validity::parse(log.public_key.valid_for)?,
+            });
+        }
+        let mut authorities = Vec::new();
+        for authority in raw.certificate_authorities {
+            let mut certificates = Vec::new();
+            for certificate in authority.cert_chain.certificates {
+                let der = b64.decode(certificate.raw_bytes.as_bytes())?;
+                certificates.push(Certificate::parse(&der)?);
+            }
+            authorities.push(CertificateAuthority {
+                certificates,
+                valid: Validity::parse(authority.valid_for)?,
+            });
+        }
+        if logs.is_empty() || authorities.is_empty() {
+            bail!("trusted root needs at least one transparency log and one CA");
+        }
+        Ok(TrustedRoot { logs, authorities })
+    }
+
+    /// Checks that one of our certificate authorities issued `leaf`, for code signing,
+    /// and that it was valid at `time`.
+    pub(super) fn verify_certificate(
+        &self,
+        leaf: &Certificate,
+        time: SystemTime,
+    ) -> Result<()> {
+        if !leaf.valid_at(time) {
+            bail!("the signing certificate wasn't valid when the signature was logged");
+        }
+        if !leaf.code_signing {
+            bail!("the signing certificate isn't for code signing");
+        }
+        let issued = self.authorities.iter().any(|authority| {
+            authority.valid.contains(time)
+                && authority
+                    .certificates
+                    .iter()
+                    .any(|ca| ca.valid_at(time) && leaf.signed_by(ca))
+        });
+        if !issued {
+            bail!("the signing certificate wasn't issued by a CA we trust");
+        }
+        Ok(())
+    }
+
+    /// Checks that `entry` is in one of our Rekor logs, as a DSSE envelope with
+    /// `signature` over `payload` by `certificate` (DER), and returns when it was
+    /// logged.
+    pub(super) fn verify_dsse_entry(
+        &self,
+        entry: &TransparencyLogEntry,
+        payload: &[u8],
+        signature: &[u8],
+        certificate: &[u8],
+    ) -> Result<SystemTime> {
+        let b64 = &data_encoding::BASE64;
+        let key_id = b64.decode(entry.log_id.key_id.as_bytes())?;
+        let Some(log) = self.logs.iter().find(|log| log.key_id == key_id) else {
+            bail!("it's in a transparency log we don't trust");
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(entry.integrated_time);
+        if !log.valid.contains(time) {
+            bail!("it was logged when the transparency log's key wasn't in service");
+        }
+        let body = b64.decode(entry.canonicalized_body.as_bytes())?;
+
+        // The signed entry timestamp is what vouches for `integrated_time`.
+        let Some(promise) = &entry.inclusion_promise else {
+            bail!("its log entry has no signed entry timestamp");
+        };
+        let signed = format!(
+            r#"{{"body":"{}","integratedTime":{},"logID":"{}","logIndex":{}}}"#,
+            entry.canonicalized_body,
+            entry.integrated_time,
+            data_encoding::HEXLOWER.encode(&key_id),
+            entry.log_index,
+        );
+        log.key
+            .verify(
+                signed.as_bytes(),
+                &b64.decode(promise.signed_entry_timestamp.as_bytes())?,
+            )
+            .wrap_err("bad signed entry timestamp")?;
+        let Some(proof) = &entry.inclusion_proof else {
+            bail!("its log entry has no inclusion proof");
+        };
+        proof.verify(log, &body).wrap_err("bad inclusion proof")?;
+
+        // And the entry has to be about this very signature.
+        let KindVersion { kind, version } = &entry.kind_version;
+        if (kind.as_str(), version.as_str()) != ("dsse", "0.0.1") {
+            bail!("its log entry is a {kind} {version}, not a dsse 0.0.1");
+        }
+        let body: DsseBody = serde_json::from_slice(&body)?;
+        let payload_hash = data_encoding::HEXLOWER.encode(&sha256(&[payload]));
+        let logged_hash = &body.spec.payload_hash;
+        if logged_hash.algorithm != "sha256"
+            || !logged_hash.value.eq_ignore_ascii_case(&payload_hash)
+        {
+            bail!("its log entry is for a different statement");
+        }
+        let mut found = false;
+        for logged in &body.spec.signatures {
+            let logged_signature = b64.decode(logged.signature.as_bytes())?;
+            let pem = String::from_utf8(b64.decode(logged.verifier.as_bytes())?)?;
+            if logged_signature == signature && pem_der(&pem)? == certificate {
+                found = true;
+            }
+        }
+        if !found {
+            bail!("its log entry is for a different signature");
+        }
+        Ok(time)
+    }
+}
+
+/// The DER inside a PEM certificate.
+fn pem_der(pem: &str) -> Result<Vec<u8>> {
+    let Some((_, rest)) = pem.split_once("-----BEGIN CERTIFICATE-----") else {
+        bail!("not a PEM certificate");
+    };
+    let Some((body, _)) = rest.split_once("-----END CERTIFICATE-----") else {
+        bail!("not a PEM certificate");
+    };
+    let body: String = body.split_whitespace().collect();
+    Ok(data_encoding::BASE64.decode(body.as_bytes())?)
+}
+
+fn sha256(parts: &[&[u8]]) -> Vec<u8> {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    for part in parts {
+        context.update(part);
+    }
+    context.finish().as_ref().to_vec()
+}
+
+/// Protobuf's JSON mapping writes 64-bit integers as strings, but not everyone follows
+/// it, so we take either.
+fn int<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int {
+        Number(u64),
+        String(String),
+    }
+    match Int::deserialize(deserializer)? {
+        Int::Number(n) => Ok(n),
+        Int::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+/// A Rekor log entry, as a sigstore bundle has it (which is how PEP 740's
+/// `transparency_entries` have it too).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransparencyLogEntry {
+    #[serde(deserialize_with = "int")]
+    log_index: u64,
+    log_id: LogId,
+    kind_version: KindVersion,
+    #[serde(deserialize_with = "int")]
+    integrated_time: u64,
+    inclusion_promise: Option<InclusionPromise>,
+    inclusion_proof: Option<InclusionProof>,
+    /// base64 JSON
+    canonicalized_body: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct KindVersion {
+    kind: String,
+    version: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionPromise {
+    /// base64
+    signed_entry_timestamp: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionProof {
+    #[serde(deserialize_with = "int")]
+    log_index: u64,
+    /// base64
+    root_hash: String,
+    #[serde(deserialize_with = "int")]
+    tree_size: u64,
+    /// base64, from the leaf up
+    hashes: Vec<String>,
+    checkpoint: Checkpoint,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Checkpoint {
+    envelope: String,
+}
+
+impl InclusionProof {
+    /// Checks that `body` is in the tree the proof says, and that the log signed off on
+    /// that tree.
+    fn verify(&self, log: &TransparencyLog, body: &[u8]) -> Result<()> {
+        let b64 = &data_encoding::BASE64;
+        let hashes = self
+            .hashes
+            .iter()
+            .map(|hash| Ok(b64.decode(hash.as_bytes())?))
+            .collect::<Result<Vec<_>>>()?;
+        let root = merkle_root(self.log_index, self.tree_size, body, &hashes)?;
+        if root != b64.decode(self.root_hash.as_bytes())? {
+            bail!("the proof doesn't lead to its root hash");
+        }
+        let (size, checkpoint_root) =
+            verify_checkpoint(log, &self.checkpoint.envelope)?;
+        if (size, checkpoint_root) != (self.tree_size, root) {
+            bail!("the log's checkpoint is for a different tree");
+        }
+        Ok(())
+    }
+}
+
+/// The root hash of a tree of `size` leaves, where the one at `index` is `leaf`, given
+/// the audit path `proof` -- RFC 9162's inclusion proof verification.
+fn merkle_root(
+    index: u64,
+    size: u64,
+    leaf: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<Vec<u8>> {
+    if index >= size {
+        bail!("leaf {index} isn't in a tree of size {size}");
+    }
+    let (mut fn_, mut sn) = (index, size - 1);
+    let mut root = sha256(&[&[0], leaf]);
+    for hash in proof {
+        if sn == 0 {
+            bail!("proof is too long");
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            root = sha256(&[&[1], hash, &root]);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            root = sha256(&[&[1], &root, hash]);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    if sn != 0 {
+        bail!("proof is too short");
+    }
+    Ok(root)
+}
+
+/// Checks that `log` signed the checkpoint `envelope` (a "signed note"), and returns
+/// the tree size and root hash it commits to.
+fn verify_checkpoint(log: &TransparencyLog, envelope: &str) -> Result<(u64, Vec<u8>)> {
+    let Some((text, signatures)) = envelope.split_once("\n\n") else {
+        bail!("malformed checkpoint");
+    };
+    // the signature covers the text, including its last newline
+    let text = format!("{text}\n");
+    let mut lines = text.lines().skip(1);
+    let (Some(size), Some(root)) = (lines.next(), lines.next()) else {
+        bail!("malformed checkpoint");
+    };
+    let size: u64 = size.parse()?;
+    let root = data_encoding::BASE64.decode(root.as_bytes())?;
+    for line in signatures.lines() {
+        let Some((_, signature)) = line
+            .strip_prefix("\u{2014} ")
+            .and_then(|line| line.rsplit_once(' '))
+        else {
+            continue;
+        };
+        // 4 bytes of key hint, then the signature
+        let signature = data_encoding::BASE64.decode(signature.as_bytes())?;
+        if signature.len() > 4
+            && log.key.verify(text.as_bytes(), &signature[4..]).is_ok()
+        {
+            return Ok((size, root));
+        }
+    }
+    bail!("the checkpoint isn't signed by the log");
+}
+
+// The body of a Rekor "dsse" entry. Anything we don't use gets ignored.
+
+#[derive(Debug, Clone, Deserialize)]
+struct DsseBody {
+    spec: DsseSpec,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DsseSpec {
+    payload_hash: DsseHash,
+    signatures: Vec<DsseSignature>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DsseHash {
+    algorithm: String,
+    value: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DsseSignature {
+    /// base64
+    signature: String,
+    /// base64 PEM certificate
+    verifier: String,
+}
+
+/// A made-up sigstore, so tests can issue certificates and log things.
+#[cfg(test)]
+pub(super) mod fake {
+    use super::*;
+    use ring::signature::{
+        EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+        ECDSA_P384_SHA384_ASN1_SIGNING,
+    };
+
+    // Just enough DER to put X.509 certificates together.
+    const DER_INTEGER: u8 = 0x02;
+    const DER_BIT_STRING: u8 = 0x03;
+    const DER_OCTET_STRING: u8 = 0x04;
+    const DER_OID: u8 = 0x06;
+    const DER_UTF8_STRING: u8 = 0x0c;
+    const DER_GENERALIZED_TIME: u8 = 0x18;
+    const DER_SEQUENCE: u8 = 0x30;
+    const DER_VERSION: u8 = 0xa0;
+    const DER_EXTENSIONS: u8 = 0xa3;
+
+    const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+    const OID_CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+
+    pub fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let contents = parts.concat();
+        let mut out = vec![tag];
+        if contents.len() < 0x80 {
+            out.push(contents.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend_from_slice(&(contents.len() as u16).to_be_bytes());
+        }
+        out.extend(contents);
+        out
+    }
+
+    fn spki(curve: &[u8], point: &[u8]) -> Vec<u8> {
+        let algorithm = der(
+            DER_SEQUENCE,
+            &[&der(DER_OID, &[OID_EC_PUBLIC_KEY]), &der(DER_OID, &[curve])],
+        );
+        der(
+            DER_SEQUENCE,
+            &[&algorithm, &der(DER_BIT_STRING, &[&[0], point])],
+        )
+    }
+
+    fn extension(oid: &[u8], value: &[u8]) -> Vec<u8> {
+        der(
+            DER_SEQUENCE,
+            &[&der(DER_OID, &[oid]), &der(DER_OCTET_STRING, &[value])],
+        )
+    }
+
+    pub fn generate(
+        alg: &'static ring::signature::EcdsaSigningAlgorithm,
+    ) -> EcdsaKeyPair {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref()).unwrap()
+    }
+
+    pub fn sign(key: &EcdsaKeyPair, message: &[u8]) -> Vec<u8> {
+        let rng = ring::rand::SystemRandom::new();
+        key.sign(&rng, message).unwrap().as_ref().to_vec()
+    }
+
+    pub struct FakeSigstore {
+        ca: EcdsaKeyPair,
+        ca_certificate: Vec<u8>,
+        log: EcdsaKeyPair,
+    }
+
+    /// Times are GeneralizedTimes, like "20240101000000Z".
+    pub struct Issue<'a> {
+        pub key: &'a EcdsaKeyPair,
+        pub not_before: &'a str,
+        pub not_after: &'a str,
+        pub repository: Option<&'a str>,
+        pub code_signing: bool,
+    }
+
+    impl FakeSigstore {
+        pub fn new() -> FakeSigstore {
+            let ca = generate(&ECDSA_P384_SHA384_ASN1_SIGNING);
+            let ca_spki = spki(OID_P384, ca.public_key().as_ref());
+            let ca_certificate =
+                certificate(&ca, &ca_spki, ("20000101000000Z", "20991231000000Z"), &[]);
+            FakeSigstore {
+                ca,
+                ca_certificate,
+                log: generate(&ECDSA_P256_SHA256_ASN1_SIGNING),
+            }
+        }
+
+        fn log_spki(&self) -> Vec<u8> {
+            spki(OID_P256, self.log.public_key().as_ref())
+        }
+
+        pub fn trusted_root(&self) -> Result<TrustedRoot> {
+            let b64 = &data_encoding::BASE64;
+            let json = serde_json::json!({
+                "mediaType":
+                    "application/vnd.dev.sigstore.trustedroot+json;version=0.1",
+                "tlogs": [{
+                    "baseUrl": "https://rekor.example.com",
+                    "hashAlgorithm": "SHA2_256",
+                    "publicKey": {
+                        "rawBytes": b64.encode(&self.log_spki()),
+                        "keyDetails": "PKIX_ECDSA_P256_SHA_256",
+                        "validFor": {"start": "2000-01-01T00:00:00Z"},
+                    },
+                    "logId": {"keyId": b64.encode(&sha256(&[&self.log_spki()]))},
+                }],
+                "certificateAuthorities": [{
+                    "uri": "https://fulcio.example.com",
+                    "certChain": {
+                        "certificates": [
+                            {"rawBytes": b64.encode(&self.ca_certificate)},
+                        ],
+                    },
+                    "validFor": {"start": "2000-01-01T00:00:00Z"},
+                }],
+            });
+            TrustedRoot::parse(json.to_string().as_bytes())
+        }
+
+        /// A Fulcio-style signing certificate for `issue.key`.
+        pub fn issue(&self, issue: &Issue) -> Vec<u8> {
+            let mut extensions = vec![extension(
+                OID_FULCIO_ISSUER,
+                &der(
+                    DER_UTF8_STRING,
+                    &[b"https://token.actions.githubusercontent.com"],
+                ),
+            )];
+            if issue.code_signing {
+                extensions.push(extension(
+                    OID_EXTENDED_KEY_USAGE,
+                    &der(DER_SEQUENCE, &[&der(DER_OID, &[OID_CODE_SIGNING])]),
+                ));
+            }
+            if let Some(repository) = issue.repository {
+                extensions.push(extension(
+                    OID_FULCIO_SOURCE_REPOSITORY,
+                    &der(DER_UTF8_STRING, &[repository.as_bytes()]),
+                ));
+            }
+            certificate(
+                &self.ca,
+                &spki(OID_P256, issue.key.public_key().as_ref()),
+                (issue.not_before, issue.not_after),
+                &extensions,
+            )
+        }
+
+        /// A Rekor entry for a DSSE envelope, logged at `time` (seconds since the
+        /// epoch), as the second leaf of a two-leaf tree.
+        pub fn log(
+            &self,
+            payload: &[u8],
+            signature: &[u8],
+            certificate: &[u8],
+            time: u64,
+        ) -> serde_json::Value {
+            let b64 = &data_encoding::BASE64;
+            let pem = format!(
+                "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+                b64.encode(certificate)
+            );
+            let body = serde_json::json!({
+                "apiVersion": "0.0.1",
+                "kind": "dsse",
+                "spec": {
+                    "envelopeHash": {"algorithm": "sha256", "value": "00"},
+                    "payloadHash": {
+                        "algorithm": "sha256",
+                        "value": data_encoding::HEXLOWER.encode(&sha256(&[payload])),
+                    },
+                    "signatures": [{
+                        "signature": b64.encode(signature),
+                        "verifier": b64.encode(pem.as_bytes()),
+                    }],
+                },
+            })
+            .to_string();
+            let body_b64 = b64.encode(body.as_bytes());
+            let key_id = sha256(&[&self.log_spki()]);
+            let set = format!(
+                r#"{{"body":"{body_b64}","integratedTime":{time},"logID":"{}","logIndex":1}}"#,
+                data_encoding::HEXLOWER.encode(&key_id)
+            );
+            let sibling = sha256(&[&[0], b"someone else"]);
+            let root = sha256(&[&[1], &sibling, &sha256(&[&[0], body.as_bytes()])]);
+            let note = format!("rekor.example.com - 1\n2\n{}\n", b64.encode(&root));
+            let mut note_signature = key_id[..4].to_vec();
+            note_signature.extend(sign(&self.log, note.as_bytes()));
+            let checkpoint = format!(
+                "{note}\n\u{2014} rekor.example.com {}\n",
+                b64.encode(&note_signature)
+            );
+            serde_json::json!({
+                "logIndex": "1",
+                "logId": {"keyId": b64.encode(&key_id)},
+                "kindVersion": {"kind": "dsse", "version": "0.0.1"},
+                "integratedTime": time.to_string(),
+                "inclusionPromise": {
+                    "signedEntryTimestamp":
+                        b64.encode(&sign(&self.log, set.as_bytes())),
+                },
+                "inclusionProof": {
+                    "logIndex": "1",
+                    "rootHash": b64.encode(&root),
+                    "treeSize": "2",
+                    "hashes": [b64.encode(&sibling)],
+                    "checkpoint": {"envelope": checkpoint},
+                },
+                "canonicalizedBody": body_b64,
+            })
+        }
+    }
+
+    fn certificate(
+        issuer: &EcdsaKeyPair,
+        subject_spki: &[u8],
+        (not_before, not_after): (&str, &str),
+        extensions: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let empty = der(DER_SEQUENCE, &[]);
+        let algorithm = der(DER_SEQUENCE, &[&der(DER_OID, &[OID_ECDSA_SHA384])]);
+        let validity = der(
+            DER_SEQUENCE,
+            &[
+                &der(DER_GENERALIZED_TIME, &[not_before.as_bytes()]),
+                &der(DER_GENERALIZED_TIME, &[not_after.as_bytes()]),
+            ],
+        );
+        let extensions = der(
+            DER_EXTENSIONS,
+            &[&der(DER_SEQUENCE, &[&extensions.concat()])],
+        );
+        let tbs = der(
+            DER_SEQUENCE,
+            &[
+                &der(DER_VERSION, &[&der(DER_INTEGER, &[&[2]])]),
+                &der(DER_INTEGER, &[&[1]]),
+                &algorithm,
+                &empty,
+                &validity,
+                &empty,
+                subject_spki,
+                &extensions,
+            ],
+        );
+        let signature = sign(issuer, &tbs);
+        der(
+            DER_SEQUENCE,
+            &[&tbs, &algorithm, &der(DER_BIT_STRING, &[&[0], &signature])],
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::fake::*;
+    use super::*;
+    use ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+
+    fn at(timestamp: &str) -> u64 {
+        parse_timestamp(timestamp)
+            .unwrap()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn test_merkle_root() -> Result<()> {
+        let leaves: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i]).collect();
+        let h = |i: usize| sha256(&[&[0], &leaves[i]]);
+        let node = |a: &[u8], b: &[u8]| sha256(&[&[1], a, b]);
+        // a five-leaf tree: ((0 1) (2 3)) 4
+        let root = node(&node(&node(&h(0), &h(1)), &node(&h(2), &h(3))), &h(4));
+        let proof = vec![h(3), node(&h(0), &h(1)), h(4)];
+        assert_eq!(merkle_root(2, 5, &leaves[2], &proof)?, root);
+        let proof = vec![node(&node(&h(0), &h(1)), &node(&h(2), &h(3)))];
+        assert_eq!(merkle_root(4, 5, &leaves[4], &proof)?, root);
+        assert_ne!(merkle_root(4, 5, &leaves[3], &proof)?, root);
+        assert!(merkle_root(2, 5, &leaves[2], &[h(3)]).is_err());
+        assert!(merkle_root(5, 5, &leaves[4], &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_dsse_entry() -> Result<()> {
+        let sigstore = FakeSigstore::new();
+        let root = sigstore.trusted_root()?;
+        let key = generate(&ECDSA_P256_SHA256_ASN1_SIGNING);
+        let issue = Issue {
+            key: &key,
+            not_before: "20240101000000Z",
+            not_after: "20240101001000Z",
+            repository: Some("https://github.com/Example/Foo"),
+            code_signing: true,
+        };
+        let certificate_der = sigstore.issue(&issue);
+        let certificate = Certificate::parse(&certificate_der)?;
+        let signature = sign(&key, b"payload");
+        certificate.key.verify(b"payload", &signature)?;
+        let time = at("2024-01-01T00:05:00Z");
+        let entry: TransparencyLogEntry = serde_json::from_value(sigstore.log(
+            b"payload",
+            &signature,
+            &certificate_der,
+            time,
+        ))?;
+        let logged =
+            root.verify_dsse_entry(&entry, b"payload", &signature, &certificate_der)?;
+        root.verify_certificate(&certificate, logged)?;
+        let identity = certificate.identity()?;
+        assert!(identity.is_repository("example/foo"));
+        assert!(identity.is_repository("https://github.com/example/foo/"));
+        assert!(!identity.is_repository("https://gitlab.com/example/foo"));
+        assert!(!identity.is_repository("evil/foo"));
+
+        // not for this payload
+        assert!(root
+            .verify_dsse_entry(&entry, b"other", &signature, &certificate_der)
+            .is_err());
+        // logged after the certificate expired
+        let late: TransparencyLogEntry = serde_json::from_value(sigstore.log(
+            b"payload",
+            &signature,
+            &certificate_der,
+            at("2024-01-01T00:15:00Z"),
+        ))?;
+        let logged =
+            root.verify_dsse_entry(&late, b"payload", &signature, &certificate_der)?;
+        assert!(root.verify_certificate(&certificate, logged).is_err());
+        // tampered with after logging
+        let mut moved = sigstore.log(b"payload", &signature, &certificate_der, time);
+        moved["integratedTime"] = at("2024-01-01T00:06:00Z").to_string().into();
+        let moved: TransparencyLogEntry = serde_json::from_value(moved)?;
+        assert!(root
+            .verify_dsse_entry(&moved, b"payload", &signature, &certificate_der)
+            .is_err());
+        // some other sigstore
+        let elsewhere = FakeSigstore::new();
+        let stranger = Certificate::parse(&elsewhere.issue(&issue))?;
+        assert!(root.verify_certificate(&stranger, logged).is_err());
+        let stranger_entry: TransparencyLogEntry = serde_json::from_value(
+            elsewhere.log(b"payload", &signature, &certificate_der, time),
+        )?;
+        assert!(root
+            .verify_dsse_entry(
+                &stranger_entry,
+                b"payload",
+                &signature,
+                &certificate_der
+            )
+            .is_err());
+        // not for code signing
+        let plain = Certificate::parse(&sigstore.issue(&Issue {
+            code_signing: false,
+            ..issue
+        }))?;
+        assert!(root.verify_certificate(&plain, time_of(time)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_certificate() {
+        let key = generate(&ECDSA_P256_SHA256_ASN1_SIGNING);
+        let certificate_der = FakeSigstore::new().issue(&Issue {
+            key: &key,
+            not_before: "20240101000000Z",
+            not_after: "20240101001000Z",
+            repository: None,
+            code_signing: true,
+        });
+        assert!(Certificate::parse(&certificate_der).is_ok());
+        for end in 0..certificate_der.len() {
+            assert!(Certificate::parse(&certificate_der[..end]).is_err());
+        }
+    }
+
+    fn time_of(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+}
@@ -13,20 +13,45 @@ struct CacheEntry {
 }
 
 pub fn fetch_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
+    fetch_simple_api_with(http, url, CacheMode::Default)
+}
+
+/// The page at `url` from the cache, as long as the index says it's still fresh, or
+/// else Err(NotCached). Never touches the network.
+pub fn cached_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
+    fetch_simple_api_with(http, url, CacheMode::OnlyIfCached)
+}
+
+fn fetch_simple_api_with(
+    http: &Http,
+    url: &Url,
+    cache_mode: CacheMode,
+) -> Result<Option<ProjectInfo>> {
     context!("Fetching simple API page at {}", url);
-    let request = Request::builder()
+    timing!("index");
+    let mut request = Request::builder()
         .uri(url.as_str())
-        .header("Cache-Control", "max-age=0")
-        .body(())?;
-
-    let response = http.request(request, CacheMode::Default)?;
+        .header("Accept", super::json::ACCEPT);
+    if cache_mode == CacheMode::Default {
+        // the page can change at any time, so make sure we have the latest
+        request = request.header("Cache-Control", "max-age=0");
+    }
+    let response = http.request(request.body(())?, cache_mode)?;
     if response.status().as_u16() == 404 {
         return Ok(None);
     }
     if response.status().as_u16() >= 400 {
-        bail!("error fetching {url}: {}", response.status().as_str());
+        Err(NetworkError::HttpStatus {
+            url: url.clone(),
+            status: response.status().as_u16(),
+        })?;
     }
     let url = response.extensions().get::<Url>().unwrap().to_owned();
+    // PyPI sends this with every page, HTML or JSON
+    let last_serial: Option<u64> = response
+        .headers()
+        .get("X-PyPI-Last-Serial")
+        .and_then(|value| value.to_str().ok()?.parse().ok());
     let content_type = if let Some(value) = response.headers().get("Content-Type") {
         value.to_str()?
     } else {
@@ -34,9 +59,13 @@ pub fn fetch_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
     }
     .to_owned();
 
-    Ok(Some(super::parse_html(
-        &url,
-        &content_type,
-        response.into_body(),
-    )?))
+    let mut pi = if content_type.starts_with(super::json::JSON_CONTENT_TYPE) {
+        super::parse_json(&url, response.into_body())?
+    } else {
+        super::parse_html(&url, &content_type, response.into_body())?
+    };
+    if pi.meta.last_serial.is_none() {
+        pi.meta.last_serial = last_serial;
+    }
+    Ok(Some(pi))
 }
@@ -12,6 +12,7 @@
 // except according to those terms.
 
 use crate::prelude::*;
+use crate::util::url_filename;
 
 use std::borrow::Borrow;
 use std::borrow::Cow;
@@ -39,6 +40,8 @@ static YANKED_ATTR: Lazy<Atom<LocalNameStaticSet>> =
     Lazy::new(|| Atom::from("data-yanked"));
 static DATA_DIST_INFO_METADATA: Lazy<Atom<LocalNameStaticSet>> =
     Lazy::new(|| Atom::from("data-dist-info-metadata"));
+static DATA_PROVENANCE: Lazy<Atom<LocalNameStaticSet>> =
+    Lazy::new(|| Atom::from("data-provenance"));
 
 struct Sink {
     next_id: usize,
@@ -83,10 +86,10 @@ impl Sink {
         attrs: &Vec<Attribute>,
     ) -> Option<Vec<ArtifactInfo>> {
         let url = self.base.join(url_str).ok()?;
-        let name: ArtifactName = url.path_segments()?.next_back()?.try_into().ok()?;
+        let name: ArtifactName = url_filename(&url)?.as_str().try_into().ok()?;
         let names = name.split_multiplatform_pybis();
         // We found a valid link
-        let hash = url.fragment().and_then(parse_hash);
+        let hashes = url.fragment().and_then(parse_hash).into_iter().collect();
         let requires_python =
             get_attr(REQUIRES_PYTHON_ATTR.borrow(), attrs).map(String::from);
         let metadata_attr = get_attr(DATA_DIST_INFO_METADATA.borrow(), attrs);
@@ -115,13 +118,18 @@ impl Sink {
                 reason: Some(reason.into()),
             },
         };
+        let provenance = get_attr(DATA_PROVENANCE.borrow(), attrs)
+            .and_then(|value| self.base.join(value).ok());
         let template = ArtifactInfo {
             name,
             url,
-            hash,
+            hashes,
             requires_python,
             dist_info_metadata,
             yanked,
+            provenance,
+            upload_time: None,
+            size: None,
         };
         Some(
             names
@@ -249,11 +257,8 @@ where
     T: Read,
 {
     let content_type: mime::Mime = content_type.parse()?;
-    match (
-        content_type.type_().as_str(),
-        content_type.subtype().as_str(),
-    ) {
-        ("text", "html") => {}
+    match content_type.essence_str() {
+        "text/html" | "application/vnd.pypi.simple.v1+html" => {}
         _ => bail!(
             "simple API page expected Content-Type: text/html, but got {}",
             content_type,
@@ -281,6 +286,26 @@ where
 mod test {
     use super::*;
 
+    #[test]
+    fn test_percent_encoded_filename() {
+        // the PyTorch index does this to the + in local versions
+        let parsed = parse_html(
+            &Url::parse("https://download.pytorch.org/whl/cu121/torch/").unwrap(),
+            "text/html",
+            br#"<a href="/whl/cu121/torch-2.1.0%2Bcu121-cp311-cp311-linux_x86_64.whl">
+                torch-2.1.0+cu121-cp311-cp311-linux_x86_64.whl</a>"#
+                as &[u8],
+        )
+        .unwrap();
+        assert_eq!(parsed.artifacts.len(), 1);
+        let ai = &parsed.artifacts[0];
+        assert_eq!(ai.name.version().to_string(), "2.1.0+cu121");
+        assert_eq!(
+            ai.name.to_string(),
+            "torch-2.1.0+cu121-cp311-cp311-linux_x86_64.whl"
+        );
+    }
+
     #[test]
     fn test_sink_simple() {
         let parsed = parse_html(
@@ -295,6 +320,7 @@ mod test {
                   <a href="link1-1.0.tar.gz#sha256=0000000000000000000000000000000000000000000000000000000000000000">link1</a>
                   <a href="/elsewhere/link2-2.0.zip" data-yanked="some reason">link2</a>
                   <a href="link3-3.0.tar.gz" data-requires-python=">= 3.17">link3</a>
+                  <a href="link4-4.0.tar.gz" data-provenance="link4-4.0.tar.gz.provenance">link4</a>
                 </body>
               </html>
             "# as &[u8],
@@ -309,7 +335,9 @@ mod test {
             ArtifactInfo(
               name: "link1-1.0.tar.gz",
               url: "https://example.com/new-base/link1-1.0.tar.gz#sha256=0000000000000000000000000000000000000000000000000000000000000000",
-              hash: Some("sha256=0000000000000000000000000000000000000000000000000000000000000000"),
+              hashes: [
+                "sha256=0000000000000000000000000000000000000000000000000000000000000000",
+              ],
               requires_python: None,
               dist_info_metadata: DistInfoMetadata(
                 available: false,
@@ -323,7 +351,7 @@ mod test {
             ArtifactInfo(
               name: "link2-2.0.zip",
               url: "https://example.com/elsewhere/link2-2.0.zip",
-              hash: None,
+              hashes: [],
               requires_python: None,
               dist_info_metadata: DistInfoMetadata(
                 available: false,
@@ -337,7 +365,7 @@ mod test {
             ArtifactInfo(
               name: "link3-3.0.tar.gz",
               url: "https://example.com/new-base/link3-3.0.tar.gz",
-              hash: None,
+              hashes: [],
               requires_python: Some(">= 3.17"),
               dist_info_metadata: DistInfoMetadata(
                 available: false,
@@ -348,6 +376,21 @@ mod test {
                 reason: None,
               ),
             ),
+            ArtifactInfo(
+              name: "link4-4.0.tar.gz",
+              url: "https://example.com/new-base/link4-4.0.tar.gz",
+              hashes: [],
+              requires_python: None,
+              dist_info_metadata: DistInfoMetadata(
+                available: false,
+                hash: None,
+              ),
+              yanked: Yanked(
+                yanked: false,
+                reason: None,
+              ),
+              provenance: Some("https://example.com/new-base/link4-4.0.tar.gz.provenance"),
+            ),
           ],
         )
         "###);
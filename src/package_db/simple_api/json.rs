@@ -0,0 +1,154 @@
+use crate::prelude::*;
+
+use super::project_info::{ArtifactInfo, DistInfoMetadata, Meta, ProjectInfo, Yanked};
+
+/// What we ask indexes for: the JSON API (PEP 691) if they have it, since that's the
+/// only one with upload times, but HTML is fine too.
+pub const ACCEPT: &str = "application/vnd.pypi.simple.v1+json, \
+                          application/vnd.pypi.simple.v1+html;q=0.2, \
+                          text/html;q=0.1";
+
+pub const JSON_CONTENT_TYPE: &str = "application/vnd.pypi.simple.v1+json";
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawMeta {
+    #[serde(rename = "api-version")]
+    api_version: String,
+    #[serde(rename = "_last-serial", default)]
+    last_serial: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RawFile {
+    filename: String,
+    url: String,
+    hashes: HashMap<String, String>,
+    requires_python: Option<String>,
+    // PEP 714 renamed this, but indexes are supposed to send both for a while
+    #[serde(default)]
+    core_metadata: DistInfoMetadata,
+    #[serde(default)]
+    dist_info_metadata: DistInfoMetadata,
+    #[serde(default)]
+    yanked: Option<Yanked>,
+    upload_time: Option<String>,
+    size: Option<u64>,
+    provenance: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawProject {
+    meta: RawMeta,
+    files: Vec<RawFile>,
+}
+
+pub fn parse_json<T: Read>(url: &Url, body: T) -> Result<ProjectInfo> {
+    let raw: RawProject = serde_json::from_reader(body)?;
+    let mut project_info = ProjectInfo {
+        meta: Meta {
+            version: raw.meta.api_version,
+            last_serial: raw.meta.last_serial,
+        },
+        artifacts: Vec::new(),
+    };
+    for file in raw.files {
+        // Same as for HTML: skip anything we can't make sense of
+        let Ok(name) = ArtifactName::try_from(file.filename.as_str()) else {
+            continue;
+        };
+        let Ok(file_url) = url.join(&file.url) else {
+            continue;
+        };
+        let hashes = file
+            .hashes
+            .iter()
+            .filter_map(|(mode, hex)| ArtifactHash::from_hex(mode, hex).ok())
+            .collect();
+        let template = ArtifactInfo {
+            name,
+            url: file_url,
+            hashes,
+            requires_python: file.requires_python,
+            dist_info_metadata: if file.core_metadata.available {
+                file.core_metadata
+            } else {
+                file.dist_info_metadata
+            },
+            yanked: file.yanked.unwrap_or_default(),
+            provenance: file.provenance.and_then(|p| url.join(&p).ok()),
+            upload_time: file.upload_time,
+            size: file.size,
+        };
+        for name in template.name.split_multiplatform_pybis() {
+            project_info.artifacts.push(ArtifactInfo {
+                name,
+                ..template.clone()
+            });
+        }
+    }
+    Ok(project_info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_json() -> Result<()> {
+        let parsed = parse_json(
+            &Url::parse("https://example.com/simple/foo/")?,
+            br#"{
+                "meta": {"api-version": "1.1", "_last-serial": 12345},
+                "name": "foo",
+                "files": [
+                    {
+                        "filename": "foo-1.0-py3-none-any.whl",
+                        "url": "../../files/foo-1.0-py3-none-any.whl",
+                        "hashes": {"sha256": "abcd", "md5": "ef01"},
+                        "requires-python": ">= 3.8",
+                        "core-metadata": {"sha256": "1234"},
+                        "dist-info-metadata": {"sha256": "1234"},
+                        "upload-time": "2023-01-23T04:56:07.123456Z",
+                        "size": 1234
+                    },
+                    {
+                        "filename": "foo-0.9.tar.gz",
+                        "url": "https://elsewhere.example.com/foo-0.9.tar.gz",
+                        "hashes": {},
+                        "yanked": "broken"
+                    },
+                    {
+                        "filename": "not an artifact",
+                        "url": "whatever",
+                        "hashes": {}
+                    }
+                ]
+            }"# as &[u8],
+        )?;
+        assert_eq!(parsed.meta.version, "1.1");
+        assert_eq!(parsed.meta.last_serial, Some(12345));
+        assert_eq!(parsed.artifacts.len(), 2);
+        let wheel = &parsed.artifacts[0];
+        assert_eq!(
+            wheel.url.as_str(),
+            "https://example.com/files/foo-1.0-py3-none-any.whl"
+        );
+        assert_eq!(wheel.hashes.len(), 2);
+        assert_eq!(wheel.hash().unwrap().to_string(), "sha256=abcd");
+        assert_eq!(wheel.requires_python.as_deref(), Some(">= 3.8"));
+        assert!(wheel.dist_info_metadata.available);
+        assert!(!wheel.yanked.yanked);
+        assert_eq!(
+            wheel.upload_time.as_deref(),
+            Some("2023-01-23T04:56:07.123456Z")
+        );
+        assert_eq!(wheel.size, Some(1234));
+        let sdist = &parsed.artifacts[1];
+        assert!(sdist.yanked.yanked);
+        assert_eq!(sdist.yanked.reason.as_deref(), Some("broken"));
+        assert!(sdist.upload_time.is_none());
+        assert!(sdist.size.is_none());
+        Ok(())
+    }
+}
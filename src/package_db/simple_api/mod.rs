@@ -1,7 +1,9 @@
 mod fetch;
 mod html;
+mod json;
 mod project_info;
 
-pub use fetch::fetch_simple_api;
+pub use fetch::{cached_simple_api, fetch_simple_api};
 use html::parse_html;
+use json::parse_json;
 pub use project_info::{pack_by_version, ArtifactInfo, ProjectInfo};
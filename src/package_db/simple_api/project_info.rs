@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::util::url_filename;
 
 use indexmap::IndexMap;
 
@@ -9,6 +10,10 @@ use indexmap::IndexMap;
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Meta {
     pub version: String,
+    /// PyPI's serial number for the last change to this project, if the index says;
+    /// it goes up every time anything about the project changes.
+    #[serde(rename = "_last-serial", skip_serializing_if = "Option::is_none")]
+    pub last_serial: Option<u64>,
 }
 
 // Clients interacting with the simple API SHOULD introspect each response for the
@@ -18,6 +23,7 @@ impl Default for Meta {
     fn default() -> Self {
         Self {
             version: "1.0".into(),
+            last_serial: None,
         }
     }
 }
@@ -93,18 +99,25 @@ impl From<RawYanked> for Yanked {
 pub struct ArtifactInfo {
     pub name: ArtifactName,
     pub url: Url,
-    // TODO: the json api allows this to be a map of algorithm->hex string, with
-    // any number of entries
-    // How do we handle multiple entries? simple API only has one hash, and for initial
-    // implementation warehouse's json API only has one hash, and supporting multiple
-    // hashes raises design questions for our caching strategy and lockfiles so... meh
-    // just gonna make that future-me's problem...
-    pub hash: Option<ArtifactHash>,
+    // The simple HTML API only has one hash, but the JSON API allows a map of
+    // algorithm->hex string, with any number of entries. We pin all of them, and
+    // check downloads against the strongest one we understand (see `hash()`) -- out
+    // of the ones we pinned, once there's a pin (see `PinnedPackage::pinned_artifact`).
+    pub hashes: Vec<ArtifactHash>,
     pub requires_python: Option<String>,
     //    #[serde(default)]
     pub dist_info_metadata: DistInfoMetadata,
     //    #[serde(default)]
     pub yanked: Yanked,
+    /// Where to find the PEP 740 attestations for this artifact, if there are any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Url>,
+    /// When it was uploaded, as an RFC 3339 timestamp. Only the JSON API has this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_time: Option<String>,
+    /// How big it is, in bytes. Only the JSON API has this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
 }
 
 impl ArtifactInfo {
@@ -112,10 +125,45 @@ impl ArtifactInfo {
         self.name.inner_as::<T::Name>().is_some()
     }
 
+    /// The hash to check downloads against, and to key caches by.
+    pub fn hash(&self) -> Option<&ArtifactHash> {
+        ArtifactHash::strongest(&self.hashes)
+    }
+
     pub fn require_hash(&self) -> Result<&ArtifactHash> {
-        self.hash
-            .as_ref()
-            .ok_or_else(|| eyre!("artifact {} has no hash", self.name))
+        self.hash().ok_or_else(|| {
+            if self.hashes.is_empty() {
+                eyre!("artifact {} has no hash", self.name)
+            } else {
+                eyre!("artifact {} has no hash we know how to check", self.name)
+            }
+        })
+    }
+
+    /// The artifact a `name @ url` requirement points at. We insist on a hash in the
+    /// fragment, same as index links have, since otherwise there's nothing to pin.
+    pub fn from_direct_reference(url: &Url) -> Result<ArtifactInfo> {
+        let filename =
+            url_filename(url).ok_or_else(|| eyre!("can't find a filename in {url}"))?;
+        let name: ArtifactName = filename.as_str().try_into()?;
+        let hash: ArtifactHash = url
+            .fragment()
+            .ok_or_else(|| eyre!("{url} needs a hash, like #sha256=..."))?
+            .try_into()
+            .wrap_err_with(|| format!("bad hash in {url}"))?;
+        // make sure we can actually check it
+        hash.checker(std::io::sink())?;
+        Ok(ArtifactInfo {
+            name,
+            url: url.clone(),
+            hashes: vec![hash],
+            requires_python: None,
+            dist_info_metadata: Default::default(),
+            yanked: Default::default(),
+            provenance: None,
+            upload_time: None,
+            size: None,
+        })
     }
 }
 
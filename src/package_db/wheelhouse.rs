@@ -0,0 +1,186 @@
+use crate::prelude::*;
+use crate::util::url_filename;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::simple_api::{ArtifactInfo, ProjectInfo};
+
+/// The file in a wheelhouse that lists everything in it.
+pub const WHEELHOUSE_MANIFEST: &str = "posy-wheelhouse.json";
+
+/// A directory of artifacts to install from instead of any index -- e.g. one that
+/// `posy bundle` filled in on a machine with network access, before it got copied to
+/// one without.
+///
+/// The manifest says what each file's hash is, and files get checked against that as
+/// we read them, same as any download. Which files we'll install is still up to the
+/// pins, so a file the manifest vouches for but posy.lock doesn't is never used.
+///
+/// Use it with `PackageDB::set_wheelhouse`.
+#[derive(Debug, Clone)]
+pub struct Wheelhouse {
+    dir: PathBuf,
+    manifest: Manifest,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Manifest {
+    /// filename -> hash
+    files: BTreeMap<String, ArtifactHash>,
+}
+
+impl Wheelhouse {
+    /// The wheelhouse in `dir`, which has to have a manifest.
+    pub fn open(dir: &Path) -> Result<Wheelhouse> {
+        let path = dir.join(WHEELHOUSE_MANIFEST);
+        context!("Reading {}", path.display());
+        let manifest = serde_json::from_slice(&fs::read(&path)?)?;
+        Ok(Wheelhouse {
+            dir: dir.into(),
+            manifest,
+        })
+    }
+
+    /// Starts a wheelhouse in `dir`, keeping anything that's already there.
+    pub fn create(dir: &Path) -> Result<Wheelhouse> {
+        if dir.join(WHEELHOUSE_MANIFEST).exists() {
+            return Wheelhouse::open(dir);
+        }
+        fs::create_dir_all(dir)?;
+        Ok(Wheelhouse {
+            dir: dir.into(),
+            manifest: Default::default(),
+        })
+    }
+
+    pub fn contains(&self, ai: &ArtifactInfo) -> bool {
+        let filename = ai.name.to_string();
+        ai.hash() == self.manifest.files.get(&filename)
+    }
+
+    /// Copies `body` in as `ai`'s file. It has to match `ai`'s hash. Call `save` when
+    /// you're done adding things.
+    pub fn add(&mut self, ai: &ArtifactInfo, body: &mut dyn Read) -> Result<()> {
+        let filename = ai.name.to_string();
+        let hash = ai.require_hash()?;
+        let path = self.dir.join(&filename);
+        context!("Writing {}", path.display());
+        let tmp = tempfile::NamedTempFile::new_in(&self.dir)?;
+        let mut checker = hash.checker(tmp.as_file())?;
+        std::io::copy(body, &mut checker)?;
+        checker.finish()?;
+        tmp.persist(&path)?;
+        self.manifest.files.insert(filename, hash.clone());
+        Ok(())
+    }
+
+    /// Writes out the manifest.
+    pub fn save(&self) -> Result<()> {
+        let path = self.dir.join(WHEELHOUSE_MANIFEST);
+        context!("Writing {}", path.display());
+        fs::write(path, serde_json::to_vec_pretty(&self.manifest)?)?;
+        Ok(())
+    }
+
+    /// How many files it has in it.
+    pub fn len(&self) -> usize {
+        self.manifest.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.manifest.files.is_empty()
+    }
+
+    fn artifact_info(
+        &self,
+        filename: &str,
+        hash: &ArtifactHash,
+    ) -> Result<ArtifactInfo> {
+        let path = self.dir.join(filename);
+        let url = Url::from_file_path(fs::canonicalize(&path)?)
+            .map_err(|()| eyre!("can't make a file url for {}", path.display()))?;
+        Ok(ArtifactInfo {
+            name: filename.try_into()?,
+            url,
+            hashes: vec![hash.clone()],
+            requires_python: None,
+            dist_info_metadata: Default::default(),
+            yanked: Default::default(),
+            provenance: None,
+            upload_time: None,
+            size: None,
+        })
+    }
+
+    /// Everything it has for `p`, as though it were an index page.
+    pub fn project_info(&self, p: &PackageName) -> Result<ProjectInfo> {
+        let mut artifacts = Vec::new();
+        for (filename, hash) in &self.manifest.files {
+            let name: ArtifactName = filename.as_str().try_into()?;
+            if name.distribution() == p {
+                artifacts.push(self.artifact_info(filename, hash)?);
+            }
+        }
+        Ok(ProjectInfo {
+            meta: Default::default(),
+            artifacts,
+        })
+    }
+
+    /// Our copy of the file at `url`, a direct reference. It has to have the same hash
+    /// as the url says.
+    pub fn direct_reference(&self, url: &Url) -> Result<ArtifactInfo> {
+        let wanted = ArtifactInfo::from_direct_reference(url)?;
+        let filename = url_filename(url).unwrap_or_default();
+        match self.manifest.files.get(&filename) {
+            Some(hash) if wanted.hashes.contains(hash) => {
+                self.artifact_info(&filename, hash)
+            }
+            _ => bail!("{url} isn't in the wheelhouse at {}", self.dir.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wheelhouse() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let contents = b"not really a wheel";
+        let digest = ring::digest::digest(&ring::digest::SHA256, contents);
+        let hex = data_encoding::HEXLOWER.encode(digest.as_ref());
+        let url = Url::parse(&format!(
+            "https://example.com/foo-1.0-py3-none-any.whl#sha256={hex}"
+        ))?;
+        let ai = ArtifactInfo::from_direct_reference(&url)?;
+
+        let mut wheelhouse = Wheelhouse::create(tmp.path())?;
+        assert!(!wheelhouse.contains(&ai));
+        // the hash has to match
+        let mut wrong = ai.clone();
+        wrong.hashes =
+            vec![format!("sha256={}", "00".repeat(32)).as_str().try_into()?];
+        assert!(wheelhouse.add(&wrong, &mut &b"something else"[..]).is_err());
+        wheelhouse.add(&ai, &mut &contents[..])?;
+        wheelhouse.save()?;
+
+        let wheelhouse = Wheelhouse::open(tmp.path())?;
+        assert!(wheelhouse.contains(&ai));
+        assert_eq!(wheelhouse.len(), 1);
+        let foo: PackageName = "foo".try_into()?;
+        let pi = wheelhouse.project_info(&foo)?;
+        assert_eq!(pi.artifacts.len(), 1);
+        assert_eq!(pi.artifacts[0].url.scheme(), "file");
+        assert_eq!(pi.artifacts[0].hashes, ai.hashes);
+        let bar: PackageName = "bar".try_into()?;
+        assert!(wheelhouse.project_info(&bar)?.artifacts.is_empty());
+        assert_eq!(wheelhouse.direct_reference(&url)?.url, pi.artifacts[0].url);
+        let elsewhere =
+            Url::parse("https://example.com/foo-1.0-py3-none-any.whl#sha256=00")?;
+        assert!(wheelhouse.direct_reference(&elsewhere).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+//! Reading pip's index settings, for people whose machines already point pip at a
+//! mirror. This only happens if posy.toml says `use-pip-config = true`, and only when
+//! posy.toml doesn't set `index-urls` itself.
+//!
+//! We look where pip does, in the same order, with later places winning:
+//!
+//! - the global config: `pip/pip.conf` in each of `$XDG_CONFIG_DIRS`, and
+//!   `/etc/pip.conf`, on Unix; `/Library/Application Support/pip/pip.conf` on macOS;
+//!   and `C:\ProgramData\pip\pip.ini` on Windows
+//! - the user config: `~/.pip/pip.conf` and `~/.config/pip/pip.conf` (or
+//!   `%APPDATA%\pip\pip.ini` on Windows)
+//! - `$VIRTUAL_ENV/pip.conf`, if there's an active virtualenv
+//! - `$PIP_CONFIG_FILE` (if that exists, pip skips the user config, and so do we)
+//! - `$PIP_INDEX_URL`, `$PIP_EXTRA_INDEX_URL`, and `$PIP_TRUSTED_HOST`
+//!
+//! Inside a file, `[install]` overrides `[global]`. We only care about `index-url`,
+//! `extra-index-url`, and `trusted-host`.
+
+use crate::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+
+/// pip's default index, for when there are only extra-index-urls.
+const PYPI_SIMPLE: &str = "https://pypi.org/simple/";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipIndexConfig {
+    pub index_url: Option<Url>,
+    pub extra_index_urls: Vec<Url>,
+    /// `host` or `host:port`
+    pub trusted_hosts: Vec<String>,
+}
+
+/// The `(section, key, value)` triples in an INI file, the way Python's configparser
+/// reads them: `key = value` or `key: value`, with indented lines continuing the
+/// previous value, and `#` or `;` starting a comment line. Keys get normalized the way
+/// pip does it, so `index_url` and `index-url` are the same.
+fn parse_ini(text: &str) -> Result<Vec<(String, String, String)>> {
+    let mut entries: Vec<(String, String, String)> = Vec::new();
+    let mut section: Option<String> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            match entries.last_mut() {
+                Some(entry) => {
+                    entry.2.push('\n');
+                    entry.2.push_str(trimmed);
+                    continue;
+                }
+                None => bail!("continuation line without a key: {line:?}"),
+            }
+        }
+        if let Some(name) = trimmed.strip_prefix('[') {
+            let Some(name) = name.strip_suffix(']') else {
+                bail!("bad section header: {line:?}");
+            };
+            section = Some(name.trim().to_owned());
+            continue;
+        }
+        let Some(section) = &section else {
+            bail!("setting outside of any [section]: {line:?}");
+        };
+        let Some((key, value)) = trimmed.split_once(['=', ':']) else {
+            bail!("expected 'key = value', not {line:?}");
+        };
+        entries.push((
+            section.clone(),
+            key.trim().to_ascii_lowercase().replace('_', "-"),
+            value.trim().to_owned(),
+        ));
+    }
+    Ok(entries)
+}
+
+/// pip doesn't care whether index urls end in a slash, but we join package names onto
+/// them, so they have to.
+fn split_urls(value: &str) -> Result<Vec<Url>> {
+    value
+        .split_whitespace()
+        .map(|url| {
+            let mut url =
+                Url::parse(url).wrap_err_with(|| format!("bad index url {url:?}"))?;
+            if !url.path().ends_with('/') {
+                url.set_path(&format!("{}/", url.path()));
+            }
+            Ok(url)
+        })
+        .collect()
+}
+
+impl PipIndexConfig {
+    /// Folds in one config file. Within a file, a setting in `[install]` beats the
+    /// same one in `[global]`; either way, it replaces whatever earlier files said.
+    fn apply_file(&mut self, text: &str) -> Result<()> {
+        let entries = parse_ini(text)?;
+        for wanted in ["global", "install"] {
+            for (section, key, value) in &entries {
+                if section != wanted {
+                    continue;
+                }
+                self.apply(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "index-url" => self.index_url = split_urls(value)?.into_iter().next(),
+            "extra-index-url" => self.extra_index_urls = split_urls(value)?,
+            "trusted-host" => {
+                self.trusted_hosts =
+                    value.split_whitespace().map(|h| h.to_owned()).collect()
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Index settings from the config `files` (in the order pip reads them), and then
+    /// from the environment, via `lookup`.
+    fn from_sources<F>(files: &[PathBuf], lookup: F) -> Result<PipIndexConfig>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = PipIndexConfig::default();
+        for path in files {
+            let Ok(text) = fs::read_to_string(path) else {
+                continue;
+            };
+            context!("Reading pip configuration from {}", path.display());
+            config.apply_file(&text)?;
+        }
+        for (var, key) in [
+            ("PIP_INDEX_URL", "index-url"),
+            ("PIP_EXTRA_INDEX_URL", "extra-index-url"),
+            ("PIP_TRUSTED_HOST", "trusted-host"),
+        ] {
+            if let Some(value) = lookup(var) {
+                context!("Reading ${var}");
+                config.apply(key, &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whatever pip would use on this machine.
+    pub fn load() -> Result<PipIndexConfig> {
+        PipIndexConfig::from_sources(&config_files(), |var| std::env::var(var).ok())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index_url.is_none() && self.extra_index_urls.is_empty()
+    }
+
+    fn trusts(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let with_port = url.port().map(|port| format!("{host}:{port}"));
+        self.trusted_hosts.iter().any(|trusted| {
+            trusted.eq_ignore_ascii_case(host)
+                || Some(trusted.to_ascii_lowercase()) == with_port
+        })
+    }
+
+    /// The indexes pip would search, in order. Like pip, we skip plain-http indexes
+    /// unless their host is in trusted-host. (For https ones, trusted-host doesn't
+    /// change anything: we check certificates against the system's trust store no
+    /// matter what.)
+    pub fn index_urls(&self) -> Vec<Url> {
+        let main = self
+            .index_url
+            .clone()
+            .unwrap_or_else(|| Url::parse(PYPI_SIMPLE).unwrap());
+        std::iter::once(main)
+            .chain(self.extra_index_urls.iter().cloned())
+            .filter(|url| {
+                if url.scheme() == "http" && !self.trusts(url) {
+                    warn!(
+                        "ignoring pip's index {url}: it isn't https, and its host \
+                         isn't in trusted-host"
+                    );
+                    return false;
+                }
+                true
+            })
+            .collect()
+    }
+}
+
+/// Where pip looks for config files, lowest priority first.
+fn config_files() -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if cfg!(windows) {
+        files.push(PathBuf::from(r"C:\ProgramData\pip\pip.ini"));
+    } else {
+        if cfg!(target_os = "macos") {
+            files.push("/Library/Application Support/pip/pip.conf".into());
+        }
+        let xdg_dirs =
+            std::env::var("XDG_CONFIG_DIRS").unwrap_or_else(|_| "/etc/xdg".into());
+        for dir in xdg_dirs.split(':').filter(|d| !d.is_empty()) {
+            files.push(PathBuf::from(dir).join("pip").join("pip.conf"));
+        }
+        files.push("/etc/pip.conf".into());
+    }
+
+    // Like pip, if $PIP_CONFIG_FILE points at something that exists (including
+    // /dev/null), that replaces the user's own config files.
+    let env_file = std::env::var_os("PIP_CONFIG_FILE").map(PathBuf::from);
+    let skip_user = env_file.as_ref().map_or(false, |f| f.exists());
+    if let (false, Some(home)) = (skip_user, directories::BaseDirs::new()) {
+        if cfg!(windows) {
+            files.push(home.home_dir().join("pip").join("pip.ini"));
+            files.push(home.config_dir().join("pip").join("pip.ini"));
+        } else {
+            files.push(home.home_dir().join(".pip").join("pip.conf"));
+            if cfg!(target_os = "macos") {
+                files.push(home.config_dir().join("pip").join("pip.conf"));
+            }
+            let xdg_config = std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.home_dir().join(".config"));
+            files.push(xdg_config.join("pip").join("pip.conf"));
+        }
+    }
+    if let Some(venv) = std::env::var_os("VIRTUAL_ENV") {
+        let name = if cfg!(windows) { "pip.ini" } else { "pip.conf" };
+        files.push(PathBuf::from(venv).join(name));
+    }
+    files.extend(env_file);
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_ini() -> Result<()> {
+        let entries = parse_ini(indoc::indoc! {"
+            # a comment
+            [global]
+            index_url = https://mirror.example.com/simple
+            extra-index-url =
+                https://a.example.com/simple
+                https://b.example.com/simple
+            ; another comment
+            [install]
+            trusted-host: a.example.com
+        "})?;
+        let entries: Vec<(&str, &str, &str)> = entries
+            .iter()
+            .map(|(s, k, v)| (s.as_str(), k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("global", "index-url", "https://mirror.example.com/simple"),
+                (
+                    "global",
+                    "extra-index-url",
+                    "\nhttps://a.example.com/simple\nhttps://b.example.com/simple"
+                ),
+                ("install", "trusted-host", "a.example.com"),
+            ]
+        );
+        assert!(parse_ini("index-url = https://example.com\n").is_err());
+        assert!(parse_ini("[global\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pip_index_config() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let global = tmp.path().join("global.conf");
+        let user = tmp.path().join("user.conf");
+        fs::write(
+            &global,
+            indoc::indoc! {"
+                [global]
+                index-url = https://global.example.com/simple
+                extra-index-url = http://insecure.example.com/simple
+                [install]
+                index-url = https://install.example.com/simple
+            "},
+        )?;
+        fs::write(
+            &user,
+            indoc::indoc! {"
+                [global]
+                extra-index-url =
+                    http://mirror.example.com:8080/simple
+                    http://insecure.example.com/simple
+                trusted-host = mirror.example.com:8080
+            "},
+        )?;
+        let missing = tmp.path().join("missing.conf");
+
+        let no_env = |_: &str| None;
+        let config = PipIndexConfig::from_sources(&[global.clone(), missing], no_env)?;
+        assert_eq!(
+            config.index_url.as_ref().map(|u| u.as_str()),
+            Some("https://install.example.com/simple/")
+        );
+        let config =
+            PipIndexConfig::from_sources(&[global.clone(), user.clone()], no_env)?;
+        let urls: Vec<String> =
+            config.index_urls().iter().map(|u| u.to_string()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://install.example.com/simple/",
+                "http://mirror.example.com:8080/simple/"
+            ]
+        );
+
+        // environment variables beat everything
+        let env = |var: &str| match var {
+            "PIP_INDEX_URL" => Some("https://env.example.com/simple".to_string()),
+            "PIP_EXTRA_INDEX_URL" => Some("http://insecure.example.com/simple".into()),
+            "PIP_TRUSTED_HOST" => Some("insecure.example.com".into()),
+            _ => None,
+        };
+        let config = PipIndexConfig::from_sources(&[global, user], env)?;
+        let urls: Vec<String> =
+            config.index_urls().iter().map(|u| u.to_string()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://env.example.com/simple/",
+                "http://insecure.example.com/simple/"
+            ]
+        );
+
+        // extra indexes on their own go after PyPI
+        let mut config = PipIndexConfig::default();
+        assert!(config.is_empty());
+        config.apply("extra-index-url", "https://extra.example.com/simple")?;
+        assert!(!config.is_empty());
+        assert_eq!(config.index_urls()[0].as_str(), PYPI_SIMPLE);
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use crate::prelude::*;
+
+// How platform tags map onto the PEP 508 marker variables that only depend on the OS
+// and CPU: os_name, sys_platform, platform_system, and platform_machine. These are
+// what Python itself reports on each platform (os.name, sys.platform,
+// platform.system(), platform.machine()), which isn't always spelled the same way as
+// the tag -- e.g. win_amd64 is "AMD64", but manylinux x86_64 is "x86_64".
+
+/// (platform tag prefix, os_name, sys_platform, platform_system)
+const OS_MARKERS: &[(&str, &str, &str, &str)] = &[
+    ("win", "nt", "win32", "Windows"),
+    ("macosx_", "posix", "darwin", "Darwin"),
+    ("manylinux", "posix", "linux", "Linux"),
+    ("musllinux_", "posix", "linux", "Linux"),
+    ("linux_", "posix", "linux", "Linux"),
+    ("ios_", "posix", "ios", "iOS"),
+    ("android_", "posix", "android", "Android"),
+    ("emscripten_", "posix", "emscripten", "Emscripten"),
+    ("pyodide_", "posix", "emscripten", "Emscripten"),
+];
+
+/// Android's ABI names -> what platform.machine() says
+const ANDROID_MACHINES: &[(&str, &str)] = &[
+    ("arm64_v8a", "aarch64"),
+    ("armeabi_v7a", "armv7l"),
+    ("x86_64", "x86_64"),
+    ("x86", "i686"),
+];
+
+/// The macOS architectures that are one particular CPU, rather than a fat binary
+const MACOS_MACHINES: &[&str] = &["arm64", "x86_64", "i386", "ppc", "ppc64"];
+
+static LINUX_ARCH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:(?:many|musl)linux_\d+_\d+|manylinux\d+|linux)_(.+)$").unwrap()
+});
+
+static MACOS_ARCH: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^macosx_\d+_\d+_(.+)$").unwrap());
+
+static ANDROID_ABI: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^android_\d+_(.+)$").unwrap());
+
+/// Just the platform part of a tag, so that wheel tags like
+/// `cp311-cp311-manylinux_2_17_x86_64` work as well as `manylinux_2_17_x86_64`.
+fn platform_part(tag: &str) -> &str {
+    tag.rsplit('-').next().unwrap()
+}
+
+/// os_name, sys_platform, and platform_system for a platform tag, if we know it.
+fn os_markers(tag: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    let tag = platform_part(tag);
+    OS_MARKERS
+        .iter()
+        .find(|(prefix, ..)| tag.starts_with(prefix))
+        .map(|(_, os_name, sys_platform, system)| (*os_name, *sys_platform, *system))
+}
+
+/// What platform.machine() returns wherever `tag` runs, if that's one thing.
+/// (It isn't for fat macOS binaries, or on iOS, where it's the device model.)
+fn tag_machine(tag: &str) -> Option<String> {
+    let tag = platform_part(tag);
+    match tag {
+        "win32" => return Some("x86".into()),
+        "win_amd64" => return Some("AMD64".into()),
+        "win_arm64" => return Some("ARM64".into()),
+        _ => (),
+    }
+    if let Some(captures) = MACOS_ARCH.captures(tag) {
+        let arch = &captures[1];
+        return MACOS_MACHINES.contains(&arch).then(|| arch.to_string());
+    }
+    if let Some(captures) = ANDROID_ABI.captures(tag) {
+        return ANDROID_MACHINES
+            .iter()
+            .find(|(abi, _)| *abi == &captures[1])
+            .map(|(_, machine)| machine.to_string());
+    }
+    if tag.starts_with("emscripten_") || tag.starts_with("pyodide_") {
+        return tag.rsplit('_').next().map(String::from);
+    }
+    // manylinux_2_17_x86_64, musllinux_1_1_aarch64, linux_armv7l, ...
+    LINUX_ARCH
+        .captures(tag)
+        .map(|captures| captures[1].to_string())
+}
+
+/// The marker variables that follow from a list of platform (or wheel) tags alone,
+/// like a `Platform`'s. The OS comes from the first tag that isn't `any`; the machine
+/// is filled in if all the tags that say one agree, so that it comes out as arm64 for
+/// a platform that can use both arm64 and universal2 wheels, but is left out for one
+/// that can use x86_64 and arm64 both.
+pub fn marker_variables_for_tags<I, S>(tags: I) -> Vec<(&'static str, String)>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let tags: Vec<S> = tags
+        .into_iter()
+        .filter(|tag| platform_part(tag.as_ref()) != "any")
+        .collect();
+    let mut vars = Vec::new();
+    let Some(first) = tags.first() else {
+        return vars;
+    };
+    let Some((os_name, sys_platform, system)) = os_markers(first.as_ref()) else {
+        return vars;
+    };
+    vars.push(("os_name", os_name.to_string()));
+    vars.push(("sys_platform", sys_platform.to_string()));
+    vars.push(("platform_system", system.to_string()));
+
+    let mut machines: Vec<String> = tags
+        .iter()
+        .filter(|tag| os_markers(tag.as_ref()).map(|m| m.1) == Some(sys_platform))
+        .filter_map(|tag| tag_machine(tag.as_ref()))
+        .collect();
+    machines.sort_unstable();
+    machines.dedup();
+    if let [machine] = machines.as_slice() {
+        vars.push(("platform_machine", machine.clone()));
+    }
+    vars
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_marker_variables_for_tags() {
+        let table: &[(&[&str], &[&str])] = &[
+            // tags => os_name, sys_platform, platform_system, platform_machine
+            (&["win32"], &["nt", "win32", "Windows", "x86"]),
+            (&["win_amd64"], &["nt", "win32", "Windows", "AMD64"]),
+            (&["win_arm64"], &["nt", "win32", "Windows", "ARM64"]),
+            (
+                &["macosx_11_0_arm64", "macosx_11_0_universal2"],
+                &["posix", "darwin", "Darwin", "arm64"],
+            ),
+            (
+                &["macosx_10_9_x86_64", "macosx_10_9_intel"],
+                &["posix", "darwin", "Darwin", "x86_64"],
+            ),
+            // could be running either way
+            (&["macosx_11_0_universal2"], &["posix", "darwin", "Darwin"]),
+            (
+                &["macosx_11_0_arm64", "macosx_11_0_x86_64"],
+                &["posix", "darwin", "Darwin"],
+            ),
+            (
+                &["manylinux_2_17_x86_64", "manylinux2014_x86_64"],
+                &["posix", "linux", "Linux", "x86_64"],
+            ),
+            (
+                &["manylinux_2_28_aarch64"],
+                &["posix", "linux", "Linux", "aarch64"],
+            ),
+            (&["manylinux1_i686"], &["posix", "linux", "Linux", "i686"]),
+            (
+                &["musllinux_1_1_ppc64le"],
+                &["posix", "linux", "Linux", "ppc64le"],
+            ),
+            (&["linux_armv7l"], &["posix", "linux", "Linux", "armv7l"]),
+            (
+                &["manylinux_2_17_s390x"],
+                &["posix", "linux", "Linux", "s390x"],
+            ),
+            (&["ios_13_0_arm64_iphoneos"], &["posix", "ios", "iOS"]),
+            (
+                &["android_21_arm64_v8a"],
+                &["posix", "android", "Android", "aarch64"],
+            ),
+            (
+                &["android_21_armeabi_v7a"],
+                &["posix", "android", "Android", "armv7l"],
+            ),
+            (
+                &["android_21_x86"],
+                &["posix", "android", "Android", "i686"],
+            ),
+            (
+                &["pyodide_2024_0_wasm32"],
+                &["posix", "emscripten", "Emscripten", "wasm32"],
+            ),
+            (
+                &["emscripten_3_1_58_wasm32"],
+                &["posix", "emscripten", "Emscripten", "wasm32"],
+            ),
+            // wheel tags work too, and `any` doesn't count
+            (
+                &[
+                    "cp311-cp311-manylinux_2_17_x86_64",
+                    "cp311-abi3-manylinux_2_17_x86_64",
+                    "py3-none-any",
+                ],
+                &["posix", "linux", "Linux", "x86_64"],
+            ),
+            (&["py3-none-any"], &[]),
+            (&["solaris_2_11_sun4v"], &[]),
+        ];
+        let names = [
+            "os_name",
+            "sys_platform",
+            "platform_system",
+            "platform_machine",
+        ];
+        for (tags, expected) in table {
+            let vars = marker_variables_for_tags(tags.iter());
+            let expected: Vec<(&str, String)> = names
+                .iter()
+                .copied()
+                .zip(expected.iter().map(|v| v.to_string()))
+                .collect();
+            assert_eq!(vars, expected, "for {tags:?}");
+        }
+    }
+}
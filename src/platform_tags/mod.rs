@@ -31,5 +31,8 @@ mod macos;
 use macos::core_platform_tags;
 
 mod expand;
+mod markers;
 mod platform;
-pub use platform::{Platform, PybiPlatform, WheelPlatform};
+pub use platform::{
+    missing_marker_variables, Platform, PybiPlatform, WheelPlatform, MARKER_VARIABLES,
+};
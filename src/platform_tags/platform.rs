@@ -1,4 +1,5 @@
 use super::expand::expand_platform_tag;
+use super::markers::marker_variables_for_tags;
 use crate::prelude::*;
 use indexmap::IndexSet;
 use once_cell::sync::OnceCell;
@@ -7,11 +8,15 @@ fn compatibility(tags: &IndexSet<String>, tag: &str) -> Option<i32> {
     tags.get_index_of(tag).map(|score| -(score as i32))
 }
 
+/// A platform that Pythons (pybis) get built for, like `manylinux_2_17_x86_64`, with
+/// all the tags that are compatible with it, best first.
 #[derive(Debug, Clone)]
 pub struct PybiPlatform {
     tags: IndexSet<String>,
 }
 
+/// The wheel tags that some particular Python can use, best first. Get one from
+/// `PybiPlatform::wheel_platform`.
 #[derive(Debug, Clone)]
 pub struct WheelPlatform {
     tags: IndexSet<String>,
@@ -31,6 +36,13 @@ pub trait Platform {
             .filter_map(|t| self.compatibility(t.as_ref()))
             .max()
     }
+
+    /// The marker variables that follow from these tags alone: os_name,
+    /// sys_platform, platform_system, and platform_machine if it's unambiguous. For
+    /// resolving for some other platform than this one, without a Python to ask.
+    fn marker_variables(&self) -> Vec<(&'static str, String)> {
+        marker_variables_for_tags(self.tags())
+    }
 }
 
 impl Platform for PybiPlatform {
@@ -53,6 +65,22 @@ impl Platform for WheelPlatform {
     }
 }
 
+/// All the PEP 508 environment marker variables (except 'extra', which isn't really
+/// about the environment).
+pub const MARKER_VARIABLES: &[&str] = &[
+    "os_name",
+    "sys_platform",
+    "platform_machine",
+    "platform_python_implementation",
+    "platform_release",
+    "platform_system",
+    "platform_version",
+    "python_version",
+    "python_full_version",
+    "implementation_name",
+    "implementation_version",
+];
+
 static NATIVE_PLATFORMS: OnceCell<Vec<PybiPlatform>> = OnceCell::new();
 
 static NATIVE_PLATFORM_REFS: OnceCell<Vec<&'static PybiPlatform>> = OnceCell::new();
@@ -91,6 +119,29 @@ impl PybiPlatform {
         Ok(refs.as_slice())
     }
 
+    /// Makes `native_platforms` return these, instead of what we detect -- e.g. to
+    /// stick to an older manylinux than this machine could run, or because detection
+    /// gets it wrong. Has to be called before anything asks for `native_platforms`.
+    pub fn set_native_platforms(core_tags: &[String]) -> Result<()> {
+        let platforms = core_tags.iter().map(|tag| PybiPlatform::new(tag)).collect();
+        if NATIVE_PLATFORMS.set(platforms).is_err() {
+            let current: Vec<&str> = NATIVE_PLATFORMS
+                .get()
+                .unwrap()
+                .iter()
+                .map(|p| p.core_tag())
+                .collect();
+            if current != core_tags {
+                bail!(
+                    "can't use platforms {}: already using {}",
+                    core_tags.join(", "),
+                    current.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub fn is_native(&self) -> Result<bool> {
         let natives = PybiPlatform::native_platforms()?;
         Ok(natives
@@ -112,6 +163,61 @@ impl PybiPlatform {
 
         Ok(WheelPlatform { tags: wheel_tags })
     }
+
+    /// Pybis are supposed to list their environment marker variables, but some leave
+    /// a few out -- especially ones that can differ between machines that can run the
+    /// same pybi, like platform_machine on a universal2 build. This fills in whatever
+    /// we can work out from the pybi's name and version, and from this platform.
+    ///
+    /// Some things can't be worked out this way (platform_release and
+    /// platform_version come from the OS kernel, not the Python), so this never fails;
+    /// `missing_marker_variables` says what's still unknown afterwards.
+    pub fn synthesize_marker_variables(
+        &self,
+        metadata: &PybiCoreMetadata,
+        vars: &mut HashMap<String, String>,
+    ) {
+        // Could be a fat binary, like universal2, in which case we go with whichever
+        // architecture this platform can actually run.
+        let mut derived = self.marker_variables();
+
+        let python = metadata.version.to_string();
+        // Just the X.Y, without any pre-release suffix (3.14rc1 is "3.14" too)
+        let python_version = metadata
+            .version
+            .0
+            .release
+            .iter()
+            .take(2)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        derived.push(("python_version", python_version));
+        derived.push(("python_full_version", python.clone()));
+        let name = metadata.name.normalized();
+        if name.starts_with("cpython") {
+            derived.push(("implementation_name", "cpython".into()));
+            derived.push(("platform_python_implementation", "CPython".into()));
+            // For CPython, the implementation *is* the language version
+            derived.push(("implementation_version", python));
+        } else if name.starts_with("pypy") {
+            derived.push(("implementation_name", "pypy".into()));
+            derived.push(("platform_python_implementation", "PyPy".into()));
+        }
+
+        for (var, value) in derived {
+            vars.entry(var.into()).or_insert(value);
+        }
+    }
+}
+
+/// The standard marker variables that aren't in `vars`.
+pub fn missing_marker_variables(vars: &HashMap<String, String>) -> Vec<&'static str> {
+    MARKER_VARIABLES
+        .iter()
+        .copied()
+        .filter(|var| !vars.contains_key(*var))
+        .collect()
 }
 
 #[cfg(test)]
@@ -134,6 +240,64 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_synthesize_marker_variables() {
+        let metadata: PybiCoreMetadata = indoc! {br#"
+            Metadata-Version: 2.1
+            Name: cpython_unofficial
+            Version: 3.11.2
+            Pybi-Environment-Marker-Variables: {"os_name": "posix"}
+            Pybi-Paths: {}
+        "#}
+        .as_slice()
+        .try_into()
+        .unwrap();
+
+        let mut vars = metadata.environment_marker_variables.clone();
+        PybiPlatform::new("manylinux_2_17_x86_64")
+            .synthesize_marker_variables(&metadata, &mut vars);
+        assert_eq!(vars["sys_platform"], "linux");
+        assert_eq!(vars["platform_system"], "Linux");
+        assert_eq!(vars["platform_machine"], "x86_64");
+        assert_eq!(vars["python_version"], "3.11");
+        assert_eq!(vars["implementation_name"], "cpython");
+        assert_eq!(vars["implementation_version"], "3.11.2");
+        assert_eq!(
+            missing_marker_variables(&vars),
+            vec!["platform_release", "platform_version"]
+        );
+
+        let mut vars = HashMap::from([("os_name".into(), "from-metadata".into())]);
+        PybiPlatform::new("win_amd64")
+            .synthesize_marker_variables(&metadata, &mut vars);
+        // Anything the pybi tells us wins
+        assert_eq!(vars["os_name"], "from-metadata");
+        assert_eq!(vars["sys_platform"], "win32");
+        assert_eq!(vars["platform_machine"], "AMD64");
+
+        let mut vars = HashMap::new();
+        PybiPlatform::new("macosx_11_0_arm64")
+            .synthesize_marker_variables(&metadata, &mut vars);
+        assert_eq!(vars["platform_machine"], "arm64");
+        assert_eq!(vars["sys_platform"], "darwin");
+
+        let metadata: PybiCoreMetadata = indoc! {br#"
+            Metadata-Version: 2.1
+            Name: cpython_unofficial
+            Version: 3.14rc1
+            Pybi-Environment-Marker-Variables: {}
+            Pybi-Paths: {}
+        "#}
+        .as_slice()
+        .try_into()
+        .unwrap();
+        let mut vars = HashMap::new();
+        PybiPlatform::new("manylinux_2_17_x86_64")
+            .synthesize_marker_variables(&metadata, &mut vars);
+        assert_eq!(vars["python_version"], "3.14");
+        assert_eq!(vars["python_full_version"], "3.14rc1");
+    }
+
     #[test]
     fn test_pybi_platform_to_wheel_platform() {
         let pybi_platform = PybiPlatform::new("macosx_11_0_arm64");
@@ -161,6 +325,9 @@ mod test {
         assert!(wheel_platform
             .compatibility("foo-bar-macosx_11_0_x86_64")
             .is_none());
+        assert!(wheel_platform
+            .marker_variables()
+            .contains(&("platform_machine", "arm64".into())));
 
         // also tags are sorted properly
         assert!(
@@ -0,0 +1,561 @@
+//! Organizational policy: which packages and versions we're allowed to pick at all,
+//! enforced by the resolver, so that anything disallowed just isn't a candidate
+//! (instead of getting flagged by some audit after it's already locked). A policy file
+//! looks like:
+//!
+//!   # if non-empty, nothing else can be installed
+//!   allow = ["attrs", "requests", "urllib3"]
+//!   deny = ["leftpad"]
+//!   # no artifacts that were uploaded less than a week ago
+//!   min-release-age-days = 7
+//!   # SPDX license ids, case-insensitive, with an optional trailing * wildcard
+//!   deny-licenses = ["AGPL-*", "SSPL-1.0"]
+//!   # if non-empty, only these licenses (and packages that declare them) will do
+//!   allow-licenses = []
+//!
+//!   [[ban]]
+//!   package = "urllib3"
+//!   versions = "< 1.26.5"
+//!   reason = "CVE-2021-33503"
+//!
+//! They can come from the user's posy.toml and the project's pyproject.toml (`policy =
+//! "path/to/policy.toml"` in either), and everything has to satisfy all of them. The
+//! rules cover everything that gets resolved, including sdist build environments, but
+//! not the Python itself.
+//!
+//! Release ages need upload times, which only the JSON index API has. Artifacts from an
+//! index that doesn't give them are let through.
+//!
+//! Licenses come from each release's METADATA: its License-Expression, or failing
+//! that, its License classifiers (see `WheelCoreMetadata::spdx_license`). An
+//! expression is fine if there's some way to satisfy it using only licenses the policy
+//! accepts, so `MIT OR AGPL-3.0-only` gets through a ban on AGPL, but `MIT AND
+//! AGPL-3.0-only` doesn't. Packages that don't declare a license we understand are let
+//! through, unless there's an `allow-licenses` list for them to be missing from.
+
+use crate::package_db::ArtifactInfo;
+use crate::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Ban {
+    pub package: PackageName,
+    pub versions: Specifiers,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct PolicyFile {
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub allow: Vec<PackageName>,
+    pub deny: Vec<PackageName>,
+    pub ban: Vec<Ban>,
+    pub min_release_age_days: Option<u64>,
+    pub allow_licenses: Vec<String>,
+    pub deny_licenses: Vec<String>,
+}
+
+impl PolicyFile {
+    pub fn parse(path: &Path, contents: &str) -> Result<PolicyFile> {
+        Ok(PolicyFile {
+            path: path.to_owned(),
+            ..toml_edit::de::from_str(contents)?
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<PolicyFile> {
+        context!("Loading policy from {}", path.display());
+        PolicyFile::parse(path, &fs::read_to_string(path)?)
+    }
+
+    fn cite(&self, why: String) -> String {
+        format!("{why}, by {}", self.path.display())
+    }
+
+    fn excludes_package(&self, p: &PackageName) -> Option<String> {
+        if self.deny.contains(p) {
+            Some(format!("{} is denied", p.as_given()))
+        } else if !self.allow.is_empty() && !self.allow.contains(p) {
+            Some(format!("{} isn't on the allow list", p.as_given()))
+        } else {
+            None
+        }
+    }
+
+    fn excludes_version(&self, p: &PackageName, v: &Version) -> Result<Option<String>> {
+        for ban in &self.ban {
+            if &ban.package == p && ban.versions.satisfied_by(v)? {
+                let mut why = format!("{} {} is banned", p.as_given(), ban.versions);
+                if let Some(reason) = &ban.reason {
+                    why.push_str(&format!(" ({reason})"));
+                }
+                return Ok(Some(why));
+            }
+        }
+        Ok(None)
+    }
+
+    fn excludes_upload(
+        &self,
+        upload_time: Option<&str>,
+        now: SystemTime,
+    ) -> Option<String> {
+        let days = self.min_release_age_days?;
+        let uploaded = parse_timestamp(upload_time?)?;
+        // An age too big to count back from now can't be meant literally, so there's
+        // no cutoff.
+        let cutoff = days
+            .checked_mul(24 * 60 * 60)
+            .and_then(|secs| now.checked_sub(Duration::from_secs(secs)))?;
+        if uploaded > cutoff {
+            Some(format!("uploaded less than {days} days ago"))
+        } else {
+            None
+        }
+    }
+
+    fn checks_licenses(&self) -> bool {
+        !self.allow_licenses.is_empty() || !self.deny_licenses.is_empty()
+    }
+
+    /// Why a single license id is no good, if it isn't.
+    fn objects_to_license_id(&self, id: &str) -> Option<String> {
+        if self.deny_licenses.iter().any(|p| license_matches(p, id)) {
+            Some(format!("{id} is denied"))
+        } else if !self.allow_licenses.is_empty()
+            && !self.allow_licenses.iter().any(|p| license_matches(p, id))
+        {
+            Some(format!("{id} isn't on allow-licenses"))
+        } else {
+            None
+        }
+    }
+
+    fn excludes_license(&self, license: Option<&str>) -> Option<String> {
+        if !self.checks_licenses() {
+            return None;
+        }
+        let unknown = || {
+            (!self.allow_licenses.is_empty()).then(|| {
+                "no recognizable license, and allow-licenses is set".to_owned()
+            })
+        };
+        let Some(expr) = license else {
+            return unknown();
+        };
+        let Ok(parsed) = LicenseExpression::parse(expr) else {
+            return unknown();
+        };
+        let mut objections: Vec<String> = Vec::new();
+        let allowed =
+            parsed.satisfiable(&mut |id| match self.objects_to_license_id(id) {
+                Some(why) => {
+                    if !objections.contains(&why) {
+                        objections.push(why);
+                    }
+                    false
+                }
+                None => true,
+            });
+        match allowed {
+            true => None,
+            false if objections.len() == 1 && !expr.contains(' ') => {
+                Some(format!("license {}", objections.pop().unwrap()))
+            }
+            false => Some(format!(
+                "license {expr} isn't allowed ({})",
+                objections.join("; ")
+            )),
+        }
+    }
+}
+
+/// Matches an SPDX id against a pattern from allow-licenses/deny-licenses.
+fn license_matches(pattern: &str, id: &str) -> bool {
+    let (pattern, id) = (pattern.to_ascii_lowercase(), id.to_ascii_lowercase());
+    match pattern.strip_suffix('*') {
+        Some(prefix) => id.starts_with(prefix),
+        None => id == pattern,
+    }
+}
+
+/// All the policy files that apply, which all have to agree before we'll use
+/// something. The empty policy allows everything.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    files: Vec<PolicyFile>,
+}
+
+impl Policy {
+    pub fn new(files: Vec<PolicyFile>) -> Policy {
+        Policy { files }
+    }
+
+    pub fn load(paths: &[&Path]) -> Result<Policy> {
+        Ok(Policy::new(
+            paths
+                .iter()
+                .map(|path| PolicyFile::load(path))
+                .collect::<Result<_>>()?,
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Whether any of the files care about licenses, so it's worth looking them up.
+    pub fn checks_licenses(&self) -> bool {
+        self.files.iter().any(|file| file.checks_licenses())
+    }
+
+    /// The first objection any of the files has, and which file it came from.
+    fn first<F>(&self, mut f: F) -> Option<String>
+    where
+        F: FnMut(&PolicyFile) -> Option<String>,
+    {
+        self.files
+            .iter()
+            .find_map(|file| f(file).map(|why| file.cite(why)))
+    }
+
+    /// Why a package can't be used at all, if it can't.
+    pub fn excludes_package(&self, p: &PackageName) -> Option<String> {
+        self.first(|file| file.excludes_package(p))
+    }
+
+    /// Why a release can't be used, if it can't, not counting the age of its
+    /// artifacts. (So this is what applies to things that are already locked.)
+    pub fn excludes_release(
+        &self,
+        p: &PackageName,
+        v: &Version,
+    ) -> Result<Option<String>> {
+        if let Some(why) = self.excludes_package(p) {
+            return Ok(Some(why));
+        }
+        for file in &self.files {
+            if let Some(why) = file.excludes_version(p, v)? {
+                return Ok(Some(file.cite(why)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Why a particular artifact can't be used, if it can't, not counting anything
+    /// `excludes_release` would catch.
+    pub fn excludes_artifact(
+        &self,
+        ai: &ArtifactInfo,
+        now: SystemTime,
+    ) -> Option<String> {
+        self.first(|file| file.excludes_upload(ai.upload_time.as_deref(), now))
+    }
+
+    /// Why a release can't be used because of its declared license (an SPDX
+    /// expression, from `PackageDB::get_license`), if it can't.
+    pub fn excludes_license(&self, license: Option<&str>) -> Option<String> {
+        self.first(|file| file.excludes_license(license))
+    }
+}
+
+/// Parses an RFC 3339 timestamp, like `2023-01-23T04:56:07.123456Z`, to the second.
+pub fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    static TIMESTAMP: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(concat!(
+            r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?",
+            r"(?:[Zz]|([+-])(\d{2}):(\d{2}))$",
+        ))
+        .unwrap()
+    });
+    let captures = TIMESTAMP.captures(s)?;
+    let field = |i: usize| -> i64 {
+        captures.get(i).map_or(0, |m| m.as_str().parse().unwrap())
+    };
+    let (year, month, day) = (field(1), field(2), field(3));
+    // days since 1970-01-01, from http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let mut secs = days * 86400 + field(4) * 3600 + field(5) * 60 + field(6);
+    let offset = field(8) * 3600 + field(9) * 60;
+    match captures.get(7).map(|m| m.as_str()) {
+        Some("+") => secs -= offset,
+        Some(_) => secs += offset,
+        None => (),
+    }
+    let secs = u64::try_from(secs).ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package_db::MemoryIndex;
+    use crate::resolve::Brief;
+    use crate::test_util::{test_platform, TestDB};
+
+    #[test]
+    fn test_parse_timestamp() {
+        let secs = |s: &str| {
+            parse_timestamp(s)
+                .unwrap()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        assert_eq!(secs("1970-01-01T00:00:00Z"), 0);
+        assert_eq!(secs("2023-01-23T04:56:07.123456Z"), 1674449767);
+        assert_eq!(secs("2023-01-23T06:56:07+02:00"), 1674449767);
+        assert_eq!(secs("2000-03-01T00:00:00Z"), 951868800);
+        assert!(parse_timestamp("last tuesday").is_none());
+    }
+
+    #[test]
+    fn test_policy() -> Result<()> {
+        let org = PolicyFile::parse(
+            Path::new("org.toml"),
+            indoc::indoc! {r#"
+                deny = ["leftpad"]
+                min-release-age-days = 7
+
+                [[ban]]
+                package = "urllib3"
+                versions = "< 1.26.5"
+                reason = "CVE-2021-33503"
+            "#},
+        )?;
+        let team = PolicyFile::parse(
+            Path::new("team.toml"),
+            r#"allow = ["urllib3", "LeftPad"]"#,
+        )?;
+        assert!(PolicyFile::parse(Path::new("typo.toml"), "alow = []").is_err());
+        let policy = Policy::new(vec![org, team]);
+
+        let name = |s: &str| -> PackageName { s.try_into().unwrap() };
+        let version = |s: &str| -> Version { s.try_into().unwrap() };
+        assert_eq!(
+            policy.excludes_package(&name("LeftPad")).unwrap(),
+            "LeftPad is denied, by org.toml"
+        );
+        assert_eq!(
+            policy.excludes_package(&name("requests")).unwrap(),
+            "requests isn't on the allow list, by team.toml"
+        );
+        assert!(policy.excludes_package(&name("urllib3")).is_none());
+        assert_eq!(
+            policy
+                .excludes_release(&name("urllib3"), &version("1.26.4"))?
+                .unwrap(),
+            "urllib3 < 1.26.5 is banned (CVE-2021-33503), by org.toml"
+        );
+        assert!(policy
+            .excludes_release(&name("urllib3"), &version("1.26.5"))?
+            .is_none());
+
+        let mut index = MemoryIndex::new();
+        let ai = index.add_wheel("urllib3", "2.0", &[])?;
+        let now = parse_timestamp("2023-01-10T00:00:00Z").unwrap();
+        assert!(policy.excludes_artifact(ai, now).is_none());
+        ai.upload_time = Some("2023-01-05T00:00:00Z".into());
+        assert_eq!(
+            policy.excludes_artifact(ai, now).unwrap(),
+            "uploaded less than 7 days ago, by org.toml"
+        );
+        ai.upload_time = Some("2023-01-01T00:00:00Z".into());
+        assert!(policy.excludes_artifact(ai, now).is_none());
+        assert!(Policy::default().excludes_artifact(ai, now).is_none());
+
+        let forever = Policy::new(vec![PolicyFile::parse(
+            Path::new("forever.toml"),
+            &format!("min-release-age-days = {}", i64::MAX),
+        )?]);
+        assert!(forever.excludes_artifact(ai, now).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_license_policy() -> Result<()> {
+        let deny = Policy::new(vec![PolicyFile::parse(
+            Path::new("deny.toml"),
+            r#"deny-licenses = ["agpl-*", "SSPL-1.0"]"#,
+        )?]);
+        let allow = Policy::new(vec![PolicyFile::parse(
+            Path::new("allow.toml"),
+            r#"allow-licenses = ["MIT", "Apache-2.0", "BSD-*"]"#,
+        )?]);
+        assert!(!Policy::default().checks_licenses());
+        assert!(deny.checks_licenses());
+
+        assert_eq!(
+            deny.excludes_license(Some("AGPL-3.0-only")).unwrap(),
+            "license AGPL-3.0-only is denied, by deny.toml"
+        );
+        assert!(deny
+            .excludes_license(Some("MIT OR AGPL-3.0-only"))
+            .is_none());
+        assert_eq!(
+            deny.excludes_license(Some("MIT AND (AGPL-3.0-only OR SSPL-1.0)"))
+                .unwrap(),
+            "license MIT AND (AGPL-3.0-only OR SSPL-1.0) isn't allowed \
+             (AGPL-3.0-only is denied; SSPL-1.0 is denied), by deny.toml"
+        );
+        assert!(deny
+            .excludes_license(Some("GPL-2.0-or-later WITH Classpath-exception-2.0"))
+            .is_none());
+        // deny-only policies let through what they can't read
+        assert!(deny.excludes_license(None).is_none());
+        assert!(deny.excludes_license(Some("AGPL-3.0-only AND")).is_none());
+
+        assert!(allow.excludes_license(Some("mit")).is_none());
+        assert!(allow
+            .excludes_license(Some("(MIT OR GPL-3.0-only) AND BSD-3-Clause"))
+            .is_none());
+        assert_eq!(
+            allow.excludes_license(Some("GPL-3.0-only")).unwrap(),
+            "license GPL-3.0-only isn't on allow-licenses, by allow.toml"
+        );
+        assert_eq!(
+            allow.excludes_license(None).unwrap(),
+            "no recognizable license, and allow-licenses is set, by allow.toml"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_with_policy() -> Result<()> {
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("app", "1.0", &["urllib3"])?;
+        index.add_wheel("app", "2.0", &["urllib3", "leftpad"])?;
+        index.add_wheel("urllib3", "1.26.4", &[])?;
+        index.add_wheel("urllib3", "1.26.5", &[])?;
+        index.add_wheel("urllib3", "2.0", &[])?.upload_time =
+            Some("2999-01-01T00:00:00Z".into());
+        index.add_wheel("leftpad", "1.0", &[])?;
+
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        db.set_policy(Policy::new(vec![PolicyFile::parse(
+            Path::new("policy.toml"),
+            indoc::indoc! {r#"
+                deny = ["leftpad"]
+                min-release-age-days = 7
+
+                [[ban]]
+                package = "urllib3"
+                versions = "< 1.26.5"
+            "#},
+        )?]));
+        let platform = test_platform();
+        let brief = |requirements: &[&str]| -> Result<Brief> {
+            Ok(Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: requirements
+                    .iter()
+                    .map(|r| (*r).try_into())
+                    .collect::<Result<_>>()?,
+                allow_pre: Default::default(),
+                constraints: Vec::new(),
+                sources: Default::default(),
+            })
+        };
+
+        let resolve_err = |requirements: &[&str]| -> Result<String> {
+            let result = brief(requirements)?.resolve(&db, &[&platform], None, &[]);
+            Ok(format!("{:#}", result.unwrap_err()))
+        };
+
+        let blueprint = brief(&["app"])?.resolve(&db, &[&platform], None, &[])?;
+        let mut pins: Vec<String> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+            .collect();
+        pins.sort_unstable();
+        assert_eq!(pins, vec!["app 1.0", "urllib3 1.26.5"]);
+
+        let err = resolve_err(&["urllib3 < 1.26.5"])?;
+        assert!(
+            err.contains("policy excludes urllib3 1.26.4: urllib3 < 1.26.5 is banned")
+        );
+        let err = resolve_err(&["urllib3 >= 2"])?;
+        assert!(err.contains("uploaded less than 7 days ago"));
+        let err = resolve_err(&["leftpad"])?;
+        assert!(err.contains("policy: leftpad is denied, by policy.toml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_with_license_policy() -> Result<()> {
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        let mut add = |name: &str, version: &str, extra: &str| -> Result<()> {
+            let metadata = format!(
+                "Metadata-Version: 2.4\nName: {name}\nVersion: {version}\n{extra}"
+            );
+            index.add(
+                &format!("{name}-{version}-py3-none-any.whl"),
+                metadata.as_bytes(),
+            )?;
+            Ok(())
+        };
+        add("app", "1.0", "Requires-Dist: lib\n")?;
+        add("lib", "1.0", "License-Expression: MIT\n")?;
+        add("lib", "2.0", "License-Expression: MIT AND AGPL-3.0-only\n")?;
+        add("tool", "1.0", "Requires-Dist: copyleft\n")?;
+        add(
+            "copyleft",
+            "1.0",
+            "Classifier: License :: OSI Approved :: GNU Affero General Public \
+             License v3\n",
+        )?;
+
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        db.set_policy(Policy::new(vec![PolicyFile::parse(
+            Path::new("policy.toml"),
+            r#"deny-licenses = ["AGPL-*"]"#,
+        )?]));
+        let platform = test_platform();
+        let brief = |requirement: &str| -> Result<Brief> {
+            Ok(Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: vec![requirement.try_into()?],
+                allow_pre: Default::default(),
+                constraints: Vec::new(),
+                sources: Default::default(),
+            })
+        };
+
+        let blueprint = brief("app")?.resolve(&db, &[&platform], None, &[])?;
+        let lib = blueprint
+            .wheels
+            .iter()
+            .find(|(pin, _)| pin.name.normalized() == "lib")
+            .unwrap();
+        assert_eq!(lib.0.version.to_string(), "1.0");
+
+        let err = brief("tool")?
+            .resolve(&db, &[&platform], None, &[])
+            .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("tool"), "{err}");
+        assert!(
+            err.contains(
+                "policy excludes copyleft 1.0: license AGPL-3.0-only is denied, by \
+                 policy.toml"
+            ),
+            "{err}"
+        );
+        Ok(())
+    }
+}
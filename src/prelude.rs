@@ -15,7 +15,7 @@ pub use serde_with::{DeserializeFromStr, SerializeDisplay};
 pub use tracing::{debug, info, trace, warn};
 pub use url::Url;
 
-pub use crate::error::PosyError;
+pub use crate::error::{InstallError, MetadataError, NetworkError, ResolveError};
 pub use crate::platform_tags::{Platform, PybiPlatform, WheelPlatform};
 
 pub use crate::tree::NicePathBuf;
@@ -23,8 +23,11 @@ pub use crate::try_from_str_boilerplate;
 pub use crate::vocab::*;
 
 pub use crate::context;
+pub use crate::progress;
+pub use crate::timing;
 
 use directories::ProjectDirs;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
 pub static PROJECT_DIRS: Lazy<ProjectDirs> = Lazy::new(|| {
     // ...Can this actually return None?
     ProjectDirs::from("", "Trio Collective", env!("CARGO_PKG_NAME")).unwrap()
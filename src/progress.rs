@@ -0,0 +1,173 @@
+//! `--progress-json`: a stream of newline-delimited JSON events saying what we're up
+//! to, for IDE extensions, CI dashboards, and anything else that wants to show live
+//! progress.
+//!
+//! Each line is one object, with an `event` saying what happened, `time` in seconds
+//! since we started, and then whatever fields go with that event (in no particular
+//! order):
+//!
+//!   {"event":"resolve-start","time":0.01,"python":"cpython_unofficial >= 3"}
+//!   {"event":"resolve-decision","time":0.4,"package":"trio","version":"0.22.0"}
+//!   {"event":"resolve-finish","time":1.2,"packages":7}
+//!   {"event":"download-start","time":1.3,"url":"https://..."}
+//!   {"event":"download-progress","time":1.6,"url":"https://...","bytes":16777216}
+//!   {"event":"download-finish","time":2.0,"url":"https://...","bytes":3402250}
+//!   {"event":"install","time":2.1,"package":"trio","version":"0.22.0"}
+//!
+//! Events get added over time, so consumers should ignore ones they don't know about.
+
+use crate::prelude::*;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+pub const POSY_PROGRESS_TARGET: &str = "posy::progress";
+
+/// Emits a progress event: `progress!("download-start", url = %url)`.
+#[macro_export]
+macro_rules! progress {
+    ($event:literal $(, $($fields:tt)*)?) => {
+        tracing::event!(
+            target: "posy::progress",
+            tracing::Level::INFO,
+            event = $event
+            $(, $($fields)*)?
+        )
+    };
+}
+
+pub struct ProgressLayer {
+    start: Instant,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+/// `--progress-json` takes either a file descriptor number (Unix only), or a path to
+/// write to.
+pub fn open(target: &str) -> Result<ProgressLayer> {
+    let out: Box<dyn Write + Send> =
+        match target.parse::<i32>() {
+            #[cfg(unix)]
+            Ok(fd) => {
+                use std::os::unix::io::FromRawFd;
+                if fd < 0 {
+                    bail!("invalid file descriptor {fd}");
+                }
+                // Whoever started us handed us this fd for exactly this purpose.
+                Box::new(unsafe { File::from_raw_fd(fd) })
+            }
+            _ => Box::new(File::create(Path::new(target)).wrap_err_with(|| {
+                format!("couldn't open {target} for progress events")
+            })?),
+        };
+    Ok(ProgressLayer {
+        start: Instant::now(),
+        out: Mutex::new(out),
+    })
+}
+
+struct JsonVisitor(serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    // Covers `%value` and `?value` fields
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+impl ProgressLayer {
+    fn render(&self, event: &Event<'_>) -> String {
+        let mut visitor = JsonVisitor(serde_json::Map::new());
+        visitor
+            .0
+            .insert("time".into(), self.start.elapsed().as_secs_f64().into());
+        event.record(&mut visitor);
+        serde_json::Value::Object(visitor.0).to_string()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ProgressLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != POSY_PROGRESS_TARGET {
+            return;
+        }
+        let line = self.render(event);
+        let mut out = self.out.lock().unwrap();
+        // If whoever's listening went away, that's their problem; it's no reason to
+        // stop what we're doing.
+        let _ = writeln!(out, "{line}").and_then(|_| out.flush());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use tracing_subscriber::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_progress_events() -> Result<()> {
+        let buf = SharedBuf::default();
+        let layer = ProgressLayer {
+            start: Instant::now(),
+            out: Mutex::new(Box::new(buf.clone())),
+        };
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let url = Url::parse("https://example.com/trio.whl").unwrap();
+            progress!("download-finish", url = %url, bytes = 1234u64);
+            info!("not a progress event");
+        });
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone())?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let mut event: serde_json::Value = serde_json::from_str(lines[0])?;
+        assert!(event["time"].as_f64().is_some());
+        event.as_object_mut().unwrap().remove("time");
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "event": "download-finish",
+                "url": "https://example.com/trio.whl",
+                "bytes": 1234,
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,770 @@
+use crate::config::EnvConfig;
+use crate::lockfile::LOCKFILE_NAME;
+use crate::package_db::AttestationConfig;
+use crate::prelude::*;
+use crate::resolve::{AllowPre, Brief, RequirementSource};
+use crate::util::split_command;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// If the project doesn't say which Python it wants, then we'll take whatever's newest.
+pub const DEFAULT_PYTHON: &str = "cpython_unofficial >= 3";
+
+/// The name for the environment described by the top level of `[tool.posy]`.
+pub const DEFAULT_ENV: &str = "default";
+
+/// The `[tool.posy]` table in pyproject.toml.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ProjectConfig {
+    pub python: Option<PythonRequirement>,
+    pub requirements: Vec<UserRequirement>,
+    pub allow_pre: AllowPre,
+    /// Where particular packages come from, if not the usual indexes, in every
+    /// environment:
+    ///
+    ///   [tool.posy.sources]
+    ///   mylib = { git = "https://github.com/me/mylib", rev = "main" }
+    ///   private-thing = { index = "internal" }
+    pub sources: BTreeMap<PackageName, RequirementSource>,
+    /// Only used when resolving environments to build sdists in.
+    pub build_constraints: Vec<UserRequirement>,
+    pub scripts: BTreeMap<String, Script>,
+    pub environments: BTreeMap<String, EnvironmentConfig>,
+    pub workspace: WorkspaceConfig,
+    pub attestations: AttestationConfig,
+    /// An organizational policy file, applied on top of the user's.
+    pub policy: Option<PathBuf>,
+    #[serde(flatten)]
+    pub run_env: EnvConfig,
+    /// From `requires-python` in `[project]`, for when `[tool.posy]` doesn't say.
+    #[serde(skip)]
+    pub project_python: Option<PythonRequirement>,
+}
+
+/// The parts of pyproject.toml's `[project]` table (PEP 621) that say what the project
+/// needs, so that a standard pyproject.toml is enough to go on:
+///
+/// - `requires-python` picks the Python, unless `[tool.posy]` has `python`
+/// - `dependencies` and `optional-dependencies` are the project's own metadata, so we
+///   can read them here instead of asking the build backend
+/// - each of the `optional-dependencies` also gets an environment with that extra
+///   installed, unless `[tool.posy.environments]` already has one by that name
+///
+/// Anything listed in `dynamic` is up to the build backend, so we leave it alone.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct ProjectTable {
+    name: Option<String>,
+    version: Option<String>,
+    requires_python: Option<String>,
+    dependencies: Vec<String>,
+    optional_dependencies: BTreeMap<String, Vec<String>>,
+    dynamic: Vec<String>,
+}
+
+impl ProjectTable {
+    fn from_document(d: &toml_edit::Document) -> Result<Option<ProjectTable>> {
+        match d.get("project") {
+            Some(table) => Ok(Some(toml_edit::de::from_item(table.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn is_static(&self, field: &str) -> bool {
+        !self.dynamic.iter().any(|d| d == field)
+    }
+
+    fn python(&self) -> Result<Option<PythonRequirement>> {
+        match &self.requires_python {
+            Some(specifiers) if self.is_static("requires-python") => {
+                let python = format!("cpython_unofficial {specifiers}");
+                Ok(Some(python.as_str().try_into().wrap_err_with(|| {
+                    format!("can't use requires-python = {specifiers:?}")
+                })?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// `req`, but only for when `extra` is installed.
+fn with_extra_marker(
+    req: PackageRequirement,
+    extra: &Extra,
+) -> Result<PackageRequirement> {
+    let mut bare: Requirement = (*req).clone();
+    let extra_marker = format!("extra == \"{}\"", extra.normalized());
+    let marker = match bare.env_marker_expr.take() {
+        Some(marker) => format!("({marker}) and {extra_marker}"),
+        None => extra_marker,
+    };
+    format!("{bare} ; {marker}").as_str().try_into()
+}
+
+/// The project's own metadata, straight from `[project]` in its pyproject.toml, if
+/// everything that matters for resolving is written down there. Otherwise (or if
+/// there's no `[project]`) we have to ask the build backend, so this gives None.
+pub fn static_metadata(pyproject: &str) -> Result<Option<WheelCoreMetadata>> {
+    let d = pyproject.parse::<toml_edit::Document>()?;
+    let Some(table) = ProjectTable::from_document(&d)? else {
+        return Ok(None);
+    };
+    let fields = [
+        "version",
+        "requires-python",
+        "dependencies",
+        "optional-dependencies",
+    ];
+    if fields.iter().any(|field| !table.is_static(field)) {
+        return Ok(None);
+    }
+    let (Some(name), Some(version)) = (&table.name, &table.version) else {
+        return Ok(None);
+    };
+    context!("Reading [project] metadata for {name}");
+    let mut requires_dist = Vec::new();
+    for dep in &table.dependencies {
+        requires_dist.push(dep.as_str().try_into()?);
+    }
+    let mut extras = HashSet::new();
+    for (extra, deps) in &table.optional_dependencies {
+        let extra: Extra = extra.as_str().try_into()?;
+        for dep in deps {
+            requires_dist.push(with_extra_marker(dep.as_str().try_into()?, &extra)?);
+        }
+        extras.insert(extra);
+    }
+    let requires_python = match &table.requires_python {
+        Some(specifiers) => specifiers.as_str().try_into()?,
+        None => Specifiers::default(),
+    };
+    Ok(Some(WheelCoreMetadata {
+        name: name.as_str().try_into()?,
+        version: version.as_str().try_into()?,
+        requires_dist,
+        requires_python,
+        extras,
+        license: None,
+        license_expression: None,
+        license_files: Vec::new(),
+        dynamic: Some(Vec::new()),
+        display: Default::default(),
+    }))
+}
+
+/// `[tool.posy.workspace]`, for a project made out of several local packages that get
+/// developed together:
+///
+///   [tool.posy.workspace]
+///   members = ["cli", "libs/*"]
+///
+/// Every member gets installed into the project's environments straight from its
+/// source directory, and any requirements on members are satisfied by those local
+/// copies instead of the index. A trailing `*` matches every subdirectory that has a
+/// pyproject.toml.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct WorkspaceConfig {
+    pub members: Vec<PathBuf>,
+}
+
+impl WorkspaceConfig {
+    fn member_dirs(&self) -> Result<Vec<PathBuf>> {
+        let mut dirs = Vec::new();
+        for member in &self.members {
+            if member.file_name() != Some(OsStr::new("*")) {
+                dirs.push(member.clone());
+                continue;
+            }
+            let parent = member.parent().unwrap();
+            context!("Looking for workspace members in {}", parent.display());
+            let mut found = Vec::new();
+            for entry in fs::read_dir(parent)? {
+                let path = entry?.path();
+                if path.join("pyproject.toml").is_file() {
+                    found.push(path);
+                }
+            }
+            found.sort();
+            dirs.extend(found);
+        }
+        Ok(dirs)
+    }
+}
+
+/// A named environment, from `[tool.posy.environments.<name>]`:
+///
+///   [tool.posy.environments.docs]
+///   requirements = ["sphinx", "furo"]
+///
+/// By default, it gets everything the default environment has, plus its own
+/// requirements. Set `inherit = false` to start from scratch instead. Either way, each
+/// environment gets resolved into its own Blueprint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct EnvironmentConfig {
+    pub inherit: bool,
+    pub python: Option<PythonRequirement>,
+    pub requirements: Vec<UserRequirement>,
+    pub allow_pre: Option<AllowPre>,
+    #[serde(flatten)]
+    pub run_env: EnvConfig,
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self {
+            inherit: true,
+            python: None,
+            requirements: Vec::new(),
+            allow_pre: None,
+            run_env: Default::default(),
+        }
+    }
+}
+
+/// An entry in `[tool.posy.scripts]`. Either just a command line:
+///
+///   test = "pytest -x"
+///
+/// or a table, if you want other things to run first:
+///
+///   test = { cmd = "pytest -x", pre = ["lint", "python -m mypkg.gen_fixtures"] }
+///
+/// Entries in `pre` can be the names of other scripts, or command lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Script {
+    Command(String),
+    Full {
+        cmd: String,
+        #[serde(default)]
+        pre: Vec<String>,
+    },
+}
+
+impl Script {
+    pub fn cmd(&self) -> &str {
+        match self {
+            Script::Command(cmd) => cmd,
+            Script::Full { cmd, .. } => cmd,
+        }
+    }
+
+    pub fn pre(&self) -> &[String] {
+        match self {
+            Script::Command(_) => &[],
+            Script::Full { pre, .. } => pre,
+        }
+    }
+}
+
+impl ProjectConfig {
+    pub fn parse_from(s: &str) -> Result<ProjectConfig> {
+        let mut d = s.parse::<toml_edit::Document>()?;
+        let tool_posy = d
+            .remove("tool")
+            .and_then(|mut tool| tool.as_table_like_mut()?.remove("posy"));
+        let mut config: ProjectConfig = if let Some(table) = tool_posy {
+            toml_edit::de::from_item(table)?
+        } else {
+            Default::default()
+        };
+        if let Some(project) = ProjectTable::from_document(&d)? {
+            config.project_python = project.python()?;
+            if let (Some(name), true) =
+                (&project.name, project.is_static("optional-dependencies"))
+            {
+                for extra in project.optional_dependencies.keys() {
+                    if config.environments.contains_key(extra) {
+                        continue;
+                    }
+                    let req = format!("{name}[{extra}]");
+                    config.environments.insert(
+                        extra.clone(),
+                        EnvironmentConfig {
+                            requirements: vec![req.as_str().try_into()?],
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Looks up a named environment. The default environment isn't in `environments`,
+    /// so it gives None.
+    pub fn environment(&self, name: &str) -> Result<Option<&EnvironmentConfig>> {
+        if let Some(env) = self.environments.get(name) {
+            return Ok(Some(env));
+        }
+        if name == DEFAULT_ENV {
+            return Ok(None);
+        }
+        bail!(
+            "no environment named '{name}' (available: {})",
+            self.environment_names().join(", ")
+        );
+    }
+
+    /// The default environment, and then all the named ones.
+    pub fn environment_names(&self) -> Vec<&str> {
+        let mut names = vec![DEFAULT_ENV];
+        names.extend(self.environments.keys().map(|k| k.as_str()));
+        names
+    }
+
+    pub fn brief(&self, env_name: &str) -> Result<Brief> {
+        let env = self.environment(env_name)?;
+        let inherit = env.map(|env| env.inherit).unwrap_or(true);
+        let python = match (env.and_then(|env| env.python.as_ref()), inherit) {
+            (Some(python), _) => Some(python),
+            (None, true) => self.python.as_ref(),
+            (None, false) => None,
+        };
+        // requires-python is about the project's own code, so it applies even to
+        // environments that don't inherit anything else
+        let python = match python.or(self.project_python.as_ref()) {
+            Some(python) => python.clone(),
+            None => DEFAULT_PYTHON.try_into()?,
+        };
+        let mut requirements = Vec::new();
+        if inherit {
+            requirements.extend(self.requirements.iter().cloned());
+        }
+        let mut allow_pre = if inherit {
+            self.allow_pre.clone()
+        } else {
+            AllowPre::default()
+        };
+        if let Some(env) = env {
+            requirements.extend(env.requirements.iter().cloned());
+            if let Some(env_allow_pre) = &env.allow_pre {
+                allow_pre = env_allow_pre.clone();
+            }
+        }
+        Ok(Brief {
+            python,
+            requirements,
+            allow_pre,
+            constraints: Vec::new(),
+            sources: self.sources.clone(),
+        })
+    }
+
+    /// The run-time environment variable layers for the given environment, in the
+    /// order they should be applied.
+    pub fn run_env_layers(&self, env_name: &str) -> Result<Vec<&EnvConfig>> {
+        let mut layers = vec![&self.run_env];
+        if let Some(env) = self.environment(env_name)? {
+            layers.push(&env.run_env);
+        }
+        Ok(layers)
+    }
+
+    /// Relative paths are relative to the project root, not wherever we're running.
+    fn rebase(self, root: &Path) -> ProjectConfig {
+        ProjectConfig {
+            run_env: self.run_env.rebase(root),
+            policy: self.policy.map(|p| root.join(p)),
+            sources: self
+                .sources
+                .into_iter()
+                .map(|(name, source)| match source {
+                    RequirementSource::Path { path } => (
+                        name,
+                        RequirementSource::Path {
+                            path: root.join(path),
+                        },
+                    ),
+                    other => (name, other),
+                })
+                .collect(),
+            environments: self
+                .environments
+                .into_iter()
+                .map(|(name, env)| {
+                    let env = EnvironmentConfig {
+                        run_env: env.run_env.rebase(root),
+                        ..env
+                    };
+                    (name, env)
+                })
+                .collect(),
+            workspace: WorkspaceConfig {
+                members: self
+                    .workspace
+                    .members
+                    .into_iter()
+                    .map(|member| root.join(member))
+                    .collect(),
+            },
+            ..self
+        }
+    }
+
+    /// If `name` is a script, returns the full sequence of command lines to run for it,
+    /// in order: all the `pre` commands (recursively), and then the script itself.
+    pub fn script_commands(&self, name: &str) -> Result<Option<Vec<Vec<String>>>> {
+        if !self.scripts.contains_key(name) {
+            return Ok(None);
+        }
+        let mut commands = Vec::new();
+        self.expand_script(name, &mut Vec::new(), &mut commands)?;
+        Ok(Some(commands))
+    }
+
+    fn expand_script<'a>(
+        &'a self,
+        name: &'a str,
+        stack: &mut Vec<&'a str>,
+        out: &mut Vec<Vec<String>>,
+    ) -> Result<()> {
+        if stack.contains(&name) {
+            stack.push(name);
+            bail!(
+                "script '{}' depends on itself: {}",
+                name,
+                stack.join(" -> ")
+            );
+        }
+        let script = &self.scripts[name];
+        stack.push(name);
+        for pre in script.pre() {
+            if self.scripts.contains_key(pre.as_str()) {
+                self.expand_script(pre, stack, out)?;
+            } else {
+                out.push(split_script_command(name, pre)?);
+            }
+        }
+        out.push(split_script_command(name, script.cmd())?);
+        stack.pop();
+        Ok(())
+    }
+}
+
+fn split_script_command(name: &str, command: &str) -> Result<Vec<String>> {
+    let words = split_command(command)
+        .wrap_err_with(|| format!("invalid command in script '{name}'"))?;
+    if words.is_empty() {
+        bail!("empty command in script '{name}'");
+    }
+    Ok(words)
+}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub config: ProjectConfig,
+    /// If the project is itself a Python package (i.e., it has a `[project]` table
+    /// with a name), then this is its name, and it gets installed into its own
+    /// environments.
+    pub package: Option<PackageName>,
+}
+
+impl Project {
+    /// Walks up from `start` looking for a pyproject.toml.
+    pub fn find(start: &Path) -> Result<Option<Project>> {
+        for dir in start.ancestors() {
+            let candidate = dir.join("pyproject.toml");
+            if candidate.is_file() {
+                return Ok(Some(Project::load(dir)?));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn load(root: &Path) -> Result<Project> {
+        let path = root.join("pyproject.toml");
+        context!("Loading project from {}", path.display());
+        let contents = fs::read_to_string(&path)?;
+        Project::from_pyproject(root, &contents)
+    }
+
+    /// Like `load`, but for a pyproject.toml we have in hand, e.g. one we're about to
+    /// write.
+    pub fn from_pyproject(root: &Path, contents: &str) -> Result<Project> {
+        let config = ProjectConfig::parse_from(contents)?;
+        Ok(Project {
+            root: root.to_owned(),
+            config: config.rebase(root),
+            package: package_name(contents)?,
+        })
+    }
+
+    /// Where the project's posy.lock lives, or None if this isn't a real project (i.e.
+    /// there's no pyproject.toml), in which case there's nowhere to put one.
+    pub fn lockfile_path(&self) -> Option<PathBuf> {
+        if self.root.join("pyproject.toml").is_file() {
+            Some(self.root.join(LOCKFILE_NAME))
+        } else {
+            None
+        }
+    }
+
+    /// The local packages that get installed into the project's environments: the
+    /// project itself, if it's a package, plus any workspace members.
+    pub fn local_packages(&self) -> Result<Vec<(PackageName, PathBuf)>> {
+        let mut packages = Vec::new();
+        if let Some(name) = &self.package {
+            packages.push((name.clone(), self.root.clone()));
+        }
+        for dir in self.config.workspace.member_dirs()? {
+            let path = dir.join("pyproject.toml");
+            context!("Loading workspace member {}", path.display());
+            let Some(name) = package_name(&fs::read_to_string(&path)?)? else {
+                bail!("workspace members need a [project] table with a name");
+            };
+            if let Some((_, other)) = packages.iter().find(|(n, _)| n == &name) {
+                bail!(
+                    "{} and {} are both named {}",
+                    other.display(),
+                    dir.display(),
+                    name.as_given()
+                );
+            }
+            packages.push((name, dir));
+        }
+        Ok(packages)
+    }
+}
+
+/// The `name` from pyproject.toml's `[project]` table, if there is one.
+fn package_name(s: &str) -> Result<Option<PackageName>> {
+    let d = s.parse::<toml_edit::Document>()?;
+    let name = d
+        .get("project")
+        .and_then(|project| project.get("name"))
+        .and_then(|name| name.as_str());
+    Ok(match name {
+        Some(name) => Some(name.try_into()?),
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_project_config() {
+        let config = ProjectConfig::parse_from(indoc::indoc! {r#"
+            [build-system]
+            requires = ["setuptools"]
+
+            [tool.posy]
+            python = "cpython_unofficial >= 3.10"
+            requirements = ["trio", "attrs >= 22"]
+            path = ["scripts"]
+
+            [tool.posy.env]
+            DJANGO_SETTINGS_MODULE = "mysite.settings"
+        "#})
+        .unwrap();
+        assert_eq!(
+            config.python.unwrap().to_string(),
+            "cpython_unofficial >= 3.10"
+        );
+        assert_eq!(config.requirements.len(), 2);
+        assert_eq!(
+            config.run_env.env.get("DJANGO_SETTINGS_MODULE").unwrap(),
+            "mysite.settings"
+        );
+        assert_eq!(config.run_env.path, vec![PathBuf::from("scripts")]);
+
+        let empty = ProjectConfig::parse_from("[project]\nname = 'foo'\n").unwrap();
+        assert!(empty.python.is_none());
+        assert!(empty.run_env.is_empty());
+
+        let name = package_name("[project]\nname = 'Foo'\n").unwrap().unwrap();
+        assert_eq!(name.as_given(), "Foo");
+        assert!(package_name("[tool.posy]\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_script_commands() {
+        let config = ProjectConfig::parse_from(indoc::indoc! {r#"
+            [tool.posy.scripts]
+            lint = "flake8 src"
+            test = { cmd = "pytest -x", pre = ["lint", "python -c 'print(1)'"] }
+            loop-a = { cmd = "true", pre = ["loop-b"] }
+            loop-b = { cmd = "true", pre = ["loop-a"] }
+        "#})
+        .unwrap();
+        assert_eq!(
+            config.script_commands("test").unwrap().unwrap(),
+            vec![
+                vec!["flake8", "src"],
+                vec!["python", "-c", "print(1)"],
+                vec!["pytest", "-x"],
+            ]
+        );
+        assert!(config.script_commands("pytest").unwrap().is_none());
+        let err = config.script_commands("loop-a").unwrap_err();
+        assert!(err.to_string().contains("loop-a -> loop-b -> loop-a"));
+    }
+
+    #[test]
+    fn test_named_environments() {
+        let config = ProjectConfig::parse_from(indoc::indoc! {r#"
+            [tool.posy]
+            python = "cpython_unofficial >= 3.10"
+            requirements = ["attrs"]
+
+            [tool.posy.environments.test]
+            requirements = ["pytest"]
+            env = { PYTEST_ADDOPTS = "-x" }
+
+            [tool.posy.environments.docs]
+            inherit = false
+            requirements = ["sphinx"]
+        "#})
+        .unwrap();
+        let names = |brief: Brief| {
+            brief
+                .requirements
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(config.brief(DEFAULT_ENV).unwrap()), vec!["attrs"]);
+        assert_eq!(
+            names(config.brief("test").unwrap()),
+            vec!["attrs", "pytest"]
+        );
+        let docs = config.brief("docs").unwrap();
+        assert_eq!(docs.python.to_string(), DEFAULT_PYTHON);
+        assert_eq!(names(docs), vec!["sphinx"]);
+        assert_eq!(config.run_env_layers("test").unwrap().len(), 2);
+        assert_eq!(config.run_env_layers(DEFAULT_ENV).unwrap().len(), 1);
+
+        let err = config.brief("gpu").unwrap_err();
+        assert!(err.to_string().contains("available: default, docs, test"));
+    }
+
+    #[test]
+    fn test_sources() -> Result<()> {
+        let config = ProjectConfig::parse_from(indoc::indoc! {r#"
+            [tool.posy]
+            requirements = ["mylib", "private-thing", "vendored"]
+
+            [tool.posy.sources]
+            mylib = { git = "https://github.com/me/mylib", rev = "main" }
+            private-thing = { index = "internal" }
+            vendored = { path = "vendor/vendored" }
+        "#})?
+        .rebase(Path::new("/project"));
+        let brief = config.brief(DEFAULT_ENV)?;
+        let source = |name: &str| {
+            brief.sources[&PackageName::try_from(name).unwrap()].to_string()
+        };
+        assert_eq!(source("mylib"), "https://github.com/me/mylib at main");
+        assert_eq!(source("private-thing"), "the \"internal\" index");
+        assert_eq!(source("vendored"), "/project/vendor/vendored");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pep621() -> Result<()> {
+        let pyproject = indoc::indoc! {r#"
+            [project]
+            name = "app"
+            version = "1.0"
+            requires-python = ">= 3.9"
+            dependencies = ["attrs >= 22", "tomli; python_version < '3.11'"]
+
+            [project.optional-dependencies]
+            test = ["pytest", "pytest-xdist; sys_platform != 'win32'"]
+            docs = ["sphinx"]
+
+            [tool.posy.environments.docs]
+            inherit = false
+            requirements = ["furo"]
+        "#};
+        let config = ProjectConfig::parse_from(pyproject)?;
+        let brief = config.brief(DEFAULT_ENV)?;
+        assert_eq!(
+            brief.python.to_string(),
+            PythonRequirement::try_from("cpython_unofficial >= 3.9")?.to_string()
+        );
+        // the dependencies come in through the local package, not the Brief
+        assert!(brief.requirements.is_empty());
+        let test = config.brief("test")?;
+        assert_eq!(test.requirements[0].to_string(), "app[test]");
+        // docs was already configured, but still gets the project's Python
+        let docs = config.brief("docs")?;
+        assert_eq!(docs.requirements[0].to_string(), "furo");
+        assert_eq!(docs.python.to_string(), brief.python.to_string());
+
+        let metadata = static_metadata(pyproject)?.unwrap();
+        assert_eq!(metadata.version.to_string(), "1.0");
+        assert_eq!(metadata.extras.len(), 2);
+        let requires_dist: Vec<String> = metadata
+            .requires_dist
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        let expected: Vec<String> = [
+            "attrs >= 22",
+            "tomli; python_version < '3.11'",
+            "sphinx; extra == 'docs'",
+            "pytest; extra == 'test'",
+            "pytest-xdist; (sys_platform != 'win32') and extra == 'test'",
+        ]
+        .into_iter()
+        .map(|r| PackageRequirement::try_from(r).unwrap().to_string())
+        .collect();
+        assert_eq!(requires_dist, expected);
+
+        // if the build backend fills anything in, we have to ask it
+        let dynamic = "[project]\nname = 'app'\ndynamic = ['version']\n";
+        assert!(static_metadata(dynamic)?.is_none());
+        assert!(static_metadata("[tool.posy]\n")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_workspace() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path();
+        fs::write(
+            root.join("pyproject.toml"),
+            indoc::indoc! {r#"
+                [project]
+                name = "app"
+
+                [tool.posy.workspace]
+                members = ["libs/*"]
+            "#},
+        )?;
+        for name in ["b", "a"] {
+            fs::create_dir_all(root.join("libs").join(name))?;
+            fs::write(
+                root.join("libs").join(name).join("pyproject.toml"),
+                format!("[project]\nname = 'lib-{name}'\n"),
+            )?;
+        }
+        // not a package, so not a member
+        fs::create_dir_all(root.join("libs/docs"))?;
+
+        let project = Project::load(root)?;
+        let packages: Vec<(String, PathBuf)> = project
+            .local_packages()?
+            .into_iter()
+            .map(|(name, dir)| (name.as_given().to_string(), dir))
+            .collect();
+        assert_eq!(
+            packages,
+            vec![
+                ("app".to_string(), root.to_owned()),
+                ("lib-a".to_string(), root.join("libs/a")),
+                ("lib-b".to_string(), root.join("libs/b")),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Looking inside zip files -- wheels, pybis -- without reading all of them.
+//!
+//! A zip's table of contents is at the end, and each member can be read on its own, so
+//! given something that can seek, we only have to touch the bytes we actually want.
+//! Over HTTP that's a `LazyRemoteFile`, where every read is a Range request; for a
+//! big pybi, getting its metadata this way is a few kilobytes instead of the whole
+//! download. Wheels and pybis open their zips with this, whether they're in the cache
+//! or still on the index, so reading their METADATA or RECORD works the same either
+//! way.
+
+use crate::prelude::*;
+use crate::tree::{slurp_member_carefully, UnpackLimits};
+use zip::ZipArchive;
+
+pub struct RemoteZip<R: Read + Seek> {
+    archive: ZipArchive<R>,
+}
+
+impl<R: Read + Seek> RemoteZip<R> {
+    /// Reads the central directory, which is all at the end of the file.
+    pub fn new(source: R) -> Result<RemoteZip<R>> {
+        Ok(RemoteZip {
+            archive: ZipArchive::new(source)?,
+        })
+    }
+
+    /// Every member's name, sorted.
+    pub fn file_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.archive.file_names().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Streams one member, decompressing as it goes. Only that member's data gets
+    /// read from the source, and its CRC is checked when you reach the end.
+    pub fn open(&mut self, name: &str) -> Result<impl Read + '_> {
+        context!("extracting {name}");
+        Ok(self.archive.by_name(name)?)
+    }
+
+    /// All of one member, as long as it's within `limits`.
+    pub fn read(&mut self, name: &str, limits: &UnpackLimits) -> Result<Vec<u8>> {
+        context!("extracting {name}");
+        let member = self.archive.by_name(name)?;
+        let (compressed, size) = (member.compressed_size(), member.size());
+        slurp_member_carefully(member, compressed, size, limits)
+    }
+
+    /// The whole archive, for going through every member, e.g. to unpack it.
+    pub fn archive(&mut self) -> &mut ZipArchive<R> {
+        &mut self.archive
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::seek_slice::SeekSlice;
+    use std::cell::Cell;
+    use std::io::{Cursor, SeekFrom};
+    use zip::write::FileOptions;
+    use zip::CompressionMethod;
+
+    /// Counts how many bytes got read.
+    struct Spy<R> {
+        inner: R,
+        total: Rc<Cell<u64>>,
+    }
+
+    impl<R: Read> Read for Spy<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let amount = self.inner.read(buf)?;
+            self.total.set(self.total.get() + amount as u64);
+            Ok(amount)
+        }
+    }
+
+    impl<R: Seek> Seek for Spy<R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_remote_zip() -> Result<()> {
+        let mut zipped = Cursor::new(Vec::new());
+        {
+            let mut w = zip::ZipWriter::new(&mut zipped);
+            let stored =
+                FileOptions::default().compression_method(CompressionMethod::Stored);
+            let deflated =
+                FileOptions::default().compression_method(CompressionMethod::Deflated);
+            w.start_file("foo/big.bin", stored)?;
+            w.write_all(&[7; 100_000])?;
+            w.start_file("foo-1.0.dist-info/METADATA", deflated)?;
+            w.write_all(&b"Metadata-Version: 2.1\n".repeat(100))?;
+            w.start_file("foo-1.0.dist-info/RECORD", deflated)?;
+            w.write_all(b"foo/big.bin,,\n")?;
+            w.finish()?;
+        }
+        // tucked away in the middle of something else, like a cache entry
+        let mut blob = b"header".to_vec();
+        blob.extend(zipped.into_inner());
+        let end = blob.len() as u64;
+        blob.extend(b"trailer");
+        let total = Rc::new(Cell::new(0));
+        let spy = Spy {
+            inner: Cursor::new(blob),
+            total: total.clone(),
+        };
+
+        let mut z = RemoteZip::new(SeekSlice::new(spy, 6, end)?)?;
+        let limits = UnpackLimits::default();
+        assert_eq!(
+            z.file_names(),
+            vec![
+                "foo-1.0.dist-info/METADATA",
+                "foo-1.0.dist-info/RECORD",
+                "foo/big.bin",
+            ]
+        );
+        assert_eq!(
+            z.read("foo-1.0.dist-info/METADATA", &limits)?,
+            b"Metadata-Version: 2.1\n".repeat(100)
+        );
+        assert_eq!(
+            z.read("foo-1.0.dist-info/RECORD", &limits)?,
+            b"foo/big.bin,,\n"
+        );
+        assert!(z.read("foo/missing.py", &limits).is_err());
+
+        // none of that needed to look at big.bin's contents
+        assert!(total.get() < 10_000, "read {} bytes", total.get());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_zip64() -> Result<()> {
+        // more members than a classic end of central directory record can count
+        let names: Vec<String> = (0..70_000).map(|i| format!("foo/{i}.py")).collect();
+        let mut members: Vec<(&str, &[u8])> =
+            names.iter().map(|name| (name.as_str(), &b""[..])).collect();
+        members.push(("foo-1.0.dist-info/METADATA", b"Metadata-Version: 2.1\n"));
+        let mut blob = b"header".to_vec();
+        blob.extend(crate::test_util::zip64_archive(&members));
+        let end = blob.len() as u64;
+
+        let mut z = RemoteZip::new(SeekSlice::new(Cursor::new(blob), 6, end)?)?;
+        let limits = UnpackLimits::default();
+        assert_eq!(z.file_names().len(), 70_001);
+        assert_eq!(
+            z.read("foo-1.0.dist-info/METADATA", &limits)?,
+            b"Metadata-Version: 2.1\n"
+        );
+        assert_eq!(z.read("foo/69999.py", &limits)?, b"");
+        Ok(())
+    }
+}
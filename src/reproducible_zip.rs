@@ -0,0 +1,113 @@
+//! Writing zips that come out the same every time, for the pybis and wheels we make:
+//! the same members in the same order always give the same bytes, and so the same
+//! hash. Timestamps are all 1980-01-01 (the earliest a zip can say), and permissions
+//! are either 0o755 or 0o644, whatever the files we started from had. Putting the
+//! members in a stable order is up to the caller.
+//!
+//! Both formats end with a RECORD listing everything else, so this keeps track of that
+//! as it goes, and writes it last.
+
+use crate::prelude::*;
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+pub struct ReproducibleZip<W: Write + Seek> {
+    z: ZipWriter<W>,
+    record: Vec<RecordEntry>,
+}
+
+fn options(mode: u32) -> FileOptions {
+    FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default())
+        .unix_permissions(mode)
+}
+
+impl<W: Write + Seek> ReproducibleZip<W> {
+    pub fn new(out: W) -> ReproducibleZip<W> {
+        ReproducibleZip {
+            z: ZipWriter::new(out),
+            record: Vec::new(),
+        }
+    }
+
+    pub fn add_directory(&mut self, path: &str) -> Result<()> {
+        self.z.add_directory(path, options(0o755))?;
+        Ok(())
+    }
+
+    pub fn add_file(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        executable: bool,
+    ) -> Result<()> {
+        self.z
+            .start_file(path, options(if executable { 0o755 } else { 0o644 }))?;
+        self.z.write_all(data)?;
+        self.record.push(RecordEntry::for_data(path, data));
+        Ok(())
+    }
+
+    /// Symlinks go in the RECORD without a hash or size, since there's no data to
+    /// check.
+    pub fn add_symlink(&mut self, path: &str, target: &str) -> Result<()> {
+        self.z.add_symlink(path, target, options(0o777))?;
+        self.record.push(RecordEntry {
+            path: path.into(),
+            hash: None,
+            size: None,
+        });
+        Ok(())
+    }
+
+    /// Writes the RECORD, at `record_path`, and finishes off the zip.
+    pub fn finish(mut self, record_path: &str) -> Result<W> {
+        let record = Record::new(record_path, self.record);
+        self.z.start_file(record_path, options(0o644))?;
+        self.z.write_all(record.render().as_bytes())?;
+        Ok(self.z.finish()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use zip::ZipArchive;
+
+    fn write() -> Result<Vec<u8>> {
+        let mut z = ReproducibleZip::new(Cursor::new(Vec::new()));
+        z.add_directory("bin")?;
+        z.add_file("bin/tool", b"#!/bin/sh\n", true)?;
+        z.add_symlink("bin/tool2", "tool")?;
+        z.add_file("info/METADATA", b"Name: tool\n", false)?;
+        Ok(z.finish("info/RECORD")?.into_inner())
+    }
+
+    #[test]
+    fn test_reproducible_zip() -> Result<()> {
+        let written = write()?;
+        assert_eq!(write()?, written);
+
+        let mut z = ZipArchive::new(Cursor::new(written))?;
+        assert_eq!(
+            z.file_names().collect::<Vec<_>>(),
+            vec![
+                "bin/",
+                "bin/tool",
+                "bin/tool2",
+                "info/METADATA",
+                "info/RECORD"
+            ]
+        );
+        assert_eq!(z.by_name("bin/tool")?.unix_mode(), Some(0o100755));
+        assert_eq!(z.by_name("info/METADATA")?.unix_mode(), Some(0o100644));
+        assert_eq!(z.by_name("info/METADATA")?.last_modified().year(), 1980);
+        let record = slurp(&mut z.by_name("info/RECORD")?)?;
+        let record = Record::parse(std::str::from_utf8(&record)?)?;
+        assert_eq!(record.entries.len(), 4);
+        record.get("bin/tool").unwrap().check(b"#!/bin/sh\n")?;
+        assert!(record.get("bin/tool2").unwrap().hash.is_none());
+        Ok(())
+    }
+}
@@ -1,12 +1,17 @@
-use crate::package_db::WheelBuilder;
+use crate::error::Error;
+use crate::package_db::{LocalTree, WheelBuilder};
+use crate::platform_tags::missing_marker_variables;
+use crate::policy::Policy;
 use crate::prelude::*;
 use elsa::FrozenMap;
+use indexmap::IndexMap;
 use pubgrub::range::Range;
-use pubgrub::report::DerivationTree;
 use pubgrub::report::Reporter;
 use pubgrub::solver::{Dependencies, DependencyConstraints};
 use std::borrow::Borrow;
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
 
 use crate::package_db::{ArtifactInfo, PackageDB};
 
@@ -73,6 +78,109 @@ fn allow_pre_is_empty(value: &AllowPre) -> bool {
     }
 }
 
+/// Where a package has to come from, instead of the indexes we'd normally search. Like
+/// Poetry's `source`, one of:
+///
+///   { index = "internal" }            # from posy.toml's [indexes]
+///   { url = "https://example.com/foo-1.0-py3-none-any.whl#sha256=..." }
+///   { git = "https://github.com/org/foo", rev = "v1.0" }
+///   { path = "vendor/foo" }           # a wheel, an sdist, or a source tree
+///
+/// Git repositories and source trees get built into a wheel for the Python we're
+/// resolving for, so the Blueprint pins whatever that turns out to be. They can't be
+/// pinned by hash, since builds aren't reproducible enough for that.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RequirementSource {
+    Index {
+        index: String,
+    },
+    Url {
+        url: Url,
+    },
+    Git {
+        git: Url,
+        /// A branch, tag, or commit; None means the default branch. In a Blueprint,
+        /// it's always the commit we used.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+    },
+    Path {
+        path: PathBuf,
+    },
+}
+
+impl RequirementSource {
+    /// Whether we have to build this ourselves before we know anything about it.
+    pub fn is_tree(&self) -> bool {
+        match self {
+            RequirementSource::Git { .. } => true,
+            RequirementSource::Path { path } => path.is_dir(),
+            RequirementSource::Index { .. } | RequirementSource::Url { .. } => false,
+        }
+    }
+
+    /// Points `db` at this as the only place to get `package`. Trees have to be built
+    /// for a particular Python first, so they need `builder` and the platform to build
+    /// for; everything else can be set up right away.
+    ///
+    /// Returns the source as the Blueprint should record it: for git, at the commit we
+    /// actually checked out.
+    pub fn apply(
+        &self,
+        db: &PackageDB,
+        package: &PackageName,
+        builder: Option<(&WheelBuilder, &WheelPlatform)>,
+    ) -> Result<RequirementSource> {
+        context!("Getting {} from {}", package.as_given(), self);
+        let build = |root: &std::path::Path| -> Result<()> {
+            let Some((builder, wheel_platform)) = builder else {
+                bail!("can't build a source tree before we know which Python it's for");
+            };
+            let tree = LocalTree::new(package.clone(), root)?;
+            let wheel = builder.local_tree_wheel_path(&tree, wheel_platform)?;
+            db.add_local_file(package, &wheel)?;
+            Ok(())
+        };
+        match self {
+            RequirementSource::Index { index } => {
+                db.add_index_source(package, index)?
+            }
+            RequirementSource::Url { url } => db.add_direct_reference(package, url)?,
+            RequirementSource::Path { path } if path.is_dir() => build(path)?,
+            RequirementSource::Path { path } => {
+                db.add_local_file(package, path)?;
+            }
+            RequirementSource::Git { git, rev } => {
+                let (root, commit) = db.git_checkout(git, rev.as_deref())?;
+                build(&root)?;
+                return Ok(RequirementSource::Git {
+                    git: git.clone(),
+                    rev: Some(commit),
+                });
+            }
+        }
+        Ok(self.clone())
+    }
+}
+
+impl Display for RequirementSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequirementSource::Index { index } => write!(f, "the {index:?} index"),
+            RequirementSource::Url { url } => write!(f, "{url}"),
+            RequirementSource::Git {
+                git,
+                rev: Some(rev),
+            } => {
+                write!(f, "{git} at {rev}")
+            }
+            RequirementSource::Git { git, rev: None } => write!(f, "{git}"),
+            RequirementSource::Path { path } => write!(f, "{}", path.display()),
+        }
+    }
+}
+
 /// A high-level description of an environment that a user would like to be able to
 /// build. Doesn't necessarily have to be what the user types in exactly, but has to
 /// represent their intentions, and you have to be able to build the whole structure
@@ -84,15 +192,71 @@ pub struct Brief {
     pub requirements: Vec<UserRequirement>,
     #[serde(skip_serializing_if = "allow_pre_is_empty")]
     pub allow_pre: AllowPre,
-    // XX TODO
-    //pub constraints: Vec<UserRequirement>,
+    /// Restrictions on which versions we can pick, for packages that end up in the
+    /// environment for some other reason. Unlike requirements, these never cause
+    /// anything to be installed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<UserRequirement>,
+    /// Where particular packages have to come from, if not the usual indexes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<PackageName, RequirementSource>,
 }
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct PinnedPackage {
     pub name: PackageName,
     pub version: Version,
+    /// Every digest the index gave us, for every artifact of this version
     pub hashes: Vec<ArtifactHash>,
+    /// Where to get it, if it came from a direct reference instead of the index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    /// Where the Brief said to get it from, if it said
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<RequirementSource>,
+}
+
+impl PinnedPackage {
+    /// Whether `ai` is one of the files we pinned. Normally any of its hashes being
+    /// pinned is enough to know it's the same file. With `strict`, its strongest hash
+    /// has to be pinned too, so that a stronger hash the index added later doesn't
+    /// get ignored.
+    pub fn covers(&self, ai: &ArtifactInfo, strict: bool) -> bool {
+        if self.is_built_locally() {
+            // whatever the tree builds to is what we pinned
+            return !strict;
+        }
+        if strict {
+            ai.hash().map_or(false, |h| self.hashes.contains(h))
+        } else {
+            ai.hashes.iter().any(|h| self.hashes.contains(h))
+        }
+    }
+
+    /// `ai`, with only the hashes we pinned, so that its download gets checked against
+    /// the strongest of those -- never against a hash that only the index vouches
+    /// for. If we didn't pin any hashes, there's nothing to go by but the index, and
+    /// it's left alone.
+    pub fn pinned_artifact(&self, ai: &ArtifactInfo) -> ArtifactInfo {
+        let mut ai = ai.clone();
+        if !self.hashes.is_empty() {
+            ai.hashes.retain(|h| self.hashes.contains(h));
+        }
+        ai
+    }
+
+    /// Whether we build this from a source tree ourselves (see `RequirementSource`),
+    /// so that there aren't any hashes to pin.
+    pub fn is_built_locally(&self) -> bool {
+        self.source
+            .as_ref()
+            .map_or(false, |source| source.is_tree())
+    }
+
+    /// Whether this comes from one of the indexes, so it's worth asking them about.
+    pub fn is_from_index(&self) -> bool {
+        self.url.is_none() && !self.is_built_locally()
+    }
 }
 
 impl Display for PinnedPackage {
@@ -107,28 +271,58 @@ impl Display for PinnedPackage {
     }
 }
 
-struct VersionHints<'a>(
-    HashMap<&'a PackageName, (&'a Version, HashSet<&'a ArtifactHash>)>,
-);
+/// What we know from an earlier resolve (usually the lock we're updating): which
+/// versions to prefer, and the metadata we already used for them, so re-resolving
+/// after a small change doesn't have to look any of that up again.
+struct VersionHints<'a> {
+    pins: HashMap<&'a PackageName, (&'a Version, HashSet<&'a ArtifactHash>)>,
+    metadata: HashMap<&'a PackageName, (&'a Version, &'a WheelResolveMetadata)>,
+}
 
 impl<'a> VersionHints<'a> {
     fn new() -> VersionHints<'a> {
-        VersionHints(HashMap::new())
+        VersionHints {
+            pins: HashMap::new(),
+            metadata: HashMap::new(),
+        }
     }
 
     fn add_pinned(&mut self, pin: &'a PinnedPackage) {
-        self.0
+        self.pins
             .insert(&pin.name, (&pin.version, pin.hashes.iter().collect()));
     }
 
     fn from(blueprint: &'a Blueprint) -> VersionHints<'a> {
         let mut hints = VersionHints::new();
         hints.add_pinned(&blueprint.pybi);
-        for (wheel, _) in &blueprint.wheels {
+        for (wheel, metadata) in &blueprint.wheels {
             hints.add_pinned(wheel);
+            // a direct reference might point somewhere new, and a source tree might
+            // have changed, so don't trust those
+            if wheel.is_from_index() {
+                hints
+                    .metadata
+                    .insert(&wheel.name, (&wheel.version, metadata));
+            }
         }
         hints
     }
+
+    fn metadata_for(
+        &self,
+        package: &PackageName,
+        version: &Version,
+    ) -> Option<&'a WheelResolveMetadata> {
+        match self.metadata.get(package) {
+            Some((v, metadata)) if *v == version => Some(*metadata),
+            _ => None,
+        }
+    }
+
+    /// The packages we'll probably end up wanting again.
+    fn packages(&self) -> impl Iterator<Item = &'a PackageName> + '_ {
+        self.pins.keys().copied()
+    }
 }
 
 /// This is the subset of WheelCoreMetadata that the resolver actually uses.
@@ -173,11 +367,17 @@ impl WheelResolveMetadata {
     }
 }
 
+/// What a Brief resolved to: an exact Python and exact packages, with hashes, that can
+/// be installed without looking at an index again (except to download them).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Blueprint {
     pub pybi: PinnedPackage,
     pub wheels: Vec<(PinnedPackage, WheelResolveMetadata)>,
+    /// Which of `wheels` were asked for by name, rather than pulled in as dependencies.
+    /// They get a REQUESTED file when installed, the way pip marks them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requested: Vec<PackageName>,
     #[serde(serialize_with = "serialize_marker_exprs")]
     pub marker_expressions: HashMap<StandaloneMarkerExpr, bool>,
 }
@@ -197,6 +397,287 @@ where
     s.collect_map(stringized.into_iter())
 }
 
+impl Blueprint {
+    /// Makes `db` look for the pins that came from one of the named indexes on that
+    /// index, like when we resolved them. Has to happen before anything looks them up.
+    pub fn use_index_sources(&self, db: &PackageDB) -> Result<()> {
+        for (pin, _) in &self.wheels {
+            if let Some(RequirementSource::Index { index }) = &pin.source {
+                db.add_index_source(&pin.name, index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A hash of everything that decides what gets installed: the pins, and which
+    /// packages were requested. Two Blueprints with the same hash make the same
+    /// environment, whatever project they came from.
+    pub fn content_hash(&self) -> Result<ArtifactHash> {
+        let pins: Vec<&PinnedPackage> = std::iter::once(&self.pybi)
+            .chain(self.wheels.iter().map(|(pin, _)| pin))
+            .collect();
+        let content = serde_json::to_vec(&(pins, &self.requested))?;
+        ArtifactHash::sha256_of(content.as_slice())
+    }
+
+    /// Everything pinned without any hashes at all.
+    pub fn unhashed(&self) -> Vec<&PinnedPackage> {
+        std::iter::once(&self.pybi)
+            .chain(self.wheels.iter().map(|(pin, _)| pin))
+            .filter(|pin| pin.hashes.is_empty())
+            .collect()
+    }
+
+    /// Which of the other pinned packages each pinned package requires. Requirements
+    /// whose markers were false when we resolved don't count. We don't record which
+    /// extras were asked for, so ones that only apply for some extra do.
+    pub fn dependency_graph(
+        &self,
+    ) -> Result<BTreeMap<&PackageName, Vec<&PackageName>>> {
+        let pinned: HashSet<&PackageName> =
+            self.wheels.iter().map(|(pin, _)| &pin.name).collect();
+        let mut graph = BTreeMap::new();
+        for (pin, metadata) in &self.wheels {
+            let mut dependencies = Vec::new();
+            for req in &metadata.inner.requires_dist {
+                let Some(&name) = pinned.get(&req.name) else {
+                    continue;
+                };
+                if self.marker_applied(req, &metadata.inner.extras)? {
+                    dependencies.push(name);
+                }
+            }
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            graph.insert(&pin.name, dependencies);
+        }
+        Ok(graph)
+    }
+
+    /// The pinned packages that require `name`, and what they say about it. Same rules
+    /// as `dependency_graph` for which requirements count.
+    pub fn requirements_on(
+        &self,
+        name: &PackageName,
+    ) -> Result<Vec<(&PinnedPackage, &Requirement)>> {
+        let mut found = Vec::new();
+        for (pin, metadata) in &self.wheels {
+            let extras = &metadata.inner.extras;
+            for req in &metadata.inner.requires_dist {
+                if &req.name == name && self.marker_applied(req, extras)? {
+                    found.push((pin, req));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Whether `req` could have applied, for the package itself or any of its
+    /// `extras`. Markers we never had to evaluate are assumed to.
+    fn marker_applied(
+        &self,
+        req: &Requirement,
+        extras: &HashSet<Extra>,
+    ) -> Result<bool> {
+        let Some(expr) = &req.env_marker_expr else {
+            return Ok(true);
+        };
+        let extras = std::iter::once(None)
+            .chain(extras.iter().map(|extra| Some(extra.normalized())));
+        for extra in extras {
+            match simplify_out_extra(expr, extra)? {
+                Simplified::True => return Ok(true),
+                Simplified::False => (),
+                Simplified::Expr(expr) => {
+                    let expr = StandaloneMarkerExpr(expr);
+                    if self.marker_expressions.get(&expr) != Some(&false) {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Groups of pinned packages that all depend on each other, directly or not --
+    /// like sphinx and the sphinxcontrib-* packages. There's no installing a group
+    /// strictly after its members' dependencies, or uninstalling it strictly before,
+    /// so anything that orders packages by their dependencies has to treat each group
+    /// as one unit. Each group is sorted, and so is the list.
+    pub fn cycles(&self) -> Result<Vec<Vec<PackageName>>> {
+        let graph = self.dependency_graph()?;
+        let mut cycles: Vec<Vec<PackageName>> = strongly_connected(&graph)
+            .into_iter()
+            .filter(|group| group.len() > 1 || graph[group[0]].contains(&group[0]))
+            .map(|group| {
+                let mut group: Vec<PackageName> = group.into_iter().cloned().collect();
+                group.sort_unstable();
+                group
+            })
+            .collect();
+        cycles.sort_unstable();
+        Ok(cycles)
+    }
+
+    /// Checks every pin against what the index says now, and returns the ones that
+    /// have been yanked since we locked them. The resolver keeps using yanked files
+    /// that are already pinned (that's what PEP 592 says to do), so without this, a
+    /// long-lived lock would never notice.
+    ///
+    /// A pin counts as yanked if any of the files it pinned are. Direct references
+    /// and source trees don't come from an index, so they're skipped. If `db` is
+    /// offline, this goes by the index pages in the cache, and skips (with a warning)
+    /// packages whose page isn't there.
+    pub fn yanked(&self, db: &PackageDB) -> Result<Vec<YankedPin>> {
+        self.use_index_sources(db)?;
+        let pins: Vec<&PinnedPackage> = std::iter::once(&self.pybi)
+            .chain(self.wheels.iter().map(|(pin, _)| pin))
+            .filter(|pin| pin.is_from_index())
+            .collect();
+        let names: Vec<&PackageName> = pins.iter().map(|pin| &pin.name).collect();
+        let mut yanked = Vec::new();
+        for (pin, artifacts) in pins.iter().zip(db.available_artifacts_many(&names)) {
+            let artifacts = match artifacts {
+                Ok(artifacts) => artifacts,
+                Err(err) if db.offline() => {
+                    warn!(
+                        "can't tell whether {} {} has been yanked: {err}",
+                        pin.name.as_given(),
+                        pin.version
+                    );
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let Some(ais) = artifacts.get(&pin.version) else {
+                warn!(
+                    "{} {} isn't on the index anymore",
+                    pin.name.as_given(),
+                    pin.version
+                );
+                continue;
+            };
+            // Pins from before we recorded hashes cover every file of their version
+            let pinned = ais
+                .iter()
+                .filter(|ai| pin.hashes.is_empty() || pin.covers(ai, false));
+            let mut files = Vec::new();
+            let mut reason = None;
+            for ai in pinned.filter(|ai| ai.yanked.yanked) {
+                files.push(ai.name.to_string());
+                reason = reason.or_else(|| ai.yanked.reason.clone());
+            }
+            if !files.is_empty() {
+                yanked.push(YankedPin {
+                    name: pin.name.clone(),
+                    version: pin.version.clone(),
+                    reason,
+                    files,
+                });
+            }
+        }
+        Ok(yanked)
+    }
+
+    /// Fetches the metadata of every pinned wheel -- not just the one the resolver
+    /// got it from -- and returns the ones whose dependencies or Requires-Python
+    /// disagree with what we resolved with. See WheelResolveMetadata for why that's a
+    /// problem; this finds it at lock time, instead of whenever someone first installs
+    /// on the wrong platform.
+    ///
+    /// This can mean downloading a lot of metadata. Direct references and source
+    /// trees only have the one file, so they're skipped.
+    pub fn metadata_divergences(
+        &self,
+        db: &PackageDB,
+    ) -> Result<Vec<MetadataDivergence>> {
+        self.use_index_sources(db)?;
+        let mut divergences = Vec::new();
+        for (pin, expected) in &self.wheels {
+            if !pin.is_from_index() {
+                continue;
+            }
+            context!(
+                "Checking the wheels of {} {}",
+                pin.name.as_given(),
+                pin.version
+            );
+            let ais = db.artifacts_for_version(&pin.name, &pin.version)?;
+            let wheels = ais.iter().filter(|ai| {
+                ai.is::<Wheel>()
+                    && ai.url.as_str() != expected.provenance
+                    && (pin.hashes.is_empty() || pin.covers(ai, false))
+            });
+            for ai in wheels {
+                let found = db.get_resolve_metadata(std::slice::from_ref(ai), None)?;
+                let differences = metadata_differences(&expected.inner, &found.inner);
+                if !differences.is_empty() {
+                    divergences.push(MetadataDivergence {
+                        name: pin.name.clone(),
+                        version: pin.version.clone(),
+                        wheel: ai.name.to_string(),
+                        resolved_from: expected.provenance.clone(),
+                        differences,
+                    });
+                }
+            }
+        }
+        Ok(divergences)
+    }
+}
+
+/// A pinned wheel whose metadata doesn't match what we resolved with, from
+/// `Blueprint::metadata_divergences`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetadataDivergence {
+    pub name: PackageName,
+    pub version: Version,
+    /// The wheel that disagrees
+    pub wheel: String,
+    /// Where the metadata we resolved with came from
+    pub resolved_from: String,
+    /// What's different, one line each
+    pub differences: Vec<String>,
+}
+
+fn metadata_differences(
+    expected: &WheelResolveMetadataInner,
+    found: &WheelResolveMetadataInner,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    // the order of Requires-Dist doesn't mean anything
+    let strings = |reqs: &[PackageRequirement]| -> BTreeSet<String> {
+        reqs.iter().map(|req| req.to_string()).collect()
+    };
+    let expected_reqs = strings(&expected.requires_dist);
+    let found_reqs = strings(&found.requires_dist);
+    for req in found_reqs.difference(&expected_reqs) {
+        differences.push(format!("extra Requires-Dist: {req}"));
+    }
+    for req in expected_reqs.difference(&found_reqs) {
+        differences.push(format!("missing Requires-Dist: {req}"));
+    }
+    if expected.requires_python != found.requires_python {
+        differences.push(format!(
+            "Requires-Python is {:?}, not {:?}",
+            found.requires_python.to_string(),
+            expected.requires_python.to_string()
+        ));
+    }
+    differences
+}
+
+/// A pinned release that's been yanked from the index, from `Blueprint::yanked`.
+#[derive(Debug, Clone)]
+pub struct YankedPin {
+    pub name: PackageName,
+    pub version: Version,
+    /// Why, if the index said
+    pub reason: Option<String>,
+    /// Which of the pinned files are yanked
+    pub files: Vec<String>,
+}
+
 impl Display for Blueprint {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "pybi: {}", self.pybi)?;
@@ -207,7 +688,7 @@ impl Display for Blueprint {
     }
 }
 
-fn pick_best_pybi<'a, 'b>(
+pub(crate) fn pick_best_pybi<'a, 'b>(
     artifact_infos: &'a [ArtifactInfo],
     platforms: &[&'b PybiPlatform],
 ) -> Option<(&'a ArtifactInfo, &'b PybiPlatform)> {
@@ -220,13 +701,13 @@ fn pick_best_pybi<'a, 'b>(
                 if let ArtifactName::Pybi(name) = &ai.name {
                     platform
                         .max_compatibility(name.arch_tags.iter())
-                        .map(|score| (ai, score))
+                        .map(|score| (ai, name, score))
                 } else {
                     None
                 }
             })
-            .max_by_key(|(_, score)| *score)
-            .map(|(ai, _)| ai)
+            .max_by_key(|(_, name, score)| binary_preference(*score, *name))
+            .map(|(ai, _, _)| ai)
         {
             return Some((ai, platform));
         }
@@ -241,7 +722,7 @@ fn resolve_pybi<'a, 'b>(
     hints: &VersionHints,
 ) -> Result<(&'a ArtifactInfo, &'b PybiPlatform)> {
     let name = &brief.python.name;
-    let versions = fetch_and_sort_versions(db, brief, name, None, hints)?;
+    let versions = fetch_and_sort_versions(db, brief, name, None, None, hints)?;
     for version in versions.iter() {
         if brief.python.specifiers.satisfied_by(version)? {
             let artifact_infos = db.artifacts_for_version(name, version)?;
@@ -250,7 +731,73 @@ fn resolve_pybi<'a, 'b>(
             }
         }
     }
-    Err(PosyError::NoPybiFound)?
+    Err(no_pybi_found(db, brief, platforms, &versions)?)?
+}
+
+/// Why `resolve_pybi` came up empty: each version of the Python we know about, with
+/// the platforms it was built for and what ruled it out, and then the tags each of
+/// `platforms` would have taken. `considered` is what made it past
+/// `fetch_and_sort_versions`.
+fn no_pybi_found(
+    db: &PackageDB,
+    brief: &Brief,
+    platforms: &[&PybiPlatform],
+    considered: &[&Version],
+) -> Result<ResolveError> {
+    let name = &brief.python.name;
+    let artifacts = db.available_artifacts(name)?;
+    let allow_prerelease = allows_prerelease(brief, name, artifacts);
+    let mut versions: Vec<&Version> = artifacts.keys().collect();
+    versions.sort_unstable_by(|a, b| b.cmp(a));
+    let mut details = Vec::new();
+    if versions.is_empty() {
+        details.push(format!("there aren't any versions of {}", name.as_given()));
+    }
+    for version in versions {
+        let mut tags: Vec<&str> = artifacts[version]
+            .iter()
+            .filter_map(|ai| match &ai.name {
+                ArtifactName::Pybi(name) => Some(name.arch_tags.iter()),
+                _ => None,
+            })
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        tags.sort_unstable();
+        tags.dedup();
+        // A requirement we can't even check counts as excluding it; we'd rather not
+        // lose the rest of the report over it.
+        let satisfied = brief.python.specifiers.satisfied_by(version);
+        let why = if !satisfied.unwrap_or(false) {
+            "excluded by the requirement"
+        } else if !allow_prerelease && version.is_prerelease() {
+            "a pre-release"
+        } else if !considered.contains(&version) {
+            "skipped (yanked, or excluded by a constraint or policy)"
+        } else {
+            "not built for any of these platforms"
+        };
+        details.push(format!(
+            "{} {version} ({}): {why}",
+            name.as_given(),
+            match tags.is_empty() {
+                true => "no pybis".into(),
+                false => tags.join(", "),
+            }
+        ));
+    }
+    for platform in platforms {
+        let tags: Vec<&str> = platform.tags().map(String::as_str).collect();
+        details.push(format!(
+            "platform {} accepts: {}",
+            platform.core_tag(),
+            tags.join(", ")
+        ));
+    }
+    Ok(ResolveError::NoPybiFound {
+        requirement: brief.python.to_string(),
+        details,
+    })
 }
 
 fn pinned(
@@ -261,26 +808,180 @@ fn pinned(
     let hashes = db
         .artifacts_for_version(&name, &version)?
         .iter()
-        .filter_map(|ai| ai.hash.clone())
+        .flat_map(|ai| ai.hashes.iter().cloned())
         .collect::<Vec<_>>();
+    let url = db.direct_reference(&name).cloned();
     Ok(PinnedPackage {
         name,
         version,
         hashes,
+        url,
+        source: None,
     })
 }
 
 impl Brief {
+    /// Whether `package` comes from a source tree we have to build (see
+    /// `RequirementSource`), instead of anywhere we can look it up.
+    fn builds_locally(&self, package: &PackageName) -> bool {
+        self.sources
+            .get(package)
+            .map_or(false, |source| source.is_tree())
+    }
+
+    /// The packages whose index pages resolving this is sure to want: the Python,
+    /// everything asked for by name, and everything pinned in `hints`.
+    fn roots<'s>(&'s self, hints: &VersionHints<'s>) -> Vec<&'s PackageName> {
+        let mut roots: Vec<&PackageName> = std::iter::once(&self.python.name)
+            .chain(self.requirements.iter().map(|r| &r.name))
+            .chain(hints.packages())
+            .filter(|name| !self.builds_locally(name))
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        roots
+    }
+
+    /// The solver itself is synchronous (pubgrub asks for one package at a time), but
+    /// we know up front that it's going to want the index pages for the python,
+    /// everything we asked for by name, and most likely everything in `like`, so we
+    /// fetch those all at once first.
+    ///
+    /// If we've resolved the same thing before and none of the index pages have
+    /// changed since, we skip all that and reuse the old answer.
+    ///
+    /// This blocks until it's done, solving and building any sdists it needs on the
+    /// calling thread, so from async code, call it wherever you'd do other blocking
+    /// work.
+    ///
+    /// Any direct references only last for this resolve (see
+    /// `PackageDB::with_direct_references`).
     pub fn resolve(
         &self,
         db: &PackageDB,
         platforms: &[&PybiPlatform],
         like: Option<&Blueprint>,
         build_stack: &[&PackageName],
+    ) -> Result<Blueprint, Error> {
+        let key = db.resolution_key(self, platforms, like)?;
+        if let Some(key) = &key {
+            if let Some(blueprint) = db.cached_resolution(key) {
+                debug!("reusing cached resolution");
+                return Ok(blueprint);
+            }
+        }
+        let blueprint = db.with_direct_references(|| {
+            self._resolve(db, platforms, like, build_stack)
+        })?;
+        if let Some(key) = &key {
+            db.save_resolution(key, &blueprint)?;
+        }
+        Ok(blueprint)
+    }
+
+    /// Resolves a batch of unrelated Briefs -- e.g. a pile of tool environments --
+    /// in the same PackageDB, so whatever one of them fetches is already there for the
+    /// rest.
+    ///
+    /// The solves still run one after another: they share the PackageDB, which isn't
+    /// Sync. (XX TODO: solve them concurrently, which needs a PackageDB that is.) What
+    /// we can do is fetch ahead: before any solve starts, we walk all the Briefs'
+    /// dependency graphs together, like `preheat` does, following the version the
+    /// resolver will try first for each package, and fetch each round's index pages and
+    /// metadata for the whole batch concurrently. So the solves mostly find what they
+    /// need in the cache, but one that backtracks onto something the walk didn't reach
+    /// still fetches it on its own, one request at a time.
+    /// Each solve gets its own direct references, but the walk shares one set, so a
+    /// Brief whose direct references disagree with an earlier one's doesn't get
+    /// anything fetched ahead. And each one's saved resolution depends on all the
+    /// pages the batch had seen so far, so it goes stale a bit sooner than one resolved
+    /// on its own would.
+    ///
+    /// Each Brief gets its own result, in order, so one failing doesn't stop the rest.
+    /// Like `resolve`, this blocks until it's done.
+    pub fn resolve_all(
+        db: &PackageDB,
+        briefs: &[&Brief],
+        platforms: &[&PybiPlatform],
+    ) -> Vec<Result<Blueprint, Error>> {
+        db.with_direct_references(|| Brief::prefetch_all(db, briefs, platforms));
+        briefs
+            .iter()
+            .map(|brief| brief.resolve(db, platforms, None, &[]))
+            .collect()
+    }
+
+    /// The fetching-ahead part of `resolve_all`.
+    fn prefetch_all(db: &PackageDB, briefs: &[&Brief], platforms: &[&PybiPlatform]) {
+        let hints = VersionHints::new();
+        let mut pending = Vec::new();
+        let mut roots = Vec::new();
+        for brief in briefs {
+            // (ones we already have an answer for won't need anything)
+            let cached = match db.resolution_key(brief, platforms, None) {
+                Ok(Some(key)) => db.cached_resolution(&key).is_some(),
+                _ => false,
+            };
+            if cached {
+                continue;
+            }
+            // (and ones that can't get this far are for their solve to report)
+            if let Err(err) = brief.add_sources(db) {
+                debug!("not prefetching for {}: {err:#}", brief.python);
+                continue;
+            }
+            roots.extend(brief.roots(&hints));
+            pending.push(*brief);
+        }
+        roots.sort_unstable();
+        roots.dedup();
+        crate::util::block_on(db.prefetch_index(&roots));
+
+        let mut walks = Vec::new();
+        for brief in pending {
+            match PreheatWalk::new(db, brief, platforms, None, 1) {
+                Ok(walk) => walks.push(walk),
+                Err(err) => debug!("not prefetching for {}: {err:#}", brief.python),
+            }
+        }
+        let mut pages = roots.into_iter().cloned().collect();
+        crate::util::block_on(preheat_together(db, walks, &mut pages));
+    }
+
+    fn _resolve(
+        &self,
+        db: &PackageDB<'_>,
+        platforms: &[&PybiPlatform],
+        like: Option<&Blueprint>,
+        build_stack: &[&PackageName],
     ) -> Result<Blueprint> {
+        // XX TODO: evaluate these once we know the target environment
+        for c in &self.constraints {
+            if c.env_marker_expr.is_some() {
+                bail!("environment markers aren't supported on constraints yet: {c}");
+            }
+            if c.url.is_some() {
+                bail!("constraints can't use urls: {c}");
+            }
+        }
+        for req in &self.requirements {
+            if let Some(url) = &req.url {
+                db.add_direct_reference(&req.name, url)
+                    .wrap_err_with(|| format!("can't use requirement '{req}'"))?;
+            }
+        }
+        // what the Blueprint says each source turned out to be
+        let mut sources = HashMap::new();
+        for (name, source) in &self.sources {
+            if !source.is_tree() {
+                sources.insert(name, source.apply(db, name, None)?);
+            }
+        }
         let version_hints = like
             .map(VersionHints::from)
             .unwrap_or_else(VersionHints::new);
+        crate::util::block_on(db.prefetch_index(&self.roots(&version_hints)));
+
         let (pybi_ai, platform) = resolve_pybi(db, self, platforms, &version_hints)?;
         let wheel_builder = WheelBuilder::new(
             db,
@@ -294,28 +995,45 @@ impl Brief {
             .wrap_err_with(|| format!("fetching metadata for {}", pybi_ai.url))?;
         let pybi_name = pybi_ai.name.inner_as::<PybiName>().unwrap();
 
-        let mut env_marker_vars = pybi_metadata.environment_marker_variables;
-        if !env_marker_vars.contains_key("platform_machine") {
-            let is_arm64 = platform.compatibility("macosx_10_0_arm64").is_some();
-            let is_x86_64 = platform.compatibility("macosx_10_0_x86_64").is_some();
-            match (is_arm64, is_x86_64) {
-                (true, false) => {
-                    env_marker_vars.insert("platform_machine".into(), "arm64".into());
-                }
-                (false, true) => {
-                    env_marker_vars.insert("platform_machine".into(), "x86_64".into());
-                }
-                _ => (),
-            };
+        let mut env_marker_vars = pybi_metadata.environment_marker_variables.clone();
+        platform.synthesize_marker_variables(&pybi_metadata, &mut env_marker_vars);
+        let missing = missing_marker_variables(&env_marker_vars);
+        if !missing.is_empty() {
+            debug!(
+                "can't work out marker variables {} for {}",
+                missing.join(", "),
+                platform.core_tag()
+            );
+        }
+        let wheel_platform = platform.wheel_platform(&pybi_metadata)?;
+        for (name, source) in &self.sources {
+            if source.is_tree() {
+                let built = (&wheel_builder, &wheel_platform);
+                sources.insert(name, source.apply(db, name, Some(built))?);
+            }
         }
 
-        let (wheels, marker_exprs) = resolve_wheels(
-            db,
-            self,
-            &env_marker_vars,
-            &version_hints,
-            &wheel_builder,
-        )?;
+        progress!("resolve-start", python = %self.python);
+        let (mut wheels, marker_exprs) =
+            resolve_wheels(db, self, &env_marker_vars, &version_hints, &wheel_builder)?;
+        progress!("resolve-finish", packages = wheels.len());
+        for (pin, _) in &mut wheels {
+            pin.source = sources.get(&pin.name).cloned();
+            if pin.is_built_locally() {
+                // it's in our cache, which isn't anywhere anyone else can get it
+                pin.url = None;
+                pin.hashes.clear();
+            }
+        }
+        // (requirements whose markers ruled them out don't get installed at all)
+        let mut requested: Vec<PackageName> = self
+            .requirements
+            .iter()
+            .map(|req| req.name.clone())
+            .filter(|name| wheels.iter().any(|(pin, _)| &pin.name == name))
+            .collect();
+        requested.sort_unstable();
+        requested.dedup();
 
         Ok(Blueprint {
             pybi: pinned(
@@ -324,9 +1042,363 @@ impl Brief {
                 pybi_name.version.to_owned(),
             )?,
             wheels,
+            requested,
             marker_expressions: marker_exprs,
         })
     }
+    /// A Brief for `base` plus some extra requirements, that keeps everything in `base`
+    /// pinned exactly where it is. If the extras can't be satisfied without moving
+    /// something, then resolving it will fail, rather than quietly giving a different
+    /// environment than the user locked.
+    pub fn overlay(
+        &self,
+        base: &Blueprint,
+        extra: &[UserRequirement],
+    ) -> Result<Brief> {
+        let python = format!("{} == {}", base.pybi.name.as_given(), base.pybi.version)
+            .try_into()?;
+        let mut requirements = self.requirements.clone();
+        for (pin, _) in &base.wheels {
+            let pin_req = format!("{} == {}", pin.name.as_given(), pin.version);
+            requirements.push(pin_req.try_into()?);
+        }
+        requirements.extend(extra.iter().cloned());
+        Ok(Brief {
+            python,
+            requirements,
+            allow_pre: self.allow_pre.clone(),
+            constraints: self.constraints.clone(),
+            sources: self.sources.clone(),
+        })
+    }
+
+    /// What to install for an environment that was locked as `locked` from this Brief,
+    /// with `with` layered on top, on `python` instead of the locked Python if that's
+    /// set. This is the hook for test runners like tox and nox, which want the same
+    /// environment on several Pythons; none of it is meant to be saved.
+    ///
+    /// With `frozen`, every pin has to stay exactly where it is, just on a different
+    /// Python, and it's an error if one can't. Otherwise we keep as many of the pins
+    /// as still work. (`with` never moves any of them; see `overlay`.)
+    pub fn provision(
+        &self,
+        db: &PackageDB,
+        locked: &Blueprint,
+        python: Option<&PythonRequirement>,
+        with: &[UserRequirement],
+        platforms: &[&PybiPlatform],
+        frozen: bool,
+    ) -> Result<Blueprint> {
+        match python {
+            None if with.is_empty() => Ok(locked.clone()),
+            None => self
+                .overlay(locked, with)?
+                .resolve(db, platforms, Some(locked), &[])
+                .wrap_err("can't add --with packages without changing existing pins"),
+            Some(python) if frozen => Brief {
+                python: python.clone(),
+                ..self.overlay(locked, with)?
+            }
+            .resolve(db, platforms, Some(locked), &[])
+            .wrap_err_with(|| {
+                format!("the locked pins don't all work on {python}, and are frozen")
+            }),
+            Some(python) => {
+                let mut brief = Brief {
+                    python: python.clone(),
+                    ..self.clone()
+                };
+                brief.requirements.extend(with.iter().cloned());
+                brief
+                    .resolve(db, platforms, Some(locked), &[])
+                    .wrap_err_with(|| format!("can't resolve for {python}"))
+            }
+        }
+    }
+
+    /// Fetches, without solving anything, the index pages and metadata that resolving
+    /// this Brief is likely to need, so they're in the cache for later -- e.g. for a CI
+    /// step that has to run without network access.
+    ///
+    /// Starting from the requirements (and anything pinned in `like`), we walk the
+    /// dependency graph the way the resolver would, but instead of picking one version
+    /// of each package, we look at the first `versions_per_package` it would try.
+    /// Markers are evaluated for the Python the resolver would pick. That's a
+    /// guess, so a resolve that backtracks a long way can still need things we didn't
+    /// fetch. Releases that only have sdists aren't built, so their metadata isn't
+    /// fetched either.
+    pub fn preheat(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+        like: Option<&Blueprint>,
+        versions_per_package: usize,
+    ) -> Result<Preheated> {
+        db.with_direct_references(|| {
+            self.add_sources(db)?;
+            let version_hints = like
+                .map(VersionHints::from)
+                .unwrap_or_else(VersionHints::new);
+            let roots = self.roots(&version_hints);
+            crate::util::block_on(db.prefetch_index(&roots));
+            let mut walk =
+                PreheatWalk::new(db, self, platforms, like, versions_per_package)?;
+            walk.preheated.index_pages += roots.len();
+            let mut pages = roots.into_iter().cloned().collect();
+            let mut preheated =
+                crate::util::block_on(preheat_together(db, vec![walk], &mut pages));
+            preheated.pop().unwrap()
+        })
+    }
+
+    /// The part of resolving that has to happen before we look anything up on the
+    /// index: direct references, and sources that aren't trees we have to build.
+    fn add_sources(&self, db: &PackageDB) -> Result<()> {
+        for req in &self.requirements {
+            if let Some(url) = &req.url {
+                db.add_direct_reference(&req.name, url)
+                    .wrap_err_with(|| format!("can't use requirement '{req}'"))?;
+            }
+        }
+        // (source trees don't have anything to fetch ahead of time)
+        for (name, source) in &self.sources {
+            if !source.is_tree() {
+                source.apply(db, name, None)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What `Brief::preheat` fetched (or found already cached).
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Preheated {
+    pub index_pages: usize,
+    pub metadata: usize,
+}
+
+/// (package, extra, specifiers) that something asked for
+type Wanted = (PackageName, Option<Extra>, Specifiers);
+
+/// One Brief's walk through its dependency graph for `Brief::preheat`, taken a round
+/// at a time, so that `preheat_together` can fetch each round's index pages and
+/// metadata for several Briefs at once.
+struct PreheatWalk<'b> {
+    brief: &'b Brief,
+    version_hints: VersionHints<'b>,
+    versions_per_package: usize,
+    env: HashMap<String, String>,
+    python: PythonFilter,
+    seen: HashSet<(PackageName, Option<Extra>, String)>,
+    fetched: HashSet<(PackageName, Version)>,
+    // what the next round looks at
+    frontier: Vec<Wanted>,
+    // the versions this round settled on, with the extra they're wanted with
+    picked: Vec<(PackageName, Option<Extra>, Version)>,
+    preheated: Preheated,
+}
+
+impl<'b> PreheatWalk<'b> {
+    /// Picks the Python, which needs its index page fetched already, and starts off
+    /// with everything `brief` asks for.
+    fn new(
+        db: &PackageDB,
+        brief: &'b Brief,
+        platforms: &[&PybiPlatform],
+        like: Option<&'b Blueprint>,
+        versions_per_package: usize,
+    ) -> Result<PreheatWalk<'b>> {
+        let version_hints = like
+            .map(VersionHints::from)
+            .unwrap_or_else(VersionHints::new);
+        let (pybi_ai, platform) = resolve_pybi(db, brief, platforms, &version_hints)?;
+        let (_, pybi_metadata) = db
+            .get_metadata::<Pybi, _>(&[pybi_ai], None)
+            .wrap_err_with(|| format!("fetching metadata for {}", pybi_ai.url))?;
+        let mut env = pybi_metadata.environment_marker_variables.clone();
+        platform.synthesize_marker_variables(&pybi_metadata, &mut env);
+        // so that re-locking after a small change is covered too
+        let hinted: Vec<&PackageName> = version_hints
+            .packages()
+            .filter(|package| *package != &brief.python.name)
+            .collect();
+        let mut walk = PreheatWalk {
+            brief,
+            version_hints,
+            versions_per_package,
+            env,
+            python: PythonFilter::new(pybi_ai.name.version()),
+            seen: HashSet::new(),
+            fetched: HashSet::new(),
+            frontier: Vec::new(),
+            picked: Vec::new(),
+            preheated: Preheated {
+                index_pages: 0,
+                metadata: 1,
+            },
+        };
+        for req in &brief.requirements {
+            if walk.allows(req, None)? {
+                walk.want(&req.name, &req.extras, &req.specifiers);
+            }
+        }
+        for package in hinted {
+            walk.want(package, &[], &Specifiers::default());
+        }
+        Ok(walk)
+    }
+
+    /// If we can't tell whether a marker applies, we'd rather fetch too much
+    fn allows(&self, req: &Requirement, extra: Option<&Extra>) -> Result<bool> {
+        let Some(expr) = &req.env_marker_expr else {
+            return Ok(true);
+        };
+        let simplified = simplify_out_extra(expr, extra.map(|e| e.normalized()))?;
+        Ok(simplified.eval(&self.env).unwrap_or(true))
+    }
+
+    fn want(&mut self, name: &PackageName, extras: &[Extra], specifiers: &Specifiers) {
+        if self.brief.builds_locally(name) {
+            return;
+        }
+        let mut extras: Vec<Option<Extra>> =
+            extras.iter().map(|e| Some(e.clone())).collect();
+        if extras.is_empty() {
+            extras.push(None);
+        }
+        for extra in extras {
+            let key = (name.clone(), extra.clone(), specifiers.to_string());
+            if self.seen.insert(key) {
+                self.frontier
+                    .push((name.clone(), extra, specifiers.clone()));
+            }
+        }
+    }
+
+    /// Picks the versions to look at for everything in this round, now that their
+    /// index pages are fetched.
+    fn pick(&mut self, db: &PackageDB) -> Result<()> {
+        for (name, extra, specifiers) in std::mem::take(&mut self.frontier) {
+            let versions = fetch_and_sort_versions(
+                db,
+                self.brief,
+                &name,
+                Some(&self.python),
+                Some(db.policy()),
+                &self.version_hints,
+            );
+            // like the metadata in `step`, this is for the real resolve to report
+            let versions = match versions {
+                Ok(versions) => versions,
+                Err(err) => {
+                    debug!("not preheating {}: {err:#}", name.as_given());
+                    continue;
+                }
+            };
+            let mut candidates = 0;
+            for version in versions {
+                if candidates == self.versions_per_package {
+                    break;
+                }
+                if specifiers.satisfied_by(version)? {
+                    candidates += 1;
+                    self.picked
+                        .push((name.clone(), extra.clone(), version.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks at what `pick` picked, now that their metadata is fetched, and queues up
+    /// whatever they depend on for the next round.
+    fn step(&mut self, db: &PackageDB) -> Result<()> {
+        for (name, extra, version) in std::mem::take(&mut self.picked) {
+            let ais = db.artifacts_for_version(&name, &version)?;
+            let metadata = match db.get_resolve_metadata(ais, None) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    debug!("not preheating {} {version}: {err:#}", name.as_given());
+                    continue;
+                }
+            };
+            if self.fetched.insert((name.clone(), version.clone())) {
+                self.preheated.metadata += 1;
+            }
+            for req in &metadata.inner.requires_dist {
+                if self.allows(req, extra.as_ref())? {
+                    self.want(&req.name, &req.extras, &req.specifiers);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Takes `walks` a round at a time until they've all run out, fetching each round's
+/// index pages for all of them at once, and then the metadata for the versions they
+/// picked from those pages. `pages` is the ones we have already. Each walk that fails
+/// stops there, without holding up the others.
+async fn preheat_together(
+    db: &PackageDB<'_>,
+    walks: Vec<PreheatWalk<'_>>,
+    pages: &mut HashSet<PackageName>,
+) -> Vec<Result<Preheated>> {
+    let mut walks: Vec<Result<PreheatWalk>> = walks.into_iter().map(Ok).collect();
+    loop {
+        let mut new = Vec::new();
+        for walk in walks.iter_mut().flatten() {
+            let mut mine: Vec<&PackageName> = walk
+                .frontier
+                .iter()
+                .map(|(name, _, _)| name)
+                .filter(|name| !pages.contains(*name))
+                .collect();
+            mine.sort_unstable();
+            mine.dedup();
+            walk.preheated.index_pages += mine.len();
+            new.extend(mine.into_iter().cloned());
+        }
+        if walks.iter().flatten().all(|walk| walk.frontier.is_empty()) {
+            break;
+        }
+        new.sort_unstable();
+        new.dedup();
+        db.prefetch_index(&new.iter().collect::<Vec<_>>()).await;
+        pages.extend(new);
+        for walk in walks.iter_mut() {
+            if let Ok(ok) = walk {
+                if let Err(err) = ok.pick(db) {
+                    *walk = Err(err);
+                }
+            }
+        }
+        let mut releases: Vec<(&PackageName, &Version)> = walks
+            .iter()
+            .flatten()
+            .flat_map(|walk| walk.picked.iter())
+            .map(|(name, _, version)| (name, version))
+            .collect();
+        releases.sort_unstable();
+        releases.dedup();
+        let artifacts: Vec<&[ArtifactInfo]> = releases
+            .into_iter()
+            .filter_map(|(name, version)| db.artifacts_for_version(name, version).ok())
+            .collect();
+        db.prefetch_metadata(&artifacts).await;
+        for walk in walks.iter_mut() {
+            if let Ok(ok) = walk {
+                if let Err(err) = ok.step(db) {
+                    *walk = Err(err);
+                }
+            }
+        }
+    }
+    walks
+        .into_iter()
+        .map(|walk| walk.map(|walk| walk.preheated))
+        .collect()
 }
 
 struct PubgrubState<'a> {
@@ -338,12 +1410,20 @@ struct PubgrubState<'a> {
     wheel_builder: &'a WheelBuilder<'a>,
 
     marker_exprs: RefCell<HashMap<StandaloneMarkerExpr, bool>>,
-    python_full_version: Version,
+    // what each requirement marker we've seen came out to, for each extra
+    marker_values:
+        RefCell<HashMap<Option<Extra>, HashMap<marker::EnvMarkerExpr, bool>>>,
+    python: PythonFilter,
     // record of the metadata we used, so we can record it and validate it later when
     // using the pins
     expected_metadata: FrozenMap<(PackageName, Version), Box<WheelResolveMetadata>>,
     // These are sorted with most-preferred first.
     versions: FrozenMap<PackageName, Vec<&'a Version>>,
+    // declared licenses, only looked up if the policy has license rules
+    licenses: FrozenMap<(PackageName, Version), Box<Option<String>>>,
+    // pubgrub only passes our errors back as Box<dyn Error>, which can't go back
+    // into an eyre::Report (it isn't Send), so we keep the original here
+    error: RefCell<Option<eyre::Report>>,
 }
 
 fn get_or_fill<'a, K, V, F>(
@@ -363,44 +1443,137 @@ where
     }
 }
 
+fn satisfies_constraints(
+    brief: &Brief,
+    package: &PackageName,
+    version: &Version,
+) -> Result<bool> {
+    for constraint in &brief.constraints {
+        let applies = &constraint.name == package;
+        if applies && !constraint.specifiers.satisfied_by(version)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Checks artifacts' Requires-Python against the python we're resolving for. Most
+/// artifacts share the same handful of strings, so we remember the answer for each one
+/// instead of parsing it over and over.
+///
+/// A pre-release python gets checked as the release it's a pre-release of, the way pip
+/// does it: to a package that says "Requires-Python: >= 3.14", 3.14.0rc1 is 3.14.0.
+/// Otherwise nothing that's started requiring the new version would install on its
+/// release candidates, which is exactly when people want to try it.
+pub(crate) struct PythonFilter {
+    release_version: Version,
+    allowed: RefCell<HashMap<String, bool>>,
+}
+
+impl PythonFilter {
+    pub(crate) fn new(full_version: &Version) -> PythonFilter {
+        let mut release_version = full_version.clone();
+        if release_version.is_prerelease() {
+            let r = release_version.make_mut();
+            r.pre = None;
+            r.dev = None;
+        }
+        PythonFilter {
+            release_version,
+            allowed: Default::default(),
+        }
+    }
+
+    fn satisfies(&self, specifiers: &Specifiers) -> Result<bool> {
+        specifiers.satisfied_by(&self.release_version)
+    }
+
+    pub(crate) fn allows(&self, requires_python: &str) -> Result<bool> {
+        if let Some(allowed) = self.allowed.borrow().get(requires_python) {
+            return Ok(*allowed);
+        }
+        let specifiers: Specifiers = requires_python.parse()?;
+        let allowed = self.satisfies(&specifiers)?;
+        self.allowed
+            .borrow_mut()
+            .insert(requires_python.to_owned(), allowed);
+        Ok(allowed)
+    }
+}
+
+/// Whether `fetch_and_sort_versions` will consider pre-releases of `package`: if the
+/// user said so, or if there's nothing else.
+fn allows_prerelease(
+    brief: &Brief,
+    package: &PackageName,
+    artifacts: &IndexMap<Version, Vec<ArtifactInfo>>,
+) -> bool {
+    artifacts.keys().all(|version| version.is_prerelease())
+        || brief.allow_pre.allow_pre_for(package)
+}
+
 fn fetch_and_sort_versions<'a>(
     db: &'a PackageDB,
     brief: &Brief,
     package: &PackageName,
-    python_version: Option<&Version>,
+    python: Option<&PythonFilter>,
+    policy: Option<&Policy>,
     hints: &VersionHints,
 ) -> Result<Vec<&'a Version>> {
+    if let Some(why) = policy.and_then(|policy| policy.excludes_package(package)) {
+        debug!("skipping all of {}: {why}", package.as_given());
+        return Ok(Vec::new());
+    }
     let artifacts = db.available_artifacts(package)?;
+    let now = std::time::SystemTime::now();
     let mut versions = Vec::new();
-    let all_pre = artifacts.iter().all(|(version, _)| version.is_prerelease());
-    let allow_prerelease = all_pre || brief.allow_pre.allow_pre_for(package);
-    let (version_hint, hash_hints) = match hints.0.get(&package) {
+    let allow_prerelease = allows_prerelease(brief, package, artifacts);
+    let (version_hint, hash_hints) = match hints.pins.get(&package) {
         Some((version, hash)) => (Some(version), Some(hash)),
         None => (None, None),
     };
+    // we'll have to build these from source, so a release that only has wheels is no
+    // good to us
+    let sdist_only = db.no_binary(package);
 
     for (version, ais) in artifacts.iter() {
         if !allow_prerelease && version.is_prerelease() {
             continue;
         }
+        if !satisfies_constraints(brief, package, version)? {
+            continue;
+        }
+        if let Some(policy) = policy {
+            if let Some(why) = policy.excludes_release(package, version)? {
+                debug!("skipping {} {version}: {why}", package.as_given());
+                continue;
+            }
+        }
         for ai in ais {
+            if sdist_only && ai.name.inner_as::<SdistName>().is_none() {
+                continue;
+            }
             if ai.yanked.yanked {
-                let is_pinned = match (&hash_hints, &ai.hash) {
-                    (Some(hints), Some(hash)) => hints.contains(&hash),
-                    _ => false,
+                let is_pinned = match &hash_hints {
+                    Some(hints) => ai.hashes.iter().any(|h| hints.contains(h)),
+                    None => false,
                 };
                 if !is_pinned {
                     continue;
                 }
             }
-            if let (Some(python_version), Some(requires_python)) =
-                (python_version, &ai.requires_python)
+            if let (Some(python), Some(requires_python)) = (python, &ai.requires_python)
             {
-                let requires_python: Specifiers = requires_python.parse()?;
-                if !requires_python.satisfied_by(python_version)? {
+                if !python.allows(requires_python)? {
                     continue;
                 }
             }
+            if let Some(why) =
+                policy.and_then(|policy| policy.excludes_artifact(ai, now))
+            {
+                debug!("skipping {}: {why}", ai.name);
+                continue;
+            }
             // we found a valid artifact for this version. So this version is valid, and
             // we can save it and move on to the next.
             versions.push(version);
@@ -444,22 +1617,48 @@ impl<'a> PubgrubState<'a> {
         release: &(PackageName, Version),
     ) -> Result<&WheelResolveMetadataInner> {
         Ok(&get_or_fill(&self.expected_metadata, release, || {
+            if let Some(metadata) =
+                self.version_hints.metadata_for(&release.0, &release.1)
+            {
+                return Ok(Box::new(metadata.clone()));
+            }
             let ais = self.db.artifacts_for_version(&release.0, &release.1)?;
-            let (ai, wheel_metadata) = self
+            let metadata = self
                 .db
-                .get_metadata::<Wheel, _>(ais, Some(self.wheel_builder))?;
-            Ok(Box::new(WheelResolveMetadata::from(ai, &wheel_metadata)))
+                .get_resolve_metadata(ais, Some(self.wheel_builder))?;
+            Ok(Box::new(metadata))
         })?
         .inner)
     }
 
+    /// Why the policy rules out a release because of its license, if it does. That
+    /// takes the full METADATA, which we otherwise wouldn't need, so we only look when
+    /// the policy cares.
+    fn excluded_by_license(
+        &self,
+        release: &(PackageName, Version),
+    ) -> Result<Option<String>> {
+        let policy = self.db.policy();
+        if !policy.checks_licenses() {
+            return Ok(None);
+        }
+        let license = get_or_fill(&self.licenses, release, || {
+            let ais = self.db.artifacts_for_version(&release.0, &release.1)?;
+            Ok(Box::new(
+                self.db.get_license(ais, Some(self.wheel_builder))?,
+            ))
+        })?;
+        Ok(policy.excludes_license(license.as_deref()))
+    }
+
     fn versions(&self, package: &PackageName) -> Result<&[&Version]> {
         get_or_fill(&self.versions, package, || {
             fetch_and_sort_versions(
                 self.db,
                 self.brief,
                 package,
-                Some(&self.python_full_version),
+                Some(&self.python),
+                Some(self.db.policy()),
                 self.version_hints,
             )
         })
@@ -483,19 +1682,24 @@ fn resolve_wheels(
         version_hints,
         wheel_builder,
         marker_exprs: Default::default(),
-        python_full_version: env
-            .get("python_full_version")
-            .ok_or(eyre!(
-                "Missing 'python_full_version' environment marker variable"
-            ))?
-            .parse()?,
+        marker_values: Default::default(),
+        python: PythonFilter::new(
+            &env.get("python_full_version")
+                .ok_or(eyre!(
+                    "Missing 'python_full_version' environment marker variable"
+                ))?
+                .parse()?,
+        ),
         expected_metadata: Default::default(),
         versions: Default::default(),
+        licenses: Default::default(),
+        error: Default::default(),
     };
 
-    // XX this error reporting is terrible. It's a hack to work around PubGrubError not
-    // being convertible to eyre::Report, because eyre::Report requires Send.
-    let result = pubgrub::solver::resolve(&state, ResPkg::Root, ROOT_VERSION.clone());
+    let result = {
+        timing!("solver");
+        pubgrub::solver::resolve(&state, ResPkg::Root, ROOT_VERSION.clone())
+    };
 
     use pubgrub::error::PubGrubError::*;
 
@@ -517,18 +1721,13 @@ fn resolve_wheels(
                 package,
                 version,
                 source,
-            } => {
-                context!("fetching dependencies of {} v{}", package, version);
-                eyre!("{}", source)
-            }
-            ErrorChoosingPackageVersion(boxed_err) => {
-                // TODO: this suuuucks... the dyn Error here is really an
-                // eyre::Report. But pubgrub-rs erases the type, and eyre can't
-                // wrap a plain dyn Error (it needs + Send + Sync as well), so
-                // we have no choice except to stringify.
-                eyre!("Error while choosing next package version to examine:\n{boxed_err:?}")
-            }
-            ErrorInShouldCancel(boxed_err) => eyre!("{}", boxed_err.to_string()),
+            } => state
+                .take_error(source)
+                .wrap_err(format!("fetching dependencies of {package} v{version}")),
+            ErrorChoosingPackageVersion(source) => state
+                .take_error(source)
+                .wrap_err("choosing the next package version to examine"),
+            ErrorInShouldCancel(source) => state.take_error(source),
             Failure(s) => eyre!("{}", s),
             // XX Maybe the empty-range and self-dependency cases should be filtered out
             // inside our code, for robustness?
@@ -536,52 +1735,179 @@ fn resolve_wheels(
                 package,
                 version,
                 dependent,
-            } => eyre!(
-                "{} v{}'s dependency on {} has self-contradictory version ranges",
-                package,
+            } => ResolveError::EmptyDependencyRange {
+                package: package.to_string(),
+                version,
+                dependency: dependent.to_string(),
+            }
+            .into(),
+            SelfDependency { package, version } => ResolveError::SelfDependency {
+                package: package.to_string(),
                 version,
-                dependent
-            ),
-            SelfDependency { package, version } => {
-                eyre!("{} v{} depends on itself", package, version)
             }
+            .into(),
 
             NoSolution(mut derivation_tree) => {
                 fn dump_tree(tree: &DerivationTree<ResPkg, Version>, depth: usize) {
                     let indent = "   ".repeat(depth);
                     match tree {
                         DerivationTree::External(inner) => {
-                            println!("{}external: {}", indent, inner);
+                            trace!("{}external: {}", indent, inner);
                         }
                         DerivationTree::Derived(inner) => {
-                            println!("{}derived (id={:?})", indent, inner.shared_id);
+                            trace!("{}derived (id={:?})", indent, inner.shared_id);
                             for (pkg, term) in inner.terms.iter() {
-                                println!("{}  {} -> {}", indent, pkg, term);
+                                trace!("{}  {} -> {}", indent, pkg, term);
                             }
-                            println!("{}cause 1:", indent);
+                            trace!("{}cause 1:", indent);
                             dump_tree(&inner.cause1, depth + 1);
-                            println!("{}cause 2:", indent);
+                            trace!("{}cause 2:", indent);
                             dump_tree(&inner.cause2, depth + 1);
                         }
                     }
                 }
 
-                println!("\n-------- derivation tree --------");
+                let mut hints = missing_package_hints(db, &derivation_tree);
+                hints.extend(policy_hints(db, &derivation_tree, &state.licenses));
+                trace!("\n-------- derivation tree --------");
                 //println!("{:?}", derivation_tree);
                 dump_tree(&derivation_tree, 0);
                 derivation_tree.collapse_no_versions();
-                println!("\n-------- derivation tree (collapsed) --------");
+                trace!("\n-------- derivation tree (collapsed) --------");
                 //println!("{:?}", derivation_tree);
                 dump_tree(&derivation_tree, 0);
-                eyre!(
-                    "{}",
-                    pubgrub::report::DefaultStringReporter::report(&derivation_tree)
-                )
+                ResolveError::NoSolution {
+                    explanation: pubgrub::report::DefaultStringReporter::report(
+                        &derivation_tree,
+                    ),
+                    hints,
+                }
+                .into()
             }
         }),
     }
 }
 
+/// The packages that resolution failed on because none of their versions would do.
+fn no_versions_packages(tree: &DerivationTree<ResPkg, Version>) -> Vec<&PackageName> {
+    fn collect<'a>(
+        tree: &'a DerivationTree<ResPkg, Version>,
+        out: &mut Vec<&'a PackageName>,
+    ) {
+        match tree {
+            DerivationTree::External(External::NoVersions(
+                ResPkg::Package(name, _),
+                _,
+            )) => {
+                if !out.contains(&name) {
+                    out.push(name);
+                }
+            }
+            DerivationTree::External(_) => (),
+            DerivationTree::Derived(inner) => {
+                collect(&inner.cause1, out);
+                collect(&inner.cause2, out);
+            }
+        }
+    }
+    let mut names = Vec::new();
+    collect(tree, &mut names);
+    names
+}
+
+/// If resolution failed because something doesn't exist on the index at all, then it's
+/// probably a typo, and it's more useful to say so than to explain the derivation.
+fn missing_package_hints(
+    db: &PackageDB,
+    tree: &DerivationTree<ResPkg, Version>,
+) -> Vec<String> {
+    let mut hints = Vec::new();
+    for name in no_versions_packages(tree) {
+        match db.available_artifacts(name) {
+            Ok(artifacts) if artifacts.is_empty() => (),
+            _ => continue,
+        }
+        let mut hint = format!(
+            "there's no package called '{}' on the index",
+            name.as_given()
+        );
+        let similar = db.similar_names(name);
+        if !similar.is_empty() {
+            let quoted: Vec<String> =
+                similar.iter().map(|s| format!("'{s}'")).collect();
+            hint.push_str(&format!("; did you mean {}?", quoted.join(" or ")));
+        }
+        hints.push(hint);
+    }
+    hints
+}
+
+/// If resolution failed because the policy ruled out versions that would otherwise
+/// have worked, then pubgrub just sees that there aren't any, so we explain why.
+/// `licenses` are the ones we looked up while resolving.
+fn policy_hints(
+    db: &PackageDB,
+    tree: &DerivationTree<ResPkg, Version>,
+    licenses: &FrozenMap<(PackageName, Version), Box<Option<String>>>,
+) -> Vec<String> {
+    let policy = db.policy();
+    if policy.is_empty() {
+        return Vec::new();
+    }
+    let now = std::time::SystemTime::now();
+    let mut hints = Vec::new();
+    for name in no_versions_packages(tree) {
+        if let Some(why) = policy.excludes_package(name) {
+            hints.push(format!("policy: {why}"));
+            continue;
+        }
+        let Ok(artifacts) = db.available_artifacts(name) else {
+            continue;
+        };
+        // reason -> versions it ruled out, in the order we first saw each reason
+        let mut excluded: Vec<(String, Vec<&Version>)> = Vec::new();
+        for (version, ais) in artifacts {
+            let why = match policy.excludes_release(name, version) {
+                Ok(Some(why)) => why,
+                Ok(None) => {
+                    let license = licenses.get(&(name.clone(), version.clone()));
+                    if let Some(why) =
+                        license.and_then(|l| policy.excludes_license(l.as_deref()))
+                    {
+                        why
+                    } else {
+                        let mut whys =
+                            ais.iter().map(|ai| policy.excludes_artifact(ai, now));
+                        match whys.next().flatten() {
+                            Some(why) if whys.all(|w| w.is_some()) => why,
+                            _ => continue,
+                        }
+                    }
+                }
+                Err(_) => continue,
+            };
+            match excluded.iter_mut().find(|(w, _)| *w == why) {
+                Some((_, versions)) => versions.push(version),
+                None => excluded.push((why, vec![version])),
+            }
+        }
+        for (why, mut versions) in excluded {
+            versions.sort_unstable();
+            let which = if versions.len() <= 3 {
+                versions
+                    .iter()
+                    .map(|v| format!("{} {v}", name.as_given()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            } else {
+                format!("{} versions of {}", versions.len(), name.as_given())
+            };
+            hints.push(format!("policy excludes {which}: {why}"));
+        }
+    }
+    hints
+}
+
 struct ExtraEnv<'a> {
     extra: Option<&'a str>,
 }
@@ -612,6 +1938,163 @@ impl Simplified {
     }
 }
 
+/// Splits `graph` into groups of nodes that can all reach each other, using Tarjan's
+/// algorithm. A node that isn't in any cycle is a group on its own.
+fn strongly_connected<'a>(
+    graph: &BTreeMap<&'a PackageName, Vec<&'a PackageName>>,
+) -> Vec<Vec<&'a PackageName>> {
+    struct Tarjan<'g, 'a> {
+        graph: &'g BTreeMap<&'a PackageName, Vec<&'a PackageName>>,
+        index: HashMap<&'a PackageName, usize>,
+        lowlink: HashMap<&'a PackageName, usize>,
+        stack: Vec<&'a PackageName>,
+        on_stack: HashSet<&'a PackageName>,
+        groups: Vec<Vec<&'a PackageName>>,
+    }
+
+    impl<'g, 'a> Tarjan<'g, 'a> {
+        fn visit(&mut self, node: &'a PackageName) {
+            let index = self.index.len();
+            self.index.insert(node, index);
+            self.lowlink.insert(node, index);
+            self.stack.push(node);
+            self.on_stack.insert(node);
+            let graph = self.graph;
+            for &next in graph.get(node).into_iter().flatten() {
+                let reachable = if !self.index.contains_key(next) {
+                    self.visit(next);
+                    self.lowlink[next]
+                } else if self.on_stack.contains(next) {
+                    self.index[next]
+                } else {
+                    continue;
+                };
+                let lowlink = self.lowlink[node].min(reachable);
+                self.lowlink.insert(node, lowlink);
+            }
+            if self.lowlink[node] == index {
+                let mut group = Vec::new();
+                loop {
+                    let member = self.stack.pop().unwrap();
+                    self.on_stack.remove(member);
+                    group.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                self.groups.push(group);
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        graph,
+        index: HashMap::new(),
+        lowlink: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: HashSet::new(),
+        groups: Vec::new(),
+    };
+    for &node in graph.keys() {
+        if !tarjan.index.contains_key(node) {
+            tarjan.visit(node);
+        }
+    }
+    tarjan.groups
+}
+
+/// Works out what a set of local packages -- the project, plus any workspace members --
+/// need from the index.
+///
+/// `requirements` are the environment's top-level requirements. Any requirement, in
+/// there or in the local packages' own metadata, that names one of the local packages
+/// is satisfied by the local copy (after checking its version), and pulls in that
+/// package's dependencies for whichever extras were asked for. Whatever's left over is
+/// what we actually have to resolve.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn external_requirements(
+    requirements: &[UserRequirement],
+    local: &HashMap<PackageName, WheelCoreMetadata>,
+) -> Result<Vec<UserRequirement>> {
+    // (package, extra) pairs whose dependencies we still have to look at. Every local
+    // package gets installed, so they all start out here.
+    let mut todo: Vec<(&PackageName, Option<&Extra>)> =
+        local.keys().map(|name| (name, None)).collect();
+    let mut seen = HashSet::new();
+    let mut external = Vec::new();
+
+    for req in requirements {
+        if !wants_local(req, local, &mut todo)? {
+            external.push(req.clone());
+        }
+    }
+    while let Some(item) = todo.pop() {
+        if !seen.insert(item) {
+            continue;
+        }
+        let (name, extra) = item;
+        let metadata = &local[name];
+        if let Some(extra) = extra {
+            if !metadata.extras.contains(extra) {
+                bail!(
+                    "package {} has no extra [{}]",
+                    name.as_given(),
+                    extra.as_given()
+                );
+            }
+        }
+        let extra_str = extra.map(|e| e.normalized());
+        for req in &metadata.requires_dist {
+            let env_marker_expr = match &req.env_marker_expr {
+                Some(expr) => match simplify_out_extra(expr, extra_str)? {
+                    Simplified::True => None,
+                    Simplified::False => continue,
+                    Simplified::Expr(expr) => Some(expr),
+                },
+                None => None,
+            };
+            if wants_local(req, local, &mut todo)? {
+                continue;
+            }
+            let req: UserRequirement = Requirement {
+                env_marker_expr,
+                ..(**req).clone()
+            }
+            .to_string()
+            .try_into()?;
+            // every extra's pass sees the package's unconditional requirements again
+            if !external.contains(&req) {
+                external.push(req);
+            }
+        }
+    }
+    Ok(external)
+}
+
+/// If `req` refers to one of the local packages, checks that the local version fits
+/// and queues up whatever it needs.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+fn wants_local<'a>(
+    req: &'a Requirement,
+    local: &'a HashMap<PackageName, WheelCoreMetadata>,
+    todo: &mut Vec<(&'a PackageName, Option<&'a Extra>)>,
+) -> Result<bool> {
+    let Some((name, metadata)) = local.get_key_value(&req.name) else {
+        return Ok(false);
+    };
+    if !req.specifiers.satisfied_by(&metadata.version)? {
+        bail!(
+            "local package {} is version {}, which doesn't satisfy '{}'",
+            name.as_given(),
+            metadata.version,
+            req
+        );
+    }
+    todo.push((name, None));
+    todo.extend(req.extras.iter().map(|extra| (name, Some(extra))));
+    Ok(true)
+}
+
 fn simplify_out_extra(
     expr: &marker::EnvMarkerExpr,
     extra: Option<&str>,
@@ -707,6 +2190,49 @@ impl Display for ResPkg {
 }
 
 impl<'a> PubgrubState<'a> {
+    /// Whether `req` applies, given its environment marker (if any) and which `extra`
+    /// we're working out the requirements for. The same few markers turn up on
+    /// requirement after requirement, so each answer gets remembered.
+    fn marker_allows(&self, req: &Requirement, extra: Option<&Extra>) -> Result<bool> {
+        let Some(expr) = &req.env_marker_expr else {
+            return Ok(true);
+        };
+        let extra = extra.cloned();
+        if let Some(value) = self
+            .marker_values
+            .borrow()
+            .get(&extra)
+            .and_then(|values| values.get(expr))
+        {
+            return Ok(*value);
+        }
+        let simplified =
+            simplify_out_extra(expr, extra.as_ref().map(|e| e.normalized()))?;
+        let value = simplified.eval(self.env).wrap_err_with(|| {
+            let missing = missing_marker_variables(self.env);
+            if missing.is_empty() {
+                format!("can't evaluate requirement '{req}'")
+            } else {
+                format!(
+                    "can't evaluate requirement '{req}': neither the Python's metadata \
+                     nor its platform tell us {}",
+                    missing.join(", ")
+                )
+            }
+        })?;
+        if let Simplified::Expr(expr) = simplified {
+            self.marker_exprs
+                .borrow_mut()
+                .insert(StandaloneMarkerExpr(expr), value);
+        }
+        self.marker_values
+            .borrow_mut()
+            .entry(extra)
+            .or_default()
+            .insert(expr.clone(), value);
+        Ok(value)
+    }
+
     fn requirements_to_pubgrub<'r, R, I>(
         &self,
         reqs: I,
@@ -718,17 +2244,15 @@ impl<'a> PubgrubState<'a> {
         I: Iterator<Item = &'r R>,
     {
         for req in reqs {
-            if let Some(expr) = &req.env_marker_expr {
-                let simplified =
-                    simplify_out_extra(expr, extra.map(|e| e.normalized()))?;
-                let value = simplified.eval(self.env)?;
-                if let Simplified::Expr(expr) = simplified {
-                    self.marker_exprs
-                        .borrow_mut()
-                        .insert(StandaloneMarkerExpr(expr), value);
-                }
-                if !value {
-                    continue;
+            if !self.marker_allows(req, extra)? {
+                continue;
+            }
+            if let Some(url) = &req.url {
+                // Brief::resolve registers the user's own direct references up front;
+                // anything else would mean letting some package pull in arbitrary urls
+                if self.db.direct_reference(&req.name) != Some(url) {
+                    let req: &Requirement = req;
+                    bail!("can't use '{req}': only your own requirements can use urls");
                 }
             }
 
@@ -738,15 +2262,48 @@ impl<'a> PubgrubState<'a> {
                 maybe_extras.push(None);
             }
 
+            let range = self.requirement_range(req)?;
             for maybe_extra in maybe_extras {
                 let pkg = ResPkg::Package(req.name.clone(), maybe_extra);
-                let range = specifiers_to_pubgrub(&req.specifiers)?;
                 trace!("adding dependency: {} {}", pkg, range);
-                dc.insert(pkg, range);
+                dc.insert(pkg, range.clone());
             }
         }
         Ok(())
     }
+
+    /// The versions of `req.name` that `req` allows. The index only has one spelling
+    /// of each version, so if an `===` wants a different one, that version's out.
+    fn requirement_range(&self, req: &Requirement) -> Result<Range<Version>> {
+        let mut range = specifiers_to_pubgrub(&req.specifiers)?;
+        let specs = &req.specifiers.0;
+        let arbitrary = specs
+            .iter()
+            .any(|spec| spec.op == CompareOp::ArbitraryEqual);
+        if arbitrary {
+            for version in self.db.available_artifacts(&req.name)?.keys() {
+                if range.contains(version)
+                    && !spelled_as_required(&req.specifiers, version)?
+                {
+                    let wrong = Range::exact(version.clone()).negate();
+                    range = range.intersection(&wrong);
+                }
+            }
+        }
+        Ok(range)
+    }
+}
+
+/// `===` compares strings, but ranges can only compare versions, and 1.0 is the same
+/// version as 1.0.0. So a version in `specifiers_to_pubgrub(specs)` can still be
+/// spelled wrong for one of its `===`s.
+fn spelled_as_required(specs: &Specifiers, version: &Version) -> Result<bool> {
+    for spec in &specs.0 {
+        if spec.op == CompareOp::ArbitraryEqual && !spec.satisfied_by(version)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
 fn specifiers_to_pubgrub(specs: &Specifiers) -> Result<Range<Version>> {
@@ -767,11 +2324,31 @@ fn specifiers_to_pubgrub(specs: &Specifiers) -> Result<Range<Version>> {
     Ok(final_range)
 }
 
-impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'a> {
-    fn choose_package_version<T, U>(
+impl<'a> PubgrubState<'a> {
+    /// Saves `err` for `take_error`, and gives pubgrub something to pass back.
+    fn stash_error<T>(
+        &self,
+        result: Result<T>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        result.map_err(|err| {
+            let message = format!("{err:#}");
+            *self.error.borrow_mut() = Some(err);
+            message.into()
+        })
+    }
+
+    /// The error pubgrub handed back, as we originally made it.
+    fn take_error(&self, fallback: Box<dyn std::error::Error>) -> eyre::Report {
+        self.error
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(|| eyre!("{fallback}"))
+    }
+
+    fn pick_version<T, U>(
         &self,
         mut potential_packages: impl Iterator<Item = (T, U)>,
-    ) -> Result<(T, Option<Version>), Box<dyn std::error::Error>>
+    ) -> Result<(T, Option<Version>)>
     where
         T: Borrow<ResPkg>,
         U: Borrow<Range<Version>>,
@@ -798,11 +2375,13 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
                         continue;
                     }
 
-                    let metadata = self.metadata(&(name.clone(), version.clone()))?;
-                    if !metadata
-                        .requires_python
-                        .satisfied_by(&self.python_full_version)?
-                    {
+                    let release = (name.clone(), version.clone());
+                    if let Some(why) = self.excluded_by_license(&release)? {
+                        debug!("skipping {} {version}: {why}", name.as_given());
+                        continue;
+                    }
+                    let metadata = self.metadata(&release)?;
+                    if !self.python.satisfies(&metadata.requires_python)? {
                         Err(eyre!(
                             "{} {}: bad requires-python, but pypi didn't tell us!",
                             name.as_given(),
@@ -810,6 +2389,11 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
                         ))?;
                     }
                     trace!("<---- decision: {} {}", respkg.borrow(), version);
+                    progress!(
+                        "resolve-decision",
+                        package = %respkg.borrow(),
+                        version = %version
+                    );
                     return Ok((respkg, Some(version.clone())));
                 }
 
@@ -817,19 +2401,17 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
                     "<---- decision: no versions of {} in range",
                     respkg.borrow()
                 );
+                progress!("resolve-no-versions", package = %respkg.borrow());
                 Ok((respkg, None))
             }
         }
     }
 
-    fn get_dependencies(
+    fn dependencies_of(
         &self,
         pkg: &ResPkg,
         version: &Version,
-    ) -> Result<
-        pubgrub::solver::Dependencies<ResPkg, Version>,
-        Box<dyn std::error::Error>,
-    > {
+    ) -> Result<pubgrub::solver::Dependencies<ResPkg, Version>> {
         trace!("----> pubgrub called get_dependencies {} v{}", pkg, version);
 
         match pkg {
@@ -854,6 +2436,16 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
                     &mut dc,
                     extra.as_ref(),
                 )?;
+                // pubgrub is going to ask about most of these soon, one at a time, so
+                // look them all up together now. Errors can wait until it does ask.
+                let deps: Vec<&PackageName> = dc
+                    .keys()
+                    .filter_map(|dep| match dep {
+                        ResPkg::Package(name, _) => Some(name),
+                        ResPkg::Root => None,
+                    })
+                    .collect();
+                self.db.available_artifacts_many(&deps);
 
                 if let Some(inner) = extra {
                     if !metadata.extras.contains(inner) {
@@ -876,6 +2468,30 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
     }
 }
 
+impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'a> {
+    fn choose_package_version<T, U>(
+        &self,
+        potential_packages: impl Iterator<Item = (T, U)>,
+    ) -> Result<(T, Option<Version>), Box<dyn std::error::Error>>
+    where
+        T: Borrow<ResPkg>,
+        U: Borrow<Range<Version>>,
+    {
+        self.stash_error(self.pick_version(potential_packages))
+    }
+
+    fn get_dependencies(
+        &self,
+        pkg: &ResPkg,
+        version: &Version,
+    ) -> Result<
+        pubgrub::solver::Dependencies<ResPkg, Version>,
+        Box<dyn std::error::Error>,
+    > {
+        self.stash_error(self.dependencies_of(pkg, version))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -935,4 +2551,816 @@ mod test {
             simplify_out_extra(req.env_marker_expr.as_ref().unwrap(), None).is_err()
         );
     }
+
+    #[test]
+    fn test_python_filter() -> Result<()> {
+        let python = PythonFilter::new(&"3.8.10".try_into()?);
+        assert!(python.allows(">= 3.7")?);
+        assert!(!python.allows(">= 3.9")?);
+        assert!(python.allows(">= 3.7")?);
+        assert_eq!(python.allowed.borrow().len(), 2);
+        assert!(python.allows("not a specifier").is_err());
+
+        // release candidates count as the release they're for
+        let python = PythonFilter::new(&"3.14.0rc1".try_into()?);
+        assert!(python.allows(">= 3.14")?);
+        assert!(python.allows(">= 3.8, < 3.15")?);
+        assert!(!python.allows("< 3.14")?);
+        assert!(python.satisfies(&"== 3.14.*".try_into()?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prerelease_python() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.13.1", "manylinux_2_17_x86_64")?;
+        index.add_pybi("cpython_unofficial", "3.14.0rc1", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &[])?;
+        index.add_wheel("foo", "2.0", &["bar; python_version >= '3.14'"])?;
+        index
+            .add(
+                "bar-1.0-py3-none-any.whl",
+                b"Metadata-Version: 2.1\nName: bar\nVersion: 1.0\n\
+                  Requires-Python: >= 3.14\n",
+            )?
+            .requires_python = Some(">= 3.14".into());
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let platform = test_platform();
+        let brief = |allow_pre: AllowPre| -> Result<Brief> {
+            Ok(Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: vec!["foo".try_into()?],
+                allow_pre,
+                constraints: Vec::new(),
+                sources: Default::default(),
+            })
+        };
+
+        let blueprint =
+            brief(Default::default())?.resolve(&db, &[&platform], None, &[])?;
+        assert_eq!(blueprint.pybi.version.to_string(), "3.13.1");
+        assert_eq!(blueprint.wheels.len(), 1);
+
+        let allow_pre =
+            AllowPre::Some(HashSet::from(["cpython_unofficial".try_into()?]));
+        let blueprint = brief(allow_pre)?.resolve(&db, &[&platform], None, &[])?;
+        assert_eq!(blueprint.pybi.version.to_string(), "3.14.0rc1");
+        let mut pins: Vec<String> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+            .collect();
+        pins.sort();
+        assert_eq!(pins, vec!["bar 1.0", "foo 2.0"]);
+
+        let roundtripped: Blueprint =
+            serde_json::from_str(&serde_json::to_string(&blueprint)?)?;
+        assert_eq!(roundtripped.pybi, blueprint.pybi);
+        assert_eq!(
+            roundtripped.marker_expressions,
+            blueprint.marker_expressions
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_hints_metadata() -> Result<()> {
+        let pin = |name: &str, version: &str, url: Option<&str>| PinnedPackage {
+            name: name.try_into().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: Vec::new(),
+            url: url.map(|u| Url::parse(u).unwrap()),
+            source: None,
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: "https://example.com/foo.whl".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec!["bar >= 1".try_into()?],
+                requires_python: Specifiers(Vec::new()),
+                extras: HashSet::new(),
+            },
+        };
+        let blueprint = Blueprint {
+            pybi: pin("cpython_unofficial", "3.10.8", None),
+            wheels: vec![
+                (pin("foo", "1.0", None), metadata.clone()),
+                (
+                    pin("baz", "2.0", Some("https://example.com/baz.whl")),
+                    metadata,
+                ),
+            ],
+            requested: Vec::new(),
+            marker_expressions: HashMap::new(),
+        };
+        let hints = VersionHints::from(&blueprint);
+
+        let foo: PackageName = "Foo".try_into()?;
+        let baz: PackageName = "baz".try_into()?;
+        let found = hints.metadata_for(&foo, &"1.0".try_into()?).unwrap();
+        assert_eq!(found.inner.requires_dist.len(), 1);
+        assert!(hints.metadata_for(&foo, &"1.1".try_into()?).is_none());
+        assert!(hints.metadata_for(&baz, &"2.0".try_into()?).is_none());
+
+        let mut packages: Vec<&str> =
+            hints.packages().map(|p| p.normalized()).collect();
+        packages.sort_unstable();
+        assert_eq!(packages, vec!["baz", "cpython-unofficial", "foo"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pinned_hashes() -> Result<()> {
+        let sha256: ArtifactHash = format!("sha256={}", "ab".repeat(32)).parse()?;
+        let sha512: ArtifactHash = format!("sha512={}", "cd".repeat(64)).parse()?;
+        let pin = |hashes: Vec<ArtifactHash>| PinnedPackage {
+            name: "foo".try_into().unwrap(),
+            version: "1.0".try_into().unwrap(),
+            hashes,
+            url: None,
+            source: None,
+        };
+        let url = Url::parse(&format!(
+            "https://example.com/foo-1.0-py3-none-any.whl#{sha256}"
+        ))?;
+        let mut ai = ArtifactInfo::from_direct_reference(&url)?;
+        ai.hashes.push(sha512.clone());
+
+        // only the weak hash is pinned, and the index added a stronger one
+        let weak = pin(vec![sha256.clone()]);
+        assert!(weak.covers(&ai, false));
+        assert!(!weak.covers(&ai, true));
+        let strong = pin(vec![sha512.clone()]);
+        assert!(strong.covers(&ai, false));
+        assert!(strong.covers(&ai, true));
+        assert!(!pin(vec![]).covers(&ai, false));
+        // and whichever it is, that's what the download gets checked against
+        assert_eq!(weak.pinned_artifact(&ai).hash(), Some(&sha256));
+        assert_eq!(strong.pinned_artifact(&ai).hash(), Some(&sha512));
+        assert_eq!(pin(vec![]).pinned_artifact(&ai).hash(), Some(&sha512));
+
+        let metadata = WheelResolveMetadata {
+            provenance: url.to_string(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: Vec::new(),
+                requires_python: Specifiers(Vec::new()),
+                extras: HashSet::new(),
+            },
+        };
+        let blueprint = Blueprint {
+            pybi: weak,
+            wheels: vec![(strong, metadata.clone()), (pin(vec![]), metadata)],
+            requested: Vec::new(),
+            marker_expressions: HashMap::new(),
+        };
+        assert_eq!(blueprint.unhashed().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash() -> Result<()> {
+        let pin = |name: &str| PinnedPackage {
+            name: name.try_into().unwrap(),
+            version: "1.0".try_into().unwrap(),
+            hashes: Vec::new(),
+            url: None,
+            source: None,
+        };
+        let metadata = |provenance: &str| WheelResolveMetadata {
+            provenance: provenance.into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: Vec::new(),
+                requires_python: Specifiers(Vec::new()),
+                extras: HashSet::new(),
+            },
+        };
+        let blueprint = Blueprint {
+            pybi: pin("cpython_unofficial"),
+            wheels: vec![(pin("foo"), metadata("https://example.com/a.whl"))],
+            requested: Vec::new(),
+            marker_expressions: HashMap::new(),
+        };
+        // where the metadata came from doesn't change what gets installed
+        let elsewhere = Blueprint {
+            wheels: vec![(pin("foo"), metadata("https://example.com/b.whl"))],
+            ..blueprint.clone()
+        };
+        assert_eq!(blueprint.content_hash()?, elsewhere.content_hash()?);
+        let requested = Blueprint {
+            requested: vec!["foo".try_into()?],
+            ..blueprint.clone()
+        };
+        assert_ne!(blueprint.content_hash()?, requested.content_hash()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cycles() -> Result<()> {
+        let pin = |name: &str| PinnedPackage {
+            name: name.try_into().unwrap(),
+            version: "1.0".try_into().unwrap(),
+            hashes: Vec::new(),
+            url: None,
+            source: None,
+        };
+        let wheel = |name: &str, requires_dist: &[&str], extras: &[&str]| {
+            let metadata = WheelResolveMetadata {
+                provenance: format!("https://example.com/{name}.whl"),
+                inner: WheelResolveMetadataInner {
+                    requires_dist: requires_dist
+                        .iter()
+                        .map(|r| (*r).try_into().unwrap())
+                        .collect(),
+                    requires_python: Specifiers(Vec::new()),
+                    extras: extras.iter().map(|e| (*e).try_into().unwrap()).collect(),
+                },
+            };
+            (pin(name), metadata)
+        };
+        let mut blueprint = Blueprint {
+            pybi: pin("cpython_unofficial"),
+            wheels: vec![
+                wheel(
+                    "sphinx",
+                    &[
+                        "sphinxcontrib-applehelp",
+                        "jinja2",
+                        "pytest; extra == 'test'",
+                    ],
+                    &["test"],
+                ),
+                wheel("sphinxcontrib-applehelp", &["sphinx"], &[]),
+                wheel("jinja2", &["markupsafe"], &[]),
+                wheel("markupsafe", &["jinja2; sys_platform == 'win32'"], &[]),
+                wheel("pytest", &["sphinx; extra == 'docs'"], &["docs"]),
+            ],
+            requested: Vec::new(),
+            marker_expressions: HashMap::new(),
+        };
+        let names = |cycles: Vec<Vec<PackageName>>| -> Vec<Vec<String>> {
+            cycles
+                .iter()
+                .map(|group| group.iter().map(|n| n.normalized().to_owned()).collect())
+                .collect()
+        };
+
+        // we never evaluated the win32 marker, so it might have applied
+        let graph = blueprint.dependency_graph()?;
+        assert_eq!(graph.len(), 5);
+        assert_eq!(
+            names(blueprint.cycles()?),
+            vec![
+                vec!["jinja2", "markupsafe"],
+                vec!["pytest", "sphinx", "sphinxcontrib-applehelp"],
+            ]
+        );
+
+        blueprint.marker_expressions.insert(
+            StandaloneMarkerExpr::try_from("sys_platform == 'win32'")?,
+            false,
+        );
+        assert_eq!(
+            names(blueprint.cycles()?),
+            vec![vec!["pytest", "sphinx", "sphinxcontrib-applehelp"]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_yanked_pins() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let index = |yank: bool| -> Result<MemoryIndex> {
+            let mut index = MemoryIndex::new();
+            index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+            index.add_wheel("foo", "1.0", &["bar"])?;
+            let bar = index.add_wheel("bar", "1.0", &[])?;
+            if yank {
+                bar.yanked.yanked = true;
+                bar.yanked.reason = Some("broken on Tuesdays".into());
+            }
+            Ok(index)
+        };
+        let test_db = TestDB::new()?;
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+        let platform = test_platform();
+
+        let db = test_db.in_memory(index(false)?)?;
+        let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+        assert!(blueprint.yanked(&db)?.is_empty());
+
+        // Later, the index yanks bar 1.0 out from under us
+        let db = test_db.in_memory(index(true)?)?;
+        let yanked = blueprint.yanked(&db)?;
+        assert_eq!(yanked.len(), 1);
+        assert_eq!(yanked[0].name.as_given(), "bar");
+        assert_eq!(yanked[0].version.to_string(), "1.0");
+        assert_eq!(yanked[0].reason.as_deref(), Some("broken on Tuesdays"));
+        assert_eq!(yanked[0].files, vec!["bar-1.0-py3-none-any.whl"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_versions() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("torch", "2.0.1+cpu", &[])?;
+        index.add_wheel("torch", "2.1.0", &[])?;
+        index.add_wheel("torch", "2.1.0+cpu", &[])?;
+        index.add_wheel("torch", "2.1.0+cu121", &["nvidia-cudnn >= 8"])?;
+        index.add_wheel("nvidia-cudnn", "8.9", &[])?;
+        index.add_wheel("vision", "1.0", &["torch == 2.1.0"])?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let platform = test_platform();
+
+        let resolve = |requirements: &[&str], constraints: &[&str]| {
+            let brief = Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: requirements
+                    .iter()
+                    .map(|r| (*r).try_into())
+                    .collect::<Result<_>>()?,
+                allow_pre: Default::default(),
+                constraints: constraints
+                    .iter()
+                    .map(|c| (*c).try_into())
+                    .collect::<Result<_>>()?,
+                sources: Default::default(),
+            };
+            let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+            // make sure the labels survive a trip through posy.lock, too
+            let blueprint: Blueprint =
+                serde_json::from_str(&serde_json::to_string(&blueprint)?)?;
+            let mut pins: Vec<String> = blueprint
+                .wheels
+                .iter()
+                .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+                .collect();
+            pins.sort_unstable();
+            Ok::<_, eyre::Report>(pins)
+        };
+        assert_eq!(
+            resolve(&["torch == 2.1.0+cpu"], &[])?,
+            vec!["torch 2.1.0+cpu"]
+        );
+        assert_eq!(resolve(&["torch < 2.1"], &[])?, vec!["torch 2.0.1+cpu"]);
+        // == without a label matches all of them, and the labels sort after it
+        assert_eq!(
+            resolve(&["vision"], &[])?,
+            vec!["nvidia-cudnn 8.9", "torch 2.1.0+cu121", "vision 1.0"]
+        );
+        // ruling out the cuda build leaves the others alone
+        assert_eq!(
+            resolve(&["vision"], &["nvidia-cudnn < 8"])?,
+            vec!["torch 2.1.0+cpu", "vision 1.0"]
+        );
+        assert_eq!(
+            resolve(&["vision"], &["torch != 2.1.0+cpu", "nvidia-cudnn < 8"])?,
+            vec!["torch 2.1.0", "vision 1.0"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_binary() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("tool", "1.0", &[])?;
+        index.add(
+            "tool-1.0.tar.gz",
+            b"Metadata-Version: 2.1\nName: tool\nVersion: 1.0\n",
+        )?;
+        index.add_wheel("tool", "2.0", &[])?;
+        let test_db = TestDB::new()?;
+        let mut db = test_db.in_memory(index)?;
+        let platform = test_platform();
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["tool".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Default::default(),
+            sources: Default::default(),
+        };
+        let version = |db: &PackageDB| -> Result<Version> {
+            let blueprint = brief.resolve(db, &[&platform], None, &[])?;
+            Ok(blueprint.wheels[0].0.version.clone())
+        };
+
+        assert_eq!(version(&db)?, "2.0".try_into()?);
+        // 2.0 only has a wheel, so there'd be nothing to build
+        db.set_no_binary(vec!["tool".try_into()?]);
+        assert_eq!(version(&db)?, "1.0".try_into()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_source() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+        use zip::write::FileOptions;
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        // the index has a newer one, but the source says where it comes from
+        index.add_wheel("vendored", "2.0", &[])?;
+        index.add_wheel("dep", "1.0", &[])?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+
+        let path = test_db.path().join("vendored-1.0-py3-none-any.whl");
+        let mut w = zip::ZipWriter::new(std::fs::File::create(&path)?);
+        for (name, contents) in [
+            (
+                "vendored-1.0.dist-info/METADATA",
+                "Metadata-Version: 2.1\nName: vendored\nVersion: 1.0\n\
+                 Requires-Dist: dep\n",
+            ),
+            (
+                "vendored-1.0.dist-info/WHEEL",
+                "Wheel-Version: 1.0\nRoot-Is-Purelib: true\n",
+            ),
+            ("vendored-1.0.dist-info/RECORD", ""),
+        ] {
+            w.start_file(name, FileOptions::default())?;
+            w.write_all(contents.as_bytes())?;
+        }
+        w.finish()?;
+
+        let source = RequirementSource::Path { path };
+        assert!(!source.is_tree());
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["vendored".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: [("vendored".try_into()?, source.clone())].into(),
+        };
+        let blueprint = brief.resolve(&db, &[&test_platform()], None, &[])?;
+        let mut pins: Vec<(String, Option<RequirementSource>)> = blueprint
+            .wheels
+            .iter()
+            .map(|(pin, _)| {
+                (
+                    format!("{} {}", pin.name.as_given(), pin.version),
+                    pin.source.clone(),
+                )
+            })
+            .collect();
+        pins.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            pins,
+            vec![
+                ("dep 1.0".to_string(), None),
+                ("vendored 1.0".to_string(), Some(source)),
+            ]
+        );
+
+        // the source only counts for that resolve, so the same PackageDB can still
+        // get it from the index for something else
+        let plain = Brief {
+            sources: Default::default(),
+            ..brief
+        };
+        let blueprint = plain.resolve(&db, &[&test_platform()], None, &[])?;
+        let newer: Version = "2.0".try_into()?;
+        assert!(blueprint
+            .wheels
+            .iter()
+            .any(|(pin, _)| pin.name == plain.requirements[0].name
+                && pin.version == newer
+                && pin.source.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_all() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("black", "24.4", &["click >= 8"])?;
+        index.add_wheel("flake8", "7.0", &["pyflakes"])?;
+        index.add_wheel("click", "8.1", &[])?;
+        index.add_wheel("pyflakes", "3.2", &[])?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let brief = |requirement: &str| -> Result<Brief> {
+            Ok(Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: vec![requirement.try_into()?],
+                allow_pre: Default::default(),
+                constraints: Vec::new(),
+                sources: Default::default(),
+            })
+        };
+        let briefs = [brief("black")?, brief("missing")?, brief("flake8")?];
+        let platform = test_platform();
+        let results =
+            Brief::resolve_all(&db, &briefs.iter().collect::<Vec<_>>(), &[&platform]);
+
+        assert_eq!(results.len(), 3);
+        let pins = |result: &Result<Blueprint, Error>| {
+            let mut pins: Vec<String> = result
+                .as_ref()
+                .unwrap()
+                .wheels
+                .iter()
+                .map(|(pin, _)| format!("{} {}", pin.name.as_given(), pin.version))
+                .collect();
+            pins.sort_unstable();
+            pins
+        };
+        assert_eq!(pins(&results[0]), vec!["black 24.4", "click 8.1"]);
+        // one failing doesn't stop the rest
+        assert!(results[1].is_err());
+        assert_eq!(pins(&results[2]), vec!["flake8 7.0", "pyflakes 3.2"]);
+        // and they come out the same as resolving each one on its own
+        assert_eq!(
+            pins(&Ok(briefs[2].resolve(&db, &[&platform], None, &[])?)),
+            pins(&results[2])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_preheat() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &["old"])?;
+        index.add_wheel("foo", "2.0", &["bar", "baz; python_version < '3'"])?;
+        index.add_wheel("bar", "1.0", &[])?;
+        index.add_wheel("bar", "2.0", &[])?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+        let platform = test_platform();
+
+        // the pybi, foo 2.0, and bar 2.0; not baz, because of its marker
+        let preheated = brief.preheat(&db, &[&platform], None, 1)?;
+        assert_eq!(preheated.index_pages, 3);
+        assert_eq!(preheated.metadata, 3);
+        // foo 1.0 needs old, which isn't on the index at all
+        let preheated = brief.preheat(&db, &[&platform], None, 2)?;
+        assert_eq!(preheated.index_pages, 4);
+        assert_eq!(preheated.metadata, 5);
+
+        // walking two at once, whoever gets to a page first fetches it for both
+        let other = Brief {
+            requirements: vec!["bar".try_into()?],
+            ..brief.clone()
+        };
+        let walks = vec![
+            PreheatWalk::new(&db, &brief, &[&platform], None, 1)?,
+            PreheatWalk::new(&db, &other, &[&platform], None, 1)?,
+        ];
+        let mut pages = HashSet::from(["cpython_unofficial".try_into()?]);
+        let preheated = crate::util::block_on(preheat_together(&db, walks, &mut pages))
+            .into_iter()
+            .map(|preheated| preheated.map(|p| (p.index_pages, p.metadata)))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(preheated, vec![(1, 3), (1, 2)]);
+        assert_eq!(pages.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_divergences() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &["bar"])?;
+        index.add_wheel("bar", "1.0", &[])?;
+        index.add_wheel("baz", "1.0", &[])?;
+        // someone forgot that the Linux build needs baz too
+        index.add(
+            "foo-1.0-cp310-cp310-manylinux_2_17_x86_64.whl",
+            b"Metadata-Version: 2.1\nName: foo\nVersion: 1.0\nRequires-Dist: bar\n\
+              Requires-Dist: baz\nRequires-Python: >= 3.10\n",
+        )?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let brief = Brief {
+            python: "cpython_unofficial >= 3".try_into()?,
+            requirements: vec!["foo".try_into()?],
+            allow_pre: Default::default(),
+            constraints: Vec::new(),
+            sources: Default::default(),
+        };
+        let platform = test_platform();
+        let blueprint = brief.resolve(&db, &[&platform], None, &[])?;
+
+        let divergences = blueprint.metadata_divergences(&db)?;
+        assert_eq!(divergences.len(), 1, "{divergences:#?}");
+        let divergence = &divergences[0];
+        assert_eq!(divergence.name.as_given(), "foo");
+        assert_eq!(divergence.differences.len(), 2);
+        assert!(divergence.differences[0].ends_with("Requires-Dist: baz"));
+        assert!(divergence.differences[1].starts_with("Requires-Python is"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_error_kinds() -> Result<()> {
+        use crate::error::ErrorKind;
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &["bar >= 2"])?;
+        index.add_wheel("bar", "1.0", &[])?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let platform = test_platform();
+        let brief = |python: &str| -> Result<Brief> {
+            Ok(Brief {
+                python: python.try_into()?,
+                requirements: vec!["foo".try_into()?],
+                allow_pre: Default::default(),
+                constraints: Vec::new(),
+                sources: Default::default(),
+            })
+        };
+
+        let err = brief("cpython_unofficial >= 3")?
+            .resolve(&db, &[&platform], None, &[])
+            .unwrap_err();
+        match err.kind() {
+            ErrorKind::Resolve(ResolveError::NoSolution { explanation, .. }) => {
+                assert!(explanation.contains("bar"), "{explanation}")
+            }
+            other => panic!("expected NoSolution, got {other:?}"),
+        }
+        let err = brief("cpython_unofficial >= 4")?
+            .resolve(&db, &[&platform], None, &[])
+            .unwrap_err();
+        match err.kind() {
+            ErrorKind::Resolve(ResolveError::NoPybiFound { details, .. }) => {
+                assert_eq!(
+                    details[0],
+                    "cpython_unofficial 3.10.8 (manylinux_2_17_x86_64): excluded by \
+                     the requirement"
+                );
+                assert!(details[1].starts_with(
+                    "platform manylinux_2_17_x86_64 accepts: manylinux_2_17_x86_64, "
+                ));
+            }
+            other => panic!("expected NoPybiFound, got {other:?}"),
+        }
+        let windows = PybiPlatform::new("win_amd64");
+        let err = brief("cpython_unofficial >= 3")?
+            .resolve(&db, &[&windows], None, &[])
+            .unwrap_err();
+        let message = format!("{err:#}");
+        assert!(
+            message.contains("3.10.8 (manylinux_2_17_x86_64): not built for any"),
+            "{message}"
+        );
+        assert!(message.contains("platform win_amd64 accepts: win_amd64"));
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_pybi("cpython_unofficial", "3.12.0a1", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0", &[])?;
+        let db = test_db.in_memory(index)?;
+        let err = brief("cpython_unofficial >= 3.11")?
+            .resolve(&db, &[&platform], None, &[])
+            .unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("3.12.0a1 (manylinux_2_17_x86_64): a pre-release"));
+        assert!(message.contains("3.10.8 (manylinux_2_17_x86_64): excluded by the"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_arbitrary_equality() -> Result<()> {
+        use crate::package_db::MemoryIndex;
+        use crate::test_util::{test_platform, TestDB};
+
+        let mut index = MemoryIndex::new();
+        index.add_pybi("cpython_unofficial", "3.10.8", "manylinux_2_17_x86_64")?;
+        index.add_wheel("foo", "1.0.0", &[])?;
+        let test_db = TestDB::new()?;
+        let db = test_db.in_memory(index)?;
+        let platform = test_platform();
+        let resolve = |req: &str| -> Result<Blueprint> {
+            Ok(Brief {
+                python: "cpython_unofficial >= 3".try_into()?,
+                requirements: vec![req.try_into()?],
+                allow_pre: Default::default(),
+                constraints: Vec::new(),
+                sources: Default::default(),
+            }
+            .resolve(&db, &[&platform], None, &[])?)
+        };
+        let blueprint = resolve("foo === 1.0.0")?;
+        assert_eq!(blueprint.wheels[0].0.version.to_string(), "1.0.0");
+        // the same version, but not the same string
+        assert!(resolve("foo === 1.0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_requirements() {
+        fn metadata(name: &str, version: &str, reqs: &[&str]) -> WheelCoreMetadata {
+            WheelCoreMetadata {
+                name: name.parse().unwrap(),
+                version: version.parse().unwrap(),
+                requires_dist: reqs.iter().map(|r| r.parse().unwrap()).collect(),
+                requires_python: Specifiers(Vec::new()),
+                extras: HashSet::from(["test".parse().unwrap()]),
+                license: None,
+                license_expression: None,
+                license_files: Vec::new(),
+                dynamic: None,
+                display: Default::default(),
+            }
+        }
+        let local = HashMap::from([
+            (
+                "app".parse().unwrap(),
+                metadata("app", "1.0", &["lib >= 1", "attrs"]),
+            ),
+            (
+                "lib".parse().unwrap(),
+                metadata(
+                    "lib",
+                    "1.2",
+                    &[
+                        "attrs",
+                        "trio; python_version >= '3.8'",
+                        "pytest; extra == 'test'",
+                    ],
+                ),
+            ),
+        ]);
+        let top: Vec<UserRequirement> = vec!["lib[test]".parse().unwrap()];
+        let mut external: Vec<String> = external_requirements(&top, &local)
+            .unwrap()
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        external.sort();
+        assert_eq!(
+            external,
+            vec!["attrs", "pytest", r#"trio; python_version >= "3.8""#]
+        );
+
+        let too_new: Vec<UserRequirement> = vec!["lib >= 2".parse().unwrap()];
+        assert!(external_requirements(&too_new, &local).is_err());
+    }
+
+    #[test]
+    fn test_specifiers_to_pubgrub() {
+        use crate::test_util::{sample_specifiers, sample_versions};
+
+        let mut examples: Vec<String> = sample_specifiers();
+        // and some intersections
+        examples.push(">= 1.0, != 1.0.post1, < 2".into());
+        examples.push("~= 1.0, != 1.0.*".into());
+        examples.push("> 1.0a1, < 1.0.post1".into());
+        for example in examples {
+            let specs: Specifiers = example.as_str().try_into().unwrap();
+            let Ok(range) = specifiers_to_pubgrub(&specs) else {
+                continue;
+            };
+            for version in sample_versions() {
+                assert_eq!(
+                    range.contains(&version)
+                        && spelled_as_required(&specs, &version).unwrap(),
+                    specs.satisfied_by(&version).unwrap(),
+                    "{version} {specs}"
+                );
+            }
+        }
+    }
 }
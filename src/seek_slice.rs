@@ -1,6 +1,8 @@
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
 
+/// A window onto `start..end` of some seekable stream, that looks like a whole stream
+/// of its own: offset 0 is `start`, and reads and writes stop at `end`.
 pub struct SeekSlice<T: Seek> {
     inner: T,
     start: u64,
@@ -20,6 +22,44 @@ impl<T: Seek> SeekSlice<T> {
             current,
         })
     }
+
+    // only the tests slice and unwrap so far
+    /// How long the slice is.
+    #[allow(dead_code)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// A slice of this slice, with `start` and `end` relative to it. It wraps the same
+    /// underlying stream, so slicing as deep as you like doesn't cost any extra
+    /// indirection.
+    #[allow(dead_code)]
+    pub fn sub_slice(self, start: u64, end: u64) -> io::Result<SeekSlice<T>> {
+        if start > end || end > self.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sub-slice doesn't fit inside its parent",
+            ));
+        }
+        SeekSlice::new(self.inner, self.start + start, self.start + end)
+    }
+
+    /// Gives back the underlying stream, positioned wherever the slice left it.
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// How much of the slice is left after the current position, capped to what fits
+    /// in a usize.
+    fn remaining(&self) -> usize {
+        (self.end - self.current).try_into().unwrap_or(usize::MAX)
+    }
 }
 
 // should be a.checked_add_signed(b), but at time of writing, that won't be stable until
@@ -42,7 +82,8 @@ impl<T: Seek> Seek for SeekSlice<T> {
         };
         match maybe_goal_idx {
             Some(goal_idx) => {
-                if goal_idx < self.start || goal_idx >= self.end {
+                // seeking to exactly the end is fine; that's where EOF is
+                if goal_idx < self.start || goal_idx > self.end {
                     Err(io::Error::new(
                         io::ErrorKind::InvalidInput,
                         "invalid seek to a negative or overflowing position",
@@ -62,16 +103,43 @@ impl<T: Seek> Seek for SeekSlice<T> {
 
 impl<T: Read + Seek> Read for SeekSlice<T> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let max_read: usize =
-            (self.end - self.current).try_into().unwrap_or(usize::MAX);
-        let read_size = std::cmp::min(max_read, buf.len());
+        let read_size = std::cmp::min(self.remaining(), buf.len());
         let amount = self.inner.read(&mut buf[..read_size])?;
         self.current += amount as u64;
         Ok(amount)
     }
 }
 
-// could impl Write as well, but so far I haven't needed it
+/// Reads through the inner stream's buffer, so wrap the inner stream in a BufReader,
+/// not the slice.
+impl<T: BufRead + Seek> BufRead for SeekSlice<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = self.remaining();
+        let buf = self.inner.fill_buf()?;
+        Ok(&buf[..std::cmp::min(remaining, buf.len())])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = std::cmp::min(amt, self.remaining());
+        self.inner.consume(amt);
+        self.current += amt as u64;
+    }
+}
+
+/// Writes overwrite the inner stream in place, and can't go past the end of the slice:
+/// once it's full, `write` returns 0, so `write_all` fails with `WriteZero`.
+impl<T: Write + Seek> Write for SeekSlice<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write_size = std::cmp::min(self.remaining(), buf.len());
+        let amount = self.inner.write(&buf[..write_size])?;
+        self.current += amount as u64;
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -106,4 +174,31 @@ mod test {
         assert_eq!(next_byte(&mut slice), 7u8);
         assert!(slice.bytes().next().is_none());
     }
+
+    #[test]
+    fn test_seek_slice_write_and_nesting() {
+        let mut cursor = Cursor::new(vec![0u8; 10]);
+        let mut slice = SeekSlice::new(&mut cursor, 2, 8).unwrap();
+        assert_eq!(slice.len(), 6);
+        assert_eq!(slice.seek(SeekFrom::End(0)).unwrap(), 6);
+        slice.seek(SeekFrom::Start(4)).unwrap();
+        // writes stop at the end of the slice
+        assert_eq!(slice.write(&[1, 2, 3]).unwrap(), 2);
+        assert_eq!(slice.write(&[3]).unwrap(), 0);
+        assert!(slice.write_all(&[3]).is_err());
+
+        // a sub-slice is relative to its parent, and can't stick out of it
+        let mut sub = slice.sub_slice(1, 5).unwrap();
+        assert_eq!(sub.stream_position().unwrap(), 0);
+        sub.write_all(&[9, 9]).unwrap();
+        assert!(sub.sub_slice(2, 5).is_err());
+        assert_eq!(cursor.get_ref(), &[0, 0, 0, 9, 9, 0, 1, 2, 0, 0]);
+
+        // BufRead goes through the inner buffer, and stops at the end too
+        let mut cursor = Cursor::new(b"skip\nfirst\nsecond\nafter".to_vec());
+        let mut slice = SeekSlice::new(&mut cursor, 5, 18).unwrap();
+        let lines: Vec<String> = (&mut slice).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["first", "second"]);
+        assert!(slice.fill_buf().unwrap().is_empty());
+    }
 }
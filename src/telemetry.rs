@@ -0,0 +1,90 @@
+//! Sending our spans somewhere else, over OTLP, for people watching lots of posy runs
+//! at once -- e.g. a platform team keeping an eye on a CI fleet, who want to notice
+//! when their index mirror gets slow.
+//!
+//! This needs a build with the `otel` feature, and is only switched on when one of the
+//! standard OpenTelemetry environment variables says where to send things:
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` (used as-is), or `OTEL_EXPORTER_OTLP_ENDPOINT`
+//! (which gets `/v1/traces` tacked on). We speak OTLP/HTTP with protobuf.
+//! `OTEL_SERVICE_NAME` overrides the default service name of `posy`.
+//!
+//! What gets exported is the same `timing!` spans that `--timings` adds up (index
+//! fetches, metadata, the solver, builds, downloads, unpacking), nested inside the
+//! `context!` spans that say what each one was for -- which page, which artifact.
+
+use crate::prelude::*;
+
+pub fn traces_endpoint() -> Option<String> {
+    let get = |var| std::env::var(var).ok().filter(|value| !value.is_empty());
+    get("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").or_else(|| {
+        get("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+    })
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use super::*;
+    use crate::timings::POSY_TIMING_TARGET;
+    use crate::util::POSY_CONTEXT_TARGET;
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::{Level, Subscriber};
+    use tracing_subscriber::{filter::Targets, registry::LookupSpan, Layer};
+
+    pub fn layer<S>() -> Result<Option<impl Layer<S>>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(endpoint) = traces_endpoint() else {
+            return Ok(None);
+        };
+        let service_name =
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "posy".into());
+        // The default resource picks up OTEL_RESOURCE_ATTRIBUTES
+        let resource = Resource::default().merge(&Resource::new([
+            KeyValue::new("service.name", service_name),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]));
+        // The simple processor exports each span as it ends, from its own thread, so
+        // it doesn't care whether we're inside tokio at the time. Batching would save
+        // a few requests, but we're a short-lived process and shutdown() has to wait
+        // for the export either way.
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_simple()
+            .wrap_err("setting up OTLP export")?;
+        Ok(Some(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(
+                    Targets::new()
+                        .with_target(POSY_TIMING_TARGET, Level::TRACE)
+                        .with_target(POSY_CONTEXT_TARGET, Level::TRACE),
+                ),
+        ))
+    }
+
+    /// Sends off anything that's still waiting. Call this before exiting.
+    pub fn shutdown() {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use self::otel::{layer, shutdown};
+
+#[cfg(not(feature = "otel"))]
+pub fn layer() -> Result<Option<tracing_subscriber::layer::Identity>> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn shutdown() {}
@@ -1,10 +1,15 @@
-use std::{
-    net::SocketAddr,
-    path::{Path, PathBuf},
-};
+use std::path::Path;
 
+use crate::env::EnvForest;
+use crate::kvstore::KVDirStore;
+use crate::package_db::{MemoryIndex, PackageDB};
+use crate::platform_tags::PybiPlatform;
 use crate::prelude::*;
 
+// its own file, so the benchmarks can use it too (see benches/engine.rs)
+mod static_http_server;
+pub use static_http_server::StaticHTTPServer;
+
 pub fn from_commented_json<T>(input: &str) -> T
 where
     T: serde::de::DeserializeOwned,
@@ -15,68 +20,259 @@ where
     serde_json::from_str(&replaced).unwrap()
 }
 
-pub struct StaticHTTPServer {
-    address: SocketAddr,
-    tx: Option<tokio::sync::oneshot::Sender<()>>,
-    runtime: tokio::runtime::Runtime,
-    join_handle: Option<tokio::task::JoinHandle<()>>,
+/// A spread of versions that hits the awkward corners of PEP 440: epochs, dev/pre/post
+/// releases, and +local labels.
+///
+/// Leaves out a few kinds of version that can't be handled exactly with ranges, like
+/// X+foo.bar (see Version::next) and pre-releases with post-releases (X.aN.postM).
+pub fn sample_versions() -> Vec<Version> {
+    [
+        "0.dev0",
+        "0",
+        "0.1",
+        "1.0.dev0",
+        "1.0.dev1",
+        "1.0a1.dev1",
+        "1.0a1",
+        "1.0a2",
+        "1.0b1",
+        "1.0rc1",
+        "1.0",
+        "1.0+local",
+        "1.0+local.1",
+        "1.0+zzz",
+        "1.0+5",
+        "1.0.post0.dev0",
+        "1.0.post0",
+        "1.0.post1.dev0",
+        "1.0.post1",
+        "1.0.post1+local",
+        "1.0.post2",
+        "1.0.1",
+        "1.1.dev0",
+        "1.1",
+        "2.0",
+        "2.0+local",
+        "1!0.5",
+        "1!1.0",
+        "1!1.0.post1",
+        "1!2.0",
+    ]
+    .iter()
+    .map(|v| v.parse().unwrap())
+    .collect()
 }
 
-impl StaticHTTPServer {
-    /// Spins up a static file server for testing against.
-    ///
-    /// Path can be absolute, or relative to the project root.
-    pub fn new<P: AsRef<Path>>(path: P) -> StaticHTTPServer {
-        let mut actual_path: PathBuf = env!("CARGO_MANIFEST_DIR").parse().unwrap();
-        actual_path.push(path);
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        let _guard = runtime.enter();
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        let (address, server) = warp::serve(warp::fs::dir(actual_path))
-            .bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
-                rx.await.ok();
-            });
-        let join_handle = runtime.spawn(server);
-
-        StaticHTTPServer {
-            address,
-            tx: Some(tx),
-            runtime,
-            join_handle: Some(join_handle),
+/// Specifiers to try against `sample_versions`. Some are invalid (e.g. "~= 1"), and
+/// it's up to the caller to skip those.
+pub fn sample_specifiers() -> Vec<String> {
+    let mut specifiers = Vec::new();
+    let values = [
+        "0",
+        "1",
+        "1.0",
+        "1.0a1",
+        "1.0.dev1",
+        "1.0.post0",
+        "1.0.post1",
+        "1.1",
+        "1!1.0",
+        "2",
+    ];
+    for op in ["==", "!=", "<=", ">=", "<", ">", "~=", "==="] {
+        for value in values {
+            specifiers.push(format!("{op} {value}"));
+        }
+    }
+    for op in ["==", "!="] {
+        for value in ["1.*", "1.0.*", "1.0.post1.*", "1!1.*"] {
+            specifiers.push(format!("{op} {value}"));
+        }
+    }
+    for op in ["==", "!=", "==="] {
+        for value in ["1.0+local", "1.0+5"] {
+            specifiers.push(format!("{op} {value}"));
         }
     }
+    specifiers.push("=== not-a-version".into());
+    specifiers
+}
+
+/// Somewhere for a test's PackageDB to live: a scratch directory for its caches, and
+/// the EnvForest and KVDirStore it builds things in. The directory goes away when this
+/// does, so keep it around as long as the PackageDB.
+pub struct TestDB {
+    tmp: tempfile::TempDir,
+    forest: EnvForest,
+    store: KVDirStore,
+}
 
-    pub fn url(&self, path: &str) -> Url {
-        let mut url =
-            Url::parse(&format!("http://127.0.0.1:{}", self.address.port())).unwrap();
-        url.set_path(path);
-        url
+impl TestDB {
+    pub fn new() -> Result<TestDB> {
+        let tmp = tempfile::tempdir()?;
+        let forest = EnvForest::new(&tmp.path().join("envs"))?;
+        let store = KVDirStore::new(&tmp.path().join("store"))?;
+        Ok(TestDB { tmp, forest, store })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.tmp.path()
+    }
+
+    /// A PackageDB for the indexes at `index_urls`, keeping its caches in
+    /// `cache_path`.
+    pub fn db(&self, index_urls: &[Url], cache_path: &Path) -> Result<PackageDB<'_>> {
+        Ok(PackageDB::new(
+            index_urls,
+            cache_path,
+            &self.forest,
+            &self.store,
+        )?)
+    }
+
+    /// A PackageDB that gets everything from `index`.
+    pub fn in_memory(&self, index: MemoryIndex) -> Result<PackageDB<'_>> {
+        Ok(PackageDB::in_memory(
+            index,
+            self.path(),
+            &self.forest,
+            &self.store,
+        )?)
     }
 }
 
-impl Drop for StaticHTTPServer {
-    fn drop(&mut self) {
-        self.tx.take().unwrap().send(()).unwrap();
-        self.runtime
-            .block_on(self.join_handle.take().unwrap())
-            .unwrap();
-        println!("server closed");
+/// The platform `MemoryIndex::add_pybi` is usually asked to build for, in tests.
+pub fn test_platform() -> PybiPlatform {
+    PybiPlatform::new("manylinux_2_17_x86_64")
+}
+
+/// Little-endian fields, for writing zip structures by hand.
+#[derive(Default)]
+struct ZipBytes(Vec<u8>);
+
+impl ZipBytes {
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend(value);
+        self
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
     }
 }
 
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_static_http_server() {
-        let server = StaticHTTPServer::new("test-data");
-        let agent = ureq::Agent::new();
-        let response = agent
-            .request_url("GET", &server.url("basic.json"))
-            .call()
-            .unwrap();
-        assert_eq!(response.status(), 200);
-        let data: HashMap<String, u32> = response.into_json().unwrap();
-        assert_eq!(data.get("hi"), Some(&1));
+/// A zip file that uses every Zip64 feature, even though nothing in it is big enough
+/// to need them: sizes and offsets that only live in Zip64 extra fields, data
+/// descriptors with 64-bit sizes, and a Zip64 end of central directory record. That's
+/// how streaming writers lay out huge pybis and wheels, and multi-gigabyte fixtures
+/// would be a bit much. Members are stored uncompressed, with mode 0644.
+pub fn zip64_archive(members: &[(&str, &[u8])]) -> Vec<u8> {
+    // data descriptor + UTF-8 names
+    const FLAGS: u16 = 0x0808;
+    const VERSION: u16 = 45;
+    // made by unix, so the mode bits count
+    const MADE_BY: u16 = (3 << 8) | VERSION;
+    // 1980-01-01, midnight
+    const DATE: u16 = 0x21;
+    const ZIP64_EXTRA: u16 = 0x0001;
+
+    let mut out = ZipBytes::default();
+    let mut central = ZipBytes::default();
+    for (name, data) in members {
+        let offset = out.len();
+        let size = data.len() as u64;
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let crc = crc.sum();
+
+        // the local header leaves the crc and sizes to the data descriptor
+        out.u32(0x04034b50)
+            .u16(VERSION)
+            .u16(FLAGS)
+            .u16(0)
+            .u16(0)
+            .u16(DATE)
+            .u32(0)
+            .u32(u32::MAX)
+            .u32(u32::MAX)
+            .u16(name.len() as u16)
+            .u16(20)
+            .bytes(name.as_bytes())
+            .u16(ZIP64_EXTRA)
+            .u16(16)
+            .u64(0)
+            .u64(0)
+            .bytes(data)
+            .u32(0x08074b50)
+            .u32(crc)
+            .u64(size)
+            .u64(size);
+
+        central
+            .u32(0x02014b50)
+            .u16(MADE_BY)
+            .u16(VERSION)
+            .u16(FLAGS)
+            .u16(0)
+            .u16(0)
+            .u16(DATE)
+            .u32(crc)
+            .u32(u32::MAX)
+            .u32(u32::MAX)
+            .u16(name.len() as u16)
+            .u16(28)
+            .u16(0)
+            .u16(0)
+            .u16(0)
+            .u32(0o100644 << 16)
+            .u32(u32::MAX)
+            .bytes(name.as_bytes())
+            .u16(ZIP64_EXTRA)
+            .u16(24)
+            .u64(size)
+            .u64(size)
+            .u64(offset);
     }
+
+    let central_offset = out.len();
+    let count = members.len() as u64;
+    out.bytes(&central.0);
+    let zip64_end_offset = out.len();
+    out.u32(0x06064b50)
+        .u64(44)
+        .u16(MADE_BY)
+        .u16(VERSION)
+        .u32(0)
+        .u32(0)
+        .u64(count)
+        .u64(count)
+        .u64(central.len())
+        .u64(central_offset);
+    // the locator, and then the classic record, which defers to the Zip64 one for
+    // everything
+    out.u32(0x07064b50).u32(0).u64(zip64_end_offset).u32(1);
+    out.u32(0x06054b50)
+        .u16(0)
+        .u16(0)
+        .u16(u16::MAX)
+        .u16(u16::MAX)
+        .u32(u32::MAX)
+        .u32(u32::MAX)
+        .u16(0);
+    out.0
 }
@@ -0,0 +1,212 @@
+//! `--timings`: where did the time go?
+//!
+//! Interesting stretches of work are wrapped in `timing!("phase")` spans. Time is
+//! charged to whichever phase is innermost, so e.g. when the solver stops to fetch some
+//! metadata, that counts as metadata time, not solver time. At exit, the CLI prints
+//! the totals.
+
+use crate::prelude::*;
+use once_cell::sync::OnceCell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+pub const POSY_TIMING_TARGET: &str = "posy::timing";
+
+#[macro_export]
+macro_rules! timing {
+    ($phase:expr) => {
+        let _timing_guard = tracing::span!(
+            target: "posy::timing",
+            tracing::Level::ERROR,
+            "timing",
+            phase = $phase,
+            // for OpenTelemetry, where every span would otherwise be called "timing"
+            otel.name = $phase
+        )
+        .entered();
+    };
+}
+
+/// The phases we know about, in pipeline order, with what to call them in the report.
+const PHASES: &[(&str, &str)] = &[
+    ("index", "index fetches"),
+    ("metadata", "metadata fetches"),
+    ("solver", "solver"),
+    ("build", "sdist builds"),
+    ("download", "downloads"),
+    ("unpack", "unpacking"),
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct PhaseTotal {
+    time: Duration,
+    count: u32,
+}
+
+static TOTALS: Lazy<Mutex<BTreeMap<String, PhaseTotal>>> = Lazy::new(Default::default);
+
+thread_local! {
+    /// The timing spans we're currently inside, innermost last, with when we last
+    /// started charging time to each.
+    static STACK: RefCell<Vec<(String, Instant)>> = RefCell::new(Vec::new());
+}
+
+fn charge(phase: &str, time: Duration, count: u32) {
+    let mut totals = TOTALS.lock().unwrap();
+    let total = totals.entry(phase.to_string()).or_default();
+    total.time += time;
+    total.count += count;
+}
+
+struct Phase(String);
+
+struct PhaseVisitor(Option<String>);
+
+impl Visit for PhaseVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "phase" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "phase" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// When we started, for the total. Set when the layer is created.
+static START: OnceCell<Instant> = OnceCell::new();
+
+pub struct TimingsLayer;
+
+pub fn layer() -> TimingsLayer {
+    START.get_or_init(Instant::now);
+    TimingsLayer
+}
+
+fn phase_of<S>(id: &Id, ctx: &Context<'_, S>) -> Option<String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let span = ctx.span(id)?;
+    let extensions = span.extensions();
+    extensions.get::<Phase>().map(|p| p.0.clone())
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for TimingsLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().target() != POSY_TIMING_TARGET {
+            return;
+        }
+        let mut visitor = PhaseVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(phase), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(Phase(phase));
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(phase) = phase_of(id, &ctx) else {
+            return;
+        };
+        let now = Instant::now();
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some((outer, since)) = stack.last() {
+                charge(outer, now - *since, 0);
+            }
+            charge(&phase, Duration::ZERO, 1);
+            stack.push((phase, now));
+        });
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if phase_of(id, &ctx).is_none() {
+            return;
+        }
+        let now = Instant::now();
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some((phase, since)) = stack.pop() {
+                charge(&phase, now - since, 0);
+            }
+            if let Some((_, since)) = stack.last_mut() {
+                *since = now;
+            }
+        });
+    }
+}
+
+fn render(totals: &BTreeMap<String, PhaseTotal>, wall: Duration) -> String {
+    let mut out = String::from("Timings:\n");
+    let mut accounted = Duration::ZERO;
+    let mut line = |label: &str, total: &PhaseTotal| {
+        out.push_str(&format!(
+            "  {label:<18} {:>7.2}s  x{}\n",
+            total.time.as_secs_f64(),
+            total.count
+        ));
+        accounted += total.time;
+    };
+    for (phase, label) in PHASES {
+        if let Some(total) = totals.get(*phase) {
+            line(label, total);
+        }
+    }
+    // Anything that isn't in the table above
+    for (phase, total) in totals {
+        if !PHASES.iter().any(|(p, _)| p == phase) {
+            line(phase, total);
+        }
+    }
+    for (label, time) in [
+        ("everything else", wall.saturating_sub(accounted)),
+        ("total", wall),
+    ] {
+        out.push_str(&format!("  {label:<18} {:>7.2}s\n", time.as_secs_f64()));
+    }
+    out
+}
+
+/// The breakdown, if we were keeping track. Printing it is up to you.
+pub fn report() -> Option<String> {
+    START
+        .get()
+        .map(|start| render(&TOTALS.lock().unwrap(), start.elapsed()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_timings() {
+        let mut totals = BTreeMap::new();
+        let total = |ms, count| PhaseTotal {
+            time: Duration::from_millis(ms),
+            count,
+        };
+        totals.insert("solver".to_string(), total(250, 1));
+        totals.insert("index".to_string(), total(1500, 12));
+        totals.insert("custom".to_string(), total(50, 2));
+        let rendered = render(&totals, Duration::from_secs(2));
+        insta::assert_snapshot!(rendered, @r###"
+        Timings:
+          index fetches         1.50s  x12
+          solver                0.25s  x1
+          custom                0.05s  x2
+          everything else       0.20s
+          total                 2.00s
+        "###);
+    }
+}
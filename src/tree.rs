@@ -207,26 +207,128 @@ pub trait WriteTree {
     fn write_symlink(&mut self, symlink: &NiceSymlinkPaths) -> Result<()>;
 }
 
+/// Writes a tree out under `root`.
+///
+/// NicePathBuf and NiceSymlinkPaths already make sure that paths can't escape the root
+/// on paper. But on disk, a path can go through symlinks -- ones that were in the
+/// directory before we started, or ones that we made ourselves, earlier in the same
+/// archive -- so before creating anything we also check where each path really ends up
+/// once all the symlinks are resolved.
 pub struct WriteTreeFS {
     root: PathBuf,
+    // `root` with all symlinks resolved, once we've made sure it exists
+    canonical_root: Option<PathBuf>,
 }
 
 impl WriteTreeFS {
     pub fn new<T: AsRef<Path>>(root: T) -> WriteTreeFS {
         WriteTreeFS {
             root: root.as_ref().into(),
+            canonical_root: None,
         }
     }
 
-    fn full_path(&self, path: &NicePathBuf) -> Result<PathBuf> {
+    fn canonical_root(&mut self) -> Result<PathBuf> {
+        if let Some(root) = &self.canonical_root {
+            return Ok(root.clone());
+        }
+        fs::create_dir_all(&self.root)?;
+        let root = fs::canonicalize(&self.root)?;
+        self.canonical_root = Some(root.clone());
+        Ok(root)
+    }
+
+    /// Fails if `path` would resolve to somewhere outside the root. It doesn't have to
+    /// exist yet: we check the deepest part that does, since anything we create below
+    /// that is a plain directory or file.
+    fn check_confined(&mut self, path: &Path) -> Result<()> {
+        let root = self.canonical_root()?;
+        let mut existing = path;
+        // symlink_metadata, so that a dangling symlink counts as existing
+        while fs::symlink_metadata(existing).is_err() {
+            match existing.parent() {
+                Some(parent) => existing = parent,
+                None => break,
+            }
+        }
+        let resolved = fs::canonicalize(existing).wrap_err_with(|| {
+            format!(
+                "can't resolve {} inside {}",
+                existing.display(),
+                root.display()
+            )
+        })?;
+        if !resolved.starts_with(&root) {
+            bail!(
+                "{} escapes {} (it resolves to {})",
+                path.display(),
+                root.display(),
+                resolved.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn full_path(&mut self, path: &NicePathBuf) -> Result<PathBuf> {
         let full_path = self.root.join(path.to_native());
         if let Some(parent) = full_path.parent() {
+            // before creating anything, so that we never make directories outside
+            self.check_confined(parent)?;
             fs::create_dir_all(parent)?;
         }
+        // The last piece can't be a symlink that sends us somewhere else, because we
+        // always create it fresh, and that fails if anything's already there.
         Ok(full_path)
     }
 }
 
+/// Where a symlink in `dir` pointing at `target` leads, worked out the way the OS will
+/// do it: through the filesystem, following any symlinks along the way (including ones
+/// we made earlier in the same archive), so that a '..' after one of them goes up from
+/// wherever it really points, not from where it sits. Anything that doesn't exist yet
+/// is taken to be a plain directory or file -- so a '..' can't come after it, since a
+/// later member could turn out to be a symlink there, and send the '..' somewhere else
+/// entirely.
+#[cfg(unix)]
+fn resolve_symlink_target(dir: &Path, target: &Path, depth: usize) -> Result<PathBuf> {
+    // same as Linux's limit
+    if depth > 40 {
+        bail!("too many levels of symlinks");
+    }
+    let mut resolved = fs::canonicalize(dir)?;
+    let mut missing = None;
+    for c in target.components() {
+        match c {
+            std::path::Component::ParentDir => {
+                if let Some(missing) = &missing {
+                    bail!(
+                        "can't tell where '..' leads from {}, since it doesn't exist \
+                         yet",
+                        missing.display()
+                    );
+                }
+                resolved.pop();
+            }
+            std::path::Component::CurDir => (),
+            std::path::Component::Normal(piece) => {
+                let next = resolved.join(piece);
+                resolved = match fs::read_link(&next) {
+                    Ok(link) => resolve_symlink_target(&resolved, &link, depth + 1)?,
+                    Err(_) => {
+                        if missing.is_none() && fs::symlink_metadata(&next).is_err() {
+                            missing = Some(next.clone());
+                        }
+                        next
+                    }
+                };
+            }
+            // an absolute target starts over from the top
+            other => resolved.push(other),
+        }
+    }
+    Ok(resolved)
+}
+
 impl WriteTree for WriteTreeFS {
     fn mkdir(&mut self, path: &NicePathBuf) -> Result<()> {
         context!("Creating {path}/");
@@ -257,10 +359,12 @@ impl WriteTree for WriteTreeFS {
         context!("Symlinking {} -> {}", symlink.source, symlink.target);
         #[cfg(unix)]
         {
-            std::os::unix::fs::symlink(
-                &symlink.target,
-                self.full_path(&symlink.source)?,
-            )?;
+            let full_path = self.full_path(&symlink.source)?;
+            let target = Path::new(&symlink.target);
+            let pointee =
+                resolve_symlink_target(full_path.parent().unwrap(), target, 0)?;
+            self.check_confined(&pointee)?;
+            std::os::unix::fs::symlink(&symlink.target, full_path)?;
         }
         #[cfg(not(unix))]
         {
@@ -270,38 +374,207 @@ impl WriteTree for WriteTreeFS {
     }
 }
 
+/// Limits on what we're willing to unpack, so that a malicious (or just very broken)
+/// archive can't fill up the disk. Sizes are in bytes. Set under `[unpack-limits]` in
+/// posy.toml. The defaults are on the cautious side: the very biggest wheels (CUDA,
+/// say) need them raised in your user config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct UnpackLimits {
+    /// The archive itself
+    pub max_compressed_size: u64,
+    /// Everything in it, added up
+    pub max_unpacked_size: u64,
+    /// How many times bigger than its compressed form a member can get. Only checked
+    /// for members over RATIO_CHECK_MIN, since small files can legitimately compress
+    /// very well.
+    pub max_compression_ratio: u64,
+    pub max_members: u64,
+}
+
+const RATIO_CHECK_MIN: u64 = 1 << 20;
+
+impl Default for UnpackLimits {
+    fn default() -> Self {
+        UnpackLimits {
+            max_compressed_size: 4 << 30,
+            max_unpacked_size: 16 << 30,
+            max_compression_ratio: 250,
+            max_members: 250_000,
+        }
+    }
+}
+
+impl UnpackLimits {
+    /// Whichever of each limit is stricter.
+    pub fn tightened(&self, other: &UnpackLimits) -> UnpackLimits {
+        UnpackLimits {
+            max_compressed_size: self
+                .max_compressed_size
+                .min(other.max_compressed_size),
+            max_unpacked_size: self.max_unpacked_size.min(other.max_unpacked_size),
+            max_compression_ratio: self
+                .max_compression_ratio
+                .min(other.max_compression_ratio),
+            max_members: self.max_members.min(other.max_members),
+        }
+    }
+
+    fn check(&self, what: &str, value: u64, limit: u64) -> Result<()> {
+        if value > limit {
+            Err(InstallError::UnpackLimitExceeded {
+                what: what.into(),
+                value,
+                limit,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Charges one member against the limits. `total_*` are the running totals, which
+    /// this updates.
+    fn check_member(
+        &self,
+        compressed: u64,
+        unpacked: u64,
+        total_compressed: &mut u64,
+        total_unpacked: &mut u64,
+    ) -> Result<()> {
+        *total_compressed = total_compressed.saturating_add(compressed);
+        *total_unpacked = total_unpacked.saturating_add(unpacked);
+        self.check(
+            "compressed size",
+            *total_compressed,
+            self.max_compressed_size,
+        )?;
+        self.check("unpacked size", *total_unpacked, self.max_unpacked_size)?;
+        if unpacked > RATIO_CHECK_MIN {
+            self.check(
+                "compression ratio",
+                unpacked / compressed.max(1),
+                self.max_compression_ratio,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Passes through at most `remaining` bytes, and fails instead of going past that. For
+/// zip members, whose headers tell us how big they are, but might be lying.
+struct SizeLimited<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for SizeLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        match self.remaining.checked_sub(n as u64) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "member is bigger than the archive says it is",
+            )),
+        }
+    }
+}
+
+/// Reads all of one archive member -- say, a wheel's METADATA -- after charging its
+/// sizes against `limits`, the same as if it were the only thing we were unpacking.
+/// `data` fails if it turns out any bigger than the header said, and we don't take the
+/// header's word for how much room to make, either.
+pub fn slurp_member_carefully<R: Read>(
+    data: R,
+    compressed: u64,
+    size: u64,
+    limits: &UnpackLimits,
+) -> Result<Vec<u8>> {
+    limits.check_member(compressed, size, &mut 0, &mut 0)?;
+    let mut out = Vec::with_capacity(size.min(1 << 20) as usize);
+    SizeLimited {
+        inner: data,
+        remaining: size,
+    }
+    .read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// What a zip member's headers say about it, for `read_zip_carefully`.
+pub struct ZipMember {
+    pub name: String,
+    pub is_dir: bool,
+    pub unix_mode: Option<u32>,
+}
+
+impl ZipMember {
+    pub fn is_symlink(&self) -> bool {
+        self.unix_mode.map_or(false, |mode| mode & 0xf000 == 0xa000)
+    }
+}
+
+/// Hands each member of `z` to `f` in turn, after charging it against `limits` -- the
+/// same checks `unpack_zip_carefully` makes, for when we want to look at what's in a
+/// zip without writing it out. The data `f` gets fails if it turns out to be any
+/// bigger than the headers said.
+pub fn read_zip_carefully<T, F>(
+    z: &mut ZipArchive<T>,
+    limits: &UnpackLimits,
+    mut f: F,
+) -> Result<()>
+where
+    T: Read + Seek,
+    F: FnMut(&ZipMember, &mut dyn Read) -> Result<()>,
+{
+    limits.check("member count", z.len() as u64, limits.max_members)?;
+    let mut total_compressed = 0;
+    let mut total_unpacked = 0;
+    for i in 0..z.len() {
+        let zip_file = z.by_index(i)?;
+        context!("Reading zip file member {}", zip_file.name());
+        let size = zip_file.size();
+        limits.check_member(
+            zip_file.compressed_size(),
+            size,
+            &mut total_compressed,
+            &mut total_unpacked,
+        )?;
+        let member = ZipMember {
+            name: zip_file.name().to_owned(),
+            is_dir: zip_file.is_dir(),
+            unix_mode: zip_file.unix_mode(),
+        };
+        let mut zip_file = SizeLimited {
+            inner: zip_file,
+            remaining: size,
+        };
+        f(&member, &mut zip_file)?;
+    }
+    Ok(())
+}
+
 pub fn unpack_zip_carefully<T: Read + Seek, W: WriteTree>(
     z: &mut ZipArchive<T>,
     dest: &mut W,
+    limits: &UnpackLimits,
 ) -> Result<()> {
     // we process symlinks in a batch at the end
     let mut symlinks = Vec::<NiceSymlinkPaths>::new();
-    // indices is sorted from end to start; flip it back around when iterating to get
-    // better locality on our reads.
-    for i in 0..z.len() {
-        let mut zip_file = z.by_index(i)?;
-        context!("Unpacking zip file member {}", zip_file.name());
-        if let Some(mode) = zip_file.unix_mode() {
-            if mode & 0xf000 == 0xa000 {
-                // it's a symlink
-                symlinks.push(NiceSymlinkPaths::new(
-                    &zip_file.name().try_into()?,
-                    slurp(&mut zip_file)?.as_slice(),
-                )?);
-                continue;
-            }
-        }
-        let path: NicePathBuf = zip_file.name().try_into()?;
-        if zip_file.is_dir() {
+    read_zip_carefully(z, limits, |member, mut data| {
+        let path: NicePathBuf = member.name.as_str().try_into()?;
+        if member.is_symlink() {
+            let target = slurp(&mut data)?;
+            symlinks.push(NiceSymlinkPaths::new(&path, &target)?);
+        } else if member.is_dir {
             dest.mkdir(&path)?;
         } else {
-            let executable = zip_file
-                .unix_mode()
-                .map(|v| v & 0o0111 != 0)
-                .unwrap_or(false);
-            dest.write_file(&path, &mut zip_file, executable)?;
+            let executable = member.unix_mode.map(|v| v & 0o0111 != 0).unwrap_or(false);
+            dest.write_file(&path, data, executable)?;
         }
-    }
+        Ok(())
+    })?;
 
     // process symlinks in order from longest to shortest, to prevent weird cases where
     // first we make a symlink foo/ -> bar/, and then we make another symlink foo/baz ->
@@ -315,14 +588,33 @@ pub fn unpack_zip_carefully<T: Read + Seek, W: WriteTree>(
 }
 
 pub fn unpack_tar_gz_carefully<T: Read + Seek, W: WriteTree>(
-    body: T,
+    mut body: T,
     mut dest: W,
+    limits: &UnpackLimits,
 ) -> Result<()> {
+    // There's only one compressed stream, so the ratio we check is the overall one
+    let compressed = body.seek(io::SeekFrom::End(0))?;
+    body.rewind()?;
+    limits.check("compressed size", compressed, limits.max_compressed_size)?;
+    let mut members = 0;
+    let mut unpacked = 0u64;
     let ungz = flate2::read::MultiGzDecoder::new(body);
     let mut archive = tar::Archive::new(ungz);
     for entry in archive.entries()? {
         let mut entry = entry?;
         let path: NicePathBuf = entry.path_bytes().deref().try_into()?;
+        members += 1;
+        limits.check("member count", members, limits.max_members)?;
+        // tar headers can't lie about sizes: the size is how much we read
+        unpacked = unpacked.saturating_add(entry.size());
+        limits.check("unpacked size", unpacked, limits.max_unpacked_size)?;
+        if unpacked > RATIO_CHECK_MIN {
+            limits.check(
+                "compression ratio",
+                unpacked / compressed.max(1),
+                limits.max_compression_ratio,
+            )?;
+        }
         let kind = entry.header().entry_type();
         let is_executable = entry.header().mode()? & 0o100 != 0;
         use tar::EntryType::*;
@@ -404,5 +696,194 @@ mod test {
         }
     }
 
-    // XX TODO: write some tests that unpacking invalid zip files are rejected!!
+    fn make_zip(
+        files: &[&str],
+        symlinks: &[(&str, &str)],
+    ) -> ZipArchive<io::Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default();
+        for name in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(b"gotcha").unwrap();
+        }
+        for (source, target) in symlinks {
+            writer.add_symlink(*source, *target, options).unwrap();
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    // Files in 'outside', which are exactly the ones that a hostile archive shouldn't
+    // be able to touch
+    #[cfg(unix)]
+    fn outside_contents(outside: &Path) -> Vec<String> {
+        fs::read_dir(outside)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_hostile_zips() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let cases: &[(&[&str], &[(&str, &str)])] = &[
+            (&["../outside/evil"], &[]),
+            (&["foo/../../outside/evil"], &[]),
+            (&["/etc/evil"], &[]),
+            (&[], &[("link", "..")]),
+            (&[], &[("foo/link", "../../outside")]),
+            // a symlink that was already there before we started
+            (&["escape/evil"], &[]),
+            (&["inside/evil"], &[("inside/link", "../escape/evil")]),
+        ];
+        for (i, (files, symlinks)) in cases.iter().enumerate() {
+            println!("{files:?} {symlinks:?}");
+            let dest = tmp.path().join(format!("dest{i}"));
+            fs::create_dir(&dest).unwrap();
+            std::os::unix::fs::symlink(&outside, dest.join("escape")).unwrap();
+            let mut z = make_zip(files, symlinks);
+            let mut tree = WriteTreeFS::new(&dest);
+            let limits = UnpackLimits::default();
+            assert!(unpack_zip_carefully(&mut z, &mut tree, &limits).is_err());
+            assert!(outside_contents(&outside).is_empty());
+        }
+
+        // and a well-behaved zip still works, symlinks and all
+        let dest = tmp.path().join("good");
+        let mut z = make_zip(&["a/b/file"], &[("a/link", "b/file"), ("up", "a/b/..")]);
+        let limits = UnpackLimits::default();
+        unpack_zip_carefully(&mut z, &mut WriteTreeFS::new(&dest), &limits).unwrap();
+        assert_eq!(fs::read(dest.join("a/link")).unwrap(), b"gotcha");
+        assert_eq!(fs::read(dest.join("up/b/file")).unwrap(), b"gotcha");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_from_the_same_archive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let dest = tmp.path().join("dest");
+        let mut tree = WriteTreeFS::new(&dest);
+        let symlink = |source: &str, target: &str| {
+            NiceSymlinkPaths::new(&source.try_into().unwrap(), target.as_bytes())
+                .unwrap()
+        };
+
+        // a/b -> .. is fine on its own: it points at the root
+        tree.write_symlink(&symlink("a/b", "..")).unwrap();
+        tree.write_file(&"a/b/file".try_into().unwrap(), &mut &b"ok"[..], false)
+            .unwrap();
+        assert_eq!(fs::read(dest.join("file")).unwrap(), b"ok");
+        // But a/b/c -> ../outside only looks like it stays inside. Since a/b is really
+        // the root, it would point at our sibling.
+        assert!(tree.write_symlink(&symlink("a/b/c", "../outside")).is_err());
+        assert!(outside_contents(&outside).is_empty());
+        assert!(!dest.join("c").exists());
+
+        // And a '..' that comes after a symlink goes up from wherever that symlink
+        // points. With d/e/x -> ../.. (the root), d/e/link -> x/../outside is our
+        // sibling, even though on paper it's d/e/outside. (NiceSymlinkPaths::new would
+        // tidy that target up, so we make it by hand.)
+        tree.write_symlink(&symlink("d/e/x", "../..")).unwrap();
+        let sneaky = NiceSymlinkPaths {
+            source: "d/e/link".try_into().unwrap(),
+            target: "x/../outside".into(),
+        };
+        assert!(tree.write_symlink(&sneaky).is_err());
+        assert!(!dest.join("d/e/link").exists());
+        let fine = NiceSymlinkPaths {
+            source: "d/e/link".try_into().unwrap(),
+            target: "x/d/../file".into(),
+        };
+        tree.write_symlink(&fine).unwrap();
+        assert_eq!(fs::read(dest.join("d/e/link")).unwrap(), b"ok");
+
+        // The same trick the other way around: when p/q/link -> y/../../outside is
+        // made, p/q/y isn't there yet, so on disk it looks like p/outside. But then
+        // p/q/y -> ../.. would make it our sibling.
+        let early = NiceSymlinkPaths {
+            source: "p/q/link".try_into().unwrap(),
+            target: "y/../../outside".into(),
+        };
+        assert!(tree.write_symlink(&early).is_err());
+        assert!(!dest.join("p/q/link").exists());
+        tree.write_symlink(&symlink("p/q/y", "../..")).unwrap();
+        assert!(outside_contents(&outside).is_empty());
+    }
+
+    #[test]
+    fn test_unpack_limits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let unpack = |z: &mut ZipArchive<_>, limits: &UnpackLimits| {
+            let dest = tempfile::tempdir_in(tmp.path()).unwrap();
+            unpack_zip_carefully(z, &mut WriteTreeFS::new(dest.path()), limits)
+        };
+        let exceeded = |result: Result<()>, expected: &str| match result
+            .unwrap_err()
+            .downcast_ref::<InstallError>(
+        ) {
+            Some(InstallError::UnpackLimitExceeded { what, .. }) => {
+                assert_eq!(what, expected)
+            }
+            other => panic!("expected UnpackLimitExceeded, got {other:?}"),
+        };
+
+        let mut z = make_zip(&["a", "b", "c"], &[]);
+        let defaults = UnpackLimits::default();
+        unpack(&mut z, &defaults).unwrap();
+        let limits = UnpackLimits {
+            max_members: 2,
+            ..defaults
+        };
+        exceeded(unpack(&mut z, &limits), "member count");
+        let limits = UnpackLimits {
+            max_unpacked_size: 10,
+            ..defaults
+        };
+        exceeded(unpack(&mut z, &limits), "unpacked size");
+        let limits = UnpackLimits {
+            max_compressed_size: 1,
+            ..defaults
+        };
+        exceeded(unpack(&mut z, &limits), "compressed size");
+
+        // a few megabytes of zeros, which compress extremely well
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("zeros", options).unwrap();
+        writer.write_all(&vec![0; 4 << 20]).unwrap();
+        let mut z = ZipArchive::new(writer.finish().unwrap()).unwrap();
+        exceeded(unpack(&mut z, &defaults), "compression ratio");
+        let limits = UnpackLimits {
+            max_compression_ratio: u64::MAX,
+            ..defaults
+        };
+        unpack(&mut z, &limits).unwrap();
+
+        // and the defaults are fine with things too big for a classic zip
+        let (mut compressed, mut unpacked) = (0, 0);
+        defaults
+            .check_member(3 << 30, 5 << 30, &mut compressed, &mut unpacked)
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_zip64() {
+        let zipped = crate::test_util::zip64_archive(&[
+            ("a/b/file", b"gotcha"),
+            ("a/empty", b""),
+        ]);
+        let mut z = ZipArchive::new(io::Cursor::new(zipped)).unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let limits = UnpackLimits::default();
+        unpack_zip_carefully(&mut z, &mut WriteTreeFS::new(tmp.path()), &limits)
+            .unwrap();
+        assert_eq!(fs::read(tmp.path().join("a/b/file")).unwrap(), b"gotcha");
+        assert_eq!(fs::read(tmp.path().join("a/empty")).unwrap(), b"");
+    }
 }
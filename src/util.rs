@@ -23,6 +23,21 @@ macro_rules! try_from_str_boilerplate {
     };
 }
 
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub const POSY_CONTEXT_TARGET: &str = "posy::context";
+#[macro_export]
+macro_rules! context {
+    ($($arg:tt)*) => {
+        let _guard = tracing::span!(
+            target: "posy::context",
+            tracing::Level::ERROR,
+            "context",
+            $($arg)*
+        )
+        .entered();
+    }
+}
+
 pub fn retry_interrupted<F, T>(mut f: F) -> std::io::Result<T>
 where
     F: FnMut() -> std::io::Result<T>,
@@ -39,3 +54,239 @@ where
         }
     }
 }
+
+// Everything async we do is fetching things over the network on tokio's blocking pool,
+// so there's no need for timers or async sockets.
+static RUNTIME: once_cell::sync::Lazy<tokio::runtime::Runtime> =
+    once_cell::sync::Lazy::new(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .thread_name("posy-io")
+            .build()
+            .expect("couldn't start tokio runtime")
+    });
+
+/// Runs `future` to completion from synchronous code. Works whether or not we're
+/// already inside a runtime -- e.g. resolving a build environment for an sdist, in
+/// the middle of resolving something else, or a library user calling us from their
+/// own runtime.
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        return RUNTIME.block_on(future);
+    };
+    match handle.runtime_flavor() {
+        tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        // block_in_place panics on a current-thread runtime, since there's no other
+        // worker to hand this thread's tasks to. But everything our futures wait on
+        // runs on the blocking pool, which doesn't need this thread, so it's fine to
+        // just wait here.
+        _ => park_on(future),
+    }
+}
+
+/// The simplest possible executor: polls `future` on this thread, sleeping until
+/// it's woken up again.
+fn park_on<F: std::future::Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+    impl std::task::Wake for Unpark {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = std::sync::Arc::new(Unpark(std::thread::current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        let poll = std::future::Future::poll(future.as_mut(), &mut cx);
+        if let std::task::Poll::Ready(output) = poll {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+/// Splits a command line into words, roughly the way a POSIX shell would: words are
+/// separated by whitespace, and quotes or backslashes can be used to put spaces or
+/// quotes inside a word. No variables, globs, pipes, etc. -- if you want those, run a
+/// shell explicitly.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn split_command(s: &str) -> eyre::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(w) = word.take() {
+                    words.push(w);
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => eyre::bail!("unterminated ' in {s:?}"),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => eyre::bail!("unterminated \" in {s:?}"),
+                        },
+                        Some(c) => w.push(c),
+                        None => eyre::bail!("unterminated \" in {s:?}"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => eyre::bail!("trailing backslash in {s:?}"),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(w) = word {
+        words.push(w);
+    }
+    Ok(words)
+}
+
+/// The filename at the end of `url`'s path, percent-decoded -- e.g. the PyTorch index
+/// links to `torch-2.1.0%2Bcu121-...whl` for `torch-2.1.0+cu121-...whl`.
+pub fn url_filename(url: &url::Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let decoded = percent_encoding::percent_decode_str(segment)
+        .decode_utf8()
+        .ok()?;
+    Some(decoded.into_owned())
+}
+
+/// A TOML array with each element on its own line.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub fn multiline_array<V: Into<toml_edit::Value>>(
+    values: impl IntoIterator<Item = V>,
+) -> toml_edit::Array {
+    let mut array: toml_edit::Array = values.into_iter().collect();
+    if !array.is_empty() {
+        for value in array.iter_mut() {
+            value.decor_mut().set_prefix("\n    ");
+        }
+        array.set_trailing("\n");
+        array.set_trailing_comma(true);
+    }
+    array
+}
+
+/// Levenshtein distance, counting in chars.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            row.push(substitute.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+/// The few `candidates` that are close enough to `word` that it might have been a typo
+/// for them, closest first.
+pub fn did_you_mean<'a, I>(word: &str, candidates: I) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // Short words are only one typo away from lots of other short words
+    let max_distance = (word.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|c| *c != word)
+        .map(|c| (edit_distance(word, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .collect();
+    close.sort();
+    close.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url_filename() {
+        let filename = |s: &str| url_filename(&url::Url::parse(s).unwrap());
+        assert_eq!(
+            filename("https://example.com/whl/torch-2.1.0%2Bcu121-py3-none-any.whl#x")
+                .as_deref(),
+            Some("torch-2.1.0+cu121-py3-none-any.whl")
+        );
+        assert_eq!(
+            filename("https://example.com/foo-1.0.tar.gz").as_deref(),
+            Some("foo-1.0.tar.gz")
+        );
+        assert_eq!(filename("https://example.com/").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("pytest -x").unwrap(), vec!["pytest", "-x"]);
+        assert_eq!(
+            split_command(r#"  echo 'a b' "c \"d\"" e\ f ''  "#).unwrap(),
+            vec!["echo", "a b", r#"c "d""#, "e f", ""]
+        );
+        assert!(split_command("echo 'oops").is_err());
+        assert!(split_command("echo oops\\").is_err());
+        assert!(split_command("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        assert_eq!(edit_distance("reqeusts", "requests"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("numpy", "numpy"), 0);
+        let names = ["requests", "requests-oauthlib", "request", "trio", "attrs"];
+        assert_eq!(
+            did_you_mean("reqeusts", names.iter().copied()),
+            vec!["requests", "request"]
+        );
+        assert_eq!(did_you_mean("trip", names.iter().copied()), vec!["trio"]);
+        assert!(did_you_mean("django", names.iter().copied()).is_empty());
+    }
+
+    #[test]
+    fn test_block_on_nested() {
+        let answer = block_on(async {
+            let inner = block_on(async { 6 * 7 });
+            tokio::task::spawn_blocking(move || inner).await.unwrap()
+        });
+        assert_eq!(answer, 42);
+    }
+
+    #[test]
+    fn test_block_on_current_thread_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let answer = runtime.block_on(async {
+            block_on(async {
+                let inner = block_on(async { 6 * 7 });
+                tokio::task::spawn_blocking(move || inner).await.unwrap()
+            })
+        });
+        assert_eq!(answer, 42);
+    }
+}
@@ -1,8 +1,14 @@
+use super::artifact_name::format_build_tag;
 use super::rfc822ish::RFC822ish;
 use crate::package_db::ArtifactInfo;
 use crate::prelude::*;
+use crate::remote_zip::RemoteZip;
 use crate::trampolines::{ScriptType, TrampolineMaker};
-use crate::tree::{unpack_tar_gz_carefully, unpack_zip_carefully, WriteTree};
+use crate::tree::{
+    read_zip_carefully, slurp_member_carefully, unpack_tar_gz_carefully,
+    unpack_zip_carefully, UnpackLimits, WriteTree,
+};
+use crate::zip_index::ZipIndex;
 use std::cell::RefCell;
 use std::io::{BufRead, BufReader};
 use zip::ZipArchive;
@@ -25,16 +31,72 @@ use zip::ZipArchive;
 pub struct Sdist {
     name: SdistName,
     body: RefCell<Box<dyn ReadPlusSeek>>,
+    limits: UnpackLimits,
 }
 
 pub struct Wheel {
     name: WheelName,
-    z: RefCell<ZipArchive<Box<dyn ReadPlusSeek>>>,
+    z: RefCell<Zip>,
+    limits: UnpackLimits,
 }
 
 pub struct Pybi {
     name: PybiName,
-    z: RefCell<ZipArchive<Box<dyn ReadPlusSeek>>>,
+    z: RefCell<Zip>,
+    limits: UnpackLimits,
+}
+
+/// The zip file inside a wheel or pybi. Opening a RemoteZip reads the whole central
+/// directory, but if we have a saved index, we can pick out single files without that,
+/// and only open it for real if we need everything (e.g. to unpack it).
+enum Zip {
+    Open(RemoteZip<Box<dyn ReadPlusSeek>>),
+    Indexed(ZipIndex, Box<dyn ReadPlusSeek>),
+    // if opening it failed partway through
+    Broken,
+}
+
+impl Zip {
+    fn file_names(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self {
+            Zip::Open(z) => Box::new(z.file_names().into_iter()),
+            Zip::Indexed(index, _) => Box::new(index.file_names()),
+            Zip::Broken => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn slurp(&mut self, name: &str, limits: &UnpackLimits) -> Result<Vec<u8>> {
+        match self {
+            Zip::Indexed(index, body) => {
+                context!("extracting {name}");
+                index.slurp(body, name, limits)
+            }
+            _ => self.open()?.read(name, limits),
+        }
+    }
+
+    fn open(&mut self) -> Result<&mut RemoteZip<Box<dyn ReadPlusSeek>>> {
+        if let Zip::Indexed(..) = self {
+            if let Zip::Indexed(_, body) = std::mem::replace(self, Zip::Broken) {
+                *self = Zip::Open(RemoteZip::new(body)?);
+            }
+        }
+        match self {
+            Zip::Open(z) => Ok(z),
+            _ => bail!("zip file is unusable after an earlier error"),
+        }
+    }
+
+    fn archive(&mut self) -> Result<&mut ZipArchive<Box<dyn ReadPlusSeek>>> {
+        Ok(self.open()?.archive())
+    }
+
+    fn index(&mut self) -> Result<ZipIndex> {
+        match self {
+            Zip::Indexed(index, _) => Ok(index.clone()),
+            _ => ZipIndex::new(self.archive()?),
+        }
+    }
 }
 
 pub trait Artifact: Sized {
@@ -42,6 +104,10 @@ pub trait Artifact: Sized {
 
     fn new(name: Self::Name, f: Box<dyn ReadPlusSeek>) -> Result<Self>;
     fn name(&self) -> &Self::Name;
+
+    /// What we'll read out of this without unpacking it (e.g. its METADATA) is held to
+    /// `limits`, instead of the defaults.
+    fn with_unpack_limits(self, limits: UnpackLimits) -> Self;
 }
 
 impl Artifact for Sdist {
@@ -51,25 +117,87 @@ impl Artifact for Sdist {
         Ok(Sdist {
             name,
             body: body.into(),
+            limits: UnpackLimits::default(),
         })
     }
 
     fn name(&self) -> &Self::Name {
         &self.name
     }
+
+    fn with_unpack_limits(self, limits: UnpackLimits) -> Self {
+        Sdist { limits, ..self }
+    }
 }
 
 impl Sdist {
-    pub fn unpack<T: WriteTree>(&self, destination: &mut T) -> Result<()> {
+    pub fn unpack<T: WriteTree>(
+        &self,
+        destination: &mut T,
+        limits: &UnpackLimits,
+    ) -> Result<()> {
         context!("Unpacking {}", self.name);
         let mut boxed = self.body.borrow_mut();
         let body = boxed.as_mut();
         match self.name.format {
             SdistFormat::Zip => {
-                unpack_zip_carefully(&mut ZipArchive::new(body)?, destination)
+                unpack_zip_carefully(&mut ZipArchive::new(body)?, destination, limits)
+            }
+            SdistFormat::TarGz => unpack_tar_gz_carefully(body, destination, limits),
+        }
+    }
+
+    /// Fetches the top-level `<name>-<version>/PKG-INFO` file, if there is one.
+    pub fn pkg_info(&self) -> Result<Option<Vec<u8>>> {
+        context!("Reading PKG-INFO from {}", self.name);
+        let mut boxed = self.body.borrow_mut();
+        let body = boxed.as_mut();
+        body.rewind()?;
+        let is_pkg_info = |path: &str| {
+            let mut pieces = path.trim_end_matches('/').split('/');
+            matches!(
+                (pieces.next(), pieces.next(), pieces.next()),
+                (Some(_), Some("PKG-INFO"), None)
+            )
+        };
+        match self.name.format {
+            SdistFormat::Zip => {
+                let mut z = ZipArchive::new(body)?;
+                for i in 0..z.len() {
+                    let entry = z.by_index(i)?;
+                    if is_pkg_info(entry.name()) {
+                        let (compressed, size) =
+                            (entry.compressed_size(), entry.size());
+                        return Ok(Some(slurp_member_carefully(
+                            entry,
+                            compressed,
+                            size,
+                            &self.limits,
+                        )?));
+                    }
+                }
+            }
+            SdistFormat::TarGz => {
+                let ungz = flate2::read::MultiGzDecoder::new(body);
+                let mut archive = tar::Archive::new(ungz);
+                for entry in archive.entries()? {
+                    let entry = entry?;
+                    let found =
+                        is_pkg_info(&String::from_utf8_lossy(&entry.path_bytes()));
+                    if found {
+                        // (tar doesn't compress members one at a time)
+                        let size = entry.size();
+                        return Ok(Some(slurp_member_carefully(
+                            entry,
+                            size,
+                            size,
+                            &self.limits,
+                        )?));
+                    }
+                }
             }
-            SdistFormat::TarGz => unpack_tar_gz_carefully(body, destination),
         }
+        Ok(None)
     }
 }
 
@@ -79,7 +207,8 @@ impl Artifact for Wheel {
     fn new(name: Self::Name, f: Box<dyn ReadPlusSeek>) -> Result<Self> {
         Ok(Wheel {
             name,
-            z: RefCell::new(ZipArchive::new(f)?),
+            z: RefCell::new(Zip::Open(RemoteZip::new(f)?)),
+            limits: UnpackLimits::default(),
         })
     }
 
@@ -87,6 +216,10 @@ impl Artifact for Wheel {
     fn name(&self) -> &Self::Name {
         &self.name
     }
+
+    fn with_unpack_limits(self, limits: UnpackLimits) -> Self {
+        Wheel { limits, ..self }
+    }
 }
 
 impl Artifact for Pybi {
@@ -95,13 +228,18 @@ impl Artifact for Pybi {
     fn new(name: Self::Name, f: Box<dyn ReadPlusSeek>) -> Result<Self> {
         Ok(Pybi {
             name,
-            z: RefCell::new(ZipArchive::new(f)?),
+            z: RefCell::new(Zip::Open(RemoteZip::new(f)?)),
+            limits: UnpackLimits::default(),
         })
     }
 
     fn name(&self) -> &Self::Name {
         &self.name
     }
+
+    fn with_unpack_limits(self, limits: UnpackLimits) -> Self {
+        Pybi { limits, ..self }
+    }
 }
 
 // This should add a 'Name: BinaryName' bound on Artifact::Name, but that's not stable
@@ -143,14 +281,24 @@ pub trait BinaryArtifact: Artifact {
         ai: &ArtifactInfo,
         platform: &Self::Platform,
     ) -> Option<Result<Self>>;
+
+    /// Like Artifact::new, but trusts `index` to describe `f`'s contents instead of
+    /// reading them; see ZipIndex for when that's ok.
+    fn with_zip_index(
+        name: Self::Name,
+        f: Box<dyn ReadPlusSeek>,
+        index: ZipIndex,
+    ) -> Self;
+
+    /// What to pass to `with_zip_index` next time.
+    fn zip_index(&self) -> Result<ZipIndex>;
 }
 
 fn parse_format_metadata_and_check_version(
     input: &[u8],
     version_field: &str,
 ) -> Result<RFC822ish> {
-    let input: &str = std::str::from_utf8(input)?;
-    let mut parsed = RFC822ish::parse(input)?;
+    let mut parsed = RFC822ish::parse_bytes(input)?;
 
     let version = parsed.take_the(version_field)?;
     if !version.starts_with("1.") {
@@ -160,14 +308,6 @@ fn parse_format_metadata_and_check_version(
     Ok(parsed)
 }
 
-fn slurp_from_zip<T: Read + Seek>(
-    z: &mut ZipArchive<T>,
-    name: &str,
-) -> Result<Vec<u8>> {
-    context!("extracting {name}");
-    slurp(&mut z.by_name(name)?)
-}
-
 struct WheelVitals {
     dist_info: String,
     data: String,
@@ -274,7 +414,7 @@ impl Wheel {
         }
 
         let wheel_path = format!("{dist_info}/WHEEL");
-        let wheel_metadata = slurp_from_zip(&mut z, &wheel_path)?;
+        let wheel_metadata = z.slurp(&wheel_path, &self.limits)?;
 
         let mut parsed =
             parse_format_metadata_and_check_version(&wheel_metadata, "Wheel-Version")?;
@@ -288,24 +428,45 @@ impl Wheel {
             ),
         };
 
+        // We pick between wheels by the build tag in the filename, so that's the one
+        // that counts, but if the two disagree then something's fishy.
+        if let Some(build) = parsed.maybe_take_first("Build") {
+            let from_name =
+                format_build_tag(self.name.build_number, &self.name.build_name);
+            if build.trim() != from_name.trim_start_matches('-') {
+                warn!(
+                    "{wheel_path} says build tag is {build:?}, but filename says {:?}",
+                    from_name.trim_start_matches('-')
+                );
+            }
+        }
+
         let metadata_path = format!("{dist_info}/METADATA");
-        let metadata_blob = slurp_from_zip(&mut z, &metadata_path)?;
+        let metadata_blob = z.slurp(&metadata_path, &self.limits)?;
 
-        let metadata: WheelCoreMetadata = metadata_blob.as_slice().try_into()?;
+        let metadata =
+            WheelCoreMetadata::try_from(metadata_blob.as_slice()).map_err(|err| {
+                MetadataError::Invalid {
+                    what: metadata_path.clone(),
+                    source: err.into(),
+                }
+            })?;
 
         if metadata.name != self.name.distribution {
-            bail!(
-                "name mismatch between {dist_info}/METADATA and filename ({} != {}",
-                metadata.name.as_given(),
-                self.name.distribution.as_given()
-            );
+            Err(MetadataError::Mismatch {
+                path: metadata_path.clone(),
+                field: "name",
+                in_metadata: metadata.name.as_given().into(),
+                in_filename: self.name.distribution.as_given().into(),
+            })?;
         }
         if metadata.version != self.name.version {
-            bail!(
-                "version mismatch between {dist_info}/METADATA and filename ({} != {})",
-                metadata.version,
-                self.name.version
-            );
+            Err(MetadataError::Mismatch {
+                path: metadata_path,
+                field: "version",
+                in_metadata: metadata.version.to_string(),
+                in_filename: self.name.version.to_string(),
+            })?;
         }
 
         Ok(WheelVitals {
@@ -361,6 +522,22 @@ impl BinaryArtifact for Wheel {
             None
         }
     }
+
+    fn with_zip_index(
+        name: Self::Name,
+        f: Box<dyn ReadPlusSeek>,
+        index: ZipIndex,
+    ) -> Self {
+        Self {
+            name,
+            z: RefCell::new(Zip::Indexed(index, f)),
+            limits: UnpackLimits::default(),
+        }
+    }
+
+    fn zip_index(&self) -> Result<ZipIndex> {
+        self.z.borrow_mut().index()
+    }
 }
 
 impl BinaryArtifact for Pybi {
@@ -375,23 +552,32 @@ impl BinaryArtifact for Pybi {
 
     fn metadata(&self) -> Result<(Vec<u8>, Self::Metadata)> {
         let mut z = self.z.borrow_mut();
-        let format_metadata_blob = slurp_from_zip(&mut z, "pybi-info/PYBI")?;
+        let format_metadata_blob = z.slurp("pybi-info/PYBI", &self.limits)?;
         parse_format_metadata_and_check_version(&format_metadata_blob, "Pybi-Version")?;
-        let metadata_blob = slurp_from_zip(&mut z, "pybi-info/METADATA")?;
-        let metadata: PybiCoreMetadata = metadata_blob.as_slice().try_into()?;
+        let metadata_blob = z.slurp("pybi-info/METADATA", &self.limits)?;
+        let path = "pybi-info/METADATA";
+        let metadata =
+            PybiCoreMetadata::try_from(metadata_blob.as_slice()).map_err(|err| {
+                MetadataError::Invalid {
+                    what: path.into(),
+                    source: err.into(),
+                }
+            })?;
         if metadata.name != self.name.distribution {
-            bail!(
-                "name mismatch between pybi/METADATA and filename ({} != {})",
-                metadata.name.as_given(),
-                self.name.distribution.as_given()
-            );
+            Err(MetadataError::Mismatch {
+                path: path.into(),
+                field: "name",
+                in_metadata: metadata.name.as_given().into(),
+                in_filename: self.name.distribution.as_given().into(),
+            })?;
         }
         if metadata.version != self.name.version {
-            bail!(
-                "version mismatch between pybi/METADATA and filename ({} != {})",
-                metadata.version,
-                self.name.version
-            );
+            Err(MetadataError::Mismatch {
+                path: path.into(),
+                field: "version",
+                in_metadata: metadata.version.to_string(),
+                in_filename: self.name.version.to_string(),
+            })?;
         }
         Ok((metadata_blob, metadata))
     }
@@ -410,13 +596,78 @@ impl BinaryArtifact for Pybi {
     ) -> Option<Result<Self>> {
         None
     }
+
+    fn with_zip_index(
+        name: Self::Name,
+        f: Box<dyn ReadPlusSeek>,
+        index: ZipIndex,
+    ) -> Self {
+        Self {
+            name,
+            z: RefCell::new(Zip::Indexed(index, f)),
+            limits: UnpackLimits::default(),
+        }
+    }
+
+    fn zip_index(&self) -> Result<ZipIndex> {
+        self.z.borrow_mut().index()
+    }
 }
 
 impl Pybi {
-    pub fn unpack<T: WriteTree>(&self, destination: &mut T) -> Result<()> {
+    pub fn unpack<T: WriteTree>(
+        &self,
+        destination: &mut T,
+        limits: &UnpackLimits,
+    ) -> Result<()> {
         context!("Unpacking {}", self.name);
-        // XX TODO RECORD?
-        unpack_zip_carefully(&mut self.z.borrow_mut(), destination)
+        unpack_zip_carefully(self.z.borrow_mut().archive()?, destination, limits)
+    }
+
+    /// Every member's name.
+    pub fn file_names(&self) -> Vec<String> {
+        self.z.borrow().file_names().map(String::from).collect()
+    }
+
+    /// Compares everything in the pybi against its pybi-info/RECORD, and describes
+    /// each mismatch. None if there's no RECORD to compare against. That means reading
+    /// every member, so it's held to the same `limits` as unpacking.
+    pub fn check_record(&self, limits: &UnpackLimits) -> Result<Option<Vec<String>>> {
+        context!("Checking {} against its RECORD", self.name);
+        let record_path = "pybi-info/RECORD";
+        let mut z = self.z.borrow_mut();
+        if !z.file_names().any(|name| name == record_path) {
+            return Ok(None);
+        }
+        let record = z.slurp(record_path, limits)?;
+        let record = Record::parse(std::str::from_utf8(&record)?)?;
+        let mut unseen: HashMap<&str, &RecordEntry> = record
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry))
+            .collect();
+        let mut problems = Vec::new();
+        read_zip_carefully(z.archive()?, limits, |member, data| {
+            if member.is_dir {
+                return Ok(());
+            }
+            let name = &member.name;
+            let Some(entry) = unseen.remove(name.as_str()) else {
+                problems.push(format!("{name} isn't listed in RECORD"));
+                return Ok(());
+            };
+            // For symlinks, this is the target, which RECORD doesn't hash anyway
+            if let Err(err) = entry.check_reader(data) {
+                problems.push(format!("{name}: {err}"));
+            }
+            Ok(())
+        })?;
+        let mut missing: Vec<&str> = unseen.into_keys().collect();
+        missing.sort_unstable();
+        for path in missing {
+            problems.push(format!("{path} is listed in RECORD, but isn't there"));
+        }
+        Ok(Some(problems))
     }
 }
 
@@ -446,23 +697,33 @@ fn script_for_entrypoint(entry: &Entrypoint, script_type: ScriptType) -> Vec<u8>
 }
 
 impl Wheel {
-    // XX TODO RECORD?
+    /// Installs the wheel into `dest`, with each of its file categories going where
+    /// `paths` says. The .dist-info ends up the way pip would leave it: INSTALLER
+    /// says posy, a REQUESTED marker if `requested` (the package was asked for
+    /// directly, rather than pulled in as a dependency), and a fresh RECORD listing
+    /// every file we actually wrote, including generated scripts.
     pub fn unpack<W: WriteTree>(
         &self,
         paths: &HashMap<String, NicePathBuf>,
         trampoline_maker: &TrampolineMaker,
+        limits: &UnpackLimits,
+        requested: bool,
         mut dest: W,
     ) -> Result<()> {
         context!("Unpacking {}", self.name);
         let vitals = self.get_vitals()?;
+        let mut recorder = RecordingTree {
+            inner: &mut dest,
+            files: Vec::new(),
+        };
         let mut transformer = WheelTreeTransformer {
             paths,
             trampoline_maker,
-            dest: &mut dest,
+            dest: &mut recorder,
             vitals: &vitals,
         };
         let mut z = self.z.borrow_mut();
-        unpack_zip_carefully(&mut z, &mut transformer)?;
+        unpack_zip_carefully(z.archive()?, &mut transformer, limits)?;
         let mut installer: &[u8] = b"posy\n";
         transformer.write_file(
             &format!("{}/INSTALLER", vitals.dist_info)
@@ -472,11 +733,19 @@ impl Wheel {
             &mut installer,
             false,
         )?;
+        if requested {
+            transformer.write_file(
+                &format!("{}/REQUESTED", vitals.dist_info)
+                    .as_str()
+                    .try_into()
+                    .unwrap(),
+                &mut &b""[..],
+                false,
+            )?;
+        }
 
-        if let Ok(entry_points) = slurp_from_zip(
-            &mut z,
-            format!("{}/{}", vitals.dist_info, "entry_points.txt").as_str(),
-        ) {
+        let entry_points_path = format!("{}/entry_points.txt", vitals.dist_info);
+        if let Ok(entry_points) = z.slurp(&entry_points_path, limits) {
             let entry_points = parse_entry_points(std::str::from_utf8(&entry_points)?)?;
 
             let mut write_scripts = |name, script_type| -> Result<()> {
@@ -498,10 +767,98 @@ impl Wheel {
             write_scripts("console_scripts", ScriptType::Console)?;
             write_scripts("gui_scripts", ScriptType::GUI)?;
         }
+
+        // And last, RECORD, now that we know everything that's in it
+        let record_path = format!("{}/RECORD", vitals.dist_info);
+        let (record_path, _) = transformer
+            .analyze_path(&record_path.as_str().try_into()?)?
+            .unwrap();
+        let record = render_record(&record_path, &recorder.files);
+        dest.write_file(&record_path, &mut record.as_bytes(), false)?;
         Ok(())
     }
 }
 
+/// Passes everything through to `inner`, keeping track of each file's size and
+/// sha256 on the way, for RECORD.
+struct RecordingTree<W: WriteTree> {
+    inner: W,
+    files: Vec<(NicePathBuf, ArtifactHash, u64)>,
+}
+
+struct HashingReader<'a> {
+    inner: &'a mut dyn Read,
+    context: ring::digest::Context,
+    size: u64,
+}
+
+impl Read for HashingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.context.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: WriteTree> WriteTree for RecordingTree<W> {
+    fn mkdir(&mut self, path: &NicePathBuf) -> Result<()> {
+        self.inner.mkdir(path)
+    }
+
+    fn write_file(
+        &mut self,
+        path: &NicePathBuf,
+        data: &mut dyn Read,
+        executable: bool,
+    ) -> Result<()> {
+        let mut reader = HashingReader {
+            inner: data,
+            context: ring::digest::Context::new(&ring::digest::SHA256),
+            size: 0,
+        };
+        self.inner.write_file(path, &mut reader, executable)?;
+        let hash = ArtifactHash {
+            mode: "sha256".into(),
+            raw_data: reader.context.finish().as_ref().to_vec(),
+        };
+        self.files.push((path.clone(), hash, reader.size));
+        Ok(())
+    }
+
+    fn write_symlink(&mut self, symlink: &crate::tree::NiceSymlinkPaths) -> Result<()> {
+        self.inner.write_symlink(symlink)
+    }
+}
+
+/// The RECORD for `files`, as a CSV file at `record_path`. Paths in it are relative to
+/// the directory that contains the .dist-info, like `../../bin/foo` for a script.
+fn render_record(
+    record_path: &NicePathBuf,
+    files: &[(NicePathBuf, ArtifactHash, u64)],
+) -> String {
+    let base = &record_path.pieces()[..record_path.len().saturating_sub(2)];
+    let relative = |path: &NicePathBuf| {
+        let common = base
+            .iter()
+            .zip(path.pieces())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut pieces = vec![".."; base.len() - common];
+        pieces.extend(path.pieces()[common..].iter().map(|p| p.as_str()));
+        pieces.join("/")
+    };
+    let entries = files
+        .iter()
+        .map(|(path, hash, size)| RecordEntry {
+            path: relative(path),
+            hash: Some(hash.clone()),
+            size: Some(*size),
+        })
+        .collect();
+    Record::new(&relative(record_path), entries).render()
+}
+
 struct WheelTreeTransformer<'a, W: WriteTree> {
     paths: &'a HashMap<String, NicePathBuf>,
     trampoline_maker: &'a TrampolineMaker,
@@ -563,6 +920,11 @@ where
         mut data: &mut dyn Read,
         _executable: bool,
     ) -> Result<()> {
+        // The wheel's own RECORD doesn't know about the scripts we generate, or what
+        // we renamed things to, so we write our own instead
+        if path.pieces() == [self.vitals.dist_info.as_str(), "RECORD"] {
+            return Ok(());
+        }
         if let Some((fixed_path, is_script)) = self.analyze_path(path)? {
             if is_script {
                 // use BufReader to "peek" into the start of the executable. Some wheels
@@ -604,3 +966,99 @@ where
         bail!("symlinks not supported in wheels");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trampolines::{FindPython, ScriptPlatform};
+    use crate::tree::WriteTreeFS;
+    use std::fs;
+    use std::io::Cursor;
+    use zip::write::FileOptions;
+
+    fn test_wheel() -> Result<Wheel> {
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut w = zip::ZipWriter::new(&mut buf);
+            let mut add = |name: &str, contents: &str| -> Result<()> {
+                w.start_file(name, FileOptions::default())?;
+                w.write_all(contents.as_bytes())?;
+                Ok(())
+            };
+            add("foo/__init__.py", "def main(): pass\n")?;
+            add("foo-1.0.data/data/share/foo,bar.txt", "hi\n")?;
+            add(
+                "foo-1.0.dist-info/METADATA",
+                "Metadata-Version: 2.1\nName: foo\nVersion: 1.0\n",
+            )?;
+            add(
+                "foo-1.0.dist-info/WHEEL",
+                "Wheel-Version: 1.0\nRoot-Is-Purelib: true\n",
+            )?;
+            add(
+                "foo-1.0.dist-info/entry_points.txt",
+                "[console_scripts]\nfoo = foo:main\n",
+            )?;
+            add("foo-1.0.dist-info/RECORD", "stale,,\n")?;
+            w.finish()?;
+        }
+        buf.set_position(0);
+        Wheel::new("foo-1.0-py3-none-any.whl".try_into()?, Box::new(buf))
+    }
+
+    #[test]
+    fn test_wheel_install_metadata() -> Result<()> {
+        let paths: HashMap<String, NicePathBuf> = HashMap::from([
+            ("scripts".into(), "bin".try_into()?),
+            ("purelib".into(), "lib".try_into()?),
+            ("platlib".into(), "lib".try_into()?),
+            ("data".into(), ".".try_into()?),
+        ]);
+        let trampolines =
+            TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Unix);
+        let tmp = tempfile::tempdir()?;
+        for requested in [false, true] {
+            let root = tmp.path().join(requested.to_string());
+            test_wheel()?.unpack(
+                &paths,
+                &trampolines,
+                &Default::default(),
+                requested,
+                WriteTreeFS::new(&root),
+            )?;
+            let dist_info = root.join("lib/foo-1.0.dist-info");
+            assert_eq!(fs::read(dist_info.join("INSTALLER"))?, b"posy\n");
+            assert_eq!(dist_info.join("REQUESTED").exists(), requested);
+
+            let record = fs::read_to_string(dist_info.join("RECORD"))?;
+            // our one filename with a comma in it gets quoted
+            assert!(record.contains("\n\"../share/foo,bar.txt\",sha256="));
+            let mut listed = Vec::new();
+            for entry in Record::parse(&record)?.entries {
+                if entry.path.ends_with("RECORD") {
+                    assert_eq!((entry.hash, entry.size), (None, None));
+                } else {
+                    let contents = fs::read(root.join("lib").join(&entry.path))?;
+                    assert_eq!(entry.hash.as_ref().unwrap().mode, "sha256");
+                    entry.check(&contents)?;
+                }
+                listed.push(entry.path);
+            }
+            let mut expected = vec![
+                "../bin/foo",
+                "../share/foo,bar.txt",
+                "foo-1.0.dist-info/INSTALLER",
+                "foo-1.0.dist-info/METADATA",
+                "foo-1.0.dist-info/RECORD",
+                "foo-1.0.dist-info/WHEEL",
+                "foo-1.0.dist-info/entry_points.txt",
+                "foo/__init__.py",
+            ];
+            if requested {
+                expected.insert(5, "foo-1.0.dist-info/REQUESTED");
+            }
+            assert_eq!(listed, expected);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 use crate::prelude::*;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::JoinHandle;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, SerializeDisplay, DeserializeFromStr)]
 pub struct ArtifactHash {
@@ -6,23 +8,115 @@ pub struct ArtifactHash {
     pub raw_data: Vec<u8>,
 }
 
+/// The algorithms we know how to check, weakest first. Indexes can publish any
+/// hashlib algorithm, so there might be others floating around, which we carry along
+/// but ignore.
+const ALGORITHMS: &[&str] = &["sha256", "sha384", "blake2b", "sha512"];
+
+enum Hasher {
+    Ring(ring::digest::Context),
+    Blake2b(blake2::Blake2b512),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Ring(context) => context.update(data),
+            Hasher::Blake2b(state) => blake2::Digest::update(state, data),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Ring(context) => context.finish().as_ref().to_vec(),
+            Hasher::Blake2b(state) => blake2::Digest::finalize(state).to_vec(),
+        }
+    }
+}
+
 impl ArtifactHash {
     pub fn from_hex(mode: &str, hex: &str) -> Result<ArtifactHash> {
         Ok(ArtifactHash {
-            mode: mode.into(),
+            mode: mode.to_ascii_lowercase(),
             raw_data: data_encoding::HEXLOWER_PERMISSIVE.decode(hex.as_bytes())?,
         })
     }
 
-    pub fn checker<T: Write>(&self, inner: T) -> Result<HashChecker<T>> {
-        let algorithm = match self.mode.as_str() {
-            "sha256" => &ring::digest::SHA256,
+    /// How much we trust this hash, if we can check it at all; higher is better.
+    pub fn strength(&self) -> Option<usize> {
+        ALGORITHMS.iter().position(|a| *a == self.mode)
+    }
+
+    /// Out of several hashes for the same file, the one we should check it against.
+    pub fn strongest<'a, I>(hashes: I) -> Option<&'a ArtifactHash>
+    where
+        I: IntoIterator<Item = &'a ArtifactHash>,
+    {
+        hashes
+            .into_iter()
+            .filter_map(|h| h.strength().map(|strength| (strength, h)))
+            .max_by_key(|(strength, _)| *strength)
+            .map(|(_, h)| h)
+    }
+
+    fn hasher(&self) -> Result<Hasher> {
+        Ok(match self.mode.as_str() {
+            "sha256" => Hasher::Ring(ring::digest::Context::new(&ring::digest::SHA256)),
+            "sha384" => Hasher::Ring(ring::digest::Context::new(&ring::digest::SHA384)),
+            "sha512" => Hasher::Ring(ring::digest::Context::new(&ring::digest::SHA512)),
+            "blake2b" => Hasher::Blake2b(Default::default()),
             _ => bail!("unknown hash algorithm {}", self.mode),
-        };
-        let state = ring::digest::Context::new(algorithm);
+        })
+    }
+
+    /// The sha256 of everything `reader` has, e.g. for a file we just wrote.
+    pub fn sha256_of<R: Read>(mut reader: R) -> Result<ArtifactHash> {
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        let mut buf = vec![0; 1 << 16];
+        loop {
+            let got = crate::util::retry_interrupted(|| reader.read(&mut buf))?;
+            if got == 0 {
+                break;
+            }
+            context.update(&buf[..got]);
+        }
+        Ok(ArtifactHash {
+            mode: "sha256".into(),
+            raw_data: context.finish().as_ref().to_vec(),
+        })
+    }
+
+    pub fn checker<T: Write>(&self, inner: T) -> Result<HashChecker<T>> {
+        Ok(HashChecker {
+            inner,
+            state: HashState::Inline(self.hasher()?),
+            expected: self,
+        })
+    }
+
+    /// Like `checker`, but does the hashing on a helper thread, so it overlaps with
+    /// whatever's feeding us data and whatever `inner` does with it. Worth it for big
+    /// artifacts, where hashing a multi-gigabyte file is otherwise a long single-core
+    /// stall on top of the download.
+    pub fn background_checker<T: Write>(&self, inner: T) -> Result<HashChecker<T>> {
+        let mut hasher = self.hasher()?;
+        let (tx, rx) = sync_channel::<Vec<u8>>(BACKGROUND_QUEUE_CHUNKS);
+        let thread =
+            std::thread::Builder::new()
+                .name("posy-hash".into())
+                .spawn(move || {
+                    for chunk in rx {
+                        hasher.update(&chunk);
+                    }
+                    hasher.finish()
+                })?;
         Ok(HashChecker {
             inner,
-            state,
+            state: HashState::Background {
+                pending: Vec::with_capacity(BACKGROUND_CHUNK_SIZE),
+                tx,
+                thread,
+            },
             expected: self,
         })
     }
@@ -52,17 +146,71 @@ impl TryFrom<&str> for ArtifactHash {
 
 try_from_str_boilerplate!(ArtifactHash);
 
+/// How much data we hand the background hashing thread at a time, and how many chunks
+/// can be waiting for it before writers block.
+const BACKGROUND_CHUNK_SIZE: usize = 1 << 20;
+const BACKGROUND_QUEUE_CHUNKS: usize = 8;
+
+enum HashState {
+    Inline(Hasher),
+    Background {
+        pending: Vec<u8>,
+        tx: SyncSender<Vec<u8>>,
+        thread: JoinHandle<Vec<u8>>,
+    },
+}
+
+impl HashState {
+    fn update(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            HashState::Inline(hasher) => hasher.update(data),
+            HashState::Background { pending, tx, .. } => {
+                pending.extend_from_slice(data);
+                if pending.len() >= BACKGROUND_CHUNK_SIZE {
+                    let chunk = std::mem::replace(
+                        pending,
+                        Vec::with_capacity(BACKGROUND_CHUNK_SIZE),
+                    );
+                    // only fails if the thread died, which we'll report in finish()
+                    let _ = tx.send(chunk);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            HashState::Inline(hasher) => Ok(hasher.finish()),
+            HashState::Background {
+                pending,
+                tx,
+                thread,
+            } => {
+                let _ = tx.send(pending);
+                drop(tx);
+                thread.join().map_err(|_| eyre!("hashing thread panicked"))
+            }
+        }
+    }
+}
+
 pub struct HashChecker<'a, T: Write> {
     inner: T,
-    state: ring::digest::Context,
+    state: HashState,
     expected: &'a ArtifactHash,
 }
 
 impl<'a, T: Write> HashChecker<'a, T> {
     pub fn finish(self) -> Result<T> {
-        let digest = self.state.finish();
-        if self.expected.raw_data != digest.as_ref() {
-            bail!("hash mismatch: {:?} != {:?}", self.expected, digest);
+        let digest = self.state.finish()?;
+        if self.expected.raw_data != digest {
+            bail!(
+                "hash mismatch: expected {}, got {}={}",
+                self.expected,
+                self.expected.mode,
+                data_encoding::HEXLOWER.encode(&digest)
+            );
         }
         Ok(self.inner)
     }
@@ -71,7 +219,7 @@ impl<'a, T: Write> HashChecker<'a, T> {
 impl<'a, T: Write> Write for HashChecker<'a, T> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let written = self.inner.write(buf)?;
-        self.state.update(&buf[..written]);
+        self.state.update(&buf[..written])?;
         Ok(written)
     }
 
@@ -128,4 +276,57 @@ mod test {
         assert!(bad_checker.flush().is_ok());
         assert!(bad_checker.finish().is_err());
     }
+
+    #[test]
+    fn test_background_checker() {
+        // big enough to go through the queue in several chunks
+        let data = b"a drop of golden sun".repeat(200_000);
+        let hash = {
+            let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+            context.update(&data);
+            let digest = context.finish();
+            ArtifactHash {
+                mode: "sha256".into(),
+                raw_data: digest.as_ref().to_vec(),
+            }
+        };
+
+        let mut checker = hash.background_checker(Vec::<u8>::new()).unwrap();
+        for piece in data.chunks(8192) {
+            checker.write_all(piece).unwrap();
+        }
+        assert_eq!(checker.finish().unwrap(), data);
+
+        let mut checker = hash.background_checker(Vec::<u8>::new()).unwrap();
+        checker.write_all(&data[1..]).unwrap();
+        assert!(checker.finish().is_err());
+    }
+
+    #[test]
+    fn test_multiple_algorithms() {
+        let gold_data = b"a drop of golden sun";
+        let hashes: Vec<ArtifactHash> = [
+            "sha256=9c7ed1509d1809656c86aa1201fde2650ec056ab79f6546ba8205f6e42cff949",
+            "SHA512=c1f19261cd736bf620f67c115a42a6dba9c84ee592211525a27e742aab42d5da\
+             d846672ad0491a52ac34c4388d2f6a3c6f734a2f6996ec6097adddaa0e992399",
+            "blake2b=2d15c41d14eb035007b136553ce903084582861958b18bfb8c8ced5f45a6170b\
+             05a82c174ed4249ea76e70f0aa81364019f35f91c69773a955e84a66abc9c484",
+            "md5=00",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        for hash in &hashes[..3] {
+            let mut checker = hash.checker(Vec::<u8>::new()).unwrap();
+            checker.write_all(gold_data).unwrap();
+            assert!(checker.finish().is_ok(), "{hash}");
+        }
+        assert!(hashes[3].checker(Vec::<u8>::new()).is_err());
+        assert!(hashes[3].background_checker(Vec::<u8>::new()).is_err());
+
+        assert_eq!(hashes[1].mode, "sha512");
+        assert_eq!(ArtifactHash::strongest(&hashes), Some(&hashes[1]));
+        assert_eq!(ArtifactHash::strongest(&hashes[2..]), Some(&hashes[2]));
+        assert_eq!(ArtifactHash::strongest(&hashes[3..]), None);
+    }
 }
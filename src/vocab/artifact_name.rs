@@ -86,8 +86,114 @@ pub struct WheelName {
     pub arch_tags: Vec<String>,
 }
 
-pub trait BinaryName {
+pub trait BinaryName: Ord {
     fn all_tags(&self) -> HashSet<String>;
+
+    /// The build tag, in the order described on WheelName.
+    fn build_tag(&self) -> (Option<u32>, &str);
+}
+
+/// Sort key for choosing between binaries of the same version, where the max is the
+/// one we want: first by how well its tags match the platform (`score`, from
+/// Platform::max_compatibility), then by build tag, since a higher one is a later
+/// rebuild. Last, by the name itself, so that ties always go the same way.
+pub fn binary_preference<N: BinaryName>(
+    score: i32,
+    name: &N,
+) -> (i32, (Option<u32>, &str), std::cmp::Reverse<&N>) {
+    (score, name.build_tag(), std::cmp::Reverse(name))
+}
+
+/// Pure-Python wheels (the ones tagged `any`), or ones built for a platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WheelKind {
+    Pure,
+    Platform,
+}
+
+/// How to choose between the wheels of a pinned version that all work on the target
+/// platform, ahead of `binary_preference`. Each setting that's given beats the
+/// platform match, in the order they're listed here. Set under `[wheel-preference]`
+/// in posy.toml, e.g.
+///
+///   [wheel-preference]
+///   prefer = "pure"
+///   manylinux = "manylinux_2_17"
+///   smallest = true
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct WheelPreference {
+    pub prefer: Option<WheelKind>,
+    /// A manylinux level, like `manylinux_2_17` or `manylinux2014`. Wheels built for
+    /// exactly that level beat ones built for any other.
+    pub manylinux: Option<String>,
+    /// Go for the smallest download, going by the sizes the index reports. Wheels it
+    /// doesn't give a size for lose to ones it does.
+    pub smallest: bool,
+}
+
+/// The (major, minor) glibc version in a manylinux platform tag or level, with or
+/// without an architecture on the end, e.g. (2, 17) for `manylinux2014_x86_64`.
+fn manylinux_level(tag: &str) -> Option<(u32, u32)> {
+    static MANYLINUX_RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^manylinux(?:_([0-9]+)_([0-9]+)|(2014|2010|1))(?:_|$)").unwrap()
+    });
+    let captures = MANYLINUX_RE.captures(tag)?;
+    match captures.get(3).map(|legacy| legacy.as_str()) {
+        Some("2014") => Some((2, 17)),
+        Some("2010") => Some((2, 12)),
+        Some(_) => Some((2, 5)),
+        None => Some((captures[1].parse().ok()?, captures[2].parse().ok()?)),
+    }
+}
+
+impl WheelPreference {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(level) = &self.manylinux {
+            if manylinux_level(level).is_none() {
+                bail!("expected a manylinux level like manylinux_2_17, not {level:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Sort key for choosing between binaries of the same version, where the max is
+    /// the one we want. `score` and `name` are as for `binary_preference`, and `size`
+    /// is the artifact's size in bytes, if we know it.
+    #[allow(clippy::type_complexity)]
+    pub fn key<'a, N: BinaryName>(
+        &self,
+        score: i32,
+        name: &'a N,
+        size: Option<u64>,
+    ) -> (
+        bool,
+        bool,
+        Option<std::cmp::Reverse<u64>>,
+        (i32, (Option<u32>, &'a str), std::cmp::Reverse<&'a N>),
+    ) {
+        let tags = name.all_tags();
+        // the platform part of each py-abi-platform triple
+        let platforms = || tags.iter().filter_map(|tag| tag.rsplit('-').next());
+        let kind_matches = self.prefer.map_or(true, |kind| {
+            let pure = platforms().all(|platform| platform == "any");
+            pure == (kind == WheelKind::Pure)
+        });
+        let level_matches = match self.manylinux.as_deref().and_then(manylinux_level) {
+            Some(level) => {
+                platforms().any(|platform| manylinux_level(platform) == Some(level))
+            }
+            None => true,
+        };
+        let size = size.filter(|_| self.smallest).map(std::cmp::Reverse);
+        (
+            kind_matches,
+            level_matches,
+            size,
+            binary_preference(score, name),
+        )
+    }
 }
 
 impl BinaryName for WheelName {
@@ -102,6 +208,10 @@ impl BinaryName for WheelName {
         }
         retval
     }
+
+    fn build_tag(&self) -> (Option<u32>, &str) {
+        (self.build_number, &self.build_name)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -117,6 +227,10 @@ impl BinaryName for PybiName {
     fn all_tags(&self) -> HashSet<String> {
         self.arch_tags.iter().cloned().collect()
     }
+
+    fn build_tag(&self) -> (Option<u32>, &str) {
+        (self.build_number, &self.build_name)
+    }
 }
 
 fn generic_parse(
@@ -177,7 +291,7 @@ fn generic_parse(
     Ok((distribution, version, build_number, build_name, tag_sets))
 }
 
-fn format_build_tag(build_number: Option<u32>, build_name: &str) -> String {
+pub(super) fn format_build_tag(build_number: Option<u32>, build_name: &str) -> String {
     match (build_number, build_name) {
         (None, "") => String::from(""),
         (None, name) => format!("-{}", name),
@@ -442,4 +556,92 @@ mod test {
 
         assert_eq!(n.to_string(), "foo.bar-0.1b3-1local-win32.win_amd64.pybi");
     }
+
+    #[test]
+    fn test_binary_preference() {
+        let names: Vec<WheelName> = [
+            "foo-1.0-py3-none-any.whl",
+            "foo-1.0-2-py3-none-any.whl",
+            "foo-1.0-10-py3-none-any.whl",
+            "foo-1.0-2rebuild-py3-none-any.whl",
+            "foo-1.0-rebuild-py3-none-any.whl",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        let best = |scored: &[(i32, &WheelName)]| {
+            scored
+                .iter()
+                .max_by_key(|(score, name)| binary_preference(*score, *name))
+                .unwrap()
+                .1
+                .to_string()
+        };
+        let all: Vec<(i32, &WheelName)> = names.iter().map(|n| (0, n)).collect();
+        // 10 > 2, numerically
+        assert_eq!(best(&all), "foo-1.0-10-py3-none-any.whl");
+        assert_eq!(best(&all[..2]), "foo-1.0-2-py3-none-any.whl");
+        assert_eq!(best(&all[3..]), "foo-1.0-2rebuild-py3-none-any.whl");
+        // without a number, it's just the rest of the tag
+        assert_eq!(best(&[all[0], all[4]]), "foo-1.0-rebuild-py3-none-any.whl");
+        // but a better platform match beats any build tag
+        assert_eq!(
+            best(&[(-1, &names[2]), (0, &names[0])]),
+            "foo-1.0-py3-none-any.whl"
+        );
+
+        // and ties don't depend on the order we saw them in
+        let same: Vec<WheelName> =
+            ["foo-1.0-1-py2-none-any.whl", "foo-1.0-1-py3-none-any.whl"]
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect();
+        let forward = best(&[(0, &same[0]), (0, &same[1])]);
+        let backward = best(&[(0, &same[1]), (0, &same[0])]);
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn test_wheel_preference() -> Result<()> {
+        let names: Vec<WheelName> = [
+            "foo-1.0-cp310-cp310-manylinux_2_28_x86_64.whl",
+            "foo-1.0-cp310-cp310-manylinux_2_17_x86_64.manylinux2014_x86_64.whl",
+            "foo-1.0-cp310-cp310-manylinux1_x86_64.whl",
+            "foo-1.0-py3-none-any.whl",
+        ]
+        .iter()
+        .map(|s| s.parse())
+        .collect::<Result<_>>()?;
+        // best platform match first, and pure-Python last
+        let scores = [0, -1, -2, -3];
+        let sizes = [Some(300), Some(200), None, Some(100)];
+        let best = |preference: &WheelPreference| {
+            (0..names.len())
+                .max_by_key(|&i| preference.key(scores[i], &names[i], sizes[i]))
+                .map(|i| names[i].to_string())
+                .unwrap()
+        };
+        let mut preference = WheelPreference::default();
+        assert_eq!(best(&preference), names[0].to_string());
+        preference.smallest = true;
+        assert_eq!(best(&preference), "foo-1.0-py3-none-any.whl");
+        preference.prefer = Some(WheelKind::Platform);
+        assert_eq!(best(&preference), names[1].to_string());
+        // legacy aliases count as the glibc version they stand for
+        preference.manylinux = Some("manylinux_2_5".into());
+        preference.validate()?;
+        assert_eq!(best(&preference), names[2].to_string());
+        preference.manylinux = Some("manylinux2014".into());
+        assert_eq!(best(&preference), names[1].to_string());
+        // a level nothing's built for doesn't rule anything out
+        preference.manylinux = Some("manylinux_2_34".into());
+        preference.smallest = false;
+        assert_eq!(best(&preference), names[0].to_string());
+        preference.prefer = Some(WheelKind::Pure);
+        assert_eq!(best(&preference), "foo-1.0-py3-none-any.whl");
+
+        preference.manylinux = Some("musllinux_1_1".into());
+        assert!(preference.validate().is_err());
+        Ok(())
+    }
 }
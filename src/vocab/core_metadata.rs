@@ -1,3 +1,4 @@
+use crate::error::MetadataError;
 use crate::prelude::*;
 
 use super::rfc822ish::RFC822ish;
@@ -12,6 +13,57 @@ pub struct WheelCoreMetadata {
     pub requires_dist: Vec<PackageRequirement>,
     pub requires_python: Specifiers,
     pub extras: HashSet<Extra>,
+    /// Free-form; sometimes a name, sometimes the whole license text.
+    pub license: Option<String>,
+    /// PEP 639's SPDX expression, e.g. "MIT OR Apache-2.0", tidied up by
+    /// `spdx_expression`
+    pub license_expression: Option<String>,
+    /// PEP 639: paths to license files, relative to the .dist-info/licenses directory
+    pub license_files: Vec<String>,
+    /// PEP 643: which fields might change when this is built, lowercased. None if the
+    /// Metadata-Version is from before 2.2, when there was no such promise, so anything
+    /// might.
+    pub dynamic: Option<Vec<String>>,
+    pub display: DisplayMetadata,
+}
+
+/// The fields that are just for showing to people.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(test, derive(Serialize))]
+pub struct DisplayMetadata {
+    pub summary: Option<String>,
+    pub home_page: Option<String>,
+    /// (label, url) pairs, in the order they were listed
+    pub project_urls: Vec<(String, String)>,
+    pub author: Option<String>,
+    pub author_email: Option<String>,
+    pub classifiers: Vec<String>,
+    /// The long description, usually a README
+    pub description: Option<String>,
+}
+
+impl DisplayMetadata {
+    fn take_from(parsed: &mut RFC822ish) -> DisplayMetadata {
+        let mut project_urls = Vec::new();
+        for entry in parsed.take_all("Project-URL") {
+            // "Label, https://..."
+            match entry.split_once(',') {
+                Some((label, url)) => {
+                    project_urls.push((label.trim().into(), url.trim().into()))
+                }
+                None => project_urls.push((String::new(), entry.trim().into())),
+            }
+        }
+        DisplayMetadata {
+            summary: parsed.maybe_take_first("Summary"),
+            home_page: parsed.maybe_take_first("Home-page"),
+            project_urls,
+            author: parsed.maybe_take_first("Author"),
+            author_email: parsed.maybe_take_first("Author-email"),
+            classifiers: parsed.take_all("Classifier"),
+            description: parsed.take_description(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +74,7 @@ pub struct PybiCoreMetadata {
     pub environment_marker_variables: HashMap<String, String>,
     pub tags: Vec<String>,
     pub paths: HashMap<String, NicePathBuf>,
+    pub display: DisplayMetadata,
 }
 
 impl PybiCoreMetadata {
@@ -33,9 +86,144 @@ impl PybiCoreMetadata {
     }
 }
 
-fn parse_common(input: &[u8]) -> Result<(PackageName, Version, RFC822ish)> {
-    let input = String::from_utf8_lossy(input);
-    let mut parsed = RFC822ish::parse(&input)?;
+/// We parse the same metadata over and over, but once per problem is plenty.
+fn warn_once(message: String) {
+    static WARNED: Lazy<std::sync::Mutex<HashSet<String>>> =
+        Lazy::new(Default::default);
+    let mut warned = WARNED.lock().unwrap();
+    if !warned.contains(&message) {
+        warn!("{message}");
+        warned.insert(message);
+    }
+}
+
+/// An SPDX license expression, parsed: license ids joined by AND/OR/WITH, with
+/// parentheses. WITH binds tightest, then AND, then OR. We don't check the ids against
+/// the SPDX list, since it keeps growing, and `LicenseRef-` ids can be anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseExpression {
+    /// A license id, and the exception it comes WITH, if any
+    License(String, Option<String>),
+    And(Vec<LicenseExpression>),
+    Or(Vec<LicenseExpression>),
+}
+
+type SpdxTokens<'a> = std::iter::Peekable<std::slice::Iter<'a, String>>;
+
+const SPDX_OPERATORS: [&str; 3] = ["AND", "OR", "WITH"];
+
+/// How deep parentheses can nest. Real expressions need one or two levels; this just
+/// keeps a hostile one from overflowing the stack.
+const SPDX_MAX_DEPTH: usize = 32;
+
+fn spdx_tokens(input: &str) -> Vec<String> {
+    input
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+fn is_spdx_operator(token: &str) -> bool {
+    SPDX_OPERATORS
+        .iter()
+        .any(|op| token.eq_ignore_ascii_case(op))
+}
+
+impl LicenseExpression {
+    pub fn parse(input: &str) -> Result<LicenseExpression> {
+        let tokens = spdx_tokens(input);
+        let mut tokens = tokens.iter().peekable();
+        match Self::or_expr(&mut tokens, 0) {
+            Some(expr) if tokens.next().is_none() => Ok(expr),
+            _ => bail!("invalid SPDX license expression {input:?}"),
+        }
+    }
+
+    /// Whether this can be satisfied with only the license ids that `ok` accepts: for
+    /// `A OR B` we get to pick, for `A AND B` we need both, and a `WITH` exception
+    /// doesn't change which license it is. `ok` gets called on every id, even once the
+    /// answer is known.
+    pub fn satisfiable(&self, ok: &mut dyn FnMut(&str) -> bool) -> bool {
+        match self {
+            LicenseExpression::License(id, _) => ok(id),
+            LicenseExpression::And(terms) => terms
+                .iter()
+                .fold(true, |all, term| term.satisfiable(ok) && all),
+            LicenseExpression::Or(terms) => terms
+                .iter()
+                .fold(false, |any, term| term.satisfiable(ok) || any),
+        }
+    }
+
+    fn or_expr(tokens: &mut SpdxTokens, depth: usize) -> Option<LicenseExpression> {
+        let mut terms = vec![Self::and_expr(tokens, depth)?];
+        while tokens.next_if(|t| t.eq_ignore_ascii_case("OR")).is_some() {
+            terms.push(Self::and_expr(tokens, depth)?);
+        }
+        Some(match terms.len() {
+            1 => terms.pop().unwrap(),
+            _ => LicenseExpression::Or(terms),
+        })
+    }
+
+    fn and_expr(tokens: &mut SpdxTokens, depth: usize) -> Option<LicenseExpression> {
+        let mut terms = vec![Self::atom(tokens, depth)?];
+        while tokens.next_if(|t| t.eq_ignore_ascii_case("AND")).is_some() {
+            terms.push(Self::atom(tokens, depth)?);
+        }
+        Some(match terms.len() {
+            1 => terms.pop().unwrap(),
+            _ => LicenseExpression::And(terms),
+        })
+    }
+
+    fn atom(tokens: &mut SpdxTokens, depth: usize) -> Option<LicenseExpression> {
+        let token = tokens.next()?;
+        if token == "(" {
+            if depth >= SPDX_MAX_DEPTH {
+                return None;
+            }
+            let inner = Self::or_expr(tokens, depth + 1)?;
+            return (tokens.next()? == ")").then_some(inner);
+        }
+        let license = Self::id(token)?;
+        let exception = match tokens.next_if(|t| t.eq_ignore_ascii_case("WITH")) {
+            Some(_) => Some(Self::id(tokens.next()?)?),
+            None => None,
+        };
+        Some(LicenseExpression::License(license, exception))
+    }
+
+    fn id(token: &str) -> Option<String> {
+        let valid = !is_spdx_operator(token)
+            && token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-.+:".contains(c));
+        valid.then(|| token.to_owned())
+    }
+}
+
+/// Checks that a License-Expression parses (see `LicenseExpression`), and normalizes
+/// the whitespace and the case of the operators.
+fn spdx_expression(input: &str) -> Result<String> {
+    LicenseExpression::parse(input)?;
+    let tokens: Vec<String> = spdx_tokens(input)
+        .into_iter()
+        .map(|t| match is_spdx_operator(&t) {
+            true => t.to_ascii_uppercase(),
+            false => t,
+        })
+        .collect();
+    Ok(tokens.join(" ").replace("( ", "(").replace(" )", ")"))
+}
+
+static FIRST_DYNAMIC_METADATA_VERSION: Lazy<Version> =
+    Lazy::new(|| "2.2".try_into().unwrap());
+
+fn parse_common(input: &[u8]) -> Result<(Version, PackageName, Version, RFC822ish)> {
+    let mut parsed = RFC822ish::parse_bytes(input)?;
 
     static NEXT_MAJOR_METADATA_VERSION: Lazy<Version> =
         Lazy::new(|| "3".try_into().unwrap());
@@ -63,6 +251,7 @@ fn parse_common(input: &[u8]) -> Result<(PackageName, Version, RFC822ish)> {
     }
 
     Ok((
+        metadata_version,
         parsed.take_the("Name")?.parse()?,
         parsed.take_the("Version")?.try_into()?,
         parsed,
@@ -73,11 +262,42 @@ impl TryFrom<&[u8]> for WheelCoreMetadata {
     type Error = eyre::Report;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let (name, version, mut parsed) = parse_common(value)?;
+        let (metadata_version, name, version, mut parsed) = parse_common(value)?;
+
+        let dynamic = if metadata_version >= *FIRST_DYNAMIC_METADATA_VERSION {
+            let mut dynamic = Vec::new();
+            for field in parsed.take_all("Dynamic") {
+                let field = field.trim().to_ascii_lowercase();
+                // PEP 643: "Name, Version, and Metadata-Version MUST NOT be dynamic"
+                if ["name", "version", "metadata-version"].contains(&field.as_str()) {
+                    return Err(MetadataError::Invalid {
+                        what: format!("the metadata for {} {version}", name.as_given()),
+                        source: eyre!("{field} can't be Dynamic").into(),
+                    }
+                    .into());
+                }
+                dynamic.push(field);
+            }
+            Some(dynamic)
+        } else {
+            None
+        };
 
         let mut requires_dist = Vec::new();
         for req_str in parsed.take_all("Requires-Dist").drain(..) {
-            requires_dist.push(req_str.try_into()?);
+            match PackageRequirement::parse_lenient(&req_str) {
+                Ok((req, fixes)) => {
+                    for fix in fixes {
+                        warn_once(format!("{} {version}: {fix}", name.as_given()));
+                    }
+                    requires_dist.push(req);
+                }
+                // resolving without it would quietly leave the dependency out
+                Err(err) => bail!(
+                    "{} {version}: can't parse Requires-Dist: {req_str}: {err:#}",
+                    name.as_given()
+                ),
+            }
         }
 
         let requires_python = match parsed.maybe_take_the("Requires-Python")? {
@@ -96,15 +316,160 @@ impl TryFrom<&[u8]> for WheelCoreMetadata {
             requires_dist,
             requires_python,
             extras,
+            license: parsed.maybe_take_text("License"),
+            license_expression: match parsed.maybe_take_the("License-Expression")? {
+                Some(expr) => match spdx_expression(&expr) {
+                    Ok(expr) => Some(expr),
+                    Err(err) => {
+                        warn_once(format!("{} {version}: {err}", name.as_given()));
+                        None
+                    }
+                },
+                None => None,
+            },
+            license_files: parsed.take_all("License-File"),
+            dynamic,
+            display: DisplayMetadata::take_from(&mut parsed),
         })
     }
 }
 
+impl WheelCoreMetadata {
+    /// PEP 643: if an sdist's PKG-INFO has Metadata-Version 2.2 or later, then every
+    /// field that isn't listed in `Dynamic` is guaranteed to be the same in any wheel
+    /// built from it. So if none of the fields that the resolver looks at are dynamic,
+    /// we can use the PKG-INFO directly and skip running the build backend.
+    ///
+    /// Returns None if the PKG-INFO isn't good enough to use that way.
+    pub fn from_static_pkg_info(value: &[u8]) -> Result<Option<WheelCoreMetadata>> {
+        let metadata: WheelCoreMetadata = value.try_into()?;
+        Ok(metadata.resolver_fields_are_static().then_some(metadata))
+    }
+
+    /// Whether the fields that the resolver looks at are guaranteed not to change
+    /// when this is built.
+    pub fn resolver_fields_are_static(&self) -> bool {
+        static RESOLVER_FIELDS: &[&str] =
+            &["requires-dist", "requires-python", "provides-extra"];
+
+        match &self.dynamic {
+            Some(dynamic) => !dynamic
+                .iter()
+                .any(|field| RESOLVER_FIELDS.contains(&field.as_str())),
+            None => false,
+        }
+    }
+
+    /// What this says its license is, as an SPDX expression: the License-Expression
+    /// if there is one, or else whatever License classifiers we can translate,
+    /// joined with OR. (Listing several classifiers usually means you get to pick,
+    /// and the free-form License field is hopeless.)
+    pub fn spdx_license(&self) -> Option<String> {
+        if let Some(expr) = &self.license_expression {
+            return Some(expr.clone());
+        }
+        let ids: Vec<&str> = self
+            .display
+            .classifiers
+            .iter()
+            .filter_map(|c| c.strip_prefix("License :: "))
+            .filter_map(|c| {
+                LICENSE_CLASSIFIERS
+                    .iter()
+                    .find(|(classifier, _)| *classifier == c)
+                    .map(|(_, id)| *id)
+            })
+            .collect();
+        if ids.is_empty() {
+            None
+        } else if ids.len() == 1 {
+            Some(ids[0].to_owned())
+        } else {
+            Some(ids.join(" OR "))
+        }
+    }
+}
+
+/// License classifiers that name one specific license, and its SPDX id. Ones like
+/// "BSD License" or "GNU General Public License (GPL)" don't say which version, so
+/// they aren't here.
+static LICENSE_CLASSIFIERS: &[(&str, &str)] = &[
+    ("OSI Approved :: Apache Software License", "Apache-2.0"),
+    ("OSI Approved :: MIT License", "MIT"),
+    (
+        "OSI Approved :: MIT No Attribution License (MIT-0)",
+        "MIT-0",
+    ),
+    ("OSI Approved :: ISC License (ISCL)", "ISC"),
+    (
+        "OSI Approved :: Python Software Foundation License",
+        "PSF-2.0",
+    ),
+    (
+        "OSI Approved :: Mozilla Public License 2.0 (MPL 2.0)",
+        "MPL-2.0",
+    ),
+    (
+        "OSI Approved :: Eclipse Public License 2.0 (EPL-2.0)",
+        "EPL-2.0",
+    ),
+    ("OSI Approved :: The Unlicense (Unlicense)", "Unlicense"),
+    ("OSI Approved :: zlib/libpng License", "Zlib"),
+    (
+        "OSI Approved :: Boost Software License 1.0 (BSL-1.0)",
+        "BSL-1.0",
+    ),
+    (
+        "OSI Approved :: GNU Affero General Public License v3",
+        "AGPL-3.0-only",
+    ),
+    (
+        "OSI Approved :: GNU Affero General Public License v3 or later (AGPLv3+)",
+        "AGPL-3.0-or-later",
+    ),
+    (
+        "OSI Approved :: GNU General Public License v2 (GPLv2)",
+        "GPL-2.0-only",
+    ),
+    (
+        "OSI Approved :: GNU General Public License v2 or later (GPLv2+)",
+        "GPL-2.0-or-later",
+    ),
+    (
+        "OSI Approved :: GNU General Public License v3 (GPLv3)",
+        "GPL-3.0-only",
+    ),
+    (
+        "OSI Approved :: GNU General Public License v3 or later (GPLv3+)",
+        "GPL-3.0-or-later",
+    ),
+    (
+        "OSI Approved :: GNU Lesser General Public License v2 (LGPLv2)",
+        "LGPL-2.0-only",
+    ),
+    (
+        "OSI Approved :: GNU Lesser General Public License v2 or later (LGPLv2+)",
+        "LGPL-2.0-or-later",
+    ),
+    (
+        "OSI Approved :: GNU Lesser General Public License v3 (LGPLv3)",
+        "LGPL-3.0-only",
+    ),
+    (
+        "OSI Approved :: GNU Lesser General Public License v3 or later (LGPLv3+)",
+        "LGPL-3.0-or-later",
+    ),
+    (
+        "CC0 1.0 Universal (CC0 1.0) Public Domain Dedication",
+        "CC0-1.0",
+    ),
+];
+
 impl TryFrom<&[u8]> for PybiCoreMetadata {
     type Error = eyre::Report;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let (name, version, mut parsed) = parse_common(value)?;
+        let (_, name, version, mut parsed) = parse_common(value)?;
 
         Ok(PybiCoreMetadata {
             name,
@@ -114,6 +479,7 @@ impl TryFrom<&[u8]> for PybiCoreMetadata {
             )?,
             tags: parsed.take_all("Pybi-Wheel-Tag"),
             paths: serde_json::from_str(&parsed.take_the("Pybi-Paths")?)?,
+            display: DisplayMetadata::take_from(&mut parsed),
         })
     }
 }
@@ -130,6 +496,11 @@ mod test {
             Name: trio
             Version: 0.16.0
             Summary: A friendly Python library for async concurrency and I/O
+            Home-page: https://github.com/python-trio/trio
+            Author: Nathaniel J. Smith
+            Author-email: njs@pobox.com
+            Project-URL: Documentation, https://trio.readthedocs.io
+            Project-URL: Changelog, https://trio.readthedocs.io/en/latest/history.html
             Classifier: Framework :: Trio
             Requires-Python: >=3.6
             Requires-Dist: attrs (>=19.2.0)
@@ -153,10 +524,126 @@ mod test {
           ],
           requires_python: ">= 3.6",
           extras: [],
+          license: None,
+          license_expression: None,
+          license_files: [],
+          dynamic: None,
+          display: DisplayMetadata(
+            summary: Some("A friendly Python library for async concurrency and I/O"),
+            home_page: Some("https://github.com/python-trio/trio"),
+            project_urls: [
+              ("Documentation", "https://trio.readthedocs.io"),
+              ("Changelog", "https://trio.readthedocs.io/en/latest/history.html"),
+            ],
+            author: Some("Nathaniel J. Smith"),
+            author_email: Some("njs@pobox.com"),
+            classifiers: [
+              "Framework :: Trio",
+            ],
+            description: Some("The Trio project\'s goal is..."),
+          ),
         )
         "###);
     }
 
+    #[test]
+    fn test_sloppy_requires_dist() {
+        let metadata_text = indoc! {r#"
+            Metadata-Version: 2.1
+            Name: sloppy
+            Version: 1.0
+            Requires-Dist: attrs >= 19.*
+            Requires-Dist: sortedcontainers,
+        "#};
+
+        let metadata: WheelCoreMetadata = metadata_text.as_bytes().try_into().unwrap();
+        let reqs: Vec<String> = metadata
+            .requires_dist
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(reqs, vec!["attrs >= 19", "sortedcontainers"]);
+
+        // but past fixing, it's an error, not a missing dependency
+        let broken = format!(
+            "{metadata_text}{}",
+            r#"Requires-Dist: outcome; python_version >> "3""#
+        );
+        let err = WheelCoreMetadata::try_from(broken.as_bytes()).unwrap_err();
+        assert!(format!("{err:#}").contains(r#"outcome; python_version >> "3""#));
+    }
+
+    #[test]
+    fn test_license_metadata() {
+        let metadata_text = indoc! {r#"
+            Metadata-Version: 2.4
+            Name: licensed
+            Version: 1.0
+            License-Expression: (mit or Apache-2.0)  AND LicenseRef-Custom
+            License-File: LICENSE
+            License-File: vendor/LICENSE.txt
+        "#}
+        .as_bytes();
+
+        let metadata: WheelCoreMetadata = metadata_text.try_into().unwrap();
+        assert_eq!(
+            metadata.license_expression.as_deref(),
+            Some("(mit OR Apache-2.0) AND LicenseRef-Custom")
+        );
+        assert_eq!(
+            metadata.license_files,
+            vec!["LICENSE", "vendor/LICENSE.txt"]
+        );
+
+        for bad in ["MIT OR", "(MIT", "MIT Apache-2.0", "MIT/X11", ""] {
+            assert!(spdx_expression(bad).is_err(), "{bad:?}");
+        }
+        let nested = |depth| format!("{}MIT{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(LicenseExpression::parse(&nested(SPDX_MAX_DEPTH)).is_ok());
+        assert!(LicenseExpression::parse(&nested(SPDX_MAX_DEPTH + 1)).is_err());
+        assert!(LicenseExpression::parse(&nested(100_000)).is_err());
+        assert_eq!(
+            spdx_expression("GPL-2.0-or-later WITH Classpath-exception-2.0").unwrap(),
+            "GPL-2.0-or-later WITH Classpath-exception-2.0"
+        );
+        assert_eq!(
+            LicenseExpression::parse("mit or Apache-2.0 and (BSD-3-Clause)").unwrap(),
+            LicenseExpression::Or(vec![
+                LicenseExpression::License("mit".into(), None),
+                LicenseExpression::And(vec![
+                    LicenseExpression::License("Apache-2.0".into(), None),
+                    LicenseExpression::License("BSD-3-Clause".into(), None),
+                ]),
+            ])
+        );
+
+        assert_eq!(
+            metadata.spdx_license().as_deref(),
+            Some("(mit OR Apache-2.0) AND LicenseRef-Custom")
+        );
+        let classified = |classifiers: &str| -> Option<String> {
+            let text =
+                format!("Metadata-Version: 2.1\nName: c\nVersion: 1\n{classifiers}");
+            WheelCoreMetadata::try_from(text.as_bytes())
+                .unwrap()
+                .spdx_license()
+        };
+        assert_eq!(
+            classified(indoc! {"
+                Classifier: License :: OSI Approved :: MIT License
+                Classifier: License :: OSI Approved :: Apache Software License
+                Classifier: Programming Language :: Python
+            "})
+            .as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(
+            classified("Classifier: License :: OSI Approved :: BSD License\n"),
+            None
+        );
+        assert_eq!(classified("License: MIT\n"), None);
+    }
+
     #[test]
     fn test_basic_pybi_parse() {
         let metadata_text = indoc! {r#"
@@ -195,8 +682,61 @@ mod test {
             "data": ".",
             "include": "include/python3.11",
           },
+          display: DisplayMetadata(
+            summary: None,
+            home_page: None,
+            project_urls: [],
+            author: None,
+            author_email: None,
+            classifiers: [],
+            description: Some("This is CPython, the standard interpreter for the Python language..."),
+          ),
         )
         "###
         );
     }
+
+    #[test]
+    fn test_static_pkg_info() {
+        let static_pkg_info = indoc! {r#"
+            Metadata-Version: 2.2
+            Name: peewee
+            Version: 3.15.4
+            Dynamic: Description
+            Requires-Dist: attrs
+        "#};
+        let metadata =
+            WheelCoreMetadata::from_static_pkg_info(static_pkg_info.as_bytes())
+                .unwrap()
+                .unwrap();
+        assert_eq!(metadata.requires_dist.len(), 1);
+
+        let dynamic_deps = static_pkg_info.replace("Description", "requires-dist");
+        assert!(
+            WheelCoreMetadata::from_static_pkg_info(dynamic_deps.as_bytes())
+                .unwrap()
+                .is_none()
+        );
+
+        let old_version = static_pkg_info.replace("2.2", "2.1");
+        assert!(
+            WheelCoreMetadata::from_static_pkg_info(old_version.as_bytes())
+                .unwrap()
+                .is_none()
+        );
+
+        // Newer minor versions make the same promise
+        let newer_version = static_pkg_info.replace("2.2", "2.4");
+        let metadata: WheelCoreMetadata = newer_version.as_bytes().try_into().unwrap();
+        assert_eq!(metadata.dynamic, Some(vec!["description".to_string()]));
+        assert!(metadata.resolver_fields_are_static());
+
+        let dynamic_version = static_pkg_info.replace("Description", "Version");
+        let err = WheelCoreMetadata::from_static_pkg_info(dynamic_version.as_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MetadataError>(),
+            Some(MetadataError::Invalid { what, .. }) if what.contains("peewee 3.15.4")
+        ));
+    }
 }
@@ -1,19 +1,17 @@
-// 'Extra' string format is not well specified. It looks like what pip does is
-// run things through pkg_resources.safe_extra, which does:
+// PEP 685 says extras are validated and normalized exactly like package names: case
+// doesn't matter, and runs of '-', '_', and '.' are all equivalent. So 'foo[SSL]' and
+// 'foo[ssl]' are the same thing, and 'Provides-Extra: tests_cov' provides 'tests-cov'.
 //
-//   re.sub('[^A-Za-z0-9.-]+', '_', extra).lower()
-//
-// So A-Z becomes a-z, a-z 0-9 . - are preserved, and any contiguous run of
-// other characters becomes a single _.
+// Before PEP 685, pip ran things through pkg_resources.safe_extra, which does:
 //
-// OTOH, PEP 508's grammar for requirement specifiers says that extras have to
-// be "identifiers", which means: first char [A-Za-z0-9], remaining chars also
-// allowed to include -_.
+//   re.sub('[^A-Za-z0-9.-]+', '_', extra).lower()
 //
-// I guess for now I'll just pretend that they act the same as package names,
-// and see how long I can get away with it.
+// That maps a few invalid names onto valid ones, but for anything valid, it gives
+// something that normalizes the same way as the original, so old metadata still
+// matches up.
 //
-// There's probably a better way to factor this and reduce code duplication...
+// Equality and hashing go through PackageName, so they only look at the normalized
+// form; the as-given form is just for display.
 
 use crate::prelude::*;
 
@@ -40,3 +38,23 @@ impl TryFrom<&str> for Extra {
 }
 
 try_from_str_boilerplate!(Extra);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extra_normalization() {
+        let given: Extra = "Tests_Cov".try_into().unwrap();
+        assert_eq!(given.as_given(), "Tests_Cov");
+        assert_eq!(given.normalized(), "tests-cov");
+
+        let provided = HashSet::from([given]);
+        for spelling in ["tests-cov", "TESTS.COV", "tests__cov"] {
+            let extra: Extra = spelling.try_into().unwrap();
+            assert!(provided.contains(&extra), "{spelling}");
+        }
+        let other: Extra = "testscov".try_into().unwrap();
+        assert!(!provided.contains(&other));
+    }
+}
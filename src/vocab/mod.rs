@@ -5,6 +5,7 @@ mod core_metadata;
 mod entry_points;
 mod extra;
 mod package_name;
+mod record;
 mod reqparse;
 mod requirement;
 mod rfc822ish;
@@ -16,13 +17,16 @@ mod version;
 pub use self::artifact_formats::{Artifact, BinaryArtifact, Pybi, Sdist, Wheel};
 pub use self::artifact_hash::ArtifactHash;
 pub use self::artifact_name::{
-    ArtifactName, BinaryName, PybiName, SdistFormat, SdistName, UnwrapFromArtifactName,
-    WheelName,
+    binary_preference, ArtifactName, BinaryName, PybiName, SdistFormat, SdistName,
+    UnwrapFromArtifactName, WheelKind, WheelName, WheelPreference,
+};
+pub use self::core_metadata::{
+    DisplayMetadata, LicenseExpression, PybiCoreMetadata, WheelCoreMetadata,
 };
-pub use self::core_metadata::{PybiCoreMetadata, WheelCoreMetadata};
 pub use self::entry_points::{parse_entry_points, Entrypoint};
 pub use self::extra::Extra;
 pub use self::package_name::PackageName;
+pub use self::record::{format_record_hash, parse_record_hash, Record, RecordEntry};
 pub use self::requirement::{
     marker, PackageRequirement, PythonRequirement, Requirement, StandaloneMarkerExpr,
     UserRequirement,
@@ -1,11 +1,26 @@
 use crate::prelude::*;
+use std::sync::{Arc, Mutex};
 
+// Names get cloned everywhere (every resolver package, every map key), so the strings
+// are interned: cloning is a refcount bump, and comparing two interned names is
+// usually just a pointer comparison.
 #[derive(Debug, Clone, DeserializeFromStr, Derivative)]
 #[derivative(Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PackageName {
     #[derivative(Hash = "ignore", PartialEq = "ignore", PartialOrd = "ignore")]
-    as_given: String,
-    normalized: String,
+    as_given: Arc<str>,
+    normalized: Arc<str>,
+}
+
+fn intern(s: &str) -> Arc<str> {
+    static INTERNED: Lazy<Mutex<HashSet<Arc<str>>>> = Lazy::new(Default::default);
+    let mut interned = INTERNED.lock().unwrap();
+    if let Some(existing) = interned.get(s) {
+        return existing.clone();
+    }
+    let new: Arc<str> = s.into();
+    interned.insert(new.clone());
+    new
 }
 
 impl PackageName {
@@ -28,19 +43,17 @@ impl TryFrom<&str> for PackageName {
         });
         // https://www.python.org/dev/peps/pep-0503/#normalized-names
         static NAME_NORMALIZE: Lazy<Regex> =
-            Lazy::new(|| Regex::new(r"[-_.]").unwrap());
+            Lazy::new(|| Regex::new(r"[-_.]+").unwrap());
 
         if !NAME_VALIDATE.is_match(as_given) {
             return Err(eyre!("Invalid package name {:?}", as_given));
         }
-        let as_given = as_given.to_owned();
-
-        let mut normalized = NAME_NORMALIZE.replace_all(&as_given, "-").to_string();
+        let mut normalized = NAME_NORMALIZE.replace_all(as_given, "-").to_string();
         normalized.make_ascii_lowercase();
 
         Ok(PackageName {
-            as_given,
-            normalized,
+            as_given: intern(as_given),
+            normalized: intern(&normalized),
         })
     }
 }
@@ -74,6 +87,20 @@ mod test {
 
         let name3: PackageName = "foo-barbaz".try_into().unwrap();
         assert_ne!(name1, name3);
+
+        let name4: PackageName = "foo__bar-.baz".try_into().unwrap();
+        assert_eq!(name4.normalized(), "foo-bar-baz");
+    }
+
+    #[test]
+    fn test_packagename_interning() {
+        let name1: PackageName = "Foo-Bar".try_into().unwrap();
+        let name2: PackageName = "foo_bar".try_into().unwrap();
+        let name3: PackageName = "foo_bar".try_into().unwrap();
+        assert!(Arc::ptr_eq(&name1.normalized, &name2.normalized));
+        assert!(Arc::ptr_eq(&name2.as_given, &name3.as_given));
+        assert!(!Arc::ptr_eq(&name1.as_given, &name2.as_given));
+        assert_eq!(name1.as_given(), "Foo-Bar");
     }
 
     #[test]
@@ -0,0 +1,277 @@
+use crate::prelude::*;
+
+// RECORD files, as in
+// https://packaging.python.org/en/latest/specifications/recording-installed-packages/
+//
+// They're CSV the way Python's csv module writes it: fields with commas, quotes, or
+// newlines in them get quoted, with "" for a literal quote. Each row is a path, then
+// `algorithm=digest` with the digest in unpadded urlsafe base64, then the size. The
+// RECORD file lists itself too, but can't know its own hash, so that row leaves the
+// last two fields empty -- and so do rows for .pyc files that get written after the
+// fact.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordEntry {
+    /// Relative to the directory containing the .dist-info (or .pybi-info), with `/`
+    /// separators. Installed scripts and data files can start with `../`.
+    pub path: String,
+    pub hash: Option<ArtifactHash>,
+    pub size: Option<u64>,
+}
+
+impl RecordEntry {
+    /// The entry for a file at `path` containing `data`, hashed with sha256 like
+    /// everyone else does.
+    #[cfg_attr(not(feature = "cli"), allow(dead_code))]
+    pub fn for_data(path: &str, data: &[u8]) -> RecordEntry {
+        let digest = ring::digest::digest(&ring::digest::SHA256, data);
+        RecordEntry {
+            path: path.into(),
+            hash: Some(ArtifactHash {
+                mode: "sha256".into(),
+                raw_data: digest.as_ref().to_vec(),
+            }),
+            size: Some(data.len() as u64),
+        }
+    }
+
+    /// Fails unless `data` matches whichever of the hash and size this entry has.
+    pub fn check(&self, mut data: &[u8]) -> Result<()> {
+        self.check_reader(&mut data)
+    }
+
+    /// Like `check`, but reads the data as it goes, instead of needing it all in
+    /// memory first.
+    pub fn check_reader(&self, data: &mut dyn Read) -> Result<()> {
+        context!("checking {} against RECORD", self.path);
+        let (len, checker) = match &self.hash {
+            Some(hash) => {
+                let mut checker = hash.checker(std::io::sink())?;
+                (std::io::copy(data, &mut checker)?, Some(checker))
+            }
+            None => (std::io::copy(data, &mut std::io::sink())?, None),
+        };
+        if let Some(size) = self.size {
+            if len != size {
+                bail!("RECORD says {size} bytes, but it's {len}");
+            }
+        }
+        if let Some(checker) = checker {
+            checker.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// `algorithm=digest`, with RECORD's base64 digest instead of our usual hex.
+pub fn parse_record_hash(value: &str) -> Result<ArtifactHash> {
+    let (mode, digest) = value
+        .split_once('=')
+        .ok_or_else(|| eyre!("expected = in RECORD hash {value:?}"))?;
+    // The spec says no padding, but some tools write it anyway
+    let digest = digest.trim_end_matches('=');
+    Ok(ArtifactHash {
+        mode: mode.to_ascii_lowercase(),
+        raw_data: data_encoding::BASE64URL_NOPAD.decode(digest.as_bytes())?,
+    })
+}
+
+pub fn format_record_hash(hash: &ArtifactHash) -> String {
+    let digest = data_encoding::BASE64URL_NOPAD.encode(&hash.raw_data);
+    format!("{}={digest}", hash.mode)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Record {
+    pub entries: Vec<RecordEntry>,
+}
+
+impl Record {
+    /// A complete RECORD for `files`, that also lists itself at `record_path`. Sorted
+    /// by path, so the same files always give the same RECORD.
+    pub fn new(record_path: &str, files: Vec<RecordEntry>) -> Record {
+        let mut entries: Vec<RecordEntry> = files
+            .into_iter()
+            .filter(|entry| entry.path != record_path)
+            .collect();
+        entries.push(RecordEntry {
+            path: record_path.into(),
+            hash: None,
+            size: None,
+        });
+        entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        Record { entries }
+    }
+
+    pub fn parse(text: &str) -> Result<Record> {
+        let mut entries = Vec::new();
+        for row in parse_csv(text)? {
+            let [path, hash, size]: [String; 3] =
+                row.try_into().map_err(|row: Vec<String>| {
+                    eyre!("RECORD rows have 3 fields, not {}: {row:?}", row.len())
+                })?;
+            if path.is_empty() {
+                bail!("RECORD row with no path");
+            }
+            context!("parsing RECORD entry for {path}");
+            let hash = match hash.as_str() {
+                "" => None,
+                hash => Some(parse_record_hash(hash)?),
+            };
+            let size = match size.as_str() {
+                "" => None,
+                size => Some(size.parse()?),
+            };
+            entries.push(RecordEntry { path, hash, size });
+        }
+        Ok(Record { entries })
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            let hash = entry.hash.as_ref().map(format_record_hash);
+            let size = entry.size.map(|size| size.to_string());
+            let fields = [
+                entry.path.as_str(),
+                hash.as_deref().unwrap_or(""),
+                size.as_deref().unwrap_or(""),
+            ];
+            let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            text.push_str(&fields.join(","));
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn get(&self, path: &str) -> Option<&RecordEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.into()
+    }
+}
+
+/// Rows of a CSV file, skipping blank lines. Quoted fields can have anything in them,
+/// newlines included.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    // whether the current row has anything in it yet, so blank lines don't count
+    let mut started = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                started = true;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => field.push(c),
+                        None => bail!("unterminated quoted field in CSV"),
+                    }
+                }
+                if !matches!(chars.peek(), None | Some(',' | '\r' | '\n')) {
+                    bail!("unexpected text after a quoted field in CSV");
+                }
+            }
+            ',' => {
+                started = true;
+                row.push(std::mem::take(&mut field));
+            }
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                if started {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                started = false;
+            }
+            c => {
+                started = true;
+                field.push(c);
+            }
+        }
+    }
+    if started {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_roundtrip() -> Result<()> {
+        let record = Record::new(
+            "foo-1.0.dist-info/RECORD",
+            vec![
+                RecordEntry::for_data("foo/__init__.py", b"print('hi')\n"),
+                RecordEntry::for_data("../../share/foo,\"bar\".txt", b""),
+                // a stale self-listing gets replaced
+                RecordEntry::for_data("foo-1.0.dist-info/RECORD", b"stale"),
+            ],
+        );
+        let text = record.render();
+        assert_eq!(
+            text,
+            indoc::indoc! {r#"
+                "../../share/foo,""bar"".txt",sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0
+                foo-1.0.dist-info/RECORD,,
+                foo/__init__.py,sha256=yvAm8l1xQCCfmAcmBTB6Q4kUuc5vPBSyPRXZZnJB3lI,12
+            "#}
+        );
+        assert_eq!(Record::parse(&text)?, record);
+
+        let init = record.get("foo/__init__.py").unwrap();
+        init.check(b"print('hi')\n")?;
+        assert!(init.check(b"print('bye')\n").is_err());
+        assert!(init.check(b"print('hi!')\n").is_err());
+        record
+            .get("foo-1.0.dist-info/RECORD")
+            .unwrap()
+            .check(b"anything")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_parse_quirks() -> Result<()> {
+        // CRLF, blank lines, padded base64, a newline inside quotes, and no newline at
+        // the end
+        let text = "a.py,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU=,0\r\n\r\n\
+                    \"b\nc.py\",,\n\
+                    d.pyc,,";
+        let record = Record::parse(text)?;
+        assert_eq!(record.entries.len(), 3);
+        record.get("a.py").unwrap().check(b"")?;
+        assert_eq!(
+            record.get("b\nc.py"),
+            Some(&RecordEntry {
+                path: "b\nc.py".into(),
+                hash: None,
+                size: None
+            })
+        );
+        assert!(record.get("d.pyc").is_some());
+
+        assert!(Record::parse("a.py,\n").is_err());
+        assert!(Record::parse("a.py,,,\n").is_err());
+        assert!(Record::parse("\"a.py,,\n").is_err());
+        assert!(Record::parse("a.py,sha256,0\n").is_err());
+        assert!(Record::parse("a.py,,big\n").is_err());
+        Ok(())
+    }
+}
@@ -22,17 +22,11 @@ peg::parser! {
 
         rule version_one() -> Specifier
             = _ op:version_cmp() _ v:$(version())
-           {?
-                // peg requires &str for errors, so we can't pass through the result of
-                // try_from directly
-                if op == "===" {
-                    Err("'===' is not implemented")
-                } else {
-                    Ok(Specifier {
-                        // unwrap ok because: the parser rule only accepts valid operators
-                        op: op.try_into().unwrap(),
-                       value: v.into(),
-                    })
+            {
+                Specifier {
+                    // unwrap ok because: the parser rule only accepts valid operators
+                    op: op.try_into().unwrap(),
+                    value: v.into(),
                 }
             }
 
@@ -42,8 +36,11 @@ peg::parser! {
         pub rule versionspec() -> Specifiers
             = ("(" vm:version_many() ")" { vm }) / version_many()
 
-        rule urlspec() -> Requirement
-            = "@" {? Err("direct url references not currently supported") }
+        // Like the 'packaging' module, we don't try to validate URL syntax here
+        // beyond "doesn't contain whitespace"; the url crate can worry about that.
+        rule urlspec() -> Url
+            = "@" _ u:$([^ ' ' | '\t']+)
+              {? Url::parse(u).or(Err("invalid URL in direct reference")) }
 
         rule not_in() -> &'static str
             = "not" wsp()+ "in" { "not in" }
@@ -124,6 +121,7 @@ peg::parser! {
                       ">=" => Operator { op: Compare(GreaterThanEqual), lhs, rhs },
                       ">" => Operator { op: Compare(StrictlyGreaterThan), lhs, rhs },
                       "~=" => Operator { op: Compare(Compatible), lhs, rhs },
+                      "===" => Operator { op: Compare(ArbitraryEqual), lhs, rhs },
                       "in" => Operator { op: In, lhs, rhs },
                       "not in" => Operator { op: NotIn, lhs, rhs },
                       _ => panic!("op can't be {:?}!", op),
@@ -168,19 +166,26 @@ peg::parser! {
                       name,
                       extras,
                       specifiers,
+                      url: None,
                       env_marker_expr,
                   }
               }
 
+        // URLs can contain ';', so PEP 508 requires whitespace before the marker
         rule url_req(parse_extra: ParseExtra) -> Requirement
             = name:name()
               _ extras:(extras() / "" { Vec::new() })
               _ url:urlspec()
-              _ env_marker:((wsp() q:quoted_marker(parse_extra) { q })?)
-            {
-                // because urlspec() errors out unconditionally, up above
-                unreachable!()
-            }
+              env_marker_expr:((wsp()+ q:quoted_marker(parse_extra) { q })?)
+              {
+                  Requirement {
+                      name,
+                      extras,
+                      specifiers: Specifiers(Vec::new()),
+                      url: Some(url),
+                      env_marker_expr,
+                  }
+              }
 
         pub rule requirement(parse_extra: ParseExtra) -> Requirement
             = _ r:( url_req(parse_extra) / name_req(parse_extra) ) _ { r }
@@ -6,41 +6,21 @@ use crate::prelude::*;
 //  === "some string"
 //  @ some_url
 //
-// Not sure if we should bother supporting them. For === they're easy to parse
-// and represent (same as all the other binary comparisons), but I don't know
-// what the semantics is, b/c we fully parse all versions. PEP 440 says "The
-// primary use case ... is to allow for specifying a version which cannot
-// otherwise by represented by this PEP". Maybe if we find ourselves supporting
-// LegacyVersion-type versions, we should add this then? Though even then, I'm not sure
-// we can convince pubgrub to handle it.
+// For === we compare the version strings exactly, without the zero-padding or
+// local-version leniency of == (see Specifier::satisfied_by). PEP 440 says
+// "The primary use case ... is to allow for specifying a version which cannot
+// otherwise by represented by this PEP", and those we can't represent at all.
 //
-// If we do want to parse @ syntax, the problem is more: how do we represent
-// them? Because it *replaces* version constraints, so I guess inside the
-// Requirement object we'd need something like:
+// For '@', we keep the URL in its own field next to the (then empty) specifiers. PEP
+// 508 includes an entire copy of (some version of) the standard URL syntax, which we
+// don't want to deal with, both because it's wildly more complicated than required,
+// and because there are >3 different standards purporting to define URL syntax and
+// we don't want to take sides. So like the 'packaging' module, anything that isn't
+// whitespace goes, and then we let the url crate decide whether it's a URL.
 //
-//   enum Specifiers {
-//      Direct(Url),
-//      Index(Vec<Specifier>),
-//   }
-//
-// ? But then that complexity propagates through to everything that uses
-// Requirements.
-//
-// Also, I don't think @ is allowed in public indexes like PyPI?
-//
-// NB: if we do decide to handle '@', then PEP 508 includes an entire copy of
-// (some version of) the standard URL syntax. We don't want to do that, both
-// because it's wildly more complicated than required, and because there are
-// >3 different standards purpoting to define URL syntax and we don't want to
-// take sides. But! The 'packaging' module just does
-//
-//    URI = Regex(r"[^ ]+")("url")
-//
-// ...so we can just steal some version of that.
-//
-// For resolving, we can treat it as a magic package that provides/depends on the
-// version it declares, so it can satisfy other dependencies that use the name or
-// versions.
+// For resolving, the URL's artifact becomes the only candidate for that package (see
+// PackageDB::add_direct_reference), so it satisfies other dependencies that use the
+// name, as long as they're happy with the version it declares.
 
 pub mod marker {
     use std::{borrow::Borrow, hash::Hash};
@@ -124,17 +104,15 @@ pub mod marker {
                     // variable: always normalize both sides (see PEP 685)
                     let lhs_holder: String;
                     let rhs_holder: String;
-                    if lhs.is_extra() {
-                        if let Ok(extra) = Extra::try_from(rhs_val) {
-                            rhs_holder = extra.normalized().to_string();
-                            rhs_val = rhs_holder.as_str();
-                        }
-                    }
-                    if rhs.is_extra() {
+                    if lhs.is_extra() || rhs.is_extra() {
                         if let Ok(extra) = Extra::try_from(lhs_val) {
                             lhs_holder = extra.normalized().to_string();
                             lhs_val = lhs_holder.as_str();
                         }
+                        if let Ok(extra) = Extra::try_from(rhs_val) {
+                            rhs_holder = extra.normalized().to_string();
+                            rhs_val = rhs_holder.as_str();
+                        }
                     }
                     match op {
                         Op::In => rhs_val.contains(lhs_val),
@@ -142,8 +120,10 @@ pub mod marker {
                         Op::Compare(op) => {
                             // If both sides can be parsed as versions (or the RHS can
                             // be parsed as a wildcard with a wildcard-accepting op),
-                            // then we do a version comparison
-                            if let Ok(lhs_ver) = lhs_val.parse() {
+                            // then we do a version comparison. Except for ===, which
+                            // is always a string comparison.
+                            let arbitrary = op == &CompareOp::ArbitraryEqual;
+                            if let (false, Ok(lhs_ver)) = (arbitrary, lhs_val.parse()) {
                                 if let Ok(rhs_ranges) = op.to_ranges(rhs_val) {
                                     return Ok(rhs_ranges
                                         .into_iter()
@@ -161,6 +141,7 @@ pub mod marker {
                                 Compatible => {
                                     bail!("~= requires valid version strings")
                                 }
+                                ArbitraryEqual => lhs_val.eq_ignore_ascii_case(rhs_val),
                             }
                         }
                     }
@@ -225,6 +206,9 @@ pub struct Requirement {
     pub name: PackageName,
     pub extras: Vec<Extra>,
     pub specifiers: Specifiers,
+    /// For `name @ https://...` direct references. If this is set, then `specifiers`
+    /// is empty.
+    pub url: Option<Url>,
     pub env_marker_expr: Option<marker::EnvMarkerExpr>,
 }
 
@@ -256,7 +240,14 @@ impl Display for Requirement {
         if !self.specifiers.0.is_empty() {
             write!(f, " {}", self.specifiers)?;
         }
+        if let Some(url) = &self.url {
+            write!(f, " @ {}", url)?;
+        }
         if let Some(env_marker) = &self.env_marker_expr {
+            // the space keeps the ';' from being read as part of the url
+            if self.url.is_some() {
+                write!(f, " ")?;
+            }
             write!(f, "; {}", env_marker)?;
         }
         Ok(())
@@ -287,6 +278,72 @@ impl TryFrom<&str> for PackageRequirement {
 
 try_from_str_boilerplate!(PackageRequirement);
 
+impl PackageRequirement {
+    /// Package metadata in the wild has all kinds of junk in it, and it'd be silly to
+    /// refuse to resolve anything just because some dependency-of-a-dependency was
+    /// sloppy. So this tries parsing the normal way, and if that doesn't work, patches
+    /// up the common mistakes and tries again. Returns the requirement, plus a
+    /// description of each thing we had to patch up, so the caller can warn about it.
+    ///
+    /// Only for metadata we got from somewhere else; things the user wrote themselves
+    /// should get fixed, not papered over.
+    pub fn parse_lenient(input: &str) -> Result<(PackageRequirement, Vec<String>)> {
+        let mut fixes = Vec::new();
+        let req = match Requirement::parse(input, ParseExtra::Allowed) {
+            Ok(req) => req,
+            Err(err) => {
+                // Trailing junk, like "foo >= 1," or "foo;"
+                let trimmed = input.trim().trim_end_matches([',', ';', ' ', '\t']);
+                match Requirement::parse(trimmed, ParseExtra::Allowed) {
+                    Ok(req) => {
+                        fixes.push(format!("ignored trailing junk in {input:?}"));
+                        req
+                    }
+                    Err(_) => return Err(err),
+                }
+            }
+        };
+        let mut specifiers = Vec::new();
+        for specifier in req.0.specifiers.0 {
+            if specifier.to_ranges().is_ok() {
+                specifiers.push(specifier);
+                continue;
+            }
+            let fixed = lenient_specifier(&specifier).ok_or_else(|| {
+                eyre!("invalid version specifier '{specifier}' in {input:?}")
+            })?;
+            fixes.push(format!("treating '{specifier}' as '{fixed}' in {input:?}"));
+            specifiers.push(fixed);
+        }
+        let req = Requirement {
+            specifiers: Specifiers(specifiers),
+            ..req.0
+        };
+        Ok((PackageRequirement(req), fixes))
+    }
+}
+
+/// What someone probably meant by an invalid specifier, if we can guess.
+fn lenient_specifier(specifier: &Specifier) -> Option<Specifier> {
+    use CompareOp::*;
+    let value = specifier.value.trim();
+    let fixed = match (specifier.op, value.strip_suffix(".*")) {
+        // ">= 2.*" -> ">= 2"
+        (op, Some(prefix)) if op != Equal && op != NotEqual => Specifier {
+            op,
+            value: prefix.into(),
+        },
+        // "~= 2" is invalid, b/c it needs at least two components to know which one
+        // can change. But the only thing it could mean is ">= 2".
+        (Compatible, None) if !value.contains('.') => Specifier {
+            op: GreaterThanEqual,
+            value: value.into(),
+        },
+        _ => return None,
+    };
+    fixed.to_ranges().is_ok().then_some(fixed)
+}
+
 #[derive(
     Shrinkwrap, Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay,
 )]
@@ -335,6 +392,9 @@ impl TryFrom<Requirement> for PythonRequirement {
                 r
             );
         }
+        if r.url.is_some() {
+            bail!("can't use a direct reference for python requirement {}", r);
+        }
         Ok(PythonRequirement(r))
     }
 }
@@ -422,6 +482,27 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_parse_lenient() {
+        let lenient = |s: &str| {
+            let (req, fixes) = PackageRequirement::parse_lenient(s).unwrap();
+            (req.to_string(), fixes.len())
+        };
+        assert_eq!(lenient("foo >= 1, < 2"), ("foo >= 1, < 2".into(), 0));
+        assert_eq!(lenient("foo >= 2.*"), ("foo >= 2".into(), 1));
+        assert_eq!(
+            lenient("foo ~= 2, != 2.1.*"),
+            ("foo >= 2, != 2.1.*".into(), 1)
+        );
+        assert_eq!(lenient("foo >= 1,"), ("foo >= 1".into(), 1));
+        assert_eq!(
+            lenient("foo; python_version < '3';"),
+            ("foo; python_version < \"3\"".into(), 1)
+        );
+        assert!(PackageRequirement::parse_lenient("foo >= bar").is_err());
+        assert!(PackageRequirement::parse_lenient("foo; nonsense").is_err());
+    }
+
     #[test]
     fn test_extra_normalization() {
         let r: PackageRequirement = "foo; extra == 'HeLlO' and extra in 'hElLoWorld'"
@@ -429,5 +510,41 @@ mod test {
             .unwrap();
         let env = HashMap::from([("extra", "hello")]);
         assert!(r.env_marker_expr.as_ref().unwrap().eval(&env).unwrap());
+
+        // PEP 685: '_', '.', and '-' are all the same, and so are runs of them
+        let r: PackageRequirement = "foo; extra == 'tests_cov'".try_into().unwrap();
+        let env = HashMap::from([("extra", "Tests--Cov")]);
+        assert!(r.env_marker_expr.as_ref().unwrap().eval(&env).unwrap());
+    }
+
+    #[test]
+    fn test_direct_references() {
+        let r: UserRequirement =
+            "foo[bar] @ https://example.com/foo-1.0-py3-none-any.whl#sha256=abcd"
+                .try_into()
+                .unwrap();
+        assert_eq!(r.name.as_given(), "foo");
+        assert!(r.specifiers.0.is_empty());
+        assert_eq!(
+            r.url.as_ref().unwrap().as_str(),
+            "https://example.com/foo-1.0-py3-none-any.whl#sha256=abcd"
+        );
+
+        // ';' can be part of a url, so the marker needs whitespace in front
+        let r: PackageRequirement =
+            "foo @ https://example.com/foo.whl;x ; python_version >= '3'"
+                .try_into()
+                .unwrap();
+        assert_eq!(r.url.as_ref().unwrap().path(), "/foo.whl;x");
+        assert!(r.env_marker_expr.is_some());
+        insta::assert_ron_snapshot!(
+            r,
+            @r###""foo @ https://example.com/foo.whl;x ; python_version >= \"3\"""###
+        );
+        assert_eq!(r, r.to_string().try_into().unwrap());
+
+        assert!(UserRequirement::try_from("foo @ not a url").is_err());
+        assert!(UserRequirement::try_from("foo >= 1 @ https://example.com/").is_err());
+        assert!(PythonRequirement::try_from("cpython @ https://example.com/").is_err());
     }
 }
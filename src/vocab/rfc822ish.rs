@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use std::borrow::Cow;
 
 pub type Fields = HashMap<String, Vec<String>>;
 
@@ -17,12 +18,12 @@ pub struct RFC822ish {
 // the "message body" (= description field, in modern PKG-INFO/METADATA
 // files).
 //
-// email.parser module is also extremely lenient of errors. We'll try to be a
-// bit more strict -- we try to be lenient of mangled utf-8, because obviously
-// someone must have messed that up in the history of PyPI, and aren't picky
-// about stuff like trailing newlines. But we fail on oddities like an empty
-// field name or a continuation line at the start of input, where email.parser
-// would keep on trucking. Fingers crossed that it works out.
+// email.parser module is also extremely lenient of errors. We try to accept
+// anything that pip does, since that's what package authors test against: mangled
+// or non-utf-8 encodings, folded fields, duplicate fields, and a body that starts
+// without the blank line in front. But we fail on oddities like an empty field name
+// or a continuation line at the start of input, where email.parser would keep on
+// trucking and hand back an empty message. Fingers crossed that it works out.
 peg::parser! {
     grammar rfc822ish_parser() for str {
         // In real RFC822, only CRLF is legal. email.parser is more lenient.
@@ -49,7 +50,8 @@ peg::parser! {
         // single line, removing the newline characters. email.parser doesn't
         // do that though -- continuation lines just get embedded newlines.
         // (But you don't include any *trailing* newlines. Those are
-        // discarded.)
+        // discarded.) We keep them too, and unfold when taking the value, since
+        // only the caller knows whether the line breaks mean anything.
         rule field_value() -> &'input str
             = $(field_value_piece() ** continuation_line_ending())
 
@@ -66,13 +68,20 @@ peg::parser! {
         rule trailing_body() -> String
             = line_ending() line_ending() b:$([_]*) { b.to_owned() }
 
+        // When email.parser hits a line that isn't a field or a continuation, it
+        // decides the body has started, blank line or no (and records a
+        // MissingHeaderBodySeparatorDefect that nobody looks at).
+        rule implicit_body() -> String
+            = line_ending() !(field_name() ":") b:$([^ '\r' | '\n'] [_]*)
+                { b.to_owned() }
+
         // The extra line_ending() is to handle the case where there's
         // no trailing body, and exactly one line ending at EOF. If
         // trailing_body matches then the input will be fully consumed by
         // then; if not, then we might have a stray trailing newline to
         // absorb.
         pub rule rfc822ish() -> RFC822ish
-            = f:fields() body:(trailing_body()?) line_ending()?
+            = f:fields() body:((trailing_body() / implicit_body())?) line_ending()?
                  {
                      let mut fields = Fields::new();
                      for (name, value) in f {
@@ -83,25 +92,100 @@ peg::parser! {
     }
 }
 
+/// Metadata is supposed to be utf-8, but some older tools wrote whatever the
+/// author's locale used, and declared it in the Description-Content-Type (e.g.
+/// "text/plain; charset=latin-1"). If it's not utf-8 and we can find a charset we
+/// know, we use that; otherwise we muddle through with replacement characters, like
+/// pip would.
+fn decode(input: &[u8]) -> Cow<str> {
+    let input = input.strip_prefix(b"\xef\xbb\xbf").unwrap_or(input);
+    if let Ok(s) = std::str::from_utf8(input) {
+        return Cow::Borrowed(s);
+    }
+    if let Some(encoding) = declared_charset(input) {
+        let (decoded, _) = encoding.decode_without_bom_handling(input);
+        return decoded;
+    }
+    String::from_utf8_lossy(input)
+}
+
+fn declared_charset(input: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    static CHARSET: Lazy<regex::bytes::Regex> = Lazy::new(|| {
+        regex::bytes::Regex::new(
+            r#"(?im-u)^description-content-type:.*;\s*charset="?([-_.:a-z0-9]+)"#,
+        )
+        .unwrap()
+    });
+    // only look at the headers, not the body
+    let end = [&b"\n\n"[..], b"\r\n\r\n", b"\r\r"]
+        .iter()
+        .filter_map(|sep| input.windows(sep.len()).position(|w| w == *sep))
+        .min()
+        .unwrap_or(input.len());
+    let label = CHARSET.captures(&input[..end])?.get(1)?.as_bytes();
+    encoding_rs::Encoding::for_label(label)
+}
+
+/// Undoes folding: each line break, plus the indentation after it, becomes a single
+/// space.
+fn unfold(value: &str) -> String {
+    static FOLD: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\r\n|\r|\n)[ \t]*").unwrap());
+    FOLD.replace_all(value, " ").into_owned()
+}
+
+/// For fields where the line breaks matter (License, Description), strips off the
+/// indentation that folding added to each line. The core metadata spec says that's 7
+/// spaces and a '|', but setuptools has always used 8 spaces, and some tools use a
+/// tab.
+fn dedent(value: &str) -> String {
+    let mut lines = value.lines();
+    let mut out = lines.next().unwrap_or_default().to_owned();
+    for line in lines {
+        out.push('\n');
+        out.push_str(match line.strip_prefix("       |") {
+            Some(rest) => rest,
+            None => match line.strip_prefix('\t') {
+                Some(rest) => rest,
+                None => {
+                    let spaces = line.len() - line.trim_start_matches(' ').len();
+                    &line[spaces.min(8)..]
+                }
+            },
+        });
+    }
+    out
+}
+
 impl RFC822ish {
     pub fn parse(input: &str) -> Result<RFC822ish> {
         Ok(rfc822ish_parser::rfc822ish(input)?)
     }
 
+    /// Like `parse`, but for raw bytes in whatever encoding we were handed.
+    pub fn parse_bytes(input: &[u8]) -> Result<RFC822ish> {
+        RFC822ish::parse(&decode(input))
+    }
+
+    fn take_raw(&mut self, key: &str) -> Vec<String> {
+        self.fields
+            .remove(&key.to_ascii_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// All the values for `key`, unfolded.
     pub fn take_all(&mut self, key: &str) -> Vec<String> {
-        match self.fields.remove(&key.to_ascii_lowercase()) {
-            Some(vec) => vec,
-            None => Vec::new(),
-        }
+        self.take_raw(key).iter().map(|v| unfold(v)).collect()
     }
 
+    /// The value for a field that should only appear once. Some tools write the same
+    /// field out twice; that's fine as long as they agree.
     pub fn maybe_take_the(&mut self, key: &str) -> Result<Option<String>> {
         let mut values = self.take_all(key);
-        match values.len() {
-            0 => Ok(None),
-            1 => Ok(values.pop()),
-            _ => bail!("multiple values for singleton key {}", key),
+        if values.iter().any(|v| v != &values[0]) {
+            bail!("multiple values for singleton key {}", key);
         }
+        values.truncate(1);
+        Ok(values.pop())
     }
 
     pub fn take_the(&mut self, key: &str) -> Result<String> {
@@ -110,6 +194,36 @@ impl RFC822ish {
             None => bail!("can't find required key {}", key),
         }
     }
+
+    /// For fields that are only for show, where it's not worth failing over
+    /// conflicting duplicates: takes the first one, like email.parser does.
+    pub fn maybe_take_first(&mut self, key: &str) -> Option<String> {
+        let mut values = self.take_all(key);
+        if values.iter().any(|v| v != &values[0]) {
+            debug!("ignoring extra values for {key}: {:?}", &values[1..]);
+        }
+        values.truncate(1);
+        values.pop()
+    }
+
+    /// Same as `maybe_take_first`, but keeps the line breaks, for free-form text.
+    pub fn maybe_take_text(&mut self, key: &str) -> Option<String> {
+        let mut values = self.take_raw(key);
+        values.truncate(1);
+        values.pop().map(|v| dedent(&v))
+    }
+
+    /// The long description. Since Metadata-Version 2.1 it's supposed to be the body,
+    /// but before that it was a Description field, and some tools write both. pip
+    /// doesn't care, so neither do we: if there's a body we use it, otherwise the
+    /// field.
+    pub fn take_description(&mut self) -> Option<String> {
+        let field = self.maybe_take_text("Description");
+        match self.body.take() {
+            Some(body) if !body.trim().is_empty() => Some(body.trim_end().to_owned()),
+            _ => field,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -220,4 +334,158 @@ mod test {
         );
         assert_eq!(got.take_the("mixed-CASE").unwrap(), "baz");
     }
+
+    #[test]
+    fn test_folding() {
+        let mut got = RFC822ish::parse(indoc! {"
+            Requires-Dist: foo >= 1,
+              < 2
+            License: Some License
+                   |
+                   |  indented
+                    second paragraph
+            Summary: one
+            \tline
+        "})
+        .unwrap();
+        assert_eq!(got.take_all("requires-dist"), vec!["foo >= 1, < 2"]);
+        assert_eq!(
+            got.maybe_take_text("license").unwrap(),
+            "Some License\n\n  indented\nsecond paragraph"
+        );
+        assert_eq!(got.take_the("summary").unwrap(), "one line");
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut got = RFC822ish::parse(indoc! {"
+            Name: foo
+            Name: foo
+            Version: 1
+            Version: 2
+            Summary: first
+            Summary: second
+        "})
+        .unwrap();
+        assert_eq!(got.take_the("name").unwrap(), "foo");
+        assert!(got.take_the("version").is_err());
+        assert_eq!(got.maybe_take_first("summary").unwrap(), "first");
+        assert_eq!(got.maybe_take_first("summary"), None);
+    }
+
+    #[test]
+    fn test_description() {
+        let mut both = RFC822ish::parse("Description: old\n\nnew\n\n").unwrap();
+        assert_eq!(both.take_description().unwrap(), "new");
+        assert!(both.fields.is_empty());
+
+        let mut field_only =
+            RFC822ish::parse("Description: line one\n        line two\n").unwrap();
+        assert_eq!(field_only.take_description().unwrap(), "line one\nline two");
+
+        // No blank line before the body: email.parser starts the body at the first
+        // line that can't be a field, so we do too
+        let mut implicit = RFC822ish::parse(indoc! {"
+            Name: foo
+            This is not: a field
+            Version: 1
+        "})
+        .unwrap();
+        assert_eq!(implicit.take_the("name").unwrap(), "foo");
+        assert!(implicit.take_the("version").is_err());
+        assert_eq!(
+            implicit.take_description().unwrap(),
+            "This is not: a field\nVersion: 1"
+        );
+    }
+
+    #[test]
+    fn test_encodings() {
+        let bom = b"\xef\xbb\xbfName: foo\n";
+        let mut got = RFC822ish::parse_bytes(bom).unwrap();
+        assert_eq!(got.take_the("name").unwrap(), "foo");
+
+        let latin1 = b"Name: foo\nAuthor: Fran\xe7ois\n\
+                       Description-Content-Type: text/plain; charset=\"ISO-8859-1\"\n\n\
+                       Voil\xe0\n";
+        let mut got = RFC822ish::parse_bytes(latin1).unwrap();
+        assert_eq!(got.take_the("author").unwrap(), "François");
+        assert_eq!(got.take_description().unwrap(), "Voilà");
+
+        // Undeclared and not utf-8: we do our best
+        let mut got = RFC822ish::parse_bytes(b"Author: Fran\xe7ois\n").unwrap();
+        assert_eq!(got.take_the("author").unwrap(), "Fran\u{fffd}ois");
+    }
+
+    #[test]
+    fn test_fuzz() {
+        // Whatever garbage we get, we should return an error rather than panic, and
+        // whatever we can parse should come back out the same when written back out
+        const PIECES: &[&[u8]] = &[
+            b"Name",
+            b"a",
+            b"Z",
+            b"-",
+            b":",
+            b": ",
+            b" ",
+            b"\t",
+            b"\n",
+            b"\r\n",
+            b"\r",
+            b"|",
+            b"\xe9",
+            b"\xff",
+            b"\xef\xbb\xbf",
+            b"\xc3\xa9",
+            b"charset=latin-1",
+            b"Description-Content-Type: text/plain; ",
+        ];
+        for seed in 0..2000 {
+            let rng = fastrand::Rng::with_seed(seed);
+            let len = rng.usize(..40);
+            let input: Vec<u8> = (0..len)
+                .flat_map(|_| PIECES[rng.usize(..PIECES.len())].iter().copied())
+                .collect();
+            if let Ok(mut parsed) = RFC822ish::parse_bytes(&input) {
+                let keys: Vec<String> = parsed.fields.keys().cloned().collect();
+                for key in keys {
+                    for value in parsed.take_all(&key) {
+                        assert!(!value.contains(['\r', '\n']), "{input:?}");
+                    }
+                }
+                parsed.take_description();
+            }
+        }
+
+        for seed in 0..500 {
+            let rng = fastrand::Rng::with_seed(seed);
+            let mut text = String::new();
+            let mut expected: Vec<(String, String)> = Vec::new();
+            for i in 0..rng.usize(1..10) {
+                let key = format!("Key-{i}");
+                let words: Vec<String> = (0..rng.usize(1..6))
+                    .map(|_| (0..rng.usize(1..8)).map(|_| rng.alphanumeric()).collect())
+                    .collect();
+                text.push_str(&key);
+                text.push(':');
+                for (j, word) in words.iter().enumerate() {
+                    let sep = match (j, rng.u8(..3)) {
+                        (0, _) | (_, 0) => " ",
+                        (_, 1) => "\n ",
+                        _ => "\r\n\t",
+                    };
+                    text.push_str(sep);
+                    text.push_str(word);
+                }
+                text.push('\n');
+                expected.push((key, words.join(" ")));
+            }
+            let mut parsed = RFC822ish::parse(&text).unwrap();
+            for (key, value) in expected {
+                assert_eq!(parsed.take_the(&key).unwrap(), value, "{text:?}");
+            }
+            assert!(parsed.fields.is_empty());
+        }
+    }
 }
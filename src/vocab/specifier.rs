@@ -9,6 +9,10 @@ pub struct Specifier {
 
 impl Specifier {
     pub fn satisfied_by(&self, version: &Version) -> Result<bool> {
+        if self.op == CompareOp::ArbitraryEqual {
+            // a plain string comparison, same as `packaging`
+            return Ok(version.to_string().eq_ignore_ascii_case(self.value.trim()));
+        }
         Ok(self.to_ranges()?.into_iter().any(|r| r.contains(version)))
     }
 
@@ -75,6 +79,7 @@ pub enum CompareOp {
     GreaterThanEqual,
     StrictlyGreaterThan,
     Compatible,
+    ArbitraryEqual,
 }
 
 impl Display for CompareOp {
@@ -91,6 +96,7 @@ impl Display for CompareOp {
                 GreaterThanEqual => ">=",
                 StrictlyGreaterThan => ">",
                 Compatible => "~=",
+                ArbitraryEqual => "===",
             }
         )
     }
@@ -109,7 +115,7 @@ impl TryFrom<&str> for CompareOp {
             ">=" => GreaterThanEqual,
             ">" => StrictlyGreaterThan,
             "~=" => Compatible,
-            "===" => bail!("'===' is not implemented"),
+            "===" => ArbitraryEqual,
             _ => bail!("unrecognized operator: {:?}", value),
         })
     }
@@ -117,6 +123,34 @@ impl TryFrom<&str> for CompareOp {
 
 try_from_str_boilerplate!(CompareOp);
 
+/// For "< X.postN", we list out X's post-releases one by one, up to this many.
+const MAX_EXACT_POSTS: u32 = 32;
+
+fn with_post_dev(base: &Version, post: Option<u32>, dev: Option<u32>) -> Version {
+    let mut v = base.clone();
+    let m = v.make_mut();
+    m.post = post;
+    m.dev = dev;
+    v
+}
+
+/// For "< X.postN", the "specified version" is X, so X's pre-releases are out. But X
+/// itself and its earlier post-releases are in -- just not their .devM releases, which
+/// are pre-releases of X too.
+fn below_post(base: &Version, post: u32) -> Vec<Range<Version>> {
+    let with = |post, dev| with_post_dev(base, post, dev);
+    let mut ranges = vec![base.clone()..with(Some(0), Some(0))];
+    if post <= MAX_EXACT_POSTS {
+        for n in 0..post {
+            ranges.push(with(Some(n), None)..with(Some(n + 1), Some(0)));
+        }
+    } else {
+        // Close enough
+        ranges.push(with(Some(0), None)..with(Some(post), Some(0)));
+    }
+    ranges
+}
+
 fn parse_version_wildcard(input: &str) -> Result<(Version, bool)> {
     let (vstr, wildcard) = if let Some(vstr) = input.strip_suffix(".*") {
         (vstr, true)
@@ -134,6 +168,21 @@ fn parse_version_wildcard(input: &str) -> Result<(Version, bool)> {
 impl CompareOp {
     pub fn to_ranges(&self, rhs: &str) -> Result<Vec<Range<Version>>> {
         use CompareOp::*;
+        if self == &ArbitraryEqual {
+            // === is a plain string comparison, meant for versions that don't follow
+            // PEP 440. But every version we can install does, so the best a range can
+            // do is the one version it names, if it names one in its normal spelling.
+            // That still lets in other spellings of the same version (1.0 is 1.0.0),
+            // so anything holding an actual version should use
+            // `Specifier::satisfied_by`, which compares the strings.
+            let rhs = rhs.trim();
+            return Ok(match Version::try_from(rhs) {
+                Ok(version) if version.to_string().eq_ignore_ascii_case(rhs) => {
+                    vec![version.clone()..version.successor()]
+                }
+                _ => Vec::new(),
+            });
+        }
         let (version, wildcard) = parse_version_wildcard(rhs)?;
         Ok(if wildcard {
             if version.0.dev.is_some() || !version.0.local.is_empty() {
@@ -143,23 +192,25 @@ impl CompareOp {
             //
             // [X.dev0, (X+1).dev0)
             let mut low = version.clone();
-            low.0.dev = Some(0);
+            low.make_mut().dev = Some(0);
             let mut high = version;
+            let h = high.make_mut();
             // .* can actually appear after .postX or .aX, so we need to find the last
             // numeric entry in the version, and increment that.
-            if let Some(post) = high.0.post {
-                high.0.post = Some(post + 1)
-            } else if let Some(pre) = high.0.pre {
+            if let Some(post) = h.post {
+                h.post = Some(post + 1)
+            } else if let Some(pre) = &h.pre {
                 use pep440::PreRelease::*;
-                high.0.pre = Some(match pre {
+                let bumped = match pre {
                     RC(n) => RC(n + 1),
                     A(n) => A(n + 1),
                     B(n) => B(n + 1),
-                })
+                };
+                h.pre = Some(bumped)
             } else {
-                *high.0.release.last_mut().unwrap() += 1;
+                *h.release.last_mut().unwrap() += 1;
             }
-            high.0.dev = Some(0);
+            h.dev = Some(0);
             match self {
                 Equal => vec![low..high],
                 NotEqual => {
@@ -172,12 +223,19 @@ impl CompareOp {
             if self != &Equal && self != &NotEqual && !version.0.local.is_empty() {
                 bail!("Operator {:?} cannot be used on a version with a +local suffix", self);
             }
+            // X.Y, without any pre/post/dev suffixes
+            let mut base = version.clone();
+            let b = base.make_mut();
+            b.pre = None;
+            b.post = None;
+            b.dev = None;
+            let with = |post, dev| with_post_dev(&base, post, dev);
             match self {
                 // These two are simple
                 LessThanEqual => vec![VERSION_ZERO.clone()..version.next()],
                 GreaterThanEqual => vec![version..VERSION_INFINITY.clone()],
                 // These are also pretty simple, because we took care of the wildcard
-                // cases up above.
+                // cases up above. (And Version::next knows about +local.)
                 Equal => vec![version.clone()..version.next()],
                 NotEqual => vec![
                     VERSION_ZERO.clone()..version.clone(),
@@ -187,10 +245,12 @@ impl CompareOp {
                 // the given version unless V itself is a post release."
                 StrictlyGreaterThan => {
                     let mut low = version.clone();
+                    let l = low.make_mut();
                     if let Some(dev) = &version.0.dev {
-                        low.0.dev = Some(dev + 1);
+                        l.dev = Some(dev + 1);
                     } else if let Some(post) = &version.0.post {
-                        low.0.post = Some(post + 1);
+                        l.post = Some(post + 1);
+                        l.dev = Some(0);
                     } else {
                         // Otherwise, want to increment either the pre-release (a0 ->
                         // a1), or the "last" release segment. But working with
@@ -198,20 +258,31 @@ impl CompareOp {
                         // release segment -- X.Y.Z is just shorthand for
                         // X.Y.Z.0.0.0.0... So instead, we tack on a .post(INFINITY) and
                         // hope no-one actually makes a version like this in practice.
-                        low.0.post = Some(u32::MAX);
+                        l.post = Some(u32::MAX);
+                    }
+                    if version.0.post.is_none() && version.is_prerelease() {
+                        // V is a pre-release, so the final release comes after it, and
+                        // then the final release's post-releases, which also don't
+                        // count -- they're post-releases of "the given version" too.
+                        vec![
+                            low..with(Some(0), Some(0)),
+                            with(Some(u32::MAX), None)..VERSION_INFINITY.clone(),
+                        ]
+                    } else {
+                        vec![low..VERSION_INFINITY.clone()]
                     }
-                    vec![low..VERSION_INFINITY.clone()]
                 }
                 // "The exclusive ordered comparison <V MUST NOT allow a pre-release of
                 // the specified version unless the specified version is itself a
                 // pre-release."
                 StrictlyLessThan => {
                     if (&version.0.pre, &version.0.dev) == (&None, &None) {
-                        let mut new_max = version;
-                        new_max.0.dev = Some(0);
-                        new_max.0.post = None;
-                        new_max.0.local = vec![];
-                        vec![VERSION_ZERO.clone()..new_max]
+                        let below_pre = VERSION_ZERO.clone()..with(None, Some(0));
+                        let mut ranges = vec![below_pre];
+                        if let Some(post) = version.0.post {
+                            ranges.extend(below_post(&base, post));
+                        }
+                        ranges
                     } else {
                         // Otherwise, some kind of pre-release
                         vec![VERSION_ZERO.clone()..version]
@@ -224,7 +295,7 @@ impl CompareOp {
                     if version.0.release.len() < 2 {
                         bail!("~= operator requires a version with two segments (X.Y)");
                     }
-                    let mut new_max = Version(pep440::Version {
+                    let mut new_max = Version::from(pep440::Version {
                         epoch: version.0.epoch,
                         release: version.0.release.clone(),
                         pre: None,
@@ -234,10 +305,12 @@ impl CompareOp {
                     });
                     // Unwraps here are safe because we confirmed that the vector has at
                     // least 2 elements above.
-                    new_max.0.release.pop().unwrap();
-                    *new_max.0.release.last_mut().unwrap() += 1;
+                    let release = &mut new_max.make_mut().release;
+                    release.pop().unwrap();
+                    *release.last_mut().unwrap() += 1;
                     vec![version..new_max]
                 }
+                ArbitraryEqual => unreachable!(),
             }
         })
     }
@@ -246,7 +319,7 @@ impl CompareOp {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test_util::from_commented_json;
+    use crate::test_util::{from_commented_json, sample_specifiers, sample_versions};
 
     #[test]
     fn test_invalid_specifiers_table() {
@@ -297,4 +370,88 @@ mod test {
             assert!(!specs.satisfied_by(&version).unwrap());
         }
     }
+
+    /// PEP 440's rules, written out one version at a time, the way the 'packaging'
+    /// library does it -- as opposed to to_ranges, which has to describe the whole set
+    /// of matching versions up front.
+    fn reference_matches(candidate: &Version, op: CompareOp, value: &str) -> bool {
+        use CompareOp::*;
+        let c = &candidate.0;
+        let mut public = candidate.clone();
+        public.make_mut().local.clear();
+        let base = |v: &Version| {
+            Version::from(pep440::Version {
+                epoch: v.0.epoch,
+                release: v.0.release.clone(),
+                pre: None,
+                post: None,
+                dev: None,
+                local: vec![],
+            })
+        };
+        if op == ArbitraryEqual {
+            return candidate.to_string().eq_ignore_ascii_case(value);
+        }
+        if let Some(prefix) = value.strip_suffix(".*") {
+            let p: Version = prefix.parse().unwrap();
+            let matches = c.epoch == p.0.epoch
+                && if p.0.pre.is_none() && p.0.post.is_none() {
+                    let segment = |i| c.release.get(i).copied().unwrap_or(0);
+                    (0..p.0.release.len()).all(|i| segment(i) == p.0.release[i])
+                } else {
+                    base(candidate) == base(&p)
+                        && c.pre == p.0.pre
+                        && (p.0.post.is_none() || c.post == p.0.post)
+                };
+            return matches == (op == Equal);
+        }
+        let v: Version = value.parse().unwrap();
+        match op {
+            Equal if v.0.local.is_empty() => public == v,
+            Equal => *candidate == v,
+            NotEqual => !reference_matches(candidate, Equal, value),
+            LessThanEqual => public <= v,
+            GreaterThanEqual => public >= v,
+            StrictlyLessThan => {
+                *candidate < v
+                    && !(!v.is_prerelease()
+                        && candidate.is_prerelease()
+                        && base(candidate) == base(&v))
+            }
+            StrictlyGreaterThan => {
+                *candidate > v
+                    && !(v.0.post.is_none()
+                        && c.post.is_some()
+                        && base(candidate) == base(&v))
+                    && !(!c.local.is_empty() && public == v)
+            }
+            Compatible => {
+                let mut prefix = v.0.release.clone();
+                prefix.pop();
+                let prefix: Vec<String> =
+                    prefix.iter().map(|n| n.to_string()).collect();
+                let prefix = format!("{}!{}.*", v.0.epoch, prefix.join("."));
+                public >= v && reference_matches(candidate, Equal, &prefix)
+            }
+            ArbitraryEqual => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_specifiers_against_reference() {
+        for spec_str in sample_specifiers() {
+            let specs: Specifiers = spec_str.as_str().try_into().unwrap();
+            let spec = &specs.0[0];
+            if spec.to_ranges().is_err() {
+                continue;
+            }
+            for version in sample_versions() {
+                assert_eq!(
+                    spec.satisfied_by(&version).unwrap(),
+                    reference_matches(&version, spec.op, &spec.value),
+                    "{version} {spec}"
+                );
+            }
+        }
+    }
 }
@@ -1,7 +1,12 @@
 use crate::prelude::*;
+use std::sync::{Arc, Mutex};
 
 // We lean on the 'pep440' crate for the heavy lifting part of representing versions,
 // but wrap it in our own type so that we can e.g. make it play nice with pubgrub.
+//
+// The resolver clones versions constantly, so they're shared behind an Arc, and the
+// ones we parse are interned so that e.g. every artifact of a release shares one copy.
+// Use make_mut() to modify one.
 
 #[derive(
     Clone,
@@ -14,14 +19,16 @@ use crate::prelude::*;
     SerializeDisplay,
     DeserializeFromStr,
 )]
-pub struct Version(pub pep440::Version);
+pub struct Version(pub Arc<pep440::Version>);
 
-pub static VERSION_ZERO: Lazy<Version> = Lazy::new(|| "0a0.dev0".try_into().unwrap());
+/// The smallest PEP 440 version: dev releases sort before pre-releases, and nothing
+/// sorts before a dev release.
+pub static VERSION_ZERO: Lazy<Version> = Lazy::new(|| "0.dev0".try_into().unwrap());
 
 pub static VERSION_INFINITY: Lazy<Version> = Lazy::new(|| {
     // Technically there is no largest PEP 440 version. But this should be good
     // enough that no-one will notice the difference...
-    Version(pep440::Version {
+    Version::from(pep440::Version {
         epoch: u32::MAX,
         release: vec![u32::MAX, u32::MAX, u32::MAX],
         pre: None,
@@ -31,12 +38,39 @@ pub static VERSION_INFINITY: Lazy<Version> = Lazy::new(|| {
     })
 });
 
+static INTERNED: Lazy<Mutex<HashSet<Arc<pep440::Version>>>> =
+    Lazy::new(Default::default);
+
+impl From<pep440::Version> for Version {
+    fn from(v: pep440::Version) -> Self {
+        Version(Arc::new(v))
+    }
+}
+
 impl Version {
+    fn interned(v: pep440::Version) -> Version {
+        let mut interned = INTERNED.lock().unwrap();
+        if let Some(existing) = interned.get(&v) {
+            return Version(existing.clone());
+        }
+        let v = Arc::new(v);
+        interned.insert(v.clone());
+        Version(v)
+    }
+
+    /// Copy-on-write access to the parts of the version.
+    pub fn make_mut(&mut self) -> &mut pep440::Version {
+        Arc::make_mut(&mut self.0)
+    }
+
     pub fn is_prerelease(&self) -> bool {
         self.0.pre.is_some() || self.0.dev.is_some()
     }
 
-    /// Returns the smallest PEP 440 version that is larger than self.
+    /// Returns the smallest PEP 440 version that is larger than self. For a public
+    /// version X, that's past all of its +local variants too, so it's the end of the
+    /// range that `== X` matches. For X+foo, it's X+foo.0 -- not quite the smallest,
+    /// since that skips over X+foo.bar and the like (see below).
     pub fn next(&self) -> Version {
         let mut new = self.clone();
         // The rules are here:
@@ -45,32 +79,68 @@ impl Version {
         //
         // The relevant ones for this:
         //
+        // - +local versions sort right after the corresponding public version, with
+        //   longer local labels after shorter ones. So the next version after X+foo
+        //   is X+foo.(something). Strictly speaking the smallest (something) would be
+        //   a zero-length string, which isn't a valid version, so we use 0, which
+        //   only skips over X+foo.(some letters).
+        //
         // - You can't attach a .postN after a .devN. So if you have a .devN,
         //   then the next possible version is .dev(N+1)
         //
         // - You can't attach a .postN after a .postN. So if you already have
-        //   a .postN, then the next possible value is .post(N+1).
+        //   a .postN, then the next possible value is .post(N+1).dev0.
         //
         // - You *can* attach a .postN after anything else. And a .devN after that. So
         // to get the next possible value, attach a .post0.dev0.
-        if let Some(dev) = &mut new.0.dev {
-            *dev += 1;
-        } else if let Some(post) = &mut new.0.post {
-            *post += 1;
+        if !new.0.local.is_empty() {
+            // unwrap is safe b/c we're just adding a numeric segment to a valid version
+            new = format!("{self}.0").as_str().try_into().unwrap();
         } else {
-            new.0.post = Some(0);
-            new.0.dev = Some(0);
+            let v = new.make_mut();
+            if let Some(dev) = &mut v.dev {
+                *dev += 1;
+            } else if let Some(post) = &mut v.post {
+                *post += 1;
+                v.dev = Some(0);
+            } else {
+                v.post = Some(0);
+                v.dev = Some(0);
+            }
         }
         new
     }
+
+    /// Returns a version just above self, without skipping over +local variants the
+    /// way `next` does. This is what pubgrub uses to pin a single version, and pinning
+    /// 2.1.0 mustn't pin 2.1.0+cu121 along with it.
+    ///
+    /// For a public version this is an approximation, because there's no smallest
+    /// version above it. Local labels sort right after the public version, a label
+    /// with letters in sorts before an all-digit one, and otherwise they go in ASCII
+    /// order, so +0a, +00a, +000a, ... just keep getting smaller. We use a label with
+    /// more 0s than anyone writes, which means a label with even more of them (like
+    /// 2.1.0+0000000000a) gets treated as if it were 2.1.0 itself.
+    pub fn successor(&self) -> Version {
+        if !self.0.local.is_empty() {
+            return self.next();
+        }
+        // unwrap is safe b/c we're just adding a local label to a valid version
+        format!("{self}+{SMALLEST_LOCAL}")
+            .as_str()
+            .try_into()
+            .unwrap()
+    }
 }
 
+const SMALLEST_LOCAL: &str = "00000000a";
+
 impl TryFrom<&str> for Version {
     type Error = eyre::Report;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         pep440::Version::parse(value)
-            .map(Version)
+            .map(Version::interned)
             .ok_or_else(|| eyre!("Failed to parse PEP 440 version {}", value))
     }
 }
@@ -89,6 +159,90 @@ impl pubgrub::version::Version for Version {
     }
 
     fn bump(&self) -> Self {
-        self.next()
+        self.successor()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version_next() {
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+        for (version, next) in [
+            ("1.0", "1.0.post0.dev0"),
+            ("1.0a1", "1.0a1.post0.dev0"),
+            ("1.0.dev3", "1.0.dev4"),
+            ("1.0.post1", "1.0.post2.dev0"),
+            ("1.0+local", "1.0+local.0"),
+        ] {
+            assert_eq!(v(version).next(), v(next));
+            assert!(v(version) < v(next));
+        }
+        assert!(*VERSION_ZERO < v("0a0.dev0"));
+        assert!(*VERSION_ZERO < v("0"));
+    }
+
+    #[test]
+    fn test_version_successor() {
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+        for (version, local) in [
+            ("2.1.0", "2.1.0+cpu"),
+            ("2.1.0", "2.1.0+cu121"),
+            ("2.1.0", "2.1.0+0a"),
+            ("2.1.0", "2.1.0+5"),
+        ] {
+            assert!(v(version) < v(version).successor());
+            assert!(v(version).successor() < v(local));
+            assert!(v(local) < v(version).next());
+        }
+        // so pinning one version doesn't pin its local variants too
+        let exact = pubgrub::range::Range::exact(v("2.1.0"));
+        assert!(exact.contains(&v("2.1.0")));
+        assert!(!exact.contains(&v("2.1.0+cu121")));
+        assert!(!exact.contains(&v("2.1.0.post0")));
+        // the approximation: see `successor`
+        assert!(exact.contains(&v("2.1.0+0000000000a")));
+    }
+
+    #[test]
+    fn test_local_version_order() {
+        // This is how the PyTorch index tells its builds apart
+        let versions: Vec<Version> = [
+            "2.0.1+cu118",
+            "2.1.0",
+            "2.1.0+cpu",
+            "2.1.0+cu118",
+            "2.1.0+cu121",
+            "2.1.0+cu121.1",
+            "2.1.0+1",
+            "2.1.0.post0",
+            "2.1.1+cpu",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        let mut sorted = versions.clone();
+        sorted.sort();
+        assert_eq!(sorted, versions);
+        for version in &versions {
+            assert_eq!(&version.to_string().parse::<Version>().unwrap(), version);
+        }
+        assert_eq!(versions[4].to_string(), "2.1.0+cu121");
+    }
+
+    #[test]
+    fn test_version_interning() {
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+        assert!(Arc::ptr_eq(&v("1.2.3").0, &v("1.2.3").0));
+        assert!(!Arc::ptr_eq(&v("1.2.3").0, &v("1.2.4").0));
+
+        // modifying a copy leaves the original alone
+        let original = v("1.0");
+        let mut modified = original.clone();
+        modified.make_mut().post = Some(1);
+        assert_eq!(original, v("1.0"));
+        assert_eq!(modified, v("1.0.post1"));
     }
 }
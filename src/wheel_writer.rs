@@ -0,0 +1,354 @@
+//! Writing wheels ourselves: repacking ones we've patched (vendored fixes, metadata
+//! overrides), or putting together new ones from build outputs.
+//!
+//! We generate WHEEL and RECORD, so those always match the filename and the contents.
+//! The zip is reproducible (see `reproducible_zip`), with everything outside the
+//! .dist-info first, sorted, then the .dist-info, sorted, with RECORD last, as the spec
+//! recommends.
+//!
+//! Everything is held in memory until it's written, so this isn't for multi-gigabyte
+//! wheels.
+
+use crate::prelude::*;
+use crate::reproducible_zip::ReproducibleZip;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+struct Member {
+    data: Vec<u8>,
+    executable: bool,
+}
+
+pub struct WheelWriter {
+    name: WheelName,
+    dist_info: String,
+    root_is_purelib: bool,
+    files: BTreeMap<String, Member>,
+}
+
+impl WheelWriter {
+    // only the tests build wheels from scratch; `posy wheel retag` just renames
+    /// An empty wheel. Add at least `{dist_info}/METADATA` before writing it.
+    #[allow(dead_code)]
+    pub fn new(name: WheelName, root_is_purelib: bool) -> WheelWriter {
+        // As the spec says to spell it, which isn't always what's out there
+        let dist_info = format!(
+            "{}-{}.dist-info",
+            name.distribution.normalized().replace('-', "_"),
+            name.version
+        );
+        WheelWriter {
+            name,
+            dist_info,
+            root_is_purelib,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Everything from an existing wheel named `name`, except for the WHEEL and RECORD
+    /// (which get regenerated) and RECORD signatures (which would be wrong now).
+    pub fn from_zip<R: Read + Seek>(name: WheelName, source: R) -> Result<WheelWriter> {
+        context!("Reading {name} to repack it");
+        let mut z = ZipArchive::new(source)?;
+        let top_levels: HashSet<&str> = z
+            .file_names()
+            .map(|n| n.split_once('/').map_or(n, |(base, _)| base))
+            .collect();
+        let dist_info = Wheel::find_special_wheel_dir(
+            top_levels,
+            &name.distribution,
+            &name.version,
+            ".dist-info",
+        )?
+        .ok_or(eyre!(".dist-info/ missing"))?
+        .to_string();
+        let mut root_is_purelib = None;
+        let mut files = BTreeMap::new();
+        for i in 0..z.len() {
+            let mut member = z.by_index(i)?;
+            if member.is_dir() {
+                continue;
+            }
+            let path = member.name().to_string();
+            let data = slurp(&mut member)?;
+            match path.strip_prefix(&dist_info) {
+                Some("/WHEEL") => {
+                    root_is_purelib = Some(parse_root_is_purelib(&data)?);
+                }
+                Some("/RECORD" | "/RECORD.jws" | "/RECORD.p7s") => (),
+                _ => {
+                    let executable =
+                        member.unix_mode().map_or(false, |mode| mode & 0o111 != 0);
+                    files.insert(path, Member { data, executable });
+                }
+            }
+        }
+        Ok(WheelWriter {
+            root_is_purelib: root_is_purelib.ok_or(eyre!("wheel has no WHEEL file"))?,
+            name,
+            dist_info,
+            files,
+        })
+    }
+
+    pub fn name(&self) -> &WheelName {
+        &self.name
+    }
+
+    /// The wheel's `.dist-info` directory, e.g. `foo-1.0.dist-info`.
+    #[allow(dead_code)]
+    pub fn dist_info(&self) -> &str {
+        &self.dist_info
+    }
+
+    /// Renames the wheel, e.g. to change its tags. The name and version have to stay
+    /// the same, since they're baked into the .dist-info.
+    pub fn set_name(&mut self, name: WheelName) -> Result<()> {
+        if name.distribution != self.name.distribution
+            || name.version != self.name.version
+        {
+            bail!("can't rename {} to {name}", self.name);
+        }
+        self.name = name;
+        Ok(())
+    }
+
+    /// Adds `path`, replacing whatever was there before.
+    #[allow(dead_code)]
+    pub fn add_file(
+        &mut self,
+        path: &str,
+        data: Vec<u8>,
+        executable: bool,
+    ) -> Result<()> {
+        let nice: NicePathBuf = path.try_into()?;
+        let path = nice.to_string();
+        if path == format!("{}/WHEEL", self.dist_info)
+            || path == format!("{}/RECORD", self.dist_info)
+        {
+            bail!("{path} gets generated when the wheel is written");
+        }
+        self.files.insert(path, Member { data, executable });
+        Ok(())
+    }
+
+    pub fn get_file(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path).map(|member| member.data.as_slice())
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_file(&mut self, path: &str) -> Option<Vec<u8>> {
+        self.files.remove(path).map(|member| member.data)
+    }
+
+    #[allow(dead_code)]
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|path| path.as_str())
+    }
+
+    /// The contents of the WHEEL file.
+    fn wheel_file(&self) -> String {
+        let mut text = format!(
+            "Wheel-Version: 1.0\nGenerator: posy {}\nRoot-Is-Purelib: {}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.root_is_purelib
+        );
+        for py in &self.name.py_tags {
+            for abi in &self.name.abi_tags {
+                for arch in &self.name.arch_tags {
+                    text.push_str(&format!("Tag: {py}-{abi}-{arch}\n"));
+                }
+            }
+        }
+        if let Some(number) = self.name.build_number {
+            text.push_str(&format!("Build: {number}{}\n", self.name.build_name));
+        }
+        text
+    }
+
+    /// Writes out the zip, after checking that METADATA agrees with the filename.
+    pub fn write<W: Write + Seek>(&self, out: W) -> Result<W> {
+        context!("Writing {}", self.name);
+        let metadata_path = format!("{}/METADATA", self.dist_info);
+        let metadata = self
+            .get_file(&metadata_path)
+            .ok_or_else(|| eyre!("wheel has no {metadata_path}"))?;
+        let metadata = WheelCoreMetadata::try_from(metadata).map_err(|err| {
+            MetadataError::Invalid {
+                what: metadata_path.clone(),
+                source: err.into(),
+            }
+        })?;
+        let mismatch =
+            |field, in_metadata: String, in_filename: String| MetadataError::Mismatch {
+                path: metadata_path.clone(),
+                field,
+                in_metadata,
+                in_filename,
+            };
+        if metadata.name != self.name.distribution {
+            Err(mismatch(
+                "name",
+                metadata.name.as_given().into(),
+                self.name.distribution.as_given().into(),
+            ))?;
+        }
+        if metadata.version != self.name.version {
+            Err(mismatch(
+                "version",
+                metadata.version.to_string(),
+                self.name.version.to_string(),
+            ))?;
+        }
+
+        let wheel = Member {
+            data: self.wheel_file().into_bytes(),
+            executable: false,
+        };
+        let wheel_path = format!("{}/WHEEL", self.dist_info);
+        let prefix = format!("{}/", self.dist_info);
+        let (mut dist_info, rest): (Vec<_>, Vec<_>) = self
+            .files
+            .iter()
+            .chain([(&wheel_path, &wheel)])
+            .partition(|(path, _)| path.starts_with(&prefix));
+        dist_info.sort_unstable_by_key(|(path, _)| *path);
+
+        let mut z = ReproducibleZip::new(out);
+        for (path, member) in rest.into_iter().chain(dist_info) {
+            z.add_file(path, &member.data, member.executable)?;
+        }
+        z.finish(&format!("{}/RECORD", self.dist_info))
+    }
+
+    /// Writes the wheel into `dir`, under its proper filename, and returns where it
+    /// went and its hash.
+    pub fn write_to_dir(&self, dir: &Path) -> Result<(PathBuf, ArtifactHash)> {
+        let tmp = self.write(tempfile::NamedTempFile::new_in(dir)?)?;
+        let path = dir.join(self.name.to_string());
+        tmp.persist(&path)?;
+        let hash = ArtifactHash::sha256_of(fs::File::open(&path)?)?;
+        Ok((path, hash))
+    }
+}
+
+fn parse_root_is_purelib(wheel: &[u8]) -> Result<bool> {
+    for line in std::str::from_utf8(wheel)?.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case("Root-Is-Purelib") {
+                return Ok(value.trim().eq_ignore_ascii_case("true"));
+            }
+        }
+    }
+    bail!("WHEEL has no Root-Is-Purelib")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn writer() -> Result<WheelWriter> {
+        let mut w = WheelWriter::new("Foo.Bar-1.0-py3-none-any.whl".try_into()?, true);
+        assert_eq!(w.dist_info(), "foo_bar-1.0.dist-info");
+        w.add_file(
+            "foo_bar-1.0.dist-info/METADATA",
+            b"Metadata-Version: 2.1\nName: Foo.Bar\nVersion: 1.0\n".to_vec(),
+            false,
+        )?;
+        w.add_file("foo_bar/__init__.py", b"".to_vec(), false)?;
+        w.add_file("foo_bar-1.0.data/scripts/foo", b"#!python\n".to_vec(), true)?;
+        w.add_file("aaa.pth", b"foo_bar\n".to_vec(), false)?;
+        Ok(w)
+    }
+
+    #[test]
+    fn test_write_wheel() -> Result<()> {
+        let w = writer()?;
+        let written = w.write(Cursor::new(Vec::new()))?.into_inner();
+        // Same files, same bytes
+        assert_eq!(
+            writer()?.write(Cursor::new(Vec::new()))?.into_inner(),
+            written
+        );
+
+        let mut z = ZipArchive::new(Cursor::new(written.clone()))?;
+        assert_eq!(
+            z.file_names().collect::<Vec<_>>(),
+            vec![
+                "aaa.pth",
+                "foo_bar-1.0.data/scripts/foo",
+                "foo_bar/__init__.py",
+                "foo_bar-1.0.dist-info/METADATA",
+                "foo_bar-1.0.dist-info/WHEEL",
+                "foo_bar-1.0.dist-info/RECORD",
+            ]
+        );
+        assert_eq!(
+            z.by_name("foo_bar-1.0.data/scripts/foo")?.unix_mode(),
+            Some(0o100755)
+        );
+        let wheel_file = slurp(&mut z.by_name("foo_bar-1.0.dist-info/WHEEL")?)?;
+        assert!(std::str::from_utf8(&wheel_file)?.contains("Tag: py3-none-any\n"));
+        let record = slurp(&mut z.by_name("foo_bar-1.0.dist-info/RECORD")?)?;
+        let record = Record::parse(std::str::from_utf8(&record)?)?;
+        assert_eq!(record.entries.len(), 6);
+        record.get("aaa.pth").unwrap().check(b"foo_bar\n")?;
+
+        // and we can read it back like any other wheel
+        let wheel =
+            Wheel::new(w.name().clone(), Box::new(Cursor::new(written.clone())))?;
+        let (_, metadata) = wheel.metadata()?;
+        assert_eq!(metadata.version, "1.0".try_into()?);
+
+        // Repacking with different tags only changes the name and the WHEEL file
+        let mut repacked =
+            WheelWriter::from_zip(w.name().clone(), Cursor::new(written))?;
+        assert!(repacked.get_file("foo_bar-1.0.dist-info/RECORD").is_none());
+        assert!(repacked.get_file("foo_bar-1.0.dist-info/WHEEL").is_none());
+        repacked.set_name("foo_bar-1.0-py2.py3-none-any.whl".try_into()?)?;
+        let written = repacked.write(Cursor::new(Vec::new()))?.into_inner();
+        let mut z = ZipArchive::new(Cursor::new(written))?;
+        assert_eq!(
+            z.by_name("foo_bar-1.0.data/scripts/foo")?.unix_mode(),
+            Some(0o100755)
+        );
+        let wheel_file = slurp(&mut z.by_name("foo_bar-1.0.dist-info/WHEEL")?)?;
+        let wheel_file = String::from_utf8(wheel_file)?;
+        assert!(wheel_file.contains("Root-Is-Purelib: true\n"));
+        assert!(wheel_file.contains("Tag: py2-none-any\nTag: py3-none-any\n"));
+        assert!(repacked
+            .set_name("foo_bar-2.0-py3-none-any.whl".try_into()?)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_wheel_checks() -> Result<()> {
+        let mut w = writer()?;
+        assert!(w
+            .add_file("foo_bar-1.0.dist-info/RECORD", Vec::new(), false)
+            .is_err());
+        assert!(w.add_file("../evil.py", Vec::new(), false).is_err());
+
+        w.add_file(
+            "foo_bar-1.0.dist-info/METADATA",
+            b"Metadata-Version: 2.1\nName: foo-bar\nVersion: 1.1\n".to_vec(),
+            false,
+        )?;
+        let err = w.write(Cursor::new(Vec::new())).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(MetadataError::Mismatch {
+                field: "version",
+                ..
+            })
+        ));
+
+        w.remove_file("foo_bar-1.0.dist-info/METADATA");
+        assert!(w.write(Cursor::new(Vec::new())).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! A zip file's central directory, saved so that next time we can go straight to the
+//! members we want, instead of having ZipArchive parse the whole directory again. (For
+//! a big wheel or pybi, the directory alone can have tens of thousands of entries.)
+//!
+//! This is only sound for files we know won't change underneath us -- in practice,
+//! entries in the by-hash cache, which we've already checked the contents of. So we
+//! don't bother with CRCs.
+
+use crate::prelude::*;
+use crate::seek_slice::SeekSlice;
+use crate::tree::{slurp_member_carefully, UnpackLimits};
+use zip::{CompressionMethod, ZipArchive};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZipIndex {
+    members: Vec<ZipMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ZipMember {
+    name: String,
+    data_start: u64,
+    compressed_size: u64,
+    size: u64,
+    deflated: bool,
+}
+
+impl ZipIndex {
+    pub fn new<R: Read + Seek>(z: &mut ZipArchive<R>) -> Result<ZipIndex> {
+        let mut members = Vec::with_capacity(z.len());
+        for i in 0..z.len() {
+            let member = z.by_index_raw(i)?;
+            let deflated = match member.compression() {
+                CompressionMethod::Stored => false,
+                CompressionMethod::Deflated => true,
+                // Not worth handling ourselves; ZipArchive can deal with these.
+                other => {
+                    bail!("can't index {} (compressed with {other:?})", member.name())
+                }
+            };
+            members.push(ZipMember {
+                name: member.name().to_owned(),
+                data_start: member.data_start(),
+                compressed_size: member.compressed_size(),
+                size: member.size(),
+                deflated,
+            });
+        }
+        Ok(ZipIndex { members })
+    }
+
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|m| m.name.as_str())
+    }
+
+    /// Reads the member called `name` out of `f`, which has to be the same zip file we
+    /// made the index from, as long as it's within `limits`.
+    pub fn slurp<R: Read + Seek>(
+        &self,
+        f: R,
+        name: &str,
+        limits: &UnpackLimits,
+    ) -> Result<Vec<u8>> {
+        let member = self
+            .members
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| eyre!("no file named {name} in zip"))?;
+        let end = member.data_start + member.compressed_size;
+        let raw = SeekSlice::new(f, member.data_start, end)?;
+        let data = if member.deflated {
+            let inflated = flate2::read::DeflateDecoder::new(raw);
+            slurp_member_carefully(
+                inflated,
+                member.compressed_size,
+                member.size,
+                limits,
+            )?
+        } else {
+            slurp_member_carefully(raw, member.compressed_size, member.size, limits)?
+        };
+        if data.len() as u64 != member.size {
+            bail!(
+                "{name} is {} bytes, but zip index says {}",
+                data.len(),
+                member.size
+            );
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use zip::write::FileOptions;
+
+    #[test]
+    fn test_zip_index() -> Result<()> {
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut w = zip::ZipWriter::new(&mut buf);
+            let stored =
+                FileOptions::default().compression_method(CompressionMethod::Stored);
+            let deflated =
+                FileOptions::default().compression_method(CompressionMethod::Deflated);
+            w.start_file("foo-1.0.dist-info/METADATA", deflated)?;
+            w.write_all(&b"Metadata-Version: 2.1\n".repeat(100))?;
+            w.start_file("foo/__init__.py", stored)?;
+            w.write_all(b"print('hi')\n")?;
+            w.start_file("foo/empty.py", deflated)?;
+            w.finish()?;
+        }
+
+        let index = ZipIndex::new(&mut ZipArchive::new(&mut buf)?)?;
+        let limits = UnpackLimits::default();
+        assert_eq!(
+            index.file_names().collect::<Vec<_>>(),
+            vec![
+                "foo-1.0.dist-info/METADATA",
+                "foo/__init__.py",
+                "foo/empty.py"
+            ]
+        );
+        assert_eq!(
+            index.slurp(&mut buf, "foo-1.0.dist-info/METADATA", &limits)?,
+            b"Metadata-Version: 2.1\n".repeat(100)
+        );
+        assert_eq!(
+            index.slurp(&mut buf, "foo/__init__.py", &limits)?,
+            b"print('hi')\n"
+        );
+        assert_eq!(index.slurp(&mut buf, "foo/empty.py", &limits)?, b"");
+        assert!(index.slurp(&mut buf, "foo/missing.py", &limits).is_err());
+
+        // a member bigger than we'd unpack doesn't get read at all
+        let tight = UnpackLimits {
+            max_unpacked_size: 100,
+            ..limits
+        };
+        let metadata = "foo-1.0.dist-info/METADATA";
+        assert!(index.slurp(&mut buf, metadata, &tight).is_err());
+        // and one that's bigger than the index says stops there
+        let mut lying = index.clone();
+        lying.members[0].size = 10;
+        assert!(lying.slurp(&mut buf, metadata, &limits).is_err());
+
+        // and it survives a round trip through the cache
+        let mut blob = Vec::new();
+        ciborium::ser::into_writer(&index, &mut blob)?;
+        let roundtripped: ZipIndex = ciborium::de::from_reader(blob.as_slice())?;
+        assert_eq!(roundtripped, index);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip64_index() -> Result<()> {
+        let big = vec![b'x'; 100_000];
+        let zipped = crate::test_util::zip64_archive(&[
+            ("foo/__init__.py", b"print('hi')\n"),
+            ("foo/big.bin", &big),
+            ("foo/empty.py", b""),
+        ]);
+        let mut buf = Cursor::new(zipped);
+        let index = ZipIndex::new(&mut ZipArchive::new(&mut buf)?)?;
+        let limits = UnpackLimits::default();
+        assert_eq!(
+            index.slurp(&mut buf, "foo/__init__.py", &limits)?,
+            b"print('hi')\n"
+        );
+        assert_eq!(index.slurp(&mut buf, "foo/big.bin", &limits)?, big);
+        assert_eq!(index.slurp(&mut buf, "foo/empty.py", &limits)?, b"");
+        Ok(())
+    }
+}